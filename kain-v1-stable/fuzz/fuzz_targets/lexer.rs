@@ -0,0 +1,10 @@
+#![no_main]
+
+use kain::Lexer;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(source) = std::str::from_utf8(data) {
+        let _ = Lexer::new(source).tokenize();
+    }
+});
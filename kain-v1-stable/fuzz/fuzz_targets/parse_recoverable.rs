@@ -0,0 +1,18 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+// parse_recoverable turns panics into diagnostics, so check for that explicitly:
+// an "internal error" diagnostic is a bug just like a crash would be.
+fuzz_target!(|data: &[u8]| {
+    if let Ok(source) = std::str::from_utf8(data) {
+        let outcome = kain::parse_recoverable(source);
+        for diag in &outcome.diagnostics {
+            assert!(
+                !diag.to_string().contains("internal error in"),
+                "front end panicked: {}",
+                diag
+            );
+        }
+    }
+});
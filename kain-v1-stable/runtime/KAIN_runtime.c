@@ -0,0 +1,488 @@
+// KAIN Native Runtime - linked into everything the llvm target builds
+//
+// The compiler carries this file in its binary and compiles it into its cache
+// directory when linking (see link_executable in packager.rs), so it has to
+// stay one self-contained C11 file. Every function here is declared by
+// emit_externs in codegen/llvm.rs.
+//
+// Heap objects (strings, arrays, structs, actors) sit behind a header holding
+// a reference count and an optional destructor. Inside a `region:` block,
+// objects come from a per-thread bump arena instead and are freed together
+// when the block exits. Their count is pinned, so retaining or releasing one
+// does nothing; destructors registered for them run when the region exits.
+#ifndef _WIN32
+#define _POSIX_C_SOURCE 200809L
+#endif
+#include <inttypes.h>
+#include <math.h>
+#include <stdbool.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+
+#ifdef _WIN32
+#include <windows.h>
+#else
+#include <pthread.h>
+#include <sys/time.h>
+#include <time.h>
+#endif
+
+typedef void (*KainDtor)(void*);
+
+static void kain_oom(void) {
+    fputs("KAIN runtime: out of memory\n", stderr);
+    abort();
+}
+
+// ============================================================================
+// Reference counting
+// ============================================================================
+
+typedef struct {
+    int64_t refcount;
+    KainDtor dtor;
+} KainHeader;
+
+// Count of arena objects, which live until their region exits
+#define KAIN_RC_PINNED (-1)
+
+static KainHeader* kain_header(void* ptr) { return (KainHeader*)ptr - 1; }
+
+void* KAIN_alloc(int64_t size) {
+    KainHeader* header = calloc(1, sizeof(KainHeader) + (size_t)size);
+    if (!header) kain_oom();
+    header->refcount = 1;
+    return header + 1;
+}
+
+void rc_retain(void* ptr) {
+    if (!ptr) return;
+    KainHeader* header = kain_header(ptr);
+    if (__atomic_load_n(&header->refcount, __ATOMIC_RELAXED) == KAIN_RC_PINNED) return;
+    __atomic_fetch_add(&header->refcount, 1, __ATOMIC_RELAXED);
+}
+
+void rc_release(void* ptr) {
+    if (!ptr) return;
+    KainHeader* header = kain_header(ptr);
+    if (__atomic_load_n(&header->refcount, __ATOMIC_RELAXED) == KAIN_RC_PINNED) return;
+    if (__atomic_sub_fetch(&header->refcount, 1, __ATOMIC_ACQ_REL) == 0) {
+        if (header->dtor) header->dtor(ptr);
+        free(header);
+    }
+}
+
+// ============================================================================
+// Regions
+// ============================================================================
+
+// A block of the arena. Positions count bytes across all chunks ever handed
+// out, so a mark taken on entry says which chunks and bytes to give back.
+typedef struct KainChunk {
+    struct KainChunk* prev;
+    int64_t start;
+    int64_t size;
+    int64_t used;
+    // 32 bytes of header keep data 16-byte aligned
+    unsigned char data[];
+} KainChunk;
+
+typedef struct {
+    int64_t position;
+    void* ptr;
+    KainDtor dtor;
+} KainRegionDtor;
+
+#define KAIN_CHUNK_SIZE (64 * 1024)
+
+static _Thread_local KainChunk* region_chunk;
+static _Thread_local KainRegionDtor* region_dtors;
+static _Thread_local int64_t region_dtor_count;
+static _Thread_local int64_t region_dtor_capacity;
+
+static int64_t kain_region_position(void) {
+    return region_chunk ? region_chunk->start + region_chunk->used : 0;
+}
+
+int64_t KAIN_region_enter(void) { return kain_region_position(); }
+
+void* KAIN_region_alloc(int64_t size) {
+    int64_t need = (int64_t)sizeof(KainHeader) + ((size + 15) & ~(int64_t)15);
+    KainChunk* chunk = region_chunk;
+    if (!chunk || chunk->used + need > chunk->size) {
+        int64_t capacity = need > KAIN_CHUNK_SIZE ? need : KAIN_CHUNK_SIZE;
+        KainChunk* next = malloc(sizeof(KainChunk) + (size_t)capacity);
+        if (!next) kain_oom();
+        next->prev = chunk;
+        next->start = kain_region_position();
+        next->size = capacity;
+        next->used = 0;
+        region_chunk = chunk = next;
+    }
+    KainHeader* header = (KainHeader*)(chunk->data + chunk->used);
+    chunk->used += need;
+    memset(header, 0, (size_t)need);
+    header->refcount = KAIN_RC_PINNED;
+    return header + 1;
+}
+
+void KAIN_region_exit(int64_t mark) {
+    // Destructors first, newest object first, while the arena is still intact
+    while (region_dtor_count > 0 && region_dtors[region_dtor_count - 1].position > mark) {
+        KainRegionDtor entry = region_dtors[--region_dtor_count];
+        entry.dtor(entry.ptr);
+    }
+    while (region_chunk && region_chunk->start > mark) {
+        KainChunk* prev = region_chunk->prev;
+        free(region_chunk);
+        region_chunk = prev;
+    }
+    // The oldest chunk is kept for the next region
+    if (region_chunk) region_chunk->used = mark - region_chunk->start;
+}
+
+void KAIN_set_destructor(void* ptr, KainDtor dtor) {
+    if (!ptr) return;
+    KainHeader* header = kain_header(ptr);
+    if (header->refcount != KAIN_RC_PINNED) {
+        header->dtor = dtor;
+        return;
+    }
+    if (region_dtor_count == region_dtor_capacity) {
+        region_dtor_capacity = region_dtor_capacity ? region_dtor_capacity * 2 : 16;
+        region_dtors = realloc(region_dtors, (size_t)region_dtor_capacity * sizeof(KainRegionDtor));
+        if (!region_dtors) kain_oom();
+    }
+    region_dtors[region_dtor_count++] = (KainRegionDtor){ kain_region_position(), ptr, dtor };
+}
+
+// ============================================================================
+// Printing
+// ============================================================================
+
+void print_i64(int64_t n) { printf("%" PRId64 "\n", n); }
+
+// The shortest digits that read back as the same double, laid out the way
+// JavaScript writes numbers. A port of `float::format` in the compiler, so the
+// interpreter and every backend print a Float alike.
+static void kain_format_f64(double f, char out[40]) {
+    if (isnan(f)) { strcpy(out, "NaN"); return; }
+    if (isinf(f)) { strcpy(out, f > 0 ? "Infinity" : "-Infinity"); return; }
+    if (f == 0) { strcpy(out, "0"); return; }
+
+    double magnitude = f < 0 ? -f : f;
+    char sci[40];
+    for (int precision = 0; precision < 17; precision++) {
+        snprintf(sci, sizeof(sci), "%.*e", precision, magnitude);
+        if (strtod(sci, NULL) == magnitude) break;
+    }
+    // `sci` is d.ddde[+-]N, and the value is 0.<digits> * 10^n
+    char digits[20];
+    int k = 0;
+    char* e = strchr(sci, 'e');
+    for (char* c = sci; c < e; c++) {
+        if (*c != '.') digits[k++] = *c;
+    }
+    while (k > 1 && digits[k - 1] == '0') k--;
+    int n = atoi(e + 1) + 1;
+
+    char* p = out;
+    if (f < 0) *p++ = '-';
+    if (k <= n && n <= 21) {
+        memcpy(p, digits, (size_t)k);
+        p += k;
+        for (int i = k; i < n; i++) *p++ = '0';
+    } else if (0 < n && n <= 21) {
+        memcpy(p, digits, (size_t)n);
+        p += n;
+        *p++ = '.';
+        memcpy(p, digits + n, (size_t)(k - n));
+        p += k - n;
+    } else if (-6 < n && n <= 0) {
+        *p++ = '0';
+        *p++ = '.';
+        for (int i = n; i < 0; i++) *p++ = '0';
+        memcpy(p, digits, (size_t)k);
+        p += k;
+    } else {
+        *p++ = digits[0];
+        if (k > 1) {
+            *p++ = '.';
+            memcpy(p, digits + 1, (size_t)(k - 1));
+            p += k - 1;
+        }
+        p += sprintf(p, "e%c%d", n > 0 ? '+' : '-', abs(n - 1));
+    }
+    *p = '\0';
+}
+
+void print_f64(double f) {
+    char buf[40];
+    kain_format_f64(f, buf);
+    printf("%s\n", buf);
+}
+
+void print_bool(bool b) { puts(b ? "true" : "false"); }
+
+// `len` is the byte length, or 0 for a NUL-terminated string
+void print_str(const char* s, int64_t len) {
+    if (!s) s = "";
+    if (len > 0) {
+        fwrite(s, 1, (size_t)len, stdout);
+        putchar('\n');
+    } else {
+        puts(s);
+    }
+}
+
+// ============================================================================
+// Strings
+// ============================================================================
+
+char* string_new(const char* text) {
+    if (!text) text = "";
+    size_t len = strlen(text);
+    char* s = KAIN_alloc((int64_t)len + 1);
+    memcpy(s, text, len + 1);
+    return s;
+}
+
+char* str_concat(const char* a, const char* b) {
+    if (!a) a = "";
+    if (!b) b = "";
+    size_t len_a = strlen(a);
+    size_t len_b = strlen(b);
+    char* s = KAIN_alloc((int64_t)(len_a + len_b) + 1);
+    memcpy(s, a, len_a);
+    memcpy(s + len_a, b, len_b + 1);
+    return s;
+}
+
+char* to_string(int64_t n) {
+    char buf[32];
+    snprintf(buf, sizeof(buf), "%" PRId64, n);
+    return string_new(buf);
+}
+
+bool deep_eq(const char* a, const char* b) {
+    if (a == b) return true;
+    if (!a || !b) return false;
+    return strcmp(a, b) == 0;
+}
+
+// ============================================================================
+// Arrays
+// ============================================================================
+
+typedef struct {
+    int64_t len;
+    int64_t cap;
+    int64_t* items;
+} KainArray;
+
+static void kain_array_dtor(void* ptr) { free(((KainArray*)ptr)->items); }
+
+void* array_new(int64_t capacity) {
+    KainArray* arr = KAIN_alloc(sizeof(KainArray));
+    arr->cap = capacity > 0 ? capacity : 8;
+    arr->items = malloc((size_t)arr->cap * sizeof(int64_t));
+    if (!arr->items) kain_oom();
+    KAIN_set_destructor(arr, kain_array_dtor);
+    return arr;
+}
+
+void array_push(void* ptr, int64_t value) {
+    KainArray* arr = ptr;
+    if (arr->len == arr->cap) {
+        arr->cap *= 2;
+        arr->items = realloc(arr->items, (size_t)arr->cap * sizeof(int64_t));
+        if (!arr->items) kain_oom();
+    }
+    arr->items[arr->len++] = value;
+}
+
+static void kain_bounds(KainArray* arr, int64_t index) {
+    if (index < 0 || index >= arr->len) {
+        fprintf(stderr, "KAIN runtime: index %" PRId64 " out of bounds for array of length %" PRId64 "\n", index, arr->len);
+        abort();
+    }
+}
+
+int64_t array_get(void* ptr, int64_t index) {
+    KainArray* arr = ptr;
+    kain_bounds(arr, index);
+    return arr->items[index];
+}
+
+void array_set(void* ptr, int64_t index, int64_t value) {
+    KainArray* arr = ptr;
+    kain_bounds(arr, index);
+    arr->items[index] = value;
+}
+
+int64_t array_len(void* ptr) { return ptr ? ((KainArray*)ptr)->len : 0; }
+
+// ============================================================================
+// Time
+// ============================================================================
+
+// Milliseconds since the Unix epoch
+int64_t clock_wrapper(void) {
+#ifdef _WIN32
+    FILETIME ft;
+    GetSystemTimeAsFileTime(&ft);
+    uint64_t ticks = ((uint64_t)ft.dwHighDateTime << 32) | ft.dwLowDateTime;
+    return (int64_t)((ticks - 116444736000000000ULL) / 10000);
+#else
+    struct timeval tv;
+    gettimeofday(&tv, NULL);
+    return (int64_t)tv.tv_sec * 1000 + tv.tv_usec / 1000;
+#endif
+}
+
+void KAIN_sleep(double seconds) {
+    if (seconds <= 0) return;
+#ifdef _WIN32
+    Sleep((DWORD)(seconds * 1000));
+#else
+    struct timespec ts;
+    ts.tv_sec = (time_t)seconds;
+    ts.tv_nsec = (long)((seconds - (double)ts.tv_sec) * 1e9);
+    nanosleep(&ts, NULL);
+#endif
+}
+
+// ============================================================================
+// Actors
+// ============================================================================
+
+typedef struct KainMessage {
+    struct KainMessage* next;
+    int64_t tag;
+    void* data;
+} KainMessage;
+
+typedef struct {
+#ifdef _WIN32
+    CRITICAL_SECTION lock;
+#else
+    pthread_mutex_t lock;
+#endif
+    KainMessage* head;
+    KainMessage* tail;
+} KainQueue;
+
+static void kain_lock(KainQueue* q) {
+#ifdef _WIN32
+    EnterCriticalSection(&q->lock);
+#else
+    pthread_mutex_lock(&q->lock);
+#endif
+}
+
+static void kain_unlock(KainQueue* q) {
+#ifdef _WIN32
+    LeaveCriticalSection(&q->lock);
+#else
+    pthread_mutex_unlock(&q->lock);
+#endif
+}
+
+static void kain_queue_dtor(void* ptr) {
+    KainQueue* q = ptr;
+    while (q->head) {
+        KainMessage* msg = q->head;
+        q->head = msg->next;
+        rc_release(msg->data);
+        free(msg);
+    }
+#ifdef _WIN32
+    DeleteCriticalSection(&q->lock);
+#else
+    pthread_mutex_destroy(&q->lock);
+#endif
+}
+
+// A mailbox; an actor's struct releases it like any other field
+void* mq_new(void) {
+    KainQueue* q = KAIN_alloc(sizeof(KainQueue));
+#ifdef _WIN32
+    InitializeCriticalSection(&q->lock);
+#else
+    pthread_mutex_init(&q->lock, NULL);
+#endif
+    KAIN_set_destructor(q, kain_queue_dtor);
+    return q;
+}
+
+// Takes over the caller's reference to `data`
+void mq_push(void* queue, int64_t tag, void* data) {
+    KainQueue* q = queue;
+    KainMessage* msg = malloc(sizeof(KainMessage));
+    if (!msg) kain_oom();
+    msg->next = NULL;
+    msg->tag = tag;
+    msg->data = data;
+    kain_lock(q);
+    if (q->tail) q->tail->next = msg;
+    else q->head = msg;
+    q->tail = msg;
+    kain_unlock(q);
+}
+
+// 1 and the oldest message, or 0 when the mailbox is empty
+int32_t mq_pop(void* queue, int64_t* tag, void** data) {
+    KainQueue* q = queue;
+    kain_lock(q);
+    KainMessage* msg = q->head;
+    if (msg) {
+        q->head = msg->next;
+        if (!q->head) q->tail = NULL;
+    }
+    kain_unlock(q);
+    if (!msg) return 0;
+    *tag = msg->tag;
+    *data = msg->data;
+    free(msg);
+    return 1;
+}
+
+typedef struct {
+    void (*run)(void*);
+    void* actor;
+} KainThreadStart;
+
+#ifdef _WIN32
+static DWORD WINAPI kain_thread_main(LPVOID arg) {
+#else
+static void* kain_thread_main(void* arg) {
+#endif
+    KainThreadStart start = *(KainThreadStart*)arg;
+    free(arg);
+    start.run(start.actor);
+    return 0;
+}
+
+// Run an actor's loop on a thread of its own
+void KAIN_spawn(void* run, void* actor) {
+    KainThreadStart* start = malloc(sizeof(KainThreadStart));
+    if (!start) kain_oom();
+    start->run = (void (*)(void*))run;
+    start->actor = actor;
+#ifdef _WIN32
+    HANDLE thread = CreateThread(NULL, 0, kain_thread_main, start, 0, NULL);
+    if (thread) CloseHandle(thread);
+#else
+    pthread_t thread;
+    if (pthread_create(&thread, NULL, kain_thread_main, start) == 0) pthread_detach(thread);
+#endif
+}
+
+// ============================================================================
+// KOS Bridge
+// ============================================================================
+
+void spawn_cube(double x, double y) {
+    printf(" [KOS Bridge] Spawning Cube at { x: %.2f, y: %.2f }\n", x, y);
+}
@@ -0,0 +1,335 @@
+//! Command-line parsing for `std/cli`
+//!
+//! The command line is described by a struct, read with comptime reflection:
+//!
+//! ```ignore
+//! enum Command:
+//!     Add { name: String }
+//!     List
+//!
+//! struct Args:
+//!     verbose: Bool               // --verbose
+//!     count: Option<Int>          // --count <INT>, optional
+//!     out: String                 // --out <STRING>, required
+//!     command: Command            // add | list
+//!     files: Array<String>        // positional
+//!
+//! const CLI: CliSpec = comptime:
+//!     return cli_spec("Args", "tool", "Does things", fields_of(Args), variants_of(Command))
+//!
+//! fn main():
+//!     let args = parse(CLI)   // std/cli: prints help or errors and exits
+//!     match args.command:
+//!         Command::Add(name) => ...
+//!         Command::List => ...
+//! ```
+//!
+//! Field and variant names are spelled in kebab case on the command line
+//! (`dry_run` is `--dry-run`, `AddUser` is `add-user`), and `-h` / `--help`
+//! prints generated help for the program or the subcommand. A subcommand's
+//! fields are parsed from the arguments after it and stored in declaration
+//! order, so the variant is matched positionally.
+
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+
+use crate::runtime::Value;
+
+const SCALARS: &[&str] = &["Int", "Float", "String"];
+
+#[derive(Debug, Clone)]
+enum Kind {
+    Flag,
+    Option { ty: String, required: bool },
+    Positional { ty: String },
+    Command { enum_name: String },
+}
+
+#[derive(Debug, Clone)]
+struct Arg {
+    name: String,
+    kind: Kind,
+}
+
+struct Command {
+    name: String,
+    args: Vec<Arg>,
+}
+
+struct Spec {
+    ty: String,
+    name: String,
+    about: String,
+    args: Vec<Arg>,
+    commands: Vec<Command>,
+}
+
+/// Validate a command line description and package it as a `CliSpec` value
+pub fn spec(ty: &str, name: &str, about: &str, fields: &Value, commands: Option<&Value>) -> Result<Value, String> {
+    let commands = match commands {
+        Some(Value::Array(items)) => items.read().unwrap().clone(),
+        Some(_) => return Err("commands must be variants_of(Enum)".to_string()),
+        None => Vec::new(),
+    };
+    let mut out = HashMap::new();
+    out.insert("ty".to_string(), Value::String(ty.to_string()));
+    out.insert("name".to_string(), Value::String(name.to_string()));
+    out.insert("about".to_string(), Value::String(about.to_string()));
+    out.insert("fields".to_string(), fields.clone());
+    out.insert("commands".to_string(), Value::Array(Arc::new(RwLock::new(commands))));
+    let spec = Value::Struct("CliSpec".to_string(), Arc::new(RwLock::new(out)));
+    Spec::from_value(&spec)?;
+    Ok(spec)
+}
+
+/// Parse `argv` (without the program name) against a `CliSpec`
+///
+/// Errors carry the text to print and the exit code: 0 for `--help`, 2 for
+/// a malformed command line.
+pub fn parse(spec: &Value, argv: &[String]) -> Result<Value, (String, i64)> {
+    let spec = Spec::from_value(spec).map_err(|e| (e, 2))?;
+    let mut argv = argv.iter();
+    let values = parse_args(&spec, &spec.args, &spec.name, &mut argv)?;
+    Ok(Value::Struct(spec.ty.clone(), Arc::new(RwLock::new(values.into_iter().collect()))))
+}
+
+impl Spec {
+    fn from_value(value: &Value) -> Result<Self, String> {
+        let mut commands = Vec::new();
+        if let Value::Array(items) = get(value, "commands")? {
+            for variant in items.read().unwrap().iter() {
+                commands.push(Command { name: str_field(variant, "name")?, args: args_of(&get(variant, "fields")?, false)? });
+            }
+        }
+        Ok(Spec {
+            ty: str_field(value, "ty")?,
+            name: str_field(value, "name")?,
+            about: str_field(value, "about")?,
+            args: args_of(&get(value, "fields")?, !commands.is_empty())?,
+            commands,
+        })
+    }
+}
+
+fn get(value: &Value, key: &str) -> Result<Value, String> {
+    match value {
+        Value::Struct(_, fields) => fields.read().unwrap().get(key).cloned().ok_or_else(|| format!("missing `{}`", key)),
+        _ => Err(format!("expected a struct with `{}`", key)),
+    }
+}
+
+fn str_field(value: &Value, key: &str) -> Result<String, String> {
+    match get(value, key)? {
+        Value::String(s) => Ok(s),
+        _ => Err(format!("`{}` must be a string", key)),
+    }
+}
+
+/// Classify `fields_of` entries by their type
+fn args_of(fields: &Value, has_commands: bool) -> Result<Vec<Arg>, String> {
+    let Value::Array(fields) = fields else {
+        return Err("fields must be fields_of(T)".to_string());
+    };
+    let mut args = Vec::new();
+    for field in fields.read().unwrap().iter() {
+        let name = str_field(field, "name")?;
+        let ty = str_field(field, "ty")?;
+        let inner = |prefix: &str| ty.strip_prefix(prefix).and_then(|t| t.strip_suffix('>')).filter(|t| SCALARS.contains(t));
+        let kind = if ty == "Bool" {
+            Kind::Flag
+        } else if SCALARS.contains(&ty.as_str()) {
+            Kind::Option { ty: ty.clone(), required: true }
+        } else if let Some(t) = inner("Option<") {
+            Kind::Option { ty: t.to_string(), required: false }
+        } else if let Some(t) = inner("Array<") {
+            Kind::Positional { ty: t.to_string() }
+        } else if has_commands {
+            Kind::Command { enum_name: ty.clone() }
+        } else {
+            return Err(format!(
+                "field `{}`: {} can't be a command-line argument (use Bool, Int, Float, String, Option<..> or Array<..>)",
+                name, ty
+            ));
+        };
+        let duplicate = |k: &Kind| args.iter().any(|a: &Arg| std::mem::discriminant(&a.kind) == std::mem::discriminant(k));
+        if matches!(kind, Kind::Positional { .. } | Kind::Command { .. }) && duplicate(&kind) {
+            return Err(format!("field `{}`: only one positional list and one command field are allowed", name));
+        }
+        args.push(Arg { name, kind });
+    }
+    Ok(args)
+}
+
+fn parse_args<'a>(
+    spec: &Spec,
+    args: &[Arg],
+    path: &str,
+    argv: &mut impl Iterator<Item = &'a String>,
+) -> Result<Vec<(String, Value)>, (String, i64)> {
+    let usage_error = |message: String| (format!("error: {}\n\n{}\nFor more information, try '--help'.", message, usage(spec, args, path)), 2);
+    let mut values: HashMap<&str, Value> = HashMap::new();
+    let mut positional = Vec::new();
+    let mut only_positional = false;
+
+    while let Some(token) = argv.next() {
+        if !only_positional && (token == "-h" || token == "--help") {
+            return Err((help(spec, args, path), 0));
+        }
+        if !only_positional && token == "--" {
+            only_positional = true;
+            continue;
+        }
+        if let Some(option) = token.strip_prefix("--").filter(|_| !only_positional) {
+            let (option, inline) = match option.split_once('=') {
+                Some((option, value)) => (option, Some(value.to_string())),
+                None => (option, None),
+            };
+            let arg = args
+                .iter()
+                .find(|a| matches!(a.kind, Kind::Flag | Kind::Option { .. }) && kebab(&a.name) == option)
+                .ok_or_else(|| usage_error(format!("unexpected option '--{}'", option)))?;
+            let value = match (&arg.kind, inline) {
+                (Kind::Flag, None) => Value::Bool(true),
+                (Kind::Flag, Some(_)) => return Err(usage_error(format!("'--{}' doesn't take a value", option))),
+                (Kind::Option { ty, .. }, inline) => {
+                    let raw = inline
+                        .or_else(|| argv.next().cloned())
+                        .ok_or_else(|| usage_error(format!("'--{}' expects a value", option)))?;
+                    convert(ty, &raw).map_err(|e| usage_error(format!("'--{}': {}", option, e)))?
+                }
+                _ => unreachable!(),
+            };
+            values.insert(&arg.name, value);
+            continue;
+        }
+
+        let command_arg = args.iter().find(|a| matches!(a.kind, Kind::Command { .. }));
+        if let Some(arg @ Arg { kind: Kind::Command { enum_name }, .. }) = command_arg {
+            let has_positional = args.iter().any(|a| matches!(a.kind, Kind::Positional { .. }));
+            let command = spec.commands.iter().find(|c| kebab(&c.name) == *token);
+            if command.is_none() && !has_positional && !values.contains_key(arg.name.as_str()) {
+                return Err(usage_error(format!("unknown command '{}'", token)));
+            }
+            if let Some(command) = command.filter(|_| !only_positional && !values.contains_key(arg.name.as_str())) {
+                let fields = parse_args(spec, &command.args, &format!("{} {}", path, kebab(&command.name)), argv)?;
+                let fields = fields.into_iter().map(|(_, v)| v).collect();
+                values.insert(&arg.name, Value::EnumVariant(enum_name.clone(), command.name.clone(), fields));
+                break;
+            }
+        }
+        match args.iter().find(|a| matches!(a.kind, Kind::Positional { .. })) {
+            Some(Arg { kind: Kind::Positional { ty }, .. }) => {
+                positional.push(convert(ty, token).map_err(|e| usage_error(format!("'{}': {}", token, e)))?);
+            }
+            _ => return Err(usage_error(format!("unexpected argument '{}'", token))),
+        }
+    }
+
+    let mut out = Vec::with_capacity(args.len());
+    for arg in args {
+        let value = match (&arg.kind, values.remove(arg.name.as_str())) {
+            (_, Some(value)) => value,
+            (Kind::Flag, None) => Value::Bool(false),
+            (Kind::Option { required: false, .. }, None) => Value::None,
+            (Kind::Option { .. }, None) => return Err(usage_error(format!("missing required option '--{}'", kebab(&arg.name)))),
+            (Kind::Positional { .. }, None) => Value::Array(Arc::new(RwLock::new(std::mem::take(&mut positional)))),
+            (Kind::Command { .. }, None) => return Err(usage_error("missing command".to_string())),
+        };
+        out.push((arg.name.clone(), value));
+    }
+    Ok(out)
+}
+
+fn convert(ty: &str, raw: &str) -> Result<Value, String> {
+    match ty {
+        "Int" => raw.parse().map(Value::Int).map_err(|_| format!("expected an integer, got '{}'", raw)),
+        "Float" => raw.parse().map(Value::Float).map_err(|_| format!("expected a number, got '{}'", raw)),
+        _ => Ok(Value::String(raw.to_string())),
+    }
+}
+
+/// `dry_run` -> `dry-run`, `AddUser` -> `add-user`
+fn kebab(name: &str) -> String {
+    let mut out = String::new();
+    for (i, c) in name.chars().enumerate() {
+        if c == '_' {
+            out.push('-');
+        } else if c.is_uppercase() {
+            if i > 0 && !out.ends_with('-') {
+                out.push('-');
+            }
+            out.extend(c.to_lowercase());
+        } else {
+            out.push(c);
+        }
+    }
+    out
+}
+
+fn usage(spec: &Spec, args: &[Arg], path: &str) -> String {
+    let mut line = format!("Usage: {} [OPTIONS]", path);
+    for arg in args {
+        match &arg.kind {
+            Kind::Command { .. } if !spec.commands.is_empty() => line.push_str(" <COMMAND>"),
+            Kind::Positional { .. } => line.push_str(&format!(" [{}]...", arg.name.to_uppercase())),
+            _ => {}
+        }
+    }
+    line
+}
+
+fn help(spec: &Spec, args: &[Arg], path: &str) -> String {
+    let mut out = String::new();
+    if path == spec.name && !spec.about.is_empty() {
+        out.push_str(&format!("{}\n\n", spec.about));
+    }
+    out.push_str(&usage(spec, args, path));
+    out.push_str("\n\nOptions:\n");
+    let mut rows = Vec::new();
+    for arg in args {
+        match &arg.kind {
+            Kind::Flag => rows.push((format!("--{}", kebab(&arg.name)), String::new())),
+            Kind::Option { ty, required } => rows.push((
+                format!("--{} <{}>", kebab(&arg.name), ty.to_uppercase()),
+                if *required { "(required)".to_string() } else { String::new() },
+            )),
+            _ => {}
+        }
+    }
+    rows.push(("-h, --help".to_string(), "Print help".to_string()));
+    let width = rows.iter().map(|(flag, _)| flag.len()).max().unwrap_or(0);
+    for (flag, note) in rows {
+        out.push_str(format!("  {:width$}  {}", flag, note, width = width).trim_end());
+        out.push('\n');
+    }
+    if args.iter().any(|a| matches!(a.kind, Kind::Command { .. })) {
+        out.push_str("\nCommands:\n");
+        for command in &spec.commands {
+            out.push_str(&format!("  {}\n", kebab(&command.name)));
+        }
+    }
+    out.trim_end().to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{eval_snippet, CompileOptions};
+
+    #[test]
+    fn test_cli_parses_flags_options_and_subcommands() {
+        let source = "enum Command:\n    Add { name: String }\n    List\n\nstruct Args:\n    dry_run: Bool\n    limit: Option<Int>\n    command: Command\n    files: Array<String>\n\nconst CLI: CliSpec = comptime:\n    return cli_spec(\"Args\", \"tool\", \"Does things\", fields_of(Args), variants_of(Command))\n\nfn main():\n    match cli_parse(CLI, args()):\n        Ok(a) =>\n            println(str(a.dry_run) + \" \" + str(a.limit) + \" \" + str(a.files))\n            match a.command:\n                Command::Add(name) => println(\"add \" + name)\n                Command::List => println(\"list\")\n        Err(e) => println(str(e.code) + \" \" + e.message)\n";
+        let run = |argv: &[&str]| {
+            let options = CompileOptions { program_args: argv.iter().map(|a| a.to_string()).collect(), ..Default::default() };
+            let result = eval_snippet(source, &options);
+            assert!(result.diagnostics.is_empty(), "{:?}", result.diagnostics);
+            result.stdout.lines().map(|l| l.trim().to_string()).collect::<Vec<_>>()
+        };
+
+        assert_eq!(run(&["a.txt", "--dry-run", "--limit=3", "add", "--name", "x"]), ["true 3 [a.txt]", "add x"]);
+        assert_eq!(run(&["list"]), ["false none []", "list"]);
+        assert!(run(&["--help"])[0].starts_with("0 Does things"));
+        assert!(run(&["add", "--help"]).iter().any(|l| l.starts_with("--name <STRING>") && l.ends_with("(required)")));
+        assert_eq!(run(&["--limit", "x", "list"])[0], "2 error: '--limit': expected an integer, got 'x'");
+        assert_eq!(run(&[])[0], "2 error: missing command");
+    }
+}
@@ -0,0 +1,157 @@
+//! Inline assembly
+//!
+//! `asm!("wasm", "i64.popcnt", x)` splices instructions the language does not
+//! expose yet straight into a backend's output. The first argument names the
+//! backend the code is written for, the second is the code, and the rest are
+//! operands handed to it. This pass checks the shape of every block and that
+//! the program is being compiled for the backend it names; the backend then
+//! validates the code itself while splicing it in.
+//!
+//! - `wasm`: whitespace separated instructions in text format. The operands
+//!   are pushed onto the stack in order, and the code must leave one `i64`.
+//! - `llvm`: one IR instruction per line. `$0`, `$1`, .. are the operands, and
+//!   the register the last line defines, an `i64`, is the value of the block.
+
+use crate::ast::*;
+use crate::ast::visit::{walk_expr, Visitor};
+use crate::error::{KainError, KainResult};
+use crate::span::Span;
+use crate::CompileTarget;
+
+/// Backends inline code can be written for
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AsmTarget {
+    Wasm,
+    Llvm,
+}
+
+impl AsmTarget {
+    pub fn name(self) -> &'static str {
+        match self {
+            AsmTarget::Wasm => "wasm",
+            AsmTarget::Llvm => "llvm",
+        }
+    }
+
+    /// Whether code written for this backend can be compiled for `target`
+    pub fn accepts(self, target: CompileTarget) -> bool {
+        match self {
+            AsmTarget::Wasm => matches!(target, CompileTarget::Wasm | CompileTarget::Wat),
+            AsmTarget::Llvm => target == CompileTarget::Llvm,
+        }
+    }
+}
+
+/// The parts of an `asm!(..)` call
+pub struct InlineAsm<'a> {
+    pub target: AsmTarget,
+    pub code: &'a str,
+    pub operands: &'a [Expr],
+}
+
+impl<'a> InlineAsm<'a> {
+    /// Read the arguments of an `asm!` call
+    pub fn parse(args: &'a [Expr], span: Span) -> KainResult<Self> {
+        let (Some(Expr::String(target, target_span)), Some(Expr::String(code, _))) = (args.first(), args.get(1)) else {
+            return Err(KainError::type_error(
+                "asm! takes the backend and the code as string literals, e.g. asm!(\"wasm\", \"i64.popcnt\", x)",
+                span,
+            ));
+        };
+        let target = match target.as_str() {
+            "wasm" => AsmTarget::Wasm,
+            "llvm" => AsmTarget::Llvm,
+            other => {
+                return Err(KainError::type_error(
+                    format!("asm! has no backend `{}` (expected \"wasm\" or \"llvm\")", other),
+                    *target_span,
+                ))
+            }
+        };
+        Ok(InlineAsm { target, code, operands: &args[2..] })
+    }
+}
+
+/// Report the first `asm!` block in `program` that is malformed or written for another backend than `target`
+pub fn check(program: &Program, target: CompileTarget) -> KainResult<()> {
+    let mut checker = Checker { target, error: None };
+    checker.visit_program(program);
+    checker.error.map_or(Ok(()), Err)
+}
+
+struct Checker {
+    target: CompileTarget,
+    error: Option<KainError>,
+}
+
+impl Checker {
+    fn check(&self, args: &[Expr], span: Span) -> KainResult<()> {
+        let asm = InlineAsm::parse(args, span)?;
+        if !asm.target.accepts(self.target) {
+            return Err(KainError::type_error(
+                format!(
+                    "asm!(\"{}\", ..) cannot be compiled for the {} target; put it under @cfg(target = \"{}\")",
+                    asm.target.name(),
+                    self.target.cfg_names()[0],
+                    asm.target.name()
+                ),
+                span,
+            ));
+        }
+        Ok(())
+    }
+}
+
+impl Visitor for Checker {
+    fn visit_expr(&mut self, expr: &Expr) {
+        if self.error.is_some() {
+            return;
+        }
+        if let Expr::MacroCall { name, args, span } = expr {
+            if name == "asm" {
+                if let Err(e) = self.check(args, *span) {
+                    self.error = Some(e);
+                    return;
+                }
+            }
+        }
+        walk_expr(self, expr);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{compile, lexer::Lexer, parser::Parser};
+
+    fn check_source(source: &str, target: CompileTarget) -> KainResult<()> {
+        let tokens = Lexer::new(source).tokenize()?;
+        check(&Parser::new(&tokens).parse()?, target)
+    }
+
+    #[test]
+    fn test_asm_is_checked_against_the_target() {
+        let source = "fn bits(x: Int) -> Int:\n    return asm!(\"wasm\", \"i64.popcnt\", x)\n";
+        assert!(check_source(source, CompileTarget::Wasm).is_ok());
+        assert!(check_source(source, CompileTarget::Wat).is_ok());
+        let err = check_source(source, CompileTarget::Js).unwrap_err().to_string();
+        assert!(err.contains("asm!(\"wasm\", ..) cannot be compiled for the js target"), "{}", err);
+
+        let err = check_source("fn f() -> Int:\n    return asm!(\"arm\", \"nop\")\n", CompileTarget::Llvm).unwrap_err();
+        assert!(err.to_string().contains("asm! has no backend `arm`"));
+        let err = check_source("fn f(code: String) -> Int:\n    return asm!(\"llvm\", code)\n", CompileTarget::Llvm).unwrap_err();
+        assert!(err.to_string().contains("as string literals"));
+    }
+
+    #[test]
+    fn test_wasm_asm_is_spliced_and_validated() {
+        let source = "fn bits(x: Int) -> Int:\n    return asm!(\"wasm\", \"i64.popcnt\", x)\n\nfn main() -> Int:\n    return bits(255)\n";
+        let wat = String::from_utf8(compile(source, CompileTarget::Wat).unwrap()).unwrap();
+        assert!(wat.contains("i64.popcnt"), "{}", wat);
+
+        let err = compile("fn root(x: Int) -> Int:\n    return asm!(\"wasm\", \"f64.sqrt\", x)\n", CompileTarget::Wasm).unwrap_err();
+        assert!(err.to_string().contains("`f64.sqrt` expects [F64] on the stack, found [I64]"), "{}", err);
+        let err = compile("fn f() -> Int:\n    return asm!(\"wasm\", \"i64.const 1 i64.const 2\")\n", CompileTarget::Wasm).unwrap_err();
+        assert!(err.to_string().contains("must leave exactly one i64 on the stack"), "{}", err);
+    }
+}
@@ -11,6 +11,8 @@
 use crate::span::Span;
 use crate::effects::Effect;
 
+pub mod visit;
+
 /// A complete KAIN program/module
 #[derive(Debug, Clone)]
 pub struct Program {
@@ -47,6 +49,9 @@ pub enum Item {
     
     /// `type Alias = Type`
     TypeAlias(TypeAlias),
+
+    /// `effect Name`, or `effect Name = IO + Other` for an alias
+    Effect(EffectDecl),
     
     /// `use path::to::item`
     Use(Use),
@@ -56,6 +61,9 @@ pub enum Item {
     
     /// `const NAME: Type = value`
     Const(Const),
+
+    /// `static mut name: Type = value`
+    Static(Static),
     
     /// `comptime { code }`
     Comptime(ComptimeBlock),
@@ -65,6 +73,122 @@ pub enum Item {
 
     /// `test "name": body`
     Test(TestDef),
+
+    /// `@cfg(target = "wasm") item`, kept or removed by comptime
+    Cfg(CfgItem),
+
+    /// Tokens that failed to parse as an item, left by [`Parser::parse_recovering`](crate::parser::Parser::parse_recovering)
+    Error(Span),
+}
+
+impl Item {
+    /// Doc comment attached to this item, if any
+    pub fn doc(&self) -> Option<&str> {
+        match self {
+            Item::Function(f) => f.doc.as_deref(),
+            Item::Component(c) => c.doc.as_deref(),
+            Item::Shader(s) => s.doc.as_deref(),
+            Item::Actor(a) => a.doc.as_deref(),
+            Item::Struct(s) => s.doc.as_deref(),
+            Item::Enum(e) => e.doc.as_deref(),
+            Item::Trait(t) => t.doc.as_deref(),
+            Item::TypeAlias(t) => t.doc.as_deref(),
+            Item::Effect(e) => e.doc.as_deref(),
+            Item::Const(c) => c.doc.as_deref(),
+            Item::Static(s) => s.doc.as_deref(),
+            Item::Cfg(c) => c.item.doc(),
+            _ => None,
+        }
+    }
+
+    /// Source span of the whole item
+    pub fn span(&self) -> Span {
+        match self {
+            Item::Function(f) => f.span,
+            Item::Component(c) => c.span,
+            Item::Shader(s) => s.span,
+            Item::Actor(a) => a.span,
+            Item::Struct(s) => s.span,
+            Item::Enum(e) => e.span,
+            Item::Trait(t) => t.span,
+            Item::Impl(i) => i.span,
+            Item::TypeAlias(t) => t.span,
+            Item::Effect(e) => e.span,
+            Item::Use(u) => u.span,
+            Item::Mod(m) => m.span,
+            Item::Const(c) => c.span,
+            Item::Static(s) => s.span,
+            Item::Comptime(b) => b.span,
+            Item::Macro(m) => m.span,
+            Item::Test(t) => t.span,
+            Item::Cfg(c) => c.span,
+            Item::Error(span) => *span,
+        }
+    }
+
+    /// Name of the item, for items that declare one
+    pub fn name(&self) -> Option<&str> {
+        match self {
+            Item::Function(f) => Some(&f.name),
+            Item::Component(c) => Some(&c.name),
+            Item::Shader(s) => Some(&s.name),
+            Item::Actor(a) => Some(&a.name),
+            Item::Struct(s) => Some(&s.name),
+            Item::Enum(e) => Some(&e.name),
+            Item::Trait(t) => Some(&t.name),
+            Item::TypeAlias(t) => Some(&t.name),
+            Item::Effect(e) => Some(&e.name),
+            Item::Const(c) => Some(&c.name),
+            Item::Static(s) => Some(&s.name),
+            Item::Cfg(c) => c.item.name(),
+            _ => None,
+        }
+    }
+
+    /// Declared visibility of items that can be marked `pub`
+    pub fn visibility(&self) -> Option<Visibility> {
+        match self {
+            Item::Function(f) => Some(f.visibility),
+            Item::Component(c) => Some(c.visibility),
+            Item::Struct(s) => Some(s.visibility),
+            Item::Enum(e) => Some(e.visibility),
+            Item::Trait(t) => Some(t.visibility),
+            Item::TypeAlias(t) => Some(t.visibility),
+            Item::Effect(e) => Some(e.visibility),
+            Item::Const(c) => Some(c.visibility),
+            Item::Static(s) => Some(s.visibility),
+            Item::Cfg(c) => c.item.visibility(),
+            _ => None,
+        }
+    }
+
+    /// Attach a doc comment to items that can carry one
+    pub fn set_doc(&mut self, doc: Option<String>) {
+        match self {
+            Item::Function(f) => f.doc = doc,
+            Item::Component(c) => c.doc = doc,
+            Item::Shader(s) => s.doc = doc,
+            Item::Actor(a) => a.doc = doc,
+            Item::Struct(s) => s.doc = doc,
+            Item::Enum(e) => e.doc = doc,
+            Item::Trait(t) => t.doc = doc,
+            Item::TypeAlias(t) => t.doc = doc,
+            Item::Effect(e) => e.doc = doc,
+            Item::Const(c) => c.doc = doc,
+            Item::Static(s) => s.doc = doc,
+            Item::Cfg(c) => c.item.set_doc(doc),
+            _ => {}
+        }
+    }
+}
+
+/// An item that only exists when its `@cfg` conditions hold
+#[derive(Debug, Clone)]
+pub struct CfgItem {
+    /// The `@cfg(...)` attributes; all of them must hold
+    pub conditions: Vec<Attribute>,
+    pub item: Box<Item>,
+    pub span: Span,
 }
 
 #[derive(Debug, Clone)]
@@ -94,6 +218,8 @@ pub struct Function {
     pub body: Block,
     pub visibility: Visibility,
     pub attributes: Vec<Attribute>,
+    /// `///` doc comment preceding the item
+    pub doc: Option<String>,
     pub span: Span,
 }
 
@@ -103,6 +229,8 @@ pub struct Param {
     pub ty: Type,
     pub mutable: bool,
     pub default: Option<Expr>,
+    /// `...rest`: the remaining arguments, as an array
+    pub variadic: bool,
     pub span: Span,
 }
 
@@ -131,6 +259,7 @@ pub struct Component {
     pub body: JSXNode,
     pub visibility: Visibility,
     pub attributes: Vec<Attribute>,
+    pub doc: Option<String>,
     pub span: Span,
 }
 
@@ -140,6 +269,8 @@ pub struct StateDecl {
     pub ty: Type,
     pub initial: Expr,
     pub weak: bool,
+    /// `persist state`: saved by `snapshot()` and restored when the actor is spawned
+    pub persist: bool,
     pub span: Span,
 }
 
@@ -205,6 +336,7 @@ pub struct Shader {
     pub outputs: Type,
     pub uniforms: Vec<Uniform>,
     pub body: Block,
+    pub doc: Option<String>,
     pub span: Span,
 }
 
@@ -230,9 +362,44 @@ pub struct Actor {
     pub name: String,
     pub state: Vec<StateDecl>,
     pub handlers: Vec<MessageHandler>,
+    /// `mailbox(...)`; without one the mailbox is unbounded
+    pub mailbox: Option<Mailbox>,
+    pub doc: Option<String>,
     pub span: Span,
 }
 
+/// `mailbox(capacity = 1024, policy = DropOldest)`: how many messages an
+/// actor holds before `send` applies the policy
+#[derive(Debug, Clone)]
+pub struct Mailbox {
+    pub capacity: usize,
+    pub policy: MailboxPolicy,
+    pub span: Span,
+}
+
+/// What `send` does when the mailbox is full
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum MailboxPolicy {
+    /// Wait until the actor takes a message
+    #[default]
+    Block,
+    /// Discard the message that has waited longest
+    DropOldest,
+    /// Fail the send with a runtime error
+    Fail,
+}
+
+impl MailboxPolicy {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "Block" => Some(MailboxPolicy::Block),
+            "DropOldest" => Some(MailboxPolicy::DropOldest),
+            "Fail" => Some(MailboxPolicy::Fail),
+            _ => None,
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct MessageHandler {
     pub message_type: String,
@@ -249,6 +416,9 @@ pub struct Struct {
     pub generics: Vec<Generic>,
     pub fields: Vec<Field>,
     pub visibility: Visibility,
+    /// `@derive(...)` and other decorators
+    pub attributes: Vec<Attribute>,
+    pub doc: Option<String>,
     pub span: Span,
 }
 
@@ -268,6 +438,8 @@ pub struct Enum {
     pub generics: Vec<Generic>,
     pub variants: Vec<Variant>,
     pub visibility: Visibility,
+    pub attributes: Vec<Attribute>,
+    pub doc: Option<String>,
     pub span: Span,
 }
 
@@ -293,6 +465,7 @@ pub struct Trait {
     pub generics: Vec<Generic>,
     pub methods: Vec<TraitMethod>,
     pub visibility: Visibility,
+    pub doc: Option<String>,
     pub span: Span,
 }
 
@@ -312,6 +485,8 @@ pub struct Impl {
     pub trait_name: Option<String>,
     pub target_type: Type,
     pub methods: Vec<Function>,
+    /// Associated constants, `Type::NAME`
+    pub consts: Vec<Const>,
     pub span: Span,
 }
 
@@ -389,15 +564,31 @@ pub struct TypeAlias {
     pub name: String,
     pub generics: Vec<Generic>,
     pub target: Type,
+    /// `type UserId = new Int`: a distinct type with `target`'s representation, not another name for it
+    pub newtype: bool,
+    pub visibility: Visibility,
+    pub doc: Option<String>,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone)]
+pub struct EffectDecl {
+    pub name: String,
+    /// The effects an alias stands for; `None` for a new effect
+    pub alias: Option<Vec<Effect>>,
     pub visibility: Visibility,
+    pub doc: Option<String>,
     pub span: Span,
 }
 
 #[derive(Debug, Clone)]
 pub struct Use {
     pub path: Vec<String>,
+    /// `use path as m`: the module's items are reached as `m.item`
     pub alias: Option<String>,
     pub glob: bool,
+    /// `use path: {a, b}`: only these items are imported
+    pub items: Option<Vec<String>>,
     pub span: Span,
 }
 
@@ -415,6 +606,19 @@ pub struct Const {
     pub ty: Type,
     pub value: Expr,
     pub visibility: Visibility,
+    pub doc: Option<String>,
+    pub span: Span,
+}
+
+/// Module-level state that code can change. Reading or writing it is the
+/// `Global` effect, and it lives for the whole run of the program.
+#[derive(Debug, Clone)]
+pub struct Static {
+    pub name: String,
+    pub ty: Type,
+    pub value: Expr,
+    pub visibility: Visibility,
+    pub doc: Option<String>,
     pub span: Span,
 }
 
@@ -482,25 +686,38 @@ pub enum Stmt {
     Expr(Expr),
     /// `return [value]`
     Return(Option<Expr>, Span),
-    /// `break [value]`
-    Break(Option<Expr>, Span),
-    /// `continue`
-    Continue(Span),
-    /// `for binding in iter: body`
+    /// `break ['label] [value]`
+    Break(Option<Expr>, Option<String>, Span),
+    /// `continue ['label]`
+    Continue(Option<String>, Span),
+    /// `['label:] for binding in iter: body [else: body]`
     For {
+        label: Option<String>,
         binding: Pattern,
         iter: Expr,
         body: Block,
+        /// Runs when the loop finishes without `break`
+        else_branch: Option<Block>,
         span: Span,
     },
-    /// `while cond: body`
+    /// `['label:] while cond: body [else: body]`
     While {
+        label: Option<String>,
         condition: Expr,
         body: Block,
+        /// Runs when the loop finishes without `break`
+        else_branch: Option<Block>,
         span: Span,
     },
-    /// `loop: body`
+    /// `['label:] loop: body`
     Loop {
+        label: Option<String>,
+        body: Block,
+        span: Span,
+    },
+    /// `region: body`: the arrays and structs allocated in `body` are freed
+    /// together when it exits, so they must not outlive it
+    Region {
         body: Block,
         span: Span,
     },
@@ -508,6 +725,39 @@ pub enum Stmt {
     Item(Box<Item>),
 }
 
+impl Stmt {
+    pub fn span(&self) -> Span {
+        match self {
+            Stmt::Let { span, .. }
+            | Stmt::Return(_, span)
+            | Stmt::Break(_, _, span)
+            | Stmt::Continue(_, span)
+            | Stmt::For { span, .. }
+            | Stmt::While { span, .. }
+            | Stmt::Loop { span, .. }
+            | Stmt::Region { span, .. } => *span,
+            Stmt::Expr(expr) => expr.span(),
+            Stmt::Item(item) => item.span(),
+        }
+    }
+
+    /// Keyword naming the statement, for traces and diagnostics
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Stmt::Let { .. } => "let",
+            Stmt::Expr(_) => "expr",
+            Stmt::Return(..) => "return",
+            Stmt::Break(..) => "break",
+            Stmt::Continue(..) => "continue",
+            Stmt::For { .. } => "for",
+            Stmt::While { .. } => "while",
+            Stmt::Loop { .. } => "loop",
+            Stmt::Region { .. } => "region",
+            Stmt::Item(_) => "item",
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub enum Expr {
     /// Literals
@@ -516,10 +766,21 @@ pub enum Expr {
     String(String, Span),
     FString(Vec<Expr>, Span),
     Bool(bool, Span),
+    /// `'a'`, one Unicode scalar value
+    Char(char, Span),
     None(Span),
     
     /// Identifier
     Ident(String, Span),
+
+    /// A local variable [`crate::resolve`] found ahead of time: `depth` scopes
+    /// out from the innermost, in `slot`. Only the interpreter sees these.
+    Local {
+        name: String,
+        depth: usize,
+        slot: usize,
+        span: Span,
+    },
     
     /// Macro call
     MacroCall {
@@ -579,10 +840,12 @@ pub enum Expr {
         span: Span,
     },
     
-    /// Struct literal: `Point { x: 1, y: 2 }`
+    /// Struct literal: `Point { x: 1, y: 2 }`, or `Point { ..p, x: 1 }` to copy
+    /// the fields not given from `p`
     Struct {
         name: String,
         fields: Vec<(String, Expr)>,
+        base: Option<Box<Expr>>,
         span: Span,
     },
 
@@ -668,6 +931,15 @@ pub enum Expr {
         span: Span,
     },
     
+    /// `with handler value for Effect: body`: the operations of `Effect`
+    /// performed while `body` runs are routed to methods of `value`
+    Handle {
+        handler: Box<Expr>,
+        effect: Effect,
+        body: Block,
+        span: Span,
+    },
+
     /// Comptime expression: `comptime { expr }`
     Comptime(Box<Expr>, Span),
     
@@ -686,11 +958,14 @@ pub enum Expr {
     /// Return expression: `return [expr]`
     Return(Option<Box<Expr>>, Span),
     
-    /// Break expression: `break [expr]`
-    Break(Option<Box<Expr>>, Span),
+    /// Break expression: `break ['label] [expr]`
+    Break(Option<Box<Expr>>, Option<String>, Span),
     
-    /// Continue expression: `continue`
-    Continue(Span),
+    /// Continue expression: `continue ['label]`
+    Continue(Option<String>, Span),
+
+    /// Tokens that failed to parse as a statement, left by [`Parser::parse_recovering`](crate::parser::Parser::parse_recovering)
+    Error(Span),
 }
 
 impl Expr {
@@ -701,8 +976,10 @@ impl Expr {
             | Expr::String(_, s)
             | Expr::FString(_, s)
             | Expr::Bool(_, s)
+            | Expr::Char(_, s)
             | Expr::None(s)
             | Expr::Ident(_, s)
+            | Expr::Local { span: s, .. }
             | Expr::Binary { span: s, .. }
             | Expr::Unary { span: s, .. }
             | Expr::Call { span: s, .. }
@@ -724,6 +1001,7 @@ impl Expr {
             | Expr::Await(_, s)
             | Expr::Spawn { span: s, .. }
             | Expr::SendMsg { span: s, .. }
+            | Expr::Handle { span: s, .. }
             | Expr::Comptime(_, s)
             | Expr::MacroCall { span: s, .. }
             | Expr::Block(_, s)
@@ -731,8 +1009,9 @@ impl Expr {
             | Expr::Assign { span: s, .. }
             | Expr::Paren(_, s)
             | Expr::Return(_, s)
-            | Expr::Break(_, s)
-            | Expr::Continue(s) => *s,
+            | Expr::Break(_, _, s)
+            | Expr::Continue(_, s)
+            | Expr::Error(s) => *s,
         }
     }
 }
@@ -748,6 +1027,8 @@ pub enum EnumVariantFields {
 pub struct CallArg {
     pub name: Option<String>,
     pub value: Expr,
+    /// `...xs`: the array's elements, each passed as an argument of its own
+    pub spread: bool,
     pub span: Span,
 }
 
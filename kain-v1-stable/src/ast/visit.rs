@@ -0,0 +1,843 @@
+//! AST traversal
+//!
+//! `Visitor` walks a program by shared reference, `VisitorMut` by mutable
+//! reference so a pass can rewrite nodes in place. Every `visit_*` method
+//! defaults to the matching `walk_*` function, which visits the node's
+//! children; override only the nodes you care about and call `walk_*` from the
+//! override to keep descending.
+//!
+//! ```ignore
+//! struct CountCalls(usize);
+//!
+//! impl Visitor for CountCalls {
+//!     fn visit_expr(&mut self, expr: &Expr) {
+//!         if let Expr::Call { .. } = expr {
+//!             self.0 += 1;
+//!         }
+//!         walk_expr(self, expr);
+//!     }
+//! }
+//! ```
+//!
+//! Children are visited in source order. Types, attributes and macro bodies
+//! are not traversed.
+
+use super::*;
+
+pub trait Visitor: Sized {
+    fn visit_program(&mut self, program: &Program) {
+        walk_program(self, program)
+    }
+
+    fn visit_item(&mut self, item: &Item) {
+        walk_item(self, item)
+    }
+
+    fn visit_function(&mut self, function: &Function) {
+        walk_function(self, function)
+    }
+
+    fn visit_param(&mut self, param: &Param) {
+        walk_param(self, param)
+    }
+
+    fn visit_block(&mut self, block: &Block) {
+        walk_block(self, block)
+    }
+
+    fn visit_stmt(&mut self, stmt: &Stmt) {
+        walk_stmt(self, stmt)
+    }
+
+    fn visit_expr(&mut self, expr: &Expr) {
+        walk_expr(self, expr)
+    }
+
+    fn visit_pattern(&mut self, pattern: &Pattern) {
+        walk_pattern(self, pattern)
+    }
+
+    fn visit_jsx(&mut self, node: &JSXNode) {
+        walk_jsx(self, node)
+    }
+}
+
+pub fn walk_program<V: Visitor>(v: &mut V, program: &Program) {
+    for item in &program.items {
+        v.visit_item(item);
+    }
+}
+
+pub fn walk_item<V: Visitor>(v: &mut V, item: &Item) {
+    match item {
+        Item::Function(f) => v.visit_function(f),
+        Item::Component(c) => walk_component(v, c),
+        Item::Shader(s) => walk_shader(v, s),
+        Item::Actor(a) => walk_actor(v, a),
+        Item::Struct(s) => walk_struct(v, s),
+        Item::Trait(t) => walk_trait(v, t),
+        Item::Impl(i) => walk_impl(v, i),
+        Item::Const(c) => v.visit_expr(&c.value),
+        Item::Static(s) => v.visit_expr(&s.value),
+        Item::Comptime(c) => v.visit_block(&c.body),
+        Item::Test(t) => v.visit_block(&t.body),
+        Item::Cfg(c) => v.visit_item(&c.item),
+        Item::Enum(_) | Item::TypeAlias(_) | Item::Effect(_) | Item::Use(_) | Item::Mod(_) | Item::Macro(_) | Item::Error(_) => {}
+    }
+}
+
+pub fn walk_component<V: Visitor>(v: &mut V, component: &Component) {
+    for param in &component.props {
+        v.visit_param(param);
+    }
+    for state in &component.state {
+        v.visit_expr(&state.initial);
+    }
+    for method in &component.methods {
+        v.visit_function(method);
+    }
+    v.visit_jsx(&component.body);
+}
+
+pub fn walk_shader<V: Visitor>(v: &mut V, shader: &Shader) {
+    for param in &shader.inputs {
+        v.visit_param(param);
+    }
+    v.visit_block(&shader.body);
+}
+
+pub fn walk_actor<V: Visitor>(v: &mut V, actor: &Actor) {
+    for state in &actor.state {
+        v.visit_expr(&state.initial);
+    }
+    for handler in &actor.handlers {
+        for param in &handler.params {
+            v.visit_param(param);
+        }
+        v.visit_block(&handler.body);
+    }
+}
+
+pub fn walk_struct<V: Visitor>(v: &mut V, def: &Struct) {
+    for field in &def.fields {
+        if let Some(default) = &field.default {
+            v.visit_expr(default);
+        }
+    }
+}
+
+pub fn walk_trait<V: Visitor>(v: &mut V, def: &Trait) {
+    for method in &def.methods {
+        for param in &method.params {
+            v.visit_param(param);
+        }
+        if let Some(body) = &method.default_impl {
+            v.visit_block(body);
+        }
+    }
+}
+
+pub fn walk_impl<V: Visitor>(v: &mut V, imp: &Impl) {
+    for c in &imp.consts {
+        v.visit_expr(&c.value);
+    }
+    for method in &imp.methods {
+        v.visit_function(method);
+    }
+}
+
+pub fn walk_function<V: Visitor>(v: &mut V, function: &Function) {
+    for param in &function.params {
+        v.visit_param(param);
+    }
+    v.visit_block(&function.body);
+}
+
+pub fn walk_param<V: Visitor>(v: &mut V, param: &Param) {
+    if let Some(default) = &param.default {
+        v.visit_expr(default);
+    }
+}
+
+pub fn walk_block<V: Visitor>(v: &mut V, block: &Block) {
+    for stmt in &block.stmts {
+        v.visit_stmt(stmt);
+    }
+}
+
+pub fn walk_stmt<V: Visitor>(v: &mut V, stmt: &Stmt) {
+    match stmt {
+        Stmt::Let { pattern, value, .. } => {
+            v.visit_pattern(pattern);
+            if let Some(value) = value {
+                v.visit_expr(value);
+            }
+        }
+        Stmt::Expr(e) => v.visit_expr(e),
+        Stmt::Return(e, _) | Stmt::Break(e, _, _) => {
+            if let Some(e) = e {
+                v.visit_expr(e);
+            }
+        }
+        Stmt::Continue(..) => {}
+        Stmt::For { binding, iter, body, else_branch, .. } => {
+            v.visit_pattern(binding);
+            v.visit_expr(iter);
+            v.visit_block(body);
+            if let Some(b) = else_branch {
+                v.visit_block(b);
+            }
+        }
+        Stmt::While { condition, body, else_branch, .. } => {
+            v.visit_expr(condition);
+            v.visit_block(body);
+            if let Some(b) = else_branch {
+                v.visit_block(b);
+            }
+        }
+        Stmt::Loop { body, .. } | Stmt::Region { body, .. } => v.visit_block(body),
+        Stmt::Item(item) => v.visit_item(item),
+    }
+}
+
+pub fn walk_expr<V: Visitor>(v: &mut V, expr: &Expr) {
+    crate::stack::grow(|| match expr {
+        Expr::Int(..)
+        | Expr::Float(..)
+        | Expr::String(..)
+        | Expr::Bool(..)
+        | Expr::Char(..)
+        | Expr::None(_)
+        | Expr::Ident(..)
+        | Expr::Local { .. }
+        | Expr::Continue(..)
+        | Expr::Error(_) => {}
+        Expr::FString(exprs, _)
+        | Expr::Array(exprs, _)
+        | Expr::Tuple(exprs, _)
+        | Expr::MacroCall { args: exprs, .. } => {
+            for e in exprs {
+                v.visit_expr(e);
+            }
+        }
+        Expr::Binary { left: a, right: b, .. }
+        | Expr::Index { object: a, index: b, .. }
+        | Expr::Assign { target: a, value: b, .. } => {
+            v.visit_expr(a);
+            v.visit_expr(b);
+        }
+        Expr::Unary { operand: e, .. }
+        | Expr::Field { object: e, .. }
+        | Expr::Ref { value: e, .. }
+        | Expr::Cast { value: e, .. }
+        | Expr::Deref(e, _)
+        | Expr::Try(e, _)
+        | Expr::Await(e, _)
+        | Expr::Comptime(e, _)
+        | Expr::Paren(e, _) => v.visit_expr(e),
+        Expr::Return(e, _) | Expr::Break(e, _, _) => {
+            if let Some(e) = e {
+                v.visit_expr(e);
+            }
+        }
+        Expr::Call { callee, args, .. } => {
+            v.visit_expr(callee);
+            for arg in args {
+                v.visit_expr(&arg.value);
+            }
+        }
+        Expr::MethodCall { receiver, args, .. } => {
+            v.visit_expr(receiver);
+            for arg in args {
+                v.visit_expr(&arg.value);
+            }
+        }
+        Expr::Struct { fields, base, .. } => {
+            if let Some(base) = base {
+                v.visit_expr(base);
+            }
+            for (_, e) in fields {
+                v.visit_expr(e);
+            }
+        }
+        Expr::Spawn { init: fields, .. } => {
+            for (_, e) in fields {
+                v.visit_expr(e);
+            }
+        }
+        Expr::EnumVariant { fields, .. } => match fields {
+            EnumVariantFields::Unit => {}
+            EnumVariantFields::Tuple(values) => {
+                for e in values {
+                    v.visit_expr(e);
+                }
+            }
+            EnumVariantFields::Struct(fields) => {
+                for (_, e) in fields {
+                    v.visit_expr(e);
+                }
+            }
+        },
+        Expr::SendMsg { target, data, .. } => {
+            v.visit_expr(target);
+            for (_, e) in data {
+                v.visit_expr(e);
+            }
+        }
+        Expr::Range { start, end, .. } => {
+            if let Some(start) = start {
+                v.visit_expr(start);
+            }
+            if let Some(end) = end {
+                v.visit_expr(end);
+            }
+        }
+        Expr::If { condition, then_branch, else_branch, .. } => {
+            v.visit_expr(condition);
+            v.visit_block(then_branch);
+            let mut branch = else_branch.as_deref();
+            while let Some(b) = branch {
+                branch = match b {
+                    ElseBranch::Else(block) => {
+                        v.visit_block(block);
+                        None
+                    }
+                    ElseBranch::ElseIf(cond, block, next) => {
+                        v.visit_expr(cond);
+                        v.visit_block(block);
+                        next.as_deref()
+                    }
+                };
+            }
+        }
+        Expr::Match { scrutinee, arms, .. } => {
+            v.visit_expr(scrutinee);
+            for arm in arms {
+                v.visit_pattern(&arm.pattern);
+                if let Some(guard) = &arm.guard {
+                    v.visit_expr(guard);
+                }
+                v.visit_expr(&arm.body);
+            }
+        }
+        Expr::Lambda { params, body, .. } => {
+            for param in params {
+                v.visit_param(param);
+            }
+            v.visit_expr(body);
+        }
+        Expr::Handle { handler, body, .. } => {
+            v.visit_expr(handler);
+            v.visit_block(body);
+        }
+        Expr::Block(block, _) => v.visit_block(block),
+        Expr::JSX(node, _) => v.visit_jsx(node),
+    })
+}
+
+pub fn walk_pattern<V: Visitor>(v: &mut V, pattern: &Pattern) {
+    match pattern {
+        Pattern::Wildcard(_) | Pattern::Binding { .. } => {}
+        Pattern::Literal(e) => v.visit_expr(e),
+        Pattern::Struct { fields, .. } => {
+            for (_, p) in fields {
+                v.visit_pattern(p);
+            }
+        }
+        Pattern::Tuple(patterns, _)
+        | Pattern::Or(patterns, _)
+        | Pattern::Slice { patterns, .. } => {
+            for p in patterns {
+                v.visit_pattern(p);
+            }
+        }
+        Pattern::Variant { fields, .. } => match fields {
+            VariantPatternFields::Unit => {}
+            VariantPatternFields::Tuple(patterns) => {
+                for p in patterns {
+                    v.visit_pattern(p);
+                }
+            }
+            VariantPatternFields::Struct(fields) => {
+                for (_, p) in fields {
+                    v.visit_pattern(p);
+                }
+            }
+        },
+        Pattern::Range { start, end, .. } => {
+            if let Some(start) = start {
+                v.visit_expr(start);
+            }
+            if let Some(end) = end {
+                v.visit_expr(end);
+            }
+        }
+    }
+}
+
+pub fn walk_jsx<V: Visitor>(v: &mut V, node: &JSXNode) {
+    crate::stack::grow(|| match node {
+        JSXNode::Element { attributes: attrs, children, .. }
+        | JSXNode::ComponentCall { props: attrs, children, .. } => {
+            for attr in attrs {
+                if let JSXAttrValue::Expr(e) = &attr.value {
+                    v.visit_expr(e);
+                }
+            }
+            for child in children {
+                v.visit_jsx(child);
+            }
+        }
+        JSXNode::Expression(e) => v.visit_expr(e),
+        JSXNode::Text(..) => {}
+        JSXNode::For { iter, body, .. } => {
+            v.visit_expr(iter);
+            v.visit_jsx(body);
+        }
+        JSXNode::If { condition, then_branch, else_branch, .. } => {
+            v.visit_expr(condition);
+            v.visit_jsx(then_branch);
+            if let Some(else_branch) = else_branch {
+                v.visit_jsx(else_branch);
+            }
+        }
+        JSXNode::Fragment(children, _) => {
+            for child in children {
+                v.visit_jsx(child);
+            }
+        }
+    })
+}
+
+/// Like `Visitor`, but with mutable access so nodes can be rewritten in place
+pub trait VisitorMut: Sized {
+    fn visit_program_mut(&mut self, program: &mut Program) {
+        walk_program_mut(self, program)
+    }
+
+    fn visit_item_mut(&mut self, item: &mut Item) {
+        walk_item_mut(self, item)
+    }
+
+    fn visit_function_mut(&mut self, function: &mut Function) {
+        walk_function_mut(self, function)
+    }
+
+    fn visit_param_mut(&mut self, param: &mut Param) {
+        walk_param_mut(self, param)
+    }
+
+    fn visit_block_mut(&mut self, block: &mut Block) {
+        walk_block_mut(self, block)
+    }
+
+    fn visit_stmt_mut(&mut self, stmt: &mut Stmt) {
+        walk_stmt_mut(self, stmt)
+    }
+
+    fn visit_expr_mut(&mut self, expr: &mut Expr) {
+        walk_expr_mut(self, expr)
+    }
+
+    fn visit_pattern_mut(&mut self, pattern: &mut Pattern) {
+        walk_pattern_mut(self, pattern)
+    }
+
+    fn visit_jsx_mut(&mut self, node: &mut JSXNode) {
+        walk_jsx_mut(self, node)
+    }
+}
+
+pub fn walk_program_mut<V: VisitorMut>(v: &mut V, program: &mut Program) {
+    for item in &mut program.items {
+        v.visit_item_mut(item);
+    }
+}
+
+pub fn walk_item_mut<V: VisitorMut>(v: &mut V, item: &mut Item) {
+    match item {
+        Item::Function(f) => v.visit_function_mut(f),
+        Item::Component(c) => walk_component_mut(v, c),
+        Item::Shader(s) => walk_shader_mut(v, s),
+        Item::Actor(a) => walk_actor_mut(v, a),
+        Item::Struct(s) => walk_struct_mut(v, s),
+        Item::Trait(t) => walk_trait_mut(v, t),
+        Item::Impl(i) => walk_impl_mut(v, i),
+        Item::Const(c) => v.visit_expr_mut(&mut c.value),
+        Item::Static(s) => v.visit_expr_mut(&mut s.value),
+        Item::Comptime(c) => v.visit_block_mut(&mut c.body),
+        Item::Test(t) => v.visit_block_mut(&mut t.body),
+        Item::Cfg(c) => v.visit_item_mut(&mut c.item),
+        Item::Enum(_) | Item::TypeAlias(_) | Item::Effect(_) | Item::Use(_) | Item::Mod(_) | Item::Macro(_) | Item::Error(_) => {}
+    }
+}
+
+pub fn walk_component_mut<V: VisitorMut>(v: &mut V, component: &mut Component) {
+    for param in &mut component.props {
+        v.visit_param_mut(param);
+    }
+    for state in &mut component.state {
+        v.visit_expr_mut(&mut state.initial);
+    }
+    for method in &mut component.methods {
+        v.visit_function_mut(method);
+    }
+    v.visit_jsx_mut(&mut component.body);
+}
+
+pub fn walk_shader_mut<V: VisitorMut>(v: &mut V, shader: &mut Shader) {
+    for param in &mut shader.inputs {
+        v.visit_param_mut(param);
+    }
+    v.visit_block_mut(&mut shader.body);
+}
+
+pub fn walk_actor_mut<V: VisitorMut>(v: &mut V, actor: &mut Actor) {
+    for state in &mut actor.state {
+        v.visit_expr_mut(&mut state.initial);
+    }
+    for handler in &mut actor.handlers {
+        for param in &mut handler.params {
+            v.visit_param_mut(param);
+        }
+        v.visit_block_mut(&mut handler.body);
+    }
+}
+
+pub fn walk_struct_mut<V: VisitorMut>(v: &mut V, def: &mut Struct) {
+    for field in &mut def.fields {
+        if let Some(default) = &mut field.default {
+            v.visit_expr_mut(default);
+        }
+    }
+}
+
+pub fn walk_trait_mut<V: VisitorMut>(v: &mut V, def: &mut Trait) {
+    for method in &mut def.methods {
+        for param in &mut method.params {
+            v.visit_param_mut(param);
+        }
+        if let Some(body) = &mut method.default_impl {
+            v.visit_block_mut(body);
+        }
+    }
+}
+
+pub fn walk_impl_mut<V: VisitorMut>(v: &mut V, imp: &mut Impl) {
+    for c in &mut imp.consts {
+        v.visit_expr_mut(&mut c.value);
+    }
+    for method in &mut imp.methods {
+        v.visit_function_mut(method);
+    }
+}
+
+pub fn walk_function_mut<V: VisitorMut>(v: &mut V, function: &mut Function) {
+    for param in &mut function.params {
+        v.visit_param_mut(param);
+    }
+    v.visit_block_mut(&mut function.body);
+}
+
+pub fn walk_param_mut<V: VisitorMut>(v: &mut V, param: &mut Param) {
+    if let Some(default) = &mut param.default {
+        v.visit_expr_mut(default);
+    }
+}
+
+pub fn walk_block_mut<V: VisitorMut>(v: &mut V, block: &mut Block) {
+    for stmt in &mut block.stmts {
+        v.visit_stmt_mut(stmt);
+    }
+}
+
+pub fn walk_stmt_mut<V: VisitorMut>(v: &mut V, stmt: &mut Stmt) {
+    match stmt {
+        Stmt::Let { pattern, value, .. } => {
+            v.visit_pattern_mut(pattern);
+            if let Some(value) = value {
+                v.visit_expr_mut(value);
+            }
+        }
+        Stmt::Expr(e) => v.visit_expr_mut(e),
+        Stmt::Return(e, _) | Stmt::Break(e, _, _) => {
+            if let Some(e) = e {
+                v.visit_expr_mut(e);
+            }
+        }
+        Stmt::Continue(..) => {}
+        Stmt::For { binding, iter, body, else_branch, .. } => {
+            v.visit_pattern_mut(binding);
+            v.visit_expr_mut(iter);
+            v.visit_block_mut(body);
+            if let Some(b) = else_branch {
+                v.visit_block_mut(b);
+            }
+        }
+        Stmt::While { condition, body, else_branch, .. } => {
+            v.visit_expr_mut(condition);
+            v.visit_block_mut(body);
+            if let Some(b) = else_branch {
+                v.visit_block_mut(b);
+            }
+        }
+        Stmt::Loop { body, .. } | Stmt::Region { body, .. } => v.visit_block_mut(body),
+        Stmt::Item(item) => v.visit_item_mut(item),
+    }
+}
+
+pub fn walk_expr_mut<V: VisitorMut>(v: &mut V, expr: &mut Expr) {
+    crate::stack::grow(|| match expr {
+        Expr::Int(..)
+        | Expr::Float(..)
+        | Expr::String(..)
+        | Expr::Bool(..)
+        | Expr::Char(..)
+        | Expr::None(_)
+        | Expr::Ident(..)
+        | Expr::Local { .. }
+        | Expr::Continue(..)
+        | Expr::Error(_) => {}
+        Expr::FString(exprs, _)
+        | Expr::Array(exprs, _)
+        | Expr::Tuple(exprs, _)
+        | Expr::MacroCall { args: exprs, .. } => {
+            for e in exprs {
+                v.visit_expr_mut(e);
+            }
+        }
+        Expr::Binary { left: a, right: b, .. }
+        | Expr::Index { object: a, index: b, .. }
+        | Expr::Assign { target: a, value: b, .. } => {
+            v.visit_expr_mut(a);
+            v.visit_expr_mut(b);
+        }
+        Expr::Unary { operand: e, .. }
+        | Expr::Field { object: e, .. }
+        | Expr::Ref { value: e, .. }
+        | Expr::Cast { value: e, .. }
+        | Expr::Deref(e, _)
+        | Expr::Try(e, _)
+        | Expr::Await(e, _)
+        | Expr::Comptime(e, _)
+        | Expr::Paren(e, _) => v.visit_expr_mut(e),
+        Expr::Return(e, _) | Expr::Break(e, _, _) => {
+            if let Some(e) = e {
+                v.visit_expr_mut(e);
+            }
+        }
+        Expr::Call { callee, args, .. } => {
+            v.visit_expr_mut(callee);
+            for arg in args {
+                v.visit_expr_mut(&mut arg.value);
+            }
+        }
+        Expr::MethodCall { receiver, args, .. } => {
+            v.visit_expr_mut(receiver);
+            for arg in args {
+                v.visit_expr_mut(&mut arg.value);
+            }
+        }
+        Expr::Struct { fields, base, .. } => {
+            if let Some(base) = base {
+                v.visit_expr_mut(base);
+            }
+            for (_, e) in fields {
+                v.visit_expr_mut(e);
+            }
+        }
+        Expr::Spawn { init: fields, .. } => {
+            for (_, e) in fields {
+                v.visit_expr_mut(e);
+            }
+        }
+        Expr::EnumVariant { fields, .. } => match fields {
+            EnumVariantFields::Unit => {}
+            EnumVariantFields::Tuple(values) => {
+                for e in values {
+                    v.visit_expr_mut(e);
+                }
+            }
+            EnumVariantFields::Struct(fields) => {
+                for (_, e) in fields {
+                    v.visit_expr_mut(e);
+                }
+            }
+        },
+        Expr::SendMsg { target, data, .. } => {
+            v.visit_expr_mut(target);
+            for (_, e) in data {
+                v.visit_expr_mut(e);
+            }
+        }
+        Expr::Range { start, end, .. } => {
+            if let Some(start) = start {
+                v.visit_expr_mut(start);
+            }
+            if let Some(end) = end {
+                v.visit_expr_mut(end);
+            }
+        }
+        Expr::If { condition, then_branch, else_branch, .. } => {
+            v.visit_expr_mut(condition);
+            v.visit_block_mut(then_branch);
+            let mut branch = else_branch.as_deref_mut();
+            while let Some(b) = branch {
+                branch = match b {
+                    ElseBranch::Else(block) => {
+                        v.visit_block_mut(block);
+                        None
+                    }
+                    ElseBranch::ElseIf(cond, block, next) => {
+                        v.visit_expr_mut(cond);
+                        v.visit_block_mut(block);
+                        next.as_deref_mut()
+                    }
+                };
+            }
+        }
+        Expr::Match { scrutinee, arms, .. } => {
+            v.visit_expr_mut(scrutinee);
+            for arm in arms {
+                v.visit_pattern_mut(&mut arm.pattern);
+                if let Some(guard) = &mut arm.guard {
+                    v.visit_expr_mut(guard);
+                }
+                v.visit_expr_mut(&mut arm.body);
+            }
+        }
+        Expr::Lambda { params, body, .. } => {
+            for param in params {
+                v.visit_param_mut(param);
+            }
+            v.visit_expr_mut(body);
+        }
+        Expr::Handle { handler, body, .. } => {
+            v.visit_expr_mut(handler);
+            v.visit_block_mut(body);
+        }
+        Expr::Block(block, _) => v.visit_block_mut(block),
+        Expr::JSX(node, _) => v.visit_jsx_mut(node),
+    })
+}
+
+pub fn walk_pattern_mut<V: VisitorMut>(v: &mut V, pattern: &mut Pattern) {
+    match pattern {
+        Pattern::Wildcard(_) | Pattern::Binding { .. } => {}
+        Pattern::Literal(e) => v.visit_expr_mut(e),
+        Pattern::Struct { fields, .. } => {
+            for (_, p) in fields {
+                v.visit_pattern_mut(p);
+            }
+        }
+        Pattern::Tuple(patterns, _)
+        | Pattern::Or(patterns, _)
+        | Pattern::Slice { patterns, .. } => {
+            for p in patterns {
+                v.visit_pattern_mut(p);
+            }
+        }
+        Pattern::Variant { fields, .. } => match fields {
+            VariantPatternFields::Unit => {}
+            VariantPatternFields::Tuple(patterns) => {
+                for p in patterns {
+                    v.visit_pattern_mut(p);
+                }
+            }
+            VariantPatternFields::Struct(fields) => {
+                for (_, p) in fields {
+                    v.visit_pattern_mut(p);
+                }
+            }
+        },
+        Pattern::Range { start, end, .. } => {
+            if let Some(start) = start {
+                v.visit_expr_mut(start);
+            }
+            if let Some(end) = end {
+                v.visit_expr_mut(end);
+            }
+        }
+    }
+}
+
+pub fn walk_jsx_mut<V: VisitorMut>(v: &mut V, node: &mut JSXNode) {
+    crate::stack::grow(|| match node {
+        JSXNode::Element { attributes: attrs, children, .. }
+        | JSXNode::ComponentCall { props: attrs, children, .. } => {
+            for attr in attrs {
+                if let JSXAttrValue::Expr(e) = &mut attr.value {
+                    v.visit_expr_mut(e);
+                }
+            }
+            for child in children {
+                v.visit_jsx_mut(child);
+            }
+        }
+        JSXNode::Expression(e) => v.visit_expr_mut(e),
+        JSXNode::Text(..) => {}
+        JSXNode::For { iter, body, .. } => {
+            v.visit_expr_mut(iter);
+            v.visit_jsx_mut(body);
+        }
+        JSXNode::If { condition, then_branch, else_branch, .. } => {
+            v.visit_expr_mut(condition);
+            v.visit_jsx_mut(then_branch);
+            if let Some(else_branch) = else_branch {
+                v.visit_jsx_mut(else_branch);
+            }
+        }
+        JSXNode::Fragment(children, _) => {
+            for child in children {
+                v.visit_jsx_mut(child);
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{lexer::Lexer, parser::Parser};
+
+    #[test]
+    fn test_visitors_reach_nested_expressions() {
+        struct Idents(Vec<String>);
+
+        impl Visitor for Idents {
+            fn visit_expr(&mut self, expr: &Expr) {
+                if let Expr::Ident(name, _) = expr {
+                    self.0.push(name.clone());
+                }
+                walk_expr(self, expr);
+            }
+        }
+
+        struct Rename;
+
+        impl VisitorMut for Rename {
+            fn visit_expr_mut(&mut self, expr: &mut Expr) {
+                if let Expr::Ident(name, _) = expr {
+                    if name == "a" {
+                        *name = "z".to_string();
+                    }
+                }
+                walk_expr_mut(self, expr);
+            }
+        }
+
+        let source = "fn main():\n    let f = |x| x + a\n    if b:\n        g(c.d[e])\n    else if f(1):\n        return [h, (i, a)]\n";
+        let tokens = Lexer::new(source).tokenize().unwrap();
+        let mut program = Parser::new(&tokens).parse().unwrap();
+
+        let mut idents = Idents(Vec::new());
+        idents.visit_program(&program);
+        assert_eq!(idents.0, ["x", "a", "b", "g", "c", "e", "f", "h", "i", "a"]);
+
+        Rename.visit_program_mut(&mut program);
+        let mut idents = Idents(Vec::new());
+        idents.visit_program(&program);
+        assert!(!idents.0.contains(&"a".to_string()));
+        assert_eq!(idents.0.iter().filter(|n| *n == "z").count(), 2);
+    }
+}
@@ -0,0 +1,344 @@
+//! KOS engine bridge
+//!
+//! Programs drive a running KOS engine over a local TCP socket. Every message
+//! is one line of JSON with a `type` tag. The client opens with `hello` and
+//! the engine answers `welcome` with the protocol version it speaks; anything
+//! but the same version ends the connection before a command is sent:
+//!
+//! ```text
+//! > {"type":"hello","protocol":1,"client":"kain 0.1.2"}
+//! < {"type":"welcome","protocol":1,"engine":"kos 0.4"}
+//! > {"type":"spawn","kind":"cube","position":[1.0,2.0,0.0]}
+//! < {"type":"spawned","id":1}
+//! > {"type":"transform","id":1,"position":[3.0,2.0,0.0]}
+//! < {"type":"done"}
+//! > {"type":"query","id":1}
+//! < {"type":"entity","id":1,"kind":"cube","position":[3.0,2.0,0.0],"rotation":[0.0,0.0,0.0],"scale":[1.0,1.0,1.0]}
+//! > {"type":"reload","asset":"water","path":"/game/Shaders/water.usf"}
+//! < {"type":"done"}
+//! ```
+//!
+//! A command the engine can't carry out is answered with
+//! `{"type":"error","message":"..."}`. The engine listens on [`DEFAULT_ADDR`]
+//! unless `KAIN_KOS_BRIDGE` names another address. [`MockServer`] speaks the
+//! engine's side with an in-memory world, for tests and for developing against
+//! without the engine (`kain dev kos-mock`).
+
+use std::collections::{BTreeMap, HashMap};
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+/// Version of the protocol below; bumped whenever a message changes shape
+pub const PROTOCOL_VERSION: u32 = 1;
+
+/// Where the engine listens unless `KAIN_KOS_BRIDGE` says otherwise
+pub const DEFAULT_ADDR: &str = "127.0.0.1:7878";
+
+/// How long the client waits for a reply before giving up on the engine
+const REPLY_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// A message from a program to the engine
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Command {
+    /// Opens every connection
+    Hello { protocol: u32, client: String },
+    /// Create an entity of `kind` (`"cube"`, or a prefab the engine knows)
+    Spawn { kind: String, position: [f64; 3] },
+    /// Change the parts of an entity's transform that are given
+    Transform {
+        id: u64,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        position: Option<[f64; 3]>,
+        /// Euler angles in degrees
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        rotation: Option<[f64; 3]>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        scale: Option<[f64; 3]>,
+    },
+    Destroy { id: u64 },
+    Query { id: u64 },
+    /// Load `asset` again from `path`, which `kain --watch` just rebuilt
+    Reload { asset: String, path: String },
+}
+
+/// The engine's answer to a [`Command`]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Reply {
+    Welcome { protocol: u32, engine: String },
+    Spawned { id: u64 },
+    Done,
+    Entity(Entity),
+    Error { message: String },
+}
+
+/// An entity as `query` reports it
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Entity {
+    pub id: u64,
+    pub kind: String,
+    pub position: [f64; 3],
+    pub rotation: [f64; 3],
+    pub scale: [f64; 3],
+}
+
+/// A connection to the engine, past the handshake
+#[derive(Debug)]
+pub struct Client {
+    reader: BufReader<TcpStream>,
+    writer: TcpStream,
+    /// How the engine named itself in `welcome`
+    pub engine: String,
+}
+
+impl Client {
+    /// Connect to the engine at `addr` and check it speaks [`PROTOCOL_VERSION`]
+    pub fn connect(addr: &str) -> Result<Client, String> {
+        let stream = TcpStream::connect(addr)
+            .map_err(|e| format!("cannot reach the KOS engine at {} ({}); is it running?", addr, e))?;
+        stream.set_read_timeout(Some(REPLY_TIMEOUT)).map_err(|e| e.to_string())?;
+        let _ = stream.set_nodelay(true);
+        let reader = BufReader::new(stream.try_clone().map_err(|e| e.to_string())?);
+        let mut client = Client { reader, writer: stream, engine: String::new() };
+
+        let hello = Command::Hello { protocol: PROTOCOL_VERSION, client: format!("kain {}", env!("CARGO_PKG_VERSION")) };
+        match client.request(&hello)? {
+            Reply::Welcome { protocol, engine } if protocol == PROTOCOL_VERSION => {
+                client.engine = engine;
+                Ok(client)
+            }
+            Reply::Welcome { protocol, engine } => Err(format!(
+                "{} speaks bridge protocol {}, this compiler speaks {}",
+                engine, protocol, PROTOCOL_VERSION
+            )),
+            other => Err(format!("expected a welcome from the engine, got {:?}", other)),
+        }
+    }
+
+    /// Connect to `KAIN_KOS_BRIDGE`, or [`DEFAULT_ADDR`] when it isn't set
+    pub fn connect_default() -> Result<Client, String> {
+        let addr = std::env::var("KAIN_KOS_BRIDGE").unwrap_or_else(|_| DEFAULT_ADDR.to_string());
+        Client::connect(&addr)
+    }
+
+    /// Send `command` and wait for its reply; an `error` reply is returned as `Err`
+    pub fn request(&mut self, command: &Command) -> Result<Reply, String> {
+        let mut line = serde_json::to_string(command).map_err(|e| e.to_string())?;
+        line.push('\n');
+        self.writer
+            .write_all(line.as_bytes())
+            .and_then(|_| self.writer.flush())
+            .map_err(|e| format!("lost the KOS engine: {}", e))?;
+
+        let mut reply = String::new();
+        match self.reader.read_line(&mut reply) {
+            Ok(0) => return Err("the KOS engine closed the connection".to_string()),
+            Ok(_) => {}
+            Err(e) => return Err(format!("no reply from the KOS engine: {}", e)),
+        }
+        match serde_json::from_str(&reply) {
+            Ok(Reply::Error { message }) => Err(message),
+            Ok(reply) => Ok(reply),
+            Err(e) => Err(format!("unreadable reply from the KOS engine: {}", e)),
+        }
+    }
+
+    /// Spawn an entity, returning its id
+    pub fn spawn(&mut self, kind: &str, position: [f64; 3]) -> Result<u64, String> {
+        match self.request(&Command::Spawn { kind: kind.to_string(), position })? {
+            Reply::Spawned { id } => Ok(id),
+            other => Err(unexpected("spawn", &other)),
+        }
+    }
+
+    pub fn transform(&mut self, id: u64, position: Option<[f64; 3]>, rotation: Option<[f64; 3]>, scale: Option<[f64; 3]>) -> Result<(), String> {
+        match self.request(&Command::Transform { id, position, rotation, scale })? {
+            Reply::Done => Ok(()),
+            other => Err(unexpected("transform", &other)),
+        }
+    }
+
+    pub fn destroy(&mut self, id: u64) -> Result<(), String> {
+        match self.request(&Command::Destroy { id })? {
+            Reply::Done => Ok(()),
+            other => Err(unexpected("destroy", &other)),
+        }
+    }
+
+    pub fn query(&mut self, id: u64) -> Result<Entity, String> {
+        match self.request(&Command::Query { id })? {
+            Reply::Entity(entity) => Ok(entity),
+            other => Err(unexpected("query", &other)),
+        }
+    }
+
+    pub fn reload(&mut self, asset: &str, path: &str) -> Result<(), String> {
+        match self.request(&Command::Reload { asset: asset.to_string(), path: path.to_string() })? {
+            Reply::Done => Ok(()),
+            other => Err(unexpected("reload", &other)),
+        }
+    }
+}
+
+fn unexpected(command: &str, reply: &Reply) -> String {
+    format!("the KOS engine answered {} with {:?}", command, reply)
+}
+
+/// The engine's side of the protocol over an in-memory world. Each connection
+/// is served on its own thread; the server runs until the process exits.
+pub struct MockServer {
+    addr: SocketAddr,
+    world: Arc<Mutex<World>>,
+}
+
+#[derive(Default)]
+struct World {
+    next_id: u64,
+    entities: BTreeMap<u64, Entity>,
+    /// Every command received after a handshake, in arrival order
+    log: Vec<Command>,
+}
+
+impl MockServer {
+    /// Listen on `addr`; port 0 picks a free one, see [`MockServer::addr`]
+    pub fn start(addr: &str) -> io::Result<MockServer> {
+        let listener = TcpListener::bind(addr)?;
+        let server = MockServer { addr: listener.local_addr()?, world: Arc::default() };
+        let world = server.world.clone();
+        std::thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                let world = world.clone();
+                std::thread::spawn(move || serve(stream, &world));
+            }
+        });
+        Ok(server)
+    }
+
+    pub fn addr(&self) -> String {
+        self.addr.to_string()
+    }
+
+    /// Commands handled so far, handshakes left out
+    pub fn commands(&self) -> Vec<Command> {
+        self.world.lock().unwrap_or_else(|e| e.into_inner()).log.clone()
+    }
+
+    /// Entities alive in the world, by id
+    pub fn entities(&self) -> HashMap<u64, Entity> {
+        let world = self.world.lock().unwrap_or_else(|e| e.into_inner());
+        world.entities.iter().map(|(id, entity)| (*id, entity.clone())).collect()
+    }
+}
+
+/// Answer one connection's commands until it closes or fails the handshake
+fn serve(stream: TcpStream, world: &Mutex<World>) {
+    let Ok(reader) = stream.try_clone() else { return };
+    let mut writer = stream;
+    let mut greeted = false;
+    for line in BufReader::new(reader).lines() {
+        let Ok(line) = line else { return };
+        let reply = match serde_json::from_str::<Command>(&line) {
+            Err(e) => Reply::Error { message: format!("malformed command: {}", e) },
+            Ok(Command::Hello { protocol, .. }) if protocol == PROTOCOL_VERSION => {
+                greeted = true;
+                Reply::Welcome { protocol: PROTOCOL_VERSION, engine: "kos-mock".to_string() }
+            }
+            // Answer with our version so the client can report the mismatch, then hang up
+            Ok(Command::Hello { .. }) => {
+                let welcome = Reply::Welcome { protocol: PROTOCOL_VERSION, engine: "kos-mock".to_string() };
+                let _ = send(&mut writer, &welcome);
+                return;
+            }
+            Ok(_) if !greeted => Reply::Error { message: "send hello before any command".to_string() },
+            Ok(command) => world.lock().unwrap_or_else(|e| e.into_inner()).apply(command),
+        };
+        if send(&mut writer, &reply).is_err() {
+            return;
+        }
+    }
+}
+
+fn send(writer: &mut TcpStream, reply: &Reply) -> io::Result<()> {
+    let mut line = serde_json::to_string(reply).map_err(io::Error::other)?;
+    line.push('\n');
+    writer.write_all(line.as_bytes())
+}
+
+impl World {
+    fn apply(&mut self, command: Command) -> Reply {
+        self.log.push(command.clone());
+        let missing = |id: u64| Reply::Error { message: format!("no entity {}", id) };
+        match command {
+            Command::Hello { .. } => Reply::Error { message: "already greeted".to_string() },
+            Command::Spawn { kind, position } => {
+                self.next_id += 1;
+                let id = self.next_id;
+                let entity = Entity { id, kind, position, rotation: [0.0; 3], scale: [1.0; 3] };
+                self.entities.insert(id, entity);
+                Reply::Spawned { id }
+            }
+            Command::Transform { id, position, rotation, scale } => {
+                let Some(entity) = self.entities.get_mut(&id) else { return missing(id) };
+                entity.position = position.unwrap_or(entity.position);
+                entity.rotation = rotation.unwrap_or(entity.rotation);
+                entity.scale = scale.unwrap_or(entity.scale);
+                Reply::Done
+            }
+            Command::Destroy { id } => match self.entities.remove(&id) {
+                Some(_) => Reply::Done,
+                None => missing(id),
+            },
+            Command::Query { id } => match self.entities.get(&id) {
+                Some(entity) => Reply::Entity(entity.clone()),
+                None => missing(id),
+            },
+            // The mock has no assets to reload; the command log shows what was asked
+            Command::Reload { .. } => Reply::Done,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_client_drives_the_mock_engine() {
+        let server = MockServer::start("127.0.0.1:0").unwrap();
+        let mut client = Client::connect(&server.addr()).unwrap();
+        assert_eq!(client.engine, "kos-mock");
+
+        let id = client.spawn("cube", [1.0, 2.0, 0.0]).unwrap();
+        client.transform(id, Some([3.0, 2.0, 0.0]), None, Some([2.0; 3])).unwrap();
+        let entity = client.query(id).unwrap();
+        assert_eq!((entity.position, entity.rotation, entity.scale), ([3.0, 2.0, 0.0], [0.0; 3], [2.0; 3]));
+        client.destroy(id).unwrap();
+        assert_eq!(client.query(id).unwrap_err(), format!("no entity {}", id));
+        assert!(server.entities().is_empty());
+        assert_eq!(server.commands()[0], Command::Spawn { kind: "cube".to_string(), position: [1.0, 2.0, 0.0] });
+
+        // The wire format is the documented one
+        let line = serde_json::to_string(&Command::Transform { id: 1, position: Some([3.0, 2.0, 0.0]), rotation: None, scale: None }).unwrap();
+        assert_eq!(line, r#"{"type":"transform","id":1,"position":[3.0,2.0,0.0]}"#);
+
+        // A client that skips the handshake is refused
+        let mut raw = TcpStream::connect(server.addr()).unwrap();
+        raw.write_all(b"{\"type\":\"destroy\",\"id\":1}\n").unwrap();
+        let mut reply = String::new();
+        BufReader::new(raw).read_line(&mut reply).unwrap();
+        assert!(reply.contains("send hello before any command"), "{}", reply);
+
+        let source = format!(
+            "fn main():\n    println(kos_connect(\"{}\"))\n    let id = kos_spawn(\"crate\", 1, 2)\n    kos_transform(id, (4, 5, 6), None, [2, 2, 2])\n    let e = kos_query(id)\n    println(e.kind + \" \" + str(e.position) + \" \" + str(e.scale))\n    kos_destroy(id)\n    kos_destroy(id)\n",
+            server.addr()
+        );
+        let result = crate::eval_snippet(&source, &crate::CompileOptions::default());
+        assert_eq!(result.stdout.lines().collect::<Vec<_>>(), ["kos-mock", "crate (4, 5, 6) (2, 2, 2)"]);
+        assert!(result.diagnostics[0].to_string().contains("kos_destroy: no entity 2"), "{:?}", result.diagnostics);
+    }
+}
@@ -0,0 +1,102 @@
+//! Build scripts
+//!
+//! A project's `build.kn`, or the file `[build].script` names in KAIN.toml,
+//! runs in the interpreter around `kain build`, so code generation, asset
+//! downloads and shader copies need no shell scripts. It may define two hooks,
+//! either of which can be left out:
+//!
+//! - `pre_build()` runs once, before the entry file is read, so sources it
+//!   writes are compiled
+//! - `post_build()` runs after each target's output is written
+//!
+//! Both read the build through `build_profile()`, `build_targets()`,
+//! `build_out_dir()`, and in `post_build` `build_target()` and
+//! `build_output()`; `env()` reads the environment. A hook that fails fails
+//! the build.
+
+use std::path::Path;
+
+use crate::error::{KainError, KainResult};
+use crate::runtime::{self, BuildContext, Env};
+use crate::{CompileOptions, CompileTarget, Parser};
+
+pub struct BuildScript {
+    env: Env,
+    context: BuildContext,
+}
+
+impl BuildScript {
+    /// Load the script at `path` and register its items, ready to run its hooks
+    pub fn load(path: &Path, context: BuildContext, options: &CompileOptions) -> KainResult<Self> {
+        let source = crate::vfs::read(path)?;
+        let tokens = crate::source_lexer(&source.text, options).tokenize()?;
+        // The script's `use` paths are relative to it
+        let options = CompileOptions { source: Some(path.to_path_buf()), ..options.clone() };
+        let program = crate::lower(Parser::new(&tokens).parse()?, CompileTarget::Interpret, &options)?;
+        let mut env = Env::new();
+        env.set_edition(options.edition);
+        env.set_build_context(context.clone());
+        runtime::load_program(&mut env, &program)?;
+        Ok(BuildScript { env, context })
+    }
+
+    pub fn pre_build(&mut self) -> KainResult<()> {
+        self.run("pre_build")
+    }
+
+    /// Run `post_build` for the output of `target` just written to `output`
+    pub fn post_build(&mut self, target: &str, output: &Path) -> KainResult<()> {
+        self.env.set_build_context(BuildContext {
+            target: Some(target.to_string()),
+            output: Some(output.display().to_string()),
+            ..self.context.clone()
+        });
+        self.run("post_build")
+    }
+
+    fn run(&mut self, hook: &str) -> KainResult<()> {
+        runtime::call_if_defined(&mut self.env, hook)
+            .map(|_| ())
+            .map_err(|e| KainError::runtime(format!("build script {} failed: {}", hook, e)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hooks_see_the_build() {
+        let dir = std::env::temp_dir().join(format!("kain-buildscript-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let log = dir.join("log.txt");
+        let script = dir.join("build.kn");
+        std::fs::write(
+            &script,
+            format!(
+                "fn pre_build():\n    write_file(\"{log}\", build_profile() + \" \" + build_out_dir() + \" \" + to_string(len(build_targets())))\n\nfn post_build():\n    write_file(\"{log}\", read_file(\"{log}\") + \" \" + build_target() + \" \" + build_output())\n",
+                log = log.display()
+            ),
+        )
+        .unwrap();
+
+        let context = BuildContext {
+            profile: "release".to_string(),
+            targets: vec!["wasm".to_string(), "js".to_string()],
+            out_dir: "dist".to_string(),
+            ..Default::default()
+        };
+        let mut build = BuildScript::load(&script, context, &CompileOptions::default()).unwrap();
+        build.pre_build().unwrap();
+        build.post_build("js", Path::new("dist/main.js")).unwrap();
+        assert_eq!(std::fs::read_to_string(&log).unwrap(), "release dist 2 js dist/main.js");
+
+        // Hooks are optional, and a failing one names itself
+        std::fs::write(&script, "fn post_build():\n    let zero = 0\n    println(1 / zero)\n").unwrap();
+        let mut build = BuildScript::load(&script, BuildContext::default(), &CompileOptions::default()).unwrap();
+        build.pre_build().unwrap();
+        let err = build.post_build("wasm", Path::new("main.wasm")).unwrap_err();
+        assert!(err.to_string().contains("build script post_build failed"), "{}", err);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
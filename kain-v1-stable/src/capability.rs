@@ -0,0 +1,255 @@
+//! Target capability matrix
+//!
+//! Not every backend can lower every construct: SPIR-V has no actors, only
+//! the interpreter can call into Python, and LLVM has no DOM for JSX. This pass
+//! runs after type checking and reports the first unsupported construct with
+//! the feature and target named, instead of letting codegen panic or silently
+//! drop the code.
+
+use crate::ast::*;
+use crate::ast::visit::{walk_expr, walk_item, walk_param, walk_pattern, walk_stmt, Visitor};
+use crate::codegen::{range_chain, RangeOp};
+use crate::error::{KainError, KainResult};
+use crate::span::Span;
+use crate::CompileTarget;
+
+/// A language feature that only some backends implement
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Capability {
+    /// `actor` declarations, `spawn` and `send`
+    Actors,
+    /// Components and JSX expressions
+    Jsx,
+    /// `py_eval`, `py_exec` and `py_import`
+    PythonFfi,
+    /// `static mut` items
+    Statics,
+    /// `with handler` blocks, which reroute effects while the program runs
+    EffectHandlers,
+    /// Actors that declare `mailbox(...)`, whose sends block, drop or fail when it is full
+    BoundedMailboxes,
+    /// `persist state`, which snapshots an actor's state to disk
+    PersistentState,
+    /// `...rest` parameters and `...xs` arguments
+    Variadics,
+    /// `pair.0` and tuple patterns like `let (a, b) = pair`
+    TupleAccess,
+    /// `let` with a pattern other than a name, including `guard let ... else`
+    DestructuringLet,
+    /// Ranges used as values rather than iterated by a `for` loop
+    RangeValues,
+}
+
+impl Capability {
+    pub fn name(self) -> &'static str {
+        match self {
+            Capability::Actors => "actors",
+            Capability::Jsx => "JSX and components",
+            Capability::PythonFfi => "Python FFI calls",
+            Capability::Statics => "`static mut` items",
+            Capability::EffectHandlers => "effect handlers",
+            Capability::BoundedMailboxes => "bounded mailboxes",
+            Capability::PersistentState => "persisted actor states",
+            Capability::Variadics => "variadic parameters and spread arguments",
+            Capability::TupleAccess => "tuple fields and tuple patterns",
+            Capability::DestructuringLet => "`let` and `guard let` patterns",
+            Capability::RangeValues => "ranges outside `for` loops",
+        }
+    }
+
+    /// Targets whose backend implements this feature
+    pub fn targets(self) -> &'static [CompileTarget] {
+        use CompileTarget::*;
+        match self {
+            Capability::Actors => &[Wasm, Wat, Llvm, Interpret, Test],
+            Capability::Jsx => &[Js, Wasm, Wat, Hybrid, Interpret, Test],
+            Capability::PythonFfi => &[Interpret, Test],
+            Capability::Statics => &[Wasm, Wat, Hybrid, Js, Llvm, Rust, Interpret, Test],
+            Capability::EffectHandlers => &[Interpret, Test],
+            Capability::BoundedMailboxes => &[Interpret, Test],
+            Capability::PersistentState => &[Interpret, Test],
+            Capability::Variadics => &[Js, Interpret, Test],
+            Capability::TupleAccess => &[Js, Rust, Interpret, Test],
+            Capability::DestructuringLet => &[Js, Rust, Interpret, Test],
+            Capability::RangeValues => &[Js, Rust, Interpret, Test],
+        }
+    }
+}
+
+/// Whether `target` can compile code that uses `capability`
+pub fn supports(target: CompileTarget, capability: Capability) -> bool {
+    capability.targets().contains(&target)
+}
+
+/// Report the first construct in `program` that `target` cannot compile
+pub fn check(program: &Program, target: CompileTarget) -> KainResult<()> {
+    let mut checker = Checker { target, error: None };
+    checker.visit_program(program);
+    checker.error.map_or(Ok(()), Err)
+}
+
+struct Checker {
+    target: CompileTarget,
+    error: Option<KainError>,
+}
+
+impl Checker {
+    fn require(&mut self, capability: Capability, span: Span) {
+        if self.error.is_some() || supports(self.target, capability) {
+            return;
+        }
+        let supported: Vec<&str> = capability.targets().iter().map(|t| t.cfg_names()[0]).collect();
+        self.error = Some(KainError::type_error(
+            format!(
+                "{} are not supported by the {} target (supported by: {})",
+                capability.name(),
+                self.target.cfg_names()[0],
+                supported.join(", ")
+            ),
+            span,
+        ));
+    }
+}
+
+impl Visitor for Checker {
+    fn visit_item(&mut self, item: &Item) {
+        if self.error.is_some() {
+            return;
+        }
+        match item {
+            Item::Component(c) => self.require(Capability::Jsx, c.span),
+            Item::Actor(a) => {
+                self.require(Capability::Actors, a.span);
+                if let Some(mailbox) = &a.mailbox {
+                    self.require(Capability::BoundedMailboxes, mailbox.span);
+                }
+                if let Some(state) = a.state.iter().find(|s| s.persist) {
+                    self.require(Capability::PersistentState, state.span);
+                }
+            }
+            Item::Static(s) => self.require(Capability::Statics, s.span),
+            _ => walk_item(self, item),
+        }
+    }
+
+    fn visit_expr(&mut self, expr: &Expr) {
+        if self.error.is_some() {
+            return;
+        }
+        match expr {
+            Expr::Spawn { span, .. } | Expr::SendMsg { span, .. } => self.require(Capability::Actors, *span),
+            Expr::JSX(_, span) => self.require(Capability::Jsx, *span),
+            Expr::Handle { span, .. } => self.require(Capability::EffectHandlers, *span),
+            Expr::Range { span, .. } => self.require(Capability::RangeValues, *span),
+            Expr::Call { callee, span, args } => {
+                if let Expr::Ident(name, _) = &**callee {
+                    if matches!(name.as_str(), "py_eval" | "py_exec" | "py_import") {
+                        self.require(Capability::PythonFfi, *span);
+                    }
+                }
+                if let Some(arg) = args.iter().find(|a| a.spread) {
+                    self.require(Capability::Variadics, arg.span);
+                }
+            }
+            Expr::Field { field, span, .. } if field.parse::<usize>().is_ok() => self.require(Capability::TupleAccess, *span),
+            Expr::MethodCall { args, .. } => {
+                if let Some(arg) = args.iter().find(|a| a.spread) {
+                    self.require(Capability::Variadics, arg.span);
+                }
+            }
+            _ => {}
+        }
+        walk_expr(self, expr);
+    }
+
+    fn visit_stmt(&mut self, stmt: &Stmt) {
+        if let Stmt::Let { pattern, span, .. } = stmt {
+            if !matches!(pattern, Pattern::Binding { .. } | Pattern::Wildcard(_)) {
+                self.require(Capability::DestructuringLet, *span);
+            }
+        }
+        // Every backend lowers a range a `for` loop iterates along with the loop;
+        // only its bounds and steps are values
+        if let Stmt::For { binding, iter, body, else_branch, .. } = stmt {
+            if let Some(chain) = range_chain(iter) {
+                self.visit_pattern(binding);
+                let steps = chain.ops.iter().filter_map(|op| match op {
+                    RangeOp::Step(k) => Some(*k),
+                    RangeOp::Rev => None,
+                });
+                for value in chain.start.into_iter().chain(chain.end).chain(steps) {
+                    self.visit_expr(value);
+                }
+                self.visit_block(body);
+                if let Some(b) = else_branch {
+                    self.visit_block(b);
+                }
+                return;
+            }
+        }
+        walk_stmt(self, stmt);
+    }
+
+    fn visit_param(&mut self, param: &Param) {
+        if param.variadic {
+            self.require(Capability::Variadics, param.span);
+        }
+        walk_param(self, param);
+    }
+
+    fn visit_pattern(&mut self, pattern: &Pattern) {
+        if let Pattern::Tuple(_, span) = pattern {
+            self.require(Capability::TupleAccess, *span);
+        }
+        walk_pattern(self, pattern);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{compile, lexer::Lexer, parser::Parser};
+
+    fn check_source(source: &str, target: CompileTarget) -> KainResult<()> {
+        let tokens = Lexer::new(source).tokenize()?;
+        check(&Parser::new(&tokens).parse()?, target)
+    }
+
+    #[test]
+    fn test_unsupported_features_are_named() {
+        let py = "fn main():\n    let x = py_eval(\"1 + 1\")\n";
+        let err = check_source(py, CompileTarget::Wasm).unwrap_err().to_string();
+        assert!(err.contains("Python FFI calls are not supported by the wasm target"));
+        assert!(check_source(py, CompileTarget::Interpret).is_ok());
+
+        let jsx = "fn view():\n    return <div>hi</div>\n";
+        let err = compile(jsx, CompileTarget::Llvm).unwrap_err().to_string();
+        assert!(err.contains("JSX and components are not supported by the llvm target"));
+        assert!(check_source(jsx, CompileTarget::Js).is_ok());
+
+        let rest = "fn sum(...nums):\n    return len(nums)\n";
+        let err = check_source(rest, CompileTarget::Llvm).unwrap_err().to_string();
+        assert!(err.contains("variadic parameters and spread arguments are not supported by the llvm target"));
+        assert!(check_source(rest, CompileTarget::Js).is_ok());
+
+        let pair = "fn main():\n    let p = (1, 2)\n    println(p.1)\n";
+        let err = check_source(pair, CompileTarget::Wasm).unwrap_err().to_string();
+        assert!(err.contains("tuple fields and tuple patterns are not supported by the wasm target"));
+        let destructure = "fn main():\n    for (k, v) in [(1, 2)]:\n        println(k)\n";
+        let err = compile(destructure, CompileTarget::Llvm).unwrap_err().to_string();
+        assert!(err.contains("tuple fields and tuple patterns are not supported by the llvm target"));
+        assert!(check_source(pair, CompileTarget::Js).is_ok());
+
+        let guarded = "fn main():\n    guard let Some(x) = Some(1) else: return\n    println(x)\n";
+        let err = check_source(guarded, CompileTarget::Wasm).unwrap_err().to_string();
+        assert!(err.contains("`let` and `guard let` patterns are not supported by the wasm target"));
+        assert!(check_source(guarded, CompileTarget::Rust).is_ok());
+
+        let looped = "fn main():\n    for i in (0..=10).step(2).rev():\n        println(i)\n";
+        assert!(check_source(looped, CompileTarget::Llvm).is_ok());
+        let held = "fn main():\n    let r = 0..10\n    println(r.contains(3))\n";
+        let err = check_source(held, CompileTarget::Wasm).unwrap_err().to_string();
+        assert!(err.contains("ranges outside `for` loops are not supported by the wasm target"));
+        assert!(check_source(held, CompileTarget::Js).is_ok());
+    }
+}
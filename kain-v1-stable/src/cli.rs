@@ -0,0 +1,1582 @@
+//! KAIN Compiler CLI
+//!
+//! The `kain` binary is [`main`]. A host embedding the compiler registers its
+//! own backends with [`register`](crate::codegen::backend::register) and then
+//! calls [`main`] or [`main_from`], so `--target <name>` reaches them.
+
+use clap::Parser as ClapParser;
+use std::path::PathBuf;
+use std::fs;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use crate::codegen::backend::{self, CodegenBackend};
+use crate::{compile, compile_with, compile_with_backend, CompileOptions, CompileTarget, CrateType, VERSION, LANGUAGE_NAME};
+use crate::bridge;
+use crate::packager;
+use crate::lsp;
+use crate::filecheck;
+use crate::conformance::{self, Backend, Outcome};
+use crate::doctest;
+use crate::edition::{self, Edition};
+use crate::log;
+use crate::pretty::PrettyOptions;
+use crate::replay::Recording;
+use crate::schedule::ScheduleOptions;
+use crate::runtime::{TraceMode, TraceOptions};
+use crate::vfs;
+
+#[derive(ClapParser, Debug)]
+#[command(name = "kain")]
+#[command(author = "Kipp")]
+#[command(version = VERSION)]
+#[command(about = "The Ultimate Programming Language Compiler", long_about = None)]
+struct Args {
+    #[command(subcommand)]
+    command: Option<Commands>,
+
+    /// Source file to compile (legacy positional argument)
+    input: Option<PathBuf>,
+
+    /// Output file
+    #[arg(short, long)]
+    output: Option<PathBuf>,
+
+    /// Compilation target
+    #[arg(short, long, default_value = "wasm")]
+    target: String,
+
+    /// Run immediately after compilation
+    #[arg(short, long)]
+    run: bool,
+
+    /// Watch for file changes and recompile; shader targets also ask a running engine to reload them
+    #[arg(short, long)]
+    watch: bool,
+
+    /// Output format for the wasm target: wasm (binary) or wat (text); for any
+    /// target, `symbols` writes the names it exports and their signatures as JSON
+    #[arg(long, value_name = "FORMAT")]
+    emit: Option<String>,
+
+    /// Emit AST for debugging  
+    #[arg(long)]
+    emit_ast: bool,
+
+    /// Emit typed AST
+    #[arg(long)]
+    emit_typed: bool,
+
+    /// Verbose output: compiler debug logs (same as KAIN_LOG=compiler=debug)
+    #[arg(short, long)]
+    verbose: bool,
+
+    /// Target plugin name for UE5 shader copy (defaults to the profile's `target.ue5-shader.plugin`)
+    #[arg(long)]
+    plugin: Option<String>,
+
+    /// Base plugins directory (defaults to the profile's, then u:\ue_factory\src-plugins)
+    #[arg(long)]
+    plugins_dir: Option<PathBuf>,
+
+    /// Print planned actions without executing
+    #[arg(long)]
+    dry_run: bool,
+
+    /// Treat transpiler warnings as errors when supported
+    #[arg(long)]
+    strict: bool,
+
+    /// Produce byte-identical output for identical source (for caching and verification)
+    #[arg(long, global = true)]
+    deterministic: bool,
+
+    /// Allow comptime code to access files, the network and the host process
+    #[arg(long, global = true)]
+    allow_comptime_io: bool,
+
+    /// Enable features for `@cfg(feature = "...")` (comma separated)
+    #[arg(long, global = true, value_delimiter = ',')]
+    features: Vec<String>,
+
+    /// Language version to compile single files under (projects use `language_version` in KAIN.toml)
+    #[arg(long, global = true, value_name = "VERSION")]
+    edition: Option<String>,
+
+    /// Columns a tab in indentation counts for (projects can set `tab_width` under `[build]`)
+    #[arg(long, global = true, value_name = "N", value_parser = clap::value_parser!(u8).range(1..=16))]
+    tab_width: Option<u8>,
+
+    /// Use WASM SIMD instructions for vector math and array sums
+    #[arg(long, global = true)]
+    enable_simd: bool,
+
+    /// What the llvm target builds: an executable (bin), or an object file (lib),
+    /// static library (staticlib) or shared library (cdylib) with a C header
+    #[arg(long, global = true, value_name = "TYPE")]
+    crate_type: Option<String>,
+
+    /// Break down the bytes of wasm output by section and function
+    #[arg(long, global = true)]
+    size_report: bool,
+
+    /// Build profile from KAIN.toml: `[profile.<name>]`, or the built-in debug and release
+    #[arg(long, global = true, value_name = "NAME")]
+    profile: Option<String>,
+
+    /// SQL schema file that `query!` statements are checked against
+    #[arg(long, global = true, value_name = "FILE")]
+    schema: Option<PathBuf>,
+
+    /// Arguments for the program when it is interpreted, after `--`
+    #[arg(last = true)]
+    program_args: Vec<String>,
+}
+
+#[derive(clap::Subcommand, Debug)]
+enum Commands {
+    /// Initialize a new KAIN project
+    Init {
+        /// Project name
+        #[arg(default_value = ".")]
+        path: PathBuf,
+        
+        /// Explicit project name
+        #[arg(long)]
+        name: Option<String>,
+    },
+    
+    /// Start the Language Server
+    Lsp,
+
+    /// Build project or file. Without input, reads KAIN.toml for multi-target build.
+    Build {
+        /// Optional input file. If omitted, builds all targets from KAIN.toml
+        input: Option<PathBuf>,
+        
+        /// Override targets (comma-separated: wasm,js,rust); with an input file, compile it for each
+        #[arg(long, value_delimiter = ',')]
+        targets: Option<Vec<String>>,
+    },
+    
+    /// Delete build outputs: the project's output directory, or with --profile just that profile's
+    Clean,
+
+    /// Run a file (explicit command)
+    Run {
+        input: PathBuf,
+
+        /// Print live values, actors with their mailbox depths and allocations per function when the program ends
+        #[arg(long)]
+        runtime_stats: bool,
+
+        /// Log each call with its arguments (`calls`, the default), also each statement with where it is (`stmts`), or also the values they produce (`values`)
+        #[arg(long, value_name = "WHAT", num_args = 0..=1, require_equals = true, default_missing_value = "calls")]
+        trace: Option<String>,
+
+        /// Write the trace to a file instead of stderr
+        #[arg(long, value_name = "FILE", requires = "trace")]
+        trace_file: Option<PathBuf>,
+
+        /// Record the clock, random numbers, stdin, environment, HTTP responses and actor message order to a file
+        #[arg(long, value_name = "FILE", conflicts_with = "replay")]
+        record: Option<PathBuf>,
+
+        /// Run again from a `--record` file, reproducing the recorded run
+        #[arg(long, value_name = "FILE")]
+        replay: Option<PathBuf>,
+
+        /// Levels of nested arrays and structs `print` and `dbg` show before `[...]` (default 32, 0 for no limit)
+        #[arg(long, value_name = "N")]
+        print_depth: Option<usize>,
+
+        /// Elements of an array, tuple or struct `print` and `dbg` show before `... N more` (default 100, 0 for all)
+        #[arg(long, value_name = "N")]
+        print_elements: Option<usize>,
+
+        /// Spread printed values over several lines, indented by this many spaces per level
+        #[arg(long, value_name = "N")]
+        print_indent: Option<usize>,
+
+        /// Arguments for the program, after `--`
+        #[arg(last = true)]
+        program_args: Vec<String>,
+    },
+
+    /// Run a file's `test` blocks, or with --doc the ```kain examples in its doc comments
+    Test {
+        input: PathBuf,
+
+        /// Run doc comment examples instead of `test` blocks
+        #[arg(long)]
+        doc: bool,
+
+        /// Run each test under this many actor schedules, each choosing a different message order
+        #[arg(long, value_name = "N")]
+        schedules: Option<u64>,
+
+        /// Number of the first schedule, to rerun one that failed
+        #[arg(long, value_name = "N", default_value_t = 0, requires = "schedules")]
+        schedule_seed: u64,
+
+        /// Try message orders in turn instead of at random
+        #[arg(long, requires = "schedules")]
+        systematic: bool,
+    },
+
+    /// Parse and type check .kn files without compiling them
+    Check {
+        /// Files or directories to check
+        #[arg(default_value = ".")]
+        paths: Vec<PathBuf>,
+    },
+
+    /// Apply suggested fixes, such as edition migrations, to .kn files in place
+    Fix {
+        /// Files or directories to fix
+        #[arg(default_value = ".")]
+        paths: Vec<PathBuf>,
+
+        /// Only report files that need fixing; exit with an error if any do
+        #[arg(long)]
+        check: bool,
+    },
+
+    /// Generate Markdown documentation from `///` doc comments
+    Doc {
+        input: PathBuf,
+
+        /// Output file (defaults to <input>.md)
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
+
+    /// Describe the project in KAIN.toml: targets and their outputs, modules, files, dependencies and toolchain
+    Metadata {
+        /// Print the description as JSON, for build systems and CI
+        #[arg(long)]
+        json: bool,
+    },
+
+    /// Install compiler versions and pick which one builds projects
+    Toolchain {
+        #[command(subcommand)]
+        command: ToolchainCommands,
+    },
+
+    /// Manage this compiler
+    #[command(name = "self")]
+    SelfManage {
+        #[command(subcommand)]
+        command: SelfCommands,
+    },
+
+    /// Read what the compiler recorded in its output
+    Inspect {
+        #[command(subcommand)]
+        command: InspectCommands,
+    },
+
+    /// Compiler developer tools
+    Dev {
+        #[command(subcommand)]
+        command: DevCommands,
+    }
+}
+
+#[derive(clap::Subcommand, Debug)]
+enum ToolchainCommands {
+    /// List installed versions, marking the default and the one KAIN.toml pins
+    List,
+    /// Download a prebuilt compiler, checking its checksum
+    Install {
+        version: String,
+    },
+    /// Remove an installed version
+    Uninstall {
+        version: String,
+    },
+    /// Run this version outside projects that pin one
+    Default {
+        version: String,
+    },
+    /// Make the project in this directory build with this version (`compiler` in KAIN.toml)
+    Pin {
+        version: String,
+    },
+}
+
+#[derive(clap::Subcommand, Debug)]
+enum SelfCommands {
+    /// Replace this compiler with the newest release
+    Update {
+        /// Only report whether a newer release exists
+        #[arg(long)]
+        check: bool,
+    },
+}
+
+#[derive(clap::Subcommand, Debug)]
+enum InspectCommands {
+    /// Show the compiler version, target, profile and source hash an artifact was built with
+    Artifact {
+        path: PathBuf,
+    },
+}
+
+#[derive(clap::Subcommand, Debug)]
+enum DevCommands {
+    /// Check emitted code against `// CHECK:` comments in .kn test files
+    Filecheck {
+        /// Test files or directories
+        #[arg(default_value = "tests/codegen")]
+        paths: Vec<PathBuf>,
+    },
+    /// Run the programs under conformance/ on every backend and compare their output
+    Conformance {
+        /// Programs or directories
+        #[arg(default_value = "conformance")]
+        paths: Vec<PathBuf>,
+        /// Only run these backends (interpret, js, wasm, llvm)
+        #[arg(long = "backend", value_delimiter = ',')]
+        backends: Vec<String>,
+    },
+    /// Serve the KOS bridge protocol with an in-memory world, to develop engine code without the engine
+    KosMock {
+        /// Address to listen on
+        #[arg(long, default_value = bridge::DEFAULT_ADDR)]
+        addr: String,
+    },
+}
+
+fn run_compile(input: &PathBuf, target: CompileTarget, output: Option<&PathBuf>, _emit_ast: bool, _emit_typed: bool, options: &CompileOptions) -> bool {
+    // The program's `use` paths are relative to it
+    let options = &CompileOptions { source: Some(input.clone()), ..options.clone() };
+
+    // Read source
+    let source = match vfs::read(input) {
+        Ok(file) => {
+            crate::crash::set_input(file.clone(), options);
+            crate::crash::set_target(target);
+            file.text.clone()
+        }
+        Err(e) => {
+            eprintln!(" Failed to read {}: {}", input.display(), e);
+            return false;
+        }
+    };
+
+    log::debug("compiler", &format!("Compiling {}", input.display()));
+    log::debug("compiler", &format!("Source: {} bytes, {} lines", source.len(), source.lines().count()));
+
+    // Code a newer edition would reject still compiles, with a warning
+    let filename = input.file_name().and_then(|s| s.to_str()).unwrap_or("input.kn");
+    let diag = crate::diagnostics::Diagnostics::new(&source, filename);
+    for lint in edition::migration_lints(&source, options.edition) {
+        eprint!("{}", diag.format_warning(&lint));
+    }
+
+    // Compile
+    match compile_with(&source, target, options) {
+        Ok(compiled) => {
+            for (phase, time) in &compiled.timings {
+                log::debug("compiler", &format!("{}: {:?}", phase.name(), time));
+            }
+            let compiled_output = compiled.artifact;
+            if target == CompileTarget::Interpret || target == CompileTarget::Test {
+                println!(" Execution complete");
+            } else {
+                // Determine where to write the primary output (IR for LLVM, Binary for others)
+                let output_path = match output {
+                    // For LLVM, we always write the IR file first
+                    // If user specified -o main.exe, we write to main.ll
+                    Some(out) if target == CompileTarget::Llvm => out.with_extension("ll"),
+                    Some(out) => out.clone(),
+                    None => match default_output(input, target.cfg_names()[0], packager::target_extension(target), options) {
+                        Ok(path) => path,
+                        Err(e) => {
+                            eprintln!(" Cannot create the output directory: {}", e);
+                            return false;
+                        }
+                    },
+                };
+                
+                // Profiles with `wasm_opt` shrink wasm however it is built
+                let profile = options.profile.as_deref().filter(|_| target == CompileTarget::Wasm).map(|name| packager::file_profile(input, name));
+                let compiled_output = match profile {
+                    Some(Ok(profile)) if profile.wasm_opt => packager::optimize_wasm(compiled_output, &output_path, options.simd),
+                    Some(Err(e)) => {
+                        eprintln!(" {}", e);
+                        return false;
+                    }
+                    _ => compiled_output,
+                };
+
+                if let Err(e) = crate::write_output(&output_path, &compiled_output, options) {
+                    eprintln!(" Failed to write output: {}", e);
+                    return false;
+                }
+                
+                println!(" Compiled to: {} ({} bytes)", output_path.display(), compiled_output.len());
+                if options.size_report && target == CompileTarget::Wasm {
+                    match crate::codegen::wasm_size::report(&compiled_output) {
+                        Ok(report) => print!("{}", report),
+                        Err(e) => eprintln!(" No size report: {}", e),
+                    }
+                }
+
+                // Post-processing for LLVM
+                if let Some(header) = &compiled.header {
+                    let header_path = output_path.with_extension("h");
+                    if let Err(e) = crate::write_output(&header_path, header.as_bytes(), options) {
+                        eprintln!(" Failed to write header: {}", e);
+                        return false;
+                    }
+                    println!(" Generated header: {}", header_path.display());
+
+                    let library_path = packager::linked_path(&output_path, options.crate_type);
+                    match packager::link_crate(&output_path, &library_path, options.crate_type, &[]) {
+                        Ok(()) => println!(" Generated library: {}", library_path.display()),
+                        Err(e) => eprintln!(" Building the library failed: {}", e),
+                    }
+                } else if target == CompileTarget::Llvm {
+                    let exe_path = output.cloned().unwrap_or_else(|| packager::linked_path(&output_path, crate::CrateType::Bin));
+
+                    println!(" Linking executable...");
+                    match packager::link_executable(&output_path, &exe_path, &[]) {
+                        Ok(()) => println!(" Generated executable: {}", exe_path.display()),
+                        Err(e) => eprintln!(" Linking failed: {}", e),
+                    }
+                }
+            }
+            true
+        }
+        Err(e) => {
+            // Use pretty error formatting
+            let filename = input.file_name()
+                .and_then(|s| s.to_str())
+                .unwrap_or("input.kn");
+            let diag = crate::diagnostics::Diagnostics::new(&source, filename);
+            eprint!("{}", diag.format_error(&e));
+            false
+        }
+    }
+}
+
+fn run_doc(input: &PathBuf, output: Option<PathBuf>) -> bool {
+    let source = match vfs::read(input) {
+        Ok(file) => file.text.clone(),
+        Err(e) => {
+            eprintln!(" Failed to read {}: {}", input.display(), e);
+            return false;
+        }
+    };
+
+    let parsed = crate::Lexer::new(&source)
+        .tokenize()
+        .and_then(|tokens| crate::Parser::new(&tokens).parse());
+    let program = match parsed {
+        Ok(p) => p,
+        Err(e) => {
+            let filename = input.file_name().and_then(|s| s.to_str()).unwrap_or("input.kn");
+            let diag = crate::diagnostics::Diagnostics::new(&source, filename);
+            eprint!("{}", diag.format_error(&e));
+            return false;
+        }
+    };
+
+    let title = input.file_stem().and_then(|s| s.to_str()).unwrap_or("module");
+    let markdown = crate::docgen::render_markdown(&program, title);
+    let output_path = output.unwrap_or_else(|| input.with_extension("md"));
+    if let Err(e) = fs::write(&output_path, markdown) {
+        eprintln!(" Failed to write output: {}", e);
+        return false;
+    }
+    println!(" Docs written to: {}", output_path.display());
+    true
+}
+
+fn run_toolchain(command: ToolchainCommands) -> crate::error::KainResult<()> {
+    use crate::release;
+    match command {
+        ToolchainCommands::List => {
+            let default = release::default_version();
+            let pinned = std::env::current_dir()
+                .ok()
+                .and_then(|cwd| packager::load_manifest(&cwd).ok())
+                .and_then(|manifest| manifest.package.compiler);
+            let mut versions = release::installed();
+            if !versions.iter().any(|version| version == VERSION) {
+                versions.push(VERSION.to_string());
+                versions.sort_by(|a, b| release::compare_versions(a, b));
+            }
+            for version in versions {
+                let mut notes = Vec::new();
+                if version == VERSION {
+                    notes.push("this compiler");
+                }
+                if default.as_deref().unwrap_or(VERSION) == version {
+                    notes.push("default");
+                }
+                if pinned.as_deref() == Some(version.as_str()) {
+                    notes.push("pinned in KAIN.toml");
+                }
+                if notes.is_empty() {
+                    println!(" {}", version);
+                } else {
+                    println!(" {} ({})", version, notes.join(", "));
+                }
+            }
+            Ok(())
+        }
+        ToolchainCommands::Install { version } => {
+            let index = release::ReleaseIndex::fetch(&release::index_url())?;
+            release::install(&index, &version).map(|_| ())
+        }
+        ToolchainCommands::Uninstall { version } => release::uninstall(&version),
+        ToolchainCommands::Default { version } => release::set_default(&version),
+        ToolchainCommands::Pin { version } => release::pin(&version),
+    }
+}
+
+/// Compile `input` with a backend registered through `codegen::backend`
+fn run_backend_compile(input: &PathBuf, plugin: &dyn CodegenBackend, output: Option<&PathBuf>, options: &CompileOptions) -> bool {
+    let source = match vfs::read(input) {
+        Ok(file) => file.text.clone(),
+        Err(e) => {
+            eprintln!(" Failed to read {}: {}", input.display(), e);
+            return false;
+        }
+    };
+
+    match compile_with_backend(&source, plugin, options) {
+        Ok(compiled_output) => {
+            let output_path = match output {
+                Some(path) => path.clone(),
+                None => match default_output(input, plugin.name(), plugin.file_extension(), options) {
+                    Ok(path) => path,
+                    Err(e) => {
+                        eprintln!(" Cannot create the output directory: {}", e);
+                        return false;
+                    }
+                },
+            };
+            if let Err(e) = fs::write(&output_path, &compiled_output) {
+                eprintln!(" Failed to write output: {}", e);
+                return false;
+            }
+            println!(" Compiled to: {} ({} bytes)", output_path.display(), compiled_output.len());
+            true
+        }
+        Err(e) => {
+            let filename = input.file_name().and_then(|s| s.to_str()).unwrap_or("input.kn");
+            eprint!("{}", crate::diagnostics::Diagnostics::new(&source, filename).format_error(&e));
+            false
+        }
+    }
+}
+
+/// Resolve `--emit` against the selected target
+fn apply_emit(target: CompileTarget, emit: Option<&str>) -> Option<CompileTarget> {
+    match (target, emit) {
+        (_, None) | (CompileTarget::Wasm, Some("wasm")) => Some(target),
+        (CompileTarget::Wasm, Some("wat")) => Some(CompileTarget::Wat),
+        (_, Some(format)) => {
+            eprintln!(" Unsupported --emit={} (every target supports: symbols; the wasm target also: wasm, wat)", format);
+            None
+        }
+    }
+}
+
+/// `kain metadata` without `--json`
+fn print_metadata(metadata: &crate::metadata::Metadata) {
+    println!(" {} v{} ({} profile)", metadata.package.name, metadata.package.version, metadata.profile);
+    for target in &metadata.targets {
+        println!(" [{}] -> {}", target.name, target.output.display());
+    }
+    println!(" Modules:");
+    for module in &metadata.modules {
+        match &module.file {
+            Some(file) => println!("   {} ({})", module.name, file.display()),
+            None => println!("   {} (not found)", module.name),
+        }
+    }
+    for dependency in &metadata.dependencies {
+        let state = if dependency.installed { "installed" } else { "not installed" };
+        println!(" Dependency {} v{} ({})", dependency.name, dependency.version, state);
+    }
+    let tools = &metadata.toolchain;
+    for (name, tool) in [("cc", &tools.cc), ("linker", &tools.linker), ("ar", &tools.ar), ("wasm-opt", &tools.wasm_opt)] {
+        println!(" {}: {}", name, tool.as_deref().unwrap_or("not found"));
+    }
+    for error in &metadata.errors {
+        eprintln!(" error: {}", error);
+    }
+}
+
+/// Write what compiling `input` for `target` exports, as JSON, to `output` or `<input>.symbols.json`
+fn run_emit_symbols(input: &PathBuf, target: CompileTarget, output: Option<&PathBuf>, options: &CompileOptions) -> bool {
+    let source = match vfs::read(input) {
+        Ok(file) => file.text.clone(),
+        Err(e) => {
+            eprintln!(" Failed to read {}: {}", input.display(), e);
+            return false;
+        }
+    };
+    let compiled = match compile_with(&source, target, options) {
+        Ok(compiled) => compiled,
+        Err(e) => {
+            let filename = input.file_name().and_then(|s| s.to_str()).unwrap_or("input.kn");
+            eprint!("{}", crate::diagnostics::Diagnostics::new(&source, filename).format_error(&e));
+            return false;
+        }
+    };
+    let path = match output {
+        Some(path) => path.clone(),
+        None => match default_output(input, target.cfg_names()[0], "symbols.json", options) {
+            Ok(path) => path,
+            Err(e) => {
+                eprintln!(" Cannot create the output directory: {}", e);
+                return false;
+            }
+        },
+    };
+    if let Err(e) = fs::write(&path, crate::symbols::to_json(&compiled.exports)) {
+        eprintln!(" Failed to write {}: {}", path.display(), e);
+        return false;
+    }
+    println!(" Wrote {} symbol(s) to {}", compiled.exports.len(), path.display());
+    true
+}
+
+fn run_doctests(input: &PathBuf, options: &CompileOptions) -> bool {
+    let source = match vfs::read(input) {
+        Ok(file) => file.text.clone(),
+        Err(e) => {
+            eprintln!(" Failed to read {}: {}", input.display(), e);
+            return false;
+        }
+    };
+
+    let parsed = crate::Lexer::new(&source)
+        .tokenize()
+        .and_then(|tokens| crate::Parser::new(&tokens).parse());
+    let program = match parsed {
+        Ok(p) => p,
+        Err(e) => {
+            let filename = input.file_name().and_then(|s| s.to_str()).unwrap_or("input.kn");
+            let diag = crate::diagnostics::Diagnostics::new(&source, filename);
+            eprint!("{}", diag.format_error(&e));
+            return false;
+        }
+    };
+
+    let tests = doctest::collect(&program, &source);
+    println!("\n Running Doc Tests...\n");
+    let (mut passed, mut failed, mut ignored) = (0, 0, 0);
+    for test in &tests {
+        print!("doctest {} (line {}) ... ", test.item, test.line);
+        if test.ignored {
+            println!("ignored");
+            ignored += 1;
+            continue;
+        }
+        match doctest::run(test, &program, options) {
+            Ok(()) => {
+                println!("ok");
+                passed += 1;
+            }
+            Err(e) => {
+                println!("FAILED");
+                println!("  Error: {}", e);
+                failed += 1;
+            }
+        }
+    }
+
+    println!(
+        "\nDoc test result: {}. {} passed; {} failed; {} ignored",
+        if failed == 0 { "ok" } else { "FAILED" },
+        passed,
+        failed,
+        ignored
+    );
+    failed == 0
+}
+
+fn run_check(paths: &[PathBuf], edition: Edition) -> bool {
+    let files = crate::fix::collect_sources(paths);
+    if files.is_empty() {
+        eprintln!(" No .kn files found");
+        return false;
+    }
+
+    let mut queries = crate::query::Database::new(edition);
+    let mut errors = 0;
+    for file in &files {
+        let check = match queries.check(file) {
+            Ok(check) => check,
+            Err(e) => {
+                eprintln!(" Failed to read {}: {}", file.display(), e);
+                return false;
+            }
+        };
+        let source = vfs::read(file).map(|f| f.text.clone()).unwrap_or_else(|_| "".into());
+        let filename = file.to_str().unwrap_or("input.kn");
+        let diag = crate::diagnostics::Diagnostics::new(&source, filename);
+        for e in check.errors() {
+            eprint!("{}", diag.format_error(e));
+            errors += 1;
+        }
+    }
+
+    println!(" Checked {} file(s): {} error(s)", files.len(), errors);
+    errors == 0
+}
+
+fn run_fix(paths: &[PathBuf], check: bool, edition: Edition) -> bool {
+    let files = crate::fix::collect_sources(paths);
+    if files.is_empty() {
+        eprintln!(" No .kn files found");
+        return false;
+    }
+
+    let mut changed = 0;
+    for file in &files {
+        let source = match fs::read_to_string(file) {
+            Ok(s) => s,
+            Err(e) => {
+                eprintln!(" Failed to read {}: {}", file.display(), e);
+                return false;
+            }
+        };
+        // A file in a project migrates to the project's edition, as `kain build` compiles it
+        let edition = match packager::file_edition(file, edition) {
+            Ok(edition) => edition,
+            Err(e) => {
+                eprintln!(" {}", e);
+                return false;
+            }
+        };
+        let (fixed, applied) = crate::fix::fix_source(&source, edition);
+        if applied == 0 {
+            continue;
+        }
+        changed += 1;
+        if check {
+            println!(" {} needs {} fix(es)", file.display(), applied);
+        } else if let Err(e) = fs::write(file, fixed) {
+            eprintln!(" Failed to write {}: {}", file.display(), e);
+            return false;
+        } else {
+            println!(" Fixed {} ({} change(s))", file.display(), applied);
+        }
+    }
+
+    println!();
+    println!(" {} of {} file(s) {}", changed, files.len(), if check { "need fixes" } else { "fixed" });
+    !check || changed == 0
+}
+
+fn run_filecheck(paths: &[PathBuf]) -> bool {
+    let files = filecheck::collect_tests(paths);
+    if files.is_empty() {
+        eprintln!(" No .kn test files found");
+        return false;
+    }
+
+    let mut failed = 0;
+    for file in &files {
+        match filecheck::run_file(file) {
+            Ok(()) => println!(" PASS {}", file.display()),
+            Err(e) => {
+                failed += 1;
+                println!(" FAIL {}", file.display());
+                let source = fs::read_to_string(file).unwrap_or_default();
+                let filename = file.file_name().and_then(|n| n.to_str()).unwrap_or("test.kn");
+                let diag = crate::diagnostics::Diagnostics::new(&source, filename);
+                eprint!("{}", diag.format_error(&e));
+            }
+        }
+    }
+
+    println!();
+    println!(" {} passed, {} failed", files.len() - failed, failed);
+    failed == 0
+}
+
+fn run_conformance(paths: &[PathBuf], names: &[String]) -> bool {
+    let mut backends = Vec::new();
+    for name in names {
+        match Backend::from_name(name) {
+            Some(backend) => backends.push(backend),
+            None => {
+                eprintln!(" Unknown backend '{}' (expected interpret, js, wasm or llvm)", name);
+                return false;
+            }
+        }
+    }
+    if backends.is_empty() {
+        backends = Backend::ALL.to_vec();
+    }
+
+    let files = conformance::collect_programs(paths);
+    if files.is_empty() {
+        eprintln!(" No conformance programs with a .out file found");
+        return false;
+    }
+
+    let (mut passed, mut failed, mut skipped) = (0, 0, 0);
+    for file in &files {
+        let results = match conformance::run_file(file, &backends) {
+            Ok(results) => results,
+            Err(e) => {
+                failed += 1;
+                println!(" FAIL {}: {}", file.display(), e);
+                continue;
+            }
+        };
+        for (backend, outcome) in results {
+            match outcome {
+                Outcome::Pass => {
+                    passed += 1;
+                    println!(" PASS {} [{}]", file.display(), backend.name());
+                }
+                Outcome::Fail(why) => {
+                    failed += 1;
+                    println!(" FAIL {} [{}]: {}", file.display(), backend.name(), why);
+                }
+                Outcome::Skipped(why) => {
+                    skipped += 1;
+                    println!(" SKIP {} [{}]: {}", file.display(), backend.name(), why);
+                }
+            }
+        }
+    }
+
+    println!();
+    println!(" {} passed, {} failed, {} skipped", passed, failed, skipped);
+    failed == 0
+}
+
+/// Rebuild `input` with `rebuild` now and whenever it changes, until Ctrl+C
+fn watch_mode(input: PathBuf, mut rebuild: impl FnMut() -> bool) {
+    use notify::{Watcher, RecursiveMode, Event};
+    use std::sync::mpsc::channel;
+    
+    let running = Arc::new(AtomicBool::new(true));
+    let r = running.clone();
+    
+    ctrlc::set_handler(move || {
+        println!("\n Stopping watch mode...");
+        r.store(false, Ordering::SeqCst);
+    }).expect("Error setting Ctrl-C handler");
+    
+    println!(" Watching {} for changes... (Ctrl+C to stop)", input.display());
+    println!("");
+    
+    // Initial compile
+    rebuild();
+    println!("");
+    
+    let (tx, rx) = channel();
+    
+    let mut watcher = notify::recommended_watcher(move |res: Result<Event, notify::Error>| {
+        if let Ok(event) = res {
+            if event.kind.is_modify() {
+                let _ = tx.send(());
+            }
+        }
+    }).expect("Failed to create watcher");
+    
+    watcher.watch(&input, RecursiveMode::NonRecursive).expect("Failed to watch file");
+    
+    // Also watch parent directory in case file is replaced
+    if let Some(parent) = input.parent() {
+        let _ = watcher.watch(parent, RecursiveMode::NonRecursive);
+    }
+    
+    while running.load(Ordering::SeqCst) {
+        match rx.recv_timeout(Duration::from_millis(100)) {
+            Ok(_) => {
+                // Debounce - wait a bit for writes to settle
+                std::thread::sleep(Duration::from_millis(50));
+                // Drain any pending events
+                while rx.try_recv().is_ok() {}
+                
+                println!(" File changed, recompiling...");
+                println!("");
+                rebuild();
+                println!("");
+            }
+            Err(std::sync::mpsc::RecvTimeoutError::Timeout) => {
+                // Keep looping
+            }
+            Err(std::sync::mpsc::RecvTimeoutError::Disconnected) => {
+                break;
+            }
+        }
+    }
+}
+
+/// Tells a running engine to reload shaders that watch mode rebuilt, over the
+/// KOS bridge. Without an engine listening, watch mode just stages the files.
+#[derive(Default)]
+struct EngineReloader {
+    client: Option<bridge::Client>,
+    /// Whether the user has been told no engine is listening
+    warned: bool,
+}
+
+impl EngineReloader {
+    fn reload(&mut self, asset: &str, path: &std::path::Path) {
+        if self.client.is_none() {
+            match bridge::Client::connect_default() {
+                Ok(client) => {
+                    println!(" Connected to {}, shaders will reload on save", client.engine);
+                    self.client = Some(client);
+                }
+                Err(e) => {
+                    if !self.warned {
+                        println!(" No engine to reload shaders in: {}", e);
+                        self.warned = true;
+                    }
+                    return;
+                }
+            }
+        }
+        let Some(client) = self.client.as_mut() else { return };
+        let path = fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+        match client.reload(asset, &path.display().to_string()) {
+            Ok(()) => println!(" Reloaded {} in {}", asset, client.engine),
+            Err(e) => {
+                // Reconnect on the next save, in case the engine restarted
+                eprintln!(" Engine reload of {} failed: {}", asset, e);
+                self.client = None;
+                self.warned = false;
+            }
+        }
+    }
+}
+
+/// Targets whose output a running engine can reload in place
+fn is_shader_target(target: CompileTarget) -> bool {
+    matches!(target, CompileTarget::SpirV | CompileTarget::Hlsl | CompileTarget::Usf)
+}
+
+/// Where `input` compiled for the target `target_dir` goes without `-o`: its
+/// stem with `ext`, in the target's directory under the profile's output
+/// directory (see `packager::file_output_dir`), which is created
+fn default_output(input: &std::path::Path, target_dir: &str, ext: &str, options: &CompileOptions) -> crate::error::KainResult<PathBuf> {
+    let profile_dir = packager::file_output_dir(input, options.profile.as_deref().unwrap_or("debug"))?;
+    let dir = profile_dir.join(target_dir);
+    fs::create_dir_all(&dir).map_err(crate::error::KainError::Io)?;
+    Ok(dir.join(input.file_stem().unwrap_or_default()).with_extension(ext))
+}
+
+/// Run the command line with the process's arguments
+pub fn main() {
+    main_from(std::env::args_os())
+}
+
+/// Run the command line with `args`, the first of which is the program name
+pub fn main_from<I, T>(args: I)
+where
+    I: IntoIterator<Item = T>,
+    T: Into<std::ffi::OsString>,
+{
+    let argv: Vec<std::ffi::OsString> = args.into_iter().map(Into::into).collect();
+    let args = Args::parse_from(argv.iter().cloned());
+    let builder = std::thread::Builder::new()
+        .name("main-thread".into())
+        .stack_size(8 * 1024 * 1024); // 8MB
+
+    crate::crash::install("main-thread");
+    let handler = builder.spawn(move || run(args, &argv[1..])).unwrap();
+
+    if handler.join().is_err() {
+        std::process::exit(report_crash());
+    }
+}
+
+/// Run the command `args` parsed from `argv`, the arguments after the program name
+fn run(args: Args, argv: &[std::ffi::OsString]) {
+    if args.verbose {
+        log::enable("compiler", log::Level::Debug);
+    }
+    let edition = match args.edition.as_deref().map(Edition::parse) {
+        None => Edition::default(),
+        Some(Some(edition)) => edition,
+        Some(None) => {
+            eprintln!(" Unknown edition: {}. Use: 0.1, 0.2, 0.3, 0.4 or 0.5", args.edition.as_deref().unwrap_or_default());
+            std::process::exit(1);
+        }
+    };
+    let crate_type = match args.crate_type.as_deref().map(CrateType::parse) {
+        None => CrateType::default(),
+        Some(Some(crate_type)) => crate_type,
+        Some(None) => {
+            eprintln!(" Unknown crate type: {}. Use: bin, lib, staticlib or cdylib", args.crate_type.as_deref().unwrap_or_default());
+            std::process::exit(1);
+        }
+    };
+    let sql_schema = args.schema.as_ref().map(|path| match std::fs::read_to_string(path) {
+        Ok(ddl) => ddl,
+        Err(e) => {
+            eprintln!(" Failed to read schema {}: {}", path.display(), e);
+            std::process::exit(1);
+        }
+    });
+    let options = CompileOptions {
+        deterministic: args.deterministic,
+        allow_comptime_io: args.allow_comptime_io,
+        features: args.features.clone(),
+        edition,
+        tab_width: args.tab_width.map(usize::from),
+        sql_schema,
+        program_args: args.program_args.clone(),
+        simd: args.enable_simd,
+        size_report: args.size_report,
+        crate_type,
+        profile: args.profile.clone(),
+        ..Default::default()
+    };
+
+    // A project can pin another compiler version, which then runs the command
+    if !matches!(args.command, Some(Commands::Toolchain { .. } | Commands::SelfManage { .. })) {
+        if let Some(code) = crate::release::hand_off(argv) {
+            std::process::exit(code);
+        }
+    }
+
+    // JSON output is all that goes to stdout, for the tool reading it
+    if !matches!(args.command, Some(Commands::Metadata { json: true })) {
+        println!(" {} Compiler v{}", LANGUAGE_NAME, VERSION);
+    }
+
+    match args.command {
+        Some(Commands::Init { path, name }) => {
+            if let Err(e) = packager::init_project(&path, name) {
+                eprintln!(" Init failed: {}", e);
+            }
+        }
+        Some(Commands::Lsp) => {
+            eprintln!(" Starting KAIN Language Server...");
+            // Manual runtime for LSP
+            let rt = tokio::runtime::Builder::new_multi_thread()
+                .enable_all()
+                .build()
+                .expect("Failed to create tokio runtime");
+            
+            rt.block_on(async {
+                lsp::run_server().await;
+            });
+        }
+        Some(Commands::Build { input, targets }) => {
+            match input {
+                Some(file) if targets.is_some() => {
+                    // Single file build for several targets, sharing the front end between them
+                    let source = match vfs::read(&file) {
+                        Ok(source) => source.text.clone(),
+                        Err(e) => {
+                            eprintln!(" Cannot read {}: {}", file.display(), e);
+                            std::process::exit(1);
+                        }
+                    };
+                    let stem = file.file_stem().and_then(|s| s.to_str()).unwrap_or("output");
+                    let profile_name = args.profile.as_deref().unwrap_or("debug");
+                    let resolved = packager::file_profile(&file, profile_name)
+                        .and_then(|profile| Ok((packager::file_output_dir(&file, profile_name)?, profile)));
+                    let (dir, profile) = match resolved {
+                        Ok(resolved) => resolved,
+                        Err(e) => {
+                            eprintln!(" Build failed: {}", e);
+                            std::process::exit(1);
+                        }
+                    };
+                    let mut options = options.clone();
+                    profile.apply(&mut options);
+                    if let Err(e) = packager::compile_targets(&source, &dir, stem, &targets.unwrap_or_default(), &profile, &options) {
+                        eprintln!(" Build failed: {}", e);
+                        std::process::exit(1);
+                    }
+                }
+                Some(file) => {
+                    // Single file build (legacy behavior)
+                    let Some(target) = apply_emit(CompileTarget::Wasm, args.emit.as_deref()) else {
+                        std::process::exit(1);
+                    };
+                    run_compile(&file, target, None, args.emit_ast, args.emit_typed, &options);
+                }
+                None => {
+                    // Project build from KAIN.toml
+                    if let Err(e) = packager::build_project(targets, args.profile.as_deref(), options) {
+                        eprintln!(" Build failed: {}", e);
+                        std::process::exit(1);
+                    }
+                }
+            }
+        }
+        Some(Commands::Clean) => {
+            if let Err(e) = packager::clean(args.profile.as_deref(), options.crate_type) {
+                eprintln!(" Clean failed: {}", e);
+                std::process::exit(1);
+            }
+        }
+        Some(Commands::Run { input, runtime_stats, trace, trace_file, record, replay, print_depth, print_elements, print_indent, program_args }) => {
+            let trace = trace.map(|mode| match TraceMode::parse(&mode) {
+                Some(mode) => TraceOptions { mode, file: trace_file, source: Some(input.clone()) },
+                None => {
+                    eprintln!(" Unknown trace mode: {}. Use: calls, stmts or values", mode);
+                    std::process::exit(1);
+                }
+            });
+            let recording = record.map(Recording::Record).or(replay.map(Recording::Replay));
+            let defaults = PrettyOptions::default();
+            let limit = |given: Option<usize>, default| given.map_or(default, |n| (n > 0).then_some(n));
+            let print = PrettyOptions {
+                max_depth: limit(print_depth, defaults.max_depth),
+                max_elements: limit(print_elements, defaults.max_elements),
+                indent: print_indent,
+            };
+            let options = CompileOptions { program_args, runtime_stats, trace, recording, print, source: Some(input.clone()), ..options.clone() };
+            run_compile(&input, CompileTarget::Interpret, None, args.emit_ast, args.emit_typed, &options);
+        }
+        Some(Commands::Test { input, doc, schedules, schedule_seed, systematic }) => {
+            let schedules = schedules.map(|count| ScheduleOptions { count, seed: schedule_seed, systematic });
+            let options = CompileOptions { schedules, ..options.clone() };
+            let ok = if doc {
+                run_doctests(&input, &options)
+            } else {
+                run_compile(&input, CompileTarget::Test, None, args.emit_ast, args.emit_typed, &options)
+            };
+            if !ok {
+                std::process::exit(1);
+            }
+        }
+        Some(Commands::Check { paths }) => {
+            if !run_check(&paths, options.edition) {
+                std::process::exit(1);
+            }
+        }
+        Some(Commands::Fix { paths, check }) => {
+            if !run_fix(&paths, check, options.edition) {
+                std::process::exit(1);
+            }
+        }
+        Some(Commands::Doc { input, output }) => {
+            if !run_doc(&input, output) {
+                std::process::exit(1);
+            }
+        }
+        Some(Commands::Metadata { json }) => {
+            let metadata = match crate::metadata::project_metadata(std::path::Path::new("."), args.profile.as_deref(), options.crate_type) {
+                Ok(metadata) => metadata,
+                Err(e) => {
+                    eprintln!(" Metadata failed: {}", e);
+                    std::process::exit(1);
+                }
+            };
+            if json {
+                println!("{}", metadata.to_json());
+            } else {
+                print_metadata(&metadata);
+            }
+        }
+        Some(Commands::Toolchain { command }) => {
+            if let Err(e) = run_toolchain(command) {
+                eprintln!(" {}", e);
+                std::process::exit(1);
+            }
+        }
+        Some(Commands::SelfManage { command: SelfCommands::Update { check } }) => {
+            let result = crate::release::ReleaseIndex::fetch(&crate::release::index_url()).and_then(|index| {
+                if check {
+                    return Ok(index.latest().map(str::to_string));
+                }
+                crate::release::self_update(&index)
+            });
+            match result {
+                Ok(Some(latest)) if check && crate::release::compare_versions(&latest, VERSION).is_gt() => println!(" kain {} is available, run `kain self update`", latest),
+                Ok(Some(latest)) if !check => println!(" Updated kain {} -> {}", VERSION, latest),
+                Ok(_) => println!(" kain {} is the newest release", VERSION),
+                Err(e) => {
+                    eprintln!(" Update failed: {}", e);
+                    std::process::exit(1);
+                }
+            }
+        }
+        Some(Commands::Inspect { command: InspectCommands::Artifact { path } }) => {
+            let bytes = match fs::read(&path) {
+                Ok(bytes) => bytes,
+                Err(e) => {
+                    eprintln!(" Cannot read {}: {}", path.display(), e);
+                    std::process::exit(1);
+                }
+            };
+            let Some(stamp) = crate::stamp::read(&bytes) else {
+                eprintln!(" {} has no build stamp; it was not built by {} {} or later", path.display(), LANGUAGE_NAME, VERSION);
+                std::process::exit(1);
+            };
+            println!(" Compiler: {} {}", LANGUAGE_NAME, stamp.compiler);
+            println!(" Target:   {}", stamp.target);
+            println!(" Profile:  {}", stamp.profile);
+            println!(" Source:   {}", stamp.source.as_deref().unwrap_or("unknown"));
+        }
+        Some(Commands::Dev { command: DevCommands::Filecheck { paths } }) => {
+            if !run_filecheck(&paths) {
+                std::process::exit(1);
+            }
+        }
+        Some(Commands::Dev { command: DevCommands::Conformance { paths, backends } }) => {
+            if !run_conformance(&paths, &backends) {
+                std::process::exit(1);
+            }
+        }
+        Some(Commands::Dev { command: DevCommands::KosMock { addr } }) => {
+            match bridge::MockServer::start(&addr) {
+                Ok(server) => {
+                    println!(" KOS mock engine listening on {} (Ctrl+C to stop)", server.addr());
+                    let mut seen = 0;
+                    loop {
+                        std::thread::sleep(std::time::Duration::from_millis(100));
+                        let commands = server.commands();
+                        for command in &commands[seen..] {
+                            println!(" {:?}", command);
+                        }
+                        seen = commands.len();
+                    }
+                }
+                Err(e) => {
+                    eprintln!(" Failed to listen on {}: {}", addr, e);
+                    std::process::exit(1);
+                }
+            }
+        }
+        None => {
+            // Legacy behavior
+            if let Some(ref input) = args.input {
+                if args.target.as_str() == "ue5-shader" {
+                    if args.watch {
+                        let mut engine = EngineReloader::default();
+                        let asset = input.file_stem().and_then(|s| s.to_str()).unwrap_or("shader").to_string();
+                        watch_mode(input.clone(), || {
+                            let built = run_ue5_shader_pipeline(input, &args);
+                            if built && !args.dry_run {
+                                engine.reload(&asset, &derive_shader_paths(input).2);
+                            }
+                            built
+                        });
+                    } else if !run_ue5_shader_pipeline(&input, &args) {
+                        std::process::exit(1);
+                    }
+                } else {
+                    let Some(target) = CompileTarget::parse(&args.target) else {
+                        let Some(plugin) = backend::find(&args.target) else {
+                            let mut known = "wasm, llvm, spirv, hlsl, usf, js, rust, hybrid, run, test, ue5-shader".to_string();
+                            for name in backend::names() {
+                                known.push_str(", ");
+                                known.push_str(&name);
+                            }
+                            eprintln!(" Unknown target: {}. Use: {}", args.target, known);
+                            std::process::exit(1);
+                        };
+                        if args.watch {
+                            eprintln!(" Watch mode is not supported for plugin targets.");
+                        }
+                        if !run_backend_compile(input, plugin.as_ref(), args.output.as_ref(), &options) {
+                            std::process::exit(1);
+                        }
+                        return;
+                    };
+                    if args.emit.as_deref() == Some("symbols") {
+                        if !run_emit_symbols(input, target, args.output.as_ref(), &options) {
+                            std::process::exit(1);
+                        }
+                        return;
+                    }
+                    let Some(target) = apply_emit(target, args.emit.as_deref()) else {
+                        std::process::exit(1);
+                    };
+
+                    if args.watch {
+                        let mut engine = EngineReloader::default();
+                        let asset = input.file_stem().and_then(|s| s.to_str()).unwrap_or("shader").to_string();
+                        // Only what an edit changed is compiled again
+                        let options = CompileOptions { cache: Some(Default::default()), ..options.clone() };
+                        watch_mode(input.clone(), || {
+                            let built = run_compile(input, target, args.output.as_ref(), args.emit_ast, args.emit_typed, &options);
+                            if built && is_shader_target(target) {
+                                let path = args.output.clone().or_else(|| default_output(input, target.cfg_names()[0], packager::target_extension(target), &options).ok());
+                                if let Some(path) = path {
+                                    engine.reload(&asset, &path);
+                                }
+                            }
+                            built
+                        });
+                    } else {
+                        if !run_compile(&input, target, args.output.as_ref(), args.emit_ast, args.emit_typed, &options) {
+                            std::process::exit(1);
+                        }
+                    }
+                }
+            } else {
+                eprintln!(" No input file provided. Use --help for usage.");
+            }
+        }
+    }
+}
+
+/// Tell the user the compiler panicked, and write a report for them to file
+fn report_crash() -> i32 {
+    const EXIT_CODE: i32 = 101;
+    let Some(panic) = crate::crash::take_panic() else { return EXIT_CODE };
+    eprintln!();
+    eprintln!(" error: the compiler panicked. This is a bug in {}, not in your code.", LANGUAGE_NAME);
+    eprintln!(" panic: {}", panic.message);
+    if let Some(context) = panic.context() {
+        eprintln!(" during: {}", context);
+    }
+    eprintln!(" Cutting the input down to a reproducer...");
+    let reproducer = crate::crash::minimize(&panic);
+    match crate::crash::write_report(&panic, &reproducer) {
+        Ok(path) => {
+            eprintln!(" Wrote a crash report to {}", path.display());
+            eprintln!(" Please open an issue at {} and attach it.", crate::crash::ISSUES_URL);
+            eprintln!(" Nothing has been sent anywhere; the report quotes your code, so read it first if that is private.");
+        }
+        Err(e) => eprintln!(" Could not write a crash report: {}", e),
+    }
+    EXIT_CODE
+}
+
+fn staging_dir() -> PathBuf {
+    let cwd = std::env::current_dir().unwrap_or_else(|_| PathBuf::from("."));
+    let candidate1 = cwd.join("src-plugins");
+    if candidate1.exists() {
+        return candidate1.join("_shaders");
+    }
+    if let Some(parent) = cwd.parent() {
+        let candidate2 = parent.join("src-plugins");
+        if candidate2.exists() {
+            return candidate2.join("_shaders");
+        }
+    }
+    cwd.join("src-plugins").join("_shaders")
+}
+
+fn ensure_dir(p: &PathBuf) -> bool {
+    if let Err(e) = fs::create_dir_all(p) {
+        eprintln!(" Failed to create directory {}: {}", p.display(), e);
+        return false;
+    }
+    true
+}
+
+fn find_binary(name: &str, fallback: Option<&str>) -> Option<PathBuf> {
+    if std::process::Command::new(name).arg("--version").output().is_ok() {
+        return Some(PathBuf::from(name));
+    }
+    if let Some(f) = fallback {
+        let pb = PathBuf::from(f);
+        if pb.exists() {
+            return Some(pb);
+        }
+    }
+    None
+}
+
+fn derive_shader_paths(input: &PathBuf) -> (PathBuf, PathBuf, PathBuf) {
+    let stage = staging_dir();
+    let stem = input.file_stem().and_then(|s| s.to_str()).unwrap_or("shader");
+    let spv = stage.join(format!("{}.spv", stem));
+    let hlsl = stage.join(format!("{}.hlsl", stem));
+    let usf = stage.join(format!("{}.usf", stem));
+    (spv, hlsl, usf)
+}
+
+fn resolve_plugin_dir(plugin: &str, base_opt: &Option<PathBuf>) -> PathBuf {
+    if let Some(base) = base_opt {
+        return base.join(plugin).join("Shaders");
+    }
+    let cwd = std::env::current_dir().unwrap_or_else(|_| PathBuf::from("."));
+    let candidate1 = cwd.join("src-plugins");
+    if candidate1.exists() {
+        return candidate1.join(plugin).join("Shaders");
+    }
+    if let Some(parent) = cwd.parent() {
+        let candidate2 = parent.join("src-plugins");
+        if candidate2.exists() {
+            return candidate2.join(plugin).join("Shaders");
+        }
+    }
+    cwd.join("src-plugins").join(plugin).join("Shaders")
+}
+
+fn run_ue5_shader_pipeline(input: &PathBuf, args: &Args) -> bool {
+    let (spv_path, hlsl_path, usf_path) = derive_shader_paths(input);
+    let stage_dir = staging_dir();
+    if !ensure_dir(&stage_dir) {
+        return false;
+    }
+
+    let source = match vfs::read(input) {
+        Ok(file) => file.text.clone(),
+        Err(e) => {
+            eprintln!(" Failed to read {}: {}", input.display(), e);
+            return false;
+        }
+    };
+
+    log::debug("compiler", &format!("Compiling {}", input.display()));
+
+    let compiled_spv = match compile(&source, CompileTarget::SpirV) {
+        Ok(bytes) => bytes,
+        Err(e) => {
+            let filename = input.file_name().and_then(|s| s.to_str()).unwrap_or("input.kn");
+            let diag = crate::diagnostics::Diagnostics::new(&source, filename);
+            eprint!("{}", diag.format_error(&e));
+            return false;
+        }
+    };
+
+    if args.dry_run {
+        println!("→ Write SPIR-V {}", spv_path.display());
+    } else if let Err(e) = fs::write(&spv_path, &compiled_spv) {
+        eprintln!(" Failed to write {}: {}", spv_path.display(), e);
+        return false;
+    } else {
+        log::debug("compiler", &format!("Wrote {}", spv_path.display()));
+    }
+
+    if let Some(val_bin) = find_binary("spirv-val", None) {
+        log::debug("compiler", "Validating SPIR-V");
+        if !args.dry_run {
+            let status = std::process::Command::new(val_bin)
+                .arg(&spv_path)
+                .status();
+            if let Ok(s) = status {
+                if !s.success() {
+                    eprintln!(" SPIR-V validation failed");
+                    return false;
+                }
+            }
+        }
+    }
+
+    let naga_bin = match find_binary("naga", None) {
+        Some(p) => p,
+        None => {
+            eprintln!(" 'naga' not found. Install with: cargo install naga-cli");
+            return false;
+        }
+    };
+
+    log::debug("compiler", "Transpiling to HLSL");
+    if args.dry_run {
+        println!("→ Run naga {} {}", spv_path.display(), hlsl_path.display());
+    } else {
+        let status = std::process::Command::new(naga_bin)
+            .arg(&spv_path)
+            .arg(&hlsl_path)
+            .status();
+        match status {
+            Ok(s) if s.success() => {
+                log::debug("compiler", &format!("Wrote {}", hlsl_path.display()));
+            }
+            _ => {
+                eprintln!(" Naga transpilation failed");
+                return false;
+            }
+        }
+    }
+
+    if args.dry_run {
+        println!("→ Write USF {}", usf_path.display());
+    } else {
+        let stem = input.file_stem().and_then(|s| s.to_str()).unwrap_or("shader");
+        let content = format!("#include \"{}.hlsl\"\n", stem);
+        if let Err(e) = fs::write(&usf_path, content) {
+            eprintln!(" Failed to write {}: {}", usf_path.display(), e);
+            return false;
+        }
+    }
+
+    // Plugin paths come from the command line, then from the profile in KAIN.toml
+    let configured = packager::load_manifest(&PathBuf::from("."))
+        .and_then(|manifest| manifest.profile(args.profile.as_deref().unwrap_or("debug")))
+        .map(|profile| profile.for_target("ue5-shader"))
+        .unwrap_or_default();
+    let plugin = args.plugin.clone().or(configured.plugin);
+    let plugins_dir = args.plugins_dir.clone().or(configured.plugins_dir);
+    if let Some(plugin) = &plugin {
+        let target_dir = resolve_plugin_dir(plugin, &plugins_dir);
+        if args.dry_run {
+            println!("→ Copy to {}", target_dir.display());
+        } else {
+            if !ensure_dir(&target_dir.clone()) {
+                return false;
+            }
+            let hlsl_target = target_dir.join(hlsl_path.file_name().unwrap());
+            let usf_target = target_dir.join(usf_path.file_name().unwrap());
+            if let Err(e) = fs::copy(&hlsl_path, &hlsl_target) {
+                eprintln!(" Copy failed: {}", e);
+                return false;
+            }
+            if let Err(e) = fs::copy(&usf_path, &usf_target) {
+                eprintln!(" Copy failed: {}", e);
+                return false;
+            }
+            println!(" {}", target_dir.display());
+        }
+    } else {
+        log::debug("compiler", &format!("Staged in {}", stage_dir.display()));
+    }
+
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::{TypedItem, TypedProgram};
+
+    struct Shout;
+
+    impl CodegenBackend for Shout {
+        fn name(&self) -> &str {
+            "shout"
+        }
+
+        fn file_extension(&self) -> &str {
+            "txt"
+        }
+
+        fn generate(&self, program: &TypedProgram) -> crate::error::KainResult<Vec<u8>> {
+            let names: Vec<String> = program.items
+                .iter()
+                .filter_map(|item| match item {
+                    TypedItem::Function(f) => Some(f.ast.name.to_uppercase()),
+                    _ => None,
+                })
+                .collect();
+            Ok(names.join(" ").into_bytes())
+        }
+    }
+
+    #[test]
+    fn test_registered_backends_are_targets_on_the_command_line() {
+        backend::register(Shout).unwrap();
+        let dir = std::env::temp_dir().join(format!("kain-cli-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let input = dir.join("hello.kn");
+        let output = dir.join("hello.txt");
+        fs::write(&input, "fn greet():\n    return\n\nfn main():\n    greet()\n").unwrap();
+
+        let argv = ["kain", input.to_str().unwrap(), "--target", "shout", "-o", output.to_str().unwrap()];
+        let args = Args::parse_from(argv);
+        run(args, &argv[1..].iter().map(Into::into).collect::<Vec<_>>());
+        assert_eq!(fs::read_to_string(&output).unwrap(), "GREET MAIN");
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,100 @@
+//! Pluggable codegen backends
+//!
+//! A backend outside this crate (Lua, GLSL ES, ...) implements
+//! [`CodegenBackend`] and is added with [`register`], after which `--target
+//! <name>` and `[build] targets` in KAIN.toml select it like a built-in target.
+//! A host embedding the compiler registers its backends and then hands control
+//! to the CLI with [`crate::cli::main`], which selects them by name.
+//!
+//! The front end does not know about plugin targets, so each backend names the
+//! built-in target whose rules it compiles under: `@cfg(target = ...)`, the
+//! capability check and monomorphization all behave as for that target.
+
+use std::sync::{Arc, RwLock};
+
+use crate::error::{KainError, KainResult};
+use crate::types::TypedProgram;
+use crate::CompileTarget;
+
+/// A code generator selectable by name
+pub trait CodegenBackend: Send + Sync {
+    /// Name used with `--target` and in KAIN.toml
+    fn name(&self) -> &str;
+
+    /// Extension of the file the output is written to, without the dot
+    fn file_extension(&self) -> &str;
+
+    /// Built-in target whose cfg names, capabilities and lowering apply
+    fn base_target(&self) -> CompileTarget {
+        CompileTarget::Js
+    }
+
+    fn generate(&self, program: &TypedProgram) -> KainResult<Vec<u8>>;
+}
+
+static REGISTRY: RwLock<Vec<Arc<dyn CodegenBackend>>> = RwLock::new(Vec::new());
+
+/// Make `backend` selectable by its name
+///
+/// Fails if the name is taken by a built-in target or another backend.
+pub fn register(backend: impl CodegenBackend + 'static) -> KainResult<()> {
+    let name = backend.name().to_lowercase();
+    let mut registry = REGISTRY.write().unwrap_or_else(|e| e.into_inner());
+    if CompileTarget::parse(&name).is_some() || registry.iter().any(|b| b.name().eq_ignore_ascii_case(&name)) {
+        return Err(KainError::runtime(format!("A target named '{}' is already registered", name)));
+    }
+    registry.push(Arc::new(backend));
+    Ok(())
+}
+
+/// The registered backend called `name`, if any
+pub fn find(name: &str) -> Option<Arc<dyn CodegenBackend>> {
+    let registry = REGISTRY.read().unwrap_or_else(|e| e.into_inner());
+    registry.iter().find(|b| b.name().eq_ignore_ascii_case(name)).cloned()
+}
+
+/// Names of all registered backends, in registration order
+pub fn names() -> Vec<String> {
+    let registry = REGISTRY.read().unwrap_or_else(|e| e.into_inner());
+    registry.iter().map(|b| b.name().to_string()).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compile_with_backend;
+
+    struct FunctionNames;
+
+    impl CodegenBackend for FunctionNames {
+        fn name(&self) -> &str {
+            "fn-names"
+        }
+
+        fn file_extension(&self) -> &str {
+            "txt"
+        }
+
+        fn generate(&self, program: &TypedProgram) -> KainResult<Vec<u8>> {
+            let names: Vec<&str> = program.items
+                .iter()
+                .filter_map(|item| match item {
+                    crate::types::TypedItem::Function(f) => Some(f.ast.name.as_str()),
+                    _ => None,
+                })
+                .collect();
+            Ok(names.join("\n").into_bytes())
+        }
+    }
+
+    #[test]
+    fn test_registered_backend_is_found_by_name() {
+        register(FunctionNames).unwrap();
+        assert!(register(FunctionNames).is_err());
+
+        let backend = find("FN-NAMES").unwrap();
+        let source = "@cfg(target = \"js\")\nfn web():\n    return\n\n@cfg(target = \"native\")\nfn desktop():\n    return\n\nfn main():\n    return\n";
+        let output = compile_with_backend(source, backend.as_ref(), &crate::CompileOptions::default()).unwrap();
+        assert_eq!(String::from_utf8(output).unwrap(), "web\nmain");
+    }
+}
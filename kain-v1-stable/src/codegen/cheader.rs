@@ -0,0 +1,129 @@
+//! C header for native libraries
+//!
+//! Library builds (`--crate-type lib`, `staticlib` or `cdylib`) of the llvm
+//! target also write a `.h` declaring what the module exports, so C and C++
+//! code (UE5 plugins included) can link it directly. Types are mapped the way the llvm backend lays them out: every
+//! integer is an `int64_t`, every float a `double`, strings are `const char*`
+//! and structs and enums are passed as pointers to opaque types. A function
+//! returning nothing still returns an `int64_t` there, so it does here too.
+//! `main` is left out: the program linking the library has its own.
+
+use std::collections::HashSet;
+use std::fmt::Write;
+
+use crate::ast::{Expr, Visibility};
+use crate::symbols;
+use crate::types::{ResolvedType, TypedItem, TypedProgram};
+
+/// The header declaring the exports of a program lowered for the llvm target
+pub fn generate(program: &TypedProgram) -> String {
+    let types: HashSet<&str> = program
+        .items
+        .iter()
+        .filter_map(|item| match item {
+            TypedItem::Struct(s) => Some(s.ast.name.as_str()),
+            TypedItem::Enum(e) => Some(e.ast.name.as_str()),
+            _ => None,
+        })
+        .collect();
+
+    let mut opaque = Vec::new();
+    let mut decls = Vec::new();
+    for item in &program.items {
+        match item {
+            TypedItem::Function(f) if symbols::exported(&f.ast) && f.ast.name != "main" => {
+                let ResolvedType::Function { params, ret, .. } = &f.resolved_type else { continue };
+                let ret = match **ret {
+                    ResolvedType::Unit | ResolvedType::Never => "int64_t".to_string(),
+                    _ => c_type(ret, &types, &mut opaque),
+                };
+                let params: Vec<String> = f
+                    .ast
+                    .params
+                    .iter()
+                    .zip(params)
+                    .map(|(p, ty)| format!("{} {}", c_type(ty, &types, &mut opaque), p.name))
+                    .collect();
+                let params = if params.is_empty() { "void".to_string() } else { params.join(", ") };
+                decls.push(format!("{} {}({});", ret, f.symbol, params));
+            }
+            // Only literal consts become globals; the rest are inlined where used
+            TypedItem::Const(c) if c.ast.visibility == Visibility::Public => {
+                if matches!(c.ast.value, Expr::Int(..) | Expr::Float(..) | Expr::Bool(..)) {
+                    decls.push(format!("extern const {} {};", c_type(&c.ty, &types, &mut opaque), c.ast.name));
+                }
+            }
+            TypedItem::Static(s) if s.ast.visibility == Visibility::Public => {
+                decls.push(format!("extern {} {};", c_type(&s.ty, &types, &mut opaque), s.ast.name));
+            }
+            _ => {}
+        }
+    }
+
+    let mut out = String::new();
+    out.push_str("/* Generated by the KAIN compiler; do not edit */\n");
+    out.push_str("#pragma once\n\n#include <stdbool.h>\n#include <stdint.h>\n\n");
+    out.push_str("#ifdef __cplusplus\nextern \"C\" {\n#endif\n\n");
+    if !opaque.is_empty() {
+        for name in &opaque {
+            writeln!(out, "typedef struct {0} {0};", name).ok();
+        }
+        out.push('\n');
+    }
+    for decl in &decls {
+        writeln!(out, "{}", decl).ok();
+    }
+    if !decls.is_empty() {
+        out.push('\n');
+    }
+    out.push_str("#ifdef __cplusplus\n}\n#endif\n");
+    out
+}
+
+/// The C spelling of `ty`, noting the opaque types it needs declared
+fn c_type(ty: &ResolvedType, types: &HashSet<&str>, opaque: &mut Vec<String>) -> String {
+    match ty {
+        ResolvedType::Float(_) => "double".to_string(),
+        ResolvedType::Bool => "bool".to_string(),
+        ResolvedType::String => "const char*".to_string(),
+        ResolvedType::Option(inner) | ResolvedType::Ref { inner, .. } | ResolvedType::Result(inner, _) => c_type(inner, types, opaque),
+        ResolvedType::Struct(name, _) | ResolvedType::Enum(name, _) if types.contains(name.as_str()) => {
+            if !opaque.contains(name) {
+                opaque.push(name.clone());
+            }
+            format!("{}*", name)
+        }
+        // Integers, and everything the backend passes as an opaque 64-bit value
+        _ => "int64_t".to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{packager, CompileOptions, CompileSession, CrateType};
+
+    #[test]
+    fn test_c_header_declares_library_exports() {
+        let source = "pub const MAX_SPEED: Float = 9.5\n\nstruct Player:\n    hp: Int\n\npub fn heal(p: Player, amount: i32) -> Bool:\n    return true\n\n@export(name = \"kain_tick\")\nfn tick(dt: Float, name: String):\n    return\n\nfn helper() -> Int:\n    return 1\n\npub fn id<T>(x: T) -> T:\n    return x\n\npub fn main():\n    println(id(3))\n";
+        let options = CompileOptions { crate_type: CrateType::Lib, ..Default::default() };
+        let header = CompileSession::new(source, options).unwrap().header().unwrap();
+        assert!(header.contains("#pragma once"), "{}", header);
+        assert!(header.contains("extern \"C\" {"), "{}", header);
+        assert!(header.contains("typedef struct Player Player;"), "{}", header);
+        assert!(header.contains("extern const double MAX_SPEED;"), "{}", header);
+        assert!(header.contains("bool heal(Player* p, int64_t amount);"), "{}", header);
+        assert!(header.contains("int64_t kain_tick(double dt, const char* name);"), "{}", header);
+        assert!(header.contains("int64_t _K2idIxE(int64_t x);"), "{}", header);
+        assert!(!header.contains("helper") && !header.contains(" main("), "{}", header);
+
+        assert_eq!(CrateType::parse("lib"), Some(CrateType::Lib));
+        assert_eq!(CrateType::parse("cdylib"), Some(CrateType::Cdylib));
+        assert_eq!(CrateType::parse("dylib"), None);
+        if cfg!(target_os = "linux") {
+            let ir = std::path::Path::new("out/physics.ll");
+            assert_eq!(packager::linked_path(ir, CrateType::Staticlib), std::path::Path::new("out/libphysics.a"));
+            assert_eq!(packager::linked_path(ir, CrateType::Cdylib), std::path::Path::new("out/libphysics.so"));
+            assert_eq!(packager::linked_path(ir, CrateType::Lib), std::path::Path::new("out/physics.o"));
+        }
+    }
+}
@@ -5,6 +5,7 @@ use crate::types::{TypedProgram, TypedItem, TypedShader};
 use crate::error::{KainResult, KainError};
 use crate::ast::{Type, ShaderStage, Expr, Stmt, Block, BinaryOp, Pattern};
 use std::collections::HashMap;
+use super::{c_range_loop, c_string_literal};
 
 pub fn generate(program: &TypedProgram) -> KainResult<String> {
     let mut output = String::new();
@@ -243,6 +244,12 @@ fn emit_stmt(ctx: &mut HLSLContext, stmt: &Stmt) -> KainResult<String> {
             output.push_str(&format!("{}{};\n", ctx.indent(), expr_code));
         },
         // If expressions are handled in Expr::If, not Stmt::If
+        Stmt::For { else_branch: Some(_), span, .. } | Stmt::While { else_branch: Some(_), span, .. } => {
+            return Err(KainError::codegen("Loop `else:` is not supported in HLSL shaders", *span));
+        },
+        Stmt::For { label: Some(_), span, .. } | Stmt::While { label: Some(_), span, .. } | Stmt::Loop { label: Some(_), span, .. } => {
+            return Err(KainError::codegen("Loop labels are not supported in HLSL shaders", *span));
+        },
         Stmt::While { condition, body, .. } => {
             let (cond_code, _) = emit_expr(ctx, condition)?;
             output.push_str(&format!("{}while ({})\n", ctx.indent(), cond_code));
@@ -252,11 +259,13 @@ fn emit_stmt(ctx: &mut HLSLContext, stmt: &Stmt) -> KainResult<String> {
             ctx.pop_indent();
             output.push_str(&format!("{}}}\n", ctx.indent()));
         },
-        Stmt::For { binding, iter: _, body, .. } => {
-            // Simple for loop support - assumes range-like iteration
+        Stmt::For { binding, iter, body, span, .. } => {
+            let range = c_range_loop(iter, |e| Ok(emit_expr(ctx, e)?.0))?.ok_or_else(|| {
+                KainError::codegen("For loops in HLSL shaders must iterate over a range", *span)
+            })?;
             if let Pattern::Binding { name, .. } = binding {
-                output.push_str(&format!("{}for (int {} = 0; {} < 10; {}++)\n", 
-                    ctx.indent(), name, name, name));
+                output.push_str(&format!("{}for (int {} = {}; {} {} {}; {} += {})\n",
+                    ctx.indent(), name, range.start, name, range.cmp, range.end, name, range.step));
                 output.push_str(&format!("{}{{\n", ctx.indent()));
                 ctx.push_indent();
                 ctx.vars.insert(name.clone(), name.clone());
@@ -265,12 +274,20 @@ fn emit_stmt(ctx: &mut HLSLContext, stmt: &Stmt) -> KainResult<String> {
                 output.push_str(&format!("{}}}\n", ctx.indent()));
             }
         },
-        Stmt::Break(_, _) => {
+        Stmt::Break(..) => {
             output.push_str(&format!("{}break;\n", ctx.indent()));
         },
-        Stmt::Continue(_) => {
+        Stmt::Continue(..) => {
             output.push_str(&format!("{}continue;\n", ctx.indent()));
         },
+        // Shaders have no heap, so a region is only a block
+        Stmt::Region { body, .. } => {
+            output.push_str(&format!("{}{{\n", ctx.indent()));
+            ctx.push_indent();
+            output.push_str(&emit_block(ctx, body)?);
+            ctx.pop_indent();
+            output.push_str(&format!("{}}}\n", ctx.indent()));
+        },
         _ => {}
     }
     
@@ -297,7 +314,7 @@ fn emit_expr(ctx: &mut HLSLContext, expr: &Expr) -> KainResult<(String, String)>
         },
         Expr::String(s, _) => {
             // HLSL doesn't have strings, but we can use this for debug/comments
-            Ok((format!("\"{}\"", s), "string".to_string()))
+            Ok((c_string_literal(s), "string".to_string()))
         },
         Expr::Binary { left, op, right, .. } => {
             let (left_code, left_ty) = emit_expr(ctx, left)?;
@@ -353,7 +370,7 @@ fn emit_expr(ctx: &mut HLSLContext, expr: &Expr) -> KainResult<(String, String)>
         },
         Expr::Call { callee, args, .. } => {
             if let Expr::Ident(name, _) = &**callee {
-                emit_function_call(ctx, name, args)
+                emit_function_call(ctx, name, args, expr.span())
             } else {
                 Err(KainError::codegen("Complex callee not supported", expr.span()))
             }
@@ -410,7 +427,7 @@ fn emit_expr(ctx: &mut HLSLContext, expr: &Expr) -> KainResult<(String, String)>
     }
 }
 
-fn emit_function_call(ctx: &mut HLSLContext, name: &str, args: &[crate::ast::CallArg]) -> KainResult<(String, String)> {
+fn emit_function_call(ctx: &mut HLSLContext, name: &str, args: &[crate::ast::CallArg], span: crate::span::Span) -> KainResult<(String, String)> {
     match name {
         // Vector constructors
         "vec2" | "Vec2" => {
@@ -724,7 +741,7 @@ fn emit_function_call(ctx: &mut HLSLContext, name: &str, args: &[crate::ast::Cal
         "flat" | "noperspective" | "centroid" => {
             // These are interpolation modifiers, not functions
             // They would be handled in struct field declarations
-            Err(KainError::codegen(format!("{} is an interpolation modifier, not a function", name), crate::span::Span::new(0, 0)))
+            Err(KainError::codegen(format!("{} is an interpolation modifier, not a function", name), span))
         },
         
         // Atomic operations (for compute shaders)
@@ -790,7 +807,7 @@ fn emit_function_call(ctx: &mut HLSLContext, name: &str, args: &[crate::ast::Cal
             Ok((format!("{}({})", hlsl_name, arg_codes.join(", ")), return_type))
         },
         
-        _ => Err(KainError::codegen(format!("Unknown function: {}", name), crate::span::Span::new(0, 0))),
+        _ => Err(KainError::codegen(format!("Unknown function: {}", name), span)),
     }
 }
 
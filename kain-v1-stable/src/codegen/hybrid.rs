@@ -10,6 +10,7 @@
 //! - Type-aware bindings with automatic marshaling
 //! - Error handling across the JS/WASM boundary
 
+use crate::ast::Visibility;
 use crate::error::KainResult;
 use crate::types::{TypedProgram, TypedItem, TypedFunction, TypedComponent, ResolvedType};
 use crate::codegen::{wasm, js};
@@ -39,6 +40,15 @@ fn component_has_wasm_attr(comp: &TypedComponent) -> bool {
     comp.ast.attributes.iter().any(|attr| attr.name == "wasm")
 }
 
+/// The JS half calls every `@wasm` function by its own name, so the WASM
+/// module exports them under it, whether or not they are `pub`
+fn exported(func: &TypedFunction) -> TypedFunction {
+    let mut func = func.clone();
+    func.ast.visibility = Visibility::Public;
+    func.symbol = func.ast.name.clone();
+    func
+}
+
 /// Extract export metadata from a function
 fn extract_export(func: &TypedFunction) -> WasmExport {
     let params: Vec<(String, ResolvedType)> = func.ast.params.iter()
@@ -69,7 +79,7 @@ pub fn generate(program: &TypedProgram) -> KainResult<HybridOutput> {
             TypedItem::Function(f) => {
                 if has_wasm_attr(f) {
                     wasm_exports.push(extract_export(f));
-                    wasm_items.push(item.clone());
+                    wasm_items.push(TypedItem::Function(exported(f)));
                 } else {
                     js_items.push(item.clone());
                 }
@@ -219,7 +229,7 @@ const __hostImports = {
         
         // Print float
         print_f64(val) {
-            console.log(val);
+            console.log(String(val));
         },
         
         // Print string from WASM memory
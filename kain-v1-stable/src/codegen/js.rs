@@ -6,6 +6,7 @@
 //! Key features:
 //! - JSX → DOM manipulation
 //! - Components → Functions returning DOM nodes
+//! - Structs → ES classes carrying their impl methods, with field getters
 //! - Clean, readable output
 //! - No runtime dependencies
 
@@ -14,9 +15,11 @@ use crate::error::{KainResult, KainError};
 use crate::ast::{
     Type, Expr, Stmt, Block, BinaryOp, UnaryOp, Pattern, Function, Struct, Enum,
     Field, Variant, VariantFields, Impl, Param, MatchArm, CallArg, ElseBranch,
-    VariantPatternFields, EnumVariantFields, Component, JSXNode, JSXAttribute, JSXAttrValue,
+    VariantPatternFields, EnumVariantFields, Component, JSXNode, JSXAttribute, JSXAttrValue, Visibility,
 };
 use crate::span::Span;
+use super::Intrinsics;
+use std::collections::HashMap;
 
 /// Generate JavaScript source code from a typed program
 pub fn generate(program: &TypedProgram) -> KainResult<String> {
@@ -24,6 +27,76 @@ pub fn generate(program: &TypedProgram) -> KainResult<String> {
     Ok(gen.gen_program(program))
 }
 
+/// Builtins with a lowering of their own, see [`Intrinsics`]
+pub(crate) const LOWERED: &[&str] = &["insert", "remove", "pop", "slice", "concat", "flatten", "zip", "index_of"];
+
+/// Array builtins as arrow functions, called with the original arguments so
+/// each is evaluated once and in order
+fn array_builtin(name: &str) -> Option<&'static str> {
+    Some(match name {
+        "insert" => "(a, i, v) => { a.splice(i, 0, v); }",
+        "remove" => "(a, i) => a.splice(i, 1)[0]",
+        "pop" => "(a) => a.length ? a.pop() : null",
+        "slice" => "(a, start, end) => a.slice(start, end)",
+        "concat" => "(a, b) => [...a, ...b]",
+        "flatten" => "(a) => a.flat()",
+        "zip" => "(a, b) => a.slice(0, Math.min(a.length, b.length)).map((x, i) => [x, b[i]])",
+        "index_of" => "(a, v) => { const i = a.indexOf(v); return i < 0 ? null : i; }",
+        _ => return None,
+    })
+}
+
+/// Runtime for range values, emitted once when the program builds one.
+/// Mirrors the interpreter: `end` is exclusive and `step` turns negative on `rev()`.
+const RANGE_CLASS: &str = "class KainRange {
+  constructor(start, end, step = 1) { this.start = start; this.end = end; this.stride = step; }
+  *[Symbol.iterator]() { for (let i = this.start; this.stride > 0 ? i < this.end : i > this.end; i += this.stride) yield i; }
+  len() { const span = this.stride > 0 ? this.end - this.start : this.start - this.end; return Math.max(0, Math.ceil(span / Math.abs(this.stride))); }
+  contains(n) { const inside = this.stride > 0 ? n >= this.start && n < this.end : n <= this.start && n > this.end; return inside && (n - this.start) % this.stride === 0; }
+  step(n) { if (n <= 0) throw new RangeError(`step must be positive, found ${n}`); return new KainRange(this.start, this.end, this.stride * n); }
+  rev() { const n = this.len(); return n === 0 ? new KainRange(this.start, this.start, -this.stride) : new KainRange(this.start + (n - 1) * this.stride, this.start - Math.sign(this.stride), -this.stride); }
+  to_array() { return [...this]; }
+}";
+
+/// Runtime for `?`, emitted once when the program uses it. `kain_try` throws
+/// `KAIN_NONE` on `None`, which the enclosing function turns into its result.
+const TRY_RUNTIME: &str = "const KAIN_NONE = Symbol('None');
+function kain_try(option) { if (option.tag === 'None') throw KAIN_NONE; return option._0; }";
+
+/// A parameter as JS declares it; `...rest` is a rest parameter there too
+fn js_param(param: &Param) -> String {
+    if param.variadic { format!("...{}", param.name) } else { param.name.clone() }
+}
+
+/// The `let`/`const` target binding a pattern's names, e.g. `[a, [, b]]`
+/// for `(a, (_, b))` or `{ _0: n }` for `Some(n)`, or None when it binds nothing
+fn destructure(pattern: &Pattern) -> Option<String> {
+    match pattern {
+        Pattern::Binding { name, .. } => Some(name.clone()),
+        Pattern::Tuple(patterns, _) => {
+            let parts: Vec<Option<String>> = patterns.iter().map(destructure).collect();
+            if parts.iter().all(Option::is_none) {
+                return None;
+            }
+            let parts: Vec<String> = parts.into_iter().map(Option::unwrap_or_default).collect();
+            Some(format!("[{}]", parts.join(", ")))
+        }
+        Pattern::Variant { fields: VariantPatternFields::Tuple(patterns), .. } => {
+            let parts: Vec<String> = patterns.iter().enumerate()
+                .filter_map(|(i, p)| destructure(p).map(|target| format!("_{}: {}", i, target)))
+                .collect();
+            (!parts.is_empty()).then(|| format!("{{ {} }}", parts.join(", ")))
+        }
+        Pattern::Variant { fields: VariantPatternFields::Struct(fields), .. } => {
+            let parts: Vec<String> = fields.iter()
+                .filter_map(|(name, p)| destructure(p).map(|target| format!("{}: {}", name, target)))
+                .collect();
+            (!parts.is_empty()).then(|| format!("{{ {} }}", parts.join(", ")))
+        }
+        _ => None,
+    }
+}
+
 // StringBuilder helper for accumulated output
 struct StringBuilder {
     lines: Vec<String>,
@@ -51,6 +124,18 @@ impl StringBuilder {
 struct JSGen {
     output: StringBuilder,
     indent: usize,
+    /// Per enclosing loop, its label and the flag its `break`s clear when it has an `else:`
+    loop_flags: Vec<(Option<String>, Option<String>)>,
+    flag_count: usize,
+    /// Runtime snippets the program needs, like `RANGE_CLASS`, emitted once each after the header
+    runtimes: Vec<&'static str>,
+    intrinsics: Intrinsics,
+    /// Field names of each struct, in declaration order, which is the order its constructor takes them in
+    struct_fields: HashMap<String, Vec<String>>,
+    /// Impl blocks of the program's structs, emitted in the body of their class
+    struct_impls: HashMap<String, Vec<Impl>>,
+    /// Inside a method, where `self` is `this`
+    in_method: bool,
 }
 
 impl JSGen {
@@ -58,6 +143,45 @@ impl JSGen {
         Self {
             output: StringBuilder::new(),
             indent: 0,
+            loop_flags: Vec::new(),
+            flag_count: 0,
+            runtimes: Vec::new(),
+            intrinsics: Intrinsics::new(LOWERED),
+            struct_fields: HashMap::new(),
+            struct_impls: HashMap::new(),
+            in_method: false,
+        }
+    }
+
+    fn begin_loop(&mut self, label: &Option<String>, has_else: bool) -> Option<String> {
+        let flag = has_else.then(|| {
+            self.flag_count += 1;
+            format!("__no_break{}", self.flag_count)
+        });
+        if let Some(flag) = &flag {
+            self.writeln(&format!("let {} = true;", flag));
+        }
+        self.loop_flags.push((label.clone(), flag.clone()));
+        if let Some(label) = label {
+            self.write(&format!("{}: ", label));
+        }
+        flag
+    }
+
+    fn end_loop(&mut self, flag: Option<String>, else_branch: &Option<Block>) {
+        self.loop_flags.pop();
+        if let (Some(flag), Some(block)) = (flag, else_branch) {
+            self.writeln(&format!("if ({}) {{", flag));
+            self.indent();
+            self.gen_block(block);
+            self.dedent();
+            self.writeln("}");
+        }
+    }
+
+    fn use_runtime(&mut self, runtime: &'static str) {
+        if !self.runtimes.contains(&runtime) {
+            self.runtimes.push(runtime);
         }
     }
 
@@ -81,32 +205,77 @@ impl JSGen {
     }
 
     fn gen_program(&mut self, program: &TypedProgram) -> String {
-        // Header comment
-        self.writeln("// Generated by KAIN compiler");
-        self.writeln("// Target: JavaScript (ES6+)");
-        self.writeln("");
+        self.intrinsics.shadow(program);
+        for item in &program.items {
+            if let TypedItem::Struct(s) = item {
+                self.struct_fields.insert(s.ast.name.clone(), s.ast.fields.iter().map(|f| f.name.clone()).collect());
+            }
+        }
+        for item in &program.items {
+            if let TypedItem::Impl(i) = item {
+                if let Type::Named { name, .. } = &i.ast.target_type {
+                    if self.struct_fields.contains_key(name) {
+                        self.struct_impls.entry(name.clone()).or_default().push(i.ast.clone());
+                    }
+                }
+            }
+        }
 
-        // Generate all items
+        // Generate all items; `pub` ones are exported from the ES module
         for item in &program.items {
+            let visibility = match item {
+                TypedItem::Function(f) if crate::symbols::exported(&f.ast) => Visibility::Public,
+                TypedItem::Function(f) => f.ast.visibility,
+                TypedItem::Struct(s) => s.ast.visibility,
+                TypedItem::Enum(e) => e.ast.visibility,
+                TypedItem::Component(c) => c.ast.visibility,
+                TypedItem::Const(c) => c.ast.visibility,
+                TypedItem::Static(s) => s.ast.visibility,
+                _ => Visibility::Private,
+            };
+            if let TypedItem::Impl(i) = item {
+                if matches!(&i.ast.target_type, Type::Named { name, .. } if self.struct_impls.contains_key(name)) {
+                    continue;
+                }
+            }
+            // A function exported under a name of its own is exported by a separate statement
+            let renamed = match item {
+                TypedItem::Function(f) if f.symbol != f.ast.name => Some(f),
+                _ => None,
+            };
+            if visibility == Visibility::Public && renamed.is_none() {
+                self.write("export ");
+            }
             match item {
                 TypedItem::Function(f) => self.gen_function(&f.ast),
                 TypedItem::Struct(s) => self.gen_struct(&s.ast),
                 TypedItem::Enum(e) => self.gen_enum(&e.ast),
                 TypedItem::Component(c) => self.gen_component(&c.ast),
                 TypedItem::Const(c) => self.gen_const(&c.ast.name, &c.ast.value),
+                TypedItem::Static(s) => self.gen_static(&s.ast.name, &s.ast.value),
                 TypedItem::Impl(i) => self.gen_impl(&i.ast),
                 _ => {} // Skip other items for now
             }
+            if let Some(f) = renamed.filter(|_| visibility == Visibility::Public) {
+                self.writeln(&format!("export {{ {} as {} }};", f.ast.name, f.symbol));
+            }
             self.writeln("");
         }
 
-        self.output.build()
+        // Header comment, then the runtime the code above turned out to need
+        let mut js = String::from("// Generated by KAIN compiler\n// Target: JavaScript (ES6+)\n\n");
+        for runtime in &self.runtimes {
+            js.push_str(runtime);
+            js.push_str("\n\n");
+        }
+        js.push_str(&self.output.build());
+        js
     }
 
     fn gen_function(&mut self, func: &Function) {
         // Function signature
         let params = func.params.iter()
-            .map(|p| p.name.clone())
+            .map(js_param)
             .collect::<Vec<_>>()
             .join(", ");
 
@@ -114,31 +283,85 @@ impl JSGen {
         self.indent();
 
         // Function body
-        self.gen_block(&func.body);
+        self.gen_body(&func.body);
 
         self.dedent();
         self.writeln("}");
     }
 
     fn gen_struct(&mut self, s: &Struct) {
-        // Generate as a class
+        // Generate as a class: fields live in private slots behind accessors,
+        // and the struct's impl methods become its methods
         self.writeln(&format!("class {} {{", s.name));
         self.indent();
 
-        // Constructor
-        let params = s.fields.iter()
-            .map(|f| f.name.clone())
-            .collect::<Vec<_>>()
-            .join(", ");
+        for field in &s.fields {
+            self.writeln(&format!("#{};", field.name));
+        }
+        if !s.fields.is_empty() {
+            self.output.push_line("");
+        }
 
-        self.writeln(&format!("constructor({}) {{", params));
+        // Constructor, taking the fields in declaration order; a field with a
+        // default gets it when the argument is left undefined
+        self.write(&format!("{}constructor(", "  ".repeat(self.indent)));
+        for (i, field) in s.fields.iter().enumerate() {
+            if i > 0 {
+                self.write(", ");
+            }
+            self.write(&field.name);
+            if let Some(default) = &field.default {
+                self.write(" = ");
+                self.gen_expr(default);
+            }
+        }
+        self.output.push_line(") {");
         self.indent();
         for field in &s.fields {
-            self.writeln(&format!("this.{} = {};", field.name, field.name));
+            self.writeln(&format!("this.#{} = {};", field.name, field.name));
         }
         self.dedent();
         self.writeln("}");
 
+        for field in &s.fields {
+            self.output.push_line("");
+            self.writeln(&format!("get {}() {{ return this.#{}; }}", field.name, field.name));
+            self.writeln(&format!("set {}(value) {{ this.#{} = value; }}", field.name, field.name));
+        }
+
+        // Private slots are invisible to JSON.stringify, so spell the fields out
+        self.output.push_line("");
+        let fields = s.fields.iter()
+            .map(|f| format!("{}: this.#{}", f.name, f.name))
+            .collect::<Vec<_>>()
+            .join(", ");
+        self.writeln(&format!("toJSON() {{ return {{ {} }}; }}", fields));
+
+        for impl_block in self.struct_impls.get(&s.name).cloned().unwrap_or_default() {
+            for method in &impl_block.methods {
+                self.output.push_line("");
+                self.gen_method(method);
+            }
+        }
+
+        self.dedent();
+        self.writeln("}");
+    }
+
+    /// A method in a class body: `self` becomes `this`, and methods without it are static
+    fn gen_method(&mut self, method: &Function) {
+        let has_self = method.params.first().map_or(false, |p| p.name == "self");
+        let params = method.params.iter()
+            .skip(has_self as usize)
+            .map(js_param)
+            .collect::<Vec<_>>()
+            .join(", ");
+        let prefix = if has_self { "" } else { "static " };
+        self.writeln(&format!("{}{}({}) {{", prefix, method.name, params));
+        self.indent();
+        let in_method = std::mem::replace(&mut self.in_method, has_self);
+        self.gen_body(&method.body);
+        self.in_method = in_method;
         self.dedent();
         self.writeln("}");
     }
@@ -230,13 +453,19 @@ impl JSGen {
         self.writeln(";");
     }
 
+    fn gen_static(&mut self, name: &str, value: &Expr) {
+        self.write(&format!("let {} = ", name));
+        self.gen_expr(value);
+        self.writeln(";");
+    }
+
     fn gen_impl(&mut self, impl_block: &Impl) {
         // Generate methods as static or prototype methods
         if let Type::Named { name, .. } = &impl_block.target_type {
             for method in &impl_block.methods {
                 let params = method.params.iter()
                     .skip(if method.params.first().map(|p| p.name == "self").unwrap_or(false) { 1 } else { 0 })
-                    .map(|p| p.name.clone())
+                    .map(js_param)
                     .collect::<Vec<_>>()
                     .join(", ");
 
@@ -253,13 +482,34 @@ impl JSGen {
                 }
                 
                 self.indent();
-                self.gen_block(&method.body);
+                let in_method = std::mem::replace(&mut self.in_method, has_self);
+                self.gen_body(&method.body);
+                self.in_method = in_method;
                 self.dedent();
                 self.writeln("};");
             }
         }
     }
 
+    /// A function's body; one using `?` returns `Option.None` when a `kain_try` throws
+    fn gen_body(&mut self, body: &Block) {
+        if crate::types::first_try(body).is_none() {
+            self.gen_block(body);
+            return;
+        }
+        self.use_runtime(TRY_RUNTIME);
+        self.writeln("try {");
+        self.indent();
+        self.gen_block(body);
+        self.dedent();
+        self.writeln("} catch (e) {");
+        self.indent();
+        self.writeln(&format!("if (e === KAIN_NONE) return {}.None;", crate::types::OPTION));
+        self.writeln("throw e;");
+        self.dedent();
+        self.writeln("}");
+    }
+
     fn gen_block(&mut self, block: &Block) {
         for stmt in &block.stmts {
             self.gen_stmt(stmt);
@@ -273,8 +523,8 @@ impl JSGen {
                 self.writeln(";");
             }
             Stmt::Let { pattern, value, .. } => {
-                if let Pattern::Binding { name, .. } = pattern {
-                    self.write(&format!("let {} = ", name));
+                if let Some(target) = destructure(pattern) {
+                    self.write(&format!("let {} = ", target));
                     if let Some(val) = value {
                         self.gen_expr(val);
                     } else {
@@ -291,18 +541,21 @@ impl JSGen {
                 }
                 self.writeln(";");
             }
-            Stmt::For { binding, iter, body, .. } => {
-                if let Pattern::Binding { name, .. } = binding {
-                    self.write(&format!("for (const {} of ", name));
+            Stmt::For { label, binding, iter, body, else_branch, .. } => {
+                if let Some(target) = destructure(binding) {
+                    let flag = self.begin_loop(label, else_branch.is_some());
+                    self.write(&format!("for (const {} of ", target));
                     self.gen_expr(iter);
                     self.writeln(") {");
                     self.indent();
                     self.gen_block(body);
                     self.dedent();
                     self.writeln("}");
+                    self.end_loop(flag, else_branch);
                 }
             }
-            Stmt::While { condition, body, .. } => {
+            Stmt::While { label, condition, body, else_branch, .. } => {
+                let flag = self.begin_loop(label, else_branch.is_some());
                 self.write("while (");
                 self.gen_expr(condition);
                 self.writeln(") {");
@@ -310,23 +563,46 @@ impl JSGen {
                 self.gen_block(body);
                 self.dedent();
                 self.writeln("}");
+                self.end_loop(flag, else_branch);
+            }
+            // The garbage collector reclaims what a region allocated
+            Stmt::Region { body, .. } => {
+                self.writeln("{");
+                self.indent();
+                self.gen_block(body);
+                self.dedent();
+                self.writeln("}");
             }
-            Stmt::Loop { body, .. } => {
-                self.writeln("while (true) {");
+            Stmt::Loop { label, body, .. } => {
+                self.begin_loop(label, false);
+                self.write("while (true) {\n");
                 self.indent();
                 self.gen_block(body);
                 self.dedent();
                 self.writeln("}");
+                self.end_loop(None, &None);
             }
-            Stmt::Break(expr, _) => {
+            Stmt::Break(expr, label, _) => {
                 if expr.is_some() {
                     self.writeln("// Note: break with value not supported in JS");
                 }
-                self.writeln("break;");
-            }
-            Stmt::Continue(_) => {
-                self.writeln("continue;");
+                let target = match label {
+                    Some(name) => self.loop_flags.iter().rev().find(|(l, _)| l.as_ref() == Some(name)),
+                    None => self.loop_flags.last(),
+                };
+                if let Some((_, Some(flag))) = target {
+                    let clear = format!("{} = false;", flag);
+                    self.writeln(&clear);
+                }
+                match label {
+                    Some(name) => self.writeln(&format!("break {};", name)),
+                    None => self.writeln("break;"),
+                }
             }
+            Stmt::Continue(label, _) => match label {
+                Some(name) => self.writeln(&format!("continue {};", name)),
+                None => self.writeln("continue;"),
+            },
             _ => {
                 self.writeln("// Unsupported statement");
             }
@@ -336,10 +612,13 @@ impl JSGen {
     fn gen_expr(&mut self, expr: &Expr) {
         match expr {
             Expr::Int(n, _) => self.write(&n.to_string()),
-            Expr::Float(f, _) => self.write(&f.to_string()),
+            Expr::Float(f, _) => self.write(&crate::float::format(*f)),
             Expr::String(s, _) => self.write(&format!("\"{}\"", s.escape_default())),
             Expr::Bool(b, _) => self.write(if *b { "true" } else { "false" }),
+            // JS has no char type; a one-char string compares and matches the same way
+            Expr::Char(c, _) => self.write(&format!("\"{}\"", c.escape_default())),
             Expr::None(_) => self.write("null"),
+            Expr::Ident(name, _) if name == "self" && self.in_method => self.write("this"),
             Expr::Ident(name, _) => self.write(name),
             
             Expr::Binary { left, op, right, .. } => {
@@ -358,12 +637,20 @@ impl JSGen {
             }
             
             Expr::Call { callee, args, .. } => {
-                self.gen_expr(callee);
+                match callee.as_ref() {
+                    Expr::Ident(name, _) if self.intrinsics.lowered(name).is_some() => {
+                        self.write(&format!("({})", array_builtin(name).unwrap_or_default()));
+                    }
+                    _ => self.gen_expr(callee),
+                }
                 self.write("(");
                 for (i, arg) in args.iter().enumerate() {
                     if i > 0 {
                         self.write(", ");
                     }
+                    if arg.spread {
+                        self.write("...");
+                    }
                     self.gen_expr(&arg.value);
                 }
                 self.write(")");
@@ -380,6 +667,31 @@ impl JSGen {
                 self.write("]");
             }
             
+            Expr::Paren(inner, _) => {
+                self.write("(");
+                self.gen_expr(inner);
+                self.write(")");
+            }
+            
+            Expr::Range { start, end, inclusive, .. } => {
+                self.use_runtime(RANGE_CLASS);
+                self.write("new KainRange(");
+                match start {
+                    Some(e) => self.gen_expr(e),
+                    None => self.write("0"),
+                }
+                self.write(", ");
+                match end {
+                    Some(e) if *inclusive => {
+                        self.gen_expr(e);
+                        self.write(" + 1");
+                    }
+                    Some(e) => self.gen_expr(e),
+                    None => self.write("Infinity"),
+                }
+                self.write(")");
+            }
+
             Expr::Tuple(elements, _) => {
                 // Tuples as arrays in JS
                 self.write("[");
@@ -401,7 +713,12 @@ impl JSGen {
             
             Expr::Field { object, field, .. } => {
                 self.gen_expr(object);
-                self.write(&format!(".{}", field));
+                // Tuples are arrays, so `pair.0` is `pair[0]`
+                if field.parse::<usize>().is_ok() {
+                    self.write(&format!("[{}]", field));
+                } else {
+                    self.write(&format!(".{}", field));
+                }
             }
             
             Expr::MethodCall { receiver, method, args, .. } => {
@@ -411,18 +728,29 @@ impl JSGen {
                     if i > 0 {
                         self.write(", ");
                     }
+                    if arg.spread {
+                        self.write("...");
+                    }
                     self.gen_expr(&arg.value);
                 }
                 self.write(")");
             }
             
             Expr::Struct { name, fields, .. } => {
+                // Arguments go in the order the constructor declares the fields;
+                // ones left out are `undefined`, so their defaults apply
+                let order = self.struct_fields.get(name).cloned()
+                    .unwrap_or_else(|| fields.iter().map(|(field, _)| field.clone()).collect());
+                let last = order.iter().rposition(|field| fields.iter().any(|(f, _)| f == field));
                 self.write(&format!("new {}(", name));
-                for (i, (_, expr)) in fields.iter().enumerate() {
+                for (i, field) in order.iter().take(last.map_or(0, |l| l + 1)).enumerate() {
                     if i > 0 {
                         self.write(", ");
                     }
-                    self.gen_expr(expr);
+                    match fields.iter().find(|(f, _)| f == field) {
+                        Some((_, expr)) => self.gen_expr(expr),
+                        None => self.write("undefined"),
+                    }
                 }
                 self.write(")");
             }
@@ -465,6 +793,9 @@ impl JSGen {
                     self.gen_pattern_match("__match", &arm.pattern);
                     self.writeln(") {");
                     self.indent();
+                    if let Some(target) = destructure(&arm.pattern) {
+                        self.writeln(&format!("const {} = __match;", target));
+                    }
                     self.write("return ");
                     self.gen_expr(&arm.body);
                     self.writeln(";");
@@ -505,7 +836,10 @@ impl JSGen {
                 self.write("`");
                 for part in parts {
                     match part {
-                        Expr::String(s, _) => self.write(s),
+                        // Escaped as a quoted string is, plus what a template gives meaning to
+                        Expr::String(s, _) => {
+                            self.write(&s.escape_default().to_string().replace('`', "\\`").replace("${", "\\${"))
+                        }
                         _ => {
                             self.write("${");
                             self.gen_expr(part);
@@ -520,6 +854,11 @@ impl JSGen {
                 self.gen_jsx(node);
             }
             
+            // Unit variants are values rather than constructors, see `gen_enum`
+            Expr::EnumVariant { enum_name, variant, fields: EnumVariantFields::Unit, .. } => {
+                self.write(&format!("{}.{}", enum_name, variant));
+            }
+
             Expr::EnumVariant { enum_name, variant, fields, .. } => {
                 self.write(&format!("{}.{}(", enum_name, variant));
                 match fields {
@@ -544,6 +883,13 @@ impl JSGen {
                 self.write(")");
             }
             
+            Expr::Try(inner, _) => {
+                self.use_runtime(TRY_RUNTIME);
+                self.write("kain_try(");
+                self.gen_expr(inner);
+                self.write(")");
+            }
+
             _ => {
                 self.write("/* unsupported expr */");
             }
@@ -564,9 +910,9 @@ impl JSGen {
                     match &attr.value {
                         JSXAttrValue::String(s) => {
                             if attr.name == "class" {
-                                self.writeln(&format!("__el.className = '{}';", s));
+                                self.writeln(&format!("__el.className = '{}';", s.escape_default()));
                             } else {
-                                self.writeln(&format!("__el.setAttribute('{}', '{}');", attr.name, s));
+                                self.writeln(&format!("__el.setAttribute('{}', '{}');", attr.name, s.escape_default()));
                             }
                         }
                         JSXAttrValue::Bool(b) => {
@@ -642,7 +988,7 @@ impl JSGen {
                     }
                     self.write(&format!("{}: ", prop.name));
                     match &prop.value {
-                        JSXAttrValue::String(s) => self.write(&format!("'{}'", s)),
+                        JSXAttrValue::String(s) => self.write(&format!("'{}'", s.escape_default())),
                         JSXAttrValue::Bool(b) => self.write(&b.to_string()),
                         JSXAttrValue::Expr(e) => self.gen_expr(e),
                     }
@@ -753,13 +1099,39 @@ impl JSGen {
                 // Check fields if needed
                 match fields {
                     VariantPatternFields::Tuple(patterns) => {
-                        for (i, _) in patterns.iter().enumerate() {
+                        for (i, pattern) in patterns.iter().enumerate() {
                             self.write(&format!(" && typeof {}._{} !== 'undefined'", scrutinee, i));
+                            if !matches!(pattern, Pattern::Wildcard(_) | Pattern::Binding { .. }) {
+                                self.write(" && (");
+                                self.gen_pattern_match(&format!("{}._{}", scrutinee, i), pattern);
+                                self.write(")");
+                            }
                         }
                     }
                     _ => {}
                 }
             }
+            Pattern::Tuple(patterns, _) => {
+                self.write(&format!("Array.isArray({}) && {}.length === {}", scrutinee, scrutinee, patterns.len()));
+                for (i, pattern) in patterns.iter().enumerate() {
+                    if !matches!(pattern, Pattern::Wildcard(_) | Pattern::Binding { .. }) {
+                        self.write(" && (");
+                        self.gen_pattern_match(&format!("{}[{}]", scrutinee, i), pattern);
+                        self.write(")");
+                    }
+                }
+            }
+            Pattern::Range { start, end, inclusive, .. } => {
+                self.write("true");
+                if let Some(lo) = start {
+                    self.write(&format!(" && {} >= ", scrutinee));
+                    self.gen_expr(lo);
+                }
+                if let Some(hi) = end {
+                    self.write(&format!(" && {} {} ", scrutinee, if *inclusive { "<=" } else { "<" }));
+                    self.gen_expr(hi);
+                }
+            }
             _ => self.write("false"),
         }
     }
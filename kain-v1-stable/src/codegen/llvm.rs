@@ -5,16 +5,27 @@
 //! reliability without requiring local LLVM library linking during the build.
 
 use crate::types::{TypedProgram, TypedItem, TypedFunction, ResolvedType};
-use crate::ast::{Expr, Stmt, BinaryOp, Block};
-use crate::error::{KainError, KainResult};
+use crate::ast::{Expr, Stmt, BinaryOp, Block, Const, Static, Visibility};
+use crate::error::{Ice, KainError, KainResult};
+use crate::{CrateType, Phase};
+use super::{range_chain, RangeChain, RangeOp};
 use std::collections::HashMap;
 
 pub fn generate(program: &TypedProgram) -> KainResult<Vec<u8>> {
+    generate_with_options(program, &crate::CompileOptions::default())
+}
+
+pub fn generate_with_options(program: &TypedProgram, options: &crate::CompileOptions) -> KainResult<Vec<u8>> {
     let mut gen = LlvmGenerator::new();
+    gen.deterministic = options.deterministic;
+    gen.crate_type = options.crate_type;
     gen.compile_module(program)?;
     Ok(gen.output.into_bytes())
 }
 
+/// Largest range pattern `match` will expand into individual switch cases
+const MAX_RANGE_PATTERN_CASES: i64 = 256;
+
 struct LlvmGenerator {
     output: String,
     reg_count: usize,
@@ -23,17 +34,32 @@ struct LlvmGenerator {
     locals: HashMap<String, (String, String)>,
     /// Maps function names to return type
     functions: HashMap<String, String>,
+    /// Functions whose symbol isn't their name (methods, `@export`): name -> symbol
+    symbols: HashMap<String, String>,
     /// Maps string content to global variable name
     strings: HashMap<String, String>,
     string_counter: usize,
-    /// Stack of (continue_label, break_label) for loops
-    loop_stack: Vec<(String, String)>,
+    /// Stack of (loop label, continue_label, break_label) for loops
+    loop_stack: Vec<(Option<String>, String, String)>,
     /// Stack of scopes, each containing list of variable names declared in that scope
     scopes: Vec<Vec<String>>,
     /// Struct definitions: Name -> Vec<(FieldName, Type)>
     struct_defs: HashMap<String, Vec<(String, String)>>,
     /// Current basic block label (for Phi nodes)
     current_block: String,
+    /// Emit string constants and stdlib externs in sorted order
+    deterministic: bool,
+    /// Library builds hide every function the program doesn't export
+    crate_type: CrateType,
+    /// Scalar consts: name -> (constant global, type)
+    const_globals: HashMap<String, (String, String)>,
+    /// String consts, compiled from the string pool where they are used
+    string_consts: HashMap<String, Expr>,
+    /// Statics: name -> (mutable global, type)
+    static_globals: HashMap<String, (String, String)>,
+    /// Depth of `region:` blocks being compiled; inside one, allocations come
+    /// from the runtime's region arena, which is reset when the block exits
+    regions: usize,
 }
 
 impl LlvmGenerator {
@@ -44,15 +70,83 @@ impl LlvmGenerator {
             label_count: 0,
             locals: HashMap::new(),
             functions: HashMap::new(),
+            symbols: HashMap::new(),
             strings: HashMap::new(),
             string_counter: 0,
             loop_stack: Vec::new(),
             scopes: Vec::new(),
             struct_defs: HashMap::new(),
             current_block: "entry".to_string(),
+            deterministic: false,
+            crate_type: CrateType::Bin,
+            const_globals: HashMap::new(),
+            string_consts: HashMap::new(),
+            static_globals: HashMap::new(),
+            regions: 0,
         }
     }
 
+    /// Linkage of a definition: only exported ones are visible outside the
+    /// module, and a DLL marks them for export
+    fn linkage(&self, exported: bool) -> &'static str {
+        match (exported, self.crate_type) {
+            (false, _) => "internal ",
+            (true, CrateType::Cdylib) if cfg!(windows) => "dllexport ",
+            (true, _) => "",
+        }
+    }
+
+    /// Linkage of a function; executables leave every function visible
+    fn function_linkage(&self, exported: bool) -> &'static str {
+        if self.crate_type.is_library() {
+            self.linkage(exported)
+        } else {
+            ""
+        }
+    }
+
+    /// Emit a const as a constant global, internal to the module unless it is `pub`
+    fn declare_const(&mut self, c: &Const) {
+        let (ty, value) = match &c.value {
+            Expr::Int(n, _) => ("i64", n.to_string()),
+            // Hex is the one float spelling LLVM accepts for every double
+            Expr::Float(f, _) => ("double", format!("0x{:016X}", f.to_bits())),
+            Expr::Bool(b, _) => ("i1", (*b as u8).to_string()),
+            Expr::Char(c, _) => ("i64", (*c as u32).to_string()),
+            Expr::String(..) => {
+                self.string_consts.insert(c.name.clone(), c.value.clone());
+                return;
+            }
+            // Arrays and structs have no constant form here yet
+            _ => return,
+        };
+        let linkage = self.linkage(matches!(c.visibility, Visibility::Public));
+        let global = format!("@{}", c.name);
+        self.emit(&format!("{} = {}constant {} {}", global, linkage, ty, value));
+        self.const_globals.insert(c.name.clone(), (global, ty.to_string()));
+    }
+
+    /// Emit a static as a mutable global, internal to the module unless it is `pub`
+    fn declare_static(&mut self, s: &Static) -> KainResult<()> {
+        let (ty, value) = match &s.value {
+            Expr::Int(n, _) => ("i64", n.to_string()),
+            Expr::Float(f, _) => ("double", format!("0x{:016X}", f.to_bits())),
+            Expr::Bool(b, _) => ("i1", (*b as u8).to_string()),
+            Expr::Char(c, _) => ("i64", (*c as u32).to_string()),
+            _ => {
+                return Err(KainError::codegen(
+                    format!("static `{}` must start from an Int, Float, Bool or Char literal on the llvm target", s.name),
+                    s.span,
+                ))
+            }
+        };
+        let linkage = self.linkage(matches!(s.visibility, Visibility::Public));
+        let global = format!("@{}", s.name);
+        self.emit(&format!("{} = {}global {} {}", global, linkage, ty, value));
+        self.static_globals.insert(s.name.clone(), (global, ty.to_string()));
+        Ok(())
+    }
+
     fn emit(&mut self, s: &str) {
         self.output.push_str(s);
         self.output.push('\n');
@@ -69,6 +163,13 @@ impl LlvmGenerator {
         r
     }
 
+    /// Emits `op i64 lhs, rhs` into a fresh register
+    fn emit_i64_op(&mut self, op: &str, lhs: &str, rhs: &str) -> String {
+        let r = self.next_reg();
+        self.emit(&format!("  {} = {} i64 {}, {}", r, op, lhs, rhs));
+        r
+    }
+
     fn next_label(&mut self) -> String {
         let l = format!("L{}", self.label_count);
         self.label_count += 1;
@@ -108,7 +209,7 @@ impl LlvmGenerator {
             ResolvedType::Bool => "i1".into(),
             ResolvedType::String => "i8*".into(),
             ResolvedType::Unit => "void".into(),
-            ResolvedType::Char => "i8".into(),
+            ResolvedType::Char => "i64".into(),
             ResolvedType::Struct(name, _) => {
                 if self.struct_defs.contains_key(name) {
                     format!("%{}*", name)
@@ -119,7 +220,7 @@ impl LlvmGenerator {
             ResolvedType::Enum(name, _) => format!("%{}*", name),
             ResolvedType::Array(_, _) => "i64".into(), // Arrays are opaque pointers for now
             ResolvedType::Slice(_) => "i64".into(),
-            ResolvedType::Option(inner) => self.map_type(inner),
+            ResolvedType::Option(_) => format!("%{}*", crate::types::OPTION),
             ResolvedType::Result(ok, _) => self.map_type(ok),
             ResolvedType::Function { .. } => "i64".into(), // Function pointers
             ResolvedType::Generic(name) => self.map_type_from_str(name),
@@ -228,33 +329,47 @@ impl LlvmGenerator {
                     }
                     self.functions.insert(func.ast.name.clone(), ret_ty);
                 }
+                if func.symbol != func.ast.name {
+                    self.symbols.insert(func.ast.name.clone(), func.symbol.clone());
+                }
             }
         }
         
         // 2c. Register StdLib functions
-        let stdlib = crate::stdlib::StdLib::new();
-        for (name, func) in stdlib.functions {
+        for (name, func) in &crate::stdlib::stdlib().functions {
             let ret_ty = self.map_type_from_str(func.return_type);
-            self.functions.insert(name, ret_ty);
+            self.functions.insert(name.clone(), ret_ty);
         }
         
         // 3. Emit External Declarations (stdlib)
         self.emit_externs();
         self.emit_runtime();
 
+        // 3b. Consts, folded to literals by comptime, become constant globals and statics mutable ones
+        for item in &program.items {
+            match item {
+                TypedItem::Const(c) => self.declare_const(&c.ast),
+                TypedItem::Static(s) => self.declare_static(&s.ast)?,
+                _ => {}
+            }
+        }
+
         // 4. Compile Items
         for item in &program.items {
             match item {
                 TypedItem::Function(func) => self.compile_function(func)?,
                 TypedItem::Actor(actor) => self.compile_actor(actor)?,
-                // TODO: Handle Structs, Enums, Consts
+                // TODO: Handle Structs, Enums
                 _ => {} 
             }
         }
         
         // 5. Emit String Constants
         // Clone strings to avoid borrow issues
-        let strings: Vec<(String, String)> = self.strings.iter().map(|(k, v)| (k.clone(), v.clone())).collect();
+        let mut strings: Vec<(String, String)> = self.strings.iter().map(|(k, v)| (k.clone(), v.clone())).collect();
+        if self.deterministic {
+            strings.sort_by(|a, b| a.1.cmp(&b.1));
+        }
         for (content, name) in strings {
             let len = content.len() + 1;
             // Escape string content for LLVM (simplified)
@@ -286,7 +401,7 @@ impl LlvmGenerator {
         let struct_ty = format!("%{}", name);
         
         // Generate Run Loop Function
-        self.emit(&format!("define void @{}_run(i8* %arg) {{", name));
+        self.emit(&format!("define {}void @{}_run(i8* %arg) {{", self.function_linkage(false), name));
         self.emit_label("entry");
         
         // Cast arg to Actor*
@@ -406,9 +521,13 @@ impl LlvmGenerator {
         self.emit("declare i8* @str_concat(i8*, i8*)");
         self.emit("declare i64 @clock_wrapper()");
         self.emit("declare i8* @KAIN_alloc(i64)");
+        self.emit("declare i8* @KAIN_region_alloc(i64)");
+        self.emit("declare i64 @KAIN_region_enter()");
+        self.emit("declare void @KAIN_region_exit(i64)");
         self.emit("declare void @rc_retain(i8*)");
         self.emit("declare void @rc_release(i8*)");
         self.emit("declare i8* @string_new(i8*)");
+        self.emit("declare void @llvm.trap()");
         self.emit("declare i8* @array_new(i64)");
         self.emit("declare void @array_push(i8*, i64)");
         self.emit("declare i64 @array_get(i8*, i64)");
@@ -432,19 +551,26 @@ impl LlvmGenerator {
     }
 
     fn emit_stdlib_externs(&mut self) {
-        let stdlib = crate::stdlib::StdLib::new();
         // Skip functions that conflict with manual runtime declarations or are handled specially
-        let skip_list = ["print", "println", "to_string"];
+        let skip_list = ["print", "println", "to_string", "spawn_cube"];
+
+        let mut functions: Vec<_> = crate::stdlib::stdlib().functions.iter().collect();
+        if self.deterministic {
+            functions.sort_by(|a, b| a.0.cmp(&b.0));
+        }
         
-        for (name, func) in stdlib.functions {
-            if skip_list.contains(&name.as_str()) {
+        for (name, func) in functions {
+            if skip_list.contains(&name.as_str()) || matches!(func.kind, crate::stdlib::BuiltinKind::Comptime | crate::stdlib::BuiltinKind::Build) {
                 continue;
             }
 
             let ret_ty = self.map_type_from_str(func.return_type);
             let mut param_tys = Vec::new();
-            for (_, p_ty) in func.params {
-                param_tys.push(self.map_type_from_str(p_ty));
+            for (_, p_ty) in &func.params {
+                match p_ty.strip_suffix("...") {
+                    Some(_) => param_tys.push("...".to_string()),
+                    None => param_tys.push(self.map_type_from_str(p_ty.trim_end_matches('?'))),
+                }
             }
             
             self.emit(&format!("declare {} @{}({})", ret_ty, name, param_tys.join(", ")));
@@ -464,7 +590,7 @@ impl LlvmGenerator {
             let struct_ty = format!("%{}", name);
             let dtor_name = format!("dtor_{}", name);
             
-            self.emit(&format!("define void @{}(i8* %ptr_void) {{", dtor_name));
+            self.emit(&format!("define {}void @{}(i8* %ptr_void) {{", self.function_linkage(false), dtor_name));
             self.emit_label("entry");
             
             // Cast to struct*
@@ -488,6 +614,11 @@ impl LlvmGenerator {
         }
     }
 
+    /// The symbol the function called `name` is defined under
+    fn symbol(&self, name: &str) -> String {
+        self.symbols.get(name).cloned().unwrap_or_else(|| name.to_string())
+    }
+
     fn compile_function(&mut self, func: &TypedFunction) -> KainResult<()> {
         self.reg_count = 0;
         self.locals.clear();
@@ -514,9 +645,9 @@ impl LlvmGenerator {
             if ret_type == "void" {
                 ret_type = "i64".into();
             }
-            ("main", true)
+            ("main".to_string(), true)
         } else {
-            (name.as_str(), false)
+            (self.symbol(name), false)
         };
 
         // Params
@@ -527,7 +658,8 @@ impl LlvmGenerator {
             param_str.push_str(&format!("{} %arg{}", p_ty, i));
         }
 
-        self.emit(&format!("define {} @{}({}) {{", ret_type, llvm_name, param_str));
+        let linkage = self.function_linkage(crate::symbols::exported(&func.ast));
+        self.emit(&format!("define {}{} @{}({}) {{", linkage, ret_type, llvm_name, param_str));
         self.emit_label("entry");
 
         // Alloc parameters to stack (standard "alloca" pattern for debuggable IR)
@@ -574,6 +706,30 @@ impl LlvmGenerator {
         Ok(())
     }
 
+    /// The allocator heap objects are taken from at this point
+    fn alloc_fn(&self) -> &'static str {
+        if self.regions > 0 { "@KAIN_region_alloc" } else { "@KAIN_alloc" }
+    }
+
+    /// The innermost loop, or the enclosing one with the given label
+    fn loop_jump(&self, label: &Option<String>) -> Option<&(Option<String>, String, String)> {
+        match label {
+            Some(name) => self.loop_stack.iter().rev().find(|(l, _, _)| l.as_ref() == Some(name)),
+            None => self.loop_stack.last(),
+        }
+    }
+
+    /// Close a loop: `done` is where it exits without `break`, `end` is past its `else:`
+    fn compile_loop_else(&mut self, else_branch: &Option<Block>, done: &str, end: &str) -> KainResult<()> {
+        if let Some(block) = else_branch {
+            self.emit_label(done);
+            self.compile_block(block)?;
+            self.emit(&format!("  br label %{}", end));
+        }
+        self.emit_label(end);
+        Ok(())
+    }
+
     fn compile_block_with_result(&mut self, block: &Block) -> KainResult<Option<(String, String)>> {
         self.scopes.push(Vec::new());
         let mut last_res = None;
@@ -726,64 +882,79 @@ impl LlvmGenerator {
                 let dead_label = self.next_label();
                 self.emit_label(&dead_label);
             }
-            Stmt::Break(_, _) => {
-                if let Some((_, break_label)) = self.loop_stack.last() {
+            Stmt::Break(_, label, _) => {
+                if let Some((_, _, break_label)) = self.loop_jump(label) {
+                    let break_label = break_label.clone();
                     self.emit(&format!("  br label %{}", break_label));
                     let dead_label = self.next_label();
                     self.emit_label(&dead_label);
                 }
             }
-            Stmt::Continue(_) => {
-                if let Some((continue_label, _)) = self.loop_stack.last() {
+            Stmt::Continue(label, _) => {
+                if let Some((_, continue_label, _)) = self.loop_jump(label) {
+                    let continue_label = continue_label.clone();
                     self.emit(&format!("  br label %{}", continue_label));
                     let dead_label = self.next_label();
                     self.emit_label(&dead_label);
                 }
             }
-            Stmt::While { condition, body, .. } => {
+            Stmt::While { label, condition, body, else_branch, .. } => {
                 let label_cond = self.next_label();
                 let label_body = self.next_label();
                 let label_end = self.next_label();
+                // Falling out of the loop runs `else`, `break` skips it
+                let label_done = if else_branch.is_some() { self.next_label() } else { label_end.clone() };
 
                 self.emit(&format!("  br label %{}", label_cond));
                 self.emit_label(&label_cond);
                 
                 let (cond_val, _) = self.compile_expr(condition)?;
-                self.emit(&format!("  br i1 {}, label %{}, label %{}", cond_val, label_body, label_end));
+                self.emit(&format!("  br i1 {}, label %{}, label %{}", cond_val, label_body, label_done));
                 
                 self.emit_label(&label_body);
                 
-                self.loop_stack.push((label_cond.clone(), label_end.clone()));
+                self.loop_stack.push((label.clone(), label_cond.clone(), label_end.clone()));
                 self.compile_block(body)?;
                 self.loop_stack.pop();
                 
                 self.emit(&format!("  br label %{}", label_cond));
                 
-                self.emit_label(&label_end);
+                self.compile_loop_else(else_branch, &label_done, &label_end)?;
             }
-            Stmt::Loop { body, .. } => {
+            // The arena is rewound to where it stood on entry; leaving early with
+            // `return` or `break` keeps its objects until an enclosing region exits
+            Stmt::Region { body, .. } => {
+                let mark = self.next_reg();
+                self.emit(&format!("  {} = call i64 @KAIN_region_enter()", mark));
+                self.regions += 1;
+                let result = self.compile_block(body);
+                self.regions -= 1;
+                result?;
+                self.emit(&format!("  call void @KAIN_region_exit(i64 {})", mark));
+            }
+            Stmt::Loop { label, body, .. } => {
                 let label_body = self.next_label();
                 let label_end = self.next_label();
                 
                 self.emit(&format!("  br label %{}", label_body));
                 self.emit_label(&label_body);
                 
-                self.loop_stack.push((label_body.clone(), label_end.clone()));
+                self.loop_stack.push((label.clone(), label_body.clone(), label_end.clone()));
                 self.compile_block(body)?;
                 self.loop_stack.pop();
                 
                 self.emit(&format!("  br label %{}", label_body));
                 self.emit_label(&label_end);
             }
-            Stmt::For { binding, iter, body, span } => {
-                // Determine start, end
-                let (start_val, end_val) = match iter {
+            Stmt::For { label, binding, iter, body, else_branch, span } => {
+                // Determine the first element, how many there are and the step between them
+                let (start_val, count_val, step_val) = match iter {
                     Expr::Call { callee, args, .. } => {
                          if let Expr::Ident(name, _) = callee.as_ref() {
                              if name == "range" && args.len() == 2 {
                                  let (s, _) = self.compile_expr(&args[0].value)?;
                                  let (e, _) = self.compile_expr(&args[1].value)?;
-                                 (s, e)
+                                 (s.clone(), self.emit_range_count(&s, &e, false), "1".to_string())
                              } else {
                                  return Err(KainError::codegen("Unsupported call in for loop", *span));
                              }
@@ -791,19 +962,10 @@ impl LlvmGenerator {
                              return Err(KainError::codegen("Unsupported call in for loop", *span));
                          }
                     }
-                    Expr::Range { start, end, inclusive, .. } => {
-                        let s = if let Some(e) = start { self.compile_expr(e)?.0 } else { "0".into() };
-                        let mut e = if let Some(e) = end { self.compile_expr(e)?.0 } else { 
-                            "9223372036854775807".into() 
-                        };
-                        if *inclusive {
-                            let tmp = self.next_reg();
-                            self.emit(&format!("  {} = add i64 {}, 1", tmp, e));
-                            e = tmp;
-                        }
-                        (s, e)
-                    }
-                    _ => return Err(KainError::codegen("Unsupported iterator in for loop", *span)),
+                    _ => match range_chain(iter) {
+                        Some(chain) => self.compile_range_chain(&chain, *span)?,
+                        None => return Err(KainError::codegen("Unsupported iterator in for loop", *span)),
+                    },
                 };
 
                 // Allocate loop variable
@@ -813,51 +975,132 @@ impl LlvmGenerator {
                 self.emit(&format!("  {} = alloca i64", var_addr));
                 self.emit(&format!("  store i64 {}, i64* {}", start_val, var_addr));
                 self.locals.insert(loop_var.to_string(), (var_addr.clone(), "i64".into()));
+                let count_addr = format!("%{}.count_{}", loop_var, self.reg_count);
+                self.reg_count += 1;
+                self.emit(&format!("  {} = alloca i64", count_addr));
+                self.emit(&format!("  store i64 {}, i64* {}", count_val, count_addr));
                 
                 let label_cond = self.next_label();
                 let label_body = self.next_label();
                 let label_step = self.next_label();
+                let label_inc = self.next_label();
                 let label_end = self.next_label();
+                let label_done = if else_branch.is_some() { self.next_label() } else { label_end.clone() };
                 
                 self.emit(&format!("  br label %{}", label_cond));
                 self.emit_label(&label_cond);
                 
-                // Check condition: var < end
-                let curr_val = self.next_reg();
-                self.emit(&format!("  {} = load i64, i64* {}", curr_val, var_addr));
-                let cond_res = self.next_reg();
-                self.emit(&format!("  {} = icmp slt i64 {}, {}", cond_res, curr_val, end_val));
-                self.emit(&format!("  br i1 {}, label %{}, label %{}", cond_res, label_body, label_end));
+                // Check condition: elements left
+                let count = self.next_reg();
+                self.emit(&format!("  {} = load i64, i64* {}", count, count_addr));
+                let empty = self.next_reg();
+                self.emit(&format!("  {} = icmp eq i64 {}, 0", empty, count));
+                self.emit(&format!("  br i1 {}, label %{}, label %{}", empty, label_done, label_body));
                 
                 self.emit_label(&label_body);
                 
-                self.loop_stack.push((label_step.clone(), label_end.clone()));
+                self.loop_stack.push((label.clone(), label_step.clone(), label_end.clone()));
                 self.compile_block(body)?;
                 self.loop_stack.pop();
                 
                 self.emit(&format!("  br label %{}", label_step));
                 self.emit_label(&label_step);
                 
+                // Stop after the last element rather than stepping past it, which could overflow
+                let count = self.next_reg();
+                self.emit(&format!("  {} = load i64, i64* {}", count, count_addr));
+                let left = self.emit_i64_op("sub", &count, "1");
+                self.emit(&format!("  store i64 {}, i64* {}", left, count_addr));
+                let last = self.next_reg();
+                self.emit(&format!("  {} = icmp eq i64 {}, 0", last, left));
+                self.emit(&format!("  br i1 {}, label %{}, label %{}", last, label_done, label_inc));
+                self.emit_label(&label_inc);
+                
                 // Increment
                 let val_before_inc = self.next_reg();
                 self.emit(&format!("  {} = load i64, i64* {}", val_before_inc, var_addr));
                 let val_after_inc = self.next_reg();
-                self.emit(&format!("  {} = add i64 {}, 1", val_after_inc, val_before_inc));
+                self.emit(&format!("  {} = add i64 {}, {}", val_after_inc, val_before_inc, step_val));
                 self.emit(&format!("  store i64 {}, i64* {}", val_after_inc, var_addr));
                 
                 self.emit(&format!("  br label %{}", label_cond));
-                self.emit_label(&label_end);
+                self.compile_loop_else(else_branch, &label_done, &label_end)?;
             }
             _ => {}
         }
         Ok(())
     }
 
+    /// The number of elements from `start` to `end`, unsigned so that no
+    /// bound, not even an inclusive `i64::MAX`, overflows it
+    fn emit_range_count(&mut self, start: &str, end: &str, inclusive: bool) -> String {
+        let distance = self.emit_i64_op("sub", end, start);
+        let count = if inclusive { self.emit_i64_op("add", &distance, "1") } else { distance };
+        let nonempty = self.next_reg();
+        self.emit(&format!("  {} = icmp {} i64 {}, {}", nonempty, if inclusive { "sge" } else { "sgt" }, end, start));
+        let clamped = self.next_reg();
+        self.emit(&format!("  {} = select i1 {}, i64 {}, i64 0", clamped, nonempty, count));
+        clamped
+    }
+
+    /// Computes (start, element count, step) for a range and its `step`/`rev` calls
+    fn compile_range_chain(&mut self, chain: &RangeChain, span: crate::span::Span) -> KainResult<(String, String, String)> {
+        let mut start = if let Some(e) = chain.start { self.compile_expr(e)?.0 } else { "0".into() };
+        let mut count = if let Some(e) = chain.end {
+            let end = self.compile_expr(e)?.0;
+            self.emit_range_count(&start, &end, chain.inclusive)
+        } else {
+            if chain.ops.iter().any(|op| matches!(op, RangeOp::Rev)) {
+                return Err(KainError::codegen("cannot reverse an unbounded range", span));
+            }
+            // As many as an unsigned count holds
+            "-1".into()
+        };
+        let mut step = "1".to_string();
+        for op in &chain.ops {
+            match op {
+                RangeOp::Step(k) => {
+                    // Trap on a non-positive step, then scale the current step by it
+                    let (k, _) = self.compile_expr(k)?;
+                    let bad = self.next_reg();
+                    self.emit(&format!("  {} = icmp sle i64 {}, 0", bad, k));
+                    let label_trap = self.next_label();
+                    let label_ok = self.next_label();
+                    self.emit(&format!("  br i1 {}, label %{}, label %{}", bad, label_trap, label_ok));
+                    self.emit_label(&label_trap);
+                    self.emit("  call void @llvm.trap()");
+                    self.emit("  unreachable");
+                    self.emit_label(&label_ok);
+                    // count = count == 0 ? 0 : (count - 1) / k + 1
+                    let before = self.emit_i64_op("sub", &count, "1");
+                    let taken = self.emit_i64_op("udiv", &before, &k);
+                    let taken = self.emit_i64_op("add", &taken, "1");
+                    let nonempty = self.next_reg();
+                    self.emit(&format!("  {} = icmp ne i64 {}, 0", nonempty, count));
+                    let new_count = self.next_reg();
+                    self.emit(&format!("  {} = select i1 {}, i64 {}, i64 0", new_count, nonempty, taken));
+                    count = new_count;
+                    step = self.emit_i64_op("mul", &step, &k);
+                }
+                RangeOp::Rev => {
+                    // start = start + (count - 1) * step; step = -step
+                    let last = self.emit_i64_op("sub", &count, "1");
+                    let offset = self.emit_i64_op("mul", &last, &step);
+                    start = self.emit_i64_op("add", &start, &offset);
+                    step = self.emit_i64_op("sub", "0", &step);
+                }
+            }
+        }
+        Ok((start, count, step))
+    }
+
     fn compile_expr(&mut self, expr: &Expr) -> KainResult<(String, String)> {
         match expr {
             Expr::Int(n, _) => Ok((format!("{}", n), "i64".to_string())),
-            Expr::Float(f, _) => Ok((format!("{:.6}", f), "double".to_string())),
+            Expr::Float(f, _) => Ok((format!("0x{:016X}", f.to_bits()), "double".to_string())),
             Expr::Bool(b, _) => Ok((if *b { "1".into() } else { "0".into() }, "i1".to_string())),
+            // A char is its code point, so it compares and matches like an Int
+            Expr::Char(c, _) => Ok(((*c as u32).to_string(), "i64".to_string())),
             Expr::String(s, _) => {
                 // Register global string constant
                 let global_name = if let Some(name) = self.strings.get(s) {
@@ -896,7 +1139,7 @@ impl LlvmGenerator {
                 self.emit(&format!("  {} = ptrtoint {}* {} to i64", size_reg, struct_ty, size_ptr_reg));
                 
                 let mem_reg = self.next_reg();
-                self.emit(&format!("  {} = call i8* @KAIN_alloc(i64 {})", mem_reg, size_reg));
+                self.emit(&format!("  {} = call i8* {}(i64 {})", mem_reg, self.alloc_fn(), size_reg));
                 
                 // Cast to struct ptr
                 let struct_ptr = self.next_reg();
@@ -1013,14 +1256,28 @@ impl LlvmGenerator {
                 }
             }
             Expr::Ident(name, span) => {
-                if let Some((ptr, ty)) = self.locals.get(name).cloned() {
+                let global = self.const_globals.get(name).or_else(|| self.static_globals.get(name));
+                if let Some((ptr, ty)) = self.locals.get(name).or(global).cloned() {
                     let reg = self.next_reg();
                     self.emit(&format!("  {} = load {}, {}* {}", reg, ty, ty, ptr));
                     Ok((reg, ty))
+                } else if let Some(value) = self.string_consts.get(name).cloned() {
+                    self.compile_expr(&value)
                 } else {
                     Err(KainError::codegen(format!("Undefined variable: {}", name), *span))
                 }
             }
+            Expr::Assign { target, value, span } => {
+                let Expr::Ident(name, _) = target.as_ref() else {
+                    return Err(KainError::codegen("Only variables can be assigned on the llvm target", *span));
+                };
+                let (val, _) = self.compile_expr(value)?;
+                let Some((ptr, ty)) = self.locals.get(name).or_else(|| self.static_globals.get(name)).cloned() else {
+                    return Err(KainError::codegen(format!("Cannot assign to '{}'", name), *span));
+                };
+                self.emit(&format!("  store {} {}, {}* {}", ty, val, ty, ptr));
+                Ok(("0".into(), "i64".into()))
+            }
             Expr::Binary { left, op, right, .. } => {
                 let (lhs, ty) = self.compile_expr(left)?;
                 let (rhs, rhs_ty) = self.compile_expr(right)?;
@@ -1094,7 +1351,9 @@ impl LlvmGenerator {
                             arg_types.push(ty);
                         }
                         
-                        let ret_ty = self.functions.get(&func_name).unwrap().clone();
+                        let ret_ty = self.functions.get(&func_name).cloned().ok_or_else(|| {
+                            KainError::internal(Ice::UndeclaredFunction, Phase::Codegen, format!("`{}` was declared and then lost", func_name), *span)
+                        })?;
                         let res = self.next_reg();
                         
                         let arg_str = compiled_args.iter().zip(arg_types.iter())
@@ -1102,7 +1361,7 @@ impl LlvmGenerator {
                             .collect::<Vec<_>>()
                             .join(", ");
                             
-                        self.emit(&format!("  {} = call {} @{}({})", res, ret_ty, func_name, arg_str));
+                        self.emit(&format!("  {} = call {} @{}({})", res, ret_ty, self.symbol(&func_name), arg_str));
                         return Ok((res, ret_ty));
                     }
                 }
@@ -1199,7 +1458,7 @@ impl LlvmGenerator {
                     .collect::<Vec<_>>()
                     .join(", ");
                     
-                self.emit(&format!("  {} = call {} @{}({})", res, ret_ty, func_name, arg_str));
+                self.emit(&format!("  {} = call {} @{}({})", res, ret_ty, self.symbol(&func_name), arg_str));
                 
                 Ok((res, ret_ty))
             }
@@ -1215,7 +1474,7 @@ impl LlvmGenerator {
                 self.emit(&format!("  {} = ptrtoint {} {} to i64", size_reg, ptr_ty, size_ptr_reg));
                 
                 let mem_reg = self.next_reg();
-                self.emit(&format!("  {} = call i8* @KAIN_alloc(i64 {})", mem_reg, size_reg));
+                self.emit(&format!("  {} = call i8* {}(i64 {})", mem_reg, self.alloc_fn(), size_reg));
                 
                 let enum_ptr = self.next_reg();
                 self.emit(&format!("  {} = bitcast i8* {} to {}", enum_ptr, mem_reg, ptr_ty));
@@ -1241,7 +1500,7 @@ impl LlvmGenerator {
                     self.emit(&format!("  {} = ptrtoint {} {} to i64", p_size, payload_ptr_ty, p_size_ptr));
                     
                     let p_mem = self.next_reg();
-                    self.emit(&format!("  {} = call i8* @KAIN_alloc(i64 {})", p_mem, p_size));
+                    self.emit(&format!("  {} = call i8* {}(i64 {})", p_mem, self.alloc_fn(), p_size));
                     
                     let p_ptr = self.next_reg();
                     self.emit(&format!("  {} = bitcast i8* {} to {}", p_ptr, p_mem, payload_ptr_ty));
@@ -1281,6 +1540,49 @@ impl LlvmGenerator {
                 
                 Ok((enum_ptr, ptr_ty))
             }
+            // `option?`: return `Option::None` from the function, or go on with the value inside
+            Expr::Try(inner, span) => {
+                let (val, val_ty) = self.compile_expr(inner)?;
+                let option = crate::types::OPTION;
+                if val_ty != format!("%{}*", option) {
+                    return Err(KainError::codegen(format!("`?` needs an Option in LLVM, got {}", val_ty), *span));
+                }
+                let tag_ptr = self.next_reg();
+                self.emit(&format!("  {} = getelementptr inbounds %{}, {} {}, i32 0, i32 0", tag_ptr, option, val_ty, val));
+                let tag = self.next_reg();
+                self.emit(&format!("  {} = load i64, i64* {}", tag, tag_ptr));
+                let is_none = self.next_reg();
+                self.emit(&format!("  {} = icmp eq i64 {}, {}", is_none, tag, self.hash_message_tag(option, "None")));
+                let label_none = self.next_label();
+                let label_some = self.next_label();
+                self.emit(&format!("  br i1 {}, label %{}, label %{}", is_none, label_none, label_some));
+
+                self.emit_label(&label_none);
+                let none = Expr::EnumVariant {
+                    enum_name: option.to_string(),
+                    variant: "None".to_string(),
+                    fields: crate::ast::EnumVariantFields::Unit,
+                    span: *span,
+                };
+                let (none_val, none_ty) = self.compile_expr(&none)?;
+                self.emit_all_scopes_cleanup();
+                self.emit(&format!("  ret {} {}", none_ty, none_val));
+
+                self.emit_label(&label_some);
+                let payload_name = format!("{}_Some", option);
+                let field_ty = self.struct_defs.get(&payload_name).and_then(|defs| defs.first()).map_or("i64".to_string(), |(_, t)| t.clone());
+                let payload_ptr_ptr = self.next_reg();
+                self.emit(&format!("  {} = getelementptr inbounds %{}, {} {}, i32 0, i32 1", payload_ptr_ptr, option, val_ty, val));
+                let payload_void = self.next_reg();
+                self.emit(&format!("  {} = load i8*, i8** {}", payload_void, payload_ptr_ptr));
+                let payload_ptr = self.next_reg();
+                self.emit(&format!("  {} = bitcast i8* {} to %{}*", payload_ptr, payload_void, payload_name));
+                let field_ptr = self.next_reg();
+                self.emit(&format!("  {} = getelementptr inbounds %{}, %{}* {}, i32 0, i32 0", field_ptr, payload_name, payload_name, payload_ptr));
+                let field_val = self.next_reg();
+                self.emit(&format!("  {} = load {}, {}* {}", field_val, field_ty, field_ty, field_ptr));
+                Ok((field_val, field_ty))
+            }
             Expr::Match { scrutinee, arms, span } => {
                 let (val, val_ty) = self.compile_expr(scrutinee)?;
                 
@@ -1302,6 +1604,7 @@ impl LlvmGenerator {
                 let label_end = self.next_label();
                 let mut arm_labels = Vec::new();
                 let mut switch_cases = String::new();
+                let mut case_values = std::collections::HashSet::new();
                 
                 for _ in arms {
                     arm_labels.push(self.next_label());
@@ -1317,13 +1620,38 @@ impl LlvmGenerator {
                     let arm_tag = match &arm.pattern {
                         crate::ast::Pattern::Variant { variant, .. } => self.hash_message_tag(enum_name, variant),
                         crate::ast::Pattern::Literal(Expr::Int(n, _)) => *n, 
+                        crate::ast::Pattern::Literal(Expr::Char(c, _)) => *c as i64,
                         _ => 0, 
                     };
                     
                     if let crate::ast::Pattern::Variant { .. } = &arm.pattern {
                         switch_cases.push_str(&format!("i64 {}, label %{} ", arm_tag, arm_labels[i]));
-                    } else if let crate::ast::Pattern::Literal(Expr::Int(..)) = &arm.pattern {
-                        switch_cases.push_str(&format!("i64 {}, label %{} ", arm_tag, arm_labels[i]));
+                    } else if let crate::ast::Pattern::Literal(Expr::Int(..) | Expr::Char(..)) = &arm.pattern {
+                        if case_values.insert(arm_tag) {
+                            switch_cases.push_str(&format!("i64 {}, label %{} ", arm_tag, arm_labels[i]));
+                        }
+                    } else if let crate::ast::Pattern::Range { start, end, inclusive, span } = &arm.pattern {
+                        // A switch has no range cases, so small ranges expand to one case per value
+                        let bound = |e: Option<&Expr>| match e {
+                            Some(Expr::Int(n, _)) => Some(*n),
+                            Some(Expr::Char(c, _)) => Some(*c as i64),
+                            _ => None,
+                        };
+                        let (Some(lo), Some(hi)) = (bound(start.as_deref()), bound(end.as_deref())) else {
+                            return Err(KainError::codegen("Range patterns need both bounds in LLVM", *span));
+                        };
+                        let hi = if *inclusive { hi } else { hi - 1 };
+                        if hi.saturating_sub(lo) >= MAX_RANGE_PATTERN_CASES {
+                            return Err(KainError::codegen(
+                                format!("Range pattern covers more than {} values", MAX_RANGE_PATTERN_CASES),
+                                *span,
+                            ));
+                        }
+                        for n in lo..=hi {
+                            if case_values.insert(n) {
+                                switch_cases.push_str(&format!("i64 {}, label %{} ", n, arm_labels[i]));
+                            }
+                        }
                     }
                 }
                 
@@ -1419,6 +1747,7 @@ impl LlvmGenerator {
                     Ok((res_reg, res_ty))
                 }
             }
+            Expr::MacroCall { name, args, span } if name == "asm" => self.compile_asm(args, *span),
             // Catch-all for unsupported expressions
             other => {
                 // For unsupported expressions, return a dummy value
@@ -1427,4 +1756,85 @@ impl LlvmGenerator {
             }
         }
     }
-}
\ No newline at end of file
+
+    /// Splice in the lines of an `asm!("llvm", ..)` block: `$N` becomes the
+    /// Nth operand and the block's own registers are renamed to fresh ones,
+    /// so it can neither clobber nor read the function's
+    fn compile_asm(&mut self, args: &[Expr], span: crate::span::Span) -> KainResult<(String, String)> {
+        let asm = crate::asm::InlineAsm::parse(args, span)?;
+        let mut operands = Vec::new();
+        for operand in asm.operands {
+            operands.push(self.compile_expr(operand)?.0);
+        }
+        let mut registers: HashMap<String, String> = HashMap::new();
+        let mut value = None;
+        for line in asm.code.lines().map(str::trim).filter(|line| !line.is_empty()) {
+            let (def, instr) = match line.split_once('=') {
+                Some((lhs, rhs)) if lhs.trim().starts_with('%') => (Some(lhs.trim()[1..].to_string()), rhs.trim()),
+                _ => (None, line),
+            };
+            let opcode = instr.split_whitespace().next().unwrap_or("");
+            if line.ends_with(':')
+                || matches!(opcode, "ret" | "br" | "switch" | "indirectbr" | "invoke" | "callbr" | "resume" | "unreachable" | "phi")
+            {
+                return Err(KainError::codegen(
+                    format!("asm!: `{}` would leave the current block; inline llvm code must run straight through", line),
+                    span,
+                ));
+            }
+            let body = Self::rename_asm_operands(instr, &operands, &registers, span)?;
+            match def {
+                Some(name) => {
+                    let reg = self.next_reg();
+                    self.emit(&format!("  {} = {}", reg, body));
+                    registers.insert(name, reg.clone());
+                    value = Some(reg);
+                }
+                None => {
+                    self.emit(&format!("  {}", body));
+                    value = None;
+                }
+            }
+        }
+        match value {
+            Some(reg) => Ok((reg, "i64".into())),
+            None => Err(KainError::codegen("asm!: the last line of llvm code must define the i64 value of the block", span)),
+        }
+    }
+
+    /// `$N` to the Nth operand and `%name` to the register the block defined
+    /// for it; `%Name` is a type and is left alone
+    fn rename_asm_operands(instr: &str, operands: &[String], registers: &HashMap<String, String>, span: crate::span::Span) -> KainResult<String> {
+        let mut out = String::new();
+        let mut chars = instr.chars().peekable();
+        while let Some(c) = chars.next() {
+            if c != '$' && c != '%' {
+                out.push(c);
+                continue;
+            }
+            let mut name = String::new();
+            while let Some(&next) = chars.peek() {
+                if !(next.is_ascii_alphanumeric() || next == '_' || next == '.') {
+                    break;
+                }
+                name.push(next);
+                chars.next();
+            }
+            if c == '$' {
+                let operand = name.parse::<usize>().ok().and_then(|i| operands.get(i)).ok_or_else(|| {
+                    KainError::codegen(format!("asm!: `${}` is not one of the {} operand(s)", name, operands.len()), span)
+                })?;
+                out.push_str(operand);
+            } else if name.starts_with(|c: char| c.is_ascii_uppercase()) {
+                out.push('%');
+                out.push_str(&name);
+            } else {
+                let reg = registers.get(&name).ok_or_else(|| {
+                    KainError::codegen(format!("asm!: `%{}` is not defined earlier in the block", name), span)
+                })?;
+                out.push_str(reg);
+            }
+        }
+        Ok(out)
+    }
+}
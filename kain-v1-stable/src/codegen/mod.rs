@@ -1,6 +1,8 @@
 //! KAIN Code Generation - Multi-target output
 
 pub mod wasm;
+pub mod wat;
+pub mod wasm_size;
 #[cfg(feature = "llvm")]
 pub mod llvm;
 pub mod spirv;
@@ -9,6 +11,8 @@ pub mod usf;
 pub mod js;
 pub mod rust;
 pub mod hybrid;
+pub mod backend;
+pub mod cheader;
 
 pub use wasm::generate as generate_wasm;
 #[cfg(feature = "llvm")]
@@ -20,3 +24,174 @@ pub use js::generate as generate_js;
 pub use rust::generate as generate_rust;
 pub use hybrid::generate as generate_hybrid;
 
+
+use std::collections::HashSet;
+
+use crate::ast::Expr;
+use crate::error::{KainError, KainResult};
+use crate::stdlib::{stdlib, BuiltinFn};
+use crate::types::{TypedItem, TypedProgram};
+
+/// The builtins a backend lowers itself, resolved through the [`stdlib`] registry
+/// so a backend can only special-case names the interpreter also provides.
+/// Functions the program defines shadow builtins of the same name.
+pub(crate) struct Intrinsics {
+    lowered: &'static [&'static str],
+    defined: HashSet<String>,
+}
+
+impl Intrinsics {
+    pub(crate) fn new(lowered: &'static [&'static str]) -> Self {
+        Self { lowered, defined: HashSet::new() }
+    }
+
+    /// Leave calls to the functions `program` defines to the program
+    pub(crate) fn shadow(&mut self, program: &TypedProgram) {
+        self.defined = program
+            .items
+            .iter()
+            .filter_map(|item| match item {
+                TypedItem::Function(f) => Some(f.ast.name.clone()),
+                _ => None,
+            })
+            .collect();
+    }
+
+    /// The registry entry `name` refers to, if it is a builtin here
+    pub(crate) fn builtin(&self, name: &str) -> Option<&'static BuiltinFn> {
+        if self.defined.contains(name) {
+            return None;
+        }
+        stdlib().functions.get(name)
+    }
+
+    /// The registry entry for a call to `name` that this backend lowers itself
+    pub(crate) fn lowered(&self, name: &str) -> Option<&'static BuiltinFn> {
+        self.builtin(name).filter(|f| self.lowered.contains(&f.name))
+    }
+}
+
+/// A range literal together with the `.step(k)` / `.rev()` calls chained onto
+/// it. Backends without a runtime range value lower the whole chain at once.
+pub(crate) struct RangeChain<'a> {
+    pub start: Option<&'a Expr>,
+    pub end: Option<&'a Expr>,
+    pub inclusive: bool,
+    pub ops: Vec<RangeOp<'a>>,
+}
+
+pub(crate) enum RangeOp<'a> {
+    Step(&'a Expr),
+    Rev,
+}
+
+/// Peel parentheses and `step`/`rev` calls off `expr` down to a range literal.
+pub(crate) fn range_chain(expr: &Expr) -> Option<RangeChain<'_>> {
+    match expr {
+        Expr::Paren(inner, _) => range_chain(inner),
+        Expr::Range { start, end, inclusive, .. } => Some(RangeChain {
+            start: start.as_deref(),
+            end: end.as_deref(),
+            inclusive: *inclusive,
+            ops: Vec::new(),
+        }),
+        Expr::MethodCall { receiver, method, args, .. } => {
+            let op = match (method.as_str(), args.as_slice()) {
+                ("step", [k]) => RangeOp::Step(&k.value),
+                ("rev", []) => RangeOp::Rev,
+                _ => return None,
+            };
+            let mut chain = range_chain(receiver)?;
+            chain.ops.push(op);
+            Some(chain)
+        }
+        _ => None,
+    }
+}
+
+/// The header of a C-style `for (int i = start; i cmp end; i += step)` loop, as
+/// emitted by the shader backends.
+pub(crate) struct CRangeLoop {
+    pub start: String,
+    pub cmp: &'static str,
+    pub end: String,
+    pub step: String,
+}
+
+/// `s` as a quoted C string literal, for the shader backends. Bytes outside
+/// printable ASCII are written in octal, which unlike `\x` stops after three
+/// digits whatever follows.
+pub(crate) fn c_string_literal(s: &str) -> String {
+    let mut out = String::from("\"");
+    for b in s.bytes() {
+        match b {
+            b'"' => out.push_str("\\\""),
+            b'\\' => out.push_str("\\\\"),
+            b'\n' => out.push_str("\\n"),
+            b'\t' => out.push_str("\\t"),
+            b' '..=b'~' => out.push(b as char),
+            _ => out.push_str(&format!("\\{:03o}", b)),
+        }
+    }
+    out.push('"');
+    out
+}
+
+/// Lower `range(n)`, `range(a, b)` or a range chain to a C-style loop header,
+/// emitting bound expressions with `emit`. Returns `None` for other iterators.
+pub(crate) fn c_range_loop(
+    iter: &Expr,
+    mut emit: impl FnMut(&Expr) -> KainResult<String>,
+) -> KainResult<Option<CRangeLoop>> {
+    if let Expr::Call { callee, args, .. } = iter {
+        if matches!(callee.as_ref(), Expr::Ident(name, _) if name == "range") {
+            let (start, end) = match args.as_slice() {
+                [end] => ("0".to_string(), emit(&end.value)?),
+                [start, end] => (emit(&start.value)?, emit(&end.value)?),
+                _ => return Ok(None),
+            };
+            return Ok(Some(CRangeLoop { start, cmp: "<", end, step: "1".to_string() }));
+        }
+    }
+    let Some(chain) = range_chain(iter) else {
+        return Ok(None);
+    };
+    let mut start = match chain.start {
+        Some(e) => emit(e)?,
+        None => "0".to_string(),
+    };
+    // An inclusive end is compared with `<=` rather than turned into `end + 1`, which overflows at the type's max
+    let mut end = match chain.end {
+        Some(e) => emit(e)?,
+        None => return Err(KainError::codegen("Unbounded ranges are not supported in shaders", iter.span())),
+    };
+    let mut inclusive = chain.inclusive;
+    let mut step = "1".to_string();
+    let mut ascending = true;
+    for op in &chain.ops {
+        match op {
+            RangeOp::Step(k) => step = format!("({} * {})", step, emit(k)?),
+            RangeOp::Rev => {
+                // Start at the last element and stop at the old start, inclusively
+                let dir = if ascending { 1 } else { -1 };
+                let len = if inclusive {
+                    format!("({d} * ({e} - {s}) >= 0 ? {d} * ({e} - {s}) / ({d} * {k}) + 1 : 0)", d = dir, e = end, s = start, k = step)
+                } else {
+                    format!("max(0, ({d} * ({e} - {s}) + {d} * {k} - 1) / ({d} * {k}))", d = dir, e = end, s = start, k = step)
+                };
+                end = start.clone();
+                inclusive = true;
+                start = format!("({} + ({} - 1) * {})", start, len, step);
+                step = format!("(-{})", step);
+                ascending = !ascending;
+            }
+        }
+    }
+    let cmp = match (ascending, inclusive) {
+        (true, false) => "<",
+        (true, true) => "<=",
+        (false, false) => ">",
+        (false, true) => ">=",
+    };
+    Ok(Some(CRangeLoop { start, cmp, end, step }))
+}
@@ -9,9 +9,16 @@ use crate::error::{KainResult, KainError};
 use crate::ast::{
     Type, Expr, Stmt, Block, BinaryOp, UnaryOp, Pattern, Function, Struct, Enum,
     Field, Variant, VariantFields, Impl, Param, MatchArm, CallArg, ElseBranch,
-    VariantPatternFields, EnumVariantFields,
+    VariantPatternFields, EnumVariantFields, Const, Static,
 };
 use crate::span::Span;
+use super::{range_chain, Intrinsics, RangeChain, RangeOp};
+use std::collections::{HashMap, HashSet};
+
+/// Builtins with a lowering of their own, see [`Intrinsics`]
+pub(crate) const LOWERED: &[&str] = &[
+    "print", "println", "insert", "remove", "pop", "slice", "concat", "flatten", "zip", "index_of",
+];
 
 /// Generate Rust source code from a typed program
 pub fn generate(program: &TypedProgram) -> KainResult<String> {
@@ -46,6 +53,14 @@ impl StringBuilder {
 struct RustGen {
     output: StringBuilder,
     indent: usize,
+    /// Per enclosing loop, its label and the flag its `break`s clear when it has an `else:`
+    loop_flags: Vec<(Option<String>, Option<String>)>,
+    flag_count: usize,
+    intrinsics: Intrinsics,
+    /// String consts, emitted as `&str` and turned into a `String` where used
+    string_consts: HashSet<String>,
+    /// Statics, each behind a mutex: name -> holds a `String`
+    statics: HashMap<String, bool>,
 }
 
 impl RustGen {
@@ -53,6 +68,45 @@ impl RustGen {
         Self {
             output: StringBuilder::new(),
             indent: 0,
+            loop_flags: Vec::new(),
+            flag_count: 0,
+            intrinsics: Intrinsics::new(LOWERED),
+            string_consts: HashSet::new(),
+            statics: HashMap::new(),
+        }
+    }
+
+    /// Pushes the loop and returns its `else:` flag plus the `'label: ` to put before the keyword
+    fn begin_loop(&mut self, label: &Option<String>, has_else: bool) -> (Option<String>, String) {
+        let flag = has_else.then(|| {
+            self.flag_count += 1;
+            format!("__no_break{}", self.flag_count)
+        });
+        if let Some(flag) = &flag {
+            self.write_line(&format!("let mut {} = true;", flag));
+        }
+        self.loop_flags.push((label.clone(), flag.clone()));
+        let prefix = label.as_ref().map(|l| format!("'{}: ", l)).unwrap_or_default();
+        (flag, prefix)
+    }
+
+    /// The flag of the loop a `break` with this label leaves
+    fn break_flag(&self, label: &Option<String>) -> Option<String> {
+        let target = match label {
+            Some(name) => self.loop_flags.iter().rev().find(|(l, _)| l.as_ref() == Some(name)),
+            None => self.loop_flags.last(),
+        };
+        target.and_then(|(_, flag)| flag.clone())
+    }
+
+    fn end_loop(&mut self, flag: Option<String>, else_branch: &Option<Block>) {
+        self.loop_flags.pop();
+        if let (Some(flag), Some(block)) = (flag, else_branch) {
+            self.write_line(&format!("if {} {{", flag));
+            self.push_indent();
+            self.gen_block(block);
+            self.pop_indent();
+            self.write_line("}");
         }
     }
 
@@ -81,6 +135,7 @@ impl RustGen {
 
     // Generate Rust code for an entire program
     fn gen_program(&mut self, program: &TypedProgram) -> String {
+        self.intrinsics.shadow(program);
         // Header
         self.write_line("// Generated by KAIN Compiler (Project Ouroboros)");
         self.write_line("// Do not edit - regenerate from .kn source");
@@ -97,6 +152,20 @@ impl RustGen {
         self.write_line("use std::cell::RefCell;");
         self.write_blank();
 
+        for item in &program.items {
+            match item {
+                TypedItem::Const(c) => {
+                    if let Expr::String(..) = c.ast.value {
+                        self.string_consts.insert(c.ast.name.clone());
+                    }
+                }
+                TypedItem::Static(s) => {
+                    self.statics.insert(s.ast.name.clone(), matches!(s.ast.value, Expr::String(..)));
+                }
+                _ => {}
+            }
+        }
+
         // Generate each item
         for item in &program.items {
             self.gen_item(item);
@@ -112,10 +181,51 @@ impl RustGen {
             TypedItem::Struct(st) => self.gen_struct(&st.ast),
             TypedItem::Enum(en) => self.gen_enum(&en.ast),
             TypedItem::Impl(im) => self.gen_impl(&im.ast),
+            TypedItem::Const(c) => self.gen_const(&c.ast),
+            TypedItem::Static(s) => self.gen_static(&s.ast),
             _ => {} // Skip shaders, actors, components, traits, etc. for Rust output
         }
     }
 
+    // Generate a const from the literal comptime folded it to
+    fn gen_const(&mut self, c: &Const) {
+        let vis = match c.visibility {
+            crate::ast::Visibility::Public => "pub ",
+            _ => "",
+        };
+        let (ty, value) = match &c.value {
+            Expr::Int(n, _) => ("i64", n.to_string()),
+            Expr::Float(f, _) => ("f64", format!("{:?}", f)),
+            Expr::Bool(b, _) => ("bool", b.to_string()),
+            Expr::Char(c, _) => ("char", format!("{:?}", c)),
+            Expr::String(s, _) => ("&str", format!("\"{}\"", self.escape_string(s))),
+            // Arrays and structs allocate, which a Rust const cannot
+            _ => return,
+        };
+        self.write_line(&format!("{}const {}: {} = {};", vis, c.name, ty, value));
+    }
+
+    // Generate a static behind a mutex; strings are built on first use
+    fn gen_static(&mut self, s: &Static) {
+        let vis = match s.visibility {
+            crate::ast::Visibility::Public => "pub ",
+            _ => "",
+        };
+        let ty = self.map_type(&s.ty);
+        let value = self.gen_expr(&s.value);
+        if self.statics.get(&s.name) == Some(&true) {
+            self.write_line(&format!(
+                "{}static {}: std::sync::LazyLock<std::sync::Mutex<{}>> = std::sync::LazyLock::new(|| std::sync::Mutex::new({}));",
+                vis, s.name, ty, value
+            ));
+        } else {
+            self.write_line(&format!(
+                "{}static {}: std::sync::Mutex<{}> = std::sync::Mutex::new({});",
+                vis, s.name, ty, value
+            ));
+        }
+    }
+
     // Generate a function definition
     fn gen_function(&mut self, func: &Function) {
         let vis = match func.visibility {
@@ -237,8 +347,11 @@ impl RustGen {
             Stmt::Let { pattern, ty, value, .. } => {
                 let pat_str = self.gen_pattern(pattern);
                 let ty_str = ty.as_ref().map(|t| format!(": {}", self.map_type(t))).unwrap_or_default();
+                // A refutable pattern comes from `guard let`, whose value diverges when it doesn't match
+                let refutable = matches!(pattern, Pattern::Variant { .. } | Pattern::Literal(_) | Pattern::Slice { .. } | Pattern::Or(..) | Pattern::Range { .. });
+                let otherwise = if refutable { " else { unreachable!() }" } else { "" };
                 if let Some(val) = value {
-                    self.write_line(&format!("let {}{} = {};", pat_str, ty_str, self.gen_expr(val)));
+                    self.write_line(&format!("let {}{} = {}{};", pat_str, ty_str, self.gen_expr(val), otherwise));
                 } else {
                     self.write_line(&format!("let {}{};", pat_str, ty_str));
                 }
@@ -252,47 +365,77 @@ impl RustGen {
                 }
             }
 
-            Stmt::Break(maybe_expr, _) => {
+            Stmt::Break(maybe_expr, label, _) => {
+                if let Some(flag) = self.break_flag(label) {
+                    self.write_line(&format!("{} = false;", flag));
+                }
+                let target = label.as_ref().map(|l| format!(" '{}", l)).unwrap_or_default();
                 if let Some(expr) = maybe_expr {
-                    self.write_line(&format!("break {};", self.gen_expr(expr)));
+                    self.write_line(&format!("break{} {};", target, self.gen_expr(expr)));
                 } else {
-                    self.write_line("break;");
+                    self.write_line(&format!("break{};", target));
                 }
             }
 
-            Stmt::Continue(_) => {
-                self.write_line("continue;");
-            }
+            Stmt::Continue(label, _) => match label {
+                Some(l) => self.write_line(&format!("continue '{};", l)),
+                None => self.write_line("continue;"),
+            },
 
-            Stmt::For { binding, iter, body, .. } => {
+            Stmt::For { label, binding, iter, body, else_branch, .. } => {
+                let (flag, prefix) = self.begin_loop(label, else_branch.is_some());
                 let pat = self.gen_pattern(binding);
-                self.write_line(&format!("for {} in {} {{", pat, self.gen_expr(iter)));
+                self.write_line(&format!("{}for {} in {} {{", prefix, pat, self.gen_expr(iter)));
+                self.push_indent();
+                self.gen_block(body);
+                self.pop_indent();
+                self.write_line("}");
+                self.end_loop(flag, else_branch);
+            }
+
+            Stmt::While { label, condition, body, else_branch, .. } => {
+                let (flag, prefix) = self.begin_loop(label, else_branch.is_some());
+                self.write_line(&format!("{}while {} {{", prefix, self.gen_expr(condition)));
                 self.push_indent();
                 self.gen_block(body);
                 self.pop_indent();
                 self.write_line("}");
+                self.end_loop(flag, else_branch);
             }
 
-            Stmt::While { condition, body, .. } => {
-                self.write_line(&format!("while {} {{", self.gen_expr(condition)));
+            // Values a region allocated are dropped with its block
+            Stmt::Region { body, .. } => {
+                self.write_line("{");
                 self.push_indent();
                 self.gen_block(body);
                 self.pop_indent();
                 self.write_line("}");
             }
 
-            Stmt::Loop { body, .. } => {
-                self.write_line("loop {");
+            Stmt::Loop { label, body, .. } => {
+                let (_, prefix) = self.begin_loop(label, false);
+                self.write_line(&format!("{}loop {{", prefix));
                 self.push_indent();
                 self.gen_block(body);
                 self.pop_indent();
                 self.write_line("}");
+                self.end_loop(None, &None);
             }
 
             Stmt::Expr(expr) => {
                 // Check for assignment expression
                 if let Expr::Assign { target, value, .. } = expr {
-                    self.write_line(&format!("{} = {};", self.gen_expr(target), self.gen_expr(value)));
+                    match target.as_ref() {
+                        // The value may read the static, so it is computed before taking the lock
+                        Expr::Ident(name, _) if self.statics.contains_key(name) => {
+                            self.write_line(&format!(
+                                "{{ let __value = {}; *{}.lock().unwrap() = __value; }}",
+                                self.gen_expr(value),
+                                name
+                            ));
+                        }
+                        _ => self.write_line(&format!("{} = {};", self.gen_expr(target), self.gen_expr(value))),
+                    }
                 } else {
                     self.write_line(&format!("{};", self.gen_expr(expr)));
                 }
@@ -305,13 +448,53 @@ impl RustGen {
         }
     }
 
+    /// Array builtins as `Vec` methods; indices are `i64` in KAIN
+    fn gen_array_builtin(&self, name: &str, args: &[CallArg]) -> Option<String> {
+        let a: Vec<String> = args.iter().map(|arg| self.gen_expr(&arg.value)).collect();
+        Some(match (name, a.as_slice()) {
+            ("insert", [arr, i, v]) => format!("{}.insert({} as usize, {})", arr, i, v),
+            ("remove", [arr, i]) => format!("{}.remove({} as usize)", arr, i),
+            ("pop", [arr]) => format!("{}.pop()", arr),
+            ("slice", [arr, start]) => format!("{}[{} as usize..].to_vec()", arr, start),
+            ("slice", [arr, start, end]) => format!("{}[{} as usize..{} as usize].to_vec()", arr, start, end),
+            ("concat", [x, y]) => format!("[&{}[..], &{}[..]].concat()", x, y),
+            ("flatten", [arr]) => format!("{}.concat()", arr),
+            ("zip", [x, y]) => format!("{}.iter().cloned().zip({}.iter().cloned()).collect::<Vec<_>>()", x, y),
+            ("index_of", [arr, v]) => format!("{}.iter().position(|x| *x == {}).map(|i| i as i64)", arr, v),
+            _ => return None,
+        })
+    }
+
+    /// Lower a range with its `step`/`rev` calls. Stepped or reversed ranges
+    /// are collected, since `StepBy<Range<i64>>` is not double-ended.
+    fn gen_range_chain(&self, chain: &RangeChain) -> String {
+        let start = chain.start.map(|e| self.gen_expr(e)).unwrap_or_else(|| "0".to_string());
+        let end = chain.end.map(|e| self.gen_expr(e)).unwrap_or_default();
+        let dots = if chain.inclusive { "..=" } else { ".." };
+        let mut out = format!("({}{}{})", start, dots, end);
+        if chain.ops.is_empty() {
+            return out;
+        }
+        for op in &chain.ops {
+            out = match op {
+                RangeOp::Step(k) => format!("{}.step_by({} as usize)", out, self.gen_expr(k)),
+                RangeOp::Rev => format!("{}.collect::<Vec<i64>>().into_iter().rev()", out),
+            };
+        }
+        format!("{}.collect::<Vec<i64>>()", out)
+    }
+
     fn gen_expr(&self, expr: &Expr) -> String {
         match expr {
             Expr::Int(n, _) => n.to_string(),
             Expr::Float(f, _) => format!("{:.1}", f),
             Expr::String(s, _) => format!("\"{}\".to_string()", self.escape_string(s)),
             Expr::Bool(b, _) => if *b { "true".to_string() } else { "false".to_string() },
+            Expr::Char(c, _) => format!("{:?}", c),
             Expr::None(_) => "None".to_string(),
+            Expr::Ident(name, _) if self.string_consts.contains(name) => format!("{}.to_string()", name),
+            Expr::Ident(name, _) if self.statics.get(name) == Some(&true) => format!("{}.lock().unwrap().clone()", name),
+            Expr::Ident(name, _) if self.statics.contains_key(name) => format!("(*{}.lock().unwrap())", name),
             Expr::Ident(name, _) => name.clone(),
 
             Expr::Binary { left, op, right, .. } => {
@@ -329,9 +512,13 @@ impl RustGen {
 
             Expr::Call { callee, args, .. } => {
                 let fn_name = self.gen_expr(callee);
+                let builtin = match callee.as_ref() {
+                    Expr::Ident(name, _) => self.intrinsics.lowered(name).map(|f| f.name),
+                    _ => None,
+                };
 
                 // Handle KAIN builtins
-                if fn_name == "println" || fn_name == "print" {
+                if matches!(builtin, Some("println" | "print")) {
                     let arg_strs: Vec<String> = args.iter().map(|a| self.gen_expr(&a.value)).collect();
                     let placeholders: Vec<&str> = arg_strs.iter().map(|_| "{}").collect();
                     let format_str = format!("\"{}\"", placeholders.join(" "));
@@ -341,6 +528,10 @@ impl RustGen {
                     return format!("{}!()", fn_name);
                 }
 
+                if let Some(lowered) = builtin.and_then(|name| self.gen_array_builtin(name, args)) {
+                    return lowered;
+                }
+
                 let arg_strs: Vec<String> = args.iter().map(|a| {
                     if let Some(name) = &a.name {
                         format!("{}: {}", name, self.gen_expr(&a.value))
@@ -352,6 +543,18 @@ impl RustGen {
             }
 
             Expr::MethodCall { receiver, method, args, .. } => {
+                if let Some(chain) = range_chain(expr) {
+                    return self.gen_range_chain(&chain);
+                }
+                if let Some(chain) = range_chain(receiver) {
+                    let recv = self.gen_range_chain(&chain);
+                    match (method.as_str(), args.as_slice()) {
+                        ("contains", [x]) => return format!("{}.contains(&{})", recv, self.gen_expr(&x.value)),
+                        ("len", []) => return format!("({}.into_iter().count() as i64)", recv),
+                        ("to_array", []) => return format!("{}.into_iter().collect::<Vec<i64>>()", recv),
+                        _ => {}
+                    }
+                }
                 let recv = self.gen_expr(receiver);
                 let arg_strs: Vec<String> = args.iter().map(|a| self.gen_expr(&a.value)).collect();
                 format!("{}.{}({})", recv, method, arg_strs.join(", "))
@@ -482,15 +685,19 @@ impl RustGen {
                 }
             }
 
-            Expr::Break(maybe_expr, _) => {
+            Expr::Break(maybe_expr, label, _) => {
+                let target = label.as_ref().map(|l| format!(" '{}", l)).unwrap_or_default();
                 if let Some(expr) = maybe_expr {
-                    format!("break {}", self.gen_expr(expr))
+                    format!("break{} {}", target, self.gen_expr(expr))
                 } else {
-                    "break".to_string()
+                    format!("break{}", target)
                 }
             }
 
-            Expr::Continue(_) => "continue".to_string(),
+            Expr::Continue(label, _) => match label {
+                Some(l) => format!("continue '{}", l),
+                None => "continue".to_string(),
+            },
 
             // FString - format string with interpolation
             Expr::FString(parts, _) => {
@@ -498,7 +705,7 @@ impl RustGen {
                 let mut args = Vec::new();
                 for part in parts {
                     if let Expr::String(s, _) = part {
-                        format_str.push_str(s);
+                        format_str.push_str(&self.escape_string(s).replace('{', "{{").replace('}', "}}"));
                     } else {
                         format_str.push_str("{}");
                         args.push(self.gen_expr(part));
@@ -631,6 +838,7 @@ impl RustGen {
                     "Float" => "f64",
                     "Bool" => "bool",
                     "String" => "String",
+                    "Char" => "char",
                     "Unit" => "()",
                     "Array" => "Vec",
                     _ => name,
@@ -731,9 +939,11 @@ impl RustGen {
         for c in s.chars() {
             match c {
                 '\n' => result.push_str("\\n"),
+                '\r' => result.push_str("\\r"),
                 '\t' => result.push_str("\\t"),
                 '\\' => result.push_str("\\\\"),
                 '"' => result.push_str("\\\""),
+                c if c.is_control() => result.push_str(&format!("\\u{{{:x}}}", c as u32)),
                 _ => result.push(c),
             }
         }
@@ -775,4 +985,45 @@ mod tests {
         };
         assert_eq!(gen.map_type(&int_ty), "i64");
     }
+
+    #[test]
+    fn test_string_literals_are_re_escaped() {
+        let gen = RustGen::new();
+        let span = Span::default();
+        let text = "say \"hi\"\\\r\n\u{1b}é";
+        assert_eq!(gen.gen_expr(&Expr::String(text.to_string(), span)), r#""say \"hi\"\\\r\n\u{1b}é".to_string()"#);
+        let fstring = Expr::FString(vec![Expr::String("{x} = ".to_string(), span), Expr::Ident("x".to_string(), span)], span);
+        assert_eq!(gen.gen_expr(&fstring), r#"format!("{{x}} = {}", x)"#);
+    }
+
+    #[test]
+    fn test_array_builtins_lower_to_vec_methods() {
+        let gen = RustGen::new();
+        let arg = |name: &str| CallArg { name: None, value: Expr::Ident(name.to_string(), Span::default()), spread: false, span: Span::default() };
+        assert_eq!(gen.gen_array_builtin("slice", &[arg("xs"), arg("i")]).unwrap(), "xs[i as usize..].to_vec()");
+        assert_eq!(gen.gen_array_builtin("index_of", &[arg("xs"), arg("v")]).unwrap(), "xs.iter().position(|x| *x == v).map(|i| i as i64)");
+        assert!(gen.gen_array_builtin("len", &[arg("xs")]).is_none());
+    }
+
+    #[test]
+    fn test_loop_else_runs_unless_broken() {
+        let mut gen = RustGen::new();
+        let span = Span::default();
+        let block = |stmts| Block { stmts, span };
+        let outer = Some("outer".to_string());
+        gen.gen_stmt(&Stmt::While {
+            label: outer.clone(),
+            condition: Expr::Bool(true, span),
+            body: block(vec![
+                Stmt::Loop { label: None, body: block(vec![Stmt::Break(None, None, span), Stmt::Break(None, outer.clone(), span)]), span },
+                Stmt::Break(None, None, span),
+            ]),
+            else_branch: Some(block(vec![Stmt::Return(None, span)])),
+            span,
+        });
+        assert_eq!(
+            gen.output.build(),
+            "let mut __no_break1 = true;\n'outer: while true {\n    loop {\n        break;\n        __no_break1 = false;\n        break 'outer;\n    }\n    __no_break1 = false;\n    break;\n}\nif __no_break1 {\n    return;\n}\n"
+        );
+    }
 }
@@ -1,7 +1,9 @@
 //! SPIR-V Code Generation for GPU shaders
 
 use crate::types::{TypedProgram, TypedItem, TypedShader};
-use crate::error::{KainResult, KainError};
+use crate::error::{Ice, KainResult, KainError};
+use crate::Phase;
+use crate::span::Span;
 use crate::ast::{Type, ShaderStage, Expr, Stmt, Block, BinaryOp};
 use rspirv::binary::Assemble;
 use rspirv::dr::{Builder, Operand};
@@ -122,8 +124,8 @@ fn emit_shader(b: &mut Builder, shader: &TypedShader) -> KainResult<()> {
     }
 
     // 4. Function Body
-    let main_fn = b.begin_function(void, None, rspirv::spirv::FunctionControl::NONE, fn_void_void).unwrap();
-    b.begin_block(None).unwrap();
+    let main_fn = b.begin_function(void, None, rspirv::spirv::FunctionControl::NONE, fn_void_void).map_err(rejected)?;
+    b.begin_block(None).map_err(rejected)?;
 
     let mut ctx = ShaderContext {
         b,
@@ -137,10 +139,10 @@ fn emit_shader(b: &mut Builder, shader: &TypedShader) -> KainResult<()> {
 
     // Ensure we always have a return
     if shader.ast.body.stmts.last().map_or(true, |s| !matches!(s, Stmt::Return(_, _))) {
-        ctx.b.ret().unwrap();
+        ctx.b.ret().map_err(rejected)?;
     }
     
-    ctx.b.end_function().unwrap();
+    ctx.b.end_function().map_err(rejected)?;
 
     // 5. Entry Point
     b.entry_point(exec_model, main_fn, &shader.ast.name, interface_vars);
@@ -171,10 +173,10 @@ fn emit_block(ctx: &mut ShaderContext, block: &Block) -> KainResult<()> {
                 if let Some(expr) = expr {
                     if let Some(out_var) = ctx.output_var {
                         let (val, _) = emit_expr(ctx, expr)?;
-                        ctx.b.store(out_var, val, None, vec![]).unwrap();
+                        ctx.b.store(out_var, val, None, vec![]).map_err(rejected)?;
                     }
                 }
-                ctx.b.ret().unwrap();
+                ctx.b.ret().map_err(rejected)?;
             },
             Stmt::Let { pattern, value, .. } => {
                 if let Some(value) = value {
@@ -210,12 +212,12 @@ fn emit_expr(ctx: &mut ShaderContext, expr: &Expr) -> KainResult<(u32, Type)> {
                         let ptr_ty = ctx.b.type_pointer(None, StorageClass::Uniform, type_id);
                         let int_ty = ctx.b.type_int(32, 0);
                         let zero = ctx.b.constant_bit32(int_ty, 0);
-                        let member_ptr = ctx.b.access_chain(ptr_ty, None, id, vec![zero]).unwrap();
-                        let val_id = ctx.b.load(type_id, None, member_ptr, None, std::iter::empty()).unwrap();
+                        let member_ptr = ctx.b.access_chain(ptr_ty, None, id, vec![zero]).map_err(rejected)?;
+                        let val_id = ctx.b.load(type_id, None, member_ptr, None, std::iter::empty()).map_err(rejected)?;
                         Ok((val_id, ty))
                     } else {
                         // Direct load for inputs and non-wrapped uniforms
-                        let val_id = ctx.b.load(type_id, None, id, None, std::iter::empty()).unwrap();
+                        let val_id = ctx.b.load(type_id, None, id, None, std::iter::empty()).map_err(rejected)?;
                         Ok((val_id, ty))
                     }
                 } else {
@@ -235,25 +237,25 @@ fn emit_expr(ctx: &mut ShaderContext, expr: &Expr) -> KainResult<(u32, Type)> {
             let res_id = match op {
                 BinaryOp::Mul => {
                     if is_mat4(&lhs_ty) && is_mat4(&rhs_ty) {
-                        ctx.b.matrix_times_matrix(res_ty_id, None, lhs, rhs).unwrap()
+                        ctx.b.matrix_times_matrix(res_ty_id, None, lhs, rhs).map_err(rejected)?
                     } else if is_mat4(&lhs_ty) && is_vec4(&rhs_ty) {
                         // Mat4 * Vec4 -> Vec4
                          let vec4_ty = map_ast_type(ctx.b, &rhs_ty);
-                         ctx.b.matrix_times_vector(vec4_ty, None, lhs, rhs).unwrap()
+                         ctx.b.matrix_times_vector(vec4_ty, None, lhs, rhs).map_err(rejected)?
                     } else if is_vec4(&lhs_ty) && is_mat4(&rhs_ty) {
                         // Vec4 * Mat4 -> Vec4
                          let vec4_ty = map_ast_type(ctx.b, &lhs_ty);
-                         ctx.b.vector_times_matrix(vec4_ty, None, lhs, rhs).unwrap()
+                         ctx.b.vector_times_matrix(vec4_ty, None, lhs, rhs).map_err(rejected)?
                     } else if is_float(&lhs_ty) && is_float(&rhs_ty) {
-                        ctx.b.f_mul(res_ty_id, None, lhs, rhs).unwrap()
+                        ctx.b.f_mul(res_ty_id, None, lhs, rhs).map_err(rejected)?
                     } else {
                          // Fallback to FMul (vector * scalar, etc - simplified)
-                        ctx.b.f_mul(res_ty_id, None, lhs, rhs).unwrap()
+                        ctx.b.f_mul(res_ty_id, None, lhs, rhs).map_err(rejected)?
                     }
                 },
-                BinaryOp::Add => ctx.b.f_add(res_ty_id, None, lhs, rhs).unwrap(),
-                BinaryOp::Sub => ctx.b.f_sub(res_ty_id, None, lhs, rhs).unwrap(),
-                BinaryOp::Div => ctx.b.f_div(res_ty_id, None, lhs, rhs).unwrap(),
+                BinaryOp::Add => ctx.b.f_add(res_ty_id, None, lhs, rhs).map_err(rejected)?,
+                BinaryOp::Sub => ctx.b.f_sub(res_ty_id, None, lhs, rhs).map_err(rejected)?,
+                BinaryOp::Div => ctx.b.f_div(res_ty_id, None, lhs, rhs).map_err(rejected)?,
                 _ => return Err(KainError::codegen("Unsupported binary op in shader", expr.span())),
             };
             
@@ -279,7 +281,7 @@ fn emit_expr(ctx: &mut ShaderContext, expr: &Expr) -> KainResult<(u32, Type)> {
                             let (val, _) = emit_expr(ctx, &arg.value)?;
                             components.push(val);
                         }
-                        let res_id = ctx.b.composite_construct(vec2, None, components).unwrap();
+                        let res_id = ctx.b.composite_construct(vec2, None, components).map_err(rejected)?;
                         return Ok((res_id, Type::Named { name: "Vec2".into(), generics: vec![], span: expr.span() }));
                     },
                     "vec3" | "Vec3" if args.len() == 3 => {
@@ -289,7 +291,7 @@ fn emit_expr(ctx: &mut ShaderContext, expr: &Expr) -> KainResult<(u32, Type)> {
                             let (val, _) = emit_expr(ctx, &arg.value)?;
                             components.push(val);
                         }
-                        let res_id = ctx.b.composite_construct(vec3, None, components).unwrap();
+                        let res_id = ctx.b.composite_construct(vec3, None, components).map_err(rejected)?;
                         return Ok((res_id, Type::Named { name: "Vec3".into(), generics: vec![], span: expr.span() }));
                     },
                     "vec4" | "Vec4" if args.len() == 4 => {
@@ -299,7 +301,7 @@ fn emit_expr(ctx: &mut ShaderContext, expr: &Expr) -> KainResult<(u32, Type)> {
                             let (val, _) = emit_expr(ctx, &arg.value)?;
                             components.push(val);
                         }
-                        let res_id = ctx.b.composite_construct(vec4, None, components).unwrap();
+                        let res_id = ctx.b.composite_construct(vec4, None, components).map_err(rejected)?;
                         return Ok((res_id, Type::Named { name: "Vec4".into(), generics: vec![], span: expr.span() }));
                     },
                     
@@ -308,21 +310,21 @@ fn emit_expr(ctx: &mut ShaderContext, expr: &Expr) -> KainResult<(u32, Type)> {
                         let (val, ty) = emit_expr(ctx, &args[0].value)?;
                         let res_ty = map_ast_type(ctx.b, &ty);
                         let glsl = ctx.get_glsl_ext();
-                        let res_id = ctx.b.ext_inst(res_ty, None, glsl, 13, vec![Operand::IdRef(val)]).unwrap(); // Sin = 13
+                        let res_id = ctx.b.ext_inst(res_ty, None, glsl, 13, vec![Operand::IdRef(val)]).map_err(rejected)?; // Sin = 13
                         return Ok((res_id, ty));
                     },
                     "cos" if args.len() == 1 => {
                         let (val, ty) = emit_expr(ctx, &args[0].value)?;
                         let res_ty = map_ast_type(ctx.b, &ty);
                         let glsl = ctx.get_glsl_ext();
-                        let res_id = ctx.b.ext_inst(res_ty, None, glsl, 14, vec![Operand::IdRef(val)]).unwrap(); // Cos = 14
+                        let res_id = ctx.b.ext_inst(res_ty, None, glsl, 14, vec![Operand::IdRef(val)]).map_err(rejected)?; // Cos = 14
                         return Ok((res_id, ty));
                     },
                     "tan" if args.len() == 1 => {
                         let (val, ty) = emit_expr(ctx, &args[0].value)?;
                         let res_ty = map_ast_type(ctx.b, &ty);
                         let glsl = ctx.b.ext_inst_import("GLSL.std.450");
-                        let res_id = ctx.b.ext_inst(res_ty, None, glsl, 15, vec![Operand::IdRef(val)]).unwrap(); // Tan = 15
+                        let res_id = ctx.b.ext_inst(res_ty, None, glsl, 15, vec![Operand::IdRef(val)]).map_err(rejected)?; // Tan = 15
                         return Ok((res_id, ty));
                     },
                     "pow" if args.len() == 2 => {
@@ -330,42 +332,42 @@ fn emit_expr(ctx: &mut ShaderContext, expr: &Expr) -> KainResult<(u32, Type)> {
                         let (exp, _) = emit_expr(ctx, &args[1].value)?;
                         let res_ty = map_ast_type(ctx.b, &ty);
                         let glsl = ctx.b.ext_inst_import("GLSL.std.450");
-                        let res_id = ctx.b.ext_inst(res_ty, None, glsl, 26, vec![Operand::IdRef(base), Operand::IdRef(exp)]).unwrap(); // Pow = 26
+                        let res_id = ctx.b.ext_inst(res_ty, None, glsl, 26, vec![Operand::IdRef(base), Operand::IdRef(exp)]).map_err(rejected)?; // Pow = 26
                         return Ok((res_id, ty));
                     },
                     "sqrt" if args.len() == 1 => {
                         let (val, ty) = emit_expr(ctx, &args[0].value)?;
                         let res_ty = map_ast_type(ctx.b, &ty);
                         let glsl = ctx.b.ext_inst_import("GLSL.std.450");
-                        let res_id = ctx.b.ext_inst(res_ty, None, glsl, 31, vec![Operand::IdRef(val)]).unwrap(); // Sqrt = 31
+                        let res_id = ctx.b.ext_inst(res_ty, None, glsl, 31, vec![Operand::IdRef(val)]).map_err(rejected)?; // Sqrt = 31
                         return Ok((res_id, ty));
                     },
                     "abs" if args.len() == 1 => {
                         let (val, ty) = emit_expr(ctx, &args[0].value)?;
                         let res_ty = map_ast_type(ctx.b, &ty);
                         let glsl = ctx.b.ext_inst_import("GLSL.std.450");
-                        let res_id = ctx.b.ext_inst(res_ty, None, glsl, 4, vec![Operand::IdRef(val)]).unwrap(); // FAbs = 4
+                        let res_id = ctx.b.ext_inst(res_ty, None, glsl, 4, vec![Operand::IdRef(val)]).map_err(rejected)?; // FAbs = 4
                         return Ok((res_id, ty));
                     },
                     "floor" if args.len() == 1 => {
                         let (val, ty) = emit_expr(ctx, &args[0].value)?;
                         let res_ty = map_ast_type(ctx.b, &ty);
                         let glsl = ctx.b.ext_inst_import("GLSL.std.450");
-                        let res_id = ctx.b.ext_inst(res_ty, None, glsl, 8, vec![Operand::IdRef(val)]).unwrap(); // Floor = 8
+                        let res_id = ctx.b.ext_inst(res_ty, None, glsl, 8, vec![Operand::IdRef(val)]).map_err(rejected)?; // Floor = 8
                         return Ok((res_id, ty));
                     },
                     "ceil" if args.len() == 1 => {
                         let (val, ty) = emit_expr(ctx, &args[0].value)?;
                         let res_ty = map_ast_type(ctx.b, &ty);
                         let glsl = ctx.b.ext_inst_import("GLSL.std.450");
-                        let res_id = ctx.b.ext_inst(res_ty, None, glsl, 9, vec![Operand::IdRef(val)]).unwrap(); // Ceil = 9
+                        let res_id = ctx.b.ext_inst(res_ty, None, glsl, 9, vec![Operand::IdRef(val)]).map_err(rejected)?; // Ceil = 9
                         return Ok((res_id, ty));
                     },
                     "fract" if args.len() == 1 => {
                         let (val, ty) = emit_expr(ctx, &args[0].value)?;
                         let res_ty = map_ast_type(ctx.b, &ty);
                         let glsl = ctx.b.ext_inst_import("GLSL.std.450");
-                        let res_id = ctx.b.ext_inst(res_ty, None, glsl, 10, vec![Operand::IdRef(val)]).unwrap(); // Fract = 10
+                        let res_id = ctx.b.ext_inst(res_ty, None, glsl, 10, vec![Operand::IdRef(val)]).map_err(rejected)?; // Fract = 10
                         return Ok((res_id, ty));
                     },
                     "min" if args.len() == 2 => {
@@ -373,7 +375,7 @@ fn emit_expr(ctx: &mut ShaderContext, expr: &Expr) -> KainResult<(u32, Type)> {
                         let (b, _) = emit_expr(ctx, &args[1].value)?;
                         let res_ty = map_ast_type(ctx.b, &ty);
                         let glsl = ctx.b.ext_inst_import("GLSL.std.450");
-                        let res_id = ctx.b.ext_inst(res_ty, None, glsl, 37, vec![Operand::IdRef(a), Operand::IdRef(b)]).unwrap(); // FMin = 37
+                        let res_id = ctx.b.ext_inst(res_ty, None, glsl, 37, vec![Operand::IdRef(a), Operand::IdRef(b)]).map_err(rejected)?; // FMin = 37
                         return Ok((res_id, ty));
                     },
                     "max" if args.len() == 2 => {
@@ -381,7 +383,7 @@ fn emit_expr(ctx: &mut ShaderContext, expr: &Expr) -> KainResult<(u32, Type)> {
                         let (b, _) = emit_expr(ctx, &args[1].value)?;
                         let res_ty = map_ast_type(ctx.b, &ty);
                         let glsl = ctx.b.ext_inst_import("GLSL.std.450");
-                        let res_id = ctx.b.ext_inst(res_ty, None, glsl, 40, vec![Operand::IdRef(a), Operand::IdRef(b)]).unwrap(); // FMax = 40
+                        let res_id = ctx.b.ext_inst(res_ty, None, glsl, 40, vec![Operand::IdRef(a), Operand::IdRef(b)]).map_err(rejected)?; // FMax = 40
                         return Ok((res_id, ty));
                     },
                     "clamp" if args.len() == 3 => {
@@ -390,7 +392,7 @@ fn emit_expr(ctx: &mut ShaderContext, expr: &Expr) -> KainResult<(u32, Type)> {
                         let (max_val, _) = emit_expr(ctx, &args[2].value)?;
                         let res_ty = map_ast_type(ctx.b, &ty);
                         let glsl = ctx.b.ext_inst_import("GLSL.std.450");
-                        let res_id = ctx.b.ext_inst(res_ty, None, glsl, 43, vec![Operand::IdRef(val), Operand::IdRef(min_val), Operand::IdRef(max_val)]).unwrap(); // FClamp = 43
+                        let res_id = ctx.b.ext_inst(res_ty, None, glsl, 43, vec![Operand::IdRef(val), Operand::IdRef(min_val), Operand::IdRef(max_val)]).map_err(rejected)?; // FClamp = 43
                         return Ok((res_id, ty));
                     },
                     "mix" if args.len() == 3 => {
@@ -399,7 +401,7 @@ fn emit_expr(ctx: &mut ShaderContext, expr: &Expr) -> KainResult<(u32, Type)> {
                         let (t, _) = emit_expr(ctx, &args[2].value)?;
                         let res_ty = map_ast_type(ctx.b, &ty);
                         let glsl = ctx.b.ext_inst_import("GLSL.std.450");
-                        let res_id = ctx.b.ext_inst(res_ty, None, glsl, 46, vec![Operand::IdRef(a), Operand::IdRef(b), Operand::IdRef(t)]).unwrap(); // FMix = 46
+                        let res_id = ctx.b.ext_inst(res_ty, None, glsl, 46, vec![Operand::IdRef(a), Operand::IdRef(b), Operand::IdRef(t)]).map_err(rejected)?; // FMix = 46
                         return Ok((res_id, ty));
                     },
                     "step" if args.len() == 2 => {
@@ -407,7 +409,7 @@ fn emit_expr(ctx: &mut ShaderContext, expr: &Expr) -> KainResult<(u32, Type)> {
                         let (x, _) = emit_expr(ctx, &args[1].value)?;
                         let res_ty = map_ast_type(ctx.b, &ty);
                         let glsl = ctx.b.ext_inst_import("GLSL.std.450");
-                        let res_id = ctx.b.ext_inst(res_ty, None, glsl, 48, vec![Operand::IdRef(edge), Operand::IdRef(x)]).unwrap(); // Step = 48
+                        let res_id = ctx.b.ext_inst(res_ty, None, glsl, 48, vec![Operand::IdRef(edge), Operand::IdRef(x)]).map_err(rejected)?; // Step = 48
                         return Ok((res_id, ty));
                     },
                     "smoothstep" if args.len() == 3 => {
@@ -416,26 +418,26 @@ fn emit_expr(ctx: &mut ShaderContext, expr: &Expr) -> KainResult<(u32, Type)> {
                         let (x, _) = emit_expr(ctx, &args[2].value)?;
                         let res_ty = map_ast_type(ctx.b, &ty);
                         let glsl = ctx.b.ext_inst_import("GLSL.std.450");
-                        let res_id = ctx.b.ext_inst(res_ty, None, glsl, 49, vec![Operand::IdRef(edge0), Operand::IdRef(edge1), Operand::IdRef(x)]).unwrap(); // SmoothStep = 49
+                        let res_id = ctx.b.ext_inst(res_ty, None, glsl, 49, vec![Operand::IdRef(edge0), Operand::IdRef(edge1), Operand::IdRef(x)]).map_err(rejected)?; // SmoothStep = 49
                         return Ok((res_id, ty));
                     },
                     "length" if args.len() == 1 => {
                         let (val, _) = emit_expr(ctx, &args[0].value)?;
                         let glsl = ctx.b.ext_inst_import("GLSL.std.450");
-                        let res_id = ctx.b.ext_inst(float, None, glsl, 66, vec![Operand::IdRef(val)]).unwrap(); // Length = 66
+                        let res_id = ctx.b.ext_inst(float, None, glsl, 66, vec![Operand::IdRef(val)]).map_err(rejected)?; // Length = 66
                         return Ok((res_id, Type::Named { name: "Float".into(), generics: vec![], span: expr.span() }));
                     },
                     "normalize" if args.len() == 1 => {
                         let (val, ty) = emit_expr(ctx, &args[0].value)?;
                         let res_ty = map_ast_type(ctx.b, &ty);
                         let glsl = ctx.b.ext_inst_import("GLSL.std.450");
-                        let res_id = ctx.b.ext_inst(res_ty, None, glsl, 69, vec![Operand::IdRef(val)]).unwrap(); // Normalize = 69
+                        let res_id = ctx.b.ext_inst(res_ty, None, glsl, 69, vec![Operand::IdRef(val)]).map_err(rejected)?; // Normalize = 69
                         return Ok((res_id, ty));
                     },
                     "dot" if args.len() == 2 => {
                         let (a, _) = emit_expr(ctx, &args[0].value)?;
                         let (b, _) = emit_expr(ctx, &args[1].value)?;
-                        let res_id = ctx.b.dot(float, None, a, b).unwrap();
+                        let res_id = ctx.b.dot(float, None, a, b).map_err(rejected)?;
                         return Ok((res_id, Type::Named { name: "Float".into(), generics: vec![], span: expr.span() }));
                     },
                     "cross" if args.len() == 2 => {
@@ -443,7 +445,7 @@ fn emit_expr(ctx: &mut ShaderContext, expr: &Expr) -> KainResult<(u32, Type)> {
                         let (b, _) = emit_expr(ctx, &args[1].value)?;
                         let res_ty = map_ast_type(ctx.b, &ty);
                         let glsl = ctx.b.ext_inst_import("GLSL.std.450");
-                        let res_id = ctx.b.ext_inst(res_ty, None, glsl, 68, vec![Operand::IdRef(a), Operand::IdRef(b)]).unwrap(); // Cross = 68
+                        let res_id = ctx.b.ext_inst(res_ty, None, glsl, 68, vec![Operand::IdRef(a), Operand::IdRef(b)]).map_err(rejected)?; // Cross = 68
                         return Ok((res_id, ty));
                     },
                     "reflect" if args.len() == 2 => {
@@ -451,7 +453,7 @@ fn emit_expr(ctx: &mut ShaderContext, expr: &Expr) -> KainResult<(u32, Type)> {
                         let (n, _) = emit_expr(ctx, &args[1].value)?;
                         let res_ty = map_ast_type(ctx.b, &ty);
                         let glsl = ctx.b.ext_inst_import("GLSL.std.450");
-                        let res_id = ctx.b.ext_inst(res_ty, None, glsl, 71, vec![Operand::IdRef(i), Operand::IdRef(n)]).unwrap(); // Reflect = 71
+                        let res_id = ctx.b.ext_inst(res_ty, None, glsl, 71, vec![Operand::IdRef(i), Operand::IdRef(n)]).map_err(rejected)?; // Reflect = 71
                         return Ok((res_id, ty));
                     },
                     
@@ -460,7 +462,7 @@ fn emit_expr(ctx: &mut ShaderContext, expr: &Expr) -> KainResult<(u32, Type)> {
                         let (sampler, _) = emit_expr(ctx, &args[0].value)?;
                         let (coords, _) = emit_expr(ctx, &args[1].value)?;
                         let vec4 = ctx.b.type_vector(float, 4);
-                        let res_id = ctx.b.image_sample_implicit_lod(vec4, None, sampler, coords, None, std::iter::empty()).unwrap();
+                        let res_id = ctx.b.image_sample_implicit_lod(vec4, None, sampler, coords, None, std::iter::empty()).map_err(rejected)?;
                         return Ok((res_id, Type::Named { name: "Vec4".into(), generics: vec![], span: expr.span() }));
                     },
                     "sample_lod" if args.len() == 3 => {
@@ -468,7 +470,7 @@ fn emit_expr(ctx: &mut ShaderContext, expr: &Expr) -> KainResult<(u32, Type)> {
                         let (coords, _) = emit_expr(ctx, &args[1].value)?;
                         let (lod, _) = emit_expr(ctx, &args[2].value)?;
                         let vec4 = ctx.b.type_vector(float, 4);
-                        let res_id = ctx.b.image_sample_explicit_lod(vec4, None, sampler, coords, rspirv::spirv::ImageOperands::LOD, vec![Operand::IdRef(lod)]).unwrap();
+                        let res_id = ctx.b.image_sample_explicit_lod(vec4, None, sampler, coords, rspirv::spirv::ImageOperands::LOD, vec![Operand::IdRef(lod)]).map_err(rejected)?;
                         return Ok((res_id, Type::Named { name: "Vec4".into(), generics: vec![], span: expr.span() }));
                     },
                     
@@ -490,41 +492,41 @@ fn emit_expr(ctx: &mut ShaderContext, expr: &Expr) -> KainResult<(u32, Type)> {
             match field.as_str() {
                 // Single component access
                 "x" | "r" => {
-                    let res_id = ctx.b.composite_extract(float, None, obj_id, vec![0]).unwrap();
+                    let res_id = ctx.b.composite_extract(float, None, obj_id, vec![0]).map_err(rejected)?;
                     Ok((res_id, Type::Named { name: "Float".into(), generics: vec![], span: *span }))
                 },
                 "y" | "g" => {
-                    let res_id = ctx.b.composite_extract(float, None, obj_id, vec![1]).unwrap();
+                    let res_id = ctx.b.composite_extract(float, None, obj_id, vec![1]).map_err(rejected)?;
                     Ok((res_id, Type::Named { name: "Float".into(), generics: vec![], span: *span }))
                 },
                 "z" | "b" => {
-                    let res_id = ctx.b.composite_extract(float, None, obj_id, vec![2]).unwrap();
+                    let res_id = ctx.b.composite_extract(float, None, obj_id, vec![2]).map_err(rejected)?;
                     Ok((res_id, Type::Named { name: "Float".into(), generics: vec![], span: *span }))
                 },
                 "w" | "a" => {
-                    let res_id = ctx.b.composite_extract(float, None, obj_id, vec![3]).unwrap();
+                    let res_id = ctx.b.composite_extract(float, None, obj_id, vec![3]).map_err(rejected)?;
                     Ok((res_id, Type::Named { name: "Float".into(), generics: vec![], span: *span }))
                 },
                 // Vec2 swizzles
                 "xy" | "rg" => {
                     let vec2 = ctx.b.type_vector(float, 2);
-                    let res_id = ctx.b.vector_shuffle(vec2, None, obj_id, obj_id, vec![0, 1]).unwrap();
+                    let res_id = ctx.b.vector_shuffle(vec2, None, obj_id, obj_id, vec![0, 1]).map_err(rejected)?;
                     Ok((res_id, Type::Named { name: "Vec2".into(), generics: vec![], span: *span }))
                 },
                 "xz" | "rb" => {
                     let vec2 = ctx.b.type_vector(float, 2);
-                    let res_id = ctx.b.vector_shuffle(vec2, None, obj_id, obj_id, vec![0, 2]).unwrap();
+                    let res_id = ctx.b.vector_shuffle(vec2, None, obj_id, obj_id, vec![0, 2]).map_err(rejected)?;
                     Ok((res_id, Type::Named { name: "Vec2".into(), generics: vec![], span: *span }))
                 },
                 "yz" | "gb" => {
                     let vec2 = ctx.b.type_vector(float, 2);
-                    let res_id = ctx.b.vector_shuffle(vec2, None, obj_id, obj_id, vec![1, 2]).unwrap();
+                    let res_id = ctx.b.vector_shuffle(vec2, None, obj_id, obj_id, vec![1, 2]).map_err(rejected)?;
                     Ok((res_id, Type::Named { name: "Vec2".into(), generics: vec![], span: *span }))
                 },
                 // Vec3 swizzles
                 "xyz" | "rgb" => {
                     let vec3 = ctx.b.type_vector(float, 3);
-                    let res_id = ctx.b.vector_shuffle(vec3, None, obj_id, obj_id, vec![0, 1, 2]).unwrap();
+                    let res_id = ctx.b.vector_shuffle(vec3, None, obj_id, obj_id, vec![0, 1, 2]).map_err(rejected)?;
                     Ok((res_id, Type::Named { name: "Vec3".into(), generics: vec![], span: *span }))
                 },
                 _ => Err(KainError::codegen(format!("Unsupported field access: {}", field), *span))
@@ -534,6 +536,11 @@ fn emit_expr(ctx: &mut ShaderContext, expr: &Expr) -> KainResult<(u32, Type)> {
     }
 }
 
+/// An instruction the builder refused, which the checks before codegen should have ruled out
+fn rejected(error: rspirv::dr::Error) -> KainError {
+    KainError::internal(Ice::SpirvBuilder, Phase::Codegen, format!("the SPIR-V builder rejected an instruction: {:?}", error), Span::default())
+}
+
 fn map_ast_type(b: &mut Builder, ty: &Type) -> u32 {
     let float = b.type_float(32);
     match ty {
@@ -11,6 +11,7 @@ use crate::types::{TypedProgram, TypedItem, TypedShader};
 use crate::error::{KainResult, KainError};
 use crate::ast::{Type, ShaderStage, Expr, Stmt, Block, BinaryOp, Pattern};
 use std::collections::HashMap;
+use super::{c_range_loop, c_string_literal};
 
 /// Generate USF code from typed KAIN program
 /// Uses two-pass generation: first collect all uniforms, then emit code
@@ -265,6 +266,12 @@ fn emit_stmt(ctx: &mut USFContext, stmt: &Stmt) -> KainResult<String> {
                 }
             }
         },
+        Stmt::For { else_branch: Some(_), span, .. } | Stmt::While { else_branch: Some(_), span, .. } => {
+            return Err(KainError::codegen("Loop `else:` is not supported in USF shaders", *span));
+        },
+        Stmt::For { label: Some(_), span, .. } | Stmt::While { label: Some(_), span, .. } | Stmt::Loop { label: Some(_), span, .. } => {
+            return Err(KainError::codegen("Loop labels are not supported in USF shaders", *span));
+        },
         Stmt::While { condition, body, .. } => {
             let (cond_code, _) = emit_expr(ctx, condition)?;
             output.push_str(&format!("{}while ({})\n", ctx.indent(), cond_code));
@@ -274,10 +281,13 @@ fn emit_stmt(ctx: &mut USFContext, stmt: &Stmt) -> KainResult<String> {
             ctx.pop_indent();
             output.push_str(&format!("{}}}\n", ctx.indent()));
         },
-        Stmt::For { binding, iter: _, body, .. } => {
+        Stmt::For { binding, iter, body, span, .. } => {
+            let range = c_range_loop(iter, |e| Ok(emit_expr(ctx, e)?.0))?.ok_or_else(|| {
+                KainError::codegen("For loops in USF shaders must iterate over a range", *span)
+            })?;
             if let Pattern::Binding { name, .. } = binding {
-                output.push_str(&format!("{}for (int {} = 0; {} < 10; {}++)\n", 
-                    ctx.indent(), name, name, name));
+                output.push_str(&format!("{}for (int {} = {}; {} {} {}; {} += {})\n",
+                    ctx.indent(), name, range.start, name, range.cmp, range.end, name, range.step));
                 output.push_str(&format!("{}{{\n", ctx.indent()));
                 ctx.push_indent();
                 ctx.vars.insert(name.clone(), (name.clone(), "int".to_string()));
@@ -295,12 +305,20 @@ fn emit_stmt(ctx: &mut USFContext, stmt: &Stmt) -> KainResult<String> {
             ctx.pop_indent();
             output.push_str(&format!("{}}}\n", ctx.indent()));
         },
-        Stmt::Break(_, _) => {
+        Stmt::Break(..) => {
             output.push_str(&format!("{}break;\n", ctx.indent()));
         },
-        Stmt::Continue(_) => {
+        Stmt::Continue(..) => {
             output.push_str(&format!("{}continue;\n", ctx.indent()));
         },
+        // Shaders have no heap, so a region is only a block
+        Stmt::Region { body, .. } => {
+            output.push_str(&format!("{}{{\n", ctx.indent()));
+            ctx.push_indent();
+            output.push_str(&emit_block(ctx, body)?);
+            ctx.pop_indent();
+            output.push_str(&format!("{}}}\n", ctx.indent()));
+        },
         Stmt::Item(_) => {
             // Nested items not supported in shader body
         },
@@ -365,7 +383,7 @@ fn emit_expr(ctx: &mut USFContext, expr: &Expr) -> KainResult<(String, String)>
             Ok((format!("{}", b), "bool".to_string()))
         },
         Expr::String(s, _) => {
-            Ok((format!("\"{}\"", s), "string".to_string()))
+            Ok((c_string_literal(s), "string".to_string()))
         },
         Expr::Binary { left, op, right, .. } => {
             let (left_code, left_ty) = emit_expr(ctx, left)?;
@@ -2,31 +2,62 @@
 //! 
 //! This module converts the Typed AST into WebAssembly.
 
-use crate::ast::{Expr, BinaryOp, Stmt, Block};
+use crate::ast::{Expr, BinaryOp, Stmt, Block, JSXAttrValue, JSXNode, Param, Function, Type};
+use crate::ast::visit::{self, Visitor};
 use crate::types::{ResolvedType, TypedFunction, TypedItem, TypedProgram};
-use crate::error::{KainResult, KainError};
+use crate::error::{Ice, KainResult, KainError};
+use crate::Phase;
+use crate::effects::EffectSet;
+use super::{range_chain, Intrinsics, RangeChain, RangeOp};
 use walrus::{FunctionBuilder, InstrSeqBuilder, LocalId, Module, ModuleConfig, ValType};
+use std::cell::{Cell, RefCell};
 use std::collections::HashMap;
 
 pub fn generate(program: &TypedProgram) -> KainResult<Vec<u8>> {
+    generate_with_options(program, &crate::CompileOptions::default())
+}
+
+pub fn generate_with_options(program: &TypedProgram, options: &crate::CompileOptions) -> KainResult<Vec<u8>> {
     let mut compiler = WasmCompiler::new();
+    compiler.intrinsics.shadow(program);
+    compiler.deterministic = options.deterministic;
+    compiler.simd = options.simd;
     compiler.compile_program(program)?;
     Ok(compiler.module.emit_wasm())
 }
 
+/// Builtins with a lowering of their own, see [`Intrinsics`]
+pub(crate) const LOWERED: &[&str] = &["print", "to_string", "now", "vec2", "vec3", "vec4", "dot", "length", "distance", "sum"];
+
+/// Bytes a vector takes in linear memory: four f64 lanes, the ones past its
+/// size zero, so every vector is two v128 halves and the math never branches on size
+const VECTOR_SIZE: u32 = 32;
+
+/// Where the region arena starts: the second page of memory, clear of the heap.
+/// Its memory is reused once a region exits, so nothing allocated from it may
+/// count on starting out zeroed.
+const REGION_ARENA: u32 = 65536;
+
 struct WasmCompiler {
     module: Module,
+    intrinsics: Intrinsics,
     /// Map function names to their WASM function IDs for call resolution
     functions: HashMap<String, walrus::FunctionId>,
     /// Memory ID for linear memory
     memory_id: Option<walrus::MemoryId>,
     heap_ptr_global: walrus::GlobalId,
+    /// Bump pointer of the region arena, see `REGION_ARENA`
+    region_ptr_global: walrus::GlobalId,
     /// Current offset in data segment for string allocation
     data_offset: u32,
     /// Map string literals to their memory offset (for deduplication)
     string_table: HashMap<String, u32>,
     /// Struct layouts: struct_name -> (field_name -> offset, total_size)
     struct_layouts: HashMap<String, (HashMap<String, u32>, u32)>,
+    /// Struct field types: struct_name -> (field_name -> type), for resolving nested field access
+    struct_fields: HashMap<String, HashMap<String, ResolvedType>>,
+    /// Declared return type of every function, for knowing which struct a call returns
+    fn_returns: HashMap<String, ResolvedType>,
     /// Enum layouts: enum_name -> (variant_name -> tag, max_payload_size, variant_name -> (field_name -> offset))
     enum_layouts: HashMap<String, (HashMap<String, u32>, u32, HashMap<String, HashMap<String, u32>>)>,
     /// Heap pointer (for runtime allocation) - starts after data segment
@@ -37,6 +68,32 @@ struct WasmCompiler {
     lambda_counter: u32,
     /// Map lambda ID -> (table_index, func_id) for indirect calls
     lambda_table: HashMap<u32, (u32, walrus::FunctionId)>,
+    /// Resolve layout/table lookups in sorted order instead of hash order
+    deterministic: bool,
+    /// Consts and statics stored in globals: name -> (global, type, holds a string pointer)
+    globals: HashMap<String, (walrus::GlobalId, ValType, bool)>,
+    /// Lower vector math and array sums to v128 instructions
+    simd: bool,
+    /// Each actor's state, stored in a block of linear memory the actor's reference points to
+    actor_states: HashMap<String, Vec<ActorField>>,
+    /// Message handlers, by their index in the mailbox: (actor, message, handler function, parameters)
+    actor_handlers: Vec<(String, String, String, Vec<(String, ValType)>)>,
+    /// The actor whose handler is being compiled, whose state its bare names refer to
+    current_actor: Option<String>,
+    /// Head and tail of the queue of messages not yet handled
+    mailbox: Option<(walrus::GlobalId, walrus::GlobalId)>,
+    /// Drains the mailbox; `main` calls it before returning
+    run_actors: Option<walrus::FunctionId>,
+    /// Set while compiling `main`, so its `return`s run the actors first
+    run_on_return: Option<walrus::FunctionId>,
+}
+
+/// A state field of an actor
+struct ActorField {
+    name: String,
+    offset: u32,
+    ty: ValType,
+    initial: Expr,
 }
 
 // Separate Context from Builder to avoid self-borrow issues
@@ -46,14 +103,68 @@ struct CompilationContext<'a> {
     functions: &'a HashMap<String, walrus::FunctionId>,
     string_table: &'a HashMap<String, u32>,
     struct_layouts: &'a HashMap<String, (HashMap<String, u32>, u32)>,
+    struct_fields: &'a HashMap<String, HashMap<String, ResolvedType>>,
+    fn_returns: &'a HashMap<String, ResolvedType>,
+    /// Struct type of each local known to hold a struct pointer
+    local_structs: RefCell<HashMap<String, String>>,
     enum_layouts: &'a HashMap<String, (HashMap<String, u32>, u32, HashMap<String, HashMap<String, u32>>)>,
     memory_id: walrus::MemoryId,
     heap_ptr_global: walrus::GlobalId,
+    region_ptr_global: walrus::GlobalId,
+    /// How many `region` blocks enclose the code being compiled
+    regions: Cell<usize>,
     tmp_i32: LocalId,
     tmp_i32_2: LocalId,
     tmp_i64: LocalId,
+    tmp_f64: LocalId,
+    /// Only allocated with SIMD enabled, so modules without it stay loadable everywhere
+    tmp_v128: Option<LocalId>,
     funcref_table: Option<walrus::TableId>,
     lambda_table: &'a HashMap<u32, (u32, walrus::FunctionId)>,
+    deterministic: bool,
+    /// Enclosing loops, innermost last, so `break`/`continue` can pick their `br` target
+    loops: RefCell<Vec<LoopTarget>>,
+}
+
+impl CompilationContext<'_> {
+    /// The bump pointer allocations come from: the region arena inside a
+    /// `region` block, so exiting it can't free memory allocated outside
+    fn alloc_ptr(&self) -> walrus::GlobalId {
+        if self.regions.get() > 0 { self.region_ptr_global } else { self.heap_ptr_global }
+    }
+}
+
+/// The struct a resolved type names. One-letter struct names resolve as generics,
+/// so those count too when a struct of that name exists.
+fn struct_name(ty: &ResolvedType, layouts: &HashMap<String, (HashMap<String, u32>, u32)>) -> Option<String> {
+    match ty {
+        ResolvedType::Struct(name, _) => Some(name.clone()),
+        ResolvedType::Generic(name) if layouts.contains_key(name) => Some(name.clone()),
+        _ => None,
+    }
+}
+
+fn load_kind(ty: ValType) -> walrus::ir::LoadKind {
+    match ty {
+        ValType::I32 => walrus::ir::LoadKind::I32 { atomic: false },
+        ValType::F64 => walrus::ir::LoadKind::F64,
+        _ => walrus::ir::LoadKind::I64 { atomic: false },
+    }
+}
+
+fn store_kind(ty: ValType) -> walrus::ir::StoreKind {
+    match ty {
+        ValType::I32 => walrus::ir::StoreKind::I32 { atomic: false },
+        ValType::F64 => walrus::ir::StoreKind::F64,
+        _ => walrus::ir::StoreKind::I64 { atomic: false },
+    }
+}
+
+/// Where `break` and `continue` branch to for one enclosing loop
+struct LoopTarget {
+    label: Option<String>,
+    break_to: walrus::ir::InstrSeqId,
+    continue_to: walrus::ir::InstrSeqId,
 }
 
 impl WasmCompiler {
@@ -61,9 +172,9 @@ impl WasmCompiler {
         let config = ModuleConfig::new();
         let mut module = Module::with_config(config);
         
-        // Create linear memory (1 page = 64KB)
+        // Create linear memory (64KB pages): the heap in the first, the region arena in the second
         // add_local(shared, memory64, initial, maximum, page_size_log2)
-        let memory_id = module.memories.add_local(false, false, 1, None, None);
+        let memory_id = module.memories.add_local(false, false, 2, None, None);
         module.exports.add("memory", memory_id);
 
         let heap_ptr = 4096u32;
@@ -73,6 +184,12 @@ impl WasmCompiler {
             false, // shared
             walrus::ConstExpr::Value(walrus::ir::Value::I32(heap_ptr as i32)),
         );
+        let region_ptr_global = module.globals.add_local(
+            ValType::I32,
+            true,
+            false, // shared
+            walrus::ConstExpr::Value(walrus::ir::Value::I32(REGION_ARENA as i32)),
+        );
         
         // --- WASM Host Imports for I/O ---
         let mut functions = HashMap::new();
@@ -151,17 +268,30 @@ impl WasmCompiler {
         
         Self {
             module,
+            intrinsics: Intrinsics::new(LOWERED),
             functions,
             memory_id: Some(memory_id),
             heap_ptr_global,
+            region_ptr_global,
             data_offset: 0,
             string_table: HashMap::new(),
             struct_layouts: HashMap::new(),
+            struct_fields: HashMap::new(),
+            fn_returns: HashMap::new(),
             enum_layouts: HashMap::new(),
             // heap_ptr, // Unused
             funcref_table: Some(funcref_table),
             lambda_counter: 0,
             lambda_table: HashMap::new(),
+            deterministic: false,
+            simd: false,
+            globals: HashMap::new(),
+            actor_states: HashMap::new(),
+            actor_handlers: Vec::new(),
+            current_actor: None,
+            mailbox: None,
+            run_actors: None,
+            run_on_return: None,
         }
     }
 
@@ -183,41 +313,75 @@ impl WasmCompiler {
             }
         }
         
+        // Actors run on a cooperative scheduler: each handler becomes a function
+        // taking the actor's state first, and `send` queues a message for it
+        let handlers = self.lower_actors(program)?;
+        let items: Vec<&TypedItem> = program.items.iter().chain(&handlers).collect();
+
         // Third pass: collect all string literals
+        for item in &items {
+            match item {
+                TypedItem::Function(f) => StringCollector(self).visit_block(&f.ast.body),
+                TypedItem::Actor(a) => a.ast.state.iter().for_each(|decl| StringCollector(self).visit_expr(&decl.initial)),
+                _ => {}
+            }
+        }
+
+        // Consts, folded to literals by comptime, become immutable globals and statics mutable ones
         for item in &program.items {
-            if let TypedItem::Function(f) = item {
-                self.collect_strings_in_block(&f.ast.body);
+            match item {
+                TypedItem::Const(c) => {
+                    self.declare_global(&c.ast.name, &c.ast.value, false, c.ast.visibility);
+                }
+                TypedItem::Static(s) => {
+                    if !self.declare_global(&s.ast.name, &s.ast.value, true, s.ast.visibility) {
+                        return Err(KainError::codegen(
+                            format!("static `{}` must start from a literal on the wasm target", s.ast.name),
+                            s.ast.span,
+                        ));
+                    }
+                }
+                _ => {}
             }
         }
 
         // Fourth pass: collect and compile all lambdas
-        let mut all_lambdas = Vec::new();
-        for item in &program.items {
+        let mut collector = LambdaCollector { compiler: self, lambdas: Vec::new() };
+        for item in &items {
             if let TypedItem::Function(f) = item {
-                self.collect_lambdas_in_block(&f.ast.body, &mut all_lambdas);
+                collector.visit_block(&f.ast.body);
             }
         }
         // Compile each lambda to a WASM function
-        for (id, params, body) in all_lambdas {
+        for (id, params, body) in collector.lambdas {
             self.compile_lambda(id, &params, &body)?;
         }
 
         // Fifth pass: declare functions (recursion support)
-        for item in &program.items {
+        for item in &items {
             if let TypedItem::Function(f) = item {
                 self.declare_function(f)?;
             }
         }
+        if !self.actor_handlers.is_empty() {
+            self.build_actor_scheduler()?;
+        }
         
         // Fifth pass: compile function bodies
-        for item in &program.items {
+        for item in &items {
             match item {
                 TypedItem::Function(f) => {
+                    self.current_actor = self.actor_handlers.iter()
+                        .find(|(.., function, _)| *function == f.ast.name)
+                        .map(|(actor, ..)| actor.clone());
+                    self.run_on_return = self.run_actors.filter(|_| f.ast.name == "main");
                     self.compile_function_body(f)?;
                 }
                 _ => {} 
             }
         }
+        self.current_actor = None;
+        self.run_on_return = None;
         
         // Sixth pass: compile components
         for item in &program.items {
@@ -229,6 +393,36 @@ impl WasmCompiler {
         Ok(())
     }
     
+    /// Store a const's value in an immutable global, exported when the const is `pub`
+    /// Declare a global holding `value`; false when it is not a literal and has no global form
+    fn declare_global(&mut self, name: &str, value: &Expr, mutable: bool, visibility: crate::ast::Visibility) -> bool {
+        let (value, is_string) = match value {
+            Expr::Int(n, _) => (walrus::ir::Value::I64(*n), false),
+            Expr::Float(f, _) => (walrus::ir::Value::F64(*f), false),
+            Expr::Bool(b, _) => (walrus::ir::Value::I32(*b as i32), false),
+            Expr::Char(c, _) => (walrus::ir::Value::I64(*c as i64), false),
+            Expr::String(s, _) => (walrus::ir::Value::I32((self.allocate_string(s) + 4) as i32), true),
+            Expr::Unary { op: crate::ast::UnaryOp::Neg, operand, .. } => match operand.as_ref() {
+                Expr::Int(n, _) => (walrus::ir::Value::I64(n.wrapping_neg()), false),
+                Expr::Float(f, _) => (walrus::ir::Value::F64(-*f), false),
+                _ => return false,
+            },
+            // Arrays and structs have no global form, and are reported where they are used
+            _ => return false,
+        };
+        let ty = match value {
+            walrus::ir::Value::I64(_) => ValType::I64,
+            walrus::ir::Value::F64(_) => ValType::F64,
+            _ => ValType::I32,
+        };
+        let global = self.module.globals.add_local(ty, mutable, false, walrus::ConstExpr::Value(value));
+        if matches!(visibility, crate::ast::Visibility::Public) {
+            self.module.exports.add(name, global);
+        }
+        self.globals.insert(name.to_string(), (global, ty, is_string));
+        true
+    }
+
     fn compute_struct_layout(&mut self, s: &crate::types::TypedStruct) {
         let mut offset = 0u32;
         let mut field_offsets = HashMap::new();
@@ -246,6 +440,7 @@ impl WasmCompiler {
         // Align total size to 4 bytes
         let total_size = (offset + 3) & !3;
         self.struct_layouts.insert(s.ast.name.clone(), (field_offsets, total_size));
+        self.struct_fields.insert(s.ast.name.clone(), s.field_types.clone());
     }
 
     fn compute_component_layout(&mut self, c: &crate::types::TypedComponent) {
@@ -280,23 +475,35 @@ impl WasmCompiler {
         let tmp_i32 = self.module.locals.add(ValType::I32);
         let tmp_i32_2 = self.module.locals.add(ValType::I32);
         let tmp_i64 = self.module.locals.add(ValType::I64);
+        let tmp_f64 = self.module.locals.add(ValType::F64);
+        let tmp_v128 = self.simd.then(|| self.module.locals.add(ValType::V128));
         
         let mut locals_map = HashMap::new();
         locals_map.insert("self".to_string(), self_local);
+        let local_structs = HashMap::from([("self".to_string(), c.ast.name.clone())]);
         
         let ctx = CompilationContext {
             locals: locals_map,
             functions: &self.functions,
             string_table: &self.string_table,
             struct_layouts: &self.struct_layouts,
+            struct_fields: &self.struct_fields,
+            fn_returns: &self.fn_returns,
+            local_structs: RefCell::new(local_structs),
             enum_layouts: &self.enum_layouts,
-            memory_id: self.memory_id.unwrap(),
+            memory_id: self.memory()?,
             heap_ptr_global: self.heap_ptr_global,
+            region_ptr_global: self.region_ptr_global,
+            regions: Cell::new(0),
             tmp_i32,
             tmp_i32_2,
             tmp_i64,
+            tmp_f64,
+            tmp_v128,
             funcref_table: self.funcref_table,
             lambda_table: &self.lambda_table,
+            deterministic: self.deterministic,
+            loops: RefCell::new(Vec::new()),
         };
         
         let mut func_body = builder.func_body();
@@ -304,7 +511,9 @@ impl WasmCompiler {
         
         let func_id = builder.finish(vec![self_local], &mut self.module.funcs);
         self.functions.insert(render_name.clone(), func_id);
-        self.module.exports.add(&render_name, func_id);
+        if matches!(c.ast.visibility, crate::ast::Visibility::Public) {
+            self.module.exports.add(&render_name, func_id);
+        }
         
         Ok(())
     }
@@ -369,7 +578,7 @@ impl WasmCompiler {
             ResolvedType::Float(crate::types::FloatSize::F32) => 4,
             ResolvedType::Float(crate::types::FloatSize::F64) => 8,
             ResolvedType::String => 4, // pointer
-            ResolvedType::Char => 4,
+            ResolvedType::Char => 8, // a code point, held like an Int
             ResolvedType::Array(_, len) => 4 + (*len as u32 * 8), // pointer + inline storage
             ResolvedType::Struct(_, _) => 4, // pointer
             _ => 8, // default to 8 bytes
@@ -384,11 +593,16 @@ impl WasmCompiler {
     ///   heap_ptr = (heap_ptr + size + 7) & ~7  // 8-byte aligned
     ///   return old_ptr
     fn emit_alloc(&self, ctx: &CompilationContext, builder: &mut InstrSeqBuilder, size: u32) {
+        self.emit_alloc_from(builder, ctx.alloc_ptr(), size);
+    }
+
+    /// `emit_alloc` from the arena `ptr` is the bump pointer of
+    fn emit_alloc_from(&self, builder: &mut InstrSeqBuilder, ptr: walrus::GlobalId, size: u32) {
         // Get current heap pointer (this will be our return value)
-        builder.global_get(ctx.heap_ptr_global);
+        builder.global_get(ptr);
         
         // Compute new heap pointer: (heap_ptr + size + 7) & ~7
-        builder.global_get(ctx.heap_ptr_global);
+        builder.global_get(ptr);
         builder.i32_const(size as i32);
         builder.binop(walrus::ir::BinaryOp::I32Add);
         builder.i32_const(7);
@@ -397,226 +611,251 @@ impl WasmCompiler {
         builder.binop(walrus::ir::BinaryOp::I32And);
         
         // Store new heap pointer
-        builder.global_set(ctx.heap_ptr_global);
+        builder.global_set(ptr);
         
         // Stack now has: [old_ptr] - which is our allocated address
     }
     
-    fn collect_strings_in_block(&mut self, block: &Block) {
-        for stmt in &block.stmts {
-            self.collect_strings_in_stmt(stmt);
-        }
-    }
-    
-    fn collect_strings_in_stmt(&mut self, stmt: &Stmt) {
-        match stmt {
-            Stmt::Expr(expr) => self.collect_strings_in_expr(expr),
-            Stmt::Let { value: Some(expr), .. } => self.collect_strings_in_expr(expr),
-            Stmt::Return(Some(expr), _) => self.collect_strings_in_expr(expr),
-            Stmt::While { condition, body, .. } => {
-                self.collect_strings_in_expr(condition);
-                self.collect_strings_in_block(body);
-            }
-            _ => {}
-        }
-    }
-    
-    fn collect_strings_in_expr(&mut self, expr: &Expr) {
-        match expr {
-            Expr::String(s, _) => {
-                self.allocate_string(s);
-            }
-            Expr::Binary { left, right, .. } => {
-                self.collect_strings_in_expr(left);
-                self.collect_strings_in_expr(right);
-            }
-            Expr::Call { args, .. } => {
-                for arg in args {
-                    self.collect_strings_in_expr(&arg.value);
-                }
-            }
-            Expr::If { condition, then_branch, else_branch, .. } => {
-                self.collect_strings_in_expr(condition);
-                self.collect_strings_in_block(then_branch);
-                if let Some(else_br) = else_branch {
-                    self.collect_strings_in_else(else_br);
-                }
-            }
-            Expr::JSX(node, _) => {
-                self.collect_strings_in_jsx(node);
-            }
-            _ => {}
-        }
-    }
-    
-    fn collect_strings_in_jsx(&mut self, node: &crate::ast::JSXNode) {
-        match node {
-            crate::ast::JSXNode::Element { tag, attributes, children, .. } => {
-                self.allocate_string(tag);
-                for attr in attributes {
-                    self.allocate_string(&attr.name);
-                    match &attr.value {
-                        crate::ast::JSXAttrValue::String(s) => { self.allocate_string(s); },
-                        crate::ast::JSXAttrValue::Expr(e) => self.collect_strings_in_expr(e),
-                        _ => {}
-                    }
-                }
-                for child in children {
-                    self.collect_strings_in_jsx(child);
-                }
-            }
-            crate::ast::JSXNode::Text(s, _) => {
-                self.allocate_string(s);
-            }
-            crate::ast::JSXNode::Expression(e) => {
-                self.collect_strings_in_expr(e);
-            }
-            crate::ast::JSXNode::ComponentCall { name, props, children, .. } => {
-                // Name might not be a string literal in runtime, but let's alloc it anyway
-                self.allocate_string(name);
-                for attr in props {
-                    self.allocate_string(&attr.name);
-                    match &attr.value {
-                        crate::ast::JSXAttrValue::String(s) => { self.allocate_string(s); },
-                        crate::ast::JSXAttrValue::Expr(e) => self.collect_strings_in_expr(e),
-                        _ => {}
-                    }
-                }
-                for child in children {
-                    self.collect_strings_in_jsx(child);
-                }
-            }
-            crate::ast::JSXNode::Fragment(children, _) => {
-                for child in children {
-                    self.collect_strings_in_jsx(child);
-                }
-            }
-            crate::ast::JSXNode::For { iter, body, .. } => {
-                self.collect_strings_in_expr(iter);
-                self.collect_strings_in_jsx(body);
-            }
-            crate::ast::JSXNode::If { condition, then_branch, else_branch, .. } => {
-                self.collect_strings_in_expr(condition);
-                self.collect_strings_in_jsx(then_branch);
-                if let Some(else_br) = else_branch {
-                    self.collect_strings_in_jsx(else_br);
-                }
-            }
-        }
-    }
-    
-    fn collect_strings_in_else(&mut self, branch: &crate::ast::ElseBranch) {
-        match branch {
-            crate::ast::ElseBranch::Else(block) => self.collect_strings_in_block(block),
-            crate::ast::ElseBranch::ElseIf(cond, then, next) => {
-                self.collect_strings_in_expr(cond);
-                self.collect_strings_in_block(then);
-                if let Some(next_br) = next {
-                    self.collect_strings_in_else(next_br);
-                }
+    // === ACTORS ===
+    //
+    // Actors run on a single-threaded cooperative scheduler built into the
+    // module. An actor reference points to its state, one 8-byte slot per
+    // field. `send` appends a message to a global queue:
+    //
+    //   [next: i32][actor: i32][handler: i32][pad][arg0: 8 bytes]..
+    //
+    // and `kain_run_actors` handles queued messages in order until none are
+    // left, including those the handlers send. `main` runs it before returning.
+
+    /// Record each actor's state layout and turn its handlers into functions
+    /// named `Actor.message` that take the actor's state as `self`
+    fn lower_actors(&mut self, program: &TypedProgram) -> KainResult<Vec<TypedItem>> {
+        let mut handlers = Vec::new();
+        for item in &program.items {
+            let TypedItem::Actor(actor) = item else { continue };
+            let mut fields = Vec::new();
+            for (i, decl) in actor.ast.state.iter().enumerate() {
+                let ty = match actor.state_types.get(&decl.name) {
+                    Some(ty) => self.map_type(ty),
+                    None => self.map_type(&crate::types::resolve_type(&decl.ty)?),
+                };
+                fields.push(ActorField { name: decl.name.clone(), offset: i as u32 * 8, ty, initial: decl.initial.clone() });
+            }
+            self.actor_states.insert(actor.ast.name.clone(), fields);
+
+            for handler in &actor.ast.handlers {
+                let name = format!("{}.{}", actor.ast.name, handler.message_type);
+                let this = Param {
+                    name: "self".to_string(),
+                    ty: Type::Named { name: actor.ast.name.clone(), generics: vec![], span: handler.span },
+                    mutable: false,
+                    default: None,
+                    variadic: false,
+                    span: handler.span,
+                };
+                let params: Vec<Param> = std::iter::once(this).chain(handler.params.iter().cloned()).collect();
+                let param_types = params.iter().map(|p| crate::types::resolve_type(&p.ty)).collect::<KainResult<Vec<_>>>()?;
+                self.actor_handlers.push((
+                    actor.ast.name.clone(),
+                    handler.message_type.clone(),
+                    name.clone(),
+                    handler.params.iter().zip(&param_types[1..]).map(|(p, t)| (p.name.clone(), self.map_type(t))).collect(),
+                ));
+                handlers.push(TypedItem::Function(TypedFunction {
+                    symbol: name.clone(),
+                    ast: Function {
+                        name,
+                        generics: vec![],
+                        params,
+                        return_type: None,
+                        effects: vec![],
+                        body: handler.body.clone(),
+                        visibility: crate::ast::Visibility::Private,
+                        attributes: vec![],
+                        doc: None,
+                        span: handler.span,
+                    },
+                    resolved_type: ResolvedType::Function {
+                        params: param_types,
+                        ret: Box::new(ResolvedType::Unit),
+                        effects: EffectSet::new(),
+                    },
+                    effects: EffectSet::new(),
+                }));
             }
         }
+        Ok(handlers)
     }
 
-    // === LAMBDA COLLECTION AND COMPILATION ===
+    /// The state field `name` of the actor whose handler is being compiled
+    /// The module's linear memory, declared before anything is compiled
+    fn memory(&self) -> KainResult<walrus::MemoryId> {
+        self.memory_id.ok_or_else(|| {
+            KainError::internal(Ice::MissingMemory, Phase::Codegen, "linear memory is used before it was declared", crate::span::Span::default())
+        })
+    }
 
-    fn collect_lambdas_in_block(&mut self, block: &Block, lambdas: &mut Vec<(u32, Vec<crate::ast::Param>, Expr)>) {
-        for stmt in &block.stmts {
-            self.collect_lambdas_in_stmt(stmt, lambdas);
-        }
+    /// The id `declare_function` gave `name`
+    fn function_id(&self, name: &str, span: crate::span::Span) -> KainResult<walrus::FunctionId> {
+        self.functions.get(name).copied().ok_or_else(|| {
+            KainError::internal(Ice::UndeclaredFunction, Phase::Codegen, format!("`{}` is compiled before it was declared", name), span)
+        })
     }
 
-    fn collect_lambdas_in_stmt(&mut self, stmt: &Stmt, lambdas: &mut Vec<(u32, Vec<crate::ast::Param>, Expr)>) {
-        match stmt {
-            Stmt::Expr(expr) => self.collect_lambdas_in_expr(expr, lambdas),
-            Stmt::Let { value: Some(expr), .. } => self.collect_lambdas_in_expr(expr, lambdas),
-            Stmt::Return(Some(expr), _) => self.collect_lambdas_in_expr(expr, lambdas),
-            Stmt::While { condition, body, .. } => {
-                self.collect_lambdas_in_expr(condition, lambdas);
-                self.collect_lambdas_in_block(body, lambdas);
-            }
-            Stmt::For { iter, body, .. } => {
-                self.collect_lambdas_in_expr(iter, lambdas);
-                self.collect_lambdas_in_block(body, lambdas);
-            }
-            Stmt::Loop { body, .. } => {
-                self.collect_lambdas_in_block(body, lambdas);
-            }
-            _ => {}
-        }
+    fn actor_field(&self, name: &str) -> Option<&ActorField> {
+        self.actor_states.get(self.current_actor.as_ref()?)?.iter().find(|f| f.name == name)
     }
 
-    fn collect_lambdas_in_expr(&mut self, expr: &Expr, lambdas: &mut Vec<(u32, Vec<crate::ast::Param>, Expr)>) {
-        match expr {
-            Expr::Lambda { params, body, .. } => {
-                let id = self.lambda_counter;
-                self.lambda_counter += 1;
-                lambdas.push((id, params.clone(), (**body).clone()));
-                // Also collect nested lambdas in body
-                self.collect_lambdas_in_expr(body, lambdas);
-            }
-            Expr::Binary { left, right, .. } => {
-                self.collect_lambdas_in_expr(left, lambdas);
-                self.collect_lambdas_in_expr(right, lambdas);
-            }
-            Expr::Unary { operand, .. } => {
-                self.collect_lambdas_in_expr(operand, lambdas);
-            }
-            Expr::Call { callee, args, .. } => {
-                self.collect_lambdas_in_expr(callee, lambdas);
-                for arg in args {
-                    self.collect_lambdas_in_expr(&arg.value, lambdas);
-                }
-            }
-            Expr::MethodCall { receiver, args, .. } => {
-                self.collect_lambdas_in_expr(receiver, lambdas);
-                for arg in args {
-                    self.collect_lambdas_in_expr(&arg.value, lambdas);
-                }
-            }
-            Expr::If { condition, then_branch, else_branch, .. } => {
-                self.collect_lambdas_in_expr(condition, lambdas);
-                self.collect_lambdas_in_block(then_branch, lambdas);
-                if let Some(else_br) = else_branch {
-                    self.collect_lambdas_in_else_branch(else_br, lambdas);
-                }
-            }
-            Expr::Match { scrutinee, arms, .. } => {
-                self.collect_lambdas_in_expr(scrutinee, lambdas);
-                for arm in arms {
-                    self.collect_lambdas_in_expr(&arm.body, lambdas);
-                }
-            }
-            Expr::Array(elements, _) => {
-                for e in elements {
-                    self.collect_lambdas_in_expr(e, lambdas);
-                }
-            }
-            Expr::Tuple(elements, _) => {
-                for e in elements {
-                    self.collect_lambdas_in_expr(e, lambdas);
+    /// Add the mailbox globals and `kain_run_actors`, once the handlers are declared
+    fn build_actor_scheduler(&mut self) -> KainResult<()> {
+        use walrus::ir::BinaryOp::I32Eq;
+        use walrus::ir::UnaryOp::I32Eqz;
+
+        let init = walrus::ConstExpr::Value(walrus::ir::Value::I32(0));
+        let head = self.module.globals.add_local(ValType::I32, true, false, init);
+        let tail = self.module.globals.add_local(ValType::I32, true, false, init);
+        self.mailbox = Some((head, tail));
+
+        let memory = self.memory()?;
+        let handlers = self.actor_handlers.iter()
+            .map(|(.., function, params)| Ok((self.function_id(function, crate::span::Span::default())?, params.clone())))
+            .collect::<KainResult<Vec<(walrus::FunctionId, Vec<ValType>)>>>()?;
+        let msg = self.module.locals.add(ValType::I32);
+        let header = |offset| walrus::ir::MemArg { align: 4, offset };
+
+        let mut builder = FunctionBuilder::new(&mut self.module.types, &[], &[]);
+        builder.func_body().block(None, |done| {
+            let done_id = done.id();
+            done.loop_(None, |top| {
+                let top_id = top.id();
+                // msg = head; if msg == 0: done
+                top.global_get(head);
+                top.local_tee(msg);
+                top.unop(I32Eqz);
+                top.br_if(done_id);
+                // head = msg.next; if head == 0: tail = 0
+                top.local_get(msg);
+                top.load(memory, walrus::ir::LoadKind::I32 { atomic: false }, header(0));
+                top.global_set(head);
+                top.global_get(head);
+                top.unop(I32Eqz);
+                top.if_else(None, |then| {
+                    then.i32_const(0);
+                    then.global_set(tail);
+                }, |_| {});
+                for (index, (function, params)) in handlers.iter().enumerate() {
+                    top.local_get(msg);
+                    top.load(memory, walrus::ir::LoadKind::I32 { atomic: false }, header(8));
+                    top.i32_const(index as i32);
+                    top.binop(I32Eq);
+                    top.if_else(None, |then| {
+                        then.local_get(msg);
+                        then.load(memory, walrus::ir::LoadKind::I32 { atomic: false }, header(4));
+                        for (i, (_, ty)) in params.iter().enumerate() {
+                            then.local_get(msg);
+                            then.load(memory, load_kind(*ty), walrus::ir::MemArg { align: 8, offset: 16 + i as u32 * 8 });
+                        }
+                        then.call(*function);
+                    }, |_| {});
                 }
-            }
-            Expr::Block(block, _) => {
-                self.collect_lambdas_in_block(block, lambdas);
-            }
-            _ => {}
+                top.br(top_id);
+            });
+        });
+        let run = builder.finish(vec![], &mut self.module.funcs);
+        self.module.exports.add("kain_run_actors", run);
+        self.run_actors = Some(run);
+        Ok(())
+    }
+
+    /// `spawn Actor(field = value, ..)`: allocate the actor's state, fields not
+    /// given starting from their declared values, and leave the pointer to it
+    fn compile_spawn(&self, ctx: &CompilationContext, builder: &mut InstrSeqBuilder, actor: &str, init: &[(String, Expr)], span: crate::span::Span) -> KainResult<()> {
+        let Some(fields) = self.actor_states.get(actor) else {
+            return Err(KainError::codegen(format!("Unknown actor: {}", actor), span));
+        };
+        if let Some((name, value)) = init.iter().find(|(name, _)| !fields.iter().any(|f| f.name == *name)) {
+            return Err(KainError::codegen(format!("Actor {} has no state `{}`", actor, name), value.span()));
+        }
+        for field in fields {
+            let value = init.iter().find(|(name, _)| *name == field.name).map_or(&field.initial, |(_, value)| value);
+            self.compile_as(ctx, builder, value, field.ty)?;
         }
+        // Actors outlive any region they're spawned in
+        self.emit_alloc_from(builder, ctx.heap_ptr_global, (fields.len() as u32 * 8).max(8));
+        builder.local_set(ctx.tmp_i32);
+        for field in fields.iter().rev() {
+            self.emit_store_from_stack(ctx, builder, field.ty, field.offset);
+        }
+        builder.local_get(ctx.tmp_i32);
+        Ok(())
     }
 
-    fn collect_lambdas_in_else_branch(&mut self, branch: &crate::ast::ElseBranch, lambdas: &mut Vec<(u32, Vec<crate::ast::Param>, Expr)>) {
-        match branch {
-            crate::ast::ElseBranch::Else(block) => self.collect_lambdas_in_block(block, lambdas),
-            crate::ast::ElseBranch::ElseIf(cond, then, next) => {
-                self.collect_lambdas_in_expr(cond, lambdas);
-                self.collect_lambdas_in_block(then, lambdas);
-                if let Some(next_br) = next {
-                    self.collect_lambdas_in_else_branch(next_br, lambdas);
-                }
+    /// `send target.message(arg = value, ..)`: queue the message for the scheduler
+    fn compile_send(&self, ctx: &CompilationContext, builder: &mut InstrSeqBuilder, target: &Expr, message: &str, data: &[(String, Expr)], span: crate::span::Span) -> KainResult<()> {
+        let actor = self.struct_of(ctx, target).filter(|name| self.actor_states.contains_key(name));
+        let mut candidates = self.actor_handlers.iter().enumerate()
+            .filter(|(_, (owner, name, ..))| name == message && actor.as_ref().map_or(true, |actor| actor == owner));
+        let (index, (owner, _, _, params)) = match (candidates.next(), candidates.next()) {
+            (Some(handler), None) => handler,
+            (None, _) => return Err(KainError::codegen(format!("No actor handles `{}`", message), span)),
+            (Some(_), Some(_)) => {
+                return Err(KainError::codegen(
+                    format!("Cannot tell which actor's `{}` handler this is; bind the actor from spawn to a variable first", message),
+                    span,
+                ))
             }
+        };
+        let (mailbox_head, mailbox_tail) = self.mailbox.ok_or_else(|| {
+            KainError::internal(Ice::MissingMailbox, Phase::Codegen, format!("`{}` is sent before the actor mailbox was declared", message), span)
+        })?;
+        if data.len() != params.len() {
+            return Err(KainError::codegen(
+                format!("{}.{} takes {} argument(s), {} given", owner, message, params.len(), data.len()),
+                span,
+            ));
+        }
+        self.compile_expr(ctx, builder, target)?;
+        // Arguments are stored in the order the handler declares them
+        for (name, ty) in params {
+            let Some((_, value)) = data.iter().find(|(arg, _)| arg == name) else {
+                return Err(KainError::codegen(format!("Missing argument `{}` for {}.{}", name, owner, message), span));
+            };
+            self.compile_as(ctx, builder, value, *ty)?;
         }
+        // Queued messages outlive any region they're sent in
+        self.emit_alloc_from(builder, ctx.heap_ptr_global, 16 + params.len() as u32 * 8);
+        builder.local_set(ctx.tmp_i32);
+        for (i, (_, ty)) in params.iter().enumerate().rev() {
+            self.emit_store_from_stack(ctx, builder, *ty, 16 + i as u32 * 8);
+        }
+        self.emit_store_from_stack(ctx, builder, ValType::I32, 4);
+        builder.local_get(ctx.tmp_i32);
+        builder.i32_const(index as i32);
+        builder.store(ctx.memory_id, walrus::ir::StoreKind::I32 { atomic: false }, walrus::ir::MemArg { align: 4, offset: 8 });
+        // The message is the last in the queue, whatever its memory held before
+        builder.local_get(ctx.tmp_i32);
+        builder.i32_const(0);
+        builder.store(ctx.memory_id, walrus::ir::StoreKind::I32 { atomic: false }, walrus::ir::MemArg { align: 4, offset: 0 });
+
+        // if tail == 0: head = msg else: tail.next = msg; then tail = msg
+        builder.global_get(mailbox_tail);
+        builder.unop(walrus::ir::UnaryOp::I32Eqz);
+        builder.if_else(None, |then| {
+            then.local_get(ctx.tmp_i32);
+            then.global_set(mailbox_head);
+        }, |otherwise| {
+            otherwise.global_get(mailbox_tail);
+            otherwise.local_get(ctx.tmp_i32);
+            otherwise.store(ctx.memory_id, walrus::ir::StoreKind::I32 { atomic: false }, walrus::ir::MemArg { align: 4, offset: 0 });
+        });
+        builder.local_get(ctx.tmp_i32);
+        builder.global_set(mailbox_tail);
+        builder.i64_const(0);
+        Ok(())
     }
 
+    // === LAMBDA COMPILATION ===
+
     /// Compile a collected lambda into a WASM function and add to funcref table
     fn compile_lambda(&mut self, id: u32, params: &[crate::ast::Param], body: &Expr) -> KainResult<()> {
         // Create function type: all params i64, returns i64
@@ -637,20 +876,31 @@ impl WasmCompiler {
         let tmp_i32 = self.module.locals.add(ValType::I32);
         let tmp_i32_2 = self.module.locals.add(ValType::I32);
         let tmp_i64 = self.module.locals.add(ValType::I64);
+        let tmp_f64 = self.module.locals.add(ValType::F64);
+        let tmp_v128 = self.simd.then(|| self.module.locals.add(ValType::V128));
         
         let ctx = CompilationContext {
             locals,
             functions: &self.functions,
             string_table: &self.string_table,
             struct_layouts: &self.struct_layouts,
+            struct_fields: &self.struct_fields,
+            fn_returns: &self.fn_returns,
+            local_structs: RefCell::new(HashMap::new()),
             enum_layouts: &self.enum_layouts,
-            memory_id: self.memory_id.unwrap(),
+            memory_id: self.memory()?,
             heap_ptr_global: self.heap_ptr_global,
+            region_ptr_global: self.region_ptr_global,
+            regions: Cell::new(0),
             tmp_i32,
             tmp_i32_2,
             tmp_i64,
+            tmp_f64,
+            tmp_v128,
             funcref_table: self.funcref_table,
             lambda_table: &self.lambda_table,
+            deterministic: self.deterministic,
+            loops: RefCell::new(Vec::new()),
         };
         
         // Compile lambda body
@@ -708,16 +958,17 @@ impl WasmCompiler {
 
         let func_id = builder.finish(param_local_ids, &mut self.module.funcs);
         self.functions.insert(func.ast.name.clone(), func_id);
+        self.fn_returns.insert(func.ast.name.clone(), (**ret_type).clone());
 
-        if matches!(func.ast.visibility, crate::ast::Visibility::Public) {
-            self.module.exports.add(&func.ast.name, func_id);
+        if crate::symbols::exported(&func.ast) {
+            self.module.exports.add(&func.symbol, func_id);
         }
 
         Ok(())
     }
 
     fn compile_function_body(&mut self, func: &TypedFunction) -> KainResult<()> {
-        let func_id = *self.functions.get(&func.ast.name).unwrap();
+        let func_id = self.function_id(&func.ast.name, func.ast.span)?;
 
         let (param_types, ret_type) = if let ResolvedType::Function { params, ret, .. } = &func.resolved_type {
             (params, ret)
@@ -744,31 +995,49 @@ impl WasmCompiler {
             param_local_ids.push(local_id);
         }
         
+        let local_structs: HashMap<String, String> = func.ast.params.iter().zip(param_types.iter())
+            .filter_map(|(param, ty)| Some((param.name.clone(), struct_name(ty, &self.struct_layouts)?)))
+            .collect();
+
         // 2. Scan body for Let bindings and pre-allocate locals
         self.preallocate_locals(&func.ast.body, &mut text_locals_map);
 
         let tmp_i32 = self.module.locals.add(ValType::I32);
         let tmp_i32_2 = self.module.locals.add(ValType::I32);
         let tmp_i64 = self.module.locals.add(ValType::I64);
+        let tmp_f64 = self.module.locals.add(ValType::F64);
+        let tmp_v128 = self.simd.then(|| self.module.locals.add(ValType::V128));
 
         let ctx = CompilationContext {
             locals: text_locals_map,
             functions: &self.functions,
             string_table: &self.string_table,
             struct_layouts: &self.struct_layouts,
+            struct_fields: &self.struct_fields,
+            fn_returns: &self.fn_returns,
+            local_structs: RefCell::new(local_structs),
             enum_layouts: &self.enum_layouts,
-            memory_id: self.memory_id.unwrap(),
+            memory_id: self.memory()?,
             heap_ptr_global: self.heap_ptr_global,
+            region_ptr_global: self.region_ptr_global,
+            regions: Cell::new(0),
             tmp_i32,
             tmp_i32_2,
             tmp_i64,
+            tmp_f64,
+            tmp_v128,
             funcref_table: self.funcref_table,
             lambda_table: &self.lambda_table,
+            deterministic: self.deterministic,
+            loops: RefCell::new(Vec::new()),
         };
 
         // 3. Compile body
         let mut func_body = builder.func_body();
         self.compile_block(&ctx, &mut func_body, &func.ast.body)?;
+        if let Some(run) = self.run_on_return {
+            func_body.call(run);
+        }
         
         // Return default value if needed
         if func.ast.body.stmts.is_empty() && !wasm_results.is_empty() {
@@ -830,20 +1099,25 @@ impl WasmCompiler {
                         }
                      }
                 }
-                Stmt::While { body, .. } => {
+                Stmt::While { body, else_branch, .. } => {
                     self.preallocate_locals(body, locals);
+                    if let Some(b) = else_branch {
+                        self.preallocate_locals(b, locals);
+                    }
                 }
-                Stmt::For { binding, body, .. } => {
-                    // Allocate loop variable
+                Stmt::For { binding, body, else_branch, .. } => {
+                    if let Some(b) = else_branch {
+                        self.preallocate_locals(b, locals);
+                    }
+                    // Allocate the loop variable plus the count of elements left and the step between them
                     if let crate::ast::Pattern::Binding { name, .. } = binding {
-                        if !locals.contains_key(name) {
-                            let local = self.module.locals.add(ValType::I64);
-                            locals.insert(name.clone(), local);
+                        for local_name in [name.clone(), format!("{}#count", name), format!("{}#step", name)] {
+                            locals.entry(local_name).or_insert_with(|| self.module.locals.add(ValType::I64));
                         }
                     }
                     self.preallocate_locals(body, locals);
                 }
-                Stmt::Loop { body, .. } => {
+                Stmt::Loop { body, .. } | Stmt::Region { body, .. } => {
                     self.preallocate_locals(body, locals);
                 }
                 _ => {}
@@ -857,18 +1131,30 @@ impl WasmCompiler {
             ResolvedType::Float(_) => ValType::F64,
             ResolvedType::Bool => ValType::I32,
             ResolvedType::String => ValType::I32, // Strings are pointers (i32 offset)
+            ResolvedType::Struct(..) => ValType::I32, // Structs are pointers into linear memory
+            ResolvedType::Option(_) => ValType::I32, // So are enums, options among them
+            ResolvedType::Generic(name) if self.struct_layouts.contains_key(name) => ValType::I32,
             _ => ValType::I64, 
         }
     }
 
     // Infer WASM ValType from an expression (for local allocation)
     fn infer_wasm_type(&self, expr: &Expr) -> ValType {
+        crate::stack::grow(|| self.infer_wasm_type_inner(expr))
+    }
+
+    fn infer_wasm_type_inner(&self, expr: &Expr) -> ValType {
         match expr {
             Expr::Int(_, _) => ValType::I64,
             Expr::Float(_, _) => ValType::F64,
             Expr::Bool(_, _) => ValType::I32,
+            Expr::Char(_, _) => ValType::I64,
             Expr::String(_, _) => ValType::I32,
+            Expr::Ident(name, _) if self.globals.contains_key(name) => self.globals[name].1,
+            Expr::Ident(name, _) if self.actor_field(name).is_some() => self.actor_field(name).map_or(ValType::I64, |f| f.ty),
             Expr::JSX(_, _) => ValType::I32, // JSX nodes are DOM element IDs (i32)
+            Expr::Spawn { .. } => ValType::I32, // Actor references point to their state
+            Expr::EnumVariant { .. } => ValType::I32, // Enum values point to their tag and payload
             Expr::Call { callee, .. } => {
                 if let Expr::Ident(name, _) = callee.as_ref() {
                     // Component calls return i32 (DOM node IDs)
@@ -876,13 +1162,19 @@ impl WasmCompiler {
                         return ValType::I32;
                     }
                     // String functions return i32 (pointers)
-                    if name == "to_string" || name == "str_concat" {
+                    if self.returns_string(name) {
                         return ValType::I32;
                     }
                     // DOM functions return i32
                     if name.starts_with("dom_") {
                         return ValType::I32;
                     }
+                    // Vectors are pointers, and their products and lengths floats
+                    match self.intrinsics.lowered(name).map(|f| f.name) {
+                        Some("vec2" | "vec3" | "vec4") => return ValType::I32,
+                        Some("dot" | "length" | "distance") => return ValType::F64,
+                        _ => {}
+                    }
                 }
                 ValType::I64 // Default for other functions
             }
@@ -965,9 +1257,16 @@ impl WasmCompiler {
                 if let Some(val_expr) = value {
                     self.compile_expr(ctx, builder, val_expr)?;
                     if let crate::ast::Pattern::Binding { name, .. } = pattern {
+                        match self.struct_of(ctx, val_expr) {
+                            Some(s) => ctx.local_structs.borrow_mut().insert(name.clone(), s),
+                            None => ctx.local_structs.borrow_mut().remove(name),
+                        };
                          if let Some(local_id) = ctx.locals.get(name) {
                              builder.local_set(*local_id);
                          }
+                    } else {
+                        // `let _ = value`; the capability check rejects every other pattern
+                        builder.drop();
                     }
                 }
             }
@@ -975,144 +1274,337 @@ impl WasmCompiler {
                 if let Some(expr) = opt_expr {
                     self.compile_expr(ctx, builder, expr)?;
                 }
+                if let Some(run) = self.run_on_return {
+                    builder.call(run);
+                }
                 builder.return_(); 
             }
-            Stmt::While { condition, body, .. } => {
-                builder.block(None, |block_builder| {
-                    let block_id = block_builder.id();
-                    
-                    block_builder.loop_(None, |loop_builder| {
-                        let loop_id = loop_builder.id();
-                        
-                        if self.compile_expr(ctx, loop_builder, condition).is_err() {
-                            return;
-                        }
-
-                        loop_builder.unop(walrus::ir::UnaryOp::I32Eqz);
-                        loop_builder.br_if(block_id);
-                        
-                        if self.compile_block(ctx, loop_builder, body).is_err() {
-                            return;
-                        }
-
-                        loop_builder.br(loop_id);
+            // block $exit { block $done { loop $top { cond; br_if $done; body; br $top } } else }
+            // `break` leaves through $exit so it skips the `else:` block
+            Stmt::While { label, condition, body, else_branch, .. } => {
+                let mut result = Ok(());
+                builder.block(None, |exit_builder| {
+                    let exit_id = exit_builder.id();
+                    exit_builder.block(None, |done_builder| {
+                        let done_id = done_builder.id();
+                        done_builder.loop_(None, |loop_builder| {
+                            let loop_id = loop_builder.id();
+                            result = self.compile_expr(ctx, loop_builder, condition).and_then(|_| {
+                                loop_builder.unop(walrus::ir::UnaryOp::I32Eqz);
+                                loop_builder.br_if(done_id);
+                                self.compile_loop_body(ctx, loop_builder, label, exit_id, loop_id, body)
+                            });
+                            loop_builder.br(loop_id);
+                        });
                     });
+                    if let (Ok(()), Some(b)) = (&result, else_branch) {
+                        result = self.compile_block(ctx, exit_builder, b);
+                    }
                 });
+                result?;
             }
             // For loop: `for i in start..end: body`
-            // Desugars to: let i = start; while i < end: body; i = i + 1
-            Stmt::For { binding, iter, body, span: _ } => {
+            // Counts the range's elements down rather than comparing `i` with the end,
+            // so no bound overflows: let i = start; while count > 0: body; i = i + step
+            Stmt::For { label, binding, iter, body, else_branch, span } => {
                 // Get the loop variable name
                 let loop_var = match binding {
                     crate::ast::Pattern::Binding { name, .. } => name.clone(),
                     _ => "".to_string(),
                 };
                 
-                // Get start and end from range expression
-                if let Expr::Range { start, end, inclusive, .. } = iter {
-                    let start_expr = start.as_ref().map(|e| e.as_ref());
-                    let end_expr = end.as_ref().map(|e| e.as_ref());
+                // Get start, end and step from the range expression and its `step`/`rev` calls
+                if let Some(chain) = range_chain(iter) {
+                    let (Some(var), Some(count_var), Some(step_var)) = (
+                        ctx.locals.get(&loop_var),
+                        ctx.locals.get(&format!("{}#count", loop_var)),
+                        ctx.locals.get(&format!("{}#step", loop_var)),
+                    ) else {
+                        return Err(KainError::codegen("for loops over ranges need a plain binding", *span));
+                    };
+                    self.compile_range_init(ctx, builder, &chain, *var, *count_var, *step_var, *span)?;
                     
-                    // Initialize loop variable with start value
-                    if let Some(start_e) = start_expr {
-                        self.compile_expr(ctx, builder, start_e)?;
-                    } else {
-                        builder.i64_const(0);
-                    }
-                    
-                    if let Some(local_id) = ctx.locals.get(&loop_var) {
-                        builder.local_set(*local_id);
-                    }
-                    
-                    // block { loop { if i >= end: break; body; i++; br loop } }
-                    builder.block(None, |block_builder| {
-                        let block_id = block_builder.id();
-                        
-                        block_builder.loop_(None, |loop_builder| {
-                            let loop_id = loop_builder.id();
-                            
-                            // Check condition: i < end (or i <= end if inclusive)
-                            if let Some(local_id) = ctx.locals.get(&loop_var) {
-                                loop_builder.local_get(*local_id);
-                            }
-                            
-                            if let Some(end_e) = end_expr {
-                                if self.compile_expr(ctx, loop_builder, end_e).is_err() {
-                                    return;
-                                }
-                            } else {
-                                loop_builder.i64_const(i64::MAX);
-                            }
-                            
-                            // Compare: if i >= end (or i > end if inclusive), break
-                            if *inclusive {
-                                loop_builder.binop(walrus::ir::BinaryOp::I64GtS);
-                            } else {
-                                loop_builder.binop(walrus::ir::BinaryOp::I64GeS);
-                            }
-                            loop_builder.br_if(block_id);
-                            
-                            // Execute body
-                            if self.compile_block(ctx, loop_builder, body).is_err() {
-                                return;
-                            }
-                            
-                            // Increment loop variable: i = i + 1
-                            if let Some(local_id) = ctx.locals.get(&loop_var) {
-                                loop_builder.local_get(*local_id);
+                    // block $exit { block $done { loop $top { if none left: br $done; block $next { body }; if --count == 0: br $done; i += step; br $top } } else }
+                    let mut result = Ok(());
+                    builder.block(None, |exit_builder| {
+                        let exit_id = exit_builder.id();
+                        exit_builder.block(None, |done_builder| {
+                            let done_id = done_builder.id();
+                            done_builder.loop_(None, |loop_builder| {
+                                let loop_id = loop_builder.id();
+
+                                // Break once no elements are left
+                                loop_builder.local_get(*count_var);
+                                loop_builder.unop(walrus::ir::UnaryOp::I64Eqz);
+                                loop_builder.br_if(done_id);
+
+                                // Execute body; `continue` jumps to the end of $next so the increment still runs
+                                loop_builder.block(None, |next_builder| {
+                                    let next_id = next_builder.id();
+                                    result = self.compile_loop_body(ctx, next_builder, label, exit_id, next_id, body);
+                                });
+
+                                // Stop after the last element rather than stepping past it, which could overflow
+                                loop_builder.local_get(*count_var);
                                 loop_builder.i64_const(1);
+                                loop_builder.binop(walrus::ir::BinaryOp::I64Sub);
+                                loop_builder.local_tee(*count_var);
+                                loop_builder.unop(walrus::ir::UnaryOp::I64Eqz);
+                                loop_builder.br_if(done_id);
+
+                                // i = i + step
+                                loop_builder.local_get(*var);
+                                loop_builder.local_get(*step_var);
                                 loop_builder.binop(walrus::ir::BinaryOp::I64Add);
-                                loop_builder.local_set(*local_id);
-                            }
-                            
-                            loop_builder.br(loop_id);
+                                loop_builder.local_set(*var);
+
+                                loop_builder.br(loop_id);
+                            });
                         });
+                        if let (Ok(()), Some(b)) = (&result, else_branch) {
+                            result = self.compile_block(ctx, exit_builder, b);
+                        }
                     });
+                    result?;
                 } else {
                     // Non-range iterators not yet supported
                     // For arrays: would need to get length, index each element
+                    if let Some(b) = else_branch {
+                        self.compile_block(ctx, builder, b)?;
+                    }
                 }
             }
+            // What a region's body allocates comes from the region arena, whose
+            // pointer on entry stays on the stack while the body runs and is
+            // restored on exit, freeing everything allocated since. Functions it
+            // calls still allocate from the heap. Leaving early with `return` or
+            // `break` keeps the allocations until an enclosing region exits.
+            Stmt::Region { body, .. } => {
+                builder.global_get(ctx.region_ptr_global);
+                ctx.regions.set(ctx.regions.get() + 1);
+                let result = self.compile_block(ctx, builder, body);
+                ctx.regions.set(ctx.regions.get() - 1);
+                result?;
+                builder.global_set(ctx.region_ptr_global);
+            }
             // Infinite loop: `loop: body` - can be exited with break
-            Stmt::Loop { body, span: _ } => {
-                builder.block(None, |block_builder| {
-                    let _block_id = block_builder.id();
-                    
-                    block_builder.loop_(None, |loop_builder| {
+            Stmt::Loop { label, body, span: _ } => {
+                let mut result = Ok(());
+                builder.block(None, |exit_builder| {
+                    let exit_id = exit_builder.id();
+                    exit_builder.loop_(None, |loop_builder| {
                         let loop_id = loop_builder.id();
-                        
-                        // Execute body
-                        if self.compile_block(ctx, loop_builder, body).is_err() {
-                            return;
-                        }
-                        
+                        result = self.compile_loop_body(ctx, loop_builder, label, exit_id, loop_id, body);
                         // Continue loop
                         loop_builder.br(loop_id);
                     });
                 });
+                result?;
             }
-            // Break statement
-            Stmt::Break(_, _) => {
-                // Break out of innermost block
-                // Note: This is simplified - would need proper block tracking for nested loops
-                builder.unreachable(); // Placeholder - real impl needs block ID tracking
+            Stmt::Break(value, label, span) => {
+                if value.is_some() {
+                    return Err(KainError::codegen("break with a value is not supported in WASM", *span));
+                }
+                let target = self.loop_target(ctx, label, *span)?;
+                builder.br(target.0);
             }
-            // Continue statement  
-            Stmt::Continue(_) => {
-                // Jump to loop header
-                builder.unreachable(); // Placeholder - real impl needs loop ID tracking
+            Stmt::Continue(label, span) => {
+                let target = self.loop_target(ctx, label, *span)?;
+                builder.br(target.1);
             }
             _ => {}
         }
         Ok(())
     }
 
+    /// Compiles a loop body with `break`/`continue` inside it branching to the given blocks
+    fn compile_loop_body(
+        &self,
+        ctx: &CompilationContext,
+        builder: &mut InstrSeqBuilder,
+        label: &Option<String>,
+        break_to: walrus::ir::InstrSeqId,
+        continue_to: walrus::ir::InstrSeqId,
+        body: &Block,
+    ) -> KainResult<()> {
+        ctx.loops.borrow_mut().push(LoopTarget { label: label.clone(), break_to, continue_to });
+        let result = self.compile_block(ctx, builder, body);
+        ctx.loops.borrow_mut().pop();
+        result
+    }
+
+    /// The (break, continue) targets of the loop a jump with this label refers to
+    fn loop_target(
+        &self,
+        ctx: &CompilationContext,
+        label: &Option<String>,
+        span: crate::span::Span,
+    ) -> KainResult<(walrus::ir::InstrSeqId, walrus::ir::InstrSeqId)> {
+        let loops = ctx.loops.borrow();
+        let target = match label {
+            Some(name) => loops.iter().rev().find(|l| l.label.as_ref() == Some(name)),
+            None => loops.last(),
+        };
+        match (target, label) {
+            (Some(l), _) => Ok((l.break_to, l.continue_to)),
+            (None, Some(name)) => Err(KainError::codegen(format!("No enclosing loop is labeled '{}", name), span)),
+            (None, None) => Err(KainError::codegen("break or continue outside of a loop", span)),
+        }
+    }
+
+    /// Sets up `i`, the number of elements `count` and `step` for iterating a
+    /// range chain, applying `step`/`rev` in order. The count is unsigned, so no
+    /// bound overflows it, not even an inclusive `i64::MAX`.
+    #[allow(clippy::too_many_arguments)]
+    fn compile_range_init(
+        &self,
+        ctx: &CompilationContext,
+        builder: &mut InstrSeqBuilder,
+        chain: &RangeChain,
+        var: LocalId,
+        count_var: LocalId,
+        step_var: LocalId,
+        span: crate::span::Span,
+    ) -> KainResult<()> {
+        use walrus::ir::BinaryOp::*;
+
+        match chain.start {
+            Some(e) => self.compile_expr(ctx, builder, e)?,
+            None => { builder.i64_const(0); }
+        }
+        builder.local_set(var);
+        match chain.end {
+            Some(e) => {
+                // count = end - i (+ 1 if inclusive) when the range isn't empty, else 0
+                self.compile_expr(ctx, builder, e)?;
+                builder.local_tee(ctx.tmp_i64);
+                builder.local_get(var);
+                builder.binop(I64Sub);
+                if chain.inclusive {
+                    builder.i64_const(1);
+                    builder.binop(I64Add);
+                }
+                builder.i64_const(0);
+                builder.local_get(ctx.tmp_i64);
+                builder.local_get(var);
+                builder.binop(if chain.inclusive { I64GeS } else { I64GtS });
+                builder.select(None);
+            }
+            None if chain.ops.iter().any(|op| matches!(op, RangeOp::Rev)) => {
+                return Err(KainError::codegen("cannot reverse an unbounded range", span));
+            }
+            // As many as an unsigned count holds
+            None => { builder.i64_const(-1); }
+        }
+        builder.local_set(count_var);
+        builder.i64_const(1);
+        builder.local_set(step_var);
+
+        for op in &chain.ops {
+            match op {
+                RangeOp::Step(k) => {
+                    // Trap on a non-positive step, then scale the current step by it
+                    self.compile_expr(ctx, builder, k)?;
+                    builder.local_tee(ctx.tmp_i64);
+                    builder.i64_const(0);
+                    builder.binop(I64LeS);
+                    builder.if_else(None, |t| { t.unreachable(); }, |_| {});
+                    // count = count == 0 ? 0 : (count - 1) / k + 1
+                    builder.local_get(count_var);
+                    builder.i64_const(1);
+                    builder.binop(I64Sub);
+                    builder.local_get(ctx.tmp_i64);
+                    builder.binop(I64DivU);
+                    builder.i64_const(1);
+                    builder.binop(I64Add);
+                    builder.i64_const(0);
+                    builder.local_get(count_var);
+                    builder.i64_const(0);
+                    builder.binop(I64Ne);
+                    builder.select(None);
+                    builder.local_set(count_var);
+                    builder.local_get(step_var);
+                    builder.local_get(ctx.tmp_i64);
+                    builder.binop(I64Mul);
+                    builder.local_set(step_var);
+                }
+                RangeOp::Rev => {
+                    // i = i + (count - 1) * step; step = -step
+                    builder.local_get(var);
+                    builder.local_get(count_var);
+                    builder.i64_const(1);
+                    builder.binop(I64Sub);
+                    builder.local_get(step_var);
+                    builder.binop(I64Mul);
+                    builder.binop(I64Add);
+                    builder.local_set(var);
+                    builder.i64_const(0);
+                    builder.local_get(step_var);
+                    builder.binop(I64Sub);
+                    builder.local_set(step_var);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// The struct an expression evaluates to a pointer to, when that is known statically
+    fn struct_of(&self, ctx: &CompilationContext, expr: &Expr) -> Option<String> {
+        let named = |ty: &ResolvedType| struct_name(ty, ctx.struct_layouts);
+        match expr {
+            Expr::Ident(name, _) => ctx.local_structs.borrow().get(name).cloned(),
+            Expr::Struct { name, .. } => Some(name.clone()),
+            Expr::Spawn { actor, .. } => Some(actor.clone()),
+            Expr::Paren(inner, _) => self.struct_of(ctx, inner),
+            Expr::Call { callee, .. } => match callee.as_ref() {
+                Expr::Ident(name, _) => ctx.fn_returns.get(name).and_then(named),
+                _ => None,
+            },
+            Expr::Field { object, field, .. } => {
+                let owner = self.struct_of(ctx, object)?;
+                ctx.struct_fields.get(&owner)?.get(field).and_then(named)
+            }
+            _ => None,
+        }
+    }
+
+    /// Byte offset of `field` in the struct `object` points to
+    fn field_offset(&self, ctx: &CompilationContext, object: &Expr, field: &str, span: crate::span::Span) -> KainResult<u32> {
+        if let Some(owner) = self.struct_of(ctx, object) {
+            return ctx.struct_layouts.get(&owner)
+                .and_then(|(offsets, _)| offsets.get(field).copied())
+                .ok_or_else(|| KainError::codegen(format!("Struct '{}' has no field '{}'", owner, field), span));
+        }
+        // Without the receiver's type, fall back to the field name, but only when
+        // every struct (or component) declaring it agrees on where it lives
+        let mut offsets: Vec<u32> = ctx.struct_layouts.values()
+            .filter_map(|(offsets, _)| offsets.get(field).copied())
+            .collect();
+        offsets.sort_unstable();
+        offsets.dedup();
+        match offsets.as_slice() {
+            [] => Err(KainError::codegen(format!("No struct has a field '{}'", field), span)),
+            [offset] => Ok(*offset),
+            _ => Err(KainError::codegen(
+                format!("Cannot tell which struct's field '{}' this is; bind the value to a typed parameter or variable", field),
+                span,
+            )),
+        }
+    }
+
+    /// Calls that return a string pointer: builtins declared to return `String`,
+    /// and the host's `str_concat`
+    fn returns_string(&self, name: &str) -> bool {
+        name == "str_concat" || self.intrinsics.lowered(name).is_some_and(|f| f.return_type == "String")
+    }
+
     fn is_string_expr(&self, expr: &Expr) -> bool {
         match expr {
             Expr::String(_, _) => true,
+            Expr::Ident(name, _) => self.globals.get(name).is_some_and(|c| c.2),
             Expr::Call { callee, .. } => {
                 if let Expr::Ident(name, _) = callee.as_ref() {
-                    name == "to_string" || name == "str_concat" 
+                    self.returns_string(name)
                 } else {
                     false
                 }
@@ -1138,13 +1630,13 @@ impl WasmCompiler {
                     // Component calls and DOM functions return i32
                     name.chars().next().map(|c| c.is_uppercase()).unwrap_or(false)
                         || name.starts_with("dom_")
-                        || name == "to_string" || name == "str_concat"
+                        || self.returns_string(name)
                 } else {
                     false
                 }
             }
-            // For identifiers, we can't know without context - return false and handle separately
-            Expr::Ident(_, _) => false, // Will be checked via is_i32_ident with context
+            // Only consts are known without context; locals are checked via is_i32_local
+            Expr::Ident(name, _) => self.globals.get(name).is_some_and(|c| c.1 == ValType::I32),
             _ => false
         }
     }
@@ -1160,6 +1652,10 @@ impl WasmCompiler {
     }
 
     fn compile_expr(&self, ctx: &CompilationContext, builder: &mut InstrSeqBuilder, expr: &Expr) -> KainResult<()> {
+        crate::stack::grow(|| self.compile_expr_inner(ctx, builder, expr))
+    }
+
+    fn compile_expr_inner(&self, ctx: &CompilationContext, builder: &mut InstrSeqBuilder, expr: &Expr) -> KainResult<()> {
         match expr {
             Expr::Int(n, _) => {
                 builder.i64_const(*n);
@@ -1170,6 +1666,10 @@ impl WasmCompiler {
             Expr::Bool(b, _) => {
                 builder.i32_const(if *b { 1 } else { 0 });
             }
+            // A char is its code point, so it compares and matches like an Int
+            Expr::Char(c, _) => {
+                builder.i64_const(*c as i64);
+            }
             Expr::String(s, span) => {
                 // String literals are stored in data segment during pre-pass
                 // Here we just emit the memory offset as an i32
@@ -1245,10 +1745,37 @@ impl WasmCompiler {
             Expr::Ident(name, span) => {
                 if let Some(local_id) = ctx.locals.get(name) {
                     builder.local_get(*local_id);
+                } else if let Some((global, ..)) = self.globals.get(name) {
+                    builder.global_get(*global);
+                } else if let (Some(field), Some(this)) = (self.actor_field(name), ctx.locals.get("self")) {
+                    builder.local_get(*this);
+                    builder.load(ctx.memory_id, load_kind(field.ty), walrus::ir::MemArg { align: 8, offset: field.offset });
                 } else {
                      return Err(KainError::codegen(format!("Variable '{}' not found in locals", name), *span));
                 }
             }
+            Expr::Spawn { actor, init, span } => self.compile_spawn(ctx, builder, actor, init, *span)?,
+            Expr::SendMsg { target, message, data, span } => self.compile_send(ctx, builder, target, message, data, *span)?,
+            Expr::Assign { target, value, span } => {
+                let Expr::Ident(name, _) = target.as_ref() else {
+                    return Err(KainError::codegen("Only variables can be assigned on the wasm target", *span));
+                };
+                if let Some(local_id) = ctx.locals.get(name) {
+                    self.compile_expr(ctx, builder, value)?;
+                    builder.local_set(*local_id);
+                } else if let Some((global, ..)) = self.globals.get(name).filter(|(g, ..)| self.module.globals.get(*g).mutable) {
+                    self.compile_expr(ctx, builder, value)?;
+                    builder.global_set(*global);
+                } else if let (Some(field), Some(this)) = (self.actor_field(name), ctx.locals.get("self")) {
+                    builder.local_get(*this);
+                    self.compile_as(ctx, builder, value, field.ty)?;
+                    builder.store(ctx.memory_id, store_kind(field.ty), walrus::ir::MemArg { align: 8, offset: field.offset });
+                } else {
+                    return Err(KainError::codegen(format!("Cannot assign to '{}'", name), *span));
+                }
+                // Assignments are expressions of unit type
+                builder.i64_const(0);
+            }
             Expr::If { condition, then_branch, else_branch, .. } => {
                  self.compile_expr(ctx, builder, condition)?;
                  
@@ -1270,8 +1797,9 @@ impl WasmCompiler {
             Expr::Call { callee, args, span } => {
                 // Get function name from callee
                 if let Expr::Ident(func_name, _) = callee.as_ref() {
+                    let builtin = self.intrinsics.lowered(func_name).map(|f| f.name);
                     // Special intrinsic: print
-                    if func_name == "print" {
+                    if builtin == Some("print") {
                         for arg in args {
                             match &arg.value {
                                 Expr::Int(_, _) => {
@@ -1306,6 +1834,7 @@ impl WasmCompiler {
                                     
                                     // Check if this is an i32 variable (JSX, bool, string ptr)
                                     let is_i32_var = match &arg.value {
+                                        Expr::Ident(name, _) if !ctx.locals.contains_key(name) => self.is_i32_expr(&arg.value),
                                         Expr::Ident(name, _) => self.is_i32_local(name, &ctx.locals),
                                         _ => self.is_i32_expr(&arg.value),
                                     };
@@ -1342,7 +1871,7 @@ impl WasmCompiler {
                     }
 
                     // Special intrinsic: to_string
-                    if func_name == "to_string" {
+                    if builtin == Some("to_string") {
                         if let Some(arg) = args.first() {
                              self.compile_expr(ctx, builder, &arg.value)?;
                              if let Some(func_id) = ctx.functions.get("int_to_str") {
@@ -1355,13 +1884,18 @@ impl WasmCompiler {
                     }
 
                     // Special intrinsic: now
-                    if func_name == "now" {
+                    if builtin == Some("now") {
                         if let Some(func_id) = ctx.functions.get("time_now") {
                             builder.call(*func_id);
                         }
                         return Ok(());
                     }
 
+                    // Vector math and array sums
+                    if let Some(name @ ("vec2" | "vec3" | "vec4" | "dot" | "length" | "distance" | "sum")) = builtin {
+                        return self.compile_vector_builtin(ctx, builder, name, args, *span);
+                    }
+
                     // Look up function ID
                     if let Some(func_id) = ctx.functions.get(func_name) {
                         // Compile arguments (push onto stack)
@@ -1394,7 +1928,7 @@ impl WasmCompiler {
                      // Store tag at offset 0
                      let aligned_size = (total_size + 7) & !7;
                      
-                     builder.global_get(ctx.heap_ptr_global);
+                     builder.global_get(ctx.alloc_ptr());
                      builder.i32_const(aligned_size as i32);
                      builder.binop(walrus::ir::BinaryOp::I32Sub);
                      
@@ -1408,10 +1942,12 @@ impl WasmCompiler {
                      match fields {
                          crate::ast::EnumVariantFields::Unit => {},
                          crate::ast::EnumVariantFields::Tuple(exprs) => {
-                             let variant_offsets = field_offsets_map.get(variant).expect("Variant offsets missing");
+                             let variant_offsets = field_offsets_map.get(variant).ok_or_else(|| {
+                                 KainError::internal(Ice::MissingVariantLayout, Phase::Codegen, format!("no field layout for {}::{}", enum_name, variant), *span)
+                             })?;
                              for (i, expr) in exprs.iter().enumerate() {
                                  if let Some(&offset) = variant_offsets.get(&i.to_string()) {
-                                     builder.global_get(ctx.heap_ptr_global);
+                                     builder.global_get(ctx.alloc_ptr());
                                      builder.i32_const(aligned_size as i32);
                                      builder.binop(walrus::ir::BinaryOp::I32Sub);
                                      builder.i32_const((4 + offset) as i32);
@@ -1423,10 +1959,12 @@ impl WasmCompiler {
                              }
                          },
                          crate::ast::EnumVariantFields::Struct(named_fields) => {
-                             let variant_offsets = field_offsets_map.get(variant).expect("Variant offsets missing");
+                             let variant_offsets = field_offsets_map.get(variant).ok_or_else(|| {
+                                 KainError::internal(Ice::MissingVariantLayout, Phase::Codegen, format!("no field layout for {}::{}", enum_name, variant), *span)
+                             })?;
                              for (name, expr) in named_fields {
                                  if let Some(&offset) = variant_offsets.get(name) {
-                                     builder.global_get(ctx.heap_ptr_global);
+                                     builder.global_get(ctx.alloc_ptr());
                                      builder.i32_const(aligned_size as i32);
                                      builder.binop(walrus::ir::BinaryOp::I32Sub);
                                      builder.i32_const((4 + offset) as i32);
@@ -1440,18 +1978,18 @@ impl WasmCompiler {
                      }
 
                      // Return base pointer
-                     builder.global_get(ctx.heap_ptr_global);
+                     builder.global_get(ctx.alloc_ptr());
                      builder.i32_const(aligned_size as i32);
                      builder.binop(walrus::ir::BinaryOp::I32Sub);
                 } else {
                     return Err(KainError::codegen(format!("Enum layout not found for {}", enum_name), *span));
                 }
             }
-            Expr::Struct { name, fields, span } => {
+            Expr::Struct { name, fields, span, .. } => {
                 if let Some((field_offsets, total_size)) = ctx.struct_layouts.get(name).cloned() {
                     // Allocate memory for struct using bump allocator
                     self.emit_alloc(ctx, builder, total_size);
-                    // Stack: [base_ptr]
+                    builder.drop(); // The base is recomputed for each store and at the end
                     
                     // We need to keep base_ptr for field stores AND return it
                     // Strategy: for each field, dup the ptr, add offset, store
@@ -1461,7 +1999,7 @@ impl WasmCompiler {
                     for (field_name, field_expr) in fields {
                         if let Some(&field_offset) = field_offsets.get(field_name) {
                             // Emit base_ptr + offset for store address
-                            builder.global_get(ctx.heap_ptr_global);
+                            builder.global_get(ctx.alloc_ptr());
                             // Need to subtract total_size to get back to our base
                             // Actually, heap_ptr now points PAST our allocation
                             // Our base = heap_ptr - aligned_size
@@ -1490,7 +2028,7 @@ impl WasmCompiler {
                     
                     // Leave struct pointer on stack (base address)
                     let aligned_size = (total_size + 7) & !7;
-                    builder.global_get(ctx.heap_ptr_global);
+                    builder.global_get(ctx.alloc_ptr());
                     builder.i32_const(aligned_size as i32);
                     builder.binop(walrus::ir::BinaryOp::I32Sub);
                 } else {
@@ -1498,24 +2036,13 @@ impl WasmCompiler {
                 }
             }
             // Field access: load from struct pointer + offset
-            Expr::Field { object, field, span: _ } => {
+            Expr::Field { object, field, span } => {
                 // Compile the object to get struct pointer
                 self.compile_expr(ctx, builder, object)?;
                 // Stack: [ptr]
                 
-                // Try to find field offset from any struct layout
-                // This is a heuristic - proper impl would use type info
-                let mut field_offset = 0u32;
-                let mut found = false;
-                for (_struct_name, (offsets, _size)) in ctx.struct_layouts.iter() {
-                    if let Some(&offset) = offsets.get(field) {
-                        field_offset = offset;
-                        found = true;
-                        break;
-                    }
-                }
-                
-                if found && field_offset > 0 {
+                let field_offset = self.field_offset(ctx, object, field, *span)?;
+                if field_offset > 0 {
                     builder.i32_const(field_offset as i32);
                     builder.binop(walrus::ir::BinaryOp::I32Add);
                 }
@@ -1571,7 +2098,7 @@ impl WasmCompiler {
                 };
                 
                 // Store length at base
-                get_base(builder, ctx.heap_ptr_global, aligned_size);
+                get_base(builder, ctx.alloc_ptr(), aligned_size);
                 builder.i32_const(len as i32);
                 builder.store(
                     ctx.memory_id,
@@ -1582,7 +2109,7 @@ impl WasmCompiler {
                 // Store each element
                 for (i, elem) in elements.iter().enumerate() {
                     // Address = base + 4 + (i * 8)
-                    get_base(builder, ctx.heap_ptr_global, aligned_size);
+                    get_base(builder, ctx.alloc_ptr(), aligned_size);
                     builder.i32_const((4 + i as u32 * element_size) as i32);
                     builder.binop(walrus::ir::BinaryOp::I32Add);
                     
@@ -1595,7 +2122,7 @@ impl WasmCompiler {
                 }
                 
                 // Leave array pointer on stack
-                get_base(builder, ctx.heap_ptr_global, aligned_size);
+                get_base(builder, ctx.alloc_ptr(), aligned_size);
             }
             // Index access: arr[i] - load from array pointer + 4 + (i * 8)
             Expr::Index { object, index, span: _ } => {
@@ -1640,7 +2167,7 @@ impl WasmCompiler {
                 // Store each element
                 for (i, elem) in elements.iter().enumerate() {
                     // Address = heap_ptr - aligned_size + (i * 8)
-                    builder.global_get(ctx.heap_ptr_global);
+                    builder.global_get(ctx.alloc_ptr());
                     builder.i32_const(aligned_size as i32);
                     builder.binop(walrus::ir::BinaryOp::I32Sub);
                     builder.i32_const((i as u32 * element_size) as i32);
@@ -1655,10 +2182,55 @@ impl WasmCompiler {
                 }
                 
                 // Leave tuple pointer on stack
-                builder.global_get(ctx.heap_ptr_global);
+                builder.global_get(ctx.alloc_ptr());
                 builder.i32_const(aligned_size as i32);
                 builder.binop(walrus::ir::BinaryOp::I32Sub);
             }
+            // `option?`: return `Option::None` from the function, or go on with the value inside
+            Expr::Try(inner, span) => {
+                let option = crate::types::OPTION;
+                let Some((tags, _, offsets)) = ctx.enum_layouts.get(option) else {
+                    return Err(KainError::codegen("`?` needs an Option in WASM", *span));
+                };
+                let none_tag = tags.get("None").copied().unwrap_or_default();
+                let payload = offsets.get("Some").and_then(|o| o.get("0")).copied().unwrap_or_default();
+
+                self.compile_expr(ctx, builder, inner)?;
+                builder.local_tee(ctx.tmp_i32);
+                builder.load(
+                    ctx.memory_id,
+                    walrus::ir::LoadKind::I32 { atomic: false },
+                    walrus::ir::MemArg { align: 4, offset: 0 },
+                );
+                builder.i32_const(none_tag as i32);
+                builder.binop(walrus::ir::BinaryOp::I32Eq);
+                let none = Expr::EnumVariant {
+                    enum_name: option.to_string(),
+                    variant: "None".to_string(),
+                    fields: crate::ast::EnumVariantFields::Unit,
+                    span: *span,
+                };
+                let mut result = Ok(());
+                builder.if_else(
+                    None,
+                    |then_b| {
+                        result = self.compile_expr(ctx, then_b, &none);
+                        if let Some(run) = self.run_on_return {
+                            then_b.call(run);
+                        }
+                        then_b.return_();
+                    },
+                    |_else_b| {},
+                );
+                result?;
+                // Payloads are stored as i64 unless the value is a literal of another type
+                builder.local_get(ctx.tmp_i32);
+                builder.load(
+                    ctx.memory_id,
+                    walrus::ir::LoadKind::I64 { atomic: false },
+                    walrus::ir::MemArg { align: 8, offset: 4 + payload },
+                );
+            }
             // Match expression: compile as chained if-else
             Expr::Match { scrutinee, arms, span: _ } => {
                 // Compile scrutinee and store in temp local
@@ -1703,6 +2275,33 @@ impl WasmCompiler {
                                 );
                             }
                         }
+                        crate::ast::Pattern::Range { start, end, inclusive, .. } => {
+                            // Check start <= scrutinee < end (or <= end if inclusive), comparing like literals
+                            builder.i32_const(1);
+                            if let Some(lo) = start {
+                                builder.local_get(ctx.tmp_i32);
+                                self.compile_expr(ctx, builder, lo)?;
+                                builder.unop(walrus::ir::UnaryOp::I32WrapI64);
+                                builder.binop(walrus::ir::BinaryOp::I32GeS);
+                                builder.binop(walrus::ir::BinaryOp::I32And);
+                            }
+                            if let Some(hi) = end {
+                                builder.local_get(ctx.tmp_i32);
+                                self.compile_expr(ctx, builder, hi)?;
+                                builder.unop(walrus::ir::UnaryOp::I32WrapI64);
+                                if *inclusive {
+                                    builder.binop(walrus::ir::BinaryOp::I32LeS);
+                                } else {
+                                    builder.binop(walrus::ir::BinaryOp::I32LtS);
+                                }
+                                builder.binop(walrus::ir::BinaryOp::I32And);
+                            }
+                            builder.if_else(
+                                None,
+                                |then_b| { let _ = self.compile_expr(ctx, then_b, &arm.body); },
+                                |_else_b| {}
+                            );
+                        }
                         crate::ast::Pattern::Binding { name, .. } => {
                             // Binding: bind scrutinee to local and execute body
                             if let Some(local_id) = ctx.locals.get(name) {
@@ -1712,7 +2311,7 @@ impl WasmCompiler {
                             }
                             self.compile_expr(ctx, builder, &arm.body)?;
                         }
-                        crate::ast::Pattern::Variant { variant, .. } => {
+                        crate::ast::Pattern::Variant { enum_name, variant, .. } => {
                             // For enum patterns: load tag, compare with variant tag
                             // Load tag from scrutinee pointer
                             builder.local_get(ctx.tmp_i32);
@@ -1722,9 +2321,13 @@ impl WasmCompiler {
                                 walrus::ir::MemArg { align: 4, offset: 0 },
                             );
                             
-                            // TODO: look up variant tag from enum_layouts
-                            // For now just use the variant name hash as placeholder
-                            let tag = variant.len() as i32 % 256; // Placeholder
+                            // The tag from the enum's layout when the pattern names it,
+                            // otherwise the variant name hash as placeholder
+                            let tag = enum_name
+                                .as_ref()
+                                .and_then(|e| ctx.enum_layouts.get(e))
+                                .and_then(|(tags, _, _)| tags.get(variant))
+                                .map_or(variant.len() as i32 % 256, |&tag| tag as i32);
                             builder.i32_const(tag);
                             builder.binop(walrus::ir::BinaryOp::I32Eq);
                             
@@ -1742,8 +2345,9 @@ impl WasmCompiler {
                 }
             }
             // MacroCall: handle println!, print!, dbg!
-            Expr::MacroCall { name, args, span: _ } => {
+            Expr::MacroCall { name, args, span } => {
                 match name.as_str() {
+                    "asm" => self.compile_asm(ctx, builder, args, *span)?,
                     "println" | "print" => {
                         // For each argument, determine type and call appropriate print function
                         for arg in args {
@@ -1815,15 +2419,9 @@ impl WasmCompiler {
                     }
                 }
             }
-            // Range expression: for now just push start value since ranges are handled inline in for loops
-            Expr::Range { start, end: _, inclusive: _, span: _ } => {
-                // Ranges are typically used inline in for loops
-                // If used standalone, just return the start value
-                if let Some(start_expr) = start {
-                    self.compile_expr(ctx, builder, start_expr)?;
-                } else {
-                    builder.i64_const(0);
-                }
+            // Ranges have no runtime representation; they are lowered inline by for loops
+            Expr::Range { span, .. } => {
+                return Err(KainError::codegen("ranges are only supported as for-loop iterators in WASM", *span));
             }
             // Lambda expression: return table index for the pre-compiled lambda function
             Expr::Lambda { params, return_type: _, body: _, span: _ } => {
@@ -1841,7 +2439,11 @@ impl WasmCompiler {
                 
                 // Search lambda_table for a lambda with matching param count
                 let mut found_index = 0i32;
-                for (_id, (table_idx, _func_id)) in ctx.lambda_table.iter() {
+                let mut lambdas: Vec<_> = ctx.lambda_table.iter().collect();
+                if ctx.deterministic {
+                    lambdas.sort_by_key(|(id, _)| **id);
+                }
+                for (_id, (table_idx, _func_id)) in lambdas {
                     // Simple heuristic: use first lambda if param counts can't be matched
                     found_index = *table_idx as i32;
                     break; // TODO: proper ID tracking
@@ -1875,6 +2477,292 @@ impl WasmCompiler {
         Ok(())
     }
 
+    /// Lower the vector constructors, `dot`, `length`, `distance` and `sum`,
+    /// with v128 instructions when SIMD is enabled and scalar ones otherwise
+    fn compile_vector_builtin(&self, ctx: &CompilationContext, builder: &mut InstrSeqBuilder, name: &str, args: &[crate::ast::CallArg], span: crate::span::Span) -> KainResult<()> {
+        use walrus::ir::UnaryOp::F64Sqrt;
+
+        if let Some(lanes) = name.strip_prefix("vec") {
+            return self.compile_vector(ctx, builder, name, lanes.parse().unwrap_or(4), args, span);
+        }
+        let arity = if matches!(name, "dot" | "distance") { 2 } else { 1 };
+        if args.len() != arity {
+            return Err(KainError::codegen(format!("{}() takes {} argument(s), got {}", name, arity, args.len()), span));
+        }
+        // Both operands are evaluated before either scratch local is set
+        self.compile_expr(ctx, builder, &args[0].value)?;
+        if arity == 2 {
+            self.compile_expr(ctx, builder, &args[1].value)?;
+            builder.local_set(ctx.tmp_i32_2);
+            builder.local_set(ctx.tmp_i32);
+        } else {
+            builder.local_tee(ctx.tmp_i32);
+            builder.local_set(ctx.tmp_i32_2);
+        }
+        match name {
+            "sum" => self.emit_array_sum(ctx, builder),
+            "dot" => self.emit_lane_sum(ctx, builder, false),
+            "length" => {
+                self.emit_lane_sum(ctx, builder, false);
+                builder.unop(F64Sqrt);
+            }
+            _ => {
+                self.emit_lane_sum(ctx, builder, true);
+                builder.unop(F64Sqrt);
+            }
+        }
+        Ok(())
+    }
+
+    /// `vecN(x, y, ..)`, or `vecN(s)` to fill every lane with `s`
+    fn compile_vector(&self, ctx: &CompilationContext, builder: &mut InstrSeqBuilder, name: &str, lanes: usize, args: &[crate::ast::CallArg], span: crate::span::Span) -> KainResult<()> {
+        if args.len() != 1 && args.len() != lanes {
+            return Err(KainError::codegen(
+                format!("{}() takes 1 or {} scalar components on the wasm target, got {}", name, lanes, args.len()),
+                span,
+            ));
+        }
+        // Every lane is computed before the allocation, so components that use
+        // the scratch locals themselves can't clobber the vector's address
+        for lane in 0..lanes {
+            self.compile_as(ctx, builder, &args[if args.len() == 1 { 0 } else { lane }].value, ValType::F64)?;
+        }
+        self.emit_alloc(ctx, builder, VECTOR_SIZE);
+        builder.local_set(ctx.tmp_i32);
+        for lane in (0..lanes).rev() {
+            self.emit_store_from_stack(ctx, builder, ValType::F64, lane as u32 * 8);
+        }
+        // The lanes past `lanes` are zeroed, as region memory may be reused
+        for lane in lanes..VECTOR_SIZE as usize / 8 {
+            builder.f64_const(0.0);
+            self.emit_store_from_stack(ctx, builder, ValType::F64, lane as u32 * 8);
+        }
+        builder.local_get(ctx.tmp_i32);
+        Ok(())
+    }
+
+    /// Compile `expr` to a value of type `ty`, converting an Int to a Float
+    fn compile_as(&self, ctx: &CompilationContext, builder: &mut InstrSeqBuilder, expr: &Expr, ty: ValType) -> KainResult<()> {
+        self.compile_expr(ctx, builder, expr)?;
+        let actual = match expr {
+            Expr::Ident(name, _) if ctx.locals.contains_key(name) => self.module.locals.get(ctx.locals[name]).ty(),
+            _ => self.infer_wasm_type(expr),
+        };
+        match (actual, ty) {
+            _ if actual == ty => Ok(()),
+            (ValType::I64, ValType::F64) => {
+                builder.unop(walrus::ir::UnaryOp::F64ConvertSI64);
+                Ok(())
+            }
+            _ => Err(KainError::codegen(format!("Expected a value of type {:?}, found {:?}", ty, actual), expr.span())),
+        }
+    }
+
+    /// Pop a value of type `ty` off the stack into `tmp_i32 + offset`
+    fn emit_store_from_stack(&self, ctx: &CompilationContext, builder: &mut InstrSeqBuilder, ty: ValType, offset: u32) {
+        let tmp = match ty {
+            ValType::F64 => ctx.tmp_f64,
+            ValType::I32 => ctx.tmp_i32_2,
+            _ => ctx.tmp_i64,
+        };
+        builder.local_set(tmp);
+        builder.local_get(ctx.tmp_i32);
+        builder.local_get(tmp);
+        builder.store(ctx.memory_id, store_kind(ty), walrus::ir::MemArg { align: 8, offset });
+    }
+
+    /// Sum over the four lanes of the vectors in `tmp_i32` and `tmp_i32_2` of
+    /// `a * b`, or of `(a - b)^2` when `difference` is set
+    fn emit_lane_sum(&self, ctx: &CompilationContext, builder: &mut InstrSeqBuilder, difference: bool) {
+        use walrus::ir::BinaryOp::*;
+
+        if let Some(v) = ctx.tmp_v128 {
+            for half in [0, 16] {
+                for ptr in [ctx.tmp_i32, ctx.tmp_i32_2] {
+                    builder.local_get(ptr);
+                    builder.load(ctx.memory_id, walrus::ir::LoadKind::V128, walrus::ir::MemArg { align: 8, offset: half });
+                }
+                if difference {
+                    builder.binop(F64x2Sub);
+                    builder.local_tee(v);
+                    builder.local_get(v);
+                }
+                builder.binop(F64x2Mul);
+                if half > 0 {
+                    builder.binop(F64x2Add);
+                }
+            }
+            builder.local_tee(v);
+            builder.unop(walrus::ir::UnaryOp::F64x2ExtractLane { idx: 0 });
+            builder.local_get(v);
+            builder.unop(walrus::ir::UnaryOp::F64x2ExtractLane { idx: 1 });
+            builder.binop(F64Add);
+            return;
+        }
+        for lane in 0..4 {
+            for ptr in [ctx.tmp_i32, ctx.tmp_i32_2] {
+                builder.local_get(ptr);
+                builder.load(ctx.memory_id, walrus::ir::LoadKind::F64, walrus::ir::MemArg { align: 8, offset: lane * 8 });
+            }
+            if difference {
+                builder.binop(F64Sub);
+                builder.local_tee(ctx.tmp_f64);
+                builder.local_get(ctx.tmp_f64);
+            }
+            builder.binop(F64Mul);
+            if lane > 0 {
+                builder.binop(F64Add);
+            }
+        }
+    }
+
+    /// Sum of the Int array in `tmp_i32`: two elements per step with SIMD,
+    /// then one at a time for whatever is left
+    fn emit_array_sum(&self, ctx: &CompilationContext, builder: &mut InstrSeqBuilder) {
+        use walrus::ir::BinaryOp::*;
+        let elem = |offset| walrus::ir::MemArg { align: 8, offset };
+
+        // end = base + 4 + len * 8, cursor = base + 4
+        builder.local_get(ctx.tmp_i32);
+        builder.local_get(ctx.tmp_i32);
+        builder.load(ctx.memory_id, walrus::ir::LoadKind::I32 { atomic: false }, walrus::ir::MemArg { align: 4, offset: 0 });
+        builder.i32_const(8);
+        builder.binop(I32Mul);
+        builder.binop(I32Add);
+        builder.i32_const(4);
+        builder.binop(I32Add);
+        builder.local_set(ctx.tmp_i32_2);
+        builder.local_get(ctx.tmp_i32);
+        builder.i32_const(4);
+        builder.binop(I32Add);
+        builder.local_set(ctx.tmp_i32);
+
+        builder.i64_const(0);
+        builder.local_set(ctx.tmp_i64);
+        if let Some(v) = ctx.tmp_v128 {
+            builder.const_(walrus::ir::Value::V128(0));
+            builder.local_set(v);
+            // while end - cursor >= 16: v += load(cursor); cursor += 16
+            builder.block(None, |done| {
+                let done_id = done.id();
+                done.loop_(None, |top| {
+                    let top_id = top.id();
+                    top.local_get(ctx.tmp_i32_2);
+                    top.local_get(ctx.tmp_i32);
+                    top.binop(I32Sub);
+                    top.i32_const(16);
+                    top.binop(I32LtS);
+                    top.br_if(done_id);
+                    top.local_get(v);
+                    top.local_get(ctx.tmp_i32);
+                    top.load(ctx.memory_id, walrus::ir::LoadKind::V128, elem(0));
+                    top.binop(I64x2Add);
+                    top.local_set(v);
+                    top.local_get(ctx.tmp_i32);
+                    top.i32_const(16);
+                    top.binop(I32Add);
+                    top.local_set(ctx.tmp_i32);
+                    top.br(top_id);
+                });
+            });
+            builder.local_get(v);
+            builder.unop(walrus::ir::UnaryOp::I64x2ExtractLane { idx: 0 });
+            builder.local_get(v);
+            builder.unop(walrus::ir::UnaryOp::I64x2ExtractLane { idx: 1 });
+            builder.binop(I64Add);
+            builder.local_set(ctx.tmp_i64);
+        }
+        // while cursor < end: total += load(cursor); cursor += 8
+        builder.block(None, |done| {
+            let done_id = done.id();
+            done.loop_(None, |top| {
+                let top_id = top.id();
+                top.local_get(ctx.tmp_i32);
+                top.local_get(ctx.tmp_i32_2);
+                top.binop(I32GeU);
+                top.br_if(done_id);
+                top.local_get(ctx.tmp_i64);
+                top.local_get(ctx.tmp_i32);
+                top.load(ctx.memory_id, walrus::ir::LoadKind::I64 { atomic: false }, elem(0));
+                top.binop(I64Add);
+                top.local_set(ctx.tmp_i64);
+                top.local_get(ctx.tmp_i32);
+                top.i32_const(8);
+                top.binop(I32Add);
+                top.local_set(ctx.tmp_i32);
+                top.br(top_id);
+            });
+        });
+        builder.local_get(ctx.tmp_i64);
+    }
+
+    /// Push the operands of an `asm!("wasm", ..)` block and splice in its
+    /// instructions, checking each against the types on the stack
+    fn compile_asm(&self, ctx: &CompilationContext, builder: &mut InstrSeqBuilder, args: &[Expr], span: crate::span::Span) -> KainResult<()> {
+        let asm = crate::asm::InlineAsm::parse(args, span)?;
+        let mut stack = Vec::new();
+        for operand in asm.operands {
+            self.compile_expr(ctx, builder, operand)?;
+            stack.push(match operand {
+                Expr::Ident(name, _) if ctx.locals.contains_key(name) => self.module.locals.get(ctx.locals[name]).ty(),
+                _ => self.infer_wasm_type(operand),
+            });
+        }
+        let mut tokens = asm.code.split_whitespace();
+        while let Some(name) = tokens.next() {
+            let instr = match name {
+                "i32.const" | "i64.const" | "f64.const" => {
+                    let immediate = tokens
+                        .next()
+                        .ok_or_else(|| KainError::codegen(format!("asm!: `{}` needs an immediate", name), span))?;
+                    let bad = || KainError::codegen(format!("asm!: `{} {}` is not a valid constant", name, immediate), span);
+                    match name {
+                        "i32.const" => AsmInstr::new(&[], Some(ValType::I32), AsmOp::I32Const(immediate.parse().map_err(|_| bad())?)),
+                        "i64.const" => AsmInstr::new(&[], Some(ValType::I64), AsmOp::I64Const(immediate.parse().map_err(|_| bad())?)),
+                        _ => AsmInstr::new(&[], Some(ValType::F64), AsmOp::F64Const(immediate.parse().map_err(|_| bad())?)),
+                    }
+                }
+                // Drops whatever is on top of the stack
+                "drop" => AsmInstr::new(&[*stack.last().unwrap_or(&ValType::I64)], None, AsmOp::Drop),
+                _ => asm_instr(name).ok_or_else(|| KainError::codegen(format!("asm!: unknown or unsupported wasm instruction `{}`", name), span))?,
+            };
+            if stack.len() < instr.params.len() {
+                return Err(KainError::codegen(
+                    format!("asm!: `{}` needs {} operand(s) but the stack holds {}", name, instr.params.len(), stack.len()),
+                    span,
+                ));
+            }
+            let operands = stack.split_off(stack.len() - instr.params.len());
+            if operands != instr.params {
+                return Err(KainError::codegen(
+                    format!("asm!: `{}` expects {:?} on the stack, found {:?}", name, instr.params, operands),
+                    span,
+                ));
+            }
+            stack.extend(instr.result);
+            let memarg = walrus::ir::MemArg { align: 8, offset: 0 };
+            match instr.op {
+                AsmOp::I32Const(n) => { builder.i32_const(n); }
+                AsmOp::I64Const(n) => { builder.i64_const(n); }
+                AsmOp::F64Const(f) => { builder.f64_const(f); }
+                AsmOp::Binary(op) => { builder.binop(op); }
+                AsmOp::Unary(op) => { builder.unop(op); }
+                AsmOp::Drop => { builder.drop(); }
+                AsmOp::Load(atomic) => { builder.load(ctx.memory_id, walrus::ir::LoadKind::I64 { atomic }, memarg); }
+                AsmOp::Store(atomic) => { builder.store(ctx.memory_id, walrus::ir::StoreKind::I64 { atomic }, memarg); }
+                AsmOp::Rmw(op) => { builder.atomic_rmw(ctx.memory_id, op, walrus::ir::AtomicWidth::I64, memarg); }
+                AsmOp::Fence => { builder.atomic_fence(); }
+            }
+        }
+        if stack != [ValType::I64] {
+            return Err(KainError::codegen(
+                format!("asm!: wasm code must leave exactly one i64 on the stack, it leaves {:?}", stack),
+                span,
+            ));
+        }
+        Ok(())
+    }
+
     fn compile_else_branch(&self, ctx: &CompilationContext, builder: &mut InstrSeqBuilder, branch: &crate::ast::ElseBranch) -> KainResult<()> {
         match branch {
             crate::ast::ElseBranch::Else(block) => {
@@ -2103,3 +2991,214 @@ impl WasmCompiler {
     }
 }
 
+/// Interns every string literal, JSX tag and attribute name into the data segment
+struct StringCollector<'a>(&'a mut WasmCompiler);
+
+impl Visitor for StringCollector<'_> {
+    fn visit_expr(&mut self, expr: &Expr) {
+        if let Expr::String(s, _) = expr {
+            self.0.allocate_string(s);
+        }
+        visit::walk_expr(self, expr);
+    }
+
+    fn visit_jsx(&mut self, node: &JSXNode) {
+        match node {
+            JSXNode::Element { tag: name, attributes: attrs, .. }
+            | JSXNode::ComponentCall { name, props: attrs, .. } => {
+                self.0.allocate_string(name);
+                for attr in attrs {
+                    self.0.allocate_string(&attr.name);
+                    if let JSXAttrValue::String(s) = &attr.value {
+                        self.0.allocate_string(s);
+                    }
+                }
+            }
+            JSXNode::Text(s, _) => {
+                self.0.allocate_string(s);
+            }
+            _ => {}
+        }
+        visit::walk_jsx(self, node);
+    }
+}
+
+/// Assigns each lambda a table id, outer lambdas before the ones nested in them
+struct LambdaCollector<'a> {
+    compiler: &'a mut WasmCompiler,
+    lambdas: Vec<(u32, Vec<Param>, Expr)>,
+}
+
+impl Visitor for LambdaCollector<'_> {
+    fn visit_expr(&mut self, expr: &Expr) {
+        if let Expr::Lambda { params, body, .. } = expr {
+            let id = self.compiler.lambda_counter;
+            self.compiler.lambda_counter += 1;
+            self.lambdas.push((id, params.clone(), (**body).clone()));
+        }
+        visit::walk_expr(self, expr);
+    }
+}
+
+/// One instruction of an `asm!("wasm", ..)` block
+struct AsmInstr {
+    params: Vec<ValType>,
+    result: Option<ValType>,
+    op: AsmOp,
+}
+
+enum AsmOp {
+    I32Const(i32),
+    I64Const(i64),
+    F64Const(f64),
+    Binary(walrus::ir::BinaryOp),
+    Unary(walrus::ir::UnaryOp),
+    Drop,
+    /// `i64.load` / `i64.atomic.load` from an i32 address
+    Load(bool),
+    /// `i64.store` / `i64.atomic.store` to an i32 address
+    Store(bool),
+    Rmw(walrus::ir::AtomicOp),
+    Fence,
+}
+
+impl AsmInstr {
+    fn new(params: &[ValType], result: Option<ValType>, op: AsmOp) -> Self {
+        AsmInstr { params: params.to_vec(), result, op }
+    }
+}
+
+/// The wasm instructions `asm!` accepts besides constants and `drop`, by their text format name
+fn asm_instr(name: &str) -> Option<AsmInstr> {
+    use walrus::ir::{AtomicOp, BinaryOp as B, UnaryOp as U};
+    use ValType::{F64, I32, I64};
+    let binary = |ty, result, op| AsmInstr::new(&[ty, ty], Some(result), AsmOp::Binary(op));
+    let unary = |ty, result, op| AsmInstr::new(&[ty], Some(result), AsmOp::Unary(op));
+    let rmw = |op| AsmInstr::new(&[I32, I64], Some(I64), AsmOp::Rmw(op));
+    Some(match name {
+        "i64.add" => binary(I64, I64, B::I64Add),
+        "i64.sub" => binary(I64, I64, B::I64Sub),
+        "i64.mul" => binary(I64, I64, B::I64Mul),
+        "i64.div_s" => binary(I64, I64, B::I64DivS),
+        "i64.div_u" => binary(I64, I64, B::I64DivU),
+        "i64.rem_s" => binary(I64, I64, B::I64RemS),
+        "i64.rem_u" => binary(I64, I64, B::I64RemU),
+        "i64.and" => binary(I64, I64, B::I64And),
+        "i64.or" => binary(I64, I64, B::I64Or),
+        "i64.xor" => binary(I64, I64, B::I64Xor),
+        "i64.shl" => binary(I64, I64, B::I64Shl),
+        "i64.shr_s" => binary(I64, I64, B::I64ShrS),
+        "i64.shr_u" => binary(I64, I64, B::I64ShrU),
+        "i64.rotl" => binary(I64, I64, B::I64Rotl),
+        "i64.rotr" => binary(I64, I64, B::I64Rotr),
+        "i64.eq" => binary(I64, I32, B::I64Eq),
+        "i64.ne" => binary(I64, I32, B::I64Ne),
+        "i64.lt_s" => binary(I64, I32, B::I64LtS),
+        "i64.lt_u" => binary(I64, I32, B::I64LtU),
+        "i64.gt_s" => binary(I64, I32, B::I64GtS),
+        "i64.gt_u" => binary(I64, I32, B::I64GtU),
+        "i64.le_s" => binary(I64, I32, B::I64LeS),
+        "i64.le_u" => binary(I64, I32, B::I64LeU),
+        "i64.ge_s" => binary(I64, I32, B::I64GeS),
+        "i64.ge_u" => binary(I64, I32, B::I64GeU),
+        "i64.clz" => unary(I64, I64, U::I64Clz),
+        "i64.ctz" => unary(I64, I64, U::I64Ctz),
+        "i64.popcnt" => unary(I64, I64, U::I64Popcnt),
+        "i64.eqz" => unary(I64, I32, U::I64Eqz),
+        "i32.add" => binary(I32, I32, B::I32Add),
+        "i32.sub" => binary(I32, I32, B::I32Sub),
+        "i32.mul" => binary(I32, I32, B::I32Mul),
+        "i32.and" => binary(I32, I32, B::I32And),
+        "i32.or" => binary(I32, I32, B::I32Or),
+        "i32.xor" => binary(I32, I32, B::I32Xor),
+        "i32.eqz" => unary(I32, I32, U::I32Eqz),
+        "f64.add" => binary(F64, F64, B::F64Add),
+        "f64.sub" => binary(F64, F64, B::F64Sub),
+        "f64.mul" => binary(F64, F64, B::F64Mul),
+        "f64.div" => binary(F64, F64, B::F64Div),
+        "f64.min" => binary(F64, F64, B::F64Min),
+        "f64.max" => binary(F64, F64, B::F64Max),
+        "f64.copysign" => binary(F64, F64, B::F64Copysign),
+        "f64.eq" => binary(F64, I32, B::F64Eq),
+        "f64.lt" => binary(F64, I32, B::F64Lt),
+        "f64.gt" => binary(F64, I32, B::F64Gt),
+        "f64.abs" => unary(F64, F64, U::F64Abs),
+        "f64.neg" => unary(F64, F64, U::F64Neg),
+        "f64.sqrt" => unary(F64, F64, U::F64Sqrt),
+        "f64.ceil" => unary(F64, F64, U::F64Ceil),
+        "f64.floor" => unary(F64, F64, U::F64Floor),
+        "f64.trunc" => unary(F64, F64, U::F64Trunc),
+        "f64.nearest" => unary(F64, F64, U::F64Nearest),
+        "i32.wrap_i64" => unary(I64, I32, U::I32WrapI64),
+        "i64.extend_i32_s" => unary(I32, I64, U::I64ExtendSI32),
+        "i64.extend_i32_u" => unary(I32, I64, U::I64ExtendUI32),
+        "i64.trunc_f64_s" => unary(F64, I64, U::I64TruncSF64),
+        "i64.trunc_f64_u" => unary(F64, I64, U::I64TruncUF64),
+        "f64.convert_i64_s" => unary(I64, F64, U::F64ConvertSI64),
+        "f64.convert_i64_u" => unary(I64, F64, U::F64ConvertUI64),
+        "i64.reinterpret_f64" => unary(F64, I64, U::I64ReinterpretF64),
+        "f64.reinterpret_i64" => unary(I64, F64, U::F64ReinterpretI64),
+        "i64.load" => AsmInstr::new(&[I32], Some(I64), AsmOp::Load(false)),
+        "i64.store" => AsmInstr::new(&[I32, I64], None, AsmOp::Store(false)),
+        "i64.atomic.load" => AsmInstr::new(&[I32], Some(I64), AsmOp::Load(true)),
+        "i64.atomic.store" => AsmInstr::new(&[I32, I64], None, AsmOp::Store(true)),
+        "i64.atomic.rmw.add" => rmw(AtomicOp::Add),
+        "i64.atomic.rmw.sub" => rmw(AtomicOp::Sub),
+        "i64.atomic.rmw.and" => rmw(AtomicOp::And),
+        "i64.atomic.rmw.or" => rmw(AtomicOp::Or),
+        "i64.atomic.rmw.xor" => rmw(AtomicOp::Xor),
+        "i64.atomic.rmw.xchg" => rmw(AtomicOp::Xchg),
+        "atomic.fence" => AsmInstr::new(&[], None, AsmOp::Fence),
+        _ => return None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{compile, compile_with_options, CompileOptions, CompileTarget};
+
+    #[test]
+    fn test_deterministic_output_is_byte_identical() {
+        let source = "struct A:\n    x: Int\n    y: Int\n\nstruct B:\n    y: Int\n\npub fn get(b: B) -> Int:\n    return b.y\n\nfn main():\n    let r = get(B { y: 1 })\n";
+        let options = CompileOptions { deterministic: true, ..Default::default() };
+        let first = compile_with_options(source, CompileTarget::Wasm, &options).unwrap();
+        for _ in 0..8 {
+            assert_eq!(compile_with_options(source, CompileTarget::Wasm, &options).unwrap(), first);
+        }
+
+        // `b.y` is B's only field, at offset 0, not A's `y` at offset 8
+        let wat = String::from_utf8(compile_with_options(source, CompileTarget::Wat, &options).unwrap()).unwrap();
+        let get = wat.split("(func $get").nth(1).and_then(|f| f.split("\n  )").next()).unwrap();
+        assert!(get.contains("(param i32) (result i64)"), "{}", get);
+        assert_eq!(get.lines().skip(1).map(str::trim).collect::<Vec<_>>(), ["local.get 0", "i64.load", "return"]);
+
+        let shader = "shader fragment Tint(uv: Vec2) -> Vec4:\n    uniform tint: Vec4 @0\n    return tint\n";
+        let spirv = compile_with_options(shader, CompileTarget::SpirV, &options).unwrap();
+        for _ in 0..8 {
+            assert_eq!(compile_with_options(shader, CompileTarget::SpirV, &options).unwrap(), spirv);
+        }
+    }
+
+    #[test]
+    fn test_wasm_vector_math_uses_simd_when_enabled() {
+        let source = "fn main() -> Float:\n    let a = vec3(1.0, 2.0, 3.0)\n    let b = vec3(4, 5, 6)\n    let total = sum([1, 2, 3])\n    let d = distance(a, b)\n    return dot(a, b)\n";
+        let simd = CompileOptions { simd: true, ..Default::default() };
+        let wat = String::from_utf8(compile_with_options(source, CompileTarget::Wat, &simd).unwrap()).unwrap();
+        assert!(wat.contains("f64x2.mul") && wat.contains("i64x2.add"), "{}", wat);
+
+        // Without the flag the same program stays scalar
+        let wat = String::from_utf8(compile(source, CompileTarget::Wat).unwrap()).unwrap();
+        assert!(!wat.contains("v128") && wat.contains("f64.mul"), "{}", wat);
+    }
+
+    #[test]
+    fn test_wasm_actors_run_on_a_cooperative_scheduler() {
+        let source = "actor Counter:\n    state count: Int = 0\n    on add(n: Int):\n        count = count + n\n        print(count)\n\nfn main():\n    let c = spawn Counter(count = 1)\n    send c.add(n = 2)\n    send c.add(n = 3)\n";
+        let wat = String::from_utf8(compile(source, CompileTarget::Wat).unwrap()).unwrap();
+        assert!(wat.contains("(export \"kain_run_actors\""), "{}", wat);
+        compile(source, CompileTarget::Wasm).unwrap();
+
+        let err = compile("actor A:\n    on ping():\n        print(1)\n\nfn main():\n    let a = spawn A()\n    send a.pong()\n", CompileTarget::Wasm).unwrap_err();
+        assert!(err.to_string().contains("No actor handles `pong`"), "{}", err);
+    }
+}
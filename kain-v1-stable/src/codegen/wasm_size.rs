@@ -0,0 +1,133 @@
+//! Where a WASM binary's bytes go (`kain build --size-report`)
+//!
+//! ```text
+//!  [wasm] -> target/release/wasm/main.wasm (1843 bytes)
+//!    code           1210  65.7%  (14 functions)
+//!    data            402  21.8%  (3 segments)
+//!    ...
+//!  Largest functions:
+//!     388  21.1%  main
+//! ```
+//!
+//! Sections are measured without their headers, so the rows add up to a
+//! little less than the file. Functions are named as the WAT printer names
+//! them: from the `name` section, else their import or export.
+
+use std::fmt;
+
+use wasmparser::{Parser, Payload};
+
+use super::wat::{function_names, invalid};
+use crate::error::KainResult;
+
+/// Functions listed under "Largest functions"
+const LARGEST_SHOWN: usize = 10;
+
+/// The bytes each section and each function body of a module takes
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SizeReport {
+    pub total: usize,
+    /// Sections in the order they appear, `custom "name"` for custom sections
+    pub sections: Vec<(String, usize)>,
+    /// Function bodies, largest first
+    pub functions: Vec<(String, usize)>,
+    pub data_segments: usize,
+}
+
+/// Measure `wasm`
+pub fn report(wasm: &[u8]) -> KainResult<SizeReport> {
+    let (names, imported) = function_names(wasm)?;
+    let mut report = SizeReport { total: wasm.len(), sections: Vec::new(), functions: Vec::new(), data_segments: 0 };
+    let mut index = imported;
+    for payload in Parser::new(0).parse_all(wasm) {
+        let payload = payload.map_err(invalid)?;
+        match &payload {
+            Payload::CodeSectionEntry(body) => {
+                let name = names.get(&index).cloned().unwrap_or_else(|| format!("func {}", index));
+                report.functions.push((name, body.range().len()));
+                index += 1;
+            }
+            Payload::DataSection(reader) => report.data_segments = reader.count() as usize,
+            _ => {}
+        }
+        let Some((id, range)) = payload.as_section() else { continue };
+        let name = match &payload {
+            Payload::CustomSection(reader) => format!("custom \"{}\"", reader.name()),
+            _ => section_name(id).to_string(),
+        };
+        report.sections.push((name, range.len()));
+    }
+    report.functions.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+    Ok(report)
+}
+
+fn section_name(id: u8) -> &'static str {
+    match id {
+        1 => "type",
+        2 => "import",
+        3 => "function",
+        4 => "table",
+        5 => "memory",
+        6 => "global",
+        7 => "export",
+        8 => "start",
+        9 => "element",
+        10 => "code",
+        11 => "data",
+        12 => "datacount",
+        13 => "tag",
+        _ => "unknown",
+    }
+}
+
+impl SizeReport {
+    fn percent(&self, size: usize) -> f64 {
+        size as f64 * 100.0 / self.total.max(1) as f64
+    }
+}
+
+impl fmt::Display for SizeReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (name, size) in &self.sections {
+            let detail = match name.as_str() {
+                "code" => format!("  ({} functions)", self.functions.len()),
+                "data" => format!("  ({} segments)", self.data_segments),
+                _ => String::new(),
+            };
+            writeln!(f, "   {:<16} {:>7} {:>5.1}%{}", name, size, self.percent(*size), detail)?;
+        }
+        if !self.functions.is_empty() {
+            writeln!(f, " Largest functions:")?;
+            for (name, size) in self.functions.iter().take(LARGEST_SHOWN) {
+                writeln!(f, "   {:>7} {:>5.1}%  {}", size, self.percent(*size), name)?;
+            }
+            if self.functions.len() > LARGEST_SHOWN {
+                writeln!(f, "   ... {} more", self.functions.len() - LARGEST_SHOWN)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_size_report_measures_sections_and_functions() {
+        let source = "fn add(a: Int, b: Int) -> Int:\n    return a + b\n\nfn main():\n    let x = add(1, 2)\n";
+        let wasm = crate::compile(source, crate::CompileTarget::Wasm).unwrap();
+        let report = report(&wasm).unwrap();
+
+        assert_eq!(report.total, wasm.len());
+        assert!(report.sections.iter().map(|(_, size)| size).sum::<usize>() < wasm.len());
+        let code = report.sections.iter().find(|(name, _)| name == "code").map(|(_, size)| *size).unwrap();
+        assert!(report.functions.iter().map(|(_, size)| size).sum::<usize>() < code);
+        assert!(report.functions.len() >= 2, "{:?}", report.functions);
+        assert!(report.functions.windows(2).all(|pair| pair[0].1 >= pair[1].1));
+
+        let printed = report.to_string();
+        assert!(printed.contains(&format!("({} functions)", report.functions.len())), "{}", printed);
+        assert!(printed.contains(" Largest functions:"), "{}", printed);
+    }
+}
@@ -0,0 +1,219 @@
+//! WebAssembly text format printer
+//!
+//! Renders the binary produced by the WASM backend as WAT with `wasmprinter`,
+//! so users and the codegen tests can read what was generated without
+//! external tooling. Functions are labelled from the `name` section when
+//! present, otherwise from their import/export names.
+
+use std::collections::{HashMap, HashSet};
+use std::ops::Range;
+
+use wasmparser::{ExternalKind, KnownCustom, Name, Parser, Payload, TypeRef, Validator};
+
+use crate::error::{KainError, KainResult};
+
+/// Print a WASM binary as WAT.
+///
+/// Modules that decode but fail validation are still printed, with the
+/// validation error as a leading comment, since that's when you want to look.
+pub fn print(wasm: &[u8]) -> KainResult<String> {
+    let named = with_names(wasm).map_err(invalid)?;
+    let text = wasmprinter::print_bytes(&named).map_err(|e| KainError::runtime(format!("invalid wasm module: {}", e)))?;
+    match Validator::new().validate_all(wasm) {
+        Ok(_) => Ok(text),
+        Err(e) => Ok(format!(";; invalid module: {}\n{}", e, text)),
+    }
+}
+
+/// Check that a WASM binary is a valid module
+pub fn validate(wasm: &[u8]) -> KainResult<()> {
+    Validator::new().validate_all(wasm).map(|_| ()).map_err(invalid)
+}
+
+/// Labels of a module's functions by index, as the printer shows them, and how many are imported
+pub(super) fn function_names(wasm: &[u8]) -> KainResult<(HashMap<u32, String>, u32)> {
+    let names = Names::read(wasm).map_err(invalid)?;
+    Ok((names.funcs, names.imported_funcs))
+}
+
+/// The backend produced a module it cannot read back, which no source location explains
+pub(super) fn invalid(e: wasmparser::BinaryReaderError) -> KainError {
+    KainError::runtime(format!("invalid wasm module: {}", e))
+}
+
+type ParseResult<T> = Result<T, wasmparser::BinaryReaderError>;
+
+/// The module with a `name` section labelling every function the printer can label,
+/// replacing any it had, so the text reads `call $add` rather than `call 3`
+fn with_names(wasm: &[u8]) -> ParseResult<Vec<u8>> {
+    let names = Names::read(wasm)?;
+    let mut out = match name_section(wasm) {
+        Some(range) => [&wasm[..range.start], &wasm[range.end..]].concat(),
+        None => wasm.to_vec(),
+    };
+    let mut funcs: Vec<_> = names.funcs.iter().collect();
+    funcs.sort();
+    let mut map = Vec::new();
+    leb128(&mut map, funcs.len() as u32);
+    for (index, name) in funcs {
+        leb128(&mut map, *index);
+        string(&mut map, name);
+    }
+    let mut body = Vec::new();
+    string(&mut body, "name");
+    body.push(1); // function names
+    leb128(&mut body, map.len() as u32);
+    body.extend(map);
+    out.push(0); // custom section
+    leb128(&mut out, body.len() as u32);
+    out.extend(body);
+    Ok(out)
+}
+
+/// Where a module that has already been read has its own `name` section, header included
+fn name_section(wasm: &[u8]) -> Option<Range<usize>> {
+    let mut at = 8;
+    while at < wasm.len() {
+        let start = at;
+        let id = wasm[at];
+        at += 1;
+        let (size, len) = read_leb128(&wasm[at..]);
+        at += len;
+        let end = at + size as usize;
+        if id == 0 {
+            let (name_len, len) = read_leb128(&wasm[at..]);
+            if wasm.get(at + len..at + len + name_len as usize) == Some(b"name".as_slice()) {
+                return Some(start..end);
+            }
+        }
+        at = end;
+    }
+    None
+}
+
+fn read_leb128(bytes: &[u8]) -> (u32, usize) {
+    let mut value = 0;
+    for (i, byte) in bytes.iter().enumerate().take(5) {
+        value |= ((byte & 0x7f) as u32) << (7 * i);
+        if byte & 0x80 == 0 {
+            return (value, i + 1);
+        }
+    }
+    (value, bytes.len().min(5))
+}
+
+fn leb128(out: &mut Vec<u8>, mut value: u32) {
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        if value == 0 {
+            out.push(byte);
+            return;
+        }
+        out.push(byte | 0x80);
+    }
+}
+
+fn string(out: &mut Vec<u8>, s: &str) {
+    leb128(out, s.len() as u32);
+    out.extend_from_slice(s.as_bytes());
+}
+
+#[derive(Default)]
+struct Names {
+    funcs: HashMap<u32, String>,
+    imported_funcs: u32,
+}
+
+impl Names {
+    fn read(wasm: &[u8]) -> ParseResult<Self> {
+        let mut names = Names::default();
+        let mut imports = Vec::new();
+        let mut exports = Vec::new();
+        for payload in Parser::new(0).parse_all(wasm) {
+            match payload? {
+                Payload::ImportSection(reader) => {
+                    for import in reader {
+                        let import = import?;
+                        if matches!(import.ty, TypeRef::Func(_)) {
+                            imports.push((names.imported_funcs, import.name.to_string()));
+                            names.imported_funcs += 1;
+                        }
+                    }
+                }
+                Payload::ExportSection(reader) => {
+                    for export in reader {
+                        let export = export?;
+                        if export.kind == ExternalKind::Func {
+                            exports.push((export.index, export.name.to_string()));
+                        }
+                    }
+                }
+                Payload::CustomSection(reader) => {
+                    if let KnownCustom::Name(section) = reader.as_known() {
+                        for name in section {
+                            if let Name::Function(map) = name? {
+                                for naming in map {
+                                    let naming = naming?;
+                                    names.funcs.insert(naming.index, naming.name.to_string());
+                                }
+                            }
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        // Label unnamed functions after their import or export, keeping labels unique
+        let mut used: HashSet<String> = names.funcs.values().cloned().collect();
+        for (index, name) in imports.into_iter().chain(exports) {
+            let valid = !name.is_empty() && name.chars().all(|c| c.is_ascii_graphic() && c != '"');
+            if valid && !names.funcs.contains_key(&index) && used.insert(name.clone()) {
+                names.funcs.insert(index, name);
+            }
+        }
+        Ok(names)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_wat_output() {
+        let source = "fn add(a: Int, b: Int) -> Int:\n    return a + b\n\nfn main():\n    let x = add(1, 2)\n";
+        let wasm = crate::compile(source, crate::CompileTarget::Wasm).unwrap();
+        let wat = print(&wasm).unwrap();
+
+        assert!(wat.starts_with("(module\n"));
+        assert!(wat.contains("(import \"host\" \"print_i64\" (func $print_i64 (;0;)"));
+        assert!(wat.contains("(param i64 i64) (result i64)\n    local.get 0\n    local.get 1\n    i64.add\n"));
+        assert!(wat.contains("(export \"memory\" (memory 0))"));
+        assert!(validate(&wasm).is_ok());
+        assert!(print(&wasm[..wasm.len() - 1]).is_err());
+    }
+
+    #[test]
+    fn test_wat_parses_back_to_the_same_module() {
+        let sources = [
+            "fn add(a: Int, b: Int) -> Int:\n    return a + b\n\nfn main():\n    let x = add(1, 2)\n",
+            // field loads at offsets
+            "struct A:\n    x: Int\n    y: Int\n\nstruct B:\n    y: Int\n\npub fn get(a: A) -> Int:\n    return a.y\n\nfn main():\n    let r = get(A { x: 1, y: 2 })\n",
+            "actor Counter:\n    state count: Int = 0\n    on add(n: Int):\n        count = count + n\n        print(count)\n\nfn main():\n    let c = spawn Counter(count = 1)\n    send c.add(n = 2)\n",
+        ];
+        let simd = crate::CompileOptions { simd: true, ..Default::default() };
+        let vectors = "fn main() -> Float:\n    let a = vec3(1.0, 2.0, 3.0)\n    let b = vec3(4, 5, 6)\n    let total = sum([1, 2, 3])\n    return dot(a, b)\n";
+        let modules = sources
+            .iter()
+            .map(|s| crate::compile(s, crate::CompileTarget::Wasm).unwrap())
+            .chain([crate::compile_with_options(vectors, crate::CompileTarget::Wasm, &simd).unwrap()]);
+        for wasm in modules {
+            let text = print(&wasm).unwrap();
+            let back = wat::parse_str(&text).unwrap_or_else(|e| panic!("{}\n{}", e, text));
+            assert!(validate(&back).is_ok());
+            assert_eq!(print(&back).unwrap(), text);
+        }
+    }
+}
@@ -1,116 +1,351 @@
 use crate::ast::*;
-use crate::runtime::{Env, eval_expr, Value};
-use crate::error::KainResult;
+use crate::ast::visit::{walk_expr_mut, VisitorMut};
+use crate::runtime::{self, Env, Value, eval_expr, value_to_expr};
+use crate::error::{KainError, KainResult};
 use crate::span::Span;
+use crate::{CompileOptions, CompileTarget};
 
+mod derive;
+mod reflect;
+mod sql;
+
+use reflect::Reflection;
+
+/// Every name `@cfg(target = "...")` accepts, so typos are errors rather than dead code
+const CFG_TARGETS: &[&str] = &[
+    "wasm", "wat", "hybrid", "js", "llvm", "rust", "interpret", "test", "spirv", "hlsl", "usf", "native", "gpu",
+];
+
+/// Natives compile-time code may call besides the pure ones: printing, to debug
+/// it, and handing items to the program
+const COMPTIME_NATIVES: &[&str] = &["print", "println", "dbg", "emit_item"];
+
+/// Evaluate comptime code with the default sandbox: only pure natives, printing and `emit_item`
 pub fn eval_program(program: &mut Program) -> KainResult<()> {
+    eval_program_with_options(program, CompileTarget::Interpret, &CompileOptions::default())
+}
+
+pub fn eval_program_with_options(program: &mut Program, target: CompileTarget, options: &CompileOptions) -> KainResult<()> {
+    let cfg = Cfg { target, features: &options.features };
+    resolve_cfg_items(&mut program.items, &cfg)?;
+    sql::expand_queries(&mut program.items, options.sql_schema.as_deref())?;
+    derive::expand_derives(&mut program.items)?;
+    crate::types::lower_associated_consts(&mut program.items);
+
     let mut env = Env::new();
-    
+    env.set_limits(options.limits);
+    env.set_edition(options.edition);
+    env.enable_item_emission();
+    if !options.allow_comptime_io {
+        let reason = "during compile-time evaluation (enable with --allow-comptime-io)";
+        env.allow_only_natives(&[runtime::PURE_NATIVES, COMPTIME_NATIVES].concat(), reason);
+    }
+
+    let cx = Context { reflection: Reflection::new(&program.items, target, &options.features), cfg };
     for item in &mut program.items {
-        eval_item(&mut env, item)?;
+        eval_item(&mut env, &cx, item)?;
     }
-    
-    Ok(())
-}
 
-fn eval_item(env: &mut Env, item: &mut Item) -> KainResult<()> {
-    match item {
-        Item::Function(f) => eval_block(env, &mut f.body)?,
-        Item::Comptime(block) => {
-            crate::runtime::eval_block(env, &block.body)?;
+    // Items from `emit_item` join the program before type checking; they may emit more
+    let mut emitted = env.take_emitted_items();
+    while !emitted.is_empty() {
+        resolve_cfg_items(&mut emitted, &cx.cfg)?;
+        for item in &mut emitted {
+            eval_item(&mut env, &cx, item)?;
         }
-        Item::Component(c) => {
-             eval_jsx(env, &mut c.body)?;
-             for method in &mut c.methods {
-                 eval_block(env, &mut method.body)?;
-             }
+        program.items.append(&mut emitted);
+        emitted = env.take_emitted_items();
+    }
+
+    fold_consts(&mut env, &mut program.items)
+}
+
+/// Evaluate the program's consts in dependency order and write each value back
+/// as a literal, so every backend compiles a true constant. A value comptime
+/// cannot produce (it calls into an imported module, say) or cannot write as a
+/// literal stays as written, for the interpreter to evaluate at startup.
+fn fold_consts(env: &mut Env, items: &mut [Item]) -> KainResult<()> {
+    let order = {
+        let consts: Vec<&Const> = items
+            .iter()
+            .filter_map(|item| match item {
+                Item::Const(c) => Some(c),
+                _ => None,
+            })
+            .collect();
+        let functions: Vec<&Function> = items
+            .iter()
+            .filter_map(|item| match item {
+                Item::Function(f) => Some(f),
+                _ => None,
+            })
+            .collect();
+        crate::consts::order(&consts, &functions)?
+    };
+    for item in items.iter() {
+        if let Item::Function(f) = item {
+            env.define_function(f);
         }
-        Item::Const(c) => {
-            eval_expr_in_place(env, &mut c.value)?;
+    }
+
+    let mut consts: Vec<&mut Const> = items
+        .iter_mut()
+        .filter_map(|item| match item {
+            Item::Const(c) => Some(c),
+            _ => None,
+        })
+        .collect();
+    for i in order {
+        let c = &mut *consts[i];
+        let Ok(value) = eval_expr(env, &c.value) else {
+            continue;
+        };
+        // `const X: Float = 1` is still a float wherever it is compiled
+        let value = match (value, &c.ty) {
+            (Value::Int(n), Type::Named { name, .. }) if name == "Float" => Value::Float(n as f64),
+            (value, _) => value,
+        };
+        env.define_const(&c.name, value.clone());
+        if is_literal(&value) {
+            c.value = value_to_expr(value, c.value.span())?;
         }
-        _ => {}
     }
     Ok(())
 }
 
-fn eval_block(env: &mut Env, block: &mut Block) -> KainResult<()> {
-    for stmt in &mut block.stmts {
-        eval_stmt(env, stmt)?;
+/// Whether `value_to_expr` writes `value` back as plain data
+fn is_literal(value: &Value) -> bool {
+    match value {
+        Value::Int(_) | Value::Float(_) | Value::Bool(_) | Value::String(_) => true,
+        Value::Array(items) => items.read().unwrap_or_else(|e| e.into_inner()).iter().all(is_literal),
+        Value::Tuple(items) => items.iter().all(is_literal),
+        Value::Struct(_, fields) => fields.read().unwrap_or_else(|e| e.into_inner()).values().all(is_literal),
+        _ => false,
     }
-    Ok(())
 }
 
-fn eval_stmt(env: &mut Env, stmt: &mut Stmt) -> KainResult<()> {
-    match stmt {
-        Stmt::Let { value: Some(e), .. } => eval_expr_in_place(env, e)?,
-        Stmt::Expr(e) => eval_expr_in_place(env, e)?,
-        Stmt::Return(Some(e), _) => eval_expr_in_place(env, e)?,
-        Stmt::For { iter, body, .. } => {
-            eval_expr_in_place(env, iter)?;
-            eval_block(env, body)?;
+/// Everything comptime evaluation consults besides the interpreter state
+struct Context<'a> {
+    cfg: Cfg<'a>,
+    reflection: Reflection,
+}
+
+/// What `@cfg(...)` and `cfg!(...)` conditions are evaluated against
+struct Cfg<'a> {
+    target: CompileTarget,
+    features: &'a [String],
+}
+
+impl Cfg<'_> {
+    /// `key = "value"`, or `not(..)` / `all(..)` / `any(..)` of other conditions
+    fn holds(&self, cond: &Expr) -> KainResult<bool> {
+        match cond {
+            Expr::Assign { target, value, span } => match (&**target, &**value) {
+                (Expr::Ident(key, _), Expr::String(value, _)) => self.key_holds(key, value, *span),
+                _ => Err(malformed_cfg(*span)),
+            },
+            Expr::Call { callee, args, span } => {
+                let Expr::Ident(op, _) = &**callee else { return Err(malformed_cfg(*span)) };
+                let mut results = Vec::with_capacity(args.len());
+                for arg in args {
+                    // Inside a call `target = "wasm"` parses as a named argument
+                    results.push(match (&arg.name, &arg.value) {
+                        (Some(key), Expr::String(value, _)) => self.key_holds(key, value, arg.span)?,
+                        (None, cond) => self.holds(cond)?,
+                        _ => return Err(malformed_cfg(arg.span)),
+                    });
+                }
+                match (op.as_str(), results.as_slice()) {
+                    ("all", _) => Ok(results.iter().all(|r| *r)),
+                    ("any", _) => Ok(results.iter().any(|r| *r)),
+                    ("not", [r]) => Ok(!r),
+                    _ => Err(malformed_cfg(*span)),
+                }
+            }
+            Expr::Paren(inner, _) => self.holds(inner),
+            Expr::Bool(b, _) => Ok(*b),
+            other => Err(malformed_cfg(other.span())),
         }
-        _ => {}
     }
-    Ok(())
+
+    fn key_holds(&self, key: &str, value: &str, span: Span) -> KainResult<bool> {
+        match key {
+            "target" if CFG_TARGETS.contains(&value) => Ok(self.target.cfg_names().contains(&value)),
+            "target" => Err(KainError::parser(
+                format!("Unknown cfg target '{}', expected one of: {}", value, CFG_TARGETS.join(", ")),
+                span,
+            )),
+            "feature" => Ok(self.features.iter().any(|f| f == value)),
+            _ => Err(KainError::parser(format!("Unknown cfg key '{}', expected `target` or `feature`", key), span)),
+        }
+    }
+
+    /// The single condition of a `@cfg(..)` attribute or `cfg!(..)` call
+    fn holds_single(&self, args: &[Expr], span: Span) -> KainResult<bool> {
+        match args {
+            [cond] => self.holds(cond),
+            _ => Err(KainError::parser("cfg takes exactly one condition; combine them with all(..) or any(..)", span)),
+        }
+    }
+}
+
+fn malformed_cfg(span: Span) -> KainError {
+    KainError::parser(
+        "Malformed cfg condition, expected `target = \"..\"`, `feature = \"..\"`, not(..), all(..) or any(..)",
+        span,
+    )
 }
 
-fn eval_expr_in_place(env: &mut Env, expr: &mut Expr) -> KainResult<()> {
-    // Check if this IS a comptime expression
-    if let Expr::Comptime(inner, span) = expr {
-        // Evaluate inner expression
-        let val = eval_expr(env, inner)?;
-        
-        // Replace current expr with result value (Literal)
-        *expr = value_to_expr(val, *span);
-        return Ok(());
-    }
-    
-    // Otherwise recurse
-    match expr {
-        Expr::Binary { left, right, .. } => {
-            eval_expr_in_place(env, left)?;
-            eval_expr_in_place(env, right)?;
+/// Drop items whose `@cfg` conditions don't hold for this build and unwrap the rest
+fn resolve_cfg_items(items: &mut Vec<Item>, cfg: &Cfg) -> KainResult<()> {
+    let mut resolved = Vec::with_capacity(items.len());
+    for item in items.drain(..) {
+        let Item::Cfg(c) = item else {
+            resolved.push(item);
+            continue;
+        };
+        let mut keep = true;
+        for attr in &c.conditions {
+            // Evaluate every condition so a malformed one is reported on all targets
+            keep &= cfg.holds_single(&attr.args, attr.span)?;
         }
-        Expr::Call { args, .. } => {
-             for arg in args {
-                 eval_expr_in_place(env, &mut arg.value)?;
-             }
+        if keep {
+            resolved.push(*c.item);
         }
-        Expr::Assign { value, .. } => eval_expr_in_place(env, value)?,
-        Expr::Paren(e, _) => eval_expr_in_place(env, e)?,
-        Expr::Block(b, _) => eval_block(env, b)?,
-        Expr::JSX(node, _) => eval_jsx(env, node)?,
-        _ => {}
     }
+    *items = resolved;
     Ok(())
 }
 
-fn eval_jsx(env: &mut Env, node: &mut JSXNode) -> KainResult<()> {
-    match node {
-        JSXNode::Element { attributes, children, .. } => {
-             for attr in attributes {
-                 if let JSXAttrValue::Expr(e) = &mut attr.value {
-                     eval_expr_in_place(env, e)?;
-                 }
-             }
-             for child in children {
-                 eval_jsx(env, child)?;
-             }
-        }
-        JSXNode::Expression(e) => eval_expr_in_place(env, e)?,
-        _ => {}
+fn eval_item(env: &mut Env, cx: &Context, item: &mut Item) -> KainResult<()> {
+    let mut folder = Folder { env, cx, error: None };
+    folder.visit_item_mut(item);
+    if let Some(e) = folder.error {
+        return Err(e);
+    }
+    if let Item::Comptime(block) = item {
+        cx.reflection.expand_block(&mut block.body)?;
+        runtime::eval_block(env, &block.body)?;
     }
     Ok(())
 }
 
-fn value_to_expr(val: Value, span: Span) -> Expr {
-    match val {
-        Value::Int(n) => Expr::Int(n, span),
-        Value::Float(n) => Expr::Float(n, span),
-        Value::Bool(b) => Expr::Bool(b, span),
-        Value::String(s) => Expr::String(s, span),
-        Value::Unit => Expr::Block(Block { stmts: vec![], span }, span), // Unit is empty block?
-        _ => Expr::String(format!("<unrepresentable comptime value: {}>", val), span),
+/// Replaces `comptime` expressions with the values they produce and `cfg!(..)`
+/// with a bool literal for this build, anywhere in the tree
+struct Folder<'a, 'e> {
+    env: &'e mut Env,
+    cx: &'a Context<'a>,
+    error: Option<KainError>,
+}
+
+impl Folder<'_, '_> {
+    fn fold(&mut self, expr: &mut Expr) -> KainResult<()> {
+        match expr {
+            Expr::Comptime(inner, span) => {
+                // Fold any cfg!() first, the interpreter doesn't know the target
+                self.visit_expr_mut(inner);
+                if let Some(e) = self.error.take() {
+                    return Err(e);
+                }
+                self.cx.reflection.expand_expr(inner)?;
+                let val = eval_expr(self.env, inner)?;
+                *expr = value_to_expr(val, *span)?;
+            }
+            Expr::MacroCall { name, args, span } if name == "cfg" => {
+                *expr = Expr::Bool(self.cx.cfg.holds_single(args, *span)?, *span);
+            }
+            _ => walk_expr_mut(self, expr),
+        }
+        Ok(())
     }
 }
 
+impl VisitorMut for Folder<'_, '_> {
+    fn visit_expr_mut(&mut self, expr: &mut Expr) {
+        if self.error.is_some() {
+            return;
+        }
+        if let Err(e) = self.fold(expr) {
+            self.error = Some(e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{compile, compile_with_options, edition, eval_snippet, runtime, CompileOptions, CompileTarget};
+
+    #[test]
+    fn test_comptime_io_is_sandboxed() {
+        let source = "const HOME: String = comptime:\n    env(\"HOME\")\n\nfn main():\n    println(HOME)\n";
+        let err = compile(source, CompileTarget::Js).unwrap_err();
+        assert!(err.to_string().contains("`env` is not allowed during compile-time evaluation"));
+
+        let options = CompileOptions { allow_comptime_io: true, ..Default::default() };
+        assert!(compile_with_options(source, CompileTarget::Js, &options).is_ok());
+
+        // Only natives known to be pure are allowed, so new ones start out refused
+        for call in ["snapshot(\"state.bin\")", "now()", "random()", "runtime_stats()"] {
+            let source = format!("const X: Int = comptime:\n    {}\n    1\n\nfn main():\n    println(X)\n", call);
+            let err = compile(&source, CompileTarget::Js).unwrap_err();
+            assert!(err.to_string().contains("is not allowed during compile-time evaluation"), "{}: {}", call, err);
+        }
+        let pure = "const X: Int = comptime:\n    len(sort([3, 1, 2])) + int(sqrt(16.0))\n\nfn main():\n    println(X)\n";
+        assert!(compile(pure, CompileTarget::Js).is_ok());
+        let env = runtime::Env::new();
+        let defined = env.native_names();
+        for name in runtime::PURE_NATIVES {
+            assert!(defined.contains(name), "`{}` is not a native", name);
+        }
+    }
+
+    #[test]
+    fn test_comptime_values_are_written_back_as_literals() {
+        let source = "enum Shape:\n    Circle(Int)\n    Empty\n\nfn area(s: Shape) -> Int:\n    match s:\n        Shape::Circle(r) => return r * r\n        Shape::Empty => return 0\n\nfn main():\n    let s = comptime:\n        Shape::Circle(2)\n    let e = comptime:\n        Shape::Empty\n    println(area(s), area(e))\n";
+        let result = eval_snippet(source, &CompileOptions::default());
+        assert!(result.diagnostics.is_empty(), "{:?}", result.diagnostics);
+        assert_eq!(result.stdout.trim_end(), "4 0");
+
+        // What has no literal form is an error where comptime produced it
+        let stepped = "fn main():\n    let r = comptime:\n        (0..10).step(2)\n    println(r)\n";
+        let err = compile(stepped, CompileTarget::Js).unwrap_err();
+        assert!(err.to_string().contains("has no literal form"), "{}", err);
+    }
+
+    #[test]
+    fn test_cfg_selects_items_per_target() {
+        let source = "@cfg(target = \"js\")\nfn storage() -> String:\n    return \"local_storage\"\n\n@cfg(not(target = \"js\"))\nfn storage() -> String:\n    return \"disk_file\"\n\n@cfg(feature = \"trace\")\nfn trace_hook():\n    println(\"trace_hook\")\n\nfn main():\n    let native = cfg!(any(target = \"native\", target = \"wasm\"))\n    println(storage())\n";
+
+        let js = String::from_utf8(compile(source, CompileTarget::Js).unwrap()).unwrap();
+        assert!(js.contains("local_storage") && !js.contains("disk_file"));
+        assert!(!js.contains("trace_hook") && js.contains("false"));
+
+        let options = CompileOptions { features: vec!["trace".to_string()], ..Default::default() };
+        let rust = String::from_utf8(compile_with_options(source, CompileTarget::Rust, &options).unwrap()).unwrap();
+        assert!(rust.contains("disk_file") && !rust.contains("local_storage"));
+        assert!(rust.contains("trace_hook") && rust.contains("true"));
+
+        // cfg!() folds wherever an expression can appear, impl methods included
+        let on = "cfg!(target = \"interpret\")";
+        let nested = format!("struct P:\n    on: Bool\n\nimpl P:\n    fn check(self) -> Bool:\n        return {on}\n\nfn main():\n    let xs = [{on}, false]\n    let p = P {{ on: {on} }}\n    let t = ({on}, 1)\n    let f = |x| x && {on}\n    let m = match 1:\n        1 => {on}\n        _ => false\n    println(type_name!({on}), xs[0], p.on, t.0, f(true), m, p.check(), [{on}, false][0], [{on}].len())\n");
+        let result = eval_snippet(&nested, &CompileOptions::default());
+        assert!(result.diagnostics.is_empty(), "{:?}", result.diagnostics);
+        assert_eq!(result.stdout.trim(), "bool true true true true true true true 1");
+
+        let typo = compile("@cfg(target = \"wsam\")\nfn f():\n    return\n", CompileTarget::Js).unwrap_err();
+        assert!(typo.to_string().contains("Unknown cfg target 'wsam'"));
+    }
+
+    #[test]
+    fn test_quote_splices_and_emits_items() {
+        let source = "comptime:\n    let greeting = \"hi from \" + \"comptime\"\n    let code = quote:\n        fn greet() -> String:\n            return splice(greeting)\n    emit_item(code)\n\nfn main():\n    println(greet())\n";
+        let options = CompileOptions { edition: edition::Edition::V0_2, ..Default::default() };
+        let result = eval_snippet(source, &options);
+        assert!(result.diagnostics.is_empty(), "{:?}", result.diagnostics);
+        assert_eq!(result.stdout.trim(), "hi from comptime");
+
+        // Before 0.2 `quote` is still an ordinary identifier
+        let old = eval_snippet("fn main():\n    let quote = 1\n    println(quote)\n", &CompileOptions::default());
+        assert_eq!(old.stdout.trim(), "1");
+    }
+}
@@ -0,0 +1,108 @@
+//! `@derive(...)`: methods generated from a struct or enum declaration
+//!
+//! ```ignore
+//! @derive(Serialize, Deserialize)
+//! struct Point:
+//!     x: Int
+//!     y: Int
+//! ```
+//!
+//! `Serialize` adds `serialize(self)`, the value in the wire format as an
+//! array of bytes, and `to_json(self)`. `Deserialize` adds `deserialize(bytes)`
+//! and `from_json(text)`, which fail unless what they read is a `Point`.
+//! The methods call the natives of the same names, so every derived type writes the
+//! same format snapshots and recordings do (see `crate::wire`).
+
+use crate::ast::*;
+use crate::error::{KainError, KainResult};
+use crate::span::Span;
+
+/// Traits `@derive` can generate
+const DERIVABLE: &[&str] = &["Serialize", "Deserialize"];
+
+/// Append an impl for every trait the program's structs and enums derive
+pub(super) fn expand_derives(items: &mut Vec<Item>) -> KainResult<()> {
+    let mut impls = Vec::new();
+    for item in items.iter() {
+        let (name, attributes) = match item {
+            Item::Struct(s) => (&s.name, &s.attributes),
+            Item::Enum(e) => (&e.name, &e.attributes),
+            _ => continue,
+        };
+        for attr in attributes.iter().filter(|a| a.name == "derive") {
+            for arg in &attr.args {
+                let span = arg.span();
+                let trait_name = match arg {
+                    Expr::Ident(t, _) if DERIVABLE.contains(&t.as_str()) => t,
+                    other => {
+                        let shown = match other {
+                            Expr::Ident(t, _) => format!("`{}`", t),
+                            _ => "this".to_string(),
+                        };
+                        return Err(KainError::type_error(
+                            format!("cannot derive {} for {}; derivable traits are {}", shown, name, DERIVABLE.join(", ")),
+                            span,
+                        ));
+                    }
+                };
+                let methods = if trait_name == "Serialize" {
+                    vec![
+                        method("serialize", self_param(span), "Array", call("serialize", "self", None, span), span),
+                        method("to_json", self_param(span), "String", call("serialize_json", "self", None, span), span),
+                    ]
+                } else {
+                    vec![
+                        method("deserialize", param("bytes", "Array", span), name, call("deserialize", "bytes", Some(name.as_str()), span), span),
+                        method("from_json", param("text", "String", span), name, call("deserialize_json", "text", Some(name.as_str()), span), span),
+                    ]
+                };
+                impls.push(Item::Impl(Impl {
+                    generics: Vec::new(),
+                    trait_name: Some(trait_name.clone()),
+                    target_type: named(name, span),
+                    methods,
+                    consts: Vec::new(),
+                    span: attr.span,
+                }));
+            }
+        }
+    }
+    items.extend(impls);
+    Ok(())
+}
+
+fn named(name: &str, span: Span) -> Type {
+    Type::Named { name: name.to_string(), generics: Vec::new(), span }
+}
+
+fn self_param(span: Span) -> Param {
+    Param { name: "self".to_string(), ty: Type::Infer(span), mutable: false, default: None, variadic: false, span }
+}
+
+fn param(name: &str, ty: &str, span: Span) -> Param {
+    Param { name: name.to_string(), ty: named(ty, span), mutable: false, default: None, variadic: false, span }
+}
+
+/// `native(arg)`, or `native(arg, "Type")` to check what was read
+fn call(native: &str, arg: &str, expected: Option<&str>, span: Span) -> Expr {
+    let mut args = vec![CallArg { name: None, value: Expr::Ident(arg.to_string(), span), spread: false, span }];
+    if let Some(ty) = expected {
+        args.push(CallArg { name: None, value: Expr::String(ty.to_string(), span), spread: false, span });
+    }
+    Expr::Call { callee: Box::new(Expr::Ident(native.to_string(), span)), args, span }
+}
+
+fn method(name: &str, param: Param, returns: &str, body: Expr, span: Span) -> Function {
+    Function {
+        name: name.to_string(),
+        generics: Vec::new(),
+        params: vec![param],
+        return_type: Some(named(returns, span)),
+        effects: Vec::new(),
+        body: Block { stmts: vec![Stmt::Return(Some(body), span)], span },
+        visibility: Visibility::Public,
+        attributes: Vec::new(),
+        doc: None,
+        span,
+    }
+}
@@ -0,0 +1,239 @@
+//! Compile-time reflection builtins
+//!
+//! Inside comptime code, `fields_of(Type)`, `variants_of(Enum)` and
+//! `functions_in_module()` describe the program's own declarations as plain
+//! values, so serializers, ORMs and CLI parsers can be generated in user space:
+//!
+//! ```ignore
+//! let columns = comptime:
+//!     let names = []
+//!     for field in fields_of(User):
+//!         names.push(field.name)
+//!     return names
+//! ```
+//!
+//! The calls are expanded into literals before the comptime code runs, which
+//! is why the argument has to name the type directly. The values are structs:
+//!
+//! - `FieldInfo { name, ty, public }` for struct fields and variant payloads
+//!   (tuple payloads are named `0`, `1`, ...)
+//! - `VariantInfo { name, fields }`
+//! - `FunctionInfo { name, params, return_type, public, attributes }`, with
+//!   `params` as `ParamInfo { name, ty }` and `return_type` empty for none
+//!
+//! The build itself is described the same way: `target()` is the name of the
+//! target being compiled (`"wasm"`, `"spirv"`, ...), `word_size()` its pointer
+//! width in bits and `features()` the enabled `--feature`s, so one source file
+//! can pick target-specific constants:
+//!
+//! ```ignore
+//! const PRECISION: String = comptime:
+//!     if target() == "spirv":
+//!         return "mediump"
+//!     return "highp"
+//! ```
+
+use std::collections::HashMap;
+
+use crate::ast::visit::{walk_expr_mut, VisitorMut};
+use crate::ast::*;
+use crate::error::{KainError, KainResult};
+use crate::lsp::format_type;
+use crate::span::Span;
+use crate::CompileTarget;
+
+const BUILTINS: &[&str] = &["fields_of", "variants_of", "functions_in_module", "target", "word_size", "features"];
+
+/// The declarations reflection can see, snapshotted after `@cfg` resolution,
+/// and the build they are compiled for
+pub(super) struct Reflection {
+    structs: HashMap<String, Struct>,
+    enums: HashMap<String, Enum>,
+    functions: Vec<Function>,
+    target: CompileTarget,
+    features: Vec<String>,
+}
+
+impl Reflection {
+    pub(super) fn new(items: &[Item], target: CompileTarget, features: &[String]) -> Self {
+        let mut reflection = Reflection {
+            structs: HashMap::new(),
+            enums: HashMap::new(),
+            functions: Vec::new(),
+            target,
+            features: features.to_vec(),
+        };
+        for item in items {
+            match item {
+                Item::Struct(s) => {
+                    reflection.structs.insert(s.name.clone(), s.clone());
+                }
+                Item::Enum(e) => {
+                    reflection.enums.insert(e.name.clone(), e.clone());
+                }
+                Item::Function(f) => reflection.functions.push(Function {
+                    body: Block { stmts: Vec::new(), span: f.body.span },
+                    ..f.clone()
+                }),
+                _ => {}
+            }
+        }
+        reflection
+    }
+
+    /// Replace reflection calls in `expr` with the values they describe
+    pub(super) fn expand_expr(&self, expr: &mut Expr) -> KainResult<()> {
+        let mut expander = Expander { reflection: self, error: None };
+        expander.visit_expr_mut(expr);
+        expander.error.map_or(Ok(()), Err)
+    }
+
+    pub(super) fn expand_block(&self, block: &mut Block) -> KainResult<()> {
+        let mut expander = Expander { reflection: self, error: None };
+        expander.visit_block_mut(block);
+        expander.error.map_or(Ok(()), Err)
+    }
+
+    fn call(&self, name: &str, args: &[CallArg], span: Span) -> KainResult<Expr> {
+        match name {
+            "fields_of" => {
+                let ty = type_arg(name, args, span)?;
+                let def = self.structs.get(ty).ok_or_else(|| {
+                    KainError::type_error(format!("fields_of: no struct named '{}'", ty), span)
+                })?;
+                Ok(Expr::Array(def.fields.iter().map(|f| field_info(f, span)).collect(), span))
+            }
+            "variants_of" => {
+                let ty = type_arg(name, args, span)?;
+                let def = self.enums.get(ty).ok_or_else(|| {
+                    KainError::type_error(format!("variants_of: no enum named '{}'", ty), span)
+                })?;
+                Ok(Expr::Array(def.variants.iter().map(|v| variant_info(v, span)).collect(), span))
+            }
+            _ if !args.is_empty() => Err(KainError::type_error(format!("{} takes no arguments", name), span)),
+            "target" => Ok(string(self.target.cfg_names()[0], span)),
+            "word_size" => Ok(Expr::Int(self.target.word_size() as i64, span)),
+            "features" => Ok(Expr::Array(self.features.iter().map(|f| string(f, span)).collect(), span)),
+            _ => Ok(Expr::Array(self.functions.iter().map(|f| function_info(f, span)).collect(), span)),
+        }
+    }
+}
+
+struct Expander<'a> {
+    reflection: &'a Reflection,
+    error: Option<KainError>,
+}
+
+impl VisitorMut for Expander<'_> {
+    fn visit_expr_mut(&mut self, expr: &mut Expr) {
+        if self.error.is_some() {
+            return;
+        }
+        if let Expr::Call { callee, args, span } = expr {
+            if let Expr::Ident(name, _) = &**callee {
+                // A user function with the same name wins over the builtin
+                let shadowed = self.reflection.functions.iter().any(|f| &f.name == name);
+                if BUILTINS.contains(&name.as_str()) && !shadowed {
+                    match self.reflection.call(name, args, *span) {
+                        Ok(value) => *expr = value,
+                        Err(e) => self.error = Some(e),
+                    }
+                    return;
+                }
+            }
+        }
+        walk_expr_mut(self, expr);
+    }
+}
+
+/// The single type name passed to `fields_of` / `variants_of`
+fn type_arg<'a>(builtin: &str, args: &'a [CallArg], span: Span) -> KainResult<&'a str> {
+    match args {
+        [CallArg { name: None, value: Expr::Ident(ty, _) | Expr::String(ty, _), .. }] => Ok(ty),
+        _ => Err(KainError::type_error(format!("{} takes a single type name, e.g. {}(Point)", builtin, builtin), span)),
+    }
+}
+
+fn info(name: &str, fields: Vec<(&str, Expr)>, span: Span) -> Expr {
+    Expr::Struct {
+        name: name.to_string(),
+        fields: fields.into_iter().map(|(k, v)| (k.to_string(), v)).collect(),
+        base: None,
+        span,
+    }
+}
+
+fn string(s: &str, span: Span) -> Expr {
+    Expr::String(s.to_string(), span)
+}
+
+fn field_info(field: &Field, span: Span) -> Expr {
+    info("FieldInfo", vec![
+        ("name", string(&field.name, span)),
+        ("ty", string(&format_type(&field.ty), span)),
+        ("public", Expr::Bool(field.visibility == Visibility::Public, span)),
+    ], span)
+}
+
+fn variant_info(variant: &Variant, span: Span) -> Expr {
+    let fields = match &variant.fields {
+        VariantFields::Unit => Vec::new(),
+        VariantFields::Tuple(types) => types
+            .iter()
+            .enumerate()
+            .map(|(i, ty)| info("FieldInfo", vec![
+                ("name", string(&i.to_string(), span)),
+                ("ty", string(&format_type(ty), span)),
+                ("public", Expr::Bool(true, span)),
+            ], span))
+            .collect(),
+        VariantFields::Struct(fields) => fields.iter().map(|f| field_info(f, span)).collect(),
+    };
+    info("VariantInfo", vec![
+        ("name", string(&variant.name, span)),
+        ("fields", Expr::Array(fields, span)),
+    ], span)
+}
+
+fn function_info(function: &Function, span: Span) -> Expr {
+    let params = function.params
+        .iter()
+        .map(|p| info("ParamInfo", vec![
+            ("name", string(&p.name, span)),
+            ("ty", string(&format_type(&p.ty), span)),
+        ], span))
+        .collect();
+    let attributes = function.attributes.iter().map(|a| string(&a.name, span)).collect();
+    info("FunctionInfo", vec![
+        ("name", string(&function.name, span)),
+        ("params", Expr::Array(params, span)),
+        ("return_type", string(&function.return_type.as_ref().map(format_type).unwrap_or_default(), span)),
+        ("public", Expr::Bool(function.visibility == Visibility::Public, span)),
+        ("attributes", Expr::Array(attributes, span)),
+    ], span)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{eval_snippet, CompileOptions};
+
+    #[test]
+    fn test_reflection_describes_declarations() {
+        let source = "struct User:\n    id: Int\n    name: String\n\nenum Shape:\n    Circle(Float)\n    Empty\n\n@route\nfn index(limit: Int) -> String:\n    return \"ok\"\n\nfn main():\n    let out = comptime:\n        let s = \"\"\n        for f in fields_of(User):\n            s = s + f.name + \":\" + f.ty + \" \"\n        for v in variants_of(Shape):\n            s = s + v.name + \"/\" + str(len(v.fields)) + \" \"\n        for f in functions_in_module():\n            s = s + f.name + \"@\" + str(len(f.attributes)) + \" \"\n        return s\n    println(out)\n";
+        let result = eval_snippet(source, &CompileOptions::default());
+        assert!(result.diagnostics.is_empty(), "{:?}", result.diagnostics);
+        assert_eq!(result.stdout.trim(), "id:Int name:String Circle/1 Empty/0 index@1 main@0");
+
+        let missing = eval_snippet("fn main():\n    let n = comptime:\n        return len(fields_of(Nope))\n", &CompileOptions::default());
+        assert!(missing.diagnostics[0].to_string().contains("fields_of: no struct named 'Nope'"));
+    }
+
+    #[test]
+    fn test_reflection_describes_the_build() {
+        let source = "const SETUP: String = comptime:\n    let s = target() + \"/\" + str(word_size())\n    for f in features():\n        s = s + \" \" + f\n    return s\n\nfn main():\n    println(SETUP)\n";
+        let options = CompileOptions { features: vec!["fast".to_string()], ..Default::default() };
+        let result = eval_snippet(source, &options);
+        assert!(result.diagnostics.is_empty(), "{:?}", result.diagnostics);
+        assert_eq!(result.stdout.trim(), format!("interpret/{} fast", usize::BITS));
+    }
+}
@@ -0,0 +1,265 @@
+//! `query!`: SQL checked against the schema at compile time
+//!
+//! ```ignore
+//! struct User:
+//!     id: Int
+//!     name: String
+//!
+//! fn find(conn: SqliteConnection, id: Int) -> Array<User> with IO, Db:
+//!     return query!(conn, User, "SELECT id, name FROM users WHERE id = ?", id)
+//! ```
+//!
+//! The schema is the DDL passed with `--schema` (or `schema` under `[build]`
+//! in KAIN.toml). Every `query!` is prepared against an empty database built
+//! from it, so unknown tables or columns and a wrong number of `?` arguments
+//! are compile errors. Result columns take the types the schema declares for
+//! the table columns they come from, as SQLite reports them for the prepared
+//! statement; with a struct after the connection the columns must match its
+//! fields, otherwise a `QueryRow<n>` struct is generated for the result.
+//!
+//! The query runs on the connection given as the first argument.
+
+use crate::ast::visit::{walk_expr_mut, VisitorMut};
+use crate::ast::*;
+use crate::error::{KainError, KainResult};
+use crate::lsp::format_type;
+use crate::span::Span;
+
+/// Expand every `query!` in `items`, appending the row structs it generates
+pub(super) fn expand_queries(items: &mut Vec<Item>, schema: Option<&str>) -> KainResult<()> {
+    let structs = items
+        .iter()
+        .filter_map(|item| match item {
+            Item::Struct(s) => Some(s.clone()),
+            _ => None,
+        })
+        .collect();
+    let mut expander = Expander { schema_source: schema, schema: None, structs, generated: Vec::new(), error: None };
+    for item in items.iter_mut() {
+        expander.visit_item_mut(item);
+    }
+    if let Some(e) = expander.error {
+        return Err(e);
+    }
+    items.extend(expander.generated.into_iter().map(Item::Struct));
+    Ok(())
+}
+
+/// An empty database with the schema applied
+struct Schema {
+    conn: rusqlite::Connection,
+}
+
+impl Schema {
+    fn load(ddl: &str, span: Span) -> KainResult<Self> {
+        let load = || -> rusqlite::Result<Schema> {
+            let conn = rusqlite::Connection::open_in_memory()?;
+            conn.execute_batch(ddl)?;
+            Ok(Schema { conn })
+        };
+        load().map_err(|e| KainError::type_error(format!("query!: invalid schema: {}", e), span))
+    }
+
+    /// Prepare `sql` without running it, returning its result columns with their
+    /// KAIN types: those of the table columns they come from (`sqlite3_column_decltype`),
+    /// `None` for computed columns
+    fn check(&self, sql: &str, params: usize, span: Span) -> KainResult<Vec<(String, Option<String>)>> {
+        let stmt = self.conn.prepare(sql).map_err(|e| KainError::type_error(format!("query!: {}", e), span))?;
+        if stmt.parameter_count() != params {
+            return Err(KainError::type_error(
+                format!("query!: the statement takes {} arguments for its `?` placeholders, found {}", stmt.parameter_count(), params),
+                span,
+            ));
+        }
+        Ok(stmt
+            .columns()
+            .into_iter()
+            .map(|column| (column.name().to_string(), column.decl_type().map(kain_type)))
+            .collect())
+    }
+}
+
+/// SQLite's type affinity rules, mapped onto KAIN types
+fn kain_type(declared: &str) -> String {
+    let declared = declared.to_uppercase();
+    let ty = if declared.contains("INT") {
+        "Int"
+    } else if ["CHAR", "CLOB", "TEXT"].iter().any(|t| declared.contains(t)) {
+        "String"
+    } else if ["REAL", "FLOA", "DOUB"].iter().any(|t| declared.contains(t)) {
+        "Float"
+    } else if declared.contains("BOOL") {
+        "Bool"
+    } else {
+        "Any"
+    };
+    ty.to_string()
+}
+
+struct Expander<'a> {
+    schema_source: Option<&'a str>,
+    schema: Option<Schema>,
+    structs: Vec<Struct>,
+    generated: Vec<Struct>,
+    error: Option<KainError>,
+}
+
+impl Expander<'_> {
+    fn expand(&mut self, args: &[Expr], span: Span) -> KainResult<Expr> {
+        let (conn, row, sql, params) = match args {
+            [conn, Expr::Ident(row, _), Expr::String(sql, _), params @ ..] => (conn, Some(row.as_str()), sql, params),
+            [conn, Expr::String(sql, _), params @ ..] => (conn, None, sql, params),
+            _ => {
+                return Err(KainError::type_error(
+                    "query! expects a connection and a SQL string literal, optionally with a struct name between them: query!(conn, User, \"SELECT ...\", args)",
+                    span,
+                ))
+            }
+        };
+        let schema = match &mut self.schema {
+            Some(schema) => schema,
+            slot @ None => {
+                let ddl = self.schema_source.ok_or_else(|| {
+                    KainError::type_error("query! needs a schema: pass --schema or set `schema` under [build] in KAIN.toml", span)
+                })?;
+                slot.insert(Schema::load(ddl, span)?)
+            }
+        };
+        let columns = schema.check(sql, params.len(), span)?;
+
+        let row = match row {
+            Some(name) => self.check_row_struct(name, &columns, span)?,
+            None => self.generate_row_struct(&columns, span)?,
+        };
+
+        let call = |name: &str, args: Vec<Expr>| Expr::Call {
+            callee: Box::new(Expr::Ident(name.to_string(), span)),
+            args: args.into_iter().map(|value| CallArg { name: None, value, spread: false, span }).collect(),
+            span,
+        };
+        Ok(call("sqlite_query", vec![
+            conn.clone(),
+            Expr::String(sql.clone(), span),
+            Expr::Array(params.to_vec(), span),
+            Expr::String(row, span),
+        ]))
+    }
+
+    /// Every column must be a field of `name` with a compatible type, and every field a column
+    fn check_row_struct(&self, name: &str, columns: &[(String, Option<String>)], span: Span) -> KainResult<String> {
+        let def = self.structs.iter().find(|s| s.name == name).ok_or_else(|| {
+            KainError::type_error(format!("query!: no struct named '{}'", name), span)
+        })?;
+        for (column, ty) in columns {
+            let field = def.fields.iter().find(|f| &f.name == column).ok_or_else(|| {
+                KainError::type_error(format!("query!: column '{}' is not a field of {}", column, name), span)
+            })?;
+            let field_ty = format_type(&field.ty);
+            if let Some(ty) = ty {
+                if ty != "Any" && field_ty != *ty && field_ty != format!("Option<{}>", ty) {
+                    return Err(KainError::type_error(
+                        format!("query!: column '{}' is {} but {}.{} is {}", column, ty, name, column, field_ty),
+                        span,
+                    ));
+                }
+            }
+        }
+        if let Some(field) = def.fields.iter().find(|f| f.default.is_none() && !columns.iter().any(|(c, _)| *c == f.name)) {
+            return Err(KainError::type_error(
+                format!("query!: {}.{} has no matching column in the result", name, field.name),
+                span,
+            ));
+        }
+        Ok(name.to_string())
+    }
+
+    fn generate_row_struct(&mut self, columns: &[(String, Option<String>)], span: Span) -> KainResult<String> {
+        let name = format!("QueryRow{}", self.generated.len());
+        let mut fields = Vec::with_capacity(columns.len());
+        for (column, ty) in columns {
+            let ty = ty.clone().ok_or_else(|| {
+                KainError::type_error(
+                    format!("query!: cannot infer the type of column '{}', name a struct for the rows: query!(Row, ...)", column),
+                    span,
+                )
+            })?;
+            fields.push(Field {
+                name: column.clone(),
+                ty: Type::Named { name: ty, generics: Vec::new(), span },
+                visibility: Visibility::Public,
+                default: None,
+                weak: false,
+                span,
+            });
+        }
+        self.generated.push(Struct {
+            name: name.clone(),
+            generics: Vec::new(),
+            fields,
+            visibility: Visibility::Public,
+            attributes: Vec::new(),
+            doc: None,
+            span,
+        });
+        Ok(name)
+    }
+}
+
+impl VisitorMut for Expander<'_> {
+    fn visit_expr_mut(&mut self, expr: &mut Expr) {
+        if self.error.is_some() {
+            return;
+        }
+        if let Expr::MacroCall { name, args, span } = expr {
+            if name == "query" {
+                match self.expand(args, *span) {
+                    Ok(call) => *expr = call,
+                    Err(e) => self.error = Some(e),
+                }
+                return;
+            }
+        }
+        walk_expr_mut(self, expr);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{eval_snippet, CompileOptions};
+
+    #[test]
+    fn test_query_is_checked_against_schema() {
+        let options = CompileOptions {
+            sql_schema: Some("CREATE TABLE users (id INTEGER PRIMARY KEY, name TEXT);".to_string()),
+            ..Default::default()
+        };
+        let source = "struct User:\n    id: Int\n    name: String\n\nfn main():\n    let conn = sqlite_open(\":memory:\")\n    sqlite_exec(conn, \"CREATE TABLE users (id INTEGER PRIMARY KEY, name TEXT)\")\n    sqlite_exec(conn, \"INSERT INTO users VALUES (1, 'ada'), (2, 'bob')\")\n    for u in query!(conn, User, \"SELECT id, name FROM users WHERE id = ?\", 2):\n        println(u.name)\n    for r in query!(conn, \"SELECT id FROM users\"):\n        println(str(r.id))\n";
+        let result = eval_snippet(source, &options);
+        assert!(result.diagnostics.is_empty(), "{:?}", result.diagnostics);
+        let lines: Vec<&str> = result.stdout.lines().map(str::trim).collect();
+        assert_eq!(lines, ["bob", "1", "2"]);
+
+        let check = |query: &str| eval_snippet(&source.replace("SELECT id, name FROM users WHERE id = ?\", 2", query), &options);
+        assert!(check("SELECT id, email FROM users\"").diagnostics[0].to_string().contains("no such column: email"));
+        assert!(check("SELECT id, name FROM users\", 2").diagnostics[0].to_string().contains("takes 0 arguments for its `?` placeholders, found 1"));
+        assert!(check("SELECT name, id FROM users WHERE 0\"").diagnostics.is_empty());
+        assert!(check("SELECT id FROM users\"").diagnostics[0].to_string().contains("User.name has no matching column"));
+
+        // Types come from the columns the schema declares, whatever the result calls them
+        let err = check("SELECT name AS id, name FROM users\"").diagnostics[0].to_string();
+        assert!(err.contains("column 'id' is String but User.id is Int"), "{}", err);
+        let renamed = source.replace("query!(conn, \"SELECT id FROM users\")", "query!(conn, \"SELECT name AS id FROM users\")");
+        let result = eval_snippet(&renamed.replace("str(r.id)", "r.id"), &options);
+        assert!(result.diagnostics.is_empty(), "{:?}", result.diagnostics);
+
+        // Each query runs on the connection it names, not the one opened last
+        let two = source.replace("    for u in", "    let other = sqlite_open(\":memory:\")\n    sqlite_exec(other, \"CREATE TABLE users (id INTEGER PRIMARY KEY, name TEXT)\")\n    for u in");
+        let result = eval_snippet(&two, &options);
+        assert!(result.diagnostics.is_empty(), "{:?}", result.diagnostics);
+        let lines: Vec<&str> = result.stdout.lines().map(str::trim).collect();
+        assert_eq!(lines, ["bob", "1", "2"]);
+        let result = eval_snippet(&two.replace("query!(conn, User", "query!(other, User"), &options);
+        let lines: Vec<&str> = result.stdout.lines().map(str::trim).collect();
+        assert_eq!(lines, ["1", "2"]);
+    }
+}
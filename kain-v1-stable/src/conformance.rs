@@ -0,0 +1,250 @@
+//! Cross-backend conformance suite
+//!
+//! A conformance test is a program under `conformance/` with a `.out` file
+//! next to it holding what it prints. Every backend runs the program and its
+//! output is compared against that file, so backends that drift apart on
+//! loops, strings or floats are noticed:
+//!
+//! * `interpret` runs in process
+//! * `js` is compiled and run with `node`
+//! * `wasm` is compiled and instantiated by `node`, which supplies the `host`
+//!   imports the module prints through
+//! * `llvm` is compiled, linked with `clang` against the bundled C runtime the
+//!   way `kain build` links it, and run natively
+//!
+//! A backend whose tool is not installed is skipped rather than failed. A
+//! `// BACKENDS: interpret, js` line limits a program to the backends named.
+//! Programs declare `pub fn main()` so every backend exports their entry point.
+//! Tests run with `kain dev conformance`.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::error::KainResult;
+use crate::packager;
+use crate::toolchain::Toolchain;
+use crate::{compile, eval_snippet, CompileOptions, CompileTarget};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Backend {
+    Interpret,
+    Js,
+    Wasm,
+    Llvm,
+}
+
+impl Backend {
+    pub const ALL: [Backend; 4] = [Backend::Interpret, Backend::Js, Backend::Wasm, Backend::Llvm];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Backend::Interpret => "interpret",
+            Backend::Js => "js",
+            Backend::Wasm => "wasm",
+            Backend::Llvm => "llvm",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Backend> {
+        Backend::ALL.into_iter().find(|b| b.name() == name)
+    }
+}
+
+/// How one program fared on one backend
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Outcome {
+    Pass,
+    /// Compiling or running failed, or the output differs from the expected
+    Fail(String),
+    /// The tool the backend runs under is not installed
+    Skipped(String),
+}
+
+/// Run the program at `path` on each of `backends` it does not opt out of
+pub fn run_file(path: &Path, backends: &[Backend]) -> KainResult<Vec<(Backend, Outcome)>> {
+    let source = fs::read_to_string(path)?;
+    let expected = fs::read_to_string(path.with_extension("out"))?;
+    let allowed = allowed_backends(&source);
+    // One directory per call, since runs in the same process remove theirs when done
+    static RUNS: AtomicUsize = AtomicUsize::new(0);
+    let run_id = RUNS.fetch_add(1, Ordering::Relaxed);
+    let scratch = std::env::temp_dir().join(format!("kain-conformance-{}-{}", std::process::id(), run_id));
+    fs::create_dir_all(&scratch)?;
+
+    let stem = path.file_stem().and_then(|s| s.to_str()).unwrap_or("program");
+    let results = backends
+        .iter()
+        .filter(|b| allowed.as_ref().map_or(true, |allowed| allowed.contains(b)))
+        .map(|&backend| {
+            let outcome = match run(backend, &source, &scratch.join(stem)) {
+                Ok(Some(actual)) => compare(&expected, &actual),
+                Ok(None) => Outcome::Skipped(format!("{} is not installed", tool(backend))),
+                Err(e) => Outcome::Fail(e),
+            };
+            (backend, outcome)
+        })
+        .collect();
+    let _ = fs::remove_dir_all(&scratch);
+    Ok(results)
+}
+
+/// The `.kn` programs under `paths` that have an expected output
+pub fn collect_programs(paths: &[PathBuf]) -> Vec<PathBuf> {
+    crate::filecheck::collect_tests(paths)
+        .into_iter()
+        .filter(|p| p.with_extension("out").exists())
+        .collect()
+}
+
+/// Backends a `// BACKENDS:` line restricts the program to, if it has one
+fn allowed_backends(source: &str) -> Option<Vec<Backend>> {
+    source.lines().find_map(|line| {
+        let names = line.trim().strip_prefix("//")?.trim().strip_prefix("BACKENDS:")?;
+        Some(names.split(',').filter_map(|n| Backend::from_name(n.trim())).collect())
+    })
+}
+
+fn tool(backend: Backend) -> &'static str {
+    match backend {
+        Backend::Interpret => "kain",
+        Backend::Js | Backend::Wasm => "node",
+        Backend::Llvm => "clang",
+    }
+}
+
+/// What the program prints on `backend`; `None` when the tool it needs is missing.
+/// Files go to `base` with an extension per backend.
+fn run(backend: Backend, source: &str, base: &Path) -> Result<Option<String>, String> {
+    let build = |target| compile(source, target).map_err(|e| format!("compile error: {}", e));
+    match backend {
+        Backend::Interpret => {
+            let result = eval_snippet(source, &CompileOptions::default());
+            match result.diagnostics.first() {
+                Some(e) => Err(format!("runtime error: {}", e)),
+                None => Ok(Some(result.stdout)),
+            }
+        }
+        Backend::Js => {
+            let js = build(CompileTarget::Js)?;
+            let path = base.with_extension("mjs");
+            let mut program = String::from(JS_PRELUDE);
+            program.push_str(&String::from_utf8_lossy(&js));
+            program.push_str("\nmain();\n");
+            write(&path, program.as_bytes())?;
+            execute(Command::new("node").arg(&path))
+        }
+        Backend::Wasm => {
+            let wasm = build(CompileTarget::Wasm)?;
+            let module = base.with_extension("wasm");
+            let host = base.with_extension("host.mjs");
+            write(&module, &wasm)?;
+            write(&host, WASM_HOST.as_bytes())?;
+            execute(Command::new("node").arg(&host).arg(&module))
+        }
+        Backend::Llvm => {
+            if Toolchain::discover().is_err() {
+                return Ok(None);
+            }
+            let ir = build(CompileTarget::Llvm)?;
+            let ll = base.with_extension("ll");
+            let exe = base.with_extension("exe");
+            write(&ll, &ir)?;
+            packager::link_executable(&ll, &exe, &[]).map_err(|e| format!("link error: {}", e))?;
+            execute(&mut Command::new(&exe))
+        }
+    }
+}
+
+fn write(path: &Path, contents: &[u8]) -> Result<(), String> {
+    fs::write(path, contents).map_err(|e| format!("cannot write {}: {}", path.display(), e))
+}
+
+/// Stdout of a successful run; `None` when the program cannot be started
+fn execute(command: &mut Command) -> Result<Option<String>, String> {
+    let Ok(out) = command.output() else {
+        return Ok(None);
+    };
+    if !out.status.success() {
+        return Err(format!("exited with {}: {}", out.status, String::from_utf8_lossy(&out.stderr).trim()));
+    }
+    Ok(Some(String::from_utf8_lossy(&out.stdout).into_owned()))
+}
+
+/// Pass, or the first line where `actual` differs from `expected`; trailing
+/// whitespace is not compared, since the interpreter ends each value with a space
+fn compare(expected: &str, actual: &str) -> Outcome {
+    let expected: Vec<&str> = expected.lines().map(str::trim_end).collect();
+    let actual: Vec<&str> = actual.lines().map(str::trim_end).collect();
+    for i in 0..expected.len().max(actual.len()) {
+        let (want, got) = (expected.get(i), actual.get(i));
+        if want != got {
+            return Outcome::Fail(format!(
+                "line {}: expected {}, got {}",
+                i + 1,
+                want.map_or("end of output".to_string(), |l| format!("`{}`", l)),
+                got.map_or("end of output".to_string(), |l| format!("`{}`", l)),
+            ));
+        }
+    }
+    Outcome::Pass
+}
+
+/// What the JS backend leaves to its host
+const JS_PRELUDE: &str = "const println = (...args) => console.log(args.join(' '));\nconst print = (...args) => process.stdout.write(args.join(' '));\n\n";
+
+/// Instantiates the module named on the command line and calls its `main`
+const WASM_HOST: &str = r#"import { readFileSync } from 'node:fs';
+
+let memory;
+const text = (ptr, len) => new TextDecoder().decode(new Uint8Array(memory.buffer, ptr, len));
+const known = {
+    print_i64: (v) => console.log(String(v)),
+    print_f64: (v) => console.log(String(v)),
+    print_str: (ptr, len) => console.log(text(ptr, len)),
+    print_bool: (v) => console.log(String(v !== 0)),
+    time_now: () => BigInt(Date.now()),
+};
+const host = new Proxy(known, {
+    get: (target, name) => target[name] ?? (() => { throw new Error(`host.${String(name)} is not available to conformance tests`); }),
+});
+const { instance } = await WebAssembly.instantiate(readFileSync(process.argv[2]), { host });
+memory = instance.exports.memory;
+instance.exports.main();
+"#;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_corpus_passes_on_the_interpreter() {
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("conformance");
+        let programs = collect_programs(&[dir]);
+        assert!(!programs.is_empty());
+        for program in programs {
+            for (backend, outcome) in run_file(&program, &[Backend::Interpret]).unwrap() {
+                assert_eq!(outcome, Outcome::Pass, "{} on {}", program.display(), backend.name());
+            }
+        }
+    }
+
+    #[test]
+    fn test_llvm_programs_link_the_runtime() {
+        let program = Path::new(env!("CARGO_MANIFEST_DIR")).join("conformance").join("loops.kn");
+        for (backend, outcome) in run_file(&program, &[Backend::Llvm]).unwrap() {
+            // Machines without clang skip the backend
+            if !matches!(outcome, Outcome::Skipped(_)) {
+                assert_eq!(outcome, Outcome::Pass, "{} on {}", program.display(), backend.name());
+            }
+        }
+    }
+
+    #[test]
+    fn test_first_difference_is_reported() {
+        assert_eq!(compare("1\n2\n", "1 \n2 \n"), Outcome::Pass);
+        assert_eq!(compare("1\n2.5\n", "1\n2.500000\n"), Outcome::Fail("line 2: expected `2.5`, got `2.500000`".to_string()));
+        assert_eq!(compare("1\n", ""), Outcome::Fail("line 1: expected `1`, got end of output".to_string()));
+    }
+}
@@ -0,0 +1,124 @@
+//! Global constant evaluation order
+//!
+//! A const may use consts declared after it, directly or through the functions
+//! its value calls. This builds the dependency graph among a program's
+//! top-level consts, rejects cycles, and gives the order comptime and the
+//! interpreter evaluate them in.
+
+use crate::ast::*;
+use crate::ast::visit::{walk_expr, Visitor};
+use crate::error::{KainError, KainResult};
+use std::collections::{HashMap, HashSet};
+
+/// Indices into `consts`, each after every const it depends on and otherwise
+/// in declaration order; `functions` are the program's, which a const's value may call
+pub fn order(consts: &[&Const], functions: &[&Function]) -> KainResult<Vec<usize>> {
+    let mut graph = Graph {
+        consts,
+        names: consts.iter().enumerate().map(|(i, c)| (c.name.as_str(), i)).collect(),
+        functions: functions.iter().map(|f| (f.name.as_str(), *f)).collect(),
+        done: HashSet::new(),
+        path: Vec::new(),
+        order: Vec::new(),
+    };
+    for i in 0..consts.len() {
+        graph.visit(i)?;
+    }
+    Ok(graph.order)
+}
+
+struct Graph<'a> {
+    consts: &'a [&'a Const],
+    names: HashMap<&'a str, usize>,
+    functions: HashMap<&'a str, &'a Function>,
+    done: HashSet<usize>,
+    /// Consts being visited, outermost first
+    path: Vec<usize>,
+    order: Vec<usize>,
+}
+
+impl Graph<'_> {
+    fn visit(&mut self, i: usize) -> KainResult<()> {
+        if self.done.contains(&i) {
+            return Ok(());
+        }
+        if let Some(start) = self.path.iter().position(|&p| p == i) {
+            let chain: Vec<&str> = self.path[start..]
+                .iter()
+                .chain(std::iter::once(&i))
+                .map(|&p| self.consts[p].name.as_str())
+                .collect();
+            return Err(KainError::type_error(
+                format!("Cycle among constants: {}", chain.join(" -> ")),
+                self.consts[i].span,
+            ));
+        }
+        self.path.push(i);
+        for dep in self.dependencies(&self.consts[i].value) {
+            self.visit(dep)?;
+        }
+        self.path.pop();
+        self.done.insert(i);
+        self.order.push(i);
+        Ok(())
+    }
+
+    /// Consts `expr` uses, looking through the bodies of the functions it calls
+    fn dependencies(&self, expr: &Expr) -> Vec<usize> {
+        let mut names = Names::default();
+        names.visit_expr(expr);
+        let mut pending = names.0;
+        let mut called = HashSet::new();
+        let mut deps = Vec::new();
+        while let Some(name) = pending.pop() {
+            if let Some(&i) = self.names.get(name.as_str()) {
+                if !deps.contains(&i) {
+                    deps.push(i);
+                }
+            } else if let Some(function) = self.functions.get(name.as_str()) {
+                if called.insert(name) {
+                    let mut names = Names::default();
+                    names.visit_block(&function.body);
+                    pending.extend(names.0);
+                }
+            }
+        }
+        deps.sort_unstable();
+        deps
+    }
+}
+
+/// Every identifier an expression mentions
+#[derive(Default)]
+struct Names(Vec<String>);
+
+impl Visitor for Names {
+    fn visit_expr(&mut self, expr: &Expr) {
+        if let Expr::Ident(name, _) = expr {
+            self.0.push(name.clone());
+        }
+        walk_expr(self, expr);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{compile, eval_snippet, CompileOptions, CompileTarget};
+
+    #[test]
+    fn test_consts_fold_in_dependency_order() {
+        let source = "const B: Int = A * 2\nconst A: Int = half()\nconst NAME: String = \"kain\"\n\nfn half() -> Int:\n    return 21\n\nfn main():\n    println(B, NAME)\n";
+        let result = eval_snippet(source, &CompileOptions::default());
+        assert!(result.diagnostics.is_empty(), "{:?}", result.diagnostics);
+        assert_eq!(result.stdout.trim(), "42 kain");
+
+        let rust = String::from_utf8(compile(source, CompileTarget::Rust).unwrap()).unwrap();
+        assert!(rust.contains("const B: i64 = 42;") && rust.contains("const NAME: &str = \"kain\";"));
+        let js = String::from_utf8(compile(source, CompileTarget::Js).unwrap()).unwrap();
+        assert!(js.contains("const B = 42;"));
+        compile("pub const LIMIT: Int = 3 + 4\n\nfn main() -> Int:\n    return LIMIT\n", CompileTarget::Wasm).unwrap();
+
+        let cycle = compile("const A: Int = B\nconst B: Int = twice()\n\nfn twice() -> Int:\n    return A * 2\n", CompileTarget::Js).unwrap_err();
+        assert!(cycle.to_string().contains("Cycle among constants: A -> B -> A"), "{}", cycle);
+    }
+}
@@ -0,0 +1,358 @@
+//! Crash reports for compiler panics
+//!
+//! A panic in the compiler is a bug, and whoever hit it often can't share the
+//! code that triggered it. When one reaches the top of `kain`, the report
+//! written by [`write_report`] holds the panic, the phase and source position
+//! the compiler was at, a backtrace, and a reproducer: the input cut down by
+//! delta debugging, dropping items and then the statements inside them for
+//! as long as the compiler still panics at the same place. The report is a
+//! local file; nothing is sent anywhere.
+
+use std::cell::{Cell, RefCell};
+use std::ops::Range;
+use std::path::PathBuf;
+use std::sync::{mpsc, Arc, Mutex, MutexGuard};
+use std::time::Duration;
+
+use crate::span::Span;
+use crate::vfs::{SourceFile, SourceMap};
+use crate::{CompileOptions, CompileTarget, Phase};
+
+/// Where a report asks to be filed
+pub const ISSUES_URL: &str = "https://github.com/ephemara/kain-lang/issues/new";
+
+/// Compiles tried while minimizing, so a slow compiler doesn't keep the user waiting long
+const MAX_ATTEMPTS: usize = 500;
+
+/// How long one candidate may compile before it counts as not crashing
+const ATTEMPT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// A panic, as the hook [`install`] sets saw it
+#[derive(Debug, Clone)]
+pub struct Panic {
+    pub message: String,
+    /// Where in the compiler it was raised, `file:line:column`
+    pub location: Option<String>,
+    pub phase: Option<Phase>,
+    /// The item or statement being worked on, see [`at`]
+    pub span: Option<Span>,
+    pub backtrace: String,
+}
+
+/// The input cut down by [`minimize`]
+#[derive(Debug, Clone)]
+pub struct Reproducer {
+    pub source: String,
+    pub original_lines: usize,
+    /// Compiles it took
+    pub attempts: usize,
+}
+
+/// What is being compiled, for the reproducer
+#[derive(Clone)]
+struct Input {
+    file: Arc<SourceFile>,
+    target: Option<CompileTarget>,
+    options: CompileOptions,
+}
+
+static INPUT: Mutex<Option<Input>> = Mutex::new(None);
+static PHASE: Mutex<Option<Phase>> = Mutex::new(None);
+static PANIC: Mutex<Option<Panic>> = Mutex::new(None);
+
+thread_local! {
+    static SPAN: Cell<Option<Span>> = const { Cell::new(None) };
+    /// Set on the threads compiling candidate reproducers, whose panics are expected
+    static IN_ATTEMPT: Cell<bool> = const { Cell::new(false) };
+    /// Where the last panic on a candidate's thread was raised
+    static ATTEMPT_PANIC: RefCell<Option<String>> = const { RefCell::new(None) };
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Record panics on the thread called `thread` for [`take_panic`], instead of
+/// printing them; panics on other threads are printed as before
+pub fn install(thread: &'static str) {
+    let previous = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        let message = panic_message(info.payload());
+        let location = info.location().map(|l| l.to_string());
+        if IN_ATTEMPT.with(Cell::get) {
+            ATTEMPT_PANIC.with(|p| *p.borrow_mut() = Some(location.unwrap_or(message)));
+            return;
+        }
+        if std::thread::current().name() != Some(thread) {
+            previous(info);
+            return;
+        }
+        let mut recorded = lock(&PANIC);
+        if recorded.is_none() {
+            *recorded = Some(Panic {
+                message,
+                location,
+                phase: *lock(&PHASE),
+                span: SPAN.with(Cell::get),
+                backtrace: std::backtrace::Backtrace::force_capture().to_string(),
+            });
+        }
+    }));
+}
+
+/// The text a panic was raised with
+pub(crate) fn panic_message(payload: &(dyn std::any::Any + Send)) -> String {
+    payload
+        .downcast_ref::<&str>()
+        .map(|s| s.to_string())
+        .or_else(|| payload.downcast_ref::<String>().cloned())
+        .unwrap_or_else(|| "unknown panic".to_string())
+}
+
+/// The panic the hook recorded, if there was one
+pub fn take_panic() -> Option<Panic> {
+    lock(&PANIC).take()
+}
+
+/// Note the file and options being compiled
+pub fn set_input(file: Arc<SourceFile>, options: &CompileOptions) {
+    // Candidates are all different programs, which a compile cache would only collect
+    let options = CompileOptions { cache: None, ..options.clone() };
+    *lock(&INPUT) = Some(Input { file, target: None, options });
+}
+
+/// Note the target the input is being compiled for
+pub fn set_target(target: CompileTarget) {
+    if let Some(input) = lock(&INPUT).as_mut() {
+        input.target = Some(target);
+    }
+}
+
+/// Note that the compiler started `phase`
+pub(crate) fn enter(phase: Phase) {
+    *lock(&PHASE) = Some(phase);
+    SPAN.with(|s| s.set(None));
+}
+
+/// Note the item or statement the current thread is working on
+pub(crate) fn at(span: Span) {
+    SPAN.with(|s| s.set(Some(span)));
+}
+
+impl Panic {
+    /// `typecheck at src/main.kn:3:5`, as far as it is known
+    pub fn context(&self) -> Option<String> {
+        let input = lock(&INPUT).clone();
+        let phase = self.phase.map(|phase| match (phase, input.as_ref().and_then(|i| i.target)) {
+            (Phase::Codegen, Some(target)) if crate::interprets(target) => "running the program",
+            _ => phase.name(),
+        });
+        let position = self.span.and_then(|span| {
+            let file = match span.file {
+                Some(id) => SourceMap::global().read().unwrap_or_else(|e| e.into_inner()).get(id)?,
+                None => input?.file,
+            };
+            let (line, col) = file.line_col(span.start);
+            Some(format!("{}:{}:{}", file.path.display(), line, col))
+        });
+        match (phase, position) {
+            (Some(phase), Some(position)) => Some(format!("{} at {}", phase, position)),
+            (Some(phase), None) => Some(phase.to_string()),
+            (None, Some(position)) => Some(format!("at {}", position)),
+            (None, None) => None,
+        }
+    }
+}
+
+/// The input cut down to what still makes the compiler panic where `panic`
+/// was raised, or why there is none
+pub fn minimize(panic: &Panic) -> Result<Reproducer, String> {
+    let Some(mut input) = lock(&INPUT).clone() else {
+        return Err("the compiler had not started on a file".to_string());
+    };
+    let target = input.target.unwrap_or(CompileTarget::Wasm);
+    if panic.phase == Some(Phase::Codegen) && crate::interprets(target) {
+        return Err("it happened while running the program, which minimizing would run again and again".to_string());
+    }
+    // Candidates are only compiled, never traced or recorded
+    input.options.trace = None;
+    input.options.recording = None;
+    input.options.runtime_stats = false;
+
+    let expected = panic.location.clone().unwrap_or_else(|| panic.message.clone());
+    let lines: Vec<&str> = input.file.text.lines().collect();
+    let mut attempts = 0;
+    let mut crashes = |keep: &[bool]| {
+        if attempts >= MAX_ATTEMPTS {
+            return false;
+        }
+        attempts += 1;
+        attempt(kept(&lines, keep), target, &input.options).as_deref() == Some(expected.as_str())
+    };
+    let mut keep = vec![true; lines.len()];
+    if !crashes(&keep) {
+        return Err("compiling the input again did not panic the same way".to_string());
+    }
+    reduce(&lines, &mut keep, blocks(&lines, 0..lines.len()), &mut crashes);
+    Ok(Reproducer { source: kept(&lines, &keep), original_lines: lines.len(), attempts })
+}
+
+/// Where compiling `source` panics, if it does. The compile runs on a thread
+/// of its own, so a candidate that never finishes is given up on.
+fn attempt(source: String, target: CompileTarget, options: &CompileOptions) -> Option<String> {
+    let (tx, rx) = mpsc::channel();
+    let options = options.clone();
+    let spawned = std::thread::Builder::new()
+        .name("kain-crash-reproducer".into())
+        .stack_size(8 * 1024 * 1024)
+        .spawn(move || {
+            IN_ATTEMPT.with(|a| a.set(true));
+            let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+                // Programs aren't run: the panic was in the compiler
+                if crate::interprets(target) {
+                    let _ = crate::front_end(&source, target, &options);
+                } else {
+                    let _ = crate::compile_with(&source, target, &options);
+                }
+            }));
+            let _ = tx.send(result.err().and_then(|_| ATTEMPT_PANIC.with(|p| p.borrow_mut().take())));
+        });
+    spawned.ok()?;
+    rx.recv_timeout(ATTEMPT_TIMEOUT).ok().flatten()
+}
+
+/// Delta debugging over `units`, the line ranges of sibling items or
+/// statements: drop ever smaller groups of them while the input still
+/// crashes, then do the same inside each one that is left
+fn reduce(lines: &[&str], keep: &mut [bool], mut units: Vec<Range<usize>>, crashes: &mut dyn FnMut(&[bool]) -> bool) {
+    let mut chunks = 2;
+    while !units.is_empty() {
+        let size = units.len().div_ceil(chunks);
+        let mut removed = false;
+        let mut start = 0;
+        while start < units.len() {
+            let end = (start + size).min(units.len());
+            set(keep, &units[start..end], false);
+            if crashes(keep) {
+                units.drain(start..end);
+                removed = true;
+            } else {
+                set(keep, &units[start..end], true);
+                start = end;
+            }
+        }
+        if removed {
+            chunks = (chunks - 1).max(2);
+        } else if size == 1 {
+            break;
+        } else {
+            chunks = (chunks * 2).min(units.len());
+        }
+    }
+    for unit in units {
+        let children = blocks(lines, unit.start + 1..unit.end);
+        reduce(lines, keep, children, crashes);
+    }
+}
+
+fn set(keep: &mut [bool], units: &[Range<usize>], value: bool) {
+    for unit in units {
+        keep[unit.clone()].fill(value);
+    }
+}
+
+/// The items or statements in `range`: each line as indented as the first
+/// non-blank one, with the more indented and blank lines after it
+fn blocks(lines: &[&str], range: Range<usize>) -> Vec<Range<usize>> {
+    let indent = |line: &str| line.len() - line.trim_start().len();
+    let Some(base) = range.clone().find(|&i| !lines[i].trim().is_empty()).map(|i| indent(lines[i])) else {
+        return Vec::new();
+    };
+    let mut blocks: Vec<Range<usize>> = Vec::new();
+    for i in range {
+        if !lines[i].trim().is_empty() && indent(lines[i]) <= base {
+            blocks.push(i..i + 1);
+        } else if let Some(last) = blocks.last_mut() {
+            last.end = i + 1;
+        }
+    }
+    blocks
+}
+
+fn kept(lines: &[&str], keep: &[bool]) -> String {
+    let mut text = String::new();
+    for (line, _) in lines.iter().zip(keep).filter(|(_, keep)| **keep) {
+        text.push_str(line);
+        text.push('\n');
+    }
+    text
+}
+
+/// Write the report of `panic` to `kain-crash-<time>-<pid>.md` in the current
+/// directory, or the temporary directory if that can't be written, and return its path
+pub fn write_report(panic: &Panic, reproducer: &Result<Reproducer, String>) -> std::io::Result<PathBuf> {
+    let input = lock(&INPUT).clone();
+    let mut report = String::from("# kain crash report\n\n");
+    let command: Vec<String> = std::env::args().collect();
+    report.push_str(&format!("- compiler: kain {} ({}-{})\n", crate::VERSION, std::env::consts::ARCH, std::env::consts::OS));
+    report.push_str(&format!("- command: `{}`\n", command.join(" ")));
+    if let Some(target) = input.as_ref().and_then(|i| i.target) {
+        report.push_str(&format!("- target: {}\n", target.cfg_names()[0]));
+    }
+    if let Some(context) = panic.context() {
+        report.push_str(&format!("- during: {}\n", context));
+    }
+    report.push_str(&format!("- panic: {}\n", panic.message));
+    if let Some(location) = &panic.location {
+        report.push_str(&format!("- raised at: {}\n", location));
+    }
+
+    report.push_str("\n## Reproducer\n\n");
+    match reproducer {
+        Ok(reproducer) => {
+            let target = input.as_ref().and_then(|i| i.target).unwrap_or(CompileTarget::Wasm);
+            report.push_str(&format!(
+                "Cut down from {} to {} lines in {} compiles. Compiling it for {} panics at the same place.\n\n```kain\n{}```\n",
+                reproducer.original_lines,
+                reproducer.source.lines().count(),
+                reproducer.attempts,
+                target.cfg_names()[0],
+                reproducer.source
+            ));
+        }
+        Err(why) => report.push_str(&format!("None: {}.\n", why)),
+    }
+    report.push_str(&format!("\n## Backtrace\n\n```text\n{}\n```\n", panic.backtrace.trim_end()));
+
+    let secs = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).map_or(0, |d| d.as_secs());
+    let name = format!("kain-crash-{}-{}.md", secs, std::process::id());
+    std::fs::write(&name, &report).map(|_| PathBuf::from(&name)).or_else(|_| {
+        let path = std::env::temp_dir().join(&name);
+        std::fs::write(&path, &report).map(|_| path)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reduce_keeps_only_what_still_crashes() {
+        let source = "fn helper() -> Int:\n    return 1\n\nfn main():\n    let a = 1\n    let boom = crash()\n    println(a)\n\nfn other():\n    pass\n";
+        let lines: Vec<&str> = source.lines().collect();
+        assert_eq!(blocks(&lines, 0..lines.len()), [0..3, 3..8, 8..10]);
+        assert_eq!(blocks(&lines, 4..8), [4..5, 5..6, 6..8]);
+
+        // Stands in for the compiler: it "panics" on any input with a `crash()` call inside `main`
+        let mut keep = vec![true; lines.len()];
+        let mut attempts = 0;
+        let mut crashes = |keep: &[bool]| {
+            attempts += 1;
+            let text = kept(&lines, keep);
+            text.contains("fn main():\n") && text.contains("crash()")
+        };
+        reduce(&lines, &mut keep, blocks(&lines, 0..lines.len()), &mut crashes);
+        assert_eq!(kept(&lines, &keep), "fn main():\n    let boom = crash()\n");
+        assert!(attempts < 20, "{} attempts", attempts);
+    }
+}
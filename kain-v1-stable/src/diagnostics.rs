@@ -3,16 +3,25 @@
 
 use crate::span::Span;
 use crate::error::KainError;
+use crate::vfs::{FileId, LineIndex, SourceFile, SourceMap};
 
 /// Diagnostic renderer for pretty error messages
 pub struct Diagnostics<'a> {
     source: &'a str,
     filename: &'a str,
+    lines: LineIndex,
+    /// The source map's id for `source`, when it came from there
+    file: Option<FileId>,
 }
 
 impl<'a> Diagnostics<'a> {
     pub fn new(source: &'a str, filename: &'a str) -> Self {
-        Self { source, filename }
+        Self { source, filename, lines: LineIndex::new(source), file: None }
+    }
+
+    /// Render against a file from the source map, reusing its line index
+    pub fn for_file(file: &'a SourceFile, filename: &'a str) -> Self {
+        Self { source: &file.text, filename, lines: file.lines.clone(), file: Some(file.id) }
     }
     
     /// Format an error with source context
@@ -22,8 +31,29 @@ impl<'a> Diagnostics<'a> {
             KainError::Parser { message, span } => self.format_with_context("Parse Error", message, *span),
             KainError::Type { message, span } => self.format_with_context("Type Error", message, *span),
             KainError::Effect { message, span } => self.format_with_context("Effect Error", message, *span),
-            KainError::Borrow { message, span } => self.format_with_context("Borrow Error", message, *span),
+            KainError::Borrow { message, span, related } => {
+                let mut output = self.format_with_context("Borrow Error", message, *span);
+                if let Some((related, note)) = related {
+                    output.push_str(&format!("   \x1b[1;34m= note\x1b[0m: {}\n", note));
+                    output.push_str(&self.source_context(*related, "\x1b[1;34m"));
+                }
+                output
+            }
             KainError::Codegen { message, span } => self.format_with_context("Codegen Error", message, *span),
+            KainError::Internal { code, phase, message, span } => {
+                let mut output = format!(
+                    "\n\x1b[1;31merror[{}]\x1b[0m: internal compiler error during {}: {}\n",
+                    code, phase, message
+                );
+                if !span.is_placeholder() {
+                    output.push_str(&self.source_context(*span, "\x1b[1;31m"));
+                }
+                output.push_str(&format!(
+                    "   \x1b[1;34m= note\x1b[0m: this is a bug in the compiler, not in your program; please report it at {}\n",
+                    crate::crash::ISSUES_URL
+                ));
+                output
+            }
             KainError::Runtime { message } => format!(
                 "\n\x1b[1;31merror\x1b[0m: {}\n",
                 message
@@ -35,9 +65,17 @@ impl<'a> Diagnostics<'a> {
         }
     }
     
+    /// Format a lint as a warning with source context and its suggestion
+    pub fn format_warning(&self, lint: &Lint) -> String {
+        let mut output = format!("\n\x1b[1;33mwarning\x1b[0m: {}\n", lint.message);
+        output.push_str(&self.source_context(lint.span, "\x1b[1;33m"));
+        if let Some(suggestion) = &lint.suggestion {
+            output.push_str(&format!("   \x1b[1;34m= help\x1b[0m: {}\n", suggestion.message));
+        }
+        output
+    }
+    
     fn format_with_context(&self, error_type: &str, message: &str, span: Span) -> String {
-        let (line_num, col, line_content) = self.get_line_info(span);
-        
         let mut output = String::new();
         
         // Error header
@@ -45,6 +83,22 @@ impl<'a> Diagnostics<'a> {
             "\n\x1b[1;31merror[{}]\x1b[0m: {}\n",
             error_type, message
         ));
+        output.push_str(&self.source_context(span, "\x1b[1;31m"));
+        output
+    }
+
+    /// Location, source line and a pointer under `span`, drawn in `color`
+    fn source_context(&self, span: Span, color: &str) -> String {
+        // A span in an imported module is drawn from that module's text
+        if let Some(id) = span.file.filter(|&id| Some(id) != self.file) {
+            let file = SourceMap::global().read().unwrap_or_else(|e| e.into_inner()).get(id);
+            if let Some(file) = file {
+                let path = file.path.display().to_string();
+                return Diagnostics::for_file(&file, &path).source_context(span, color);
+            }
+        }
+        let (line_num, col, line_content) = self.get_line_info(span);
+        let mut output = String::new();
         
         // Location
         output.push_str(&format!(
@@ -63,14 +117,15 @@ impl<'a> Diagnostics<'a> {
         
         // Error pointer
         let pointer_offset = col.saturating_sub(1);
-        let content_len = line_content.len();
+        let content_len = line_content.chars().count();
         let remaining_len = content_len.saturating_sub(pointer_offset);
         let span_len = span.end.saturating_sub(span.start);
         let pointer_len = span_len.min(remaining_len).max(1);
         
         output.push_str(&format!(
-            "   \x1b[1;34m|\x1b[0m {}\x1b[1;31m{}\x1b[0m\n",
+            "   \x1b[1;34m|\x1b[0m {}{}{}\x1b[0m\n",
             " ".repeat(pointer_offset),
+            color,
             "^".repeat(pointer_len)
         ));
         
@@ -82,41 +137,28 @@ impl<'a> Diagnostics<'a> {
     
     /// Get line number, column, and line content for a span
     fn get_line_info(&self, span: Span) -> (usize, usize, &str) {
-        let mut line_num = 1;
-        let mut line_start = 0;
-        
-        // Safety check for span bounds
-        let start = span.start.min(self.source.len());
-        
-        for (i, c) in self.source.char_indices() {
-            if i >= start {
-                break;
-            }
-            if c == '\n' {
-                line_num += 1;
-                line_start = i + 1;
-            }
-        }
-        
-        let col = start.saturating_sub(line_start) + 1;
-        
-        // Find line end
-        let line_end = if start < self.source.len() {
-            self.source[start..]
-                .find('\n')
-                .map(|i| start + i)
-                .unwrap_or(self.source.len())
-        } else {
-            self.source.len()
-        };
-        
-        let line_start = line_start.min(self.source.len());
-        let line_content = &self.source[line_start..line_end];
-        
-        (line_num, col, line_content)
+        let (line_num, col) = self.lines.line_col(self.source, span.start);
+        (line_num, col, self.lines.line(self.source, line_num - 1))
     }
 }
 
+/// A warning about code that still compiles but should change
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Lint {
+    pub message: String,
+    pub span: Span,
+    /// A fix that can be applied without review
+    pub suggestion: Option<Suggestion>,
+}
+
+/// Replace the text at `span` with `replacement`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Suggestion {
+    pub message: String,
+    pub span: Span,
+    pub replacement: String,
+}
+
 /// Format an error without source context (for runtime errors)
 pub fn format_simple_error(error: &KainError) -> String {
     match error {
@@ -145,5 +187,29 @@ mod tests {
         assert_eq!(line, 2);
         assert_eq!(content, "let y = x + 1");
     }
+
+    #[test]
+    fn test_spans_in_other_files_render_from_that_file() {
+        let module = SourceMap::global().write().unwrap().add("lib/shapes.kn", "fn area() -> Int:\n    return \"wide\"\n");
+        let diag = Diagnostics::new("use shapes\n", "main.kn");
+        let error = KainError::type_error("expected Int, found String", Span::new(29, 35).in_file(module.id));
+        let rendered = diag.format_error(&error);
+        assert!(rendered.contains("shapes.kn:2:12"), "{}", rendered);
+        assert!(rendered.contains("return \"wide\""), "{}", rendered);
+    }
+    #[test]
+    fn test_internal_errors_carry_their_code_and_phase() {
+        use crate::error::Ice;
+        let diag = Diagnostics::new("fn main():\n    send(x)\n", "main.kn");
+        let error = KainError::internal(Ice::MissingMailbox, crate::Phase::Codegen, "`ping` is sent before the actor mailbox was declared", Span::new(15, 22));
+        let rendered = diag.format_error(&error);
+        assert!(rendered.contains("error[ICE0003]"), "{}", rendered);
+        assert!(rendered.contains("internal compiler error during codegen"), "{}", rendered);
+        assert!(rendered.contains("main.kn:2:5"), "{}", rendered);
+        assert!(rendered.contains(crate::crash::ISSUES_URL), "{}", rendered);
+
+        let unlocated = KainError::internal(Ice::SpirvBuilder, crate::Phase::Codegen, "rejected", Span::default());
+        assert!(!diag.format_error(&unlocated).contains("-->"));
+    }
 }
 
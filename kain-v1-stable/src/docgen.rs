@@ -0,0 +1,50 @@
+//! Markdown documentation from `///` doc comments
+
+use crate::ast::{Item, Program};
+use crate::lsp::{format_effect_decl, format_fn_signature, format_type, format_type_alias};
+
+/// Render every documented item of a program as a Markdown page
+pub fn render_markdown(program: &Program, title: &str) -> String {
+    let mut out = format!("# {}\n", title);
+
+    for item in &program.items {
+        // Document target-specific items whichever target they are for
+        let item = match item {
+            Item::Cfg(c) => &*c.item,
+            other => other,
+        };
+        if let Item::Impl(imp) = item {
+            // Methods are documented under their target type
+            for method in &imp.methods {
+                if let Some(doc) = &method.doc {
+                    let heading = format!("{}::{}", format_type(&imp.target_type), method.name);
+                    push_entry(&mut out, &heading, &format_fn_signature(method), doc);
+                }
+            }
+            continue;
+        }
+
+        let Some(doc) = item.doc() else { continue };
+        let (heading, signature) = match item {
+            Item::Function(f) => (f.name.clone(), format_fn_signature(f)),
+            Item::Struct(s) => (s.name.clone(), format!("struct {}", s.name)),
+            Item::Enum(e) => (e.name.clone(), format!("enum {}", e.name)),
+            Item::Component(c) => (c.name.clone(), format!("component {}", c.name)),
+            Item::Shader(s) => (s.name.clone(), format!("shader {}", s.name)),
+            Item::Actor(a) => (a.name.clone(), format!("actor {}", a.name)),
+            Item::Const(c) => (c.name.clone(), format!("const {}: {}", c.name, format_type(&c.ty))),
+            Item::Static(s) => (s.name.clone(), format!("static mut {}: {}", s.name, format_type(&s.ty))),
+            Item::Trait(t) => (t.name.clone(), format!("trait {}", t.name)),
+            Item::TypeAlias(t) => (t.name.clone(), format_type_alias(t)),
+            Item::Effect(e) => (e.name.clone(), format_effect_decl(e)),
+            _ => continue,
+        };
+        push_entry(&mut out, &heading, &signature, doc);
+    }
+
+    out
+}
+
+fn push_entry(out: &mut String, heading: &str, signature: &str, doc: &str) {
+    out.push_str(&format!("\n## {}\n\n```kain\n{}\n```\n\n{}\n", heading, signature, doc));
+}
@@ -0,0 +1,123 @@
+//! Doc tests: run the ```kain examples found in `///` doc comments
+//!
+//! Each fenced block runs as its own program, alongside the items of the file
+//! it was found in (minus that file's `main` and tests), so an example that no
+//! longer compiles or that fails at runtime is reported against the item it
+//! documents. Fences tagged `kain,ignore` are collected but not run.
+
+use std::time::Duration;
+
+use crate::ast::{Item, Program};
+use crate::lexer::Lexer;
+use crate::parser::Parser;
+use crate::error::KainResult;
+use crate::{eval_parsed, CompileOptions};
+
+/// How long a single example may run before it counts as hung
+const EXAMPLE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// One code block from a doc comment
+#[derive(Debug, Clone)]
+pub struct DocTest {
+    /// Item the example documents, e.g. `add` or `Point::new`
+    pub item: String,
+    /// Line of the documented item in its file
+    pub line: usize,
+    pub source: String,
+    pub ignored: bool,
+}
+
+/// Collect the examples from every documented item, in source order
+pub fn collect(program: &Program, source: &str) -> Vec<DocTest> {
+    let line_of = |offset: usize| source[..offset.min(source.len())].matches('\n').count() + 1;
+    let mut tests = Vec::new();
+
+    for item in &program.items {
+        if let Item::Impl(imp) = item {
+            for method in &imp.methods {
+                if let Some(doc) = &method.doc {
+                    let name = format!("{}::{}", crate::lsp::format_type(&imp.target_type), method.name);
+                    push_blocks(&mut tests, &name, line_of(method.span.start), doc);
+                }
+            }
+            continue;
+        }
+        if let Some(doc) = item.doc() {
+            let name = item.name().unwrap_or("<item>");
+            push_blocks(&mut tests, name, line_of(item.span().start), doc);
+        }
+    }
+    tests
+}
+
+/// Compile and run one example with the items of `file` in scope
+pub fn run(test: &DocTest, file: &Program, options: &CompileOptions) -> KainResult<()> {
+    let example = Parser::new(&Lexer::new(&test.source).tokenize()?).parse()?;
+
+    let mut items: Vec<Item> = file.items
+        .iter()
+        .filter(|item| item.name() != Some("main") && !matches!(item, Item::Test(_)))
+        .cloned()
+        .collect();
+    items.extend(example.items);
+    let program = Program { items, span: example.span };
+
+    let mut options = options.clone();
+    options.limits.timeout.get_or_insert(EXAMPLE_TIMEOUT);
+    match eval_parsed(program, &options).diagnostics.into_iter().next() {
+        Some(e) => Err(e),
+        None => Ok(()),
+    }
+}
+
+/// Split a doc comment into its ```kain blocks
+fn push_blocks(tests: &mut Vec<DocTest>, item: &str, line: usize, doc: &str) {
+    let mut current: Option<(String, bool)> = None;
+    for doc_line in doc.lines() {
+        let fence = doc_line.trim_start().strip_prefix("```");
+        match (current.take(), fence) {
+            (None, Some(info)) => {
+                let mut tags = info.split(',').map(str::trim);
+                if tags.next() == Some("kain") {
+                    current = Some((String::new(), tags.any(|t| t == "ignore")));
+                }
+            }
+            (None, None) => {}
+            (Some((source, ignored)), Some(_)) => tests.push(DocTest {
+                item: item.to_string(),
+                line,
+                source,
+                ignored,
+            }),
+            (Some((mut source, ignored)), None) => {
+                source.push_str(doc_line);
+                source.push('\n');
+                current = Some((source, ignored));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{lexer::Lexer, parser::Parser};
+
+    #[test]
+    fn test_doc_examples_run() {
+        let source = "/// Adds two numbers\n///\n/// ```kain\n/// assert(add(1, 2) == 3)\n/// ```\n///\n/// ```kain,ignore\n/// add(\n/// ```\nfn add(a: Int, b: Int) -> Int:\n    return a + b\n\n/// ```kain\n/// assert(broken() == 1)\n/// ```\nfn broken() -> Int:\n    return 0\n";
+        let tokens = Lexer::new(source).tokenize().unwrap();
+        let program = Parser::new(&tokens).parse().unwrap();
+        let tests = collect(&program, source);
+
+        assert_eq!(tests.len(), 3);
+        assert_eq!((tests[0].item.as_str(), tests[0].line), ("add", 10));
+        assert!(tests[1].ignored);
+        assert_eq!((tests[2].item.as_str(), tests[2].line), ("broken", 16));
+
+        let options = CompileOptions::default();
+        assert!(run(&tests[0], &program, &options).is_ok());
+        let err = run(&tests[2], &program, &options).unwrap_err();
+        assert!(err.to_string().contains("Assertion failed"));
+    }
+}
@@ -0,0 +1,224 @@
+//! Language editions
+//!
+//! Breaking syntax changes only apply from the edition that introduces them,
+//! so code written for an older `language_version` keeps compiling. For each
+//! change, `migration_lints` reports the code a newer edition would reject,
+//! with a suggested rewrite.
+//!
+//! | Edition | Change                                                  |
+//! |---------|---------------------------------------------------------|
+//! | 0.1     | Initial language                                        |
+//! | 0.2     | `#` line comments removed (`#` is reserved), use `//`   |
+//! | 0.2     | `quote` is a keyword (`quote:` blocks in comptime code) |
+//! | 0.2     | Only `pub` functions of an imported module are callable |
+//! | 0.3     | Only `mut` bindings and `mut self` can be changed       |
+//! | 0.4     | Values move and borrows are checked (see `ownership`)   |
+//! | 0.5     | `unless` is a keyword (`unless cond:` statements)       |
+
+use std::fmt;
+
+use logos::Logos;
+
+use crate::diagnostics::{Lint, Suggestion};
+use crate::lexer::{Lexer, TokenKind};
+use crate::parser::Parser;
+use crate::span::Span;
+
+/// A `language_version` of the language
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Edition {
+    /// Code without a `language_version` is treated as the first edition
+    #[default]
+    V0_1,
+    V0_2,
+    V0_3,
+    V0_4,
+    V0_5,
+}
+
+impl Edition {
+    /// What `kain init` writes into new manifests
+    pub const LATEST: Edition = Edition::V0_5;
+
+    pub fn parse(version: &str) -> Option<Edition> {
+        match version.trim() {
+            "0.1" => Some(Edition::V0_1),
+            "0.2" => Some(Edition::V0_2),
+            "0.3" => Some(Edition::V0_3),
+            "0.4" => Some(Edition::V0_4),
+            "0.5" => Some(Edition::V0_5),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Edition::V0_1 => "0.1",
+            Edition::V0_2 => "0.2",
+            Edition::V0_3 => "0.3",
+            Edition::V0_4 => "0.4",
+            Edition::V0_5 => "0.5",
+        }
+    }
+
+    /// Whether `#` starts a line comment
+    pub fn hash_comments(self) -> bool {
+        self < Edition::V0_2
+    }
+
+    /// Whether `quote` is a keyword rather than an identifier
+    pub fn quote_keyword(self) -> bool {
+        self >= Edition::V0_2
+    }
+
+    /// Whether `unless` is a keyword rather than an identifier
+    pub fn unless_keyword(self) -> bool {
+        self >= Edition::V0_5
+    }
+
+    /// Whether code outside a module can only call its `pub` functions
+    pub fn enforce_visibility(self) -> bool {
+        self >= Edition::V0_2
+    }
+
+    /// Whether only `mut` bindings can be assigned to or have `mut self` methods called
+    pub fn enforce_mutability(self) -> bool {
+        self >= Edition::V0_3
+    }
+
+    /// Whether using a moved value, conflicting borrows and references to locals escaping are rejected
+    pub fn enforce_ownership(self) -> bool {
+        self >= Edition::V0_4
+    }
+}
+
+impl fmt::Display for Edition {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Everything in `source` that stops compiling when moving past `edition`
+pub fn migration_lints(source: &str, edition: Edition) -> Vec<Lint> {
+    let mut lints = Vec::new();
+    let mut lex = TokenKind::lexer(source);
+    while let Some(token) = lex.next() {
+        let span = Span::new(lex.span().start, lex.span().end);
+        match token {
+            Ok(TokenKind::HashComment) if edition.hash_comments() => lints.push(Lint {
+                message: format!("`#` comments are removed in edition {}", Edition::V0_2),
+                span,
+                suggestion: Some(Suggestion {
+                    message: "replace `#` with `//`".to_string(),
+                    span: Span::new(span.start, span.start + 1),
+                    replacement: "//".to_string(),
+                }),
+            }),
+            Ok(TokenKind::Quote) if !edition.quote_keyword() => lints.push(Lint {
+                message: format!("`quote` is a keyword in edition {}", Edition::V0_2),
+                span,
+                suggestion: Some(Suggestion {
+                    message: "rename to `quote_`".to_string(),
+                    span,
+                    replacement: "quote_".to_string(),
+                }),
+            }),
+            Ok(TokenKind::Unless) if !edition.unless_keyword() => lints.push(Lint {
+                message: format!("`unless` is a keyword in edition {}", Edition::V0_5),
+                span,
+                suggestion: Some(Suggestion {
+                    message: "rename to `unless_`".to_string(),
+                    span,
+                    replacement: "unless_".to_string(),
+                }),
+            }),
+            _ => {}
+        }
+    }
+    if !edition.enforce_mutability() {
+        lints.extend(mutability_lints(source, edition));
+    }
+    if !edition.enforce_ownership() {
+        lints.extend(ownership_lints(source, edition));
+    }
+    lints
+}
+
+/// Changes to bindings not declared `mut`, each declaration suggested once.
+/// Source that does not parse has none; compiling it reports why.
+fn mutability_lints(source: &str, edition: Edition) -> Vec<Lint> {
+    let Ok(tokens) = Lexer::with_edition(source, edition).tokenize() else {
+        return Vec::new();
+    };
+    let Ok(program) = Parser::new(&tokens).parse() else {
+        return Vec::new();
+    };
+    let mut suggested = Vec::new();
+    crate::mutability::violations(&program)
+        .into_iter()
+        .map(|v| {
+            let decl = v.decl.filter(|decl| !suggested.contains(&decl.start));
+            suggested.extend(decl.map(|decl| decl.start));
+            Lint {
+                message: format!("{} (rejected from edition {})", v.message, Edition::V0_3),
+                span: v.span,
+                suggestion: decl.map(|decl| Suggestion {
+                    message: "declare it `mut`".to_string(),
+                    span: Span::new(decl.start, decl.start),
+                    replacement: "mut ".to_string(),
+                }),
+            }
+        })
+        .collect()
+}
+
+/// Uses of values that the ownership pass rejects; the fix depends on the
+/// program's intent, so none is suggested
+fn ownership_lints(source: &str, edition: Edition) -> Vec<Lint> {
+    let Ok(tokens) = Lexer::with_edition(source, edition).tokenize() else {
+        return Vec::new();
+    };
+    let Ok(program) = Parser::new(&tokens).parse() else {
+        return Vec::new();
+    };
+    crate::ownership::violations(&program)
+        .into_iter()
+        .map(|v| Lint {
+            message: format!("{} (rejected from edition {})", v.message, Edition::V0_4),
+            span: v.span,
+            suggestion: None,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::Lexer;
+
+    #[test]
+    fn test_hash_comments_are_gated() {
+        let source = "# old style\nfn main():\n    let s = \"# not a comment\"\n    println(s) # trailing\n";
+        assert!(Lexer::with_edition(source, Edition::V0_1).tokenize().is_ok());
+        let err = Lexer::with_edition(source, Edition::V0_2).tokenize().unwrap_err();
+        assert!(err.to_string().contains("`#` comments were removed in edition 0.2"));
+
+        let lints = migration_lints(source, Edition::V0_1);
+        assert_eq!(lints.len(), 2);
+        assert_eq!(&source[lints[1].span.start..lints[1].span.end], "# trailing");
+        assert!(migration_lints(source, Edition::V0_2).is_empty());
+    }
+
+    #[test]
+    fn test_unless_is_a_keyword_from_0_5() {
+        let source = "fn main():\n    let unless = 1\n    println(unless)\n";
+        let tokens = Lexer::with_edition(source, Edition::V0_4).tokenize().unwrap();
+        assert!(Parser::new(&tokens).parse().is_ok());
+        assert!(Lexer::with_edition(source, Edition::V0_5).tokenize().is_ok_and(|tokens| Parser::new(&tokens).parse().is_err()));
+
+        let lints = migration_lints(source, Edition::V0_4);
+        assert_eq!(lints.len(), 2);
+        assert!(lints[0].message.contains("`unless` is a keyword in edition 0.5"));
+        assert!(migration_lints(source, Edition::V0_5).is_empty());
+    }
+}
@@ -1,10 +1,21 @@
 //! KAIN Effect System - Track side effects at compile time
+//!
+//! Besides the built-in effects, a program can declare its own with
+//! `effect Database`, and name a group of effects with `effect App = IO + Db`.
+//! An alias stands for its members wherever it is written, so a function
+//! `with App` may call one `with IO`.
+//!
+//! `with handler value for Effect:` runs a block with `value` standing in for
+//! an effect: calls to functions declared `with Effect`, or for `IO` and `Db`
+//! to the natives that perform them, go to the method of the same name on
+//! `value` when it has one. Tests use this to run code against a mock.
 
 use crate::span::Span;
 use crate::error::{KainError, KainResult};
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub enum Effect {
     Pure,      // No side effects
     IO,        // File/Network/Console
@@ -14,6 +25,11 @@ pub enum Effect {
     Unsafe,    // Breaks safety guarantees
     Alloc,     // Memory allocation
     Panic,     // Can abort
+    Db,        // Database access
+    Global,    // Reads or writes `static mut` state
+    /// Declared by the program (`effect Database`), or an alias the type
+    /// checker has not expanded yet
+    Named(String),
 }
 
 impl Effect {
@@ -25,11 +41,24 @@ impl Effect {
             "GPU" => Some(Effect::GPU),
             "Reactive" => Some(Effect::Reactive),
             "Unsafe" => Some(Effect::Unsafe),
+            "Alloc" => Some(Effect::Alloc),
+            "Panic" => Some(Effect::Panic),
+            "Db" => Some(Effect::Db),
+            "Global" => Some(Effect::Global),
             _ => None,
         }
     }
 }
 
+impl fmt::Display for Effect {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Effect::Named(name) => write!(f, "{}", name),
+            other => write!(f, "{:?}", other),
+        }
+    }
+}
+
 #[derive(Debug, Clone, Default, PartialEq, Eq)]
 pub struct EffectSet {
     pub effects: HashSet<Effect>,
@@ -56,6 +85,79 @@ impl EffectSet {
     }
 }
 
+impl fmt::Display for EffectSet {
+    /// Effects by name, sorted, as a `with` clause lists them
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut names: Vec<String> = self.effects.iter().map(|e| e.to_string()).collect();
+        names.sort();
+        write!(f, "{}", names.join(", "))
+    }
+}
+
+/// The effects a program declares, and what each of its aliases stands for
+#[derive(Debug, Clone, Default)]
+pub struct EffectRegistry {
+    declared: HashSet<String>,
+    aliases: HashMap<String, Vec<Effect>>,
+}
+
+impl EffectRegistry {
+    /// Declare `name`, as an alias for `members` if there are any
+    pub fn declare(&mut self, name: &str, members: Option<&[Effect]>, span: Span) -> KainResult<()> {
+        if Effect::from_str(name).is_some() {
+            return Err(KainError::effect_error(format!("`{}` is a built-in effect and cannot be declared again", name), span));
+        }
+        if self.declared.contains(name) || self.aliases.contains_key(name) {
+            return Err(KainError::effect_error(format!("Effect `{}` is declared more than once", name), span));
+        }
+        match members {
+            Some(members) => {
+                self.aliases.insert(name.to_string(), members.to_vec());
+            }
+            None => {
+                self.declared.insert(name.to_string());
+            }
+        }
+        Ok(())
+    }
+
+    /// The set `effects` stands for, with every alias replaced by its members.
+    /// Effects that are neither built in nor declared are reported at `span`.
+    pub fn expand(&self, effects: &[Effect], span: Span) -> KainResult<EffectSet> {
+        let mut set = EffectSet::new();
+        for effect in effects {
+            self.expand_into(effect, &mut set, &mut Vec::new(), span)?;
+        }
+        Ok(set)
+    }
+
+    fn expand_into(&self, effect: &Effect, set: &mut EffectSet, expanding: &mut Vec<String>, span: Span) -> KainResult<()> {
+        let Effect::Named(name) = effect else {
+            set.effects.insert(effect.clone());
+            return Ok(());
+        };
+        if self.declared.contains(name) {
+            set.effects.insert(effect.clone());
+            return Ok(());
+        }
+        let Some(members) = self.aliases.get(name) else {
+            return Err(KainError::effect_error(
+                format!("Unknown effect `{}`; declare it with `effect {}`", name, name),
+                span,
+            ));
+        };
+        if expanding.contains(name) {
+            return Err(KainError::effect_error(format!("Effect alias `{}` includes itself", name), span));
+        }
+        expanding.push(name.clone());
+        for member in members {
+            self.expand_into(member, set, expanding, span)?;
+        }
+        expanding.pop();
+        Ok(())
+    }
+}
+
 pub fn check_effect_call(caller: &EffectSet, callee: &EffectSet, span: Span) -> KainResult<()> {
     if !caller.can_call(callee) {
         return Err(KainError::effect_error(
@@ -66,3 +168,60 @@ pub fn check_effect_call(caller: &EffectSet, callee: &EffectSet, span: Span) ->
     Ok(())
 }
 
+#[cfg(test)]
+mod tests {
+    use crate::{compile, eval_snippet, lsp, types, CompileOptions, CompileTarget, Item, Lexer, Parser, TypedItem};
+
+    #[test]
+    fn test_static_mut_needs_global_effect() {
+        let source = "static mut counter: Int = 0\n\nfn next_id() -> Int with Global:\n    counter = counter + 1\n    return counter\n\nfn main():\n    next_id()\n    println(next_id())\n";
+        let result = eval_snippet(source, &CompileOptions::default());
+        assert!(result.diagnostics.is_empty(), "{:?}", result.diagnostics);
+        assert_eq!(result.stdout.trim(), "2");
+
+        let js = String::from_utf8(compile(source, CompileTarget::Js).unwrap()).unwrap();
+        assert!(js.contains("let counter = 0;"));
+        let rust = String::from_utf8(compile(source, CompileTarget::Rust).unwrap()).unwrap();
+        assert!(rust.contains("static counter: std::sync::Mutex<i64> = std::sync::Mutex::new(0);"));
+        compile(source, CompileTarget::Wasm).unwrap();
+
+        let undeclared = eval_snippet("static mut counter: Int = 0\n\nfn bump() with IO:\n    counter = counter + 1\n\nfn main():\n    bump()\n", &CompileOptions::default());
+        assert!(undeclared.diagnostics[0].to_string().contains("'bump' uses static `counter` but does not declare the Global effect"));
+    }
+
+    #[test]
+    fn test_user_effects_and_aliases() {
+        let source = "effect App = IO + Database
+effect Database
+
+fn load(id: Int) -> Int with Database:
+    return id * 2
+
+fn run() -> Int with App:
+    return load(21)
+
+fn main():
+    println(run())
+";
+        let result = eval_snippet(source, &CompileOptions::default());
+        assert!(result.diagnostics.is_empty(), "{:?}", result.diagnostics);
+        assert_eq!(result.stdout.trim(), "42");
+
+        let tokens = Lexer::new(source).tokenize().unwrap();
+        let program = Parser::new(&tokens).parse().unwrap();
+        let Item::Function(run) = &program.items[3] else { panic!("expected run") };
+        assert_eq!(lsp::format_fn_signature(run), "fn run() -> Int with App");
+        let typed = types::check(&program).unwrap();
+        let Some(TypedItem::Function(run)) = typed.items.iter().find(|i| matches!(i, TypedItem::Function(f) if f.ast.name == "run")) else { panic!() };
+        assert_eq!(run.effects.to_string(), "Database, IO");
+
+        let narrower = source.replace("with App:\n    return load", "with IO:\n    return load");
+        let err = compile(&narrower, CompileTarget::Js).unwrap_err();
+        assert!(err.to_string().contains("'run' calls 'load' with Database but only declares IO"), "{}", err);
+
+        let unknown = compile("fn f() with Network:\n    return\n", CompileTarget::Js).unwrap_err();
+        assert!(unknown.to_string().contains("Unknown effect `Network`"), "{}", unknown);
+        let cycle = compile("effect A = IO + B\neffect B = A\n\nfn main():\n    return\n", CompileTarget::Js).unwrap_err();
+        assert!(cycle.to_string().contains("includes itself"), "{}", cycle);
+    }
+}
@@ -1,6 +1,9 @@
 //! Error types for the KAIN compiler
 
+use std::fmt;
+
 use crate::span::Span;
+use crate::Phase;
 use thiserror::Error;
 
 #[derive(Error, Debug)]
@@ -18,11 +21,21 @@ pub enum KainError {
     Effect { message: String, span: Span },
 
     #[error("Borrow error at {span:?}: {message}")]
-    Borrow { message: String, span: Span },
+    Borrow {
+        message: String,
+        span: Span,
+        /// The other side of the conflict, such as where the value moved or was
+        /// first borrowed, and what happened there
+        related: Option<(Span, String)>,
+    },
 
     #[error("Codegen error at {span:?}: {message}")]
     Codegen { message: String, span: Span },
 
+    /// A bug in the compiler rather than the program, reported instead of panicking
+    #[error("Internal compiler error {code} during {phase} at {span:?}: {message}")]
+    Internal { code: Ice, phase: Phase, message: String, span: Span },
+
     #[error("Runtime error: {message}")]
     Runtime { message: String },
 
@@ -30,44 +43,108 @@ pub enum KainError {
     Io(#[from] std::io::Error),
 }
 
+/// Kinds of internal compiler error, each with its own `ICE` code so reports
+/// of the same bug can be matched up. Codes are never reused.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Ice {
+    /// A function is compiled or called before the backend declared it
+    UndeclaredFunction,
+    /// Linear memory is used before the wasm module declared it
+    MissingMemory,
+    /// An actor message is sent or received before the mailbox was declared
+    MissingMailbox,
+    /// An enum variant has no field layout
+    MissingVariantLayout,
+    /// The SPIR-V builder rejected an instruction
+    SpirvBuilder,
+}
+
+impl Ice {
+    pub fn code(self) -> &'static str {
+        match self {
+            Ice::UndeclaredFunction => "ICE0001",
+            Ice::MissingMemory => "ICE0002",
+            Ice::MissingMailbox => "ICE0003",
+            Ice::MissingVariantLayout => "ICE0004",
+            Ice::SpirvBuilder => "ICE0005",
+        }
+    }
+}
+
+impl fmt::Display for Ice {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.code())
+    }
+}
+
+/// The message of a diagnostic that points into the source; debug builds
+/// reject a [`Span::is_placeholder`] span, which would point at nothing
+fn located(message: impl Into<String>, span: Span) -> String {
+    let message = message.into();
+    debug_assert!(!span.is_placeholder(), "diagnostic without a source location: {}", message);
+    message
+}
+
 impl KainError {
     pub fn lexer(message: impl Into<String>, span: Span) -> Self {
         KainError::Lexer {
-            message: message.into(),
+            message: located(message, span),
             span,
         }
     }
 
     pub fn parser(message: impl Into<String>, span: Span) -> Self {
         KainError::Parser {
-            message: message.into(),
+            message: located(message, span),
             span,
         }
     }
 
     pub fn type_error(message: impl Into<String>, span: Span) -> Self {
         KainError::Type {
-            message: message.into(),
+            message: located(message, span),
             span,
         }
     }
 
     pub fn effect_error(message: impl Into<String>, span: Span) -> Self {
         KainError::Effect {
-            message: message.into(),
+            message: located(message, span),
             span,
         }
     }
 
     pub fn borrow_error(message: impl Into<String>, span: Span) -> Self {
         KainError::Borrow {
-            message: message.into(),
+            message: located(message, span),
             span,
+            related: None,
+        }
+    }
+
+    /// A borrow error at `span` that conflicts with what happened at `related`,
+    /// which `note` describes
+    pub fn borrow_conflict(message: impl Into<String>, span: Span, related: Span, note: impl Into<String>) -> Self {
+        KainError::Borrow {
+            message: located(message, span),
+            span,
+            related: Some((related, located(note, related))),
         }
     }
 
     pub fn codegen(message: impl Into<String>, span: Span) -> Self {
         KainError::Codegen {
+            message: located(message, span),
+            span,
+        }
+    }
+
+    /// An internal compiler error in `phase`; `span` is the code being compiled
+    /// when it is known, and may be a placeholder when it is not
+    pub fn internal(code: Ice, phase: Phase, message: impl Into<String>, span: Span) -> Self {
+        KainError::Internal {
+            code,
+            phase,
             message: message.into(),
             span,
         }
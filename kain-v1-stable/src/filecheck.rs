@@ -0,0 +1,184 @@
+//! FileCheck-style codegen tests
+//!
+//! A codegen test is a `.kn` file whose `//` comments describe the expected
+//! backend output, so regressions are caught without running what we emit:
+//!
+//! ```text
+//! // TARGET: js
+//! // CHECK: function add(a, b) {
+//! // CHECK-NEXT: return (a + b)
+//! // CHECK-NOT: println
+//! ```
+//!
+//! * `CHECK:` matches the first later line containing the pattern
+//! * `CHECK-NEXT:` must match the line right after the previous match
+//! * `CHECK-NOT:` must not appear between the surrounding matches
+//!
+//! Runs of whitespace compare equal, as in LLVM's FileCheck.
+//! Tests live under `tests/codegen/` and run with `kain dev filecheck`.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::error::{KainError, KainResult};
+use crate::span::Span;
+use crate::{compile_with_options, CompileOptions, CompileTarget};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum CheckKind {
+    Check,
+    Next,
+    Not,
+}
+
+#[derive(Debug)]
+struct Directive {
+    kind: CheckKind,
+    pattern: String,
+    span: Span,
+}
+
+/// Compile a test file's source and verify its `CHECK` directives
+pub fn check_source(source: &str) -> KainResult<()> {
+    let mut target = None;
+    let mut directives = Vec::new();
+
+    let mut offset = 0;
+    for line in source.split_inclusive('\n') {
+        let start = offset;
+        offset += line.len();
+        let Some(comment) = line.trim_start().strip_prefix("//") else { continue };
+        let comment = comment.trim();
+        let span = Span::new(start, start + line.trim_end().len());
+
+        if let Some(name) = comment.strip_prefix("TARGET:") {
+            target = Some((name.trim().to_string(), span));
+            continue;
+        }
+        let (kind, pattern) = if let Some(p) = comment.strip_prefix("CHECK:") {
+            (CheckKind::Check, p)
+        } else if let Some(p) = comment.strip_prefix("CHECK-NEXT:") {
+            (CheckKind::Next, p)
+        } else if let Some(p) = comment.strip_prefix("CHECK-NOT:") {
+            (CheckKind::Not, p)
+        } else {
+            continue;
+        };
+        directives.push(Directive { kind, pattern: normalize(pattern), span });
+    }
+
+    let (target_name, target_span) = target
+        .ok_or_else(|| KainError::codegen("filecheck test is missing a `// TARGET:` line", Span::new(0, source.len())))?;
+    let target = text_target(&target_name).ok_or_else(|| {
+        KainError::codegen(format!("filecheck cannot check target '{}'", target_name), target_span)
+    })?;
+    if directives.is_empty() {
+        return Err(KainError::codegen("filecheck test has no CHECK directives", target_span));
+    }
+
+    let output = compile_with_options(source, target, &CompileOptions { deterministic: true, ..Default::default() })?;
+    match_directives(&String::from_utf8_lossy(&output), &directives)
+}
+
+/// Run a single test file
+pub fn run_file(path: &Path) -> KainResult<()> {
+    let source = fs::read_to_string(path)?;
+    check_source(&source)
+}
+
+/// Expand directories into the `.kn` files beneath them, in a stable order
+pub fn collect_tests(paths: &[PathBuf]) -> Vec<PathBuf> {
+    let mut files = Vec::new();
+    for path in paths {
+        if path.is_dir() {
+            let mut entries: Vec<PathBuf> = fs::read_dir(path)
+                .map(|rd| rd.filter_map(|e| e.ok()).map(|e| e.path()).collect())
+                .unwrap_or_default();
+            entries.sort();
+            files.extend(collect_tests(&entries));
+        } else if path.extension().is_some_and(|ext| ext == "kn") {
+            files.push(path.clone());
+        }
+    }
+    files
+}
+
+/// Targets whose output is text we can match against
+fn text_target(name: &str) -> Option<CompileTarget> {
+    match name {
+        "wat" => Some(CompileTarget::Wat),
+        "js" => Some(CompileTarget::Js),
+        "rust" => Some(CompileTarget::Rust),
+        "hlsl" => Some(CompileTarget::Hlsl),
+        "usf" => Some(CompileTarget::Usf),
+        "llvm" => Some(CompileTarget::Llvm),
+        "hybrid" => Some(CompileTarget::Hybrid),
+        _ => None,
+    }
+}
+
+fn normalize(s: &str) -> String {
+    s.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+fn match_directives(output: &str, directives: &[Directive]) -> KainResult<()> {
+    let lines: Vec<String> = output.lines().map(normalize).collect();
+    // Next line to search from, and the CHECK-NOTs waiting for the next match
+    let mut pos = 0;
+    let mut pending_not: Vec<&Directive> = Vec::new();
+
+    for d in directives {
+        let found = match d.kind {
+            CheckKind::Not => {
+                pending_not.push(d);
+                continue;
+            }
+            CheckKind::Check => (pos..lines.len()).find(|&i| lines[i].contains(&d.pattern)),
+            CheckKind::Next => (pos < lines.len() && pos > 0 && lines[pos].contains(&d.pattern)).then_some(pos),
+        };
+        let Some(line) = found else {
+            let what = if d.kind == CheckKind::Next { "CHECK-NEXT" } else { "CHECK" };
+            return Err(KainError::codegen(format!("{}: no match for '{}'", what, d.pattern), d.span));
+        };
+        check_absent(&lines[pos..line], &pending_not)?;
+        pending_not.clear();
+        pos = line + 1;
+    }
+    check_absent(&lines[pos.min(lines.len())..], &pending_not)
+}
+
+fn check_absent(lines: &[String], nots: &[&Directive]) -> KainResult<()> {
+    for d in nots {
+        if lines.iter().any(|l| l.contains(&d.pattern)) {
+            return Err(KainError::codegen(format!("CHECK-NOT: found '{}'", d.pattern), d.span));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_filecheck_directives() {
+        let src = "// TARGET: js\n// CHECK: function add(a, b)\n// CHECK-NEXT: return (a + b) ;\n// CHECK-NOT: add(1\n// CHECK: function main\n\nfn add(a: Int, b: Int) -> Int:\n    return a + b\n\nfn main():\n    add(1, 2)\n";
+        assert!(check_source(src).is_ok());
+
+        let bad_next = src.replace("CHECK-NEXT: return", "CHECK-NEXT: yield");
+        assert!(check_source(&bad_next).is_err());
+
+        let bad_not = src.replace("CHECK-NOT: add(1", "CHECK-NOT: }");
+        assert!(check_source(&bad_not).is_err());
+    }
+
+    #[test]
+    fn test_codegen_suite() {
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests").join("codegen");
+        for file in collect_tests(&[dir]) {
+            if let Err(e) = run_file(&file) {
+                panic!("{}: {}", file.display(), e);
+            }
+        }
+    }
+}
@@ -0,0 +1,98 @@
+//! `kain fix`: apply machine-applicable suggestions to source files
+//!
+//! Suggestions come from lints (today: edition migrations). Each one replaces
+//! a span of the original source; they are applied back to front so earlier
+//! spans stay valid, and a suggestion overlapping one already taken is skipped
+//! rather than guessed at. Fixed code produces no further suggestions, so
+//! running `kain fix` twice changes nothing the second time.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::diagnostics::Suggestion;
+use crate::edition::{migration_lints, Edition};
+
+/// Apply `suggestions` to `source`, returning the new text and how many were applied
+pub fn apply(source: &str, suggestions: &[Suggestion]) -> (String, usize) {
+    let mut sorted: Vec<&Suggestion> = suggestions.iter().collect();
+    sorted.sort_by_key(|s| (s.span.start, s.span.end));
+
+    // Keep the first of any overlapping suggestions
+    let mut accepted: Vec<&Suggestion> = Vec::new();
+    for suggestion in sorted {
+        let in_bounds = suggestion.span.start <= suggestion.span.end
+            && source.is_char_boundary(suggestion.span.start)
+            && source.is_char_boundary(suggestion.span.end);
+        let overlaps = accepted.last().is_some_and(|prev| suggestion.span.start < prev.span.end);
+        if in_bounds && !overlaps {
+            accepted.push(suggestion);
+        }
+    }
+
+    let mut fixed = source.to_string();
+    for suggestion in accepted.iter().rev() {
+        fixed.replace_range(suggestion.span.start..suggestion.span.end, &suggestion.replacement);
+    }
+    (fixed, accepted.len())
+}
+
+/// Apply every migration from `edition` to the latest edition
+pub fn fix_source(source: &str, edition: Edition) -> (String, usize) {
+    let suggestions: Vec<Suggestion> = migration_lints(source, edition)
+        .into_iter()
+        .filter_map(|lint| lint.suggestion)
+        .collect();
+    apply(source, &suggestions)
+}
+
+/// Expand directories into the `.kn` files beneath them, skipping hidden and `target` directories
+pub fn collect_sources(paths: &[PathBuf]) -> Vec<PathBuf> {
+    let mut files = Vec::new();
+    for path in paths {
+        if path.is_dir() {
+            let mut entries: Vec<PathBuf> = fs::read_dir(path)
+                .map(|rd| rd.filter_map(|e| e.ok()).map(|e| e.path()).collect())
+                .unwrap_or_default();
+            entries.retain(|p| !p.is_dir() || !is_skipped_dir(p));
+            entries.sort();
+            files.extend(collect_sources(&entries));
+        } else if path.extension().is_some_and(|ext| ext == "kn") {
+            files.push(path.clone());
+        }
+    }
+    files
+}
+
+fn is_skipped_dir(path: &Path) -> bool {
+    path.file_name()
+        .and_then(|n| n.to_str())
+        .is_some_and(|n| n.starts_with('.') || n == "target")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::Lexer;
+    use crate::span::Span;
+
+    #[test]
+    fn test_fix_is_idempotent() {
+        let source = "# header\nfn main():\n    println(\"# kept\") # trailing\n";
+        let (fixed, applied) = fix_source(source, Edition::V0_1);
+        assert_eq!(applied, 2);
+        assert_eq!(fixed, "// header\nfn main():\n    println(\"# kept\") // trailing\n");
+        assert!(Lexer::with_edition(&fixed, Edition::LATEST).tokenize().is_ok());
+        assert_eq!(fix_source(&fixed, Edition::V0_1), (fixed.clone(), 0));
+    }
+
+    #[test]
+    fn test_overlapping_suggestions_are_skipped() {
+        let edit = |start, end, replacement: &str| Suggestion {
+            message: String::new(),
+            span: Span::new(start, end),
+            replacement: replacement.to_string(),
+        };
+        let (fixed, applied) = apply("abcdef", &[edit(4, 6, "X"), edit(0, 3, "Y"), edit(2, 4, "Z")]);
+        assert_eq!((fixed.as_str(), applied), ("YdX", 2));
+    }
+}
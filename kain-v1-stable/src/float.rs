@@ -0,0 +1,98 @@
+//! How a Float is written out and read back, the same on every backend
+//!
+//! Floats print as ECMAScript's `Number::toString` prints them, which the JS
+//! and wasm backends get from the engine for free: the fewest digits that
+//! read back as the same double, plain decimals from `1e-7` up to `1e21`,
+//! exponents (`1e+21`, `1.5e-7`) outside that, and no `.0` on whole numbers.
+//!
+//! ```text
+//! 0.1 + 0.2   0.30000000000000004
+//! 6.0         6
+//! 1e21        1e+21
+//! -0.0        0
+//! 1.0 / 0.0   Infinity
+//! ```
+//!
+//! The interpreter formats with [`format`], and the C runtime the llvm target
+//! links with has a port of it, `print_f64` in `runtime/KAIN_runtime.c`.
+
+/// The shortest text that reads back as `value`
+pub fn format(value: f64) -> String {
+    if value.is_nan() {
+        return "NaN".to_string();
+    }
+    if value.is_infinite() {
+        return if value > 0.0 { "Infinity" } else { "-Infinity" }.to_string();
+    }
+    if value == 0.0 {
+        return "0".to_string();
+    }
+    let sign = if value < 0.0 { "-" } else { "" };
+
+    // Rust's `{:e}` is already the shortest round trip, as `d.ddde-N`
+    let scientific = format!("{:e}", value.abs());
+    let (mantissa, exponent) = scientific.split_once('e').unwrap_or((&scientific, "0"));
+    let digits: String = mantissa.chars().filter(|c| *c != '.').collect();
+    let k = digits.len() as i32;
+    // The value is 0.<digits> * 10^n
+    let n = exponent.parse::<i32>().unwrap_or(0) + 1;
+
+    let body = if k <= n && n <= 21 {
+        format!("{}{}", digits, "0".repeat((n - k) as usize))
+    } else if 0 < n && n <= 21 {
+        format!("{}.{}", &digits[..n as usize], &digits[n as usize..])
+    } else if -6 < n && n <= 0 {
+        format!("0.{}{}", "0".repeat(-n as usize), digits)
+    } else {
+        let fraction = if k > 1 { format!(".{}", &digits[1..]) } else { String::new() };
+        format!("{}{}e{}{}", &digits[..1], fraction, if n > 0 { "+" } else { "-" }, (n - 1).abs())
+    };
+    format!("{}{}", sign, body)
+}
+
+/// Read a Float as `float(text)` does: anything [`format`] writes, and decimal
+/// literals with surrounding whitespace
+pub fn parse(text: &str) -> Option<f64> {
+    match text.trim() {
+        "Infinity" | "+Infinity" => Some(f64::INFINITY),
+        "-Infinity" => Some(f64::NEG_INFINITY),
+        "NaN" => Some(f64::NAN),
+        // Rust also takes `inf` and `nan`, which no backend writes
+        trimmed if trimmed.chars().any(|c| c.is_ascii_alphabetic() && !matches!(c, 'e' | 'E')) => None,
+        trimmed => trimmed.parse().ok(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_floats_print_like_ecmascript_and_read_back() {
+        let cases = [
+            (0.1 + 0.2, "0.30000000000000004"),
+            (6.0, "6"),
+            (2.5, "2.5"),
+            (-1.25, "-1.25"),
+            (-0.0, "0"),
+            (1e21, "1e+21"),
+            (123456789012345680000.0, "123456789012345680000"),
+            (1e-7, "1e-7"),
+            (1.5e-7, "1.5e-7"),
+            (0.000001, "0.000001"),
+            (5e-324, "5e-324"),
+            (f64::MAX, "1.7976931348623157e+308"),
+            (f64::INFINITY, "Infinity"),
+            (f64::NEG_INFINITY, "-Infinity"),
+            (f64::NAN, "NaN"),
+        ];
+        for (value, text) in cases {
+            assert_eq!(format(value), text);
+            let back = parse(text).unwrap();
+            assert!(back == value || (back.is_nan() && value.is_nan()), "{} read back as {}", text, back);
+        }
+        assert_eq!(parse(" 1.5 "), Some(1.5));
+        assert_eq!(parse("inf"), None);
+        assert_eq!(parse("1.5x"), None);
+    }
+}
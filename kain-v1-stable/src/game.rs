@@ -0,0 +1,298 @@
+//! Windows, input and audio for `std/game`
+//!
+//! Built with `--features game`, the interpreter can open one window per
+//! thread, draw [`crate::image`] images into it and poll the keyboard, mouse
+//! and gamepads, with winit, softbuffer, gilrs and rodio underneath. A game
+//! loop pumps the window's events once per frame:
+//!
+//! ```ignore
+//! fn main() with IO:
+//!     game_open("Pong", 640, 480)
+//!     let frame = image_new(640, 480)
+//!     while game_poll():
+//!         if game_key_down("escape"):
+//!             break
+//!         game_present(frame)
+//! ```
+//!
+//! Keys are named by what they type, lowercased (`"w"`, `"1"`), or by winit's
+//! name for keys that type nothing (`"space"`, `"arrowleft"`, `"shift"`).
+//! Mouse buttons are `"left"`, `"right"` and `"middle"`; gamepad buttons and
+//! axes use gilrs's names (`"south"`, `"dpadup"`, `"leftstickx"`).
+//!
+//! Window, gamepad and audio state live on the thread that opened them, since
+//! they can't move between threads; an actor that owns the window runs the
+//! whole loop. Without the feature every native fails with a message saying how
+//! to enable it.
+
+use std::cell::RefCell;
+use std::collections::HashSet;
+use std::fs::File;
+use std::io::BufReader;
+use std::num::NonZeroU32;
+use std::rc::Rc;
+use std::time::Duration;
+
+use gilrs::Gilrs;
+use rodio::{Decoder, OutputStream, OutputStreamHandle, Sink};
+use softbuffer::{Context, Surface};
+use winit::application::ApplicationHandler;
+use winit::event::{ElementState, MouseButton, WindowEvent};
+use winit::event_loop::{ActiveEventLoop, EventLoop};
+use winit::keyboard::Key;
+use winit::platform::pump_events::{EventLoopExtPumpEvents, PumpStatus};
+use winit::window::{Window, WindowAttributes, WindowId};
+
+thread_local! {
+    static GAME: RefCell<Option<Game>> = const { RefCell::new(None) };
+    /// Opened the first time a gamepad is asked about
+    static GAMEPADS: RefCell<Option<Gilrs>> = const { RefCell::new(None) };
+    /// Opened by the first sound, and kept because it has to outlive every sound played on it
+    static AUDIO: RefCell<Option<(OutputStream, OutputStreamHandle)>> = const { RefCell::new(None) };
+}
+
+/// The window opened on this thread
+struct Game {
+    event_loop: EventLoop<()>,
+    app: App,
+}
+
+/// Receives the window's events and keeps the input state they describe
+struct App {
+    attributes: WindowAttributes,
+    window: Option<Rc<Window>>,
+    surface: Option<Surface<Rc<Window>, Rc<Window>>>,
+    keys: HashSet<String>,
+    buttons: HashSet<String>,
+    mouse: (f64, f64),
+    closed: bool,
+    error: Option<String>,
+}
+
+impl ApplicationHandler for App {
+    fn resumed(&mut self, event_loop: &ActiveEventLoop) {
+        if self.window.is_some() {
+            return;
+        }
+        let window = match event_loop.create_window(self.attributes.clone()) {
+            Ok(window) => Rc::new(window),
+            Err(e) => {
+                self.error = Some(e.to_string());
+                return;
+            }
+        };
+        match Context::new(window.clone()).and_then(|context| Surface::new(&context, window.clone())) {
+            Ok(surface) => self.surface = Some(surface),
+            Err(e) => self.error = Some(e.to_string()),
+        }
+        self.window = Some(window);
+    }
+
+    fn window_event(&mut self, _event_loop: &ActiveEventLoop, _id: WindowId, event: WindowEvent) {
+        match event {
+            WindowEvent::CloseRequested => self.closed = true,
+            WindowEvent::KeyboardInput { event, .. } => {
+                let name = key_name(&event.logical_key);
+                match event.state {
+                    ElementState::Pressed => self.keys.insert(name),
+                    ElementState::Released => self.keys.remove(&name),
+                };
+            }
+            WindowEvent::CursorMoved { position, .. } => self.mouse = (position.x, position.y),
+            WindowEvent::MouseInput { state, button, .. } => {
+                let name = match button {
+                    MouseButton::Left => "left".to_string(),
+                    MouseButton::Right => "right".to_string(),
+                    MouseButton::Middle => "middle".to_string(),
+                    other => format!("{:?}", other).to_lowercase(),
+                };
+                match state {
+                    ElementState::Pressed => self.buttons.insert(name),
+                    ElementState::Released => self.buttons.remove(&name),
+                };
+            }
+            // Keys held while the window loses focus never report their release
+            WindowEvent::Focused(false) => {
+                self.keys.clear();
+                self.buttons.clear();
+            }
+            _ => {}
+        }
+    }
+}
+
+fn key_name(key: &Key) -> String {
+    match key {
+        Key::Character(text) => text.to_lowercase(),
+        Key::Named(named) => format!("{:?}", named).to_lowercase(),
+        other => format!("{:?}", other).to_lowercase(),
+    }
+}
+
+fn with_game<T>(native: &str, f: impl FnOnce(&mut Game) -> Result<T, String>) -> Result<T, String> {
+    GAME.with(|game| match game.borrow_mut().as_mut() {
+        Some(game) => f(game),
+        None => Err(format!("{}: no window open, call game_open first", native)),
+    })
+}
+
+/// Open a `width` x `height` window titled `title`
+pub fn open(title: &str, width: u32, height: u32) -> Result<(), String> {
+    GAME.with(|game| {
+        let mut game = game.borrow_mut();
+        if game.is_some() {
+            return Err("game_open: this thread already opened a window".to_string());
+        }
+        let mut event_loop = EventLoop::new().map_err(|e| format!("game_open: {}", e))?;
+        let mut app = App {
+            attributes: Window::default_attributes()
+                .with_title(title)
+                .with_inner_size(winit::dpi::LogicalSize::new(width, height)),
+            window: None,
+            surface: None,
+            keys: HashSet::new(),
+            buttons: HashSet::new(),
+            mouse: (0.0, 0.0),
+            closed: false,
+            error: None,
+        };
+        // The window is made when the event loop resumes, within its first few events
+        for _ in 0..100 {
+            event_loop.pump_app_events(Some(Duration::from_millis(10)), &mut app);
+            if let Some(e) = app.error.take() {
+                return Err(format!("game_open: {}", e));
+            }
+            if app.window.is_some() {
+                *game = Some(Game { event_loop, app });
+                return Ok(());
+            }
+        }
+        Err("game_open: the window system never created the window".to_string())
+    })
+}
+
+/// Handle the events since the last poll; false once the window is closed
+pub fn poll() -> Result<bool, String> {
+    with_game("game_poll", |game| {
+        if let PumpStatus::Exit(_) = game.event_loop.pump_app_events(Some(Duration::ZERO), &mut game.app) {
+            game.app.closed = true;
+        }
+        Ok(!game.app.closed)
+    })
+}
+
+pub fn key_down(name: &str) -> Result<bool, String> {
+    with_game("game_key_down", |game| Ok(game.app.keys.contains(&name.to_lowercase())))
+}
+
+pub fn mouse_down(button: &str) -> Result<bool, String> {
+    with_game("game_mouse_down", |game| Ok(game.app.buttons.contains(&button.to_lowercase())))
+}
+
+/// The cursor's position in pixels from the window's top left
+pub fn mouse() -> Result<(f64, f64), String> {
+    with_game("game_mouse", |game| Ok(game.app.mouse))
+}
+
+/// Draw `rgba`, a `width` x `height` image, scaled to fill the window
+pub fn present(width: usize, height: usize, rgba: &[u8]) -> Result<(), String> {
+    with_game("game_present", |game| {
+        let (Some(window), Some(surface)) = (&game.app.window, &mut game.app.surface) else {
+            return Err("game_present: the window has no surface to draw on".to_string());
+        };
+        let size = window.inner_size();
+        let (Some(w), Some(h)) = (NonZeroU32::new(size.width), NonZeroU32::new(size.height)) else {
+            // Minimized
+            return Ok(());
+        };
+        surface.resize(w, h).map_err(|e| format!("game_present: {}", e))?;
+        let mut buffer = surface.buffer_mut().map_err(|e| format!("game_present: {}", e))?;
+        let (w, h) = (size.width as usize, size.height as usize);
+        for y in 0..h {
+            let row = y * height / h * width;
+            for x in 0..w {
+                let at = (row + x * width / w) * 4;
+                let [r, g, b] = [rgba[at] as u32, rgba[at + 1] as u32, rgba[at + 2] as u32];
+                buffer[y * w + x] = (r << 16) | (g << 8) | b;
+            }
+        }
+        buffer.present().map_err(|e| format!("game_present: {}", e))
+    })
+}
+
+/// Run `f` on the gamepads, updated with the events since the last call
+fn with_gamepads<T>(native: &str, f: impl FnOnce(&Gilrs) -> Result<T, String>) -> Result<T, String> {
+    GAMEPADS.with(|gamepads| {
+        let mut gamepads = gamepads.borrow_mut();
+        if gamepads.is_none() {
+            *gamepads = Some(Gilrs::new().map_err(|e| format!("{}: {}", native, e))?);
+        }
+        let gamepads = gamepads.as_mut().expect("opened above");
+        while gamepads.next_event().is_some() {}
+        f(gamepads)
+    })
+}
+
+/// Whether `button` is held on the `pad`th connected gamepad; false if there is no such pad
+pub fn gamepad_down(pad: usize, button: &str) -> Result<bool, String> {
+    let button = match button.to_lowercase().as_str() {
+        "south" => gilrs::Button::South,
+        "east" => gilrs::Button::East,
+        "north" => gilrs::Button::North,
+        "west" => gilrs::Button::West,
+        "lefttrigger" => gilrs::Button::LeftTrigger,
+        "lefttrigger2" => gilrs::Button::LeftTrigger2,
+        "righttrigger" => gilrs::Button::RightTrigger,
+        "righttrigger2" => gilrs::Button::RightTrigger2,
+        "select" => gilrs::Button::Select,
+        "start" => gilrs::Button::Start,
+        "mode" => gilrs::Button::Mode,
+        "leftthumb" => gilrs::Button::LeftThumb,
+        "rightthumb" => gilrs::Button::RightThumb,
+        "dpadup" => gilrs::Button::DPadUp,
+        "dpaddown" => gilrs::Button::DPadDown,
+        "dpadleft" => gilrs::Button::DPadLeft,
+        "dpadright" => gilrs::Button::DPadRight,
+        other => return Err(format!("game_gamepad_down: unknown button '{}'", other)),
+    };
+    with_gamepads("game_gamepad_down", |gamepads| {
+        Ok(gamepads.gamepads().nth(pad).is_some_and(|(_, gamepad)| gamepad.is_pressed(button)))
+    })
+}
+
+/// Position of `axis` on the `pad`th connected gamepad, from -1.0 to 1.0
+pub fn gamepad_axis(pad: usize, axis: &str) -> Result<f64, String> {
+    let axis = match axis.to_lowercase().as_str() {
+        "leftstickx" => gilrs::Axis::LeftStickX,
+        "leftsticky" => gilrs::Axis::LeftStickY,
+        "rightstickx" => gilrs::Axis::RightStickX,
+        "rightsticky" => gilrs::Axis::RightStickY,
+        "leftz" => gilrs::Axis::LeftZ,
+        "rightz" => gilrs::Axis::RightZ,
+        "dpadx" => gilrs::Axis::DPadX,
+        "dpady" => gilrs::Axis::DPadY,
+        other => return Err(format!("game_gamepad_axis: unknown axis '{}'", other)),
+    };
+    with_gamepads("game_gamepad_axis", |gamepads| {
+        Ok(gamepads.gamepads().nth(pad).map_or(0.0, |(_, gamepad)| gamepad.value(axis) as f64))
+    })
+}
+
+/// Start playing the sound file at `path` (WAV, OGG, FLAC or MP3) at `volume`
+/// (1.0 as recorded), without waiting for it to finish
+pub fn play_sound(path: &str, volume: f32) -> Result<(), String> {
+    AUDIO.with(|audio| {
+        let mut audio = audio.borrow_mut();
+        if audio.is_none() {
+            *audio = Some(OutputStream::try_default().map_err(|e| format!("game_play_sound: {}", e))?);
+        }
+        let (_, handle) = audio.as_ref().expect("opened above");
+        let file = File::open(path).map_err(|e| format!("game_play_sound: {}: {}", path, e))?;
+        let source = Decoder::new(BufReader::new(file)).map_err(|e| format!("game_play_sound: {}: {}", path, e))?;
+        let sink = Sink::try_new(handle).map_err(|e| format!("game_play_sound: {}", e))?;
+        sink.set_volume(volume);
+        sink.append(source);
+        sink.detach();
+        Ok(())
+    })
+}
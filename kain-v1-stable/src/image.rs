@@ -0,0 +1,115 @@
+//! Images for `std/image`
+//!
+//! `image_new(w, h)` makes an 8-bit RGBA image and `image_new_float(w, h)` a
+//! Float framebuffer, for shader simulations that accumulate light outside
+//! 0..1. Both are structs `{ width, height, pixels }` with four channels per
+//! pixel in `pixels`, row by row from the top left, so programs can also read
+//! and write them directly. `image_save_png` clamps a framebuffer to 0..1
+//! before writing it:
+//!
+//! ```ignore
+//! let img = image_new_float(256, 256)
+//! for y in 0..256:
+//!     for x in 0..256:
+//!         image_set_pixel(img, x, y, x / 255.0, y / 255.0, 0.5)
+//! image_save_png(img, "gradient.png")
+//! ```
+
+use std::io::Write;
+
+use flate2::write::ZlibEncoder;
+use flate2::{Compression, Crc};
+
+use crate::runtime::Value;
+
+/// Struct name of 8-bit images, whose channels are Ints from 0 to 255
+pub const IMAGE: &str = "Image";
+/// Struct name of Float framebuffers, whose channels are 0.0 to 1.0 when shown
+pub const FLOAT_IMAGE: &str = "FloatImage";
+
+/// One channel of a pixel as a byte: Ints are clamped to 0..=255, Floats to 0..=1 and scaled
+pub fn channel_to_u8(value: &Value) -> Result<u8, String> {
+    match value {
+        Value::Int(n) => Ok((*n).clamp(0, 255) as u8),
+        Value::Float(f) if f.is_nan() => Ok(0),
+        Value::Float(f) => Ok((f.clamp(0.0, 1.0) * 255.0).round() as u8),
+        other => Err(format!("pixel channels must be numbers, found {}", other)),
+    }
+}
+
+/// Encode 8-bit RGBA pixels as a PNG file
+pub fn encode_png(width: u32, height: u32, rgba: &[u8]) -> Vec<u8> {
+    let row = width as usize * 4;
+    // Every scanline starts with its filter type; 0 stores the bytes unfiltered
+    let mut scanlines = Vec::with_capacity((row + 1) * height as usize);
+    for line in rgba.chunks(row.max(1)).take(height as usize) {
+        scanlines.push(0);
+        scanlines.extend_from_slice(line);
+    }
+    let mut zlib = ZlibEncoder::new(Vec::new(), Compression::default());
+    zlib.write_all(&scanlines).expect("writing to a Vec cannot fail");
+    let data = zlib.finish().expect("writing to a Vec cannot fail");
+
+    let mut header = Vec::with_capacity(13);
+    header.extend_from_slice(&width.to_be_bytes());
+    header.extend_from_slice(&height.to_be_bytes());
+    // Bit depth 8, color type 6 (RGBA), default compression, filtering and no interlacing
+    header.extend_from_slice(&[8, 6, 0, 0, 0]);
+
+    let mut png = b"\x89PNG\r\n\x1a\n".to_vec();
+    chunk(&mut png, b"IHDR", &header);
+    chunk(&mut png, b"IDAT", &data);
+    chunk(&mut png, b"IEND", &[]);
+    png
+}
+
+/// Append a chunk: its length, type, data, then a CRC of the type and data
+fn chunk(png: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    png.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    png.extend_from_slice(kind);
+    png.extend_from_slice(data);
+    let mut crc = Crc::new();
+    crc.update(kind);
+    crc.update(data);
+    png.extend_from_slice(&crc.sum().to_be_bytes());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{eval_snippet, CompileOptions};
+    use flate2::read::ZlibDecoder;
+    use std::io::Read;
+
+    /// The scanlines of a PNG written by `encode_png`, which has a single IDAT chunk
+    fn scanlines(png: &[u8]) -> Vec<u8> {
+        let start = png.windows(4).position(|w| w == b"IDAT").unwrap();
+        let len = u32::from_be_bytes(png[start - 4..start].try_into().unwrap()) as usize;
+        let mut out = Vec::new();
+        ZlibDecoder::new(&png[start + 4..start + 4 + len]).read_to_end(&mut out).unwrap();
+        out
+    }
+
+    #[test]
+    fn test_png_encoding_and_image_natives() {
+        let png = encode_png(2, 1, &[255, 0, 0, 255, 0, 0, 255, 128]);
+        assert_eq!(&png[..8], b"\x89PNG\r\n\x1a\n");
+        assert_eq!(&png[12..16], b"IHDR");
+        // IHDR's CRC, as every PNG decoder checks it
+        assert_eq!(&png[29..33], &[0xf4, 0x22, 0x7f, 0x8a]);
+        assert_eq!(scanlines(&png), vec![0, 255, 0, 0, 255, 0, 0, 255, 128]);
+        assert!(png.ends_with(&[0, 0, 0, 0, b'I', b'E', b'N', b'D', 0xae, 0x42, 0x60, 0x82]));
+
+        let path = std::env::temp_dir().join(format!("kain-image-{}.png", std::process::id()));
+        let source = format!(
+            "fn main():\n    let img = image_new_float(2, 1)\n    image_set_pixel(img, 0, 0, 2.0, 0.5, 0.0)\n    image_set_pixel(img, 1, 0, 0.0, 0.0, 1.0, 0.0)\n    println(str(image_get_pixel(img, 0, 0)))\n    image_save_png(img, \"{}\")\n    let bytes = image_new(1, 1)\n    image_set_pixel(bytes, 0, 0, 1, 2, 3)\n    println(str(image_get_pixel(bytes, 0, 0)))\n    image_set_pixel(bytes, 1, 0, 0, 0, 0)\n",
+            path.display().to_string().replace('\\', "/")
+        );
+        let result = eval_snippet(&source, &CompileOptions::default());
+        assert_eq!(result.stdout.lines().collect::<Vec<_>>(), ["(2, 0.5, 0, 1)", "(1, 2, 3, 255)"]);
+        assert!(result.diagnostics[0].to_string().contains("image_set_pixel: (1, 0) is outside the 1x1 image"));
+        let png = std::fs::read(&path).unwrap();
+        let _ = std::fs::remove_file(&path);
+        assert_eq!(scanlines(&png), vec![0, 255, 128, 0, 255, 0, 0, 255, 0]);
+    }
+}
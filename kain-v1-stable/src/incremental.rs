@@ -0,0 +1,491 @@
+//! Incremental compilation: work is only redone for modules that changed
+//!
+//! A module is known by a hash of its text. A program's *fingerprint* adds the
+//! hash of every module the entry imports, directly or not, so editing any of
+//! them changes the fingerprint, and editing a module nothing imports does not.
+//!
+//! There are two layers:
+//!
+//! * [`Handle`] is an in-memory cache for a process that compiles the same
+//!   program again and again, such as watch mode. When it is set as
+//!   [`CompileOptions::cache`], the entry's parse is kept while its text stays
+//!   the same, and the lowered program is kept for each target while the
+//!   fingerprint stays the same. When it changes, each module linked into the
+//!   program keeps its typed items while it reads the same after comptime and
+//!   the declarations it is checked against (see [`ItemContext::fingerprint`])
+//!   do too, so editing one function body checks that module again and no
+//!   other. Imported modules keep their parse in the interpreter's module
+//!   cache; this cache remembers what each one imports.
+//! * A [`Record`] is written to `.incremental/` in the profile's output
+//!   directory for each artifact `kain build` writes. The next build skips a
+//!   target when the fingerprint, the compiler and the options all match and
+//!   the artifact is still the one written, without parsing anything.
+//!
+//! A program with comptime file or network access can depend on anything, so
+//! its lowering is never cached.
+
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use crate::ast::{Item, Program};
+use crate::edition::Edition;
+use crate::error::KainResult;
+use crate::stamp::fnv1a;
+use crate::types::{ItemContext, Symbol, TypedItem, TypedProgram};
+use crate::{vfs, CompileOptions, CompileTarget, Parser, Phase};
+
+/// A module the entry imports, and the hash of its text when it was read
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Dependency {
+    /// The path `use` names it by
+    pub name: String,
+    /// `None` when it could not be found, which it may be by the next build
+    pub file: Option<PathBuf>,
+    pub hash: u64,
+}
+
+/// The in-memory cache, shared by every clone of the handle
+#[derive(Clone, Default)]
+pub struct Handle(Arc<Mutex<Cache>>);
+
+#[derive(Default)]
+struct Cache {
+    /// Where `use` paths are resolved from; the working directory when empty
+    root: PathBuf,
+    /// Entry programs by the file they were read from, `None` for text from elsewhere
+    entries: HashMap<Option<PathBuf>, Entry>,
+    /// What each imported module imports, by the hash of its text
+    modules: HashMap<PathBuf, (u64, Vec<String>)>,
+    /// The typed items of each module of the programs last checked, by entry
+    /// and lowering key (see [`option_keys`]), then by [`module_key`]
+    checked: HashMap<(Option<PathBuf>, u64), HashMap<u64, Vec<TypedItem>>>,
+    /// How many items have been type checked, for telling a kept module from a checked one
+    item_checks: usize,
+}
+
+struct Entry {
+    hash: u64,
+    /// The parse key (see [`option_keys`]) it was parsed with
+    parse: u64,
+    program: Arc<Program>,
+    /// Lowered programs by lowering key, with the fingerprint they were lowered at
+    lowered: HashMap<u64, (u64, TypedProgram, Vec<Symbol>)>,
+}
+
+impl Handle {
+    /// A cache resolving imports from `root` instead of the working directory
+    pub fn in_dir(root: impl Into<PathBuf>) -> Self {
+        Handle(Arc::new(Mutex::new(Cache { root: root.into(), ..Cache::default() })))
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Cache> {
+        self.0.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Lex, parse and lower `source` for `target`, or reuse what an earlier
+    /// compile of the same program did. Phases that run are added to `timings`.
+    pub(crate) fn lower(
+        &self,
+        source: &str,
+        target: CompileTarget,
+        options: &CompileOptions,
+        timings: &mut crate::Timings,
+    ) -> KainResult<(TypedProgram, Vec<Symbol>)> {
+        let hash = fnv1a(source.as_bytes());
+        let path = options.source.clone();
+        let (parse, key) = option_keys(target, options);
+        let cached = self
+            .lock()
+            .entries
+            .get(&path)
+            .filter(|e| e.hash == hash && e.parse == parse)
+            .map(|e| e.program.clone());
+        let program = match cached {
+            Some(program) => program,
+            None => {
+                let tokens = crate::timed(timings, Phase::Lex, || crate::source_lexer(source, options).tokenize())?;
+                let program = Arc::new(crate::timed(timings, Phase::Parse, || Parser::new(&tokens).parse())?);
+                let entry = Entry { hash, parse, program: program.clone(), lowered: HashMap::new() };
+                self.lock().entries.insert(path.clone(), entry);
+                program
+            }
+        };
+        if options.allow_comptime_io {
+            return crate::lower_timed((*program).clone(), target, options, timings);
+        }
+
+        let fingerprint = self.fingerprint(hash, &program, options.edition);
+        if let Some((_, typed, symbols)) = self.lock().entries.get(&path).and_then(|e| e.lowered.get(&key)).filter(|l| l.0 == fingerprint) {
+            return Ok((typed.clone(), symbols.clone()));
+        }
+        // Lowering runs unlocked, since comptime code may compile programs of its own
+        let (typed, symbols) = crate::lower_timed((*program).clone(), target, options, timings)?;
+        if let Some(entry) = self.lock().entries.get_mut(&path).filter(|e| e.hash == hash) {
+            entry.lowered.insert(key, (fingerprint, typed.clone(), symbols.clone()));
+        }
+        Ok((typed, symbols))
+    }
+
+    /// Type check `program` as [`crate::types::check`] does, keeping the typed
+    /// items of each of its modules that reads as it did when the same entry
+    /// was last checked for the same target and options
+    pub(crate) fn check(&self, program: &Program, target: CompileTarget, options: &CompileOptions) -> KainResult<TypedProgram> {
+        let mut context = ItemContext::new(program)?;
+        let bucket = (options.source.clone(), option_keys(target, options).1);
+        let mut previous = self.lock().checked.remove(&bucket).unwrap_or_default();
+        let mut checked = HashMap::new();
+        let mut items = Vec::new();
+        let mut item_checks = 0;
+        for module in modules(&program.items) {
+            let key = module_key(module, context.fingerprint());
+            let typed = match previous.remove(&key) {
+                Some(typed) => typed,
+                None => {
+                    let mut typed = Vec::new();
+                    for item in module {
+                        crate::crash::at(item.span());
+                        item_checks += 1;
+                        match context.check(item) {
+                            Ok(item) => typed.extend(item),
+                            Err(e) => {
+                                // What was checked so far is still good for the next try
+                                checked.extend(previous);
+                                let mut cache = self.lock();
+                                cache.checked.insert(bucket, checked);
+                                cache.item_checks += item_checks;
+                                return Err(e);
+                            }
+                        }
+                    }
+                    typed
+                }
+            };
+            items.extend(typed.iter().cloned());
+            checked.insert(key, typed);
+        }
+        let mut cache = self.lock();
+        cache.checked.insert(bucket, checked);
+        cache.item_checks += item_checks;
+        Ok(TypedProgram { items })
+    }
+
+    /// The fingerprint of a program whose entry module is `program`, with text hashing to `hash`
+    pub fn fingerprint(&self, hash: u64, program: &Program, edition: Edition) -> u64 {
+        fingerprint(hash, &self.dependencies(program, edition))
+    }
+
+    /// Every module `program` imports, directly or not, sorted by name
+    pub fn dependencies(&self, program: &Program, edition: Edition) -> Vec<Dependency> {
+        let mut cache = self.lock();
+        let root = cache.root.clone();
+        let mut found = BTreeMap::new();
+        let mut queue: VecDeque<(String, Option<PathBuf>)> = imports(program).into_iter().map(|name| (name, None)).collect();
+        let mut seen: HashSet<String> = queue.iter().map(|(name, _)| name.clone()).collect();
+        while let Some((name, importer)) = queue.pop_front() {
+            let file = match crate::runtime::resolve_import(&root, importer.as_deref(), &name) {
+                // The core stdlib is part of the compiler
+                Ok(None) => continue,
+                Ok(Some(file)) => Some(file),
+                Err(_) => None,
+            };
+            let text = file.as_deref().and_then(|file| vfs::read(file).ok());
+            let hash = text.as_ref().map_or(0, |text| fnv1a(text.text.as_bytes()));
+            if let (Some(file), Some(_)) = (&file, &text) {
+                let known = cache.modules.get(file).filter(|(known, _)| *known == hash).map(|(_, imports)| imports.clone());
+                let module_imports = match known {
+                    Some(module_imports) => module_imports,
+                    None => {
+                        // A module that doesn't parse fails the compile that loads it, not this one
+                        let module_imports = crate::runtime::parse_module(file, &name, edition).map(|p| imports(&p)).unwrap_or_default();
+                        cache.modules.insert(file.clone(), (hash, module_imports.clone()));
+                        module_imports
+                    }
+                };
+                for import in module_imports {
+                    if seen.insert(import.clone()) {
+                        queue.push_back((import, Some(file.clone())));
+                    }
+                }
+            }
+            found.insert(name.clone(), Dependency { name, file, hash });
+        }
+        found.into_values().collect()
+    }
+}
+
+impl std::fmt::Debug for Handle {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("Handle(..)")
+    }
+}
+
+/// Handles are equal when they share a cache
+impl PartialEq for Handle {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl Eq for Handle {}
+
+/// The paths of the modules `program` uses, as `use` names them
+fn imports(program: &Program) -> Vec<String> {
+    let mut names = Vec::new();
+    crate::metadata::uses(&program.items, &mut |u| names.push(u.path.join("/")));
+    names
+}
+
+fn fingerprint(hash: u64, dependencies: &[Dependency]) -> u64 {
+    let mut text = format!("{:016x}", hash);
+    for dependency in dependencies {
+        text.push_str(&format!("\n{} {:016x}", dependency.name, dependency.hash));
+    }
+    fnv1a(text.as_bytes())
+}
+
+/// What decides how a program is parsed, and how it is lowered for `target`,
+/// besides its text. Every option is named, so a new one is sorted into
+/// these or the others by whoever adds it.
+fn option_keys(target: CompileTarget, options: &CompileOptions) -> (u64, u64) {
+    let CompileOptions {
+        // The lexer reads these
+        edition,
+        tab_width,
+        // Comptime runs under these, and monomorphization reads `deterministic`
+        features,
+        sql_schema,
+        limits,
+        allow_comptime_io,
+        deterministic,
+        // The rest only change what backends and the interpreter do with the lowered program
+        program_args: _,
+        simd: _,
+        runtime_stats: _,
+        size_report: _,
+        print: _,
+        profile: _,
+        source: _,
+        crate_type: _,
+        trace: _,
+        recording: _,
+        schedules: _,
+        cache: _,
+    } = options;
+    let parse = format!("{:?} {:?}", edition, tab_width);
+    let lowering = format!("{:?} {} {:?} {:?} {:?} {:?} {:?}", target, parse, features, sql_schema, limits, allow_comptime_io, deterministic);
+    (fnv1a(parse.as_bytes()), fnv1a(lowering.as_bytes()))
+}
+
+/// The runs of `items` that come from one module each: linking puts each
+/// module's items together, and the program's own, from no file, last
+fn modules(items: &[Item]) -> Vec<&[Item]> {
+    let mut modules = Vec::new();
+    let mut start = 0;
+    for end in 1..=items.len() {
+        if end == items.len() || items[end].span().file != items[start].span().file {
+            modules.push(&items[start..end]);
+            start = end;
+        }
+    }
+    modules
+}
+
+/// What a module's items check the same way for: how they read once linked
+/// and through comptime, and the declarations they are checked against
+fn module_key(items: &[Item], context: u64) -> u64 {
+    fnv1a(format!("{:016x} {:?}", context, items).as_bytes())
+}
+
+/// What one artifact of `kain build` was made from
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Record {
+    pub compiler: String,
+    /// A hash of the target, the compile options and the profile
+    pub options: u64,
+    /// The hash of the entry module's text
+    pub entry: u64,
+    pub dependencies: Vec<Dependency>,
+    /// The hash of the artifact as it was written
+    pub artifact: u64,
+}
+
+impl Record {
+    pub fn new(source: &str, dependencies: Vec<Dependency>, options: u64, artifact: &[u8]) -> Self {
+        Record {
+            compiler: env!("CARGO_PKG_VERSION").to_string(),
+            options,
+            entry: fnv1a(source.as_bytes()),
+            dependencies,
+            artifact: fnv1a(artifact),
+        }
+    }
+
+    /// Where the record of `target`'s artifact named `stem` is kept
+    pub fn path(output_dir: &Path, target: &str, stem: &str) -> PathBuf {
+        output_dir.join(".incremental").join(format!("{}-{}", target, stem))
+    }
+
+    pub fn load(path: &Path) -> Option<Self> {
+        Self::parse(&fs::read_to_string(path).ok()?)
+    }
+
+    pub fn save(&self, path: &Path) -> std::io::Result<()> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        fs::write(path, self.to_text())
+    }
+
+    fn to_text(&self) -> String {
+        let mut text = format!("compiler {}\noptions {:016x}\nentry {:016x}\nartifact {:016x}\n", self.compiler, self.options, self.entry, self.artifact);
+        for dependency in &self.dependencies {
+            let file = dependency.file.as_ref().map_or(String::new(), |file| file.display().to_string());
+            text.push_str(&format!("module {} {:016x} {}\n", dependency.name, dependency.hash, file));
+        }
+        text
+    }
+
+    fn parse(text: &str) -> Option<Self> {
+        let mut record = Record { compiler: String::new(), options: 0, entry: 0, dependencies: Vec::new(), artifact: 0 };
+        let hex = |value: &str| u64::from_str_radix(value, 16).ok();
+        for line in text.lines() {
+            let (key, value) = line.split_once(' ')?;
+            match key {
+                "compiler" => record.compiler = value.to_string(),
+                "options" => record.options = hex(value)?,
+                "entry" => record.entry = hex(value)?,
+                "artifact" => record.artifact = hex(value)?,
+                "module" => {
+                    let mut fields = value.splitn(3, ' ');
+                    let name = fields.next()?.to_string();
+                    let hash = hex(fields.next()?)?;
+                    let file = fields.next().filter(|file| !file.is_empty()).map(PathBuf::from);
+                    record.dependencies.push(Dependency { name, file, hash });
+                }
+                _ => return None,
+            }
+        }
+        Some(record)
+    }
+
+    /// Whether building `source` with `options` would write `artifact` again:
+    /// nothing it was made from changed, and nothing changed the artifact since
+    pub fn is_current(&self, root: &Path, source: &str, options: u64, artifact: &Path) -> bool {
+        self.compiler == env!("CARGO_PKG_VERSION")
+            && self.options == options
+            && self.entry == fnv1a(source.as_bytes())
+            && self.dependencies.iter().all(|dependency| {
+                // Found next to the module importing it, or from `root`
+                let file = match &dependency.file {
+                    Some(file) if vfs::exists(file) => Some(file.clone()),
+                    _ => crate::runtime::resolve_module_in(root, &dependency.name).ok().flatten(),
+                };
+                let hash = file.as_deref().and_then(|file| vfs::read(file).ok()).map_or(0, |text| fnv1a(text.text.as_bytes()));
+                file == dependency.file && hash == dependency.hash
+            })
+            && fs::read(artifact).is_ok_and(|bytes| fnv1a(&bytes) == self.artifact)
+    }
+}
+
+/// The [`Record::options`] of `target` built with `options` under `profile`
+pub fn options_key(target: &str, options: &CompileOptions, profile: &crate::packager::Profile) -> u64 {
+    let options = CompileOptions { cache: None, ..options.clone() };
+    fnv1a(format!("{} {:?} {:?}", target, options, profile).as_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_only_changed_modules_invalidate_the_cache() {
+        let dir = std::env::temp_dir().join(format!("kain-incremental-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let root = dir.canonicalize().unwrap();
+        fs::write(root.join("physics.kn"), "use units\n\npub fn step(x: Int) -> Int:\n    return x + 1\n").unwrap();
+        fs::write(root.join("units.kn"), "pub fn meters(x: Int) -> Int:\n    return x\n").unwrap();
+        fs::write(root.join("unused.kn"), "pub fn nothing() -> Int:\n    return 0\n").unwrap();
+        let source = "use physics\n\nfn main():\n    println(step(1))\n";
+        let program = Parser::new(&crate::Lexer::new(source).tokenize().unwrap()).parse().unwrap();
+        let hash = fnv1a(source.as_bytes());
+
+        let cache = Handle::in_dir(&root);
+        let names: Vec<_> = cache.dependencies(&program, Edition::default()).into_iter().map(|d| d.name).collect();
+        assert_eq!(names, ["physics", "units"]);
+        let before = cache.fingerprint(hash, &program, Edition::default());
+        fs::write(root.join("unused.kn"), "pub fn nothing() -> Int:\n    return 1\n").unwrap();
+        assert_eq!(cache.fingerprint(hash, &program, Edition::default()), before);
+        fs::write(root.join("units.kn"), "pub fn meters(x: Int) -> Int:\n    return x * 100\n").unwrap();
+        assert_ne!(cache.fingerprint(hash, &program, Edition::default()), before);
+
+        let artifact = root.join("main.js");
+        fs::write(&artifact, "main()").unwrap();
+        let record = Record::new(source, cache.dependencies(&program, Edition::default()), 7, b"main()");
+        let path = Record::path(&root, "js", "main");
+        record.save(&path).unwrap();
+        let record = Record::load(&path).unwrap();
+        assert!(record.is_current(&root, source, 7, &artifact));
+        assert!(!record.is_current(&root, source, 8, &artifact));
+        assert!(!record.is_current(&root, "fn main():\n    println(2)\n", 7, &artifact));
+        fs::write(root.join("physics.kn"), "use units\n\npub fn step(x: Int) -> Int:\n    return x + 2\n").unwrap();
+        assert!(!record.is_current(&root, source, 7, &artifact));
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_recompiling_an_unchanged_program_skips_the_front_end() {
+        let options = CompileOptions { cache: Some(Handle::default()), ..CompileOptions::default() };
+        let source = "fn main():\n    let x = 1 + 2\n";
+        let first = crate::compile_with(source, CompileTarget::Js, &options).unwrap();
+        let again = crate::compile_with(source, CompileTarget::Js, &options).unwrap();
+        assert_eq!(first.artifact, again.artifact);
+        assert!(first.timings.iter().any(|(phase, _)| *phase == Phase::TypeCheck));
+        let phases: Vec<_> = again.timings.iter().map(|(phase, _)| *phase).collect();
+        assert_eq!(phases, [Phase::Codegen]);
+
+        let changed = crate::compile_with("fn main():\n    let x = 1 + 3\n", CompileTarget::Js, &options).unwrap();
+        assert!(changed.timings.iter().any(|(phase, _)| *phase == Phase::Parse));
+    }
+
+    #[test]
+    fn test_options_that_change_lowering_miss_the_cache() {
+        let options = CompileOptions { cache: Some(Handle::default()), ..CompileOptions::default() };
+        let source = "fn main():\n    let x = 1 + 2\n";
+        let lowered = |options: &CompileOptions| {
+            let output = crate::compile_with(source, CompileTarget::Js, options).unwrap();
+            output.timings.iter().any(|(phase, _)| *phase == Phase::TypeCheck)
+        };
+        assert!(lowered(&options));
+        assert!(!lowered(&options));
+        let deterministic = CompileOptions { deterministic: true, ..options.clone() };
+        assert!(lowered(&deterministic));
+        assert!(!lowered(&deterministic));
+        let limits = crate::runtime::InterpretOptions { max_steps: Some(1000), ..Default::default() };
+        assert!(lowered(&CompileOptions { limits, ..options.clone() }));
+        let tabs = "fn main():\n\tlet x = 1 + 2\n";
+        let wide = CompileOptions { tab_width: Some(8), ..options.clone() };
+        crate::compile_with(tabs, CompileTarget::Js, &options).unwrap();
+        let output = crate::compile_with(tabs, CompileTarget::Js, &wide).unwrap();
+        assert!(output.timings.iter().any(|(phase, _)| *phase == Phase::Parse));
+    }
+
+    #[test]
+    fn test_editing_one_module_checks_only_that_module_again() {
+        let cache = Handle::default();
+        let options = CompileOptions { cache: Some(cache.clone()), ..CompileOptions::default() };
+        let source = "use tests/modules/shapes\nuse tests/modules/units\n\nfn main():\n    println(area(3, 4), unit())\n";
+        crate::compile_with(source, CompileTarget::Js, &options).unwrap();
+        let first = cache.lock().item_checks;
+        assert!(first > 1);
+
+        // The modules are linked in unchanged, so only `main` is checked again
+        crate::compile_with(&source.replace("area(3, 4)", "area(3, 5)"), CompileTarget::Js, &options).unwrap();
+        assert_eq!(cache.lock().item_checks, first + 1);
+
+        // A new declaration changes what every item is checked against
+        crate::compile_with(&format!("{}\nstatic mut count: Int = 0\n", source), CompileTarget::Js, &options).unwrap();
+        assert!(cache.lock().item_checks > first + 3);
+    }
+}
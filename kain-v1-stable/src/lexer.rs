@@ -8,10 +8,24 @@
 
 use logos::Logos;
 use crate::span::Span;
+use crate::vfs::FileId;
 use crate::error::{KainError, KainResult};
+use crate::edition::Edition;
+
+/// Why the text at a token could not be lexed
+#[derive(Debug, Clone, PartialEq, Default)]
+pub enum LexError {
+    /// No token starts here
+    #[default]
+    Unexpected,
+    /// An escape in a string or char literal that means nothing, `at` bytes
+    /// into the token and `len` bytes long
+    Escape { at: usize, len: usize, message: String },
+}
 
 #[derive(Logos, Debug, Clone, PartialEq)]
 #[logos(skip r"[ \t\r]+")]  // Skip horizontal whitespace AND carriage returns
+#[logos(error = LexError)]
 pub enum TokenKind {
     // === Keywords ===
     #[token("fn")]
@@ -36,6 +50,8 @@ pub enum TokenKind {
     For,
     #[token("while")]
     While,
+    #[token("unless")]
+    Unless,
     #[token("loop")]
     Loop,
     #[token("break")]
@@ -96,6 +112,9 @@ pub enum TokenKind {
     Emit,
     #[token("comptime")]
     Comptime,
+    /// Keyword from edition 0.2, an identifier before that
+    #[token("quote")]
+    Quote,
     #[token("macro")]
     Macro,
     #[token("vertex")]
@@ -131,28 +150,18 @@ pub enum TokenKind {
     #[regex(r"[0-9][0-9_]*\.[0-9][0-9_]*", |lex| lex.slice().replace('_', "").parse().ok())]
     Float(f64),
 
-    #[regex(r#""([^"\\]|\\.)*""#, |lex| {
-        let s = lex.slice();
-        Some(unescape(&s[1..s.len()-1]))
-    })]
+    #[regex(r#""([^"\\]|\\.)*""#, |lex| literal(lex.slice(), 1, false))]
     String(String),
 
+    /// Kept escaped, since `\{` only means a brace once the parser has split
+    /// out the `{expr}` parts; the escapes are checked here all the same
     #[regex(r#"f"([^"\\]|\\.)*""#, |lex| {
         let s = lex.slice();
-        // For f-strings, we currently don't unescape everything because brace handling is complex,
-        // but we SHOULD unescape quotes at least.
-        // For now, let's treat f-strings same as strings but knowing formatting comes later.
-        // Actually, if we unescape, we might break {variable} if the user wrote \{variable} (escaped brace).
-        // Let's leave FString raw for now or handle it carefully.
-        // The user issue is specifically about standard strings.
-        Some(s[2..s.len()-1].to_string())
+        literal(s, 2, true).map(|_| s[2..s.len()-1].to_string())
     })]
     FString(String),
 
-    #[regex(r#"'([^'\\]|\\.)*'"#, |lex| {
-        let s = lex.slice();
-        Some(unescape(&s[1..s.len()-1]))
-    })]
+    #[regex(r#"'([^'\\]|\\.)*'"#, |lex| literal(lex.slice(), 1, false))]
     Char(String),
 
     // === Identifiers ===
@@ -194,6 +203,8 @@ pub enum TokenKind {
     Amp,
     #[token("|")]
     Pipe,
+    #[token("|>")]
+    PipeGt,
     #[token("^")]
     Caret,
     #[token("~")]
@@ -234,6 +245,8 @@ pub enum TokenKind {
     Dot,
     #[token("..")]
     DotDot,
+    #[token("..=")]
+    DotDotEq,
     #[token("...")]
     DotDotDot,
     #[token(":")]
@@ -262,9 +275,21 @@ pub enum TokenKind {
     #[regex(r"//[^\n]*", priority = 3)]
     Comment,
 
-    #[regex(r"#[^\n]*", priority = 2)]
+    /// `/// text` - kept by the lexer and attached to the next token
+    #[regex(r"///[^\n]*", |lex| lex.slice()[3..].strip_prefix(' ').unwrap_or(&lex.slice()[3..]).to_string(), priority = 4)]
+    DocComment(String),
+
+    // `#[[` is reserved for block comments, so a hash comment may not start with it
+    #[regex(r"#([^\[\n][^\n]*|\[([^\[\n][^\n]*)?)?", priority = 2)]
     HashComment,
 
+    /// `#[[ ... ]]` - may span lines and nest
+    #[token("#[[", block_comment)]
+    BlockComment,
+
+    /// Loop label `'outer`, split out of the quote syntax in `tokenize`
+    Label(String),
+
     // Synthetic tokens (inserted during indent processing)
     Indent,
     Dedent,
@@ -275,36 +300,158 @@ pub enum TokenKind {
 pub struct Token {
     pub kind: TokenKind,
     pub span: Span,
+    /// Doc comment lines (`///`) immediately preceding this token
+    pub doc: Option<String>,
 }
 
 impl Token {
     pub fn new(kind: TokenKind, span: Span) -> Self {
-        Self { kind, span }
+        Self { kind, span, doc: None }
+    }
+}
+
+/// Skip to the matching `]]`, allowing nested `#[[ ... ]]` pairs
+fn block_comment(lex: &mut logos::Lexer<TokenKind>) -> bool {
+    let bytes = lex.remainder().as_bytes();
+    let mut depth = 1;
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i..].starts_with(b"#[[") {
+            depth += 1;
+            i += 3;
+        } else if bytes[i..].starts_with(b"]]") {
+            depth -= 1;
+            i += 2;
+            if depth == 0 {
+                lex.bump(i);
+                return true;
+            }
+        } else {
+            i += 1;
+        }
     }
+    false
 }
 
+/// Columns a tab in indentation counts for, unless `--tab-width` says otherwise
+pub const DEFAULT_TAB_WIDTH: usize = 4;
+
 pub struct Lexer<'a> {
     source: &'a str,
+    edition: Edition,
+    file: Option<FileId>,
+    tab_width: usize,
 }
 
 impl<'a> Lexer<'a> {
     pub fn new(source: &'a str) -> Self {
-        Self { source }
+        Self::with_edition(source, Edition::default())
+    }
+
+    /// Lex under the syntax rules of a specific `language_version`
+    pub fn with_edition(source: &'a str, edition: Edition) -> Self {
+        Self { source, edition, file: None, tab_width: DEFAULT_TAB_WIDTH }
+    }
+
+    /// Count a tab in indentation as `width` columns
+    pub fn with_tab_width(mut self, width: usize) -> Self {
+        self.tab_width = width;
+        self
+    }
+
+    /// Stamp every token's span with `file`, so diagnostics from code in an
+    /// imported module point into that module rather than the entry file
+    pub fn in_file(mut self, file: FileId) -> Self {
+        self.file = Some(file);
+        self
+    }
+
+    /// `'name` is a loop label, not a quoted string, when it follows `break` or
+    /// `continue`, or when it's `'name:` in front of `for`, `while` or `loop`
+    fn loop_label(&self, pos: usize, prev: Option<&Token>) -> Option<String> {
+        let rest = self.source[pos..].strip_prefix('\'')?;
+        let len = rest.find(|c: char| !(c.is_ascii_alphanumeric() || c == '_')).unwrap_or(rest.len());
+        let (name, after) = rest.split_at(len);
+        if !name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_') {
+            return None;
+        }
+        let after_jump = prev.is_some_and(|t| matches!(t.kind, TokenKind::Break | TokenKind::Continue)) && !after.starts_with('\'');
+        let before_loop = after.strip_prefix(':').map(|s| s.trim_start_matches([' ', '\t'])).is_some_and(|s| {
+            ["for", "while", "loop"].iter().any(|kw| {
+                s.strip_prefix(kw).is_some_and(|s| !s.starts_with(|c: char| c.is_ascii_alphanumeric() || c == '_'))
+            })
+        });
+        (after_jump || before_loop).then(|| name.to_string())
     }
 
     pub fn tokenize(&self) -> KainResult<Vec<Token>> {
         let mut lex = TokenKind::lexer(self.source);
         let mut raw_tokens = Vec::new();
+        let mut pending_doc: Option<String> = None;
 
         while let Some(result) = lex.next() {
             let span = Span::new(lex.span().start, lex.span().end);
+            if let Some(label) = self.loop_label(span.start, raw_tokens.last()) {
+                // The quote regex may have run on to a later `'`, so resume right after the label
+                let end = span.start + 1 + label.len();
+                raw_tokens.push(Token::new(TokenKind::Label(label), Span::new(span.start, end)));
+                lex = TokenKind::lexer(self.source);
+                lex.bump(end);
+                continue;
+            }
             match result {
                 Ok(kind) => {
+                    if kind == TokenKind::HashComment && !self.edition.hash_comments() {
+                        return Err(KainError::lexer(
+                            format!("`#` comments were removed in edition {}, use `//`", self.edition),
+                            span,
+                        ));
+                    }
                     // Skip comments
-                    if matches!(kind, TokenKind::Comment | TokenKind::HashComment) {
+                    if matches!(kind, TokenKind::Comment | TokenKind::HashComment | TokenKind::BlockComment) {
+                        continue;
+                    }
+                    // Collect doc lines until the next real token
+                    if let TokenKind::DocComment(line) = kind {
+                        match pending_doc.as_mut() {
+                            Some(doc) => {
+                                doc.push('\n');
+                                doc.push_str(&line);
+                            }
+                            None => pending_doc = Some(line),
+                        }
                         continue;
                     }
-                    raw_tokens.push(Token::new(kind, span));
+                    let kind = match kind {
+                        TokenKind::Quote if !self.edition.quote_keyword() => TokenKind::Ident("quote".to_string()),
+                        TokenKind::Unless if !self.edition.unless_keyword() => TokenKind::Ident("unless".to_string()),
+                        kind => kind,
+                    };
+                    // `pair.0.1` is two tuple indices, not the float `0.1`
+                    if let TokenKind::Float(_) = kind {
+                        let text = &self.source[span.start..span.end];
+                        let after_dot = raw_tokens.last().is_some_and(|t: &Token| t.kind == TokenKind::Dot);
+                        if let Some((first, second)) = text.split_once('.').filter(|_| after_dot) {
+                            if let (Ok(a), Ok(b)) = (first.parse(), second.parse()) {
+                                let dot = span.start + first.len();
+                                raw_tokens.push(Token::new(TokenKind::Int(a), Span::new(span.start, dot)));
+                                raw_tokens.push(Token::new(TokenKind::Dot, Span::new(dot, dot + 1)));
+                                raw_tokens.push(Token::new(TokenKind::Int(b), Span::new(dot + 1, span.end)));
+                                continue;
+                            }
+                        }
+                    }
+                    let mut token = Token::new(kind, span);
+                    if !matches!(token.kind, TokenKind::Newline(_)) {
+                        token.doc = pending_doc.take();
+                    }
+                    raw_tokens.push(token);
+                }
+                Err(LexError::Escape { at, len, message }) => {
+                    return Err(KainError::lexer(message, Span::new(span.start + at, span.start + at + len)));
+                }
+                Err(_) if self.source[span.start..].starts_with("#[[") => {
+                    return Err(KainError::lexer("Unterminated block comment", span));
                 }
                 Err(_) => {
                     return Err(KainError::lexer(
@@ -321,9 +468,15 @@ impl<'a> Lexer<'a> {
     }
 
     /// Convert newlines with leading whitespace into INDENT/DEDENT tokens
+    ///
+    /// Outside brackets, a line's indentation must not mix tabs and spaces, and a
+    /// line that dedents must land on the indentation of a block it is in.
     fn process_indentation(&self, raw: Vec<Token>) -> KainResult<Vec<Token>> {
         let mut result = Vec::new();
-        let mut indent_stack: Vec<usize> = vec![0]; // Stack of indent levels
+        // Stack of indent levels, in columns, with the indentation that opened each
+        let mut indent_stack: Vec<(usize, String)> = vec![(0, String::new())];
+        // Inside brackets lines continue an expression, so their indentation is free
+        let mut depth: usize = 0;
         let mut iter = raw.into_iter().peekable();
 
         while let Some(token) = iter.next() {
@@ -336,28 +489,49 @@ impl<'a> Lexer<'a> {
                         }
                     }
 
-                    // Calculate indent level (count spaces, tabs = 4 spaces)
-                    let indent: usize = ws[1..].chars().map(|c| if c == '\t' { 4 } else { 1 }).sum();
-                    let current = *indent_stack.last().unwrap();
+                    let leading = &ws[1..];
+                    let at = Span::new(token.span.start + 1, token.span.end);
+                    // Trailing whitespace at the end of the file is no line to check
+                    let checked = depth == 0 && iter.peek().is_some();
+                    if checked && leading.contains(' ') && leading.contains('\t') {
+                        return Err(KainError::lexer(
+                            format!(
+                                "this line's indentation mixes tabs and spaces, so how deep it is depends on the tab width ({}); indent with one or the other",
+                                self.tab_width
+                            ),
+                            at,
+                        ));
+                    }
+                    let indent = self.width(leading);
+                    let current = indent_stack.last().map_or(0, |(width, _)| *width);
 
                     if indent > current {
                         // Increased indent
-                        indent_stack.push(indent);
+                        indent_stack.push((indent, leading.to_string()));
                         result.push(Token::new(TokenKind::Newline(ws.clone()), token.span));
                         result.push(Token::new(TokenKind::Indent, token.span));
                     } else if indent < current {
                         // Decreased indent - may produce multiple DEDENTs
                         result.push(Token::new(TokenKind::Newline(ws.clone()), token.span));
-                        while indent_stack.len() > 1 && *indent_stack.last().unwrap() > indent {
-                            indent_stack.pop();
+                        let mut left = String::new();
+                        while indent_stack.len() > 1 && indent_stack.last().is_some_and(|(top, _)| *top > indent) {
+                            left = indent_stack.pop().map(|(_, opened)| opened).unwrap_or_default();
                             result.push(Token::new(TokenKind::Dedent, token.span));
                         }
+                        if checked && indent_stack.last().is_some_and(|(top, _)| *top != indent) {
+                            return Err(KainError::lexer(self.misaligned(leading, &left), at));
+                        }
                     } else {
                         // Same indent level
                         result.push(Token::new(TokenKind::Newline(ws.clone()), token.span));
                     }
                 }
                 _ => {
+                    match token.kind {
+                        TokenKind::LParen | TokenKind::LBracket | TokenKind::LBrace => depth += 1,
+                        TokenKind::RParen | TokenKind::RBracket | TokenKind::RBrace => depth = depth.saturating_sub(1),
+                        _ => {}
+                    }
                     result.push(token);
                 }
             }
@@ -371,34 +545,111 @@ impl<'a> Lexer<'a> {
         }
 
         result.push(Token::new(TokenKind::Eof, final_span));
+        if let Some(file) = self.file {
+            for token in &mut result {
+                token.span = token.span.in_file(file);
+            }
+        }
         Ok(result)
     }
+
+    /// Columns `indentation` spans, a tab counting `tab_width` of them
+    fn width(&self, indentation: &str) -> usize {
+        indentation.chars().map(|c| if c == '\t' { self.tab_width } else { 1 }).sum()
+    }
+
+    /// Why a line indented with `line` fits no block, having dedented out of one indented with `block`
+    fn misaligned(&self, line: &str, block: &str) -> String {
+        let unit = |ws: &str| if ws.starts_with('\t') { "tab" } else { "space" };
+        let count = |ws: &str| {
+            let n = ws.chars().count();
+            format!("{} {}{}", n, unit(ws), if n == 1 { "" } else { "s" })
+        };
+        if unit(line) == unit(block) {
+            format!("this line is indented {} but the enclosing block uses {}", count(line), block.chars().count())
+        } else {
+            format!(
+                "this line is indented {} but the enclosing block uses {}, with a tab counting as {} spaces",
+                count(line),
+                count(block),
+                self.tab_width
+            )
+        }
+    }
 }
 
-fn unescape(s: &str) -> String {
+/// The text of a quoted literal `slice`, whose body starts `open` bytes in
+fn literal(slice: &str, open: usize, braces: bool) -> Result<String, LexError> {
+    unescape(&slice[open..slice.len() - 1], braces).map_err(|(at, len, message)| LexError::Escape { at: open + at, len, message })
+}
+
+/// Resolve the escapes in the body of a string or char literal:
+///
+/// ```text
+/// \n \r \t \0 \\ \" \'   the usual
+/// \x41              an ASCII char, two hex digits up to 7F
+/// \u{1F600}         any Unicode scalar value, one to six hex digits
+/// \{ \}             braces, in f-strings only
+/// ```
+///
+/// A bad escape is reported as where it starts in `s`, how long it is, and why.
+pub(crate) fn unescape(s: &str, braces: bool) -> Result<String, (usize, usize, String)> {
     let mut result = String::with_capacity(s.len());
-    let mut chars = s.chars();
-    while let Some(c) = chars.next() {
-        if c == '\\' {
-            match chars.next() {
-                Some('n') => result.push('\n'),
-                Some('r') => result.push('\r'),
-                Some('t') => result.push('\t'),
-                Some('0') => result.push('\0'),
-                Some('\\') => result.push('\\'),
-                Some('"') => result.push('"'),
-                Some('\'') => result.push('\''),
-                Some(other) => {
-                    result.push('\\');
-                    result.push(other);
+    let mut chars = s.char_indices();
+    while let Some((at, c)) = chars.next() {
+        if c != '\\' {
+            result.push(c);
+            continue;
+        }
+        let Some((_, kind)) = chars.next() else {
+            return Err((at, 1, "A `\\` at the end of a literal escapes nothing; write `\\\\` for a backslash".to_string()));
+        };
+        match kind {
+            'n' => result.push('\n'),
+            'r' => result.push('\r'),
+            't' => result.push('\t'),
+            '0' => result.push('\0'),
+            '\\' => result.push('\\'),
+            '"' => result.push('"'),
+            '\'' => result.push('\''),
+            '{' | '}' if braces => result.push(kind),
+            'x' => {
+                let digits: String = s[at + 2..].chars().take(2).take_while(char::is_ascii_hexdigit).collect();
+                let len = 2 + digits.len();
+                if digits.len() < 2 {
+                    return Err((at, len, "`\\x` takes two hex digits, as in `\\x41`".to_string()));
+                }
+                let code = u8::from_str_radix(&digits, 16).expect("two hex digits");
+                if code > 0x7F {
+                    return Err((at, len, format!("`\\x{}` is past ASCII; write `\\u{{{:X}}}` for that character", digits, code)));
                 }
-                None => result.push('\\'),
+                result.push(code as char);
+                chars.nth(1);
+            }
+            'u' => {
+                let rest = &s[at + 2..];
+                let body = rest.strip_prefix('{').and_then(|r| r.find('}').map(|end| &r[..end]));
+                let Some(digits) = body.filter(|d| (1..=6).contains(&d.len()) && d.chars().all(|c| c.is_ascii_hexdigit())) else {
+                    let len = 2 + rest.strip_prefix('{').and_then(|r| r.find('}')).map_or(0, |end| end + 2);
+                    return Err((at, len, "`\\u` takes one to six hex digits in braces, as in `\\u{1F600}`".to_string()));
+                };
+                let len = digits.len() + 4;
+                let code = u32::from_str_radix(digits, 16).expect("hex digits");
+                let Some(c) = char::from_u32(code) else {
+                    return Err((at, len, format!("`\\u{{{}}}` is not a Unicode scalar value", digits)));
+                };
+                result.push(c);
+                for _ in 0..digits.len() + 2 {
+                    chars.next();
+                }
+            }
+            other => {
+                let hint = if matches!(other, '{' | '}') { "only f-strings escape braces" } else { "write `\\\\` for a backslash" };
+                return Err((at, 1 + other.len_utf8(), format!("Unknown escape `\\{}`; {}", other, hint)));
             }
-        } else {
-            result.push(c);
         }
     }
-    result
+    Ok(result)
 }
 
 #[cfg(test)]
@@ -419,5 +670,62 @@ mod tests {
         let tokens = Lexer::new(source).tokenize().unwrap();
         assert!(tokens.iter().any(|t| matches!(t.kind, TokenKind::Indent)));
     }
-}
 
+    #[test]
+    fn test_indentation_errors_point_at_the_line() {
+        let cases = [
+            ("fn f():\n    let x = 1\n   x\n", 22, 25, "this line is indented 3 spaces but the enclosing block uses 4"),
+            ("fn f():\n\tlet x = 1\n \tx\n", 19, 21, "mixes tabs and spaces"),
+            ("fn f():\n        let x = 1\n\tx\n", 26, 27, "indented 1 tab but the enclosing block uses 8 spaces, with a tab counting as 4 spaces"),
+        ];
+        for (source, start, end, message) in cases {
+            match Lexer::new(source).tokenize() {
+                Err(KainError::Lexer { message: found, span }) => {
+                    assert_eq!((span.start, span.end), (start, end), "{:?}", source);
+                    assert!(found.contains(message), "{:?}: {}", source, found);
+                }
+                other => panic!("{:?}: {:?}", source, other),
+            }
+        }
+        // A wider tab lines it up; inside brackets lines may sit anywhere
+        assert!(Lexer::new(cases[2].0).with_tab_width(8).tokenize().is_ok());
+        assert!(Lexer::new("fn f():\n    let a = [\n        1,\n      ]\n    a\n").tokenize().is_ok());
+    }
+
+    #[test]
+    fn test_block_and_doc_comments() {
+        let source = "#[[ outer #[[ nested ]]\nstill comment ]]\n/// Adds one\n/// to x\nfn inc(x: Int) -> Int:\n    x + 1\n";
+        let tokens = Lexer::new(source).tokenize().unwrap();
+        let fn_tok = tokens.iter().find(|t| matches!(t.kind, TokenKind::Fn)).unwrap();
+        assert_eq!(fn_tok.doc.as_deref(), Some("Adds one\nto x"));
+        assert!(Lexer::new("#[[ never closed").tokenize().is_err());
+    }
+
+    #[test]
+    fn test_string_escapes_resolve_or_point_at_the_bad_one() {
+        let tokens = Lexer::new(r#""a\n\t\\\"\u{1F600}\x41" '\u{e9}'"#).tokenize().unwrap();
+        assert_eq!(tokens[0].kind, TokenKind::String("a\n\t\\\"\u{1F600}A".to_string()));
+        assert_eq!(tokens[1].kind, TokenKind::Char("é".to_string()));
+        assert_eq!(unescape(r"\{x\}", true).unwrap(), "{x}");
+
+        let cases = [
+            (r#"let s = "ok \q""#, 12, 14, "Unknown escape `\\q`"),
+            (r#""\x4""#, 1, 4, "`\\x` takes two hex digits"),
+            (r#""\xE9""#, 1, 5, "write `\\u{E9}`"),
+            (r#""\u41""#, 1, 3, "`\\u` takes one to six hex digits in braces"),
+            (r#""\u{1234567}""#, 1, 12, "`\\u` takes one to six hex digits in braces"),
+            (r#""\u{D800}""#, 1, 9, "is not a Unicode scalar value"),
+            (r#""\{""#, 1, 3, "only f-strings escape braces"),
+            (r#"f"\d{x}""#, 2, 4, "Unknown escape `\\d`"),
+        ];
+        for (source, start, end, message) in cases {
+            match Lexer::new(source).tokenize() {
+                Err(KainError::Lexer { message: found, span }) => {
+                    assert_eq!((span.start, span.end), (start, end), "{}", source);
+                    assert!(found.contains(message), "{}: {}", source, found);
+                }
+                other => panic!("{}: {:?}", source, other),
+            }
+        }
+    }
+}
@@ -24,14 +24,53 @@ pub mod types;
 pub mod effects;
 pub mod codegen;
 pub mod runtime;
+pub mod pretty;
+pub mod float;
+pub mod replay;
+pub mod wire;
+pub mod schedule;
 pub mod stdlib;
 pub mod error;
 pub mod span;
+pub mod stack;
 pub mod comptime;
 pub mod diagnostics;
 pub mod packager;
+pub mod metadata;
 pub mod lsp;
 pub mod monomorphize;
+pub mod docgen;
+pub mod filecheck;
+pub mod conformance;
+pub mod buildscript;
+pub mod vfs;
+pub mod capability;
+pub mod modules;
+pub mod asm;
+pub mod consts;
+pub mod visibility;
+pub mod mutability;
+pub mod ownership;
+pub mod region;
+pub mod resolve;
+pub mod query;
+pub mod symbols;
+pub mod stamp;
+pub mod incremental;
+pub mod toolchain;
+pub mod release;
+pub mod doctest;
+pub mod edition;
+pub mod template;
+pub mod argparse;
+pub mod log;
+pub mod image;
+pub mod bridge;
+#[cfg(feature = "game")]
+pub mod game;
+pub mod fix;
+pub mod crash;
+pub mod cli;
 
 
 pub use lexer::Lexer;
@@ -42,76 +81,680 @@ pub use effects::*;
 pub use error::KainError;
 pub use span::Span;
 
+/// Options that change how output is produced, never what the program means
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CompileOptions {
+    /// Emit byte-identical output for identical source: hash-ordered tables
+    /// (struct layouts, string pools, generated fields) are walked in sorted order,
+    /// and [`write_output`] pins artifact timestamps. Generated names come from
+    /// counters owned by each compile, so they need no extra handling.
+    pub deterministic: bool,
+    /// Resource limits applied when the target is the interpreter
+    pub limits: runtime::InterpretOptions,
+    /// Let comptime code use file, network and process natives (sandboxed by default)
+    pub allow_comptime_io: bool,
+    /// Features enabled for `@cfg(feature = "...")` and `cfg!(feature = "...")`
+    pub features: Vec<String>,
+    /// Syntax rules to compile under (`language_version` in KAIN.toml)
+    pub edition: edition::Edition,
+    /// Columns a tab in indentation counts for (`--tab-width`, or `tab_width` under
+    /// `[build]`); [`lexer::DEFAULT_TAB_WIDTH`] when `None`
+    pub tab_width: Option<usize>,
+    /// SQL DDL that `query!` statements are checked against (`--schema`)
+    pub sql_schema: Option<String>,
+    /// Arguments the interpreted program sees from `args()`
+    pub program_args: Vec<String>,
+    /// Let the wasm backend lower vector math and array sums to 128-bit SIMD (`--enable-simd`)
+    pub simd: bool,
+    /// Print the interpreter's [`runtime::RuntimeStats`] to stderr when the program ends (`--runtime-stats`)
+    pub runtime_stats: bool,
+    /// Print the bytes each section and function of wasm output takes (`--size-report`)
+    pub size_report: bool,
+    /// How `print`, `println` and `dbg` show values (`kain run --print-depth`, `--print-elements`, `--print-indent`)
+    pub print: pretty::PrettyOptions,
+    /// Build profile named in the artifact's [`stamp`] (`--profile`); `debug` when `None`
+    pub profile: Option<String>,
+    /// The file being compiled, read through [`vfs`]; `dbg!` quotes the expressions it prints from it
+    pub source: Option<std::path::PathBuf>,
+    /// What the llvm target builds (`--crate-type`)
+    pub crate_type: CrateType,
+    /// Record the interpreter's calls and statements (`kain run --trace`)
+    pub trace: Option<runtime::TraceOptions>,
+    /// Record the interpreted run's inputs, or replay them (`kain run --record`, `--replay`)
+    pub recording: Option<replay::Recording>,
+    /// Run each test under these actor schedules (`kain test --schedules`)
+    pub schedules: Option<schedule::ScheduleOptions>,
+    /// Reuse the front end's work from earlier compiles of the same program (watch mode)
+    pub cache: Option<incremental::Handle>,
+}
+
+/// Write a compiled artifact. Deterministic builds also set its modification time
+/// to `SOURCE_DATE_EPOCH` (or the Unix epoch), so archiving the output is reproducible too.
+pub fn write_output(path: &std::path::Path, bytes: &[u8], options: &CompileOptions) -> std::io::Result<()> {
+    std::fs::write(path, bytes)?;
+    if options.deterministic {
+        let secs = std::env::var("SOURCE_DATE_EPOCH").ok().and_then(|s| s.parse().ok()).unwrap_or(0);
+        let time = std::time::UNIX_EPOCH + std::time::Duration::from_secs(secs);
+        std::fs::File::options().write(true).open(path)?.set_modified(time)?;
+    }
+    Ok(())
+}
+
 /// Compile KAIN source to the specified target
 pub fn compile(source: &str, target: CompileTarget) -> Result<Vec<u8>, KainError> {
-    // 1. Lex
-    let tokens = Lexer::new(source).tokenize()?;
-    
-    // 2. Parse
-    let mut ast = Parser::new(&tokens).parse()?;
-    
-    // 2.5 Comptime Execution
-    // Evaluate comptime blocks and expressions before type checking
-    comptime::eval_program(&mut ast)?;
+    compile_with_options(source, target, &CompileOptions::default())
+}
 
-    // 3. Type check with effect inference
-    let mut typed_ast = types::check(&ast)?;
-    
-    // 3.5 Monomorphization (for native targets and interpreter if we want to test lowering)
-    if matches!(target, CompileTarget::Llvm | CompileTarget::Wasm | CompileTarget::SpirV | CompileTarget::Interpret | CompileTarget::Hybrid) {
-        let mono_prog = monomorphize::monomorphize(&typed_ast)?;
-        // Replace items with monomorphized items
-        // Since codegen expects TypedProgram, we can just update it.
-        // But TypedProgram might have other fields later. 
-        // For now, MonomorphizedProgram just has items.
-        typed_ast.items = mono_prog.items; 
-    }
-    
-    // 4. Generate code
+/// Compile KAIN source to the specified target with explicit [`CompileOptions`]
+pub fn compile_with_options(source: &str, target: CompileTarget, options: &CompileOptions) -> Result<Vec<u8>, KainError> {
+    let typed_ast = front_end(source, target, options)?;
+    let mut artifact = generate(&typed_ast, target, options)?;
+    stamp::apply(&mut artifact, target, &stamp::Stamp::new(target, options.profile.as_deref(), Some(source)));
+    Ok(artifact)
+}
+
+/// Everything one [`compile_with`] run produced, so embedders, the LSP and the
+/// test runner don't run phases again to learn what the compiler already knew
+#[derive(Debug)]
+pub struct CompileOutput {
+    /// The backend's output; empty for `Interpret` and `Test`
+    pub artifact: Vec<u8>,
+    /// Code that compiled but that a newer edition would reject
+    pub warnings: Vec<diagnostics::Lint>,
+    /// Wall-clock time of each phase, in the order they ran
+    pub timings: Vec<(Phase, std::time::Duration)>,
+    /// Named top-level items of the type checked program
+    pub symbol_table: Vec<types::Symbol>,
+    /// What the artifact exposes to its host, under the names [`symbols`] gives it
+    pub exports: Vec<symbols::Export>,
+    /// C declarations of the exports, for llvm libraries (see [`codegen::cheader`])
+    pub header: Option<String>,
+}
+
+/// A step of the compiler, as [`CompileOutput::timings`] reports it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Phase {
+    Lex,
+    Parse,
+    /// Finding the modules `use` names, checking that the program only uses
+    /// their `pub` items, and linking them in for compiled targets
+    Modules,
+    Comptime,
+    TypeCheck,
+    /// Capability, inline assembly, mutability and ownership checks
+    Check,
+    Monomorphize,
+    Codegen,
+}
+
+impl Phase {
+    pub fn name(self) -> &'static str {
+        match self {
+            Phase::Lex => "lex",
+            Phase::Parse => "parse",
+            Phase::Modules => "modules",
+            Phase::Comptime => "comptime",
+            Phase::TypeCheck => "typecheck",
+            Phase::Check => "check",
+            Phase::Monomorphize => "monomorphize",
+            Phase::Codegen => "codegen",
+        }
+    }
+}
+
+impl std::fmt::Display for Phase {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+type Timings = Vec<(Phase, std::time::Duration)>;
+
+/// Run `f`, recording how long it took as `phase`, and for a [`crash`] report that it is running
+fn timed<T>(timings: &mut Timings, phase: Phase, f: impl FnOnce() -> T) -> T {
+    crash::enter(phase);
+    let start = std::time::Instant::now();
+    let result = f();
+    timings.push((phase, start.elapsed()));
+    result
+}
+
+/// Compile KAIN source to `target`, returning the artifact along with the
+/// warnings, phase timings, symbols and exports the compile produced
+pub fn compile_with(source: &str, target: CompileTarget, options: &CompileOptions) -> Result<CompileOutput, KainError> {
+    let mut timings = Timings::new();
+    let (typed_ast, symbol_table) = lower_source(source, target, options, &mut timings)?;
+    let mut artifact = timed(&mut timings, Phase::Codegen, || generate(&typed_ast, target, options))?;
+    stamp::apply(&mut artifact, target, &stamp::Stamp::new(target, options.profile.as_deref(), Some(source)));
+
+    let exports = symbols::exports(&typed_ast)?;
+    let header = (target == CompileTarget::Llvm && options.crate_type.is_library()).then(|| codegen::cheader::generate(&typed_ast));
+    Ok(CompileOutput {
+        artifact,
+        warnings: edition::migration_lints(source, options.edition),
+        timings,
+        symbol_table,
+        exports,
+        header,
+    })
+}
+
+/// Run the backend for `target` on a lowered program
+fn generate(typed_ast: &TypedProgram, target: CompileTarget, options: &CompileOptions) -> Result<Vec<u8>, KainError> {
     match target {
-        CompileTarget::Wasm => codegen::wasm::generate(&typed_ast),
+        CompileTarget::Wasm => codegen::wasm::generate_with_options(typed_ast, options),
+        CompileTarget::Wat => {
+            let wasm = codegen::wasm::generate_with_options(typed_ast, options)?;
+            Ok(codegen::wat::print(&wasm)?.into_bytes())
+        }
         #[cfg(feature = "llvm")]
-        CompileTarget::Llvm => codegen::llvm::generate(&typed_ast),
+        CompileTarget::Llvm => codegen::llvm::generate_with_options(typed_ast, options),
         #[cfg(not(feature = "llvm"))]
-        CompileTarget::Llvm => Err(KainError::codegen("LLVM backend not compiled. Rebuild with --features llvm", Span::new(0, 0))),
-        CompileTarget::SpirV => codegen::spirv::generate(&typed_ast),
+        CompileTarget::Llvm => Err(KainError::runtime("LLVM backend not compiled. Rebuild with --features llvm")),
+        CompileTarget::SpirV => codegen::spirv::generate(typed_ast),
         CompileTarget::Hlsl => {
-            let hlsl_code = codegen::hlsl::generate(&typed_ast)?;
+            let hlsl_code = codegen::hlsl::generate(typed_ast)?;
             Ok(hlsl_code.into_bytes())
         },
         CompileTarget::Usf => {
-            let usf_code = codegen::usf::generate(&typed_ast)?;
+            let usf_code = codegen::usf::generate(typed_ast)?;
             Ok(usf_code.into_bytes())
         },
         CompileTarget::Js => {
-            let js_code = codegen::js::generate(&typed_ast)?;
+            let js_code = codegen::js::generate(typed_ast)?;
             Ok(js_code.into_bytes())
         },
         CompileTarget::Rust => {
-            let rust_code = codegen::rust::generate(&typed_ast)?;
+            let rust_code = codegen::rust::generate(typed_ast)?;
             Ok(rust_code.into_bytes())
         },
         CompileTarget::Interpret => {
-            runtime::interpret(&typed_ast)?;
+            let mut env = runtime::Env::new();
+            env.set_limits(options.limits);
+            env.set_print_options(options.print);
+            env.set_program_args(options.program_args.clone());
+            env.set_edition(options.edition);
+            if let Some(file) = options.source.as_deref().and_then(|path| vfs::SourceMap::global().read().unwrap_or_else(|e| e.into_inner()).lookup(path)) {
+                env.set_source(file);
+            }
+            if let Some(trace) = &options.trace {
+                env.set_trace(runtime::Tracer::open(trace)?);
+            }
+            if let Some(recording) = &options.recording {
+                env.set_journal(replay::Journal::open(recording, &options.program_args)?);
+            }
+            let result = runtime::interpret_in(&mut env, typed_ast);
+            if options.runtime_stats {
+                eprint!("{}", env.runtime_stats());
+            }
+            result?;
             Ok(vec![])
         }
         CompileTarget::Test => {
-            runtime::run_tests(&typed_ast)?;
+            runtime::run_tests(typed_ast, options.schedules.as_ref())?;
             Ok(vec![])
         }
         CompileTarget::Hybrid => {
             // Hybrid outputs both WASM and JS. For simplicity, we return JS with WASM inline (base64)
             // or as a separate file. For now, return just the JS with WASM loader code.
-            let hybrid = codegen::hybrid::generate(&typed_ast)?;
+            let hybrid = codegen::hybrid::generate(typed_ast)?;
             // Return JS code; WASM is embedded/fetched separately
             Ok(hybrid.js.into_bytes())
         }
     }
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// Compiles one program for several targets, running the front end once per
+/// distinct lowering rather than once per target.
+///
+/// Comptime only sees the target through `@cfg`, `cfg!()` and comptime code,
+/// so a program without them is type checked once, and monomorphized once for every backend
+/// that works on monomorphized programs.
+pub struct CompileSession {
+    ast: Program,
+    options: CompileOptions,
+    /// Whether comptime's result can depend on the target
+    target_dependent: bool,
+    /// The program after comptime and its type checked form, by [`Lowering`]
+    checked: std::collections::HashMap<Lowering, (Program, TypedProgram)>,
+    /// Monomorphized programs, keyed like `checked`
+    monomorphized: std::collections::HashMap<Lowering, TypedProgram>,
+    /// Programs with their locals resolved for the interpreter, by target and the key of `checked`
+    resolved: std::collections::HashMap<(CompileTarget, Lowering), TypedProgram>,
+    /// The source's hash for the artifacts' stamps, when the session was made from text
+    source_hash: Option<String>,
+}
+
+/// Whether a lowering's options became an enum, whether it kept its
+/// references, and the cfg names it was lowered for when comptime depends on them
+type Lowering = (bool, bool, Option<&'static [&'static str]>);
+
+impl CompileSession {
+    /// Lex and parse `source`, ready to compile for any number of targets
+    pub fn new(source: &str, options: CompileOptions) -> Result<Self, KainError> {
+        let tokens = source_lexer(source, &options).tokenize()?;
+        let ast = Parser::new(&tokens).parse()?;
+        let source_hash = stamp::Stamp::new(CompileTarget::Wasm, None, Some(source)).source;
+        Ok(Self { source_hash, ..Self::from_program(ast, options) })
+    }
+
+    pub fn from_program(ast: Program, options: CompileOptions) -> Self {
+        let mut finder = CfgFinder(false);
+        ast::visit::Visitor::visit_program(&mut finder, &ast);
+        Self {
+            ast,
+            options,
+            target_dependent: finder.0,
+            checked: Default::default(),
+            monomorphized: Default::default(),
+            resolved: Default::default(),
+            source_hash: None,
+        }
+    }
+
+    /// Compile the program for `target`, reusing the front end's work from earlier targets
+    pub fn compile(&mut self, target: CompileTarget) -> Result<Vec<u8>, KainError> {
+        let options = self.options.clone();
+        let mut artifact = generate(self.lower(target)?, target, &options)?;
+        let stamp = stamp::Stamp { source: self.source_hash.clone(), ..stamp::Stamp::new(target, options.profile.as_deref(), None) };
+        stamp::apply(&mut artifact, target, &stamp);
+        Ok(artifact)
+    }
+
+    /// Compile the program with a registered [`codegen::backend::CodegenBackend`]
+    pub fn compile_with_backend(&mut self, backend: &dyn codegen::backend::CodegenBackend) -> Result<Vec<u8>, KainError> {
+        backend.generate(self.lower(backend.base_target())?)
+    }
+
+    /// The program as parsed, before comptime
+    pub fn program(&self) -> &Program {
+        &self.ast
+    }
+
+    /// C declarations of what an llvm library build of the program exports
+    pub fn header(&mut self) -> Result<String, KainError> {
+        Ok(codegen::cheader::generate(self.lower(CompileTarget::Llvm)?))
+    }
+
+    /// The program lowered for `target`, as [`lower`] would produce it
+    fn lower(&mut self, target: CompileTarget) -> Result<&TypedProgram, KainError> {
+        let key = (lowers_options(target), keeps_references(target), self.target_dependent.then(|| target.cfg_names()));
+        if !self.checked.contains_key(&key) {
+            let mut ast = self.ast.clone();
+            link_modules(&mut ast, &self.options)?;
+            comptime::eval_program_with_options(&mut ast, target, &self.options)?;
+            let mut typed_ast = types::check(&ast)?;
+            mutability::check(&ast, self.options.edition)?;
+            ownership::check(&ast, self.options.edition)?;
+            region::check(&ast)?;
+            lower_checked(&mut typed_ast, &ast, target);
+            self.checked.insert(key, (ast, typed_ast));
+        }
+        let (ast, typed_ast) = &self.checked[&key];
+        capability::check(ast, target)?;
+        asm::check(ast, target)?;
+
+        if monomorphizes(target) && !self.monomorphized.contains_key(&key) {
+            // On a copy: targets that don't monomorphize share `checked`, and call methods as methods
+            let program = monomorphized(typed_ast.clone(), &self.options)?;
+            self.monomorphized.insert(key, program);
+        }
+        let lowered = if monomorphizes(target) { &self.monomorphized[&key] } else { typed_ast };
+        symbols::exports(lowered)?;
+        if !interprets(target) {
+            return Ok(lowered);
+        }
+        if !self.resolved.contains_key(&(target, key)) {
+            let mut program = lowered.clone();
+            resolve::resolve(&mut program);
+            self.resolved.insert((target, key), program);
+        }
+        Ok(&self.resolved[&(target, key)])
+    }
+}
+
+/// Finds what makes comptime depend on the target: `@cfg` items, `cfg!()`,
+/// and comptime code, which may emit either
+struct CfgFinder(bool);
+
+impl ast::visit::Visitor for CfgFinder {
+    fn visit_item(&mut self, item: &Item) {
+        match item {
+            Item::Cfg(_) | Item::Comptime(_) => self.0 = true,
+            _ => ast::visit::walk_item(self, item),
+        }
+    }
+
+    fn visit_expr(&mut self, expr: &Expr) {
+        match expr {
+            Expr::MacroCall { name, .. } if name == "cfg" => self.0 = true,
+            Expr::Comptime(..) => self.0 = true,
+            _ => ast::visit::walk_expr(self, expr),
+        }
+    }
+}
+
+/// The lexer for the file being compiled, under the options' edition and tab width
+fn source_lexer<'a>(source: &'a str, options: &CompileOptions) -> Lexer<'a> {
+    Lexer::with_edition(source, options.edition).with_tab_width(options.tab_width.unwrap_or(lexer::DEFAULT_TAB_WIDTH))
+}
+
+/// Targets whose backends work on monomorphized programs
+fn monomorphizes(target: CompileTarget) -> bool {
+    matches!(target, CompileTarget::Llvm | CompileTarget::Wasm | CompileTarget::Wat | CompileTarget::SpirV | CompileTarget::Interpret | CompileTarget::Hybrid)
+}
+
+/// Targets whose backends compile options as the `Option` enum, see [`types::lower_options`]
+fn lowers_options(target: CompileTarget) -> bool {
+    matches!(target, CompileTarget::Js | CompileTarget::Wasm | CompileTarget::Wat | CompileTarget::Llvm | CompileTarget::Hybrid)
+}
+
+/// Targets whose backends compile `&` and `&mut` as references of their own
+fn keeps_references(target: CompileTarget) -> bool {
+    matches!(target, CompileTarget::Rust)
+}
+
+/// Targets the interpreter runs, which reads locals by slot
+fn interprets(target: CompileTarget) -> bool {
+    matches!(target, CompileTarget::Interpret | CompileTarget::Test)
+}
+
+/// Compile KAIN source with a registered [`codegen::backend::CodegenBackend`]
+pub fn compile_with_backend(source: &str, backend: &dyn codegen::backend::CodegenBackend, options: &CompileOptions) -> Result<Vec<u8>, KainError> {
+    let typed_ast = front_end(source, backend.base_target(), options)?;
+    backend.generate(&typed_ast)
+}
+
+/// Lex, parse, run comptime, type check and lower `source` for `target`
+fn front_end(source: &str, target: CompileTarget, options: &CompileOptions) -> Result<TypedProgram, KainError> {
+    lower_source(source, target, options, &mut Timings::new()).map(|(typed_ast, _)| typed_ast)
+}
+
+/// Lex, parse and lower `source`, or take what an earlier compile did from
+/// [`CompileOptions::cache`]
+fn lower_source(source: &str, target: CompileTarget, options: &CompileOptions, timings: &mut Timings) -> Result<(TypedProgram, Vec<types::Symbol>), KainError> {
+    if let Some(cache) = &options.cache {
+        return cache.lower(source, target, options, timings);
+    }
+    // 1. Lex
+    let tokens = timed(timings, Phase::Lex, || source_lexer(source, options).tokenize())?;
+
+    // 2. Parse
+    let ast = timed(timings, Phase::Parse, || Parser::new(&tokens).parse())?;
+
+    lower_timed(ast, target, options, timings)
+}
+
+/// Run comptime, type check and lower an already parsed program
+fn lower(ast: Program, target: CompileTarget, options: &CompileOptions) -> Result<TypedProgram, KainError> {
+    lower_timed(ast, target, options, &mut Timings::new()).map(|(typed_ast, _)| typed_ast)
+}
+
+/// [`lower`], timing each phase and returning the symbols of the checked
+/// program, since monomorphization replaces generic functions
+fn lower_timed(mut ast: Program, target: CompileTarget, options: &CompileOptions, timings: &mut Timings) -> Result<(TypedProgram, Vec<types::Symbol>), KainError> {
+    // 2.4 Modules
+    // Every module `use` reaches is found and parsed before anything runs, so cycles and
+    // missing files are reported at the `use`, and linked into the program for every target
+    timed(timings, Phase::Modules, || link_modules(&mut ast, options))?;
+
+    // 2.5 Comptime Execution
+    // Resolve @cfg items, then evaluate comptime blocks and expressions before type checking
+    timed(timings, Phase::Comptime, || comptime::eval_program_with_options(&mut ast, target, options))?;
+
+    // 3. Type check with effect inference; a cache keeps the typed items of modules that didn't change
+    let mut typed_ast = timed(timings, Phase::TypeCheck, || match &options.cache {
+        Some(cache) => cache.check(&ast, target, options),
+        None => types::check(&ast),
+    })?;
+    let symbols = typed_ast.symbols();
+
+    timed(timings, Phase::Check, || {
+        // 3.1 Reject constructs this backend cannot compile, including inline code written for another one
+        capability::check(&ast, target)?;
+        asm::check(&ast, target)?;
+
+        // 3.2 Reject changes to bindings not declared `mut`
+        mutability::check(&ast, options.edition)?;
+
+        // 3.3 Reject uses of moved values, conflicting borrows and references to locals escaping
+        ownership::check(&ast, options.edition)?;
+
+        // 3.3b Reject values outliving the region they were allocated in
+        region::check(&ast)
+    })?;
+
+    // 3.4 Lower what backends don't compile themselves
+    lower_checked(&mut typed_ast, &ast, target);
+
+    // 3.5 Monomorphization (for native targets and interpreter if we want to test lowering)
+    if monomorphizes(target) {
+        typed_ast = timed(timings, Phase::Monomorphize, || monomorphized(typed_ast, options))?;
+    }
+
+    // 3.5b Reject two items exported under one name, now that methods and instances have theirs
+    symbols::exports(&typed_ast)?;
+
+    // 3.6 Let the interpreter read locals by slot
+    if interprets(target) {
+        resolve::resolve(&mut typed_ast);
+    }
+
+    Ok((typed_ast, symbols))
+}
+
+/// Lower a checked program for `target`'s backend. [`lower_timed`] and
+/// [`CompileSession`] both go through here, so the two can't lower differently
+fn lower_checked(typed_ast: &mut TypedProgram, ast: &Program, target: CompileTarget) {
+    // Only Rust has references; elsewhere they are the values they refer to
+    if !keeps_references(target) {
+        ownership::erase_references(typed_ast);
+    }
+
+    // Spell out the fields `Point { ..p, x: 1 }` copies, so backends see plain literals,
+    // and compile type aliases and newtypes down to the types underneath
+    types::expand_struct_updates(typed_ast);
+    types::lower_type_aliases(typed_ast, &ast.items);
+
+    // Compiled targets get `Some`, `None` and `T?` as a generic `Option` enum
+    if lowers_options(target) {
+        types::lower_options(typed_ast);
+    }
+}
+
+/// Monomorphize a lowered program, for the targets that [`monomorphizes`] names
+fn monomorphized(mut typed_ast: TypedProgram, options: &CompileOptions) -> Result<TypedProgram, KainError> {
+    // `Lexer::new(source)` calls the `Lexer_new` monomorphization makes of the method
+    types::lower_associated_calls(&mut typed_ast);
+    let items = monomorphize::monomorphize_with_options(&typed_ast, options)?.items;
+    Ok(TypedProgram { items })
+}
+
+/// Resolve the modules `ast` imports, relative to `options.source` first,
+/// reject uses of their private items and link them into `ast`
+fn link_modules(ast: &mut Program, options: &CompileOptions) -> Result<(), KainError> {
+    let graph = modules::graph(ast, options.source.as_deref(), options.edition)?;
+    // Checked on the program as written, before linking renames what it uses
+    visibility::check(ast, &graph, options.edition)?;
+    modules::link(ast, &graph, options.edition)
+}
+
+/// Result of [`parse_recoverable`]: whatever the front end produced plus its diagnostics
+#[derive(Debug, Default)]
+pub struct ParseOutcome {
+    pub program: Option<Program>,
+    pub typed: Option<TypedProgram>,
+    pub diagnostics: Vec<KainError>,
+}
+
+/// Lex, parse and type check without ever panicking.
+///
+/// Every failure is reported as a diagnostic (a panic left in one of the passes
+/// as an internal error of that pass), so long-lived hosts like the LSP and the
+/// fuzz targets can feed arbitrary input through the front end. Syntax errors
+/// don't stop the parse: the program comes back with [`Item::Error`] and
+/// [`Expr::Error`] nodes where they were, and is type checked as far as it goes.
+pub fn parse_recoverable(source: &str) -> ParseOutcome {
+    let mut outcome = ParseOutcome::default();
+
+    let tokens = match guard_pass(Stage::Lexer, source, || Lexer::new(source).tokenize()) {
+        Ok(tokens) => tokens,
+        Err(e) => {
+            outcome.diagnostics.push(e);
+            return outcome;
+        }
+    };
+
+    let program = match guard_pass(Stage::Parser, source, || Ok(Parser::new(&tokens).parse_recovering())) {
+        Ok((program, errors)) => {
+            outcome.diagnostics.extend(errors);
+            program
+        }
+        Err(e) => {
+            outcome.diagnostics.push(e);
+            return outcome;
+        }
+    };
+
+    match guard_pass(Stage::TypeChecker, source, || types::check(&program)) {
+        Ok(typed) => outcome.typed = Some(typed),
+        Err(e) => outcome.diagnostics.push(e),
+    }
+    outcome.program = Some(program);
+    outcome
+}
+
+/// The pass a [`guard_pass`] is protecting, so a panic is reported as the right kind of error
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum Stage {
+    Lexer,
+    Parser,
+    TypeChecker,
+    Interpreter,
+}
+
+thread_local! {
+    /// Set while a [`guard_pass`] runs, so its panics don't reach stderr
+    static GUARDED: std::cell::Cell<bool> = const { std::cell::Cell::new(false) };
+}
+
+/// Run a pass, turning a panic into a diagnostic for its stage.
+///
+/// The passes report failures as `KainError`s; this only catches bugs that
+/// still panic, and cannot help at all when built with `panic = "abort"`. The
+/// diagnostic spans all of `source`, since nothing says where the pass was.
+pub(crate) fn guard_pass<T>(stage: Stage, source: &str, f: impl FnOnce() -> Result<T, KainError>) -> Result<T, KainError> {
+    static QUIET_HOOK: std::sync::Once = std::sync::Once::new();
+    QUIET_HOOK.call_once(|| {
+        let previous = std::panic::take_hook();
+        std::panic::set_hook(Box::new(move |info| {
+            if !GUARDED.with(|g| g.get()) {
+                previous(info);
+            }
+        }));
+    });
+
+    let was_guarded = GUARDED.with(|g| g.replace(true));
+    let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(f));
+    GUARDED.with(|g| g.set(was_guarded));
+
+    result.unwrap_or_else(|payload| {
+        let reason = crash::panic_message(&*payload);
+        let span = Span::new(0, source.len());
+        Err(match stage {
+            Stage::Lexer => KainError::lexer(format!("internal error in the lexer: {}", reason), span),
+            Stage::Parser => KainError::parser(format!("internal error in the parser: {}", reason), span),
+            Stage::TypeChecker => KainError::type_error(format!("internal error in the type checker: {}", reason), span),
+            Stage::Interpreter => KainError::runtime(format!("internal error in the interpreter: {}", reason)),
+        })
+    })
+}
+
+/// Everything a playground or doc example needs from one [`eval_snippet`] run
+#[derive(Debug)]
+pub struct EvalResult {
+    /// Output of `print` and `println`
+    pub stdout: String,
+    /// Errors and warnings no caller could receive, such as failures in actor handlers
+    pub stderr: String,
+    /// What `main` returned, or `None` if compiling or running failed
+    pub value: Option<runtime::Value>,
+    /// Compile errors, runtime errors and exceeded limits (including the timeout)
+    pub diagnostics: Vec<KainError>,
+    /// Wall-clock time spent compiling and running
+    pub duration: std::time::Duration,
+}
+
+/// Compile and interpret `source` in one call, capturing its output.
+///
+/// `options.limits` bounds the run; set `limits.timeout` so a snippet that
+/// loops forever comes back with a diagnostic instead of hanging the host.
+/// Comptime code gets the same limits, counted separately.
+pub fn eval_snippet(source: &str, options: &CompileOptions) -> EvalResult {
+    let start = std::time::Instant::now();
+    let parsed = guard_pass(Stage::Lexer, source, || source_lexer(source, options).tokenize())
+        .and_then(|tokens| guard_pass(Stage::Parser, source, || Parser::new(&tokens).parse()));
+    let mut result = match parsed {
+        Ok(program) => {
+            // Registered under a name of its own, so `dbg!` can quote the snippet
+            let file = vfs::SourceMap::global().write().unwrap_or_else(|e| e.into_inner()).add("<snippet>", source);
+            eval_program(program, options, Some(file))
+        }
+        Err(e) => EvalResult {
+            stdout: String::new(),
+            stderr: String::new(),
+            value: None,
+            diagnostics: vec![e],
+            duration: Default::default(),
+        },
+    };
+    result.duration = start.elapsed();
+    result
+}
+
+/// [`eval_snippet`] for a program that is already parsed (or assembled from several)
+pub fn eval_parsed(program: Program, options: &CompileOptions) -> EvalResult {
+    eval_program(program, options, None)
+}
+
+fn eval_program(program: Program, options: &CompileOptions, source: Option<std::sync::Arc<vfs::SourceFile>>) -> EvalResult {
+    let start = std::time::Instant::now();
+    let mut env = runtime::Env::new();
+    let output = env.capture_output();
+    let errors = env.capture_errors();
+    if let Some(file) = source {
+        env.set_source(file);
+    }
+
+    let result = guard_pass(Stage::Interpreter, "", || {
+        let typed_ast = lower(program, CompileTarget::Interpret, options)?;
+        env.set_limits(options.limits);
+        env.set_print_options(options.print);
+        env.set_program_args(options.program_args.clone());
+        env.set_edition(options.edition);
+        if let Some(trace) = &options.trace {
+            env.set_trace(runtime::Tracer::open(trace)?);
+        }
+        if let Some(recording) = &options.recording {
+            env.set_journal(replay::Journal::open(recording, &options.program_args)?);
+        }
+        runtime::interpret_in(&mut env, &typed_ast)
+    });
+
+    let stdout = std::mem::take(&mut *output.lock().unwrap_or_else(|e| e.into_inner()));
+    let stderr = std::mem::take(&mut *errors.lock().unwrap_or_else(|e| e.into_inner()));
+    let (value, diagnostics) = match result {
+        Ok(runtime::Value::Return(value)) => (Some(*value), vec![]),
+        Ok(value) => (Some(value), vec![]),
+        Err(e) => (None, vec![e]),
+    };
+    EvalResult { stdout, stderr, value, diagnostics, duration: start.elapsed() }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum CompileTarget {
     Wasm,
+    Wat,  // WASM printed as WebAssembly text
     Llvm,
     SpirV,
     Hlsl,
@@ -123,7 +766,180 @@ pub enum CompileTarget {
     Hybrid,  // WASM + JS with auto bindings
 }
 
+impl CompileTarget {
+    /// Parse a `--target` name or alias (`wasm`, `native`, `gpu`, `run`, ...)
+    pub fn parse(name: &str) -> Option<CompileTarget> {
+        Some(match name.to_lowercase().as_str() {
+            "wasm" | "w" => CompileTarget::Wasm,
+            "wat" => CompileTarget::Wat,
+            "llvm" | "native" | "n" => CompileTarget::Llvm,
+            "spirv" | "gpu" | "shader" | "s" => CompileTarget::SpirV,
+            "hlsl" | "h" => CompileTarget::Hlsl,
+            "usf" | "ue5" => CompileTarget::Usf,
+            "js" | "javascript" => CompileTarget::Js,
+            "rust" | "rs" => CompileTarget::Rust,
+            "run" | "r" | "interpret" | "i" => CompileTarget::Interpret,
+            "test" | "t" => CompileTarget::Test,
+            "hybrid" | "web" => CompileTarget::Hybrid,
+            _ => return None,
+        })
+    }
+
+    /// Names that match this target in `@cfg(target = "...")`: its own name, then its family
+    pub fn cfg_names(self) -> &'static [&'static str] {
+        match self {
+            CompileTarget::Wasm => &["wasm"],
+            CompileTarget::Wat => &["wat", "wasm"],
+            CompileTarget::Hybrid => &["hybrid", "wasm"],
+            CompileTarget::Js => &["js"],
+            CompileTarget::Llvm => &["llvm", "native"],
+            CompileTarget::Rust => &["rust", "native"],
+            CompileTarget::Interpret => &["interpret", "native"],
+            CompileTarget::Test => &["test", "native"],
+            CompileTarget::SpirV => &["spirv", "gpu"],
+            CompileTarget::Hlsl => &["hlsl", "gpu"],
+            CompileTarget::Usf => &["usf", "gpu"],
+        }
+    }
+
+    /// Pointer width in bits of the code this target produces, as comptime's `word_size()` reports it
+    pub fn word_size(self) -> u32 {
+        match self {
+            CompileTarget::Wasm | CompileTarget::Wat | CompileTarget::Hybrid | CompileTarget::Js => 32,
+            CompileTarget::SpirV | CompileTarget::Hlsl | CompileTarget::Usf => 32,
+            CompileTarget::Llvm | CompileTarget::Rust | CompileTarget::Interpret | CompileTarget::Test => usize::BITS,
+        }
+    }
+}
+
+/// What an llvm build produces
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum CrateType {
+    /// An executable with `main` as its entry point
+    #[default]
+    Bin,
+    /// An object file for a C or C++ project to link, with a header declaring its exports
+    Lib,
+    /// A static archive (`.a`, `.lib`) of the program and the C runtime, with a header
+    Staticlib,
+    /// A shared library (`.so`, `.dylib`, `.dll`) of the program and the C runtime,
+    /// with a header. Only the program's exports are visible outside it.
+    Cdylib,
+}
+
+impl CrateType {
+    /// Parse a `--crate-type` name
+    pub fn parse(name: &str) -> Option<CrateType> {
+        Some(match name.to_lowercase().as_str() {
+            "bin" => CrateType::Bin,
+            "lib" => CrateType::Lib,
+            "staticlib" => CrateType::Staticlib,
+            "cdylib" => CrateType::Cdylib,
+            _ => return None,
+        })
+    }
+
+    /// Whether the build is linked into someone else's program rather than run
+    pub fn is_library(self) -> bool {
+        self != CrateType::Bin
+    }
+}
+
 /// Version of the KAIN language
 pub const VERSION: &str = "0.1.0";
 pub const LANGUAGE_NAME: &str = "KAIN";
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_recoverable_reports_instead_of_panicking() {
+        for source in ["fn (", "struct:\n    x", "<div>{</span>", "match x:\n  =>", "f\"{\"", "#[[ open"] {
+            let outcome = parse_recoverable(source);
+            assert!(!outcome.diagnostics.is_empty(), "expected diagnostics for {:?}", source);
+            assert!(!outcome.diagnostics[0].to_string().contains("internal error"), "{:?} panicked", source);
+        }
+
+        let caught = guard_pass(Stage::TypeChecker, "fn main():\n", || -> Result<(), KainError> { panic!("boom") });
+        assert!(matches!(caught, Err(KainError::Type { ref message, .. }) if message == "internal error in the type checker: boom"));
+
+        let outcome = parse_recoverable("fn main():\n    println(1)\n");
+        assert!(outcome.diagnostics.is_empty());
+        assert!(outcome.typed.is_some());
+    }
+
+    #[test]
+    fn test_parse_recoverable_keeps_going_after_syntax_errors() {
+        let outcome = parse_recoverable("fn broken(:\n    x\n\nfn main():\n    let = 1\n    println(2)\n\nfn ok() -> Int:\n    return 1\n");
+        assert_eq!(outcome.diagnostics.len(), 2, "{:?}", outcome.diagnostics);
+        let program = outcome.program.unwrap();
+        assert!(matches!(program.items[0], Item::Error(_)));
+        let Item::Function(main) = &program.items[1] else { panic!("{:?}", program.items[1]) };
+        assert!(matches!(main.body.stmts[0], Stmt::Expr(Expr::Error(_))));
+        assert_eq!(main.body.stmts.len(), 2);
+        assert_eq!(program.items[2].name(), Some("ok"));
+        assert!(outcome.typed.is_some());
+
+        // Compiling still stops at the first error
+        assert!(compile("fn main():\n    let = 1\n", CompileTarget::Js).is_err());
+    }
+
+    #[test]
+    fn test_eval_snippet_captures_output_and_times_out() {
+        let result = eval_snippet("fn main() -> Int:\n    println(\"hello\")\n    return 41 + 1\n", &CompileOptions::default());
+        assert!(result.diagnostics.is_empty(), "{:?}", result.diagnostics);
+        assert_eq!(result.stdout.trim(), "hello");
+        assert!(matches!(result.value, Some(runtime::Value::Int(42))));
+
+        let mut options = CompileOptions::default();
+        options.limits.timeout = Some(std::time::Duration::from_millis(50));
+        let result = eval_snippet("fn main():\n    println(\"start\")\n    loop:\n        let x = 1\n", &options);
+        assert_eq!(result.stdout.trim(), "start");
+        assert!(result.value.is_none());
+        assert!(result.diagnostics[0].to_string().contains("ran longer than 50ms"));
+
+        // Blocking natives count no steps, so they check the deadline themselves
+        let result = eval_snippet("fn main():\n    dbg(7)\n    sleep(60000)\n", &options);
+        assert!(result.duration < std::time::Duration::from_secs(5), "{:?}", result.duration);
+        assert_eq!(result.stdout.trim(), "[DEBUG] 7");
+        assert!(result.diagnostics[0].to_string().contains("ran longer than 50ms"));
+    }
+
+    #[test]
+    fn test_compile_session_lowers_once_for_many_targets() {
+        let source = "fn id<T>(x: T) -> T:\n    return x\n\nfn main():\n    println(id(42))\n";
+        let mut session = CompileSession::new(source, CompileOptions::default()).unwrap();
+        for target in [CompileTarget::Wasm, CompileTarget::Wat, CompileTarget::Js] {
+            assert_eq!(session.compile(target).unwrap(), compile(source, target).unwrap(), "{:?}", target);
+        }
+        assert!(!session.target_dependent);
+        assert_eq!((session.checked.len(), session.monomorphized.len()), (1, 1));
+
+        // Programs that read the target are lowered again for each
+        let source = "@cfg(target = \"js\")\nfn storage() -> String:\n    return \"local_storage\"\n\n@cfg(not(target = \"js\"))\nfn storage() -> String:\n    return \"disk_file\"\n\nfn main():\n    println(storage())\n";
+        let mut session = CompileSession::new(source, CompileOptions::default()).unwrap();
+        let js = String::from_utf8(session.compile(CompileTarget::Js).unwrap()).unwrap();
+        let rust = String::from_utf8(session.compile(CompileTarget::Rust).unwrap()).unwrap();
+        assert!(js.contains("local_storage") && !js.contains("disk_file"));
+        assert!(rust.contains("disk_file") && !rust.contains("local_storage"));
+        assert_eq!(session.checked.len(), 2);
+    }
+
+    #[test]
+    fn test_compile_with_reports_what_the_compile_learned() {
+        let source = "pub const LIMIT: Int = 10\n\nstruct Point:\n    x: Int\n\nfn id<T>(x: T) -> T:\n    return x\n\npub fn main():\n    println(id(LIMIT))\n";
+        let output = compile_with(source, CompileTarget::Wasm, &CompileOptions::default()).unwrap();
+        assert_eq!(output.artifact, compile(source, CompileTarget::Wasm).unwrap());
+        let exports: Vec<_> = output.exports.iter().map(|e| e.symbol.as_str()).collect();
+        assert_eq!(exports, vec!["LIMIT", "main"]);
+
+        // Generic functions are listed even though monomorphization replaced them
+        let names: Vec<_> = output.symbol_table.iter().map(|s| (s.name.as_str(), s.kind)).collect();
+        assert_eq!(names, vec![("LIMIT", types::SymbolKind::Const), ("Point", types::SymbolKind::Struct), ("id", types::SymbolKind::Function), ("main", types::SymbolKind::Function)]);
+
+        let phases: Vec<_> = output.timings.iter().map(|(phase, _)| *phase).collect();
+        assert_eq!(phases, vec![Phase::Lex, Phase::Parse, Phase::Modules, Phase::Comptime, Phase::TypeCheck, Phase::Check, Phase::Monomorphize, Phase::Codegen]);
+        assert!(output.warnings.is_empty());
+    }
+}
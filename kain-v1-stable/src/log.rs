@@ -0,0 +1,237 @@
+//! Leveled logging for KAIN programs and the compiler
+//!
+//! The `log_debug` / `log_info` / `log_warn` / `log_error` builtins and the
+//! compiler's own progress output both end up in [`log`]. A record is kept if
+//! its level reaches the level configured for its target: the module that
+//! logged it (`main` for the program itself, `compiler` for the toolchain).
+//!
+//! The filter is read from `KAIN_LOG`, e.g. `warn` or `info,http=debug`, and
+//! can be replaced at runtime with `log_set_filter`. `KAIN_LOG_FORMAT=json`
+//! (or `log_set_format("json")`) writes one JSON object per record instead of
+//! a text line. Records go to stderr so they don't mix with program output.
+
+use std::fmt;
+use std::io::Write;
+use std::sync::RwLock;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use once_cell::sync::Lazy;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Level {
+    Debug,
+    Info,
+    Warn,
+    Error,
+}
+
+impl Level {
+    pub fn parse(s: &str) -> Option<Level> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "debug" | "trace" => Some(Level::Debug),
+            "info" => Some(Level::Info),
+            "warn" | "warning" => Some(Level::Warn),
+            "error" => Some(Level::Error),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Level::Debug => "debug",
+            Level::Info => "info",
+            Level::Warn => "warn",
+            Level::Error => "error",
+        }
+    }
+}
+
+impl fmt::Display for Level {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Minimum level per target, with a default for targets not listed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Filter {
+    default: Level,
+    targets: Vec<(String, Level)>,
+}
+
+impl Filter {
+    /// `level`, `target=level`, or a comma separated mix of both
+    pub fn parse(spec: &str) -> Result<Filter, String> {
+        let mut filter = Filter::default();
+        for part in spec.split(',').map(str::trim).filter(|p| !p.is_empty()) {
+            let invalid = || format!("invalid log level in '{}', expected debug, info, warn or error", part);
+            match part.split_once('=') {
+                Some((target, level)) => {
+                    filter.targets.push((target.trim().to_string(), Level::parse(level).ok_or_else(invalid)?))
+                }
+                None => filter.default = Level::parse(part).ok_or_else(invalid)?,
+            }
+        }
+        Ok(filter)
+    }
+
+    /// The most specific entry wins: `http` also covers `http/client`
+    pub fn enabled(&self, target: &str, level: Level) -> bool {
+        let covers = |prefix: &str| {
+            target == prefix || target.strip_prefix(prefix).is_some_and(|rest| rest.starts_with('/') || rest.starts_with("::"))
+        };
+        let min = self
+            .targets
+            .iter()
+            .filter(|(prefix, _)| covers(prefix))
+            .max_by_key(|(prefix, _)| prefix.len())
+            .map_or(self.default, |(_, level)| *level);
+        level >= min
+    }
+}
+
+impl Default for Filter {
+    fn default() -> Self {
+        Filter { default: Level::Info, targets: Vec::new() }
+    }
+}
+
+struct Config {
+    filter: Filter,
+    json: bool,
+}
+
+static CONFIG: Lazy<RwLock<Config>> = Lazy::new(|| {
+    let filter = std::env::var("KAIN_LOG").ok().and_then(|spec| Filter::parse(&spec).ok()).unwrap_or_default();
+    let json = std::env::var("KAIN_LOG_FORMAT").is_ok_and(|f| f.eq_ignore_ascii_case("json"));
+    RwLock::new(Config { filter, json })
+});
+
+pub fn set_filter(filter: Filter) {
+    CONFIG.write().unwrap_or_else(|e| e.into_inner()).filter = filter;
+}
+
+pub fn set_json(json: bool) {
+    CONFIG.write().unwrap_or_else(|e| e.into_inner()).json = json;
+}
+
+/// Lower the level for `target` to `level` unless something more verbose is set
+pub fn enable(target: &str, level: Level) {
+    let mut config = CONFIG.write().unwrap_or_else(|e| e.into_inner());
+    if !config.filter.enabled(target, level) {
+        config.filter.targets.push((target.to_string(), level));
+    }
+}
+
+pub fn enabled(target: &str, level: Level) -> bool {
+    CONFIG.read().unwrap_or_else(|e| e.into_inner()).filter.enabled(target, level)
+}
+
+/// Write a record to stderr if the filter lets it through
+pub fn log(level: Level, target: &str, message: &str, fields: &[(String, serde_json::Value)]) {
+    let config = CONFIG.read().unwrap_or_else(|e| e.into_inner());
+    if !config.filter.enabled(target, level) {
+        return;
+    }
+    let line = format_record(SystemTime::now(), level, target, message, fields, config.json);
+    drop(config);
+    let _ = writeln!(std::io::stderr().lock(), "{}", line);
+}
+
+pub fn debug(target: &str, message: &str) {
+    log(Level::Debug, target, message, &[]);
+}
+
+pub fn info(target: &str, message: &str) {
+    log(Level::Info, target, message, &[]);
+}
+
+/// One record as a text line or a JSON object
+pub fn format_record(
+    time: SystemTime,
+    level: Level,
+    target: &str,
+    message: &str,
+    fields: &[(String, serde_json::Value)],
+    json: bool,
+) -> String {
+    let ts = timestamp(time);
+    if json {
+        let mut record = serde_json::Map::new();
+        record.insert("ts".to_string(), ts.into());
+        record.insert("level".to_string(), level.as_str().into());
+        record.insert("target".to_string(), target.into());
+        record.insert("msg".to_string(), message.into());
+        for (key, value) in fields {
+            record.insert(key.clone(), value.clone());
+        }
+        return serde_json::Value::Object(record).to_string();
+    }
+    let mut line = format!("{} {:<5} {}: {}", ts, level.as_str().to_uppercase(), target, message);
+    for (key, value) in fields {
+        match value {
+            serde_json::Value::String(s) if !s.contains(char::is_whitespace) && !s.is_empty() => {
+                line.push_str(&format!(" {}={}", key, s))
+            }
+            value => line.push_str(&format!(" {}={}", key, value)),
+        }
+    }
+    line
+}
+
+/// RFC 3339 in UTC with milliseconds
+fn timestamp(time: SystemTime) -> String {
+    let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let secs = since_epoch.as_secs() as i64;
+    let (days, secs_of_day) = (secs.div_euclid(86_400), secs.rem_euclid(86_400));
+
+    // Civil date from days since 1970-01-01 (Howard Hinnant's algorithm)
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z",
+        year,
+        month,
+        day,
+        secs_of_day / 3600,
+        secs_of_day % 3600 / 60,
+        secs_of_day % 60,
+        since_epoch.subsec_millis()
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn test_filters_and_formats_records() {
+        let filter = Filter::parse("warn,http=debug,http/pool=error").unwrap();
+        assert!(!filter.enabled("main", Level::Info));
+        assert!(filter.enabled("main", Level::Error));
+        assert!(filter.enabled("http/client", Level::Debug));
+        assert!(!filter.enabled("http/pool", Level::Warn));
+        assert!(!filter.enabled("https", Level::Info));
+        assert!(Filter::parse("loud").is_err());
+
+        let time = UNIX_EPOCH + Duration::from_millis(1_792_152_000_250);
+        let fields = vec![("user".to_string(), "ada".into()), ("note".to_string(), "two words".into())];
+        assert_eq!(
+            format_record(time, Level::Warn, "main", "slow request", &fields, false),
+            "2026-10-16T12:00:00.250Z WARN  main: slow request user=ada note=\"two words\""
+        );
+        assert_eq!(
+            format_record(time, Level::Info, "http", "ok", &fields[..1], true),
+            r#"{"level":"info","msg":"ok","target":"http","ts":"2026-10-16T12:00:00.250Z","user":"ada"}"#
+        );
+    }
+}
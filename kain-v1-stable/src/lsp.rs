@@ -2,12 +2,12 @@ use std::collections::{HashMap, hash_map::Entry};
 use tower_lsp::jsonrpc::Result;
 use tower_lsp::lsp_types::*;
 use tower_lsp::{Client, LanguageServer, LspService, Server};
-use crate::ast::{Program, Item, Function, Type};
-use crate::lexer::Lexer;
-use crate::parser::Parser;
+use crate::ast::{Program, Item, Function, Type, TypeAlias, EffectDecl};
 use crate::span::Span;
 use crate::error::KainError;
-use crate::types;
+use crate::vfs::{LineIndex, SourceMap};
+use crate::query::Database;
+use crate::edition::Edition;
 
 #[derive(Debug, Clone)]
 struct Document {
@@ -95,26 +95,54 @@ impl DocumentStore {
 #[derive(Debug, Clone)]
 struct DocumentAnalysis {
     symbols: HashMap<String, Vec<SymbolInfo>>,
+    /// Associated functions and constants of each type, completed after `Type::`
+    associated: HashMap<String, Vec<(String, SymbolInfo)>>,
 }
 
 #[derive(Debug, Clone)]
 struct SymbolInfo {
     range: Range,
     detail: Option<String>,
+    doc: Option<String>,
     kind: SymbolKind,
 }
 
 impl DocumentAnalysis {
     fn from_program(text: &str, program: &Program) -> Self {
         let mut symbols: HashMap<String, Vec<SymbolInfo>> = HashMap::new();
+        let mut associated: HashMap<String, Vec<(String, SymbolInfo)>> = HashMap::new();
 
         for item in &program.items {
+            if let Item::Impl(imp) = item {
+                let Type::Named { name: ty, .. } = &imp.target_type else { continue };
+                let members = associated.entry(ty.clone()).or_default();
+                for c in &imp.consts {
+                    if let Some(range) = find_identifier_range(text, &c.name, Some(c.span)) {
+                        let detail = Some(format!("const {}: {}", c.name, format_type(&c.ty)));
+                        members.push((c.name.clone(), SymbolInfo { range, detail, doc: c.doc.clone(), kind: SymbolKind::Constant }));
+                    }
+                }
+                for method in &imp.methods {
+                    if let Some(range) = find_identifier_range(text, &method.name, Some(method.span)) {
+                        let detail = Some(format_fn_signature(method));
+                        members.push((method.name.clone(), SymbolInfo { range, detail, doc: method.doc.clone(), kind: SymbolKind::Function }));
+                    }
+                }
+            }
+            if let Item::TypeAlias(alias) = item {
+                if let Some(range) = find_identifier_range(text, &alias.name, Some(alias.span)) {
+                    let detail = Some(format_type_alias(alias));
+                    symbols.entry(alias.name.clone())
+                        .or_default()
+                        .push(SymbolInfo { range, detail, doc: alias.doc.clone(), kind: SymbolKind::Type });
+                }
+            }
             if let Item::Function(func) = item {
                 if let Some(range) = find_identifier_range(text, &func.name, Some(func.span)) {
                     let detail = Some(format_fn_signature(func));
                     symbols.entry(func.name.clone())
                         .or_default()
-                        .push(SymbolInfo { range, detail, kind: SymbolKind::Function });
+                        .push(SymbolInfo { range, detail, doc: func.doc.clone(), kind: SymbolKind::Function });
                 }
 
                 for param in &func.params {
@@ -122,13 +150,13 @@ impl DocumentAnalysis {
                         let detail = Some(format!("param {}: {}", param.name, format_type(&param.ty)));
                         symbols.entry(param.name.clone())
                             .or_default()
-                            .push(SymbolInfo { range, detail, kind: SymbolKind::Variable });
+                            .push(SymbolInfo { range, detail, doc: None, kind: SymbolKind::Variable });
                     }
                 }
             }
         }
 
-        Self { symbols }
+        Self { symbols, associated }
     }
 
     fn lookup(&self, ident: &str) -> Option<&[SymbolInfo]> {
@@ -140,6 +168,8 @@ impl DocumentAnalysis {
 enum SymbolKind {
     Function,
     Variable,
+    Constant,
+    Type,
 }
 
 impl SymbolKind {
@@ -147,14 +177,16 @@ impl SymbolKind {
         match self {
             SymbolKind::Function => CompletionItemKind::FUNCTION,
             SymbolKind::Variable => CompletionItemKind::VARIABLE,
+            SymbolKind::Constant => CompletionItemKind::CONSTANT,
+            SymbolKind::Type => CompletionItemKind::CLASS,
         }
     }
 }
 
-fn format_fn_signature(function: &Function) -> String {
+pub(crate) fn format_fn_signature(function: &Function) -> String {
     let params = function.params
         .iter()
-        .map(|p| format!("{}: {}", p.name, format_type(&p.ty)))
+        .map(|p| format!("{}{}: {}", if p.variadic { "..." } else { "" }, p.name, format_type(&p.ty)))
         .collect::<Vec<_>>()
         .join(", ");
 
@@ -163,10 +195,36 @@ fn format_fn_signature(function: &Function) -> String {
         .map(|t| format_type(t))
         .unwrap_or_else(|| "()".to_string());
 
-    format!("fn {}({}) -> {}", function.name, params, ret)
+    if function.effects.is_empty() {
+        format!("fn {}({}) -> {}", function.name, params, ret)
+    } else {
+        let effects = function.effects.iter().map(|e| e.to_string()).collect::<Vec<_>>().join(", ");
+        format!("fn {}({}) -> {} with {}", function.name, params, ret, effects)
+    }
+}
+
+pub(crate) fn format_type_alias(alias: &TypeAlias) -> String {
+    let params = if alias.generics.is_empty() {
+        String::new()
+    } else {
+        format!("<{}>", alias.generics.iter().map(|g| g.name.as_str()).collect::<Vec<_>>().join(", "))
+    };
+    let new = if alias.newtype { "new " } else { "" };
+    format!("type {}{} = {}{}", alias.name, params, new, format_type(&alias.target))
+}
+
+pub(crate) fn format_effect_decl(decl: &EffectDecl) -> String {
+    match &decl.alias {
+        Some(members) => format!(
+            "effect {} = {}",
+            decl.name,
+            members.iter().map(|e| e.to_string()).collect::<Vec<_>>().join(" + ")
+        ),
+        None => format!("effect {}", decl.name),
+    }
 }
 
-fn format_type(ty: &Type) -> String {
+pub(crate) fn format_type(ty: &Type) -> String {
     match ty {
         Type::Named { name, generics, .. } => {
             if generics.is_empty() {
@@ -271,6 +329,14 @@ fn find_ident_at_offset(text: &str, offset: usize) -> Option<(String, Range)> {
     Some((ident, Range { start: start_pos, end: end_pos }))
 }
 
+/// `Type` when the identifier being typed at `offset` follows `Type::`
+fn associated_type_at(text: &str, offset: usize) -> Option<String> {
+    let before = text.get(..offset)?;
+    let before = before.trim_end_matches(is_ident_char).strip_suffix("::")?;
+    let ty = &before[before.trim_end_matches(is_ident_char).len()..];
+    (!ty.is_empty()).then(|| ty.to_string())
+}
+
 fn is_ident_char(c: char) -> bool {
     c.is_alphanumeric() || c == '_'
 }
@@ -281,16 +347,16 @@ fn diagnostic_from_error(text: &str, err: &KainError) -> Vec<Diagnostic> {
         KainError::Parser { message, span } => (message.clone(), *span),
         KainError::Type { message, span } => (message.clone(), *span),
         KainError::Effect { message, span } => (message.clone(), *span),
-        KainError::Borrow { message, span } => (message.clone(), *span),
+        KainError::Borrow { message, span, .. } => (message.clone(), *span),
         KainError::Codegen { message, span } => (message.clone(), *span),
+        KainError::Internal { code, message, span, .. } => (format!("internal compiler error [{}]: {}", code, message), *span),
         KainError::Runtime { message } => (message.clone(), Span::default()),
         KainError::Io(_) => return vec![],
     };
 
-    let range = span_to_range(text, span);
-    vec![Diagnostic {
-        range,
-        severity: Some(DiagnosticSeverity::ERROR),
+    let diagnostic = |span: Span, severity: DiagnosticSeverity, message: String| Diagnostic {
+        range: span_to_range(text, span),
+        severity: Some(severity),
         code: None,
         code_description: None,
         source: Some("KAIN".to_string()),
@@ -298,7 +364,13 @@ fn diagnostic_from_error(text: &str, err: &KainError) -> Vec<Diagnostic> {
         related_information: None,
         tags: None,
         data: None,
-    }]
+    };
+    let mut diagnostics = vec![diagnostic(span, DiagnosticSeverity::ERROR, message)];
+    // The other side of a borrow conflict is marked where it happened
+    if let KainError::Borrow { related: Some((related, note)), .. } = err {
+        diagnostics.push(diagnostic(*related, DiagnosticSeverity::INFORMATION, note.clone()));
+    }
+    diagnostics
 }
 
 fn span_to_range(text: &str, span: Span) -> Range {
@@ -311,6 +383,8 @@ fn span_to_range(text: &str, span: Span) -> Range {
 struct Backend {
     client: Client,
     docs: DocumentStore,
+    /// Parses and item checks kept between edits, so an edit rechecks only what it touched
+    queries: std::sync::Mutex<Database>,
 }
 
 #[tower_lsp::async_trait]
@@ -351,6 +425,7 @@ impl LanguageServer for Backend {
         let text = params.text_document.text;
         let version = params.text_document.version;
         self.docs.upsert(uri.clone(), text.clone(), version).await;
+        set_overlay(&uri, Some(&text));
         self.validate_document(uri, text).await;
     }
 
@@ -363,6 +438,7 @@ impl LanguageServer for Backend {
         let version = params.text_document.version;
         match self.docs.apply_changes(&uri, version, &params.content_changes).await {
             Some(text) => {
+                set_overlay(&uri, Some(&text));
                 self.validate_document(uri.clone(), text).await;
             }
             None => {
@@ -378,6 +454,8 @@ impl LanguageServer for Backend {
 
     async fn did_close(&self, params: DidCloseTextDocumentParams) {
         self.docs.remove(&params.text_document.uri).await;
+        set_overlay(&params.text_document.uri, None);
+        self.queries.lock().unwrap_or_else(|e| e.into_inner()).remove(&document_path(&params.text_document.uri));
         // Clear diagnostics on close
         self.client.publish_diagnostics(params.text_document.uri, vec![], None).await;
     }
@@ -407,9 +485,13 @@ impl LanguageServer for Backend {
 
         if let Some(symbols) = analysis.lookup(&ident) {
             if let Some(info) = symbols.first() {
+                let mut value = info.detail.clone().unwrap_or_else(|| ident.clone());
+                if let Some(doc) = &info.doc {
+                    value = format!("```kain\n{}\n```\n---\n{}", value, doc);
+                }
                 let contents = HoverContents::Markup(MarkupContent {
                     kind: MarkupKind::Markdown,
-                    value: info.detail.clone().unwrap_or_else(|| ident.clone()),
+                    value,
                 });
                 return Ok(Some(Hover {
                     contents,
@@ -462,6 +544,25 @@ impl LanguageServer for Backend {
             None => return Ok(None),
         };
 
+        // After `Type::`, only what the type's impls declare
+        let pos = params.text_document_position.position;
+        let offset = position_to_offset(&text, &pos);
+        if let Some(members) = offset.and_then(|offset| associated_type_at(&text, offset)).and_then(|ty| analysis.associated.get(&ty)) {
+            let prefix = offset.and_then(|offset| find_ident_at_offset(&text, offset)).map(|(prefix, _)| prefix).unwrap_or_default();
+            let items = members
+                .iter()
+                .filter(|(name, _)| name.starts_with(&prefix))
+                .map(|(name, info)| CompletionItem {
+                    label: name.clone(),
+                    kind: Some(info.kind.completion_item_kind()),
+                    detail: info.detail.clone(),
+                    documentation: info.doc.clone().map(Documentation::String),
+                    ..CompletionItem::default()
+                })
+                .collect();
+            return Ok(Some(CompletionResponse::Array(items)));
+        }
+
         let mut items = Vec::new();
         for (name, infos) in analysis.symbols.iter() {
             if let Some(info) = infos.first() {
@@ -474,9 +575,33 @@ impl LanguageServer for Backend {
             }
         }
 
+        // Builtins the document doesn't shadow
+        let stdlib = crate::stdlib::stdlib();
+        for (name, func) in &stdlib.functions {
+            if !analysis.symbols.contains_key(name) {
+                items.push(CompletionItem {
+                    label: name.clone(),
+                    kind: Some(CompletionItemKind::FUNCTION),
+                    detail: Some(func.signature()),
+                    documentation: Some(Documentation::String(func.doc.to_string())),
+                    ..CompletionItem::default()
+                });
+            }
+        }
+        for (name, constant) in &stdlib.constants {
+            if !analysis.symbols.contains_key(name) {
+                items.push(CompletionItem {
+                    label: name.clone(),
+                    kind: Some(CompletionItemKind::CONSTANT),
+                    detail: Some(constant.ty.to_string()),
+                    documentation: Some(Documentation::String(constant.doc.to_string())),
+                    ..CompletionItem::default()
+                });
+            }
+        }
+
         // Basic filtering by current ident prefix (optional)
-        let pos = params.text_document_position.position;
-        if let Some(offset) = position_to_offset(&text, &pos) {
+        if let Some(offset) = offset {
             if let Some((prefix, _)) = find_ident_at_offset(&text, offset) {
                 items.retain(|item| item.label.starts_with(&prefix));
             }
@@ -488,75 +613,46 @@ impl LanguageServer for Backend {
 
 impl Backend {
     async fn validate_document(&self, uri: Url, text: String) {
-        // Run lexer and parser to get errors
-        let lexer = Lexer::new(&text);
-        let tokens = match lexer.tokenize() {
-            Ok(t) => t,
-            Err(e) => {
-                let diag = diagnostic_from_error(&text, &e);
-                self.client.publish_diagnostics(uri.clone(), diag, None).await;
-                self.docs.update_analysis(&uri, None).await;
-                return;
-            }
-        };
-
-        let mut parser = Parser::new(&tokens);
-        
-        let program = match parser.parse() {
-            Ok(p) => p,
-            Err(e) => {
-                let diag = diagnostic_from_error(&text, &e);
-                self.client.publish_diagnostics(uri.clone(), diag, None).await;
-                self.docs.update_analysis(&uri, None).await;
-                return;
+        // Run the front end through the query database; it reports panics and
+        // errors as diagnostics, recovers from syntax errors so the rest of the
+        // file is still analysed, and only rechecks the items an edit changed
+        let (diagnostics, analysis) = {
+            let mut queries = self.queries.lock().unwrap_or_else(|e| e.into_inner());
+            let path = document_path(&uri);
+            queries.set_text(path.clone(), text.as_str());
+            match queries.check(&path) {
+                Ok(check) => (
+                    check.errors().flat_map(|e| diagnostic_from_error(&text, e)).collect(),
+                    // Build analysis for hover/definition/completion
+                    check.parsed.program.as_ref().map(|program| DocumentAnalysis::from_program(&text, program)),
+                ),
+                Err(e) => (diagnostic_from_error(&text, &e), None),
             }
         };
+        self.docs.update_analysis(&uri, analysis).await;
 
-        // Type check for richer diagnostics
-        if let Err(e) = types::check(&program) {
-            let diag = diagnostic_from_error(&text, &e);
-            self.client.publish_diagnostics(uri.clone(), diag, None).await;
-            self.docs.update_analysis(&uri, None).await;
-            return;
-        }
-
-        // Build analysis for hover/definition/completion
-        let analysis = DocumentAnalysis::from_program(&text, &program);
-        self.docs.update_analysis(&uri, Some(analysis)).await;
-
-        self.client.publish_diagnostics(uri, vec![], None).await;
-    }
-
-    fn span_to_range(&self, text: &str, span: crate::span::Span) -> Range {
-        // Convert byte offset span to line/col range
-        let start = self.offset_to_position(text, span.start);
-        let end = self.offset_to_position(text, span.end);
-        Range { start, end }
+        self.client.publish_diagnostics(uri, diagnostics, None).await;
     }
+}
 
-    #[allow(dead_code)]
-    fn offset_to_position(&self, text: &str, offset: usize) -> Position {
-        let mut line = 0;
-        let mut col = 0;
-        let mut cur = 0;
-
-        for c in text.chars() {
-            if cur >= offset {
-                break;
-            }
-            if c == '\n' {
-                line += 1;
-                col = 0;
-            } else {
-                col += 1;
-            }
-            cur += c.len_utf8();
-        }
+/// Where the query database files a document: its path, or its URI if it has none
+fn document_path(uri: &Url) -> std::path::PathBuf {
+    uri.to_file_path().unwrap_or_else(|_| std::path::PathBuf::from(uri.as_str()))
+}
 
-        Position { line, character: col }
+/// Let modules that import an open document see its unsaved text, or the file on disk again once closed
+fn set_overlay(uri: &Url, text: Option<&str>) {
+    let Ok(path) = uri.to_file_path() else {
+        return;
+    };
+    let mut sources = SourceMap::global().write().unwrap_or_else(|e| e.into_inner());
+    match text {
+        Some(text) => sources.set_overlay(&path, text),
+        None => sources.remove_overlay(&path),
     }
 }
 
+
 pub async fn run_server() {
     let stdin = tokio::io::stdin();
     let stdout = tokio::io::stdout();
@@ -564,6 +660,8 @@ pub async fn run_server() {
     let (service, socket) = LspService::new(|client| Backend {
         client,
         docs: DocumentStore::default(),
+        // Documents in a project are read under its KAIN.toml's edition, others under the default
+        queries: std::sync::Mutex::new(Database::new(Edition::default())),
     });
     Server::new(stdin, stdout, socket).serve(service).await;
 }
@@ -582,63 +680,12 @@ fn apply_change(text: &str, range: &Range, new_text: &str) -> Option<String> {
     Some(result)
 }
 
+/// Byte offset of an LSP position; columns past the end of a line land at its end (append edits)
 fn position_to_offset(text: &str, position: &Position) -> Option<usize> {
-    let mut offset = 0usize;
-    let mut line = 0usize;
-    let mut col = 0usize;
-    let target_line = position.line as usize;
-    let target_col = position.character as usize;
-
-    for ch in text.chars() {
-        if line == target_line && col == target_col {
-            return Some(offset);
-        }
-
-        let ch_len = ch.len_utf8();
-        offset += ch_len;
-
-        if ch == '\n' {
-            if line == target_line && target_col == col {
-                return Some(offset);
-            }
-            line += 1;
-            col = 0;
-        } else {
-            col += 1;
-        }
-    }
-
-    if line == target_line && col == target_col {
-        Some(offset)
-    } else if line == target_line && target_col >= col {
-        // Allow positions that extend past the current line (append edits)
-        Some(offset)
-    } else {
-        None
-    }
+    LineIndex::new(text).offset(text, position.line as usize, position.character as usize)
 }
 
-
 fn offset_to_position_standalone(text: &str, offset: usize) -> Position {
-    let mut line = 0;
-    let mut col = 0;
-    let mut cur = 0;
-
-    for c in text.chars() {
-        if cur >= offset {
-            break;
-        }
-        if c == '\n' {
-            line += 1;
-            col = 0;
-        } else {
-            col += 1;
-        }
-        cur += c.len_utf8();
-    }
-
-    Position { line, character: col }
+    let (line, col) = LineIndex::new(text).line_col(text, offset);
+    Position { line: (line - 1) as u32, character: (col - 1) as u32 }
 }
-
-
-
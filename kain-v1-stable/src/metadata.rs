@@ -0,0 +1,263 @@
+//! Project metadata for other build tools (`kain metadata --json`)
+//!
+//! Everything a build system driving `kain build` needs to know without
+//! parsing KAIN.toml itself: the package, the profile's targets and where
+//! each one's output lands, the modules the entry file imports (followed
+//! transitively) and the files they are read from, dependencies, and the
+//! native tools this machine would build with. Paths are absolute.
+//!
+//! The document is produced even for a project that doesn't fully resolve:
+//! a module that can't be found or parsed is listed without a file, and why
+//! is added to `errors`, so a CI runner sees every problem at once.
+
+use std::collections::{BTreeSet, HashSet, VecDeque};
+use std::path::{Path, PathBuf};
+
+use serde::Serialize;
+
+use crate::ast::{Item, Use};
+use crate::edition::Edition;
+use crate::error::{KainError, KainResult};
+use crate::packager::{self, PackageManifest};
+use crate::toolchain::{self, Toolchain};
+use crate::CrateType;
+
+/// Version of the document's layout, raised when a field changes meaning or is removed
+pub const FORMAT_VERSION: u32 = 1;
+
+#[derive(Debug, Serialize)]
+pub struct Metadata {
+    pub format_version: u32,
+    pub compiler_version: &'static str,
+    /// The directory holding KAIN.toml
+    pub root: PathBuf,
+    pub package: Package,
+    pub profile: String,
+    pub targets: Vec<Target>,
+    /// The entry module first, then the modules it imports in the order they are found
+    pub modules: Vec<Module>,
+    /// Every file the build reads: sources, the build script and the SQL schema
+    pub files: Vec<PathBuf>,
+    pub dependencies: Vec<Dependency>,
+    pub toolchain: Tools,
+    pub errors: Vec<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Package {
+    pub name: String,
+    pub version: String,
+    pub language_version: String,
+    pub entry: PathBuf,
+    pub manifest: PathBuf,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Target {
+    pub name: String,
+    /// The file `kain build` writes
+    pub output: PathBuf,
+    /// Files made from the output afterwards, such as the linked executable of the llvm target
+    pub artifacts: Vec<PathBuf>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Module {
+    /// The path `use` names it by, or the package name for the entry module
+    pub name: String,
+    /// `None` when it could not be found
+    pub file: Option<PathBuf>,
+    /// Modules this one uses, by name
+    pub imports: Vec<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Dependency {
+    pub name: String,
+    pub version: String,
+    /// Where `kain install` unpacks it
+    pub path: PathBuf,
+    pub installed: bool,
+}
+
+/// The commands the toolchain resolves to; `None` for a tool that isn't found
+#[derive(Debug, Default, Serialize)]
+pub struct Tools {
+    pub cc: Option<String>,
+    pub linker: Option<String>,
+    pub ar: Option<String>,
+    pub wasm_opt: Option<String>,
+}
+
+impl Metadata {
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).unwrap_or_else(|_| "{}".to_string())
+    }
+}
+
+/// Describe the project in `root` as built with `profile` (`debug` when `None`)
+pub fn project_metadata(root: &Path, profile: Option<&str>, crate_type: CrateType) -> KainResult<Metadata> {
+    let root = root.canonicalize().map_err(KainError::Io)?;
+    let manifest = packager::load_manifest(&root)?;
+    let profile_name = profile.unwrap_or("debug");
+    let settings = manifest.profile(profile_name)?;
+    let language_version = manifest.package.language_version.clone().unwrap_or_else(|| "0.1".to_string());
+    let edition = Edition::parse(&language_version).ok_or_else(|| {
+        KainError::runtime(format!("Unknown language_version '{}' in KAIN.toml, expected 0.1, 0.2, 0.3, 0.4 or 0.5", language_version))
+    })?;
+    let entry = root.join(&manifest.build.entry);
+    let mut errors = Vec::new();
+
+    let stem = entry.file_stem().and_then(|s| s.to_str()).unwrap_or("output");
+    let output_dir = manifest.profile_dir(&root, profile_name, &settings);
+    let names = settings.targets.clone().unwrap_or_else(|| manifest.build.targets.clone());
+    let mut targets = Vec::new();
+    for name in names {
+        let Some(ext) = packager::output_extension(&name) else {
+            errors.push(format!("Unknown target: {}", name));
+            continue;
+        };
+        let dir = root.join(packager::target_dir(&output_dir, &name, &settings.for_target(&name)));
+        let output = dir.join(stem).with_extension(ext);
+        let artifacts = if name == "llvm" { vec![packager::linked_path(&output, crate_type)] } else { Vec::new() };
+        targets.push(Target { name, output, artifacts });
+    }
+
+    let modules = find_modules(&root, &entry, &manifest.package.name, edition, &mut errors);
+    let mut files: BTreeSet<PathBuf> = modules.iter().filter_map(|m| m.file.clone()).collect();
+    let script = root.join(manifest.build.script.clone().unwrap_or_else(|| PathBuf::from("build.kn")));
+    if manifest.build.script.is_some() || script.exists() {
+        files.insert(script);
+    }
+    if let Some(schema) = &manifest.build.schema {
+        files.insert(root.join(schema));
+    }
+
+    let mut dependencies: Vec<Dependency> = manifest
+        .dependencies
+        .iter()
+        .map(|(name, version)| {
+            let path = root.join("deps").join(name);
+            Dependency { name: name.clone(), version: version.clone(), installed: path.exists(), path }
+        })
+        .collect();
+    dependencies.sort_by(|a, b| a.name.cmp(&b.name));
+
+    Ok(Metadata {
+        format_version: FORMAT_VERSION,
+        compiler_version: env!("CARGO_PKG_VERSION"),
+        package: Package {
+            name: manifest.package.name.clone(),
+            version: manifest.package.version.clone(),
+            language_version,
+            entry,
+            manifest: root.join("KAIN.toml"),
+        },
+        profile: profile_name.to_string(),
+        targets,
+        modules,
+        files: files.into_iter().collect(),
+        dependencies,
+        toolchain: tools(&manifest),
+        errors,
+        root,
+    })
+}
+
+/// The entry module and every module it imports, directly or not
+fn find_modules(root: &Path, entry: &Path, package: &str, edition: Edition, errors: &mut Vec<String>) -> Vec<Module> {
+    let mut modules = Vec::new();
+    let mut seen = HashSet::new();
+    let mut queue = VecDeque::from([(package.to_string(), Some(entry.to_path_buf()))]);
+    while let Some((name, file)) = queue.pop_front() {
+        let mut imports = Vec::new();
+        if let Some(file) = &file {
+            match crate::runtime::parse_module(file, &name, edition) {
+                Ok(program) => uses(&program.items, &mut |u| imports.push(u.path.join("/"))),
+                Err(e) => errors.push(format!("{}: {}", file.display(), e)),
+            }
+        }
+        for import in &imports {
+            if !seen.insert(import.clone()) {
+                continue;
+            }
+            match crate::runtime::resolve_import(root, file.as_deref(), import) {
+                // The core stdlib is part of the compiler
+                Ok(None) => {}
+                Ok(Some(file)) => queue.push_back((import.clone(), Some(file))),
+                Err(e) => {
+                    errors.push(format!("{} (imported by {})", e, name));
+                    queue.push_back((import.clone(), None));
+                }
+            }
+        }
+        modules.push(Module { name, file, imports });
+    }
+    modules
+}
+
+/// Call `f` with each `use` in `items`, including those in inline modules and behind `@cfg`
+pub(crate) fn uses(items: &[Item], f: &mut dyn FnMut(&Use)) {
+    for item in items {
+        match item {
+            Item::Use(u) => f(u),
+            Item::Cfg(c) => uses(std::slice::from_ref(&*c.item), f),
+            Item::Mod(m) => uses(m.inline.as_deref().unwrap_or_default(), f),
+            _ => {}
+        }
+    }
+}
+
+fn tools(manifest: &PackageManifest) -> Tools {
+    let env = |name: &str| std::env::var(name).ok();
+    let mut tools = Tools {
+        wasm_opt: toolchain::resolve_wasm_opt(&manifest.toolchain, env).ok().map(|tool| tool.to_string()),
+        ..Tools::default()
+    };
+    if let Ok(found) = Toolchain::resolve(manifest.toolchain.clone(), env) {
+        tools.cc = Some(found.cc.to_string());
+        tools.linker = Some(found.linker.to_string());
+        tools.ar = found.archiver().ok().map(|tool| tool.to_string());
+    }
+    tools
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_metadata_follows_imports_and_places_outputs() {
+        let dir = std::env::temp_dir().join(format!("kain-metadata-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("src")).unwrap();
+        std::fs::write(
+            dir.join("KAIN.toml"),
+            "[package]\nname = \"game\"\nversion = \"0.2.0\"\n\n[build]\ntargets = [\"wasm\", \"js\", \"cobol\"]\n\n[dependencies]\njson = \"1.0\"\n",
+        )
+        .unwrap();
+        std::fs::write(dir.join("src/main.kn"), "use physics\nuse missing\n\nfn main():\n    println(step(1))\n").unwrap();
+        std::fs::write(dir.join("src/physics.kn"), "use stdlib\n\npub fn step(x: Int) -> Int:\n    return x + 1\n").unwrap();
+
+        let metadata = project_metadata(&dir, None, CrateType::Bin).unwrap();
+        let root = dir.canonicalize().unwrap();
+        assert_eq!(metadata.profile, "debug");
+        assert_eq!(metadata.package.entry, root.join("src/main.kn"));
+        let outputs: Vec<_> = metadata.targets.iter().map(|t| (t.name.as_str(), t.output.clone())).collect();
+        assert_eq!(outputs, [("wasm", root.join("target/debug/wasm/main.wasm")), ("js", root.join("target/debug/js/main.js"))]);
+
+        let names: Vec<_> = metadata.modules.iter().map(|m| m.name.as_str()).collect();
+        assert_eq!(names, ["game", "physics", "missing"]);
+        assert_eq!(metadata.modules[0].imports, ["physics", "missing"]);
+        assert!(metadata.modules[2].file.is_none());
+        assert_eq!(metadata.files.len(), 2);
+        assert!(metadata.errors.iter().any(|e| e.contains("Unknown target: cobol")), "{:?}", metadata.errors);
+        assert!(metadata.errors.iter().any(|e| e.contains("Module not found: missing")), "{:?}", metadata.errors);
+        assert_eq!(metadata.dependencies[0].path, root.join("deps/json"));
+        assert!(!metadata.dependencies[0].installed);
+
+        let json: serde_json::Value = serde_json::from_str(&metadata.to_json()).unwrap();
+        assert_eq!(json["format_version"], FORMAT_VERSION);
+        assert_eq!(json["package"]["name"], "game");
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,541 @@
+//! Modules, resolved before anything is type checked
+//!
+//! [`graph`] follows a program's `use` paths to the files they name and parses
+//! each module once, so an import cycle or a missing module is reported at the
+//! `use` that leads to it. A path is looked up next to the file that imports
+//! it first, then from the working directory.
+//!
+//! Every target runs or compiles one program, so [`link`] copies the imported
+//! modules' items into it and the type checker sees them as if they had been
+//! written there:
+//!
+//! ```text
+//! use tests/modules/points: {new}     new(1, 2)    ->  tests_modules_points__new(1, 2)
+//! use tests/modules/circles as c      c.area(2)    ->  area(2)
+//! ```
+//!
+//! A function, const or static keeps its name unless the program or another
+//! module declares one of the same name, in which case it is prefixed with its
+//! module's path, along with every use of it. Structs, enums and the other
+//! types keep their names, so two modules declaring one are an error.
+
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use crate::ast::visit::{walk_expr_mut, walk_param, walk_pattern, Visitor, VisitorMut};
+use crate::ast::*;
+use crate::edition::Edition;
+use crate::error::{KainError, KainResult};
+use crate::metadata::uses;
+use crate::runtime::{parse_module, resolve_import};
+use crate::span::Span;
+
+/// A module a program imports, directly or through another module
+#[derive(Debug, Clone)]
+pub struct Module {
+    /// The path `use` names it by, `a/b` for `use a::b`
+    pub path: String,
+    pub file: PathBuf,
+    pub program: Arc<Program>,
+}
+
+/// The modules a program imports, each after the modules it imports
+#[derive(Debug, Clone, Default)]
+pub struct ModuleGraph {
+    pub modules: Vec<Module>,
+    /// Index in `modules` by the file of each module importing one (`None`
+    /// for the program) and the path it imports it by
+    by_path: HashMap<(Option<PathBuf>, String), usize>,
+}
+
+impl ModuleGraph {
+    /// The module `u` in `importer`'s file (the program's when `None`) imports;
+    /// `None` for the core stdlib
+    pub fn get(&self, importer: Option<&Path>, u: &Use) -> Option<&Module> {
+        self.index(importer, u).map(|index| &self.modules[index])
+    }
+
+    fn index(&self, importer: Option<&Path>, u: &Use) -> Option<usize> {
+        self.by_path.get(&(importer.map(Path::to_path_buf), u.path.join("/"))).copied()
+    }
+}
+
+/// Find and parse every module `program` imports; `entry` is the file it was read from, if any
+pub fn graph(program: &Program, entry: Option<&Path>, edition: Edition) -> KainResult<ModuleGraph> {
+    let mut loader = Loader { entry: entry.map(Path::to_path_buf), edition, graph: ModuleGraph::default(), loading: Vec::new() };
+    let mut result = Ok(());
+    uses(&program.items, &mut |u| {
+        if result.is_ok() {
+            result = loader.load(u, None);
+        }
+    });
+    result.map(|()| loader.graph)
+}
+
+struct Loader {
+    /// The program's file, which its `use` paths are relative to
+    entry: Option<PathBuf>,
+    edition: Edition,
+    graph: ModuleGraph,
+    /// Modules being loaded, innermost last, as the cycle message names them
+    loading: Vec<(PathBuf, String)>,
+}
+
+impl Loader {
+    /// Load the module `u` in `importer`'s file (the program's when `None`) imports
+    fn load(&mut self, u: &Use, importer: Option<&Path>) -> KainResult<()> {
+        let path = u.path.join("/");
+        let key = (importer.map(Path::to_path_buf), path.clone());
+        let file = match resolve_import(Path::new(""), importer.or(self.entry.as_deref()), &path) {
+            Ok(Some(file)) => file,
+            Ok(None) => return Ok(()),
+            Err(e) => return Err(KainError::type_error(message(e), u.span)),
+        };
+        if let Some(start) = self.loading.iter().position(|(loading, _)| *loading == file) {
+            let chain: Vec<&str> = self.loading[start..]
+                .iter()
+                .map(|(_, name)| name.as_str())
+                .chain(std::iter::once(path.as_str()))
+                .collect();
+            return Err(KainError::type_error(format!("Import cycle: {}", chain.join(" -> ")), u.span));
+        }
+        if let Some(index) = self.graph.modules.iter().position(|m| m.file == file) {
+            self.graph.by_path.insert(key, index);
+            return Ok(());
+        }
+
+        let program = parse_module(&file, &path, self.edition)?;
+        self.loading.push((file.clone(), path.clone()));
+        let mut result = Ok(());
+        uses(&program.items, &mut |inner| {
+            if result.is_ok() {
+                result = self.load(inner, Some(file.as_path()));
+            }
+        });
+        self.loading.pop();
+        result?;
+        self.graph.by_path.insert(key, self.graph.modules.len());
+        self.graph.modules.push(Module { path, file, program });
+        Ok(())
+    }
+}
+
+/// The text of a runtime error, which points at no source
+fn message(error: KainError) -> String {
+    match error {
+        KainError::Runtime { message } => message,
+        other => other.to_string(),
+    }
+}
+
+/// Copy the items of every module in `graph` into `program`, which must be
+/// the program the graph was made from. `use` items are removed, since what
+/// they import is now declared in the program itself.
+pub fn link(program: &mut Program, graph: &ModuleGraph, edition: Edition) -> KainResult<()> {
+    // Value items declared more than once anywhere are renamed in the modules declaring them
+    let mut declared: HashMap<&str, usize> = HashMap::new();
+    for items in std::iter::once(&program.items).chain(graph.modules.iter().map(|m| &m.program.items)) {
+        for name in items.iter().filter(|item| is_value(item)).filter_map(Item::name) {
+            *declared.entry(name).or_default() += 1;
+        }
+    }
+    let renamed: Vec<HashMap<String, String>> = graph
+        .modules
+        .iter()
+        .map(|module| {
+            module
+                .program
+                .items
+                .iter()
+                .filter(|item| is_value(item))
+                .filter_map(Item::name)
+                .filter(|name| declared[name] > 1)
+                .map(|name| (name.to_string(), mangle(&module.path, name)))
+                .collect()
+        })
+        .collect();
+    let linked_name = |index: usize, name: &str| renamed[index].get(name).cloned().unwrap_or_else(|| name.to_string());
+
+    check_types(program, graph)?;
+
+    let mut linked = Vec::new();
+    for (index, module) in graph.modules.iter().enumerate() {
+        let mut items: Vec<Item> = module
+            .program
+            .items
+            .iter()
+            .filter(|item| !matches!(item, Item::Use(_) | Item::Test(_) | Item::Mod(_)))
+            .cloned()
+            .collect();
+        for item in &mut items {
+            if let Some(name) = value_name(item) {
+                *name = linked_name(index, name);
+            }
+            // Only the program's own `pub` items are exported from what it compiles to
+            set_private(item);
+        }
+        let mut linker = Linker::new(&module.program.items, Some(module.file.as_path()), graph, edition, &linked_name)?;
+        for (name, linked) in &renamed[index] {
+            linker.names.insert(name.clone(), linked.clone());
+        }
+        for item in &mut items {
+            linker.visit_item_mut(item);
+        }
+        linker.finish()?;
+        linked.extend(items);
+    }
+
+    let mut linker = Linker::new(&program.items, None, graph, edition, &linked_name)?;
+    program.items.retain(|item| !matches!(item, Item::Use(_)));
+    for item in &mut program.items {
+        linker.visit_item_mut(item);
+    }
+    linker.finish()?;
+    linked.append(&mut program.items);
+    program.items = linked;
+    Ok(())
+}
+
+/// Whether `item` was linked in from an imported module rather than written
+/// in the program; only a module's items carry the id of its file
+pub fn is_linked(item: &Item) -> bool {
+    item.span().file.is_some()
+}
+
+/// Items uses refer to by name, which linking may rename
+fn is_value(item: &Item) -> bool {
+    match item {
+        Item::Function(_) | Item::Const(_) | Item::Static(_) => true,
+        Item::Cfg(c) => is_value(&c.item),
+        _ => false,
+    }
+}
+
+fn value_name(item: &mut Item) -> Option<&mut String> {
+    match item {
+        Item::Function(f) => Some(&mut f.name),
+        Item::Const(c) => Some(&mut c.name),
+        Item::Static(s) => Some(&mut s.name),
+        Item::Cfg(c) => value_name(&mut c.item),
+        _ => None,
+    }
+}
+
+fn set_private(item: &mut Item) {
+    let visibility = match item {
+        Item::Function(f) => &mut f.visibility,
+        Item::Component(c) => &mut c.visibility,
+        Item::Struct(s) => &mut s.visibility,
+        Item::Enum(e) => &mut e.visibility,
+        Item::Trait(t) => &mut t.visibility,
+        Item::TypeAlias(t) => &mut t.visibility,
+        Item::Effect(e) => &mut e.visibility,
+        Item::Const(c) => &mut c.visibility,
+        Item::Static(s) => &mut s.visibility,
+        Item::Cfg(c) => return set_private(&mut c.item),
+        _ => return,
+    };
+    *visibility = Visibility::Private;
+}
+
+/// `tests/modules/points` and `new` give `tests_modules_points__new`
+fn mangle(path: &str, name: &str) -> String {
+    let prefix: String = path.chars().map(|c| if c.is_ascii_alphanumeric() { c } else { '_' }).collect();
+    format!("{}__{}", prefix, name)
+}
+
+/// Report a type declared by two modules, or by a module and the program
+fn check_types(program: &Program, graph: &ModuleGraph) -> KainResult<()> {
+    let mut declared: HashMap<&str, &str> = HashMap::new();
+    let owners = std::iter::once(("the program", &program.items)).chain(graph.modules.iter().map(|m| (m.path.as_str(), &m.program.items)));
+    for (owner, items) in owners {
+        for item in items.iter().filter(|item| !is_value(item)) {
+            let Some(name) = item.name() else { continue };
+            // A module may declare one type several times behind `@cfg`
+            if let Some(first) = declared.insert(name, owner).filter(|first| *first != owner) {
+                return Err(KainError::type_error(
+                    format!("`{}` is declared by both {} and {}; types keep their names when modules are linked, so rename one", name, first, owner),
+                    item.span(),
+                ));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Rewrites the uses of imported items in one module, or in the program, to the names they are linked under
+struct Linker<'a> {
+    /// The module's file; `None` for the program
+    file: Option<&'a Path>,
+    /// What each name the module uses without binding it refers to
+    names: HashMap<String, String>,
+    /// The module each imported name comes from, and whether `use path: {name}` named it
+    imports: HashMap<String, (usize, bool)>,
+    /// Names two plain imports both export, which are an error only where used
+    ambiguous: HashMap<String, (usize, usize)>,
+    /// Modules imported with `use path as alias`, by alias
+    aliases: HashMap<String, usize>,
+    /// Parameters and bindings, which shadow imported items wherever they are bound;
+    /// the same approximation [`crate::visibility`] makes
+    locals: HashSet<String>,
+    linked_name: &'a dyn Fn(usize, &str) -> String,
+    graph: &'a ModuleGraph,
+    /// The first use of an ambiguous or unimported name
+    error: Option<KainError>,
+}
+
+impl<'a> Linker<'a> {
+    fn new(
+        items: &[Item],
+        file: Option<&'a Path>,
+        graph: &'a ModuleGraph,
+        edition: Edition,
+        linked_name: &'a dyn Fn(usize, &str) -> String,
+    ) -> KainResult<Self> {
+        let mut locals = Locals::default();
+        for item in items {
+            locals.visit_item(item);
+        }
+        let mut linker = Linker {
+            file,
+            names: HashMap::new(),
+            imports: HashMap::new(),
+            ambiguous: HashMap::new(),
+            aliases: HashMap::new(),
+            locals: locals.0,
+            linked_name,
+            graph,
+            error: None,
+        };
+        // The module's own items come first and shadow anything imported
+        for name in items.iter().filter(|item| is_value(item)).filter_map(Item::name) {
+            linker.names.insert(name.to_string(), name.to_string());
+        }
+        let mut result = Ok(());
+        uses(items, &mut |u| {
+            if result.is_ok() {
+                result = linker.import(u, edition);
+            }
+        });
+        result.map(|()| linker)
+    }
+
+    /// Bind what `u` imports. Names from two plain imports become ambiguous;
+    /// an explicit import replaces a plain one, and two explicit imports of
+    /// one name are an error.
+    fn import(&mut self, u: &Use, edition: Edition) -> KainResult<()> {
+        let Some(index) = self.graph.index(self.file, u) else { return Ok(()) };
+        if let Some(alias) = &u.alias {
+            self.aliases.insert(alias.clone(), index);
+            return Ok(());
+        }
+        let graph = self.graph;
+        let module = &graph.modules[index];
+        let items = &module.program.items;
+        if let Some(names) = &u.items {
+            if let Some(name) = names.iter().find(|name| !items.iter().any(|item| item.name() == Some(name.as_str()))) {
+                return Err(KainError::type_error(format!("Module {} has no item `{}`", module.path, name), u.span));
+            }
+        }
+        let explicit = u.items.is_some();
+        for item in items.iter().filter(|item| is_value(item)) {
+            let Some(name) = item.name() else { continue };
+            let imported = match &u.items {
+                Some(names) => names.iter().any(|n| n == name),
+                None => !edition.enforce_visibility() || item.visibility() != Some(Visibility::Private),
+            };
+            if !imported || (self.names.contains_key(name) && !self.imports.contains_key(name) && !self.ambiguous.contains_key(name)) {
+                continue;
+            }
+            match self.imports.get(name).copied().or_else(|| self.ambiguous.get(name).map(|&(first, _)| (first, false))) {
+                Some((from, _)) if from == index => continue,
+                Some((from, true)) if explicit => {
+                    return Err(KainError::type_error(
+                        format!(
+                            "`{}` is imported from both {} and {}; import one of them with `as`",
+                            name, graph.modules[from].path, module.path
+                        ),
+                        u.span,
+                    ));
+                }
+                Some((_, true)) => continue,
+                Some((from, false)) if !explicit => {
+                    self.imports.remove(name);
+                    self.names.remove(name);
+                    self.ambiguous.insert(name.to_string(), (from, index));
+                    continue;
+                }
+                _ => {}
+            }
+            self.ambiguous.remove(name);
+            self.imports.insert(name.to_string(), (index, explicit));
+            self.names.insert(name.to_string(), (self.linked_name)(index, name));
+        }
+        Ok(())
+    }
+
+    /// Why `name`, used without being bound, names nothing here: it is
+    /// ambiguous, or declared by a linked module this one doesn't import
+    fn unresolved(&self, name: &str, span: Span) -> Option<KainError> {
+        if let Some(&(first, second)) = self.ambiguous.get(name) {
+            let (first, second) = (&self.graph.modules[first].path, &self.graph.modules[second].path);
+            return Some(KainError::type_error(
+                format!(
+                    "`{}` is ambiguous: both {} and {} export it; pick one with `use {}: {{{}}}` or import a module with `as`",
+                    name, first, second, first, name
+                ),
+                span,
+            ));
+        }
+        let module = self.graph.modules.iter().enumerate().find_map(|(index, module)| {
+            let mut declared = module.program.items.iter().filter(|item| is_value(item)).filter_map(Item::name);
+            declared.any(|declared| (self.linked_name)(index, declared) == name).then_some(module)
+        })?;
+        Some(KainError::type_error(
+            format!("`{}` is declared by module {} but not imported; import it with `use {}: {{{}}}`", name, module.path, module.path, name),
+            span,
+        ))
+    }
+
+    fn finish(self) -> KainResult<()> {
+        self.error.map_or(Ok(()), Err)
+    }
+
+    /// The linked name of `alias.name`, when `alias` names an imported module
+    fn member(&self, object: &Expr, name: &str) -> Option<String> {
+        let Expr::Ident(alias, _) = object else { return None };
+        if self.locals.contains(alias) {
+            return None;
+        }
+        let index = *self.aliases.get(alias)?;
+        let declared = self.graph.modules[index].program.items.iter().any(|item| is_value(item) && item.name() == Some(name));
+        declared.then(|| (self.linked_name)(index, name))
+    }
+}
+
+impl VisitorMut for Linker<'_> {
+    fn visit_expr_mut(&mut self, expr: &mut Expr) {
+        let linked = match expr {
+            Expr::Ident(name, span) if !self.locals.contains(name.as_str()) => {
+                match self.names.get(name.as_str()) {
+                    Some(linked) => *name = linked.clone(),
+                    None if self.error.is_none() => self.error = self.unresolved(name, *span),
+                    None => {}
+                }
+                None
+            }
+            Expr::MethodCall { receiver, method, args, span } => self.member(receiver, method).map(|linked| Expr::Call {
+                callee: Box::new(Expr::Ident(linked, *span)),
+                args: std::mem::take(args),
+                span: *span,
+            }),
+            Expr::Field { object, field, span } => self.member(object, field).map(|linked| Expr::Ident(linked, *span)),
+            _ => None,
+        };
+        if let Some(linked) = linked {
+            *expr = linked;
+        }
+        walk_expr_mut(self, expr);
+    }
+}
+
+/// Names bound as parameters or by patterns
+#[derive(Default)]
+struct Locals(HashSet<String>);
+
+impl Visitor for Locals {
+    fn visit_param(&mut self, param: &Param) {
+        self.0.insert(param.name.clone());
+        walk_param(self, param);
+    }
+
+    fn visit_pattern(&mut self, pattern: &Pattern) {
+        if let Pattern::Binding { name, .. } = pattern {
+            self.0.insert(name.clone());
+        }
+        walk_pattern(self, pattern);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{compile, compile_with, edition, eval_snippet, CompileOptions, CompileTarget, KainError};
+
+    #[test]
+    fn test_modules_load_once_and_report_cycles() {
+        // shapes imports units too, as `use units` next to it; the second import is a no-op
+        let source = "use tests/modules/shapes\nuse tests/modules/units\n\nfn main():\n    println(area(3, 4), unit())\n";
+        let result = eval_snippet(source, &CompileOptions::default());
+        assert!(result.diagnostics.is_empty(), "{:?}", result.diagnostics);
+        assert_eq!(result.stdout.trim(), "12 1");
+
+        let cycle = eval_snippet("use tests/modules/cycle_a\n\nfn main():\n    return\n", &CompileOptions::default());
+        let message = cycle.diagnostics[0].to_string();
+        assert!(message.contains("Import cycle: tests/modules/cycle_a -> tests/modules/cycle_b -> tests/modules/cycle_a"), "{}", message);
+
+        // From 0.2 a module's private functions are only callable from inside it
+        let private = "use tests/modules/shapes\n\nfn main():\n    println(area(1, 2))\n    println(double(2))\n";
+        let old = eval_snippet(private, &CompileOptions::default());
+        assert_eq!(old.stdout.lines().map(str::trim).collect::<Vec<_>>(), ["2", "4"]);
+        let options = CompileOptions { edition: edition::Edition::V0_2, ..Default::default() };
+        let result = eval_snippet(private, &options);
+        assert!(result.stdout.is_empty(), "{}", result.stdout);
+        assert!(result.diagnostics[0].to_string().contains("function `double` is private to module tests/modules/shapes, consider making it pub"));
+    }
+
+    #[test]
+    fn test_selective_and_aliased_imports() {
+        let source = "use tests/modules/points: {new}\nuse tests/modules/circles as c\n\nfn main():\n    println(new(1, 2), c.new(3), c.area(2))\n";
+        let result = eval_snippet(source, &CompileOptions::default());
+        assert!(result.diagnostics.is_empty(), "{:?}", result.diagnostics);
+        assert_eq!(result.stdout.trim(), "(1, 2) 3 12");
+
+        // Both modules export `new`: only using it is an error, reported before anything runs
+        let both = "use tests/modules/points\nuse tests/modules/circles\n\nfn main():\n    println(area(1))\n    println(new(1, 2))\n";
+        let result = eval_snippet(both, &CompileOptions::default());
+        assert!(result.stdout.is_empty(), "{}", result.stdout);
+        assert!(compile(both, CompileTarget::Js).unwrap_err().to_string().contains("`new` is ambiguous"));
+        assert!(eval_snippet("use tests/modules/points\nuse tests/modules/circles\n\nfn main():\n    println(area(1))\n", &CompileOptions::default()).diagnostics.is_empty());
+        assert!(result.diagnostics[0].to_string().contains("`new` is ambiguous: both tests/modules/points and tests/modules/circles export it"));
+
+        let explicit = "use tests/modules/points: {new}\nuse tests/modules/circles: {new}\n\nfn main():\n    return\n";
+        let result = eval_snippet(explicit, &CompileOptions::default());
+        assert!(result.diagnostics[0].to_string().contains("`new` is imported from both tests/modules/points and tests/modules/circles"));
+
+        let missing = eval_snippet("use tests/modules/points: {nope}\n\nfn main():\n    return\n", &CompileOptions::default());
+        assert!(missing.diagnostics[0].to_string().contains("Module tests/modules/points has no item `nope`"));
+        let private = eval_snippet("use tests/modules/circles as c\n\nfn main():\n    println(c.square(2))\n", &CompileOptions { edition: edition::Edition::V0_2, ..Default::default() });
+        assert!(private.diagnostics[0].to_string().contains("function `square` is private to module tests/modules/circles"));
+    }
+
+    #[test]
+    fn test_imported_modules_are_linked_into_compiled_targets() {
+        // points and circles both declare `new`, circles and shapes `area`, so those are renamed after their modules
+        let source = "use tests/modules/points: {new}\nuse tests/modules/circles as c\nuse tests/modules/shapes\n\nfn main():\n    println(new(1, 2), c.new(3), c.area(2), area(3, 4))\n";
+        let js = String::from_utf8(compile(source, CompileTarget::Js).unwrap()).unwrap();
+        for call in ["tests_modules_points__new(1", "tests_modules_circles__new(3)", "tests_modules_circles__area(2)", "tests_modules_shapes__area(3"] {
+            assert!(js.contains(call), "{} missing from\n{}", call, js);
+        }
+        assert!(js.contains("function square(x)") && js.contains("function double(x)") && js.contains("function unit()"), "{}", js);
+        assert!(!js.contains("export function tests_modules"), "{}", js);
+        // The interpreter runs the same linked program
+        assert_eq!(eval_snippet(source, &CompileOptions::default()).stdout.trim(), "(1, 2) 3 12 12");
+
+        // Linked items are the program's own, but only its `pub` items are exported
+        let source = "use tests/modules/shapes\nuse tests/modules/units\n\npub fn main() -> Int:\n    return area(3, 4) + unit()\n";
+        let output = compile_with(source, CompileTarget::Wasm, &CompileOptions::default()).unwrap();
+        let exports: Vec<_> = output.exports.iter().map(|e| e.symbol.as_str()).collect();
+        assert_eq!(exports, ["main"]);
+
+        let cycle = compile("use tests/modules/cycle_a\n\nfn main():\n    return\n", CompileTarget::Wasm).unwrap_err();
+        assert!(cycle.to_string().contains("Import cycle: tests/modules/cycle_a -> tests/modules/cycle_b -> tests/modules/cycle_a"), "{}", cycle);
+        let missing = compile("fn main():\n    return\n\nuse tests/modules/nowhere\n", CompileTarget::Js).unwrap_err();
+        assert!(missing.to_string().contains("Module not found: tests/modules/nowhere"), "{}", missing);
+        assert!(matches!(missing, KainError::Type { span, .. } if span.start == 23), "{:?}", missing);
+
+        // Items of a module linked in only for another module aren't the program's to use
+        let unimported = eval_snippet("use tests/modules/shapes\n\nfn main():\n    println(unit())\n", &CompileOptions::default());
+        assert!(unimported.diagnostics[0].to_string().contains("`unit` is declared by module units but not imported"), "{:?}", unimported.diagnostics);
+    }
+}
@@ -9,7 +9,12 @@ pub struct MonomorphizedProgram {
 }
 
 pub fn monomorphize(program: &TypedProgram) -> KainResult<MonomorphizedProgram> {
+    monomorphize_with_options(program, &crate::CompileOptions::default())
+}
+
+pub fn monomorphize_with_options(program: &TypedProgram, options: &crate::CompileOptions) -> KainResult<MonomorphizedProgram> {
     let mut ctx = MonoContext::new();
+    ctx.deterministic = options.deterministic;
     
 
     
@@ -22,6 +27,9 @@ pub fn monomorphize(program: &TypedProgram) -> KainResult<MonomorphizedProgram>
                     ctx.generic_functions.insert(func.ast.name.clone(), func.clone());
 
                 } else {
+                    if let ResolvedType::Function { ret, .. } = &func.resolved_type {
+                        ctx.returns.insert(func.ast.name.clone(), (**ret).clone());
+                    }
                     ctx.concrete_items.push(item.clone());
 
                 }
@@ -54,6 +62,10 @@ pub fn monomorphize(program: &TypedProgram) -> KainResult<MonomorphizedProgram>
                 
                 for method in &imp.ast.methods {
                     let mangled_name = format!("{}_{}", type_name, method.name);
+                    let symbol = match crate::symbols::declared_name(method)? {
+                        Some(name) => name,
+                        None => crate::symbols::mangle(&[&type_name, &method.name], &[]),
+                    };
                     
                     let mut standalone_fn = method.clone();
                     standalone_fn.name = mangled_name.clone();
@@ -71,6 +83,7 @@ pub fn monomorphize(program: &TypedProgram) -> KainResult<MonomorphizedProgram>
                         .map(|t| resolve_ast_type(t).unwrap_or(ResolvedType::Unknown))
                         .unwrap_or(ResolvedType::Unit);
                     
+                    ctx.returns.insert(mangled_name.clone(), ret.clone());
                     let method_ty = ResolvedType::Function {
                         params,
                         ret: Box::new(ret),
@@ -81,6 +94,7 @@ pub fn monomorphize(program: &TypedProgram) -> KainResult<MonomorphizedProgram>
                         ast: standalone_fn,
                         resolved_type: method_ty,
                         effects: crate::effects::EffectSet::new(),
+                        symbol,
                     };
                     
                     ctx.methods.entry(type_name.clone()).or_default().insert(method.name.clone(), mangled_name.clone());
@@ -131,6 +145,10 @@ struct MonoContext {
     structs: HashMap<String, HashMap<String, ResolvedType>>,
     /// (TraitName, TypeName) -> Implemented
     trait_impls: HashSet<(String, String)>,
+    /// Return type of each concrete function, methods and instances included
+    returns: HashMap<String, ResolvedType>,
+    /// Emit generated struct fields in sorted order (see `CompileOptions::deterministic`)
+    deterministic: bool,
 }
 
 impl MonoContext {
@@ -142,18 +160,20 @@ impl MonoContext {
             methods: HashMap::new(),
             structs: HashMap::new(),
             trait_impls: HashSet::new(),
+            returns: HashMap::new(),
+            deterministic: false,
         }
     }
     
-    fn instantiate(&mut self, name: &str, type_args: &[ResolvedType]) -> KainResult<String> {
-        let mangled_name = format!("{}_{}", name, mangle_types(type_args));
+    fn instantiate(&mut self, name: &str, type_args: &[ResolvedType], span: crate::span::Span) -> KainResult<String> {
+        let mangled_name = crate::symbols::mangle(&[name], type_args);
         
         if self.instantiated.contains_key(&mangled_name) {
             return Ok(mangled_name);
         }
         
         let generic_func = self.generic_functions.get(name)
-            .ok_or_else(|| KainError::type_error(format!("Generic function {} not found", name), crate::span::Span::new(0,0)))?
+            .ok_or_else(|| KainError::type_error(format!("Generic function {} not found", name), span))?
             .clone();
             
         if generic_func.ast.generics.len() != type_args.len() {
@@ -167,6 +187,7 @@ impl MonoContext {
         
         let mut new_func = generic_func.clone();
         new_func.ast.name = mangled_name.clone();
+        new_func.symbol = mangled_name.clone();
         new_func.ast.generics.clear();
         
         if let ResolvedType::Function { params, ret, .. } = &mut new_func.resolved_type {
@@ -174,6 +195,7 @@ impl MonoContext {
                 *p = substitute_type(p, &mapping);
             }
             *ret = Box::new(substitute_type(&ret, &mapping));
+            self.returns.insert(mangled_name.clone(), (**ret).clone());
         }
         
         self.instantiated.insert(mangled_name.clone(), mangled_name.clone());
@@ -194,15 +216,12 @@ fn type_to_string(ty: &ResolvedType) -> String {
         ResolvedType::Unit => "Unit".to_string(),
         ResolvedType::Struct(n, _) => n.clone(),
         ResolvedType::Enum(n, _) => n.clone(),
+        ResolvedType::Option(_) => "Option".to_string(),
         ResolvedType::Tuple(ts) => format!("({})", ts.iter().map(type_to_string).collect::<Vec<_>>().join(", ")),
         _ => "Any".to_string(),
     }
 }
 
-fn mangle_types(types: &[ResolvedType]) -> String {
-    types.iter().map(type_to_string).collect::<Vec<_>>().join("_")
-}
-
 fn resolve_ast_type(ty: &Type) -> KainResult<ResolvedType> {
     crate::types::resolve_type(ty)
 }
@@ -240,6 +259,9 @@ fn unify(
         (ResolvedType::Array(p_inner, _), ResolvedType::Array(a_inner, _)) => {
             unify(p_inner, a_inner, bindings);
         }
+        (ResolvedType::Option(p_inner), ResolvedType::Option(a_inner)) => {
+            unify(p_inner, a_inner, bindings);
+        }
         
         // Recursively unify tuple types
         (ResolvedType::Tuple(p_elems), ResolvedType::Tuple(a_elems)) => {
@@ -258,6 +280,7 @@ fn infer_type_args(
     ctx: &MonoContext,
     generic_func: &TypedFunction,
     arg_types: &[ResolvedType],
+    span: crate::span::Span,
 ) -> KainResult<Vec<ResolvedType>> {
     let mut bindings: HashMap<String, ResolvedType> = HashMap::new();
     
@@ -279,14 +302,28 @@ fn infer_type_args(
     // Extract the inferred types in the order of the generic parameters
     let mut inferred = Vec::new();
     for generic in &generic_func.ast.generics {
+        // Bounds are checked nowhere else, so one on a type we could not infer is an error
+        let unknown = matches!(bindings.get(&generic.name), None | Some(ResolvedType::Unknown));
+        if let Some(bound) = generic.bounds.first().filter(|_| unknown) {
+            return Err(KainError::type_error(
+                format!(
+                    "can't tell which type `{}` is in this call to `{}`, so it can't be checked to implement `{}`; give the argument a type, as in `let x: Type = ...`",
+                    generic.name, generic_func.ast.name, bound.trait_name
+                ),
+                span,
+            ));
+        }
         if let Some(ty) = bindings.get(&generic.name) {
              // Check Bounds!
              for bound in &generic.bounds {
                  let type_name = type_to_string(ty);
                  if !ctx.trait_impls.contains(&(bound.trait_name.clone(), type_name.clone())) {
                      return Err(KainError::type_error(
-                         format!("Type '{}' does not satisfy bound '{}'", type_name, bound.trait_name), 
-                         generic.span
+                         format!(
+                             "`{}` does not implement `{}`, which `{}` requires of `{}`",
+                             type_name, bound.trait_name, generic_func.ast.name, generic.name
+                         ),
+                         span
                      ));
                  }
              }
@@ -311,6 +348,7 @@ fn substitute_type(ty: &ResolvedType, mapping: &HashMap<String, ResolvedType>) -
             }
         }
         ResolvedType::Array(inner, n) => ResolvedType::Array(Box::new(substitute_type(inner, mapping)), *n),
+        ResolvedType::Option(inner) => ResolvedType::Option(Box::new(substitute_type(inner, mapping))),
         _ => ty.clone() 
     }
 }
@@ -348,19 +386,30 @@ fn substitute_stmt(stmt: &mut Stmt, mapping: &HashMap<String, ResolvedType>) {
         }
         Stmt::Expr(e) => substitute_expr(e, mapping),
         Stmt::Return(Some(e), _) => substitute_expr(e, mapping),
-        Stmt::For { iter, body, .. } => {
+        Stmt::For { iter, body, else_branch, .. } => {
             substitute_expr(iter, mapping);
             substitute_block(body, mapping);
+            if let Some(b) = else_branch {
+                substitute_block(b, mapping);
+            }
         }
-        Stmt::While { condition, body, .. } => {
+        Stmt::While { condition, body, else_branch, .. } => {
             substitute_expr(condition, mapping);
             substitute_block(body, mapping);
+            if let Some(b) = else_branch {
+                substitute_block(b, mapping);
+            }
         }
+        Stmt::Region { body, .. } => substitute_block(body, mapping),
         _ => {}
     }
 }
 
 fn substitute_expr(expr: &mut Expr, mapping: &HashMap<String, ResolvedType>) {
+    crate::stack::grow(|| substitute_expr_inner(expr, mapping))
+}
+
+fn substitute_expr_inner(expr: &mut Expr, mapping: &HashMap<String, ResolvedType>) {
     match expr {
         Expr::Cast { value, target, .. } => {
             substitute_expr(value, mapping);
@@ -390,7 +439,10 @@ fn substitute_expr(expr: &mut Expr, mapping: &HashMap<String, ResolvedType>) {
             substitute_expr(object, mapping);
             substitute_expr(index, mapping);
         }
-        Expr::Struct { fields, .. } => {
+        Expr::Struct { fields, base, .. } => {
+             if let Some(base) = base {
+                 substitute_expr(base, mapping);
+             }
              for (_, v) in fields {
                  substitute_expr(v, mapping);
              }
@@ -466,7 +518,7 @@ fn substitute_type_ast(ty: &mut Type, mapping: &HashMap<String, ResolvedType>) {
         Type::Array(inner, _, _) => {
              substitute_type_ast(inner, mapping);
          }
-         Type::Slice(inner, _) => {
+         Type::Slice(inner, _) | Type::Option(inner, _) => {
              substitute_type_ast(inner, mapping);
          }
          _ => {}
@@ -481,6 +533,7 @@ fn resolved_to_ast_type(res: &ResolvedType, span: crate::span::Span) -> Type {
         ResolvedType::String => Type::Named { name: "String".into(), generics: vec![], span },
         ResolvedType::Unit => Type::Unit(span),
         ResolvedType::Struct(n, _) => Type::Named { name: n.clone(), generics: vec![], span },
+        ResolvedType::Option(inner) => Type::Option(Box::new(resolved_to_ast_type(inner, span)), span),
         _ => Type::Named { name: "Any".into(), generics: vec![], span }, // Fallback
     }
 }
@@ -530,6 +583,12 @@ fn lower_async_fn(ctx: &mut MonoContext, func: &TypedFunction) -> KainResult<Typ
     }
     
     let _struct_ty = ResolvedType::Struct(state_machine_name.clone(), fields.clone());
+
+    // Field order decides the struct layout, so don't leave it to hash order
+    let mut field_list: Vec<(&String, &ResolvedType)> = fields.iter().collect();
+    if ctx.deterministic {
+        field_list.sort_by(|a, b| a.0.cmp(b.0));
+    }
     
     // Register Struct
     ctx.structs.insert(state_machine_name.clone(), fields.clone());
@@ -540,7 +599,7 @@ fn lower_async_fn(ctx: &mut MonoContext, func: &TypedFunction) -> KainResult<Typ
         ast: Struct {
             name: state_machine_name.clone(),
             generics: vec![],
-            fields: fields.iter().map(|(n, t)| Field {
+            fields: field_list.into_iter().map(|(n, t)| Field {
                 name: n.clone(),
                 ty: resolved_to_ast_type(t, func.ast.span),
                 visibility: Visibility::Public,
@@ -549,6 +608,8 @@ fn lower_async_fn(ctx: &mut MonoContext, func: &TypedFunction) -> KainResult<Typ
                 span: func.ast.span
             }).collect(),
             visibility: Visibility::Public,
+            attributes: vec![],
+            doc: None,
             span: func.ast.span,
         },
         field_types: fields.clone(),
@@ -566,6 +627,7 @@ fn lower_async_fn(ctx: &mut MonoContext, func: &TypedFunction) -> KainResult<Typ
         ty: resolved_to_ast_type(&self_type, func.ast.span),
         mutable: true,
         default: None,
+        variadic: false,
         span: func.ast.span,
     };
     
@@ -632,6 +694,7 @@ fn lower_async_fn(ctx: &mut MonoContext, func: &TypedFunction) -> KainResult<Typ
             args: vec![CallArg {
                 name: None,
                 value: Expr::String("polled after completion".to_string(), func.ast.span),
+                spread: false,
                 span: func.ast.span,
             }],
             span: func.ast.span,
@@ -665,6 +728,7 @@ fn lower_async_fn(ctx: &mut MonoContext, func: &TypedFunction) -> KainResult<Typ
             body: poll_body,
             visibility: Visibility::Public,
             attributes: vec![],
+            doc: None,
             span: func.ast.span,
         },
         resolved_type: ResolvedType::Function {
@@ -673,6 +737,7 @@ fn lower_async_fn(ctx: &mut MonoContext, func: &TypedFunction) -> KainResult<Typ
             effects: crate::effects::EffectSet::new(),
         },
         effects: crate::effects::EffectSet::new(),
+        symbol: poll_name.clone(),
     });
     ctx.concrete_items.push(poll_fn);
     
@@ -704,6 +769,7 @@ fn lower_async_fn(ctx: &mut MonoContext, func: &TypedFunction) -> KainResult<Typ
     let body_expr = Expr::Struct {
         name: state_machine_name.clone(),
         fields: init_fields,
+        base: None,
         span: func.ast.span,
     };
     
@@ -740,14 +806,21 @@ fn rewrite_stmt(stmt: &mut Stmt, fields: &HashMap<String, ResolvedType>) {
         Stmt::Expr(e) => rewrite_expr(e, fields),
         Stmt::Return(Some(e), _) => rewrite_expr(e, fields),
         Stmt::Let { value: Some(e), .. } => rewrite_expr(e, fields),
-        Stmt::For { iter, body, .. } => {
+        Stmt::For { iter, body, else_branch, .. } => {
             rewrite_expr(iter, fields);
             rewrite_access_to_self(body, fields);
+            if let Some(b) = else_branch {
+                rewrite_access_to_self(b, fields);
+            }
         }
-        Stmt::While { condition, body, .. } => {
+        Stmt::While { condition, body, else_branch, .. } => {
             rewrite_expr(condition, fields);
             rewrite_access_to_self(body, fields);
+            if let Some(b) = else_branch {
+                rewrite_access_to_self(b, fields);
+            }
         }
+        Stmt::Region { body, .. } => rewrite_access_to_self(body, fields),
         _ => {}
     }
     
@@ -772,6 +845,10 @@ fn rewrite_stmt(stmt: &mut Stmt, fields: &HashMap<String, ResolvedType>) {
 }
 
 fn rewrite_expr(expr: &mut Expr, fields: &HashMap<String, ResolvedType>) {
+    crate::stack::grow(|| rewrite_expr_inner(expr, fields))
+}
+
+fn rewrite_expr_inner(expr: &mut Expr, fields: &HashMap<String, ResolvedType>) {
     match expr {
         Expr::Ident(name, span) => {
             if fields.contains_key(name) {
@@ -873,10 +950,13 @@ fn collect_awaits_from_stmt(stmt: &Stmt, points: &mut Vec<AwaitPoint>) {
         }
         // Note: In KAIN, if is an expression, not a statement. If used in Stmt::Expr, 
         // collect_awaits_from_expr will handle it.
-        Stmt::While { body, .. } | Stmt::Loop { body, .. } => {
+        Stmt::While { body, else_branch, .. } | Stmt::For { body, else_branch, .. } => {
             collect_awaits_from_block(body, points);
+            if let Some(b) = else_branch {
+                collect_awaits_from_block(b, points);
+            }
         }
-        Stmt::For { body, .. } => {
+        Stmt::Loop { body, .. } | Stmt::Region { body, .. } => {
             collect_awaits_from_block(body, points);
         }
         _ => {}
@@ -884,6 +964,10 @@ fn collect_awaits_from_stmt(stmt: &Stmt, points: &mut Vec<AwaitPoint>) {
 }
 
 fn collect_awaits_from_expr(expr: &Expr, points: &mut Vec<AwaitPoint>) {
+    crate::stack::grow(|| collect_awaits_from_expr_inner(expr, points))
+}
+
+fn collect_awaits_from_expr_inner(expr: &Expr, points: &mut Vec<AwaitPoint>) {
     match expr {
         Expr::Await(inner, _) => {
             points.push(AwaitPoint {
@@ -1192,12 +1276,12 @@ fn wrap_stmt_returns(stmt: &mut Stmt, span: crate::span::Span) {
         }
         // Note: In KAIN, if is an expression. Expr::If would be in Stmt::Expr, 
         // but we handle expressions separately if needed.
-        Stmt::While { body, .. } | Stmt::Loop { body, .. } => {
-            for s in &mut body.stmts {
+        Stmt::While { body, else_branch, .. } | Stmt::For { body, else_branch, .. } => {
+            for s in body.stmts.iter_mut().chain(else_branch.iter_mut().flat_map(|b| b.stmts.iter_mut())) {
                 wrap_stmt_returns(s, span);
             }
         }
-        Stmt::For { body, .. } => {
+        Stmt::Loop { body, .. } | Stmt::Region { body, .. } => {
             for s in &mut body.stmts {
                 wrap_stmt_returns(s, span);
             }
@@ -1235,17 +1319,21 @@ fn scan_stmt(ctx: &mut MonoContext, env: &mut MonoTypeEnv, stmt: &mut Stmt) -> K
     match stmt {
         Stmt::Expr(e) => { scan_expr(ctx, env, e)?; }
         Stmt::Return(Some(e), _) => { scan_expr(ctx, env, e)?; }
-        Stmt::Let { pattern, value, .. } => {
+        Stmt::Let { pattern, ty: declared, value, .. } => {
             // Scan the value expression (may contain generic calls like identity(42))
             if let Some(val_expr) = value {
-                let ty = scan_expr(ctx, env, val_expr)?;
+                // The declared type stands in for one the value's can't be told from
+                let ty = match (scan_expr(ctx, env, val_expr)?, declared) {
+                    (ResolvedType::Unknown, Some(declared)) => resolve_ast_type(declared).unwrap_or(ResolvedType::Unknown),
+                    (ty, _) => ty,
+                };
                 // Also define the binding in the environment for type inference
                 if let Pattern::Binding { name, .. } = pattern {
                     env.define(name.clone(), ty);
                 }
             }
         }
-        Stmt::For { binding, iter, body, .. } => {
+        Stmt::For { binding, iter, body, else_branch, .. } => {
             let iter_ty = scan_expr(ctx, env, iter)?;
             let elem_ty = match iter_ty {
                 ResolvedType::Array(inner, _) => *inner,
@@ -1258,24 +1346,39 @@ fn scan_stmt(ctx: &mut MonoContext, env: &mut MonoTypeEnv, stmt: &mut Stmt) -> K
             }
             scan_block(ctx, env, body)?;
             env.pop();
+            if let Some(b) = else_branch {
+                scan_block(ctx, env, b)?;
+            }
         }
-        Stmt::While { condition, body, .. } => {
+        Stmt::While { condition, body, else_branch, .. } => {
             scan_expr(ctx, env, condition)?;
             scan_block(ctx, env, body)?;
+            if let Some(b) = else_branch {
+                scan_block(ctx, env, b)?;
+            }
         }
+        Stmt::Region { body, .. } => scan_block(ctx, env, body)?,
         _ => {}
     }
     Ok(())
 }
 
 fn scan_expr(ctx: &mut MonoContext, env: &mut MonoTypeEnv, expr: &mut Expr) -> KainResult<ResolvedType> {
+    crate::stack::grow(|| scan_expr_inner(ctx, env, expr))
+}
+
+fn scan_expr_inner(ctx: &mut MonoContext, env: &mut MonoTypeEnv, expr: &mut Expr) -> KainResult<ResolvedType> {
     match expr {
         Expr::Int(_, _) => Ok(ResolvedType::Int(IntSize::I64)),
         Expr::Float(_, _) => Ok(ResolvedType::Float(FloatSize::F64)),
         Expr::String(_, _) => Ok(ResolvedType::String),
         Expr::Bool(_, _) => Ok(ResolvedType::Bool),
+        Expr::Char(_, _) => Ok(ResolvedType::Char),
         Expr::Ident(name, _) => Ok(env.get(name)),
-        Expr::Struct { name, fields, .. } => {
+        Expr::Struct { name, fields, base, .. } => {
+            if let Some(base) = base {
+                scan_expr(ctx, env, base)?;
+            }
             for (_, val) in fields {
                 scan_expr(ctx, env, val)?;
             }
@@ -1303,10 +1406,12 @@ fn scan_expr(ctx: &mut MonoContext, env: &mut MonoTypeEnv, expr: &mut Expr) -> K
             let receiver_ty = scan_expr(ctx, env, receiver)?;
             
             let type_name = match &receiver_ty {
-                ResolvedType::Struct(name, _) => name.clone(),
+                ResolvedType::Struct(name, _) | ResolvedType::Enum(name, _) => name.clone(),
                 ResolvedType::Int(_) => "Int".to_string(),
                 ResolvedType::Float(_) => "Float".to_string(),
                 ResolvedType::String => "String".to_string(),
+                ResolvedType::Bool => "Bool".to_string(),
+                ResolvedType::Char => "Char".to_string(),
                 _ => {
                     if let ResolvedType::Unknown = receiver_ty {
                          // Don't error hard yet, as we might be in partial state
@@ -1327,26 +1432,25 @@ fn scan_expr(ctx: &mut MonoContext, env: &mut MonoTypeEnv, expr: &mut Expr) -> K
             
             if let Some(target_name) = mangled_target {
                  let mut new_args = args.clone();
-                 new_args.insert(0, CallArg { name: None, value: *receiver.clone(), span: receiver.span() });
+                 new_args.insert(0, CallArg { name: None, value: *receiver.clone(), spread: false, span: receiver.span() });
                  
                  for arg in &mut new_args {
                      scan_expr(ctx, env, &mut arg.value)?;
                  }
 
+                 let ret = ctx.returns.get(&target_name).cloned().unwrap_or(ResolvedType::Unknown);
                  *expr = Expr::Call {
                      callee: Box::new(Expr::Ident(target_name, *span)), // No ctx borrow here
                      args: new_args,
                      span: *span
                  };
                  
-                 // Ideally lookup return type of function
-                 // For now Unknown is safe for logic
-                 return Ok(ResolvedType::Unknown);
+                 return Ok(ret);
             }
 
             Ok(ResolvedType::Unknown)
         }
-        Expr::Call { callee, args, .. } => {
+        Expr::Call { callee, args, span } => {
             if let Expr::Ident(name, _) = callee.as_ref() {
                 if let Some(generic_func) = ctx.generic_functions.get(name).cloned() {
                     // First, scan all arguments to get their types
@@ -1356,20 +1460,24 @@ fn scan_expr(ctx: &mut MonoContext, env: &mut MonoTypeEnv, expr: &mut Expr) -> K
                     }
                     
                     // Infer type arguments through unification
-                    let inferred_type_args = infer_type_args(ctx, &generic_func, &arg_types)?;
+                    let inferred_type_args = infer_type_args(ctx, &generic_func, &arg_types, *span)?;
                     
-                    let new_name = ctx.instantiate(name, &inferred_type_args)?;
+                    let new_name = ctx.instantiate(name, &inferred_type_args, callee.span())?;
+                    let ret = ctx.returns.get(&new_name).cloned().unwrap_or(ResolvedType::Unknown);
                     *callee = Box::new(Expr::Ident(new_name, callee.span()));
-                    return Ok(ResolvedType::Unknown); 
+                    return Ok(ret); 
                 }
-                
-                // If it's a standard function, we might want to lookup return type
-                // But for now, just scan args
             }
              for arg in args {
                 scan_expr(ctx, env, &mut arg.value)?;
             }
-            Ok(ResolvedType::Unknown)
+            // The program's own functions return what they declare
+            match callee.as_ref() {
+                Expr::Ident(name, _) if env.get(name) == ResolvedType::Unknown => {
+                    Ok(ctx.returns.get(name).cloned().unwrap_or(ResolvedType::Unknown))
+                }
+                _ => Ok(ResolvedType::Unknown),
+            }
         }
         Expr::Binary { left, right, .. } => {
             let t = scan_expr(ctx, env, left)?;
@@ -1410,9 +1518,13 @@ fn collect_locals_recursive(block: &Block, locals: &mut HashMap<String, Resolved
     for stmt in &block.stmts {
         match stmt {
             Stmt::Let { pattern, .. } => collect_from_pattern(pattern, locals),
-            Stmt::For { body, .. } => collect_locals_recursive(body, locals),
-            Stmt::While { body, .. } => collect_locals_recursive(body, locals),
-            Stmt::Expr(Expr::Block(b, _)) => collect_locals_recursive(b, locals),
+            Stmt::For { body, else_branch, .. } | Stmt::While { body, else_branch, .. } => {
+                collect_locals_recursive(body, locals);
+                if let Some(b) = else_branch {
+                    collect_locals_recursive(b, locals);
+                }
+            }
+            Stmt::Expr(Expr::Block(b, _)) | Stmt::Region { body: b, .. } => collect_locals_recursive(b, locals),
             Stmt::Expr(Expr::If { then_branch, else_branch, .. }) => {
                 collect_locals_recursive(then_branch, locals);
                 if let Some(b) = else_branch {
@@ -0,0 +1,413 @@
+//! Mutable bindings
+//!
+//! From edition 0.3 a binding can only be changed if it is declared mutable:
+//! `let mut x`, `var x`, or a `mut` parameter. This pass reports assigning to
+//! an immutable binding, assigning to a field or element reached through one,
+//! and calling a method that takes `mut self` on one. Inside a method, `self`
+//! is mutable only when the method takes `mut self`. What a `&mut` reference
+//! refers to can be changed through it without the binding being `mut`.
+//!
+//! Names the pass does not see bound, such as statics, actor state and
+//! component state, are left alone, and so are the items of imported modules
+//! linked into the program, which the standard library's are among.
+
+use crate::ast::*;
+use crate::ast::visit::{walk_expr, walk_item, walk_stmt, Visitor};
+use crate::edition::Edition;
+use crate::error::{KainError, KainResult};
+use crate::modules;
+use crate::span::Span;
+use std::collections::HashMap;
+
+/// Report the first change in `program` to a binding not declared mutable
+pub fn check(program: &Program, edition: Edition) -> KainResult<()> {
+    if !edition.enforce_mutability() {
+        return Ok(());
+    }
+    match violations(program).into_iter().next() {
+        Some(v) => Err(KainError::type_error(v.message, v.span)),
+        None => Ok(()),
+    }
+}
+
+/// A change to an immutable binding
+#[derive(Debug, Clone)]
+pub struct Violation {
+    pub message: String,
+    /// Where the binding is changed
+    pub span: Span,
+    /// Where the binding is declared, if `mut ` can be written there
+    pub decl: Option<Span>,
+}
+
+/// Every change in `program` to a binding not declared mutable, in source order
+pub fn violations(program: &Program) -> Vec<Violation> {
+    let mut checker = Checker {
+        mut_self: HashMap::new(),
+        impl_type: None,
+        scopes: vec![HashMap::new()],
+        violations: Vec::new(),
+    };
+    for item in &program.items {
+        if let Item::Impl(imp) = item {
+            let Type::Named { name, .. } = &imp.target_type else {
+                continue;
+            };
+            for method in &imp.methods {
+                if let Some(receiver) = method.params.first().filter(|p| p.name == "self") {
+                    checker.mut_self.insert((name.clone(), method.name.clone()), receiver.mutable);
+                }
+            }
+        }
+    }
+    for item in program.items.iter().filter(|item| !modules::is_linked(item)) {
+        checker.visit_item(item);
+    }
+    checker.violations
+}
+
+struct Binding {
+    mutable: bool,
+    /// The struct the binding holds, when known from its declaration
+    ty: Option<String>,
+    decl: Option<Span>,
+    param: bool,
+    /// Holds a `&mut` reference, so fields and elements can be changed through it
+    reference: bool,
+}
+
+struct Checker {
+    /// Whether each `(type, method)` takes `mut self`
+    mut_self: HashMap<(String, String), bool>,
+    /// Type of the `impl` being checked
+    impl_type: Option<String>,
+    scopes: Vec<HashMap<String, Binding>>,
+    violations: Vec<Violation>,
+}
+
+impl Checker {
+    fn scoped(&mut self, f: impl FnOnce(&mut Self)) {
+        self.scopes.push(HashMap::new());
+        f(self);
+        self.scopes.pop();
+    }
+
+    fn bind(&mut self, name: &str, binding: Binding) {
+        if let Some(scope) = self.scopes.last_mut() {
+            scope.insert(name.to_string(), binding);
+        }
+    }
+
+    fn lookup(&self, name: &str) -> Option<&Binding> {
+        self.scopes.iter().rev().find_map(|scope| scope.get(name))
+    }
+
+    fn bind_pattern(&mut self, pattern: &Pattern, ty: Option<String>) {
+        match pattern {
+            Pattern::Binding { name, mutable, span } => {
+                self.bind(name, Binding { mutable: *mutable, ty, decl: Some(*span), param: false, reference: false })
+            }
+            Pattern::Struct { fields, .. } | Pattern::Variant { fields: VariantPatternFields::Struct(fields), .. } => {
+                for (_, field) in fields {
+                    self.bind_pattern(field, None);
+                }
+            }
+            Pattern::Tuple(patterns, _) | Pattern::Variant { fields: VariantPatternFields::Tuple(patterns), .. } => {
+                for p in patterns {
+                    self.bind_pattern(p, None);
+                }
+            }
+            Pattern::Slice { patterns, rest, .. } => {
+                for p in patterns {
+                    self.bind_pattern(p, None);
+                }
+                if let Some(rest) = rest {
+                    self.bind(rest, Binding { mutable: false, ty: None, decl: None, param: false, reference: false });
+                }
+            }
+            // Every alternative binds the same names
+            Pattern::Or(alternatives, _) => {
+                if let Some(first) = alternatives.first() {
+                    self.bind_pattern(first, None);
+                }
+            }
+            Pattern::Wildcard(_) | Pattern::Literal(_) | Pattern::Range { .. } | Pattern::Variant { .. } => {}
+        }
+    }
+
+    /// The immutable binding `name`, and how to make it mutable
+    fn immutable(&self, name: &str) -> Option<(Option<Span>, String)> {
+        let binding = self.lookup(name).filter(|b| !b.mutable)?;
+        let hint = match (name, binding.param) {
+            ("self", _) => "take `mut self`".to_string(),
+            (_, true) => format!("declare the parameter `mut {}`", name),
+            (_, false) => format!("use `let mut {}`", name),
+        };
+        Some((binding.decl, hint))
+    }
+
+    fn report(&mut self, message: String, span: Span, decl: Option<Span>) {
+        self.violations.push(Violation { message, span, decl });
+    }
+
+    fn check_assign(&mut self, target: &Expr, span: Span) {
+        let (root, what) = match target {
+            Expr::Ident(name, _) => (name, None),
+            Expr::Field { object, .. } => match place_root(object) {
+                Some(root) => (root, Some("a field of")),
+                None => return,
+            },
+            Expr::Index { object, .. } => match place_root(object) {
+                Some(root) => (root, Some("an element of")),
+                None => return,
+            },
+            _ => return,
+        };
+        if what.is_some() && self.lookup(root).is_some_and(|b| b.reference) {
+            return;
+        }
+        let Some((decl, hint)) = self.immutable(root) else {
+            return;
+        };
+        let message = match what {
+            None => format!("cannot assign to `{}`, which is not declared `mut`; {}", root, hint),
+            Some(what) => format!("cannot assign to {} `{}`, which is not declared `mut`; {}", what, root, hint),
+        };
+        self.report(message, span, decl);
+    }
+
+    fn check_method_call(&mut self, receiver: &Expr, method: &str, span: Span) {
+        let Expr::Ident(name, _) = receiver else {
+            return;
+        };
+        let Some(ty) = self.lookup(name).and_then(|b| b.ty.clone()) else {
+            return;
+        };
+        if self.mut_self.get(&(ty, method.to_string())) != Some(&true) {
+            return;
+        }
+        if let Some((decl, hint)) = self.immutable(name) {
+            let message = format!("`{}` takes `mut self`, but `{}` is not declared `mut`; {}", method, name, hint);
+            self.report(message, span, decl);
+        }
+    }
+}
+
+/// The binding a place such as `p.pos.x` or `grid[i][j]` is reached through
+fn place_root(expr: &Expr) -> Option<&String> {
+    match expr {
+        Expr::Ident(name, _) => Some(name),
+        Expr::Field { object, .. } | Expr::Index { object, .. } => place_root(object),
+        Expr::Paren(inner, _) => place_root(inner),
+        _ => None,
+    }
+}
+
+/// The struct a declaration says a binding holds
+fn struct_name(ty: Option<&Type>, value: Option<&Expr>) -> Option<String> {
+    match (ty, value) {
+        (Some(Type::Named { name, .. }), _) => Some(name.clone()),
+        (None, Some(Expr::Struct { name, .. })) => Some(name.clone()),
+        _ => None,
+    }
+}
+
+impl Visitor for Checker {
+    fn visit_item(&mut self, item: &Item) {
+        match item {
+            Item::Impl(imp) => {
+                let outer = self.impl_type.take();
+                if let Type::Named { name, .. } = &imp.target_type {
+                    self.impl_type = Some(name.clone());
+                }
+                walk_item(self, item);
+                self.impl_type = outer;
+            }
+            // Each handler and default method binds its own parameters
+            Item::Actor(actor) => {
+                for state in &actor.state {
+                    self.visit_expr(&state.initial);
+                }
+                for handler in &actor.handlers {
+                    self.scoped(|c| {
+                        for param in &handler.params {
+                            c.visit_param(param);
+                        }
+                        c.visit_block(&handler.body);
+                    });
+                }
+            }
+            Item::Trait(def) => {
+                for method in &def.methods {
+                    self.scoped(|c| {
+                        for param in &method.params {
+                            c.visit_param(param);
+                        }
+                        if let Some(body) = &method.default_impl {
+                            c.visit_block(body);
+                        }
+                    });
+                }
+            }
+            _ => self.scoped(|c| walk_item(c, item)),
+        }
+    }
+
+    fn visit_function(&mut self, function: &Function) {
+        self.scoped(|c| {
+            for param in &function.params {
+                c.visit_param(param);
+            }
+            c.visit_block(&function.body);
+        });
+    }
+
+    fn visit_param(&mut self, param: &Param) {
+        if let Some(default) = &param.default {
+            self.visit_expr(default);
+        }
+        let ty = if param.name == "self" { self.impl_type.clone() } else { struct_name(Some(&param.ty), None) };
+        let reference = matches!(param.ty, Type::Ref { mutable: true, .. });
+        self.bind(&param.name, Binding { mutable: param.mutable, ty, decl: Some(param.span), param: true, reference });
+    }
+
+    fn visit_block(&mut self, block: &Block) {
+        self.scoped(|c| {
+            for stmt in &block.stmts {
+                c.visit_stmt(stmt);
+            }
+        });
+    }
+
+    fn visit_stmt(&mut self, stmt: &Stmt) {
+        match stmt {
+            Stmt::Let { pattern, ty, value, .. } => {
+                if let Some(value) = value {
+                    self.visit_expr(value);
+                }
+                self.bind_pattern(pattern, struct_name(ty.as_ref(), value.as_ref()));
+                let reference = matches!(ty, Some(Type::Ref { mutable: true, .. })) || matches!(value, Some(Expr::Ref { mutable: true, .. }));
+                if let (Pattern::Binding { name, .. }, true) = (pattern, reference) {
+                    if let Some(binding) = self.scopes.last_mut().and_then(|scope| scope.get_mut(name)) {
+                        binding.reference = true;
+                    }
+                }
+            }
+            Stmt::For { binding, iter, body, else_branch, .. } => {
+                self.visit_expr(iter);
+                self.scoped(|c| {
+                    c.bind_pattern(binding, None);
+                    c.visit_block(body);
+                });
+                if let Some(b) = else_branch {
+                    self.visit_block(b);
+                }
+            }
+            _ => walk_stmt(self, stmt),
+        }
+    }
+
+    fn visit_expr(&mut self, expr: &Expr) {
+        match expr {
+            Expr::Assign { target, span, .. } => self.check_assign(target, *span),
+            Expr::MethodCall { receiver, method, span, .. } => self.check_method_call(receiver, method, *span),
+            Expr::Match { scrutinee, arms, .. } => {
+                self.visit_expr(scrutinee);
+                for arm in arms {
+                    self.scoped(|c| {
+                        c.bind_pattern(&arm.pattern, None);
+                        if let Some(guard) = &arm.guard {
+                            c.visit_expr(guard);
+                        }
+                        c.visit_expr(&arm.body);
+                    });
+                }
+                return;
+            }
+            Expr::Lambda { .. } => {
+                self.scoped(|c| walk_expr(c, expr));
+                return;
+            }
+            _ => {}
+        }
+        walk_expr(self, expr);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{compile_with, compile_with_options, edition, eval_snippet, fix, CompileOptions, CompileTarget, Lexer, Parser};
+
+    fn check_source(source: &str) -> KainResult<()> {
+        let tokens = Lexer::with_edition(source, Edition::V0_3).tokenize()?;
+        let program = Parser::new(&tokens).parse()?;
+        check(&program, Edition::V0_3)
+    }
+
+    #[test]
+    fn test_changes_need_a_mutable_binding() {
+        let point = "struct Point:\n    x: Int\n    y: Int\n\nimpl Point:\n    fn shift(mut self, dx: Int):\n        self.x = self.x + dx\n\n    fn norm(self) -> Int:\n        return self.x * self.x\n\n";
+
+        let err = check_source("fn main():\n    let n = 1\n    n = 2\n").unwrap_err();
+        assert!(err.to_string().contains("cannot assign to `n`, which is not declared `mut`; use `let mut n`"), "{}", err);
+
+        let err = check_source(&format!("{}fn main():\n    let p = Point {{ x: 1, y: 2 }}\n    p.x = 3\n", point)).unwrap_err();
+        assert!(err.to_string().contains("cannot assign to a field of `p`"), "{}", err);
+
+        let err = check_source(&format!("{}fn main():\n    let p = Point {{ x: 1, y: 2 }}\n    p.shift(1)\n", point)).unwrap_err();
+        assert!(err.to_string().contains("`shift` takes `mut self`, but `p` is not declared `mut`"), "{}", err);
+
+        let err = check_source("struct C:\n    n: Int\n\nimpl C:\n    fn bump(self):\n        self.n = self.n + 1\n").unwrap_err();
+        assert!(err.to_string().contains("a field of `self`, which is not declared `mut`; take `mut self`"), "{}", err);
+
+        let err = check_source("fn fill(xs: [Int]):\n    xs[0] = 1\n").unwrap_err();
+        assert!(err.to_string().contains("an element of `xs`, which is not declared `mut`; declare the parameter `mut xs`"), "{}", err);
+
+        // Mutable bindings, `&mut` references, shadowing, `var` and names bound elsewhere are fine
+        check_source(&format!(
+            "{}fn reset(p: &mut Point):\n    p.x = 0\n\nstatic mut TOTAL: Int = 0\n\nfn main():\n    let mut p = Point {{ x: 1, y: 2 }}\n    p.shift(1)\n    p.x = p.norm()\n    var n = 0\n    n = 1\n    let q = Point {{ x: 0, y: 0 }}\n    println(q.norm())\n    let k = 1\n    for k in 0..3:\n        let mut k = k\n        k = k + 1\n    TOTAL = n\n",
+            point
+        ))
+        .unwrap();
+    }
+
+    #[test]
+    fn test_linked_modules_are_left_alone() {
+        // std/future reassigns bindings it declares without `mut`
+        let source = "use std/future
+
+fn main():
+    let mut n = 1
+    n = n + 1
+    println(n)
+";
+        for edition in [Edition::V0_3, Edition::V0_4] {
+            let options = crate::CompileOptions { edition, ..Default::default() };
+            crate::compile_with_options(source, crate::CompileTarget::Js, &options).unwrap();
+            let err = crate::compile_with_options(&source.replace("let mut n", "let n"), crate::CompileTarget::Js, &options).unwrap_err();
+            assert!(err.to_string().contains("cannot assign to `n`"), "{}", err);
+        }
+    }
+
+    #[test]
+    fn test_immutable_bindings_are_enforced_from_edition_0_3() {
+        let source = "struct Counter:\n    n: Int\n\nimpl Counter:\n    fn bump(mut self):\n        self.n = self.n + 1\n\nfn main():\n    let c = Counter { n: 0 }\n    c.bump()\n    let total = c.n\n    total = total * 2\n    println(total)\n";
+        let options = CompileOptions { edition: edition::Edition::V0_3, ..Default::default() };
+        let err = compile_with_options(source, CompileTarget::Js, &options).unwrap_err();
+        assert!(err.to_string().contains("`bump` takes `mut self`, but `c` is not declared `mut`; use `let mut c`"), "{}", err);
+
+        // Older editions still run it, warn, and `kain fix` declares both bindings `mut`
+        let result = eval_snippet(source, &CompileOptions::default());
+        assert!(result.diagnostics.is_empty(), "{:?}", result.diagnostics);
+        assert_eq!(result.stdout.trim_end(), "2");
+        let output = compile_with(source, CompileTarget::Js, &CompileOptions::default()).unwrap();
+        assert_eq!(output.warnings.len(), 2);
+        assert!(output.warnings[1].message.contains("cannot assign to `total`, which is not declared `mut` (rejected from edition 0.3)"));
+        let (fixed, applied) = fix::fix_source(source, edition::Edition::V0_1);
+        assert_eq!(applied, 2);
+        assert!(fixed.contains("let mut c = Counter") && fixed.contains("let mut total = c.n"));
+        let result = eval_snippet(&fixed, &CompileOptions { edition: edition::Edition::V0_3, ..Default::default() });
+        assert!(result.diagnostics.is_empty(), "{:?}", result.diagnostics);
+    }
+}
@@ -0,0 +1,1011 @@
+//! Ownership and borrowing
+//!
+//! From edition 0.4 values of the program's structs, of its enums with
+//! payloads, and arrays and maps move: `let b = a`, passing `a` to a function
+//! or storing it in a literal leaves `a` unusable until it is assigned again.
+//! `&x` and `&mut x` borrow a place without moving it. This pass runs after
+//! type checking and rejects using a value after it moved, borrowing a place
+//! mutably while another borrow of it is live (or at all while it is borrowed
+//! mutably), and returning a reference to a local. Each error points at both
+//! sides of the conflict: where the value moved, was first borrowed, or was
+//! declared.
+//!
+//! A borrow held in a `let` lasts until the binding's last use, and one
+//! passed to a call until the call returns, unless the function returns a
+//! reference, which keeps the borrow for as long as its result. Numbers,
+//! booleans, characters, strings and enums without payloads are copied, and
+//! so are values whose type the pass can't tell from their declaration.
+//! Closures are not analyzed, nor are the items of imported modules linked
+//! into the program, though their declarations are read.
+//!
+//! Only the Rust backend has references of its own; for every other target
+//! [`erase_references`] replaces them with the values they refer to once the
+//! program is checked.
+
+use crate::ast::*;
+use crate::ast::visit::{walk_expr, walk_expr_mut, walk_function_mut, walk_param_mut, walk_stmt_mut, Visitor, VisitorMut};
+use crate::edition::Edition;
+use crate::error::{KainError, KainResult};
+use crate::modules;
+use crate::span::Span;
+use crate::stdlib::stdlib;
+use crate::types::typed_visit::TypedVisitorMut;
+use crate::types::TypedProgram;
+use std::collections::{HashMap, HashSet};
+
+/// Report the first use of `program`'s values that breaks ownership or borrowing
+pub fn check(program: &Program, edition: Edition) -> KainResult<()> {
+    if !edition.enforce_ownership() {
+        return Ok(());
+    }
+    match violations(program).into_iter().next() {
+        Some(v) => Err(KainError::borrow_conflict(v.message, v.span, v.related, v.note)),
+        None => Ok(()),
+    }
+}
+
+/// A use of a value that conflicts with a move, a borrow or its declaration
+#[derive(Debug, Clone)]
+pub struct Violation {
+    pub message: String,
+    /// Where the value is used
+    pub span: Span,
+    /// Where it moved, was first borrowed, or was declared
+    pub related: Span,
+    /// What happened at `related`
+    pub note: String,
+}
+
+/// Every use of `program`'s values that breaks ownership or borrowing, in source order
+pub fn violations(program: &Program) -> Vec<Violation> {
+    let declarations = Declarations::of(program);
+    let mut checker = Checker::new(&declarations);
+    for item in program.items.iter().filter(|item| !modules::is_linked(item)) {
+        checker.item(item);
+    }
+    let mut violations = checker.violations;
+    violations.sort_by_key(|v| v.span.start);
+    violations
+}
+
+/// What a function's call gives back
+#[derive(Clone, Copy, PartialEq)]
+enum Returns {
+    Copied,
+    Owned,
+    Reference,
+}
+
+/// What the program declares that decides how its values are passed
+#[derive(Default)]
+struct Declarations {
+    /// Structs and enums with payloads, whose values move
+    owned: HashSet<String>,
+    /// What each function returns
+    functions: HashMap<String, Returns>,
+    /// Methods the program declares, which take their arguments
+    methods: HashSet<String>,
+    /// Methods that take `mut self`, so calling one borrows the receiver mutably
+    mut_methods: HashSet<String>,
+}
+
+impl Declarations {
+    fn of(program: &Program) -> Self {
+        let items: Vec<&Item> = program
+            .items
+            .iter()
+            .map(|item| match item {
+                Item::Cfg(c) => &*c.item,
+                item => item,
+            })
+            .collect();
+        let mut declarations = Declarations::default();
+        for item in &items {
+            match item {
+                Item::Struct(s) => {
+                    declarations.owned.insert(s.name.clone());
+                }
+                Item::Enum(e) if e.variants.iter().any(|v| !matches!(v.fields, VariantFields::Unit)) => {
+                    declarations.owned.insert(e.name.clone());
+                }
+                _ => {}
+            }
+        }
+        for item in &items {
+            match item {
+                Item::Function(f) => {
+                    let returns = declarations.returns(f.return_type.as_ref());
+                    declarations.functions.insert(f.name.clone(), returns);
+                }
+                Item::Impl(imp) => {
+                    for method in &imp.methods {
+                        declarations.methods.insert(method.name.clone());
+                        if method.params.first().is_some_and(|p| p.name == "self" && p.mutable) {
+                            declarations.mut_methods.insert(method.name.clone());
+                        }
+                    }
+                }
+                Item::Trait(def) => declarations.methods.extend(def.methods.iter().map(|m| m.name.clone())),
+                _ => {}
+            }
+        }
+        declarations
+    }
+
+    fn returns(&self, ty: Option<&Type>) -> Returns {
+        match ty {
+            Some(Type::Ref { .. }) => Returns::Reference,
+            Some(ty) if self.owned_type(ty) => Returns::Owned,
+            _ => Returns::Copied,
+        }
+    }
+
+    /// Whether values of `ty` move rather than being copied
+    fn owned_type(&self, ty: &Type) -> bool {
+        match ty {
+            Type::Named { name, generics, .. } => {
+                self.owned.contains(name) || matches!(name.as_str(), "Array" | "Map" | "Box") || generics.iter().any(|t| self.owned_type(t))
+            }
+            Type::Array(..) | Type::Slice(..) => true,
+            Type::Tuple(types, _) => types.iter().any(|t| self.owned_type(t)),
+            Type::Option(inner, _) => self.owned_type(inner),
+            Type::Result(ok, err, _) => self.owned_type(ok) || self.owned_type(err),
+            _ => false,
+        }
+    }
+}
+
+/// A binding, or a field or element reached through it
+#[derive(Debug, Clone)]
+struct Place {
+    /// Index of the binding in [`Checker::bindings`]
+    root: usize,
+    /// `.field` and `[..]` steps from it; every element counts as the same one
+    path: Vec<String>,
+}
+
+impl Place {
+    /// Whether changing one can change the other
+    fn overlaps(&self, other: &Place) -> bool {
+        self.root == other.root && self.path.iter().zip(&other.path).all(|(a, b)| a == b)
+    }
+}
+
+/// A place borrowed by `&` or `&mut`
+#[derive(Debug, Clone)]
+struct Loan {
+    place: Place,
+    mutable: bool,
+    span: Span,
+    /// Where the last use of the binding holding it ends; a temporary one
+    /// ends with its call or statement instead
+    until: Option<usize>,
+}
+
+struct Binding {
+    name: String,
+    decl: Span,
+    /// Moves when used as a value, rather than being copied
+    owned: bool,
+    /// Declared by the function itself, so a reference to it can't outlive the call
+    local: bool,
+    /// What the reference it holds borrows
+    borrows: Vec<Loan>,
+}
+
+/// Where each binding that may have moved last did
+type Moves = HashMap<usize, Span>;
+
+/// The moves on each path out of, or back around, the innermost loop
+#[derive(Default)]
+struct LoopExits {
+    breaks: Vec<Moves>,
+    continues: Vec<Moves>,
+}
+
+struct Checker<'a> {
+    declarations: &'a Declarations,
+    /// Every binding the body declares; scopes map names to them
+    bindings: Vec<Binding>,
+    scopes: Vec<HashMap<String, usize>>,
+    /// The moves on the path being checked
+    moved: Moves,
+    loans: Vec<Loan>,
+    /// Whether the path being checked has returned or left its loop
+    diverged: bool,
+    loops: Vec<LoopExits>,
+    /// Whether a loop body is being checked again, as its next iteration
+    repeating: bool,
+    violations: Vec<Violation>,
+}
+
+impl<'a> Checker<'a> {
+    fn new(declarations: &'a Declarations) -> Self {
+        Checker {
+            declarations,
+            bindings: Vec::new(),
+            scopes: vec![HashMap::new()],
+            moved: HashMap::new(),
+            loans: Vec::new(),
+            diverged: false,
+            loops: Vec::new(),
+            repeating: false,
+            violations: Vec::new(),
+        }
+    }
+
+    /// Check each body `item` declares on its own
+    fn item(&mut self, item: &Item) {
+        match item {
+            Item::Function(f) => self.body(&f.params, &f.body, f.return_type.is_some()),
+            Item::Impl(imp) => {
+                for method in &imp.methods {
+                    self.body(&method.params, &method.body, method.return_type.is_some());
+                }
+            }
+            Item::Trait(def) => {
+                for method in &def.methods {
+                    if let Some(body) = &method.default_impl {
+                        self.body(&method.params, body, method.return_type.is_some());
+                    }
+                }
+            }
+            Item::Actor(actor) => {
+                for handler in &actor.handlers {
+                    self.body(&handler.params, &handler.body, false);
+                }
+            }
+            Item::Component(c) => {
+                for method in &c.methods {
+                    self.body(&method.params, &method.body, method.return_type.is_some());
+                }
+            }
+            Item::Test(t) => self.body(&[], &t.body, false),
+            Item::Cfg(c) => self.item(&c.item),
+            _ => {}
+        }
+    }
+
+    /// `returns` is whether the body's last expression is the function's result
+    fn body(&mut self, params: &[Param], body: &Block, returns: bool) {
+        let mut checker = Checker::new(self.declarations);
+        for param in params {
+            // `self` and `&T` parameters refer to the caller's values
+            let borrowed = param.name == "self" || matches!(param.ty, Type::Ref { .. });
+            let owned = !borrowed && self.declarations.owned_type(&param.ty);
+            checker.bind(&param.name, param.span, owned, !borrowed, Vec::new());
+        }
+        checker.block(body, returns);
+        self.violations.extend(checker.violations);
+    }
+
+    fn bind(&mut self, name: &str, decl: Span, owned: bool, local: bool, borrows: Vec<Loan>) {
+        let id = self.bindings.len();
+        self.bindings.push(Binding { name: name.to_string(), decl, owned, local, borrows });
+        if let Some(scope) = self.scopes.last_mut() {
+            scope.insert(name.to_string(), id);
+        }
+    }
+
+    fn lookup(&self, name: &str) -> Option<usize> {
+        self.scopes.iter().rev().find_map(|scope| scope.get(name).copied())
+    }
+
+    fn bind_pattern(&mut self, pattern: &Pattern) {
+        match pattern {
+            Pattern::Binding { name, span, .. } => self.bind(name, *span, false, true, Vec::new()),
+            Pattern::Struct { fields, .. } | Pattern::Variant { fields: VariantPatternFields::Struct(fields), .. } => {
+                for (_, field) in fields {
+                    self.bind_pattern(field);
+                }
+            }
+            Pattern::Tuple(patterns, _) | Pattern::Variant { fields: VariantPatternFields::Tuple(patterns), .. } => {
+                for p in patterns {
+                    self.bind_pattern(p);
+                }
+            }
+            Pattern::Slice { patterns, rest, span } => {
+                for p in patterns {
+                    self.bind_pattern(p);
+                }
+                if let Some(rest) = rest {
+                    self.bind(rest, *span, false, true, Vec::new());
+                }
+            }
+            // Every alternative binds the same names
+            Pattern::Or(alternatives, _) => {
+                if let Some(first) = alternatives.first() {
+                    self.bind_pattern(first);
+                }
+            }
+            Pattern::Wildcard(_) | Pattern::Literal(_) | Pattern::Range { .. } | Pattern::Variant { .. } => {}
+        }
+    }
+
+    fn report(&mut self, message: String, span: Span, related: Span, note: String) {
+        // Loop bodies are checked twice, and find the same errors the second time
+        if self.violations.iter().any(|v| v.span == span) {
+            return;
+        }
+        self.violations.push(Violation { message, span, related, note });
+    }
+
+    fn describe(&self, place: &Place) -> String {
+        format!("{}{}", self.bindings[place.root].name, place.path.concat())
+    }
+
+    /// The binding, field or element `expr` names
+    fn place(&self, expr: &Expr) -> Option<Place> {
+        match expr {
+            Expr::Ident(name, _) => Some(Place { root: self.lookup(name)?, path: Vec::new() }),
+            Expr::Field { object, field, .. } => {
+                let mut place = self.place(object)?;
+                place.path.push(format!(".{}", field));
+                Some(place)
+            }
+            Expr::Index { object, .. } => {
+                let mut place = self.place(object)?;
+                place.path.push("[..]".to_string());
+                Some(place)
+            }
+            Expr::Paren(inner, _) => self.place(inner),
+            _ => None,
+        }
+    }
+
+    /// Evaluate the indices on the way to a place
+    fn indices(&mut self, expr: &Expr) {
+        match expr {
+            Expr::Field { object, .. } | Expr::Paren(object, _) => self.indices(object),
+            Expr::Index { object, index, .. } => {
+                self.indices(object);
+                self.expr(index);
+            }
+            _ => {}
+        }
+    }
+
+    /// A live borrow of a place overlapping `place` that `conflicts` with what happens at `at`
+    fn live_loan(&self, place: &Place, at: Span, conflicts: impl Fn(&Loan) -> bool) -> Option<Loan> {
+        self.loans
+            .iter()
+            .find(|loan| loan.until.is_none_or(|end| at.start <= end) && loan.place.overlaps(place) && conflicts(loan))
+            .cloned()
+    }
+
+    fn check_moved(&mut self, place: &Place, span: Span) {
+        let Some(&moved_at) = self.moved.get(&place.root) else { return };
+        let name = self.bindings[place.root].name.clone();
+        let note = if self.repeating && moved_at.start >= span.start {
+            format!("`{}` was moved here, in the previous iteration of the loop", name)
+        } else {
+            format!("`{}` was moved here", name)
+        };
+        self.report(format!("use of moved value `{}`", name), span, moved_at, note);
+    }
+
+    /// Read `expr`, or move it when `moves` and it is a binding holding an owned value
+    fn use_place(&mut self, expr: &Expr, moves: bool) {
+        let Some(place) = self.place(expr) else {
+            walk_expr(self, expr);
+            return;
+        };
+        self.indices(expr);
+        let span = expr.span();
+        self.check_moved(&place, span);
+        let moving = moves && place.path.is_empty() && self.bindings[place.root].owned;
+        if let Some(loan) = self.live_loan(&place, span, |loan| moving || loan.mutable) {
+            let name = self.describe(&place);
+            let message = if moving {
+                format!("cannot move out of `{}` while it is borrowed", name)
+            } else {
+                format!("cannot use `{}` while it is mutably borrowed", name)
+            };
+            self.report(message, span, loan.span, format!("`{}` is borrowed here", self.describe(&loan.place)));
+        }
+        if moving {
+            self.moved.insert(place.root, span);
+        }
+    }
+
+    /// Evaluate `expr` where its value is taken, moving it if it is an owned binding
+    fn consume(&mut self, expr: &Expr) {
+        match expr {
+            Expr::Ident(..) => self.use_place(expr, true),
+            Expr::Paren(inner, _) => self.consume(inner),
+            _ => self.expr(expr),
+        }
+    }
+
+    fn borrow(&mut self, value: &Expr, mutable: bool, span: Span) {
+        let Some(place) = self.place(value) else {
+            self.expr(value);
+            return;
+        };
+        self.indices(value);
+        self.check_moved(&place, span);
+        if let Some(loan) = self.live_loan(&place, span, |loan| mutable || loan.mutable) {
+            let name = self.describe(&place);
+            let message = match (mutable, loan.mutable) {
+                (true, true) => format!("cannot borrow `{}` as mutable more than once at a time", name),
+                (true, false) => format!("cannot borrow `{}` as mutable because it is also borrowed as immutable", name),
+                (false, _) => format!("cannot borrow `{}` as immutable because it is also borrowed as mutable", name),
+            };
+            let note = format!("`{}` is first borrowed here", self.describe(&loan.place));
+            self.report(message, span, loan.span, note);
+        }
+        self.loans.push(Loan { place, mutable, span, until: None });
+    }
+
+    fn assign(&mut self, target: &Expr, value: &Expr, span: Span) {
+        self.consume(value);
+        let Some(place) = self.place(target) else {
+            self.expr(target);
+            return;
+        };
+        self.indices(target);
+        if let Some(loan) = self.live_loan(&place, span, |_| true) {
+            let message = format!("cannot assign to `{}` while it is borrowed", self.describe(&place));
+            self.report(message, span, loan.span, format!("`{}` is borrowed here", self.describe(&loan.place)));
+        }
+        if place.path.is_empty() {
+            // A binding given a new value can be used again
+            self.moved.remove(&place.root);
+        } else {
+            self.check_moved(&place, target.span());
+        }
+    }
+
+    /// Whether a call to `callee` takes its arguments; the standard library's functions only look at theirs
+    fn moves_args(&self, callee: &Expr) -> bool {
+        match callee {
+            Expr::Ident(name, _) => {
+                self.lookup(name).is_some()
+                    || self.declarations.functions.contains_key(name)
+                    || matches!(name.as_str(), "Some" | "Ok" | "Err")
+                    || !stdlib().functions.contains_key(name)
+            }
+            _ => true,
+        }
+    }
+
+    /// Whether a call to `callee` returns a reference, which borrows from its arguments
+    fn returns_reference(&self, callee: &Expr) -> bool {
+        match callee {
+            Expr::Ident(name, _) => {
+                self.lookup(name).is_none() && self.declarations.functions.get(name) == Some(&Returns::Reference)
+            }
+            _ => false,
+        }
+    }
+
+    /// Whether `value` makes an owned value, as far as its expression tells
+    fn owned_value(&self, value: &Expr) -> bool {
+        match value {
+            Expr::Struct { name, .. } => self.declarations.owned.contains(name),
+            Expr::EnumVariant { enum_name, .. } => self.declarations.owned.contains(enum_name),
+            Expr::Array(..) => true,
+            Expr::Tuple(items, _) => items.iter().any(|e| self.owned_value(e)),
+            Expr::Ident(name, _) => self.lookup(name).is_some_and(|id| self.bindings[id].owned),
+            Expr::Paren(inner, _) => self.owned_value(inner),
+            Expr::Call { callee, args, .. } => match &**callee {
+                Expr::Ident(name, _) if matches!(name.as_str(), "Some" | "Ok" | "Err") => {
+                    args.iter().any(|a| self.owned_value(&a.value))
+                }
+                Expr::Ident(name, _) => {
+                    self.lookup(name).is_none() && self.declarations.functions.get(name) == Some(&Returns::Owned)
+                }
+                _ => false,
+            },
+            _ => false,
+        }
+    }
+
+    /// What the references `value` evaluates to borrow: its own `&x`s, those
+    /// held by bindings it copies, and those passed to a function returning a
+    /// reference, in whichever branch gives the value
+    fn borrows_in(&self, value: &Expr) -> Vec<Loan> {
+        match value {
+            Expr::Ref { mutable, value, span } => match self.place(value) {
+                Some(place) => vec![Loan { place, mutable: *mutable, span: *span, until: None }],
+                None => Vec::new(),
+            },
+            Expr::Ident(name, _) => self.lookup(name).map(|id| self.bindings[id].borrows.clone()).unwrap_or_default(),
+            Expr::Paren(inner, _) => self.borrows_in(inner),
+            Expr::Tuple(items, _) | Expr::Array(items, _) => items.iter().flat_map(|e| self.borrows_in(e)).collect(),
+            Expr::Struct { fields, .. } => fields.iter().flat_map(|(_, e)| self.borrows_in(e)).collect(),
+            Expr::Call { callee, args, .. } if self.returns_reference(callee) => {
+                args.iter().flat_map(|a| self.borrows_in(&a.value)).collect()
+            }
+            Expr::If { then_branch, else_branch, .. } => {
+                let mut borrows = self.tail_borrows(then_branch);
+                let mut branch = else_branch.as_deref();
+                while let Some(b) = branch {
+                    match b {
+                        ElseBranch::Else(block) => {
+                            borrows.extend(self.tail_borrows(block));
+                            branch = None;
+                        }
+                        ElseBranch::ElseIf(_, block, rest) => {
+                            borrows.extend(self.tail_borrows(block));
+                            branch = rest.as_deref();
+                        }
+                    }
+                }
+                borrows
+            }
+            Expr::Match { arms, .. } => arms.iter().flat_map(|arm| self.borrows_in(&arm.body)).collect(),
+            Expr::Block(block, _) => self.tail_borrows(block),
+            _ => Vec::new(),
+        }
+    }
+
+    fn tail_borrows(&self, block: &Block) -> Vec<Loan> {
+        match block.stmts.last() {
+            Some(Stmt::Expr(e)) => self.borrows_in(e),
+            _ => Vec::new(),
+        }
+    }
+
+    /// Report references `value` would return to the function's own bindings
+    fn check_escape(&mut self, value: &Expr) {
+        let within = value.span();
+        for loan in self.borrows_in(value) {
+            let binding = &self.bindings[loan.place.root];
+            if !binding.local {
+                continue;
+            }
+            let (name, decl) = (binding.name.clone(), binding.decl);
+            let what = if loan.place.path.is_empty() {
+                format!("local `{}`", name)
+            } else {
+                format!("`{}`, part of local `{}`", self.describe(&loan.place), name)
+            };
+            // Point at the `&` itself, unless it was taken earlier into a binding
+            let span = if loan.span.start >= within.start && loan.span.end <= within.end { loan.span } else { within };
+            self.report(format!("cannot return a reference to {}", what), span, decl, format!("`{}` is declared here", name));
+        }
+    }
+
+    fn ret(&mut self, value: Option<&Expr>) {
+        if let Some(value) = value {
+            self.check_escape(value);
+            self.consume(value);
+        }
+        self.diverged = true;
+    }
+
+    fn leave_loop(&mut self, value: Option<&Expr>, continues: bool) {
+        if let Some(value) = value {
+            self.consume(value);
+        }
+        let moved = self.moved.clone();
+        if let Some(exits) = self.loops.last_mut() {
+            if continues { exits.continues.push(moved) } else { exits.breaks.push(moved) }
+        }
+        self.diverged = true;
+    }
+
+    /// `returns` is whether the block's last expression is the function's result
+    fn block(&mut self, block: &Block, returns: bool) {
+        let loans = self.loans.len();
+        self.scopes.push(HashMap::new());
+        for (i, stmt) in block.stmts.iter().enumerate() {
+            if self.diverged {
+                break;
+            }
+            let rest = &block.stmts[i + 1..];
+            if returns && rest.is_empty() {
+                if let Stmt::Expr(value) = stmt {
+                    self.check_escape(value);
+                }
+            }
+            self.stmt(stmt, rest);
+        }
+        self.scopes.pop();
+        self.loans.truncate(loans);
+    }
+
+    /// `rest` are the statements after `stmt` in its block
+    fn stmt(&mut self, stmt: &Stmt, rest: &[Stmt]) {
+        let loans = self.loans.len();
+        match stmt {
+            Stmt::Let { pattern, ty, value, span } => {
+                let mut owned = ty.as_ref().is_some_and(|ty| self.declarations.owned_type(ty));
+                let mut borrows = Vec::new();
+                if let Some(value) = value {
+                    owned |= self.owned_value(value);
+                    borrows = self.borrows_in(value);
+                    self.consume(value);
+                }
+                self.loans.truncate(loans);
+                match pattern {
+                    Pattern::Binding { name, span: decl, .. } => {
+                        // What it borrows stays borrowed until it is last used
+                        let until = last_use(name, rest).unwrap_or(span.end);
+                        let borrows: Vec<Loan> = borrows.into_iter().map(|loan| Loan { until: Some(until), ..loan }).collect();
+                        self.loans.extend(borrows.iter().cloned());
+                        self.bind(name, *decl, owned, true, borrows);
+                    }
+                    pattern => self.bind_pattern(pattern),
+                }
+                return;
+            }
+            Stmt::Expr(e) => self.expr(e),
+            Stmt::Return(value, _) => self.ret(value.as_ref()),
+            Stmt::Break(value, ..) => self.leave_loop(value.as_ref(), false),
+            Stmt::Continue(..) => self.leave_loop(None, true),
+            Stmt::For { binding, iter, body, else_branch, .. } => {
+                self.expr(iter);
+                self.repeat(|c| {
+                    c.scopes.push(HashMap::new());
+                    c.bind_pattern(binding);
+                    c.block(body, false);
+                    c.scopes.pop();
+                });
+                if let Some(b) = else_branch {
+                    self.block(b, false);
+                }
+            }
+            Stmt::While { condition, body, else_branch, .. } => {
+                self.repeat(|c| {
+                    c.expr(condition);
+                    c.block(body, false);
+                });
+                if let Some(b) = else_branch {
+                    self.block(b, false);
+                }
+            }
+            Stmt::Loop { body, .. } => self.repeat(|c| c.block(body, false)),
+            Stmt::Region { body, .. } => self.block(body, false),
+            Stmt::Item(item) => self.item(item),
+        }
+        self.loans.truncate(loans);
+    }
+
+    /// Check a loop body twice, the second time with what the first moved, and
+    /// leave what any way out of the loop may have moved
+    fn repeat(&mut self, body: impl Fn(&mut Self)) {
+        let entry = self.moved.clone();
+        let repeating = self.repeating;
+        self.loops.push(LoopExits::default());
+        body(self);
+        let mut again = std::mem::take(&mut self.loops.last_mut().expect("loop pushed above").continues);
+        if !self.diverged {
+            again.push(std::mem::take(&mut self.moved));
+        }
+        let mut exits = vec![entry];
+        if !again.is_empty() {
+            self.moved = union(again);
+            self.diverged = false;
+            self.repeating = true;
+            body(self);
+            if !self.diverged {
+                exits.push(std::mem::take(&mut self.moved));
+            }
+        }
+        let LoopExits { breaks, continues } = self.loops.pop().expect("loop pushed above");
+        exits.extend(breaks);
+        exits.extend(continues);
+        self.moved = union(exits);
+        self.diverged = false;
+        self.repeating = repeating;
+    }
+
+    /// Check `branches` from the same state; afterwards a value may have moved
+    /// if it did on any branch that carries on past them, or on skipping them
+    /// all when they are not `exhaustive`
+    fn branches<T>(&mut self, branches: &[T], exhaustive: bool, check: impl Fn(&mut Self, &T)) {
+        let entry = self.moved.clone();
+        let mut exits = Vec::new();
+        if !exhaustive {
+            exits.push(entry.clone());
+        }
+        for branch in branches {
+            self.moved = entry.clone();
+            self.diverged = false;
+            check(self, branch);
+            if !self.diverged {
+                exits.push(std::mem::take(&mut self.moved));
+            }
+        }
+        self.diverged = exits.is_empty();
+        self.moved = union(exits);
+    }
+
+    fn if_expr(&mut self, condition: &Expr, then_branch: &Block, else_branch: Option<&ElseBranch>) {
+        self.expr(condition);
+        // `None` is the `then` block
+        let branches = match else_branch {
+            Some(b) => vec![None, Some(b)],
+            None => vec![None],
+        };
+        self.branches(&branches, else_branch.is_some(), |c, branch| match branch {
+            None => c.block(then_branch, false),
+            Some(ElseBranch::Else(block)) => c.block(block, false),
+            Some(ElseBranch::ElseIf(condition, block, rest)) => c.if_expr(condition, block, rest.as_deref()),
+        });
+    }
+
+    fn args(&mut self, args: &[CallArg], moves: bool) {
+        for arg in args {
+            if moves {
+                self.consume(&arg.value);
+            } else {
+                self.expr(&arg.value);
+            }
+        }
+    }
+
+    fn expr(&mut self, expr: &Expr) {
+        match expr {
+            Expr::Ident(..) | Expr::Field { .. } | Expr::Index { .. } => self.use_place(expr, false),
+            Expr::Ref { mutable, value, span } => self.borrow(value, *mutable, *span),
+            Expr::Assign { target, value, span } => self.assign(target, value, *span),
+            Expr::Call { callee, args, .. } => {
+                let loans = self.loans.len();
+                self.expr(callee);
+                self.args(args, self.moves_args(callee));
+                // The borrows passed to a call end with it, unless its result holds them
+                if !self.returns_reference(callee) {
+                    self.loans.truncate(loans);
+                }
+                if matches!(&**callee, Expr::Ident(name, _) if name == "panic") {
+                    self.diverged = true;
+                }
+            }
+            Expr::MethodCall { receiver, method, args, .. } => {
+                let loans = self.loans.len();
+                let moves = self.declarations.methods.contains(method);
+                if self.declarations.mut_methods.contains(method) && self.place(receiver).is_some() {
+                    // Like Rust's two-phase borrows, the receiver is borrowed once the arguments are evaluated
+                    self.args(args, moves);
+                    self.borrow(receiver, true, receiver.span());
+                } else {
+                    self.expr(receiver);
+                    self.args(args, moves);
+                }
+                self.loans.truncate(loans);
+            }
+            Expr::Struct { fields, base, .. } => {
+                for (_, value) in fields {
+                    self.consume(value);
+                }
+                if let Some(base) = base {
+                    self.expr(base);
+                }
+            }
+            Expr::Array(items, _) | Expr::Tuple(items, _) => {
+                for item in items {
+                    self.consume(item);
+                }
+            }
+            Expr::EnumVariant { fields, .. } => match fields {
+                EnumVariantFields::Unit => {}
+                EnumVariantFields::Tuple(values) => values.iter().for_each(|v| self.consume(v)),
+                EnumVariantFields::Struct(fields) => fields.iter().for_each(|(_, v)| self.consume(v)),
+            },
+            Expr::Spawn { init, .. } => init.iter().for_each(|(_, v)| self.consume(v)),
+            Expr::SendMsg { target, data, .. } => {
+                self.expr(target);
+                data.iter().for_each(|(_, v)| self.consume(v));
+            }
+            Expr::If { condition, then_branch, else_branch, .. } => {
+                self.if_expr(condition, then_branch, else_branch.as_deref())
+            }
+            Expr::Match { scrutinee, arms, .. } => {
+                self.expr(scrutinee);
+                self.branches(arms, true, |c, arm| {
+                    c.scopes.push(HashMap::new());
+                    c.bind_pattern(&arm.pattern);
+                    if let Some(guard) = &arm.guard {
+                        c.expr(guard);
+                    }
+                    c.consume(&arm.body);
+                    c.scopes.pop();
+                });
+            }
+            Expr::Block(block, _) => self.block(block, false),
+            Expr::Return(value, _) => self.ret(value.as_deref()),
+            Expr::Break(value, ..) => self.leave_loop(value.as_deref(), false),
+            Expr::Continue(..) => self.leave_loop(None, true),
+            Expr::Lambda { .. } | Expr::Comptime(..) | Expr::JSX(..) => {}
+            _ => walk_expr(self, expr),
+        }
+    }
+}
+
+/// Where the last use of `name` in `stmts` ends
+fn last_use(name: &str, stmts: &[Stmt]) -> Option<usize> {
+    let mut finder = LastUse { name, end: None };
+    for stmt in stmts {
+        finder.visit_stmt(stmt);
+    }
+    finder.end
+}
+
+struct LastUse<'n> {
+    name: &'n str,
+    end: Option<usize>,
+}
+
+impl Visitor for LastUse<'_> {
+    fn visit_expr(&mut self, expr: &Expr) {
+        if let Expr::Ident(name, span) = expr {
+            if name == self.name {
+                self.end = self.end.max(Some(span.end));
+            }
+        }
+        walk_expr(self, expr);
+    }
+}
+
+/// What moved on any of `paths`, and where it first did
+fn union(paths: Vec<Moves>) -> Moves {
+    let mut moved = Moves::new();
+    for path in paths {
+        for (id, span) in path {
+            moved.entry(id).or_insert(span);
+        }
+    }
+    moved
+}
+
+/// Subexpressions reached by the default walk are evaluated for their values
+impl Visitor for Checker<'_> {
+    fn visit_expr(&mut self, expr: &Expr) {
+        self.expr(expr);
+    }
+
+    fn visit_block(&mut self, block: &Block) {
+        self.block(block, false);
+    }
+}
+
+/// Replace `&x` with `x` and `&T` with `T`, for backends without references
+pub fn erase_references(program: &mut TypedProgram) {
+    ReferenceEraser.visit_typed_program_mut(program);
+}
+
+struct ReferenceEraser;
+
+/// `ty` with every `&` and `&mut` in it removed
+fn erase(ty: &mut Type) {
+    if let Type::Ref { inner, span, .. } = ty {
+        *ty = std::mem::replace(&mut **inner, Type::Infer(*span));
+        erase(ty);
+        return;
+    }
+    match ty {
+        Type::Named { generics: types, .. } | Type::Tuple(types, _) => types.iter_mut().for_each(erase),
+        Type::Array(inner, ..) | Type::Slice(inner, _) | Type::Option(inner, _) => erase(inner),
+        Type::Result(ok, err, _) => {
+            erase(ok);
+            erase(err);
+        }
+        Type::Function { params, return_type, .. } => {
+            params.iter_mut().for_each(erase);
+            erase(return_type);
+        }
+        _ => {}
+    }
+}
+
+impl VisitorMut for ReferenceEraser {
+    fn visit_function_mut(&mut self, function: &mut Function) {
+        if let Some(ty) = &mut function.return_type {
+            erase(ty);
+        }
+        walk_function_mut(self, function);
+    }
+
+    fn visit_param_mut(&mut self, param: &mut Param) {
+        erase(&mut param.ty);
+        walk_param_mut(self, param);
+    }
+
+    fn visit_stmt_mut(&mut self, stmt: &mut Stmt) {
+        if let Stmt::Let { ty: Some(ty), .. } = stmt {
+            erase(ty);
+        }
+        walk_stmt_mut(self, stmt);
+    }
+
+    fn visit_expr_mut(&mut self, expr: &mut Expr) {
+        walk_expr_mut(self, expr);
+        if let Expr::Ref { value, span, .. } = expr {
+            *expr = std::mem::replace(&mut **value, Expr::Error(*span));
+        }
+    }
+}
+
+impl TypedVisitorMut for ReferenceEraser {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{compile_with, compile_with_options, diagnostics, edition, eval_snippet, CompileOptions, CompileTarget, Lexer, Parser};
+
+    fn check_source(source: &str) -> KainResult<()> {
+        let tokens = Lexer::with_edition(source, Edition::V0_4).tokenize()?;
+        let program = Parser::new(&tokens).parse()?;
+        check(&program, Edition::V0_4)
+    }
+
+    /// The message, where it points, and where and what its note points at
+    fn conflict(source: &str) -> (String, usize, usize, String) {
+        match check_source(source).unwrap_err() {
+            KainError::Borrow { message, span, related: Some((related, note)) } => (message, span.start, related.start, note),
+            err => panic!("expected a borrow error, got {}", err),
+        }
+    }
+
+    #[test]
+    fn test_conflicts_point_at_both_sides() {
+        let point = "struct Point:\n    x: Int\n    y: Int\n\nfn take(p: Point) -> Int:\n    return p.x\n\n";
+        let at = |source: &str, needle: &str| source.find(needle).unwrap();
+
+        let source = format!("{}fn main():\n    let a = Point {{ x: 1, y: 2 }}\n    let b = a\n    println(a.x)\n", point);
+        let (message, span, related, note) = conflict(&source);
+        assert_eq!(message, "use of moved value `a`");
+        assert_eq!((span, related), (at(&source, "a.x)"), at(&source, "= a\n") + 2));
+        assert_eq!(note, "`a` was moved here");
+
+        let source = format!("{}fn swap(a: &mut Point, b: &mut Point):\n    let t = a.x\n    a.x = b.x\n    b.x = t\n\nfn main():\n    let mut p = Point {{ x: 1, y: 2 }}\n    swap(&mut p, &mut p)\n", point);
+        let (message, span, related, note) = conflict(&source);
+        assert_eq!(message, "cannot borrow `p` as mutable more than once at a time");
+        assert_eq!((span, related), (source.rfind("&mut p").unwrap(), at(&source, "&mut p,")));
+        assert_eq!(note, "`p` is first borrowed here");
+
+        let source = format!("{}fn main():\n    let mut p = Point {{ x: 1, y: 2 }}\n    let r = &p\n    let m = &mut p\n    println(r.x)\n", point);
+        let (message, span, related, _) = conflict(&source);
+        assert_eq!(message, "cannot borrow `p` as mutable because it is also borrowed as immutable");
+        assert_eq!((span, related), (at(&source, "&mut p"), at(&source, "&p")));
+
+        let source = format!("{}fn origin() -> &Point:\n    let p = Point {{ x: 0, y: 0 }}\n    return &p\n", point);
+        let (message, span, related, note) = conflict(&source);
+        assert_eq!(message, "cannot return a reference to local `p`");
+        assert_eq!((span, related), (at(&source, "&p"), at(&source, "let p") + 4));
+        assert_eq!(note, "`p` is declared here");
+
+        let source = format!("{}fn main():\n    let p = Point {{ x: 1, y: 2 }}\n    for i in 0..3:\n        println(take(p))\n", point);
+        let (message, span, related, note) = conflict(&source);
+        assert_eq!(message, "use of moved value `p`");
+        assert_eq!(span, related);
+        assert_eq!(note, "`p` was moved here, in the previous iteration of the loop");
+
+        // Reassigned values, borrows past their last use, copies, references
+        // that outlive no local, and moves on paths that return are fine
+        check_source(&format!(
+            "{}fn first(points: &Array<Point>) -> &Point:\n    return &points[0]\n\nfn main():\n    var p = Point {{ x: 1, y: 2 }}\n    let n = take(p)\n    p = Point {{ x: n, y: 0 }}\n    let r = &mut p\n    r.x = 3\n    let q = &p\n    println(q.x)\n    let m = n\n    println(n, m)\n    let all = [p]\n    println(len(all), len(all))\n    let head = first(&all)\n    println(head.x)\n    if n > 0:\n        let gone = all\n        return\n    println(len(all))\n",
+            point
+        ))
+        .unwrap();
+    }
+
+    #[test]
+    fn test_ownership_is_enforced_from_edition_0_4() {
+        let source = "struct Account:\n    balance: Int\n\nfn deposit(account: &mut Account, amount: Int):\n    account.balance = account.balance + amount\n\nfn close(account: Account) -> Int:\n    return account.balance\n\nfn main():\n    let mut savings = Account { balance: 10 }\n    deposit(&mut savings, 5)\n    let total = close(savings)\n    println(total + savings.balance)\n";
+        let options = CompileOptions { edition: edition::Edition::V0_4, ..Default::default() };
+        let err = compile_with_options(source, CompileTarget::Js, &options).unwrap_err();
+        assert!(err.to_string().contains("use of moved value `savings`"), "{}", err);
+        let rendered = diagnostics::Diagnostics::new(source, "bank.kn").format_error(&err);
+        assert!(rendered.contains("`savings` was moved here") && rendered.contains("close(savings)"), "{}", rendered);
+
+        // Older editions still run it, and warn
+        let result = eval_snippet(source, &CompileOptions::default());
+        assert!(result.diagnostics.is_empty(), "{:?}", result.diagnostics);
+        assert_eq!(result.stdout.trim_end(), "30");
+        let output = compile_with(source, CompileTarget::Js, &CompileOptions::default()).unwrap();
+        assert_eq!(output.warnings.len(), 1);
+        assert!(output.warnings[0].message.contains("use of moved value `savings` (rejected from edition 0.4)"));
+
+        // Backends other than Rust get the values references refer to
+        let fixed = source.replace("println(total + savings.balance)", "println(total)");
+        let js = String::from_utf8(compile_with_options(&fixed, CompileTarget::Js, &options).unwrap()).unwrap();
+        assert!(js.contains("deposit(savings, 5)"), "{}", js);
+        let result = eval_snippet(&fixed, &options);
+        assert!(result.diagnostics.is_empty(), "{:?}", result.diagnostics);
+        assert_eq!(result.stdout.trim_end(), "15");
+    }
+}
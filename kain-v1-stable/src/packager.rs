@@ -1,10 +1,14 @@
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::fs;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use flate2::read::GzDecoder;
 use tar::Archive;
 use crate::error::{KainError, KainResult};
+use crate::edition::Edition;
+use crate::incremental;
+use crate::toolchain::{Tool, Toolchain, ToolchainConfig};
+use crate::CrateType;
 
 const REGISTRY_URL: &str = "https://greeble.co/KAIN/index.json";
 
@@ -15,20 +19,108 @@ pub struct PackageManifest {
     pub build: BuildConfig,
     #[serde(default)]
     pub dependencies: HashMap<String, String>,
+    /// Named build settings, `[profile.<name>]`, picked with `kain build --profile`
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub profile: HashMap<String, Profile>,
+    /// Native tools for the llvm target and wasm-opt, see [`crate::toolchain`]
+    #[serde(default, skip_serializing_if = "ToolchainConfig::is_empty")]
+    pub toolchain: ToolchainConfig,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct BuildConfig {
     #[serde(default = "default_entry")]
     pub entry: PathBuf,
+    /// Where builds go, in a directory per profile holding one per target; `target` by default
     #[serde(default = "default_output")]
     pub output: PathBuf,
     #[serde(default)]
     pub targets: Vec<String>,
+    /// Always build reproducibly, as if `--deterministic` were passed
+    #[serde(default)]
+    pub deterministic: bool,
+    /// Let comptime code do IO, as if `--allow-comptime-io` were passed
+    #[serde(default)]
+    pub allow_comptime_io: bool,
+    /// Features always enabled for `@cfg(feature = "...")`, added to `--features`
+    #[serde(default)]
+    pub features: Vec<String>,
+    /// SQL schema file for `query!`, as if `--schema` were passed
+    #[serde(default)]
+    pub schema: Option<PathBuf>,
+    /// Build script whose hooks run around the build; `build.kn` when it exists
+    #[serde(default)]
+    pub script: Option<PathBuf>,
+    /// Columns a tab in indentation counts for, unless `--tab-width` is passed
+    #[serde(default)]
+    pub tab_width: Option<usize>,
+}
+
+/// Settings a build profile layers over `[build]`
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Profile {
+    /// Output directory, instead of `<[build].output>/<profile>`; targets still get a subdirectory each
+    #[serde(default)]
+    pub output: Option<PathBuf>,
+    /// Targets built when `--targets` is not given, instead of `[build].targets`
+    #[serde(default)]
+    pub targets: Option<Vec<String>>,
+    /// Build reproducibly, as if `--deterministic` were passed
+    #[serde(default)]
+    pub deterministic: bool,
+    /// Features enabled on top of `[build].features`
+    #[serde(default)]
+    pub features: Vec<String>,
+    /// Use WASM SIMD instructions, as if `--enable-simd` were passed
+    #[serde(default)]
+    pub simd: bool,
+    /// Shrink wasm output with `wasm-opt -Oz`, see [`crate::toolchain::wasm_opt`]
+    #[serde(default)]
+    pub wasm_opt: bool,
+    /// Flags passed to clang when the llvm target is linked into an executable or shared library
+    #[serde(default)]
+    pub link_flags: Vec<String>,
+    /// Settings for one target, `[profile.<name>.target.<target>]`
+    #[serde(default)]
+    pub target: HashMap<String, TargetProfile>,
+}
+
+/// Settings a profile applies to one target
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct TargetProfile {
+    /// Output directory for this target, instead of its subdirectory of the profile's
+    #[serde(default)]
+    pub output: Option<PathBuf>,
+    /// Flags passed to clang after the profile's `link_flags`
+    #[serde(default)]
+    pub link_flags: Vec<String>,
+    /// UE5 plugin whose `Shaders` directory hlsl and usf output is copied to
+    #[serde(default)]
+    pub plugin: Option<String>,
+    /// Directory holding the UE5 plugins (defaults to `src-plugins`)
+    #[serde(default)]
+    pub plugins_dir: Option<PathBuf>,
+}
+
+impl Profile {
+    pub fn for_target(&self, target: &str) -> TargetProfile {
+        self.target.get(target).cloned().unwrap_or_default()
+    }
+
+    /// Turn on what the profile sets in `options`: determinism, SIMD and its features
+    pub fn apply(&self, options: &mut crate::CompileOptions) {
+        options.deterministic |= self.deterministic;
+        options.simd |= self.simd;
+        for feature in &self.features {
+            if !options.features.contains(feature) {
+                options.features.push(feature.clone());
+            }
+        }
+    }
 }
 
 fn default_entry() -> PathBuf { PathBuf::from("src/main.kn") }
-fn default_output() -> PathBuf { PathBuf::from("dist") }
+fn default_output() -> PathBuf { PathBuf::from("target") }
 
 impl Default for BuildConfig {
     fn default() -> Self {
@@ -36,6 +128,12 @@ impl Default for BuildConfig {
             entry: default_entry(),
             output: default_output(),
             targets: vec!["wasm".to_string()],
+            deterministic: false,
+            allow_comptime_io: false,
+            features: vec![],
+            schema: None,
+            script: None,
+            tab_width: None,
         }
     }
 }
@@ -48,6 +146,12 @@ pub struct PackageInfo {
     pub authors: Vec<String>,
     #[serde(default)]
     pub description: Option<String>,
+    /// Edition the sources are written for; `0.1` when absent
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub language_version: Option<String>,
+    /// Compiler version the project builds with, which `kain` hands commands to, see [`crate::release`]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub compiler: Option<String>,
 }
 
 // Registry Structures
@@ -75,9 +179,51 @@ impl PackageManifest {
                 version: "0.1.0".to_string(),
                 authors: vec![],
                 description: None,
+                language_version: Some(Edition::LATEST.to_string()),
+                compiler: None,
             },
             build: BuildConfig::default(),
             dependencies: HashMap::new(),
+            profile: HashMap::new(),
+            toolchain: ToolchainConfig::default(),
+        }
+    }
+
+    /// The edition `language_version` names, if it names one
+    pub fn edition(&self) -> KainResult<Option<Edition>> {
+        let Some(version) = &self.package.language_version else {
+            return Ok(None);
+        };
+        Edition::parse(version).map(Some).ok_or_else(|| {
+            KainError::runtime(format!("Unknown language_version '{}' in KAIN.toml, expected 0.1, 0.2, 0.3, 0.4 or 0.5", version))
+        })
+    }
+
+    /// The profile called `name`: one declared under `[profile.<name>]`, or the
+    /// built-in `debug` (no changes) and `release` (deterministic, with `wasm_opt`)
+    pub fn profile(&self, name: &str) -> KainResult<Profile> {
+        if let Some(profile) = self.profile.get(name) {
+            return Ok(profile.clone());
+        }
+        match name {
+            "debug" => Ok(Profile::default()),
+            "release" => Ok(Profile { deterministic: true, wasm_opt: true, ..Profile::default() }),
+            _ => {
+                let mut known: Vec<&str> = self.profile.keys().map(String::as_str).collect();
+                known.extend(["debug", "release"]);
+                known.sort_unstable();
+                known.dedup();
+                Err(KainError::runtime(format!("Unknown profile '{}' in KAIN.toml, expected one of: {}", name, known.join(", "))))
+            }
+        }
+    }
+
+    /// The directory builds with the profile `name` write into, under the
+    /// project in `root`: the profile's `output`, else `<[build].output>/<name>`
+    pub fn profile_dir(&self, root: &Path, name: &str, profile: &Profile) -> PathBuf {
+        match &profile.output {
+            Some(dir) => root.join(dir),
+            None => root.join(&self.build.output).join(name),
         }
     }
 }
@@ -107,7 +253,7 @@ pub fn init_project(path: &PathBuf, name: Option<String>) -> KainResult<()> {
 
     // Create main.kn
     let main_src = format!(r#"
-# {} - Main Entry Point
+// {} - Main Entry Point
 
 fn main():
     println("Hello, KAIN World!")
@@ -260,29 +406,70 @@ fn install_package(name: &str, version: &str, url: &str) -> KainResult<()> {
     Ok(())
 }
 
-/// Build all targets specified in KAIN.toml
-pub fn build_project(target_overrides: Option<Vec<String>>) -> KainResult<()> {
+/// Build all targets specified in KAIN.toml, with the settings of `profile` (`debug` when `None`)
+pub fn build_project(target_overrides: Option<Vec<String>>, profile: Option<&str>, mut options: crate::CompileOptions) -> KainResult<()> {
     let cwd = std::env::current_dir().unwrap_or_else(|_| PathBuf::from("."));
     let manifest = load_manifest(&cwd)?;
+    let profile_name = profile.unwrap_or("debug");
+    let profile = manifest.profile(profile_name)?;
+    options.profile = Some(profile_name.to_string());
+    options.deterministic |= manifest.build.deterministic;
+    options.allow_comptime_io |= manifest.build.allow_comptime_io;
+    if let Some(edition) = manifest.edition()? {
+        options.edition = edition;
+    }
+    options.tab_width = options.tab_width.or(manifest.build.tab_width);
+    if let (None, Some(path)) = (&options.sql_schema, &manifest.build.schema) {
+        let ddl = std::fs::read_to_string(cwd.join(path)).map_err(|e| {
+            KainError::runtime(format!("Failed to read schema {}: {}", path.display(), e))
+        })?;
+        options.sql_schema = Some(ddl);
+    }
+    for feature in &manifest.build.features {
+        if !options.features.contains(feature) {
+            options.features.push(feature.clone());
+        }
+    }
+    profile.apply(&mut options);
     
-    // Use overrides or manifest targets
-    let targets = target_overrides.unwrap_or_else(|| manifest.build.targets.clone());
+    // Use overrides, then the profile's targets, then the manifest's
+    let targets = target_overrides
+        .or_else(|| profile.targets.clone())
+        .unwrap_or_else(|| manifest.build.targets.clone());
     
     if targets.is_empty() {
         println!(" No targets specified in KAIN.toml [build.targets]");
         println!(" Defaulting to wasm");
-        return build_targets(&manifest, &cwd, &["wasm".to_string()]);
+        return build_targets(&manifest, &cwd, &["wasm".to_string()], profile_name, &profile, &options);
     }
     
-    build_targets(&manifest, &cwd, &targets)
+    build_targets(&manifest, &cwd, &targets, profile_name, &profile, &options)
 }
 
-fn build_targets(manifest: &PackageManifest, cwd: &PathBuf, targets: &[String]) -> KainResult<()> {
-    use crate::{compile, CompileTarget};
+fn build_targets(manifest: &PackageManifest, cwd: &PathBuf, targets: &[String], profile_name: &str, profile: &Profile, options: &crate::CompileOptions) -> KainResult<()> {
+    let output_dir = manifest.profile_dir(cwd, profile_name, profile);
+    let root = cwd.join(&manifest.build.output);
+    if output_dir.starts_with(&root) {
+        create_output_root(&root).map_err(KainError::Io)?;
+    }
     
-    // Ensure output directory exists
-    let output_dir = cwd.join(&manifest.build.output);
-    fs::create_dir_all(&output_dir).map_err(|e| KainError::Io(e))?;
+    // Build script hooks, which may generate the sources read below
+    let script_path = manifest.build.script.clone().unwrap_or_else(|| PathBuf::from("build.kn"));
+    let mut script = if manifest.build.script.is_some() || cwd.join(&script_path).exists() {
+        let context = crate::runtime::BuildContext {
+            profile: profile_name.to_string(),
+            targets: targets.to_vec(),
+            out_dir: output_dir.display().to_string(),
+            target: None,
+            output: None,
+        };
+        let mut script = crate::buildscript::BuildScript::load(&cwd.join(&script_path), context, options)?;
+        println!(" Running {} pre_build", script_path.display());
+        script.pre_build()?;
+        Some(script)
+    } else {
+        None
+    };
     
     // Read source file
     let entry_path = cwd.join(&manifest.build.entry);
@@ -292,56 +479,522 @@ fn build_targets(manifest: &PackageManifest, cwd: &PathBuf, targets: &[String])
         )));
     }
     
-    let source = fs::read_to_string(&entry_path).map_err(|e| KainError::Io(e))?;
+    let file = crate::vfs::read(&entry_path).map_err(KainError::Io)?;
+    crate::crash::set_input(file.clone(), options);
+    let source = file.text.clone();
     let file_stem = entry_path.file_stem()
         .and_then(|s| s.to_str())
         .unwrap_or("output");
     
     println!(" Building {} v{}", manifest.package.name, manifest.package.version);
     println!(" Entry: {}", manifest.build.entry.display());
-    println!(" Output: {}/", manifest.build.output.display());
+    println!(" Profile: {}", profile_name);
+    println!(" Output: {}/", output_dir.strip_prefix(cwd).unwrap_or(&output_dir).display());
     println!();
+
+    let filename = manifest.build.entry.to_string_lossy();
+    let diag = crate::diagnostics::Diagnostics::new(&source, &filename);
+    for lint in crate::edition::migration_lints(&source, options.edition) {
+        eprint!("{}", diag.format_warning(&lint));
+    }
+    
+    let outputs = compile_targets(&source, &output_dir, file_stem, targets, profile, options)?;
+    if let Some(script) = &mut script {
+        for (target, path) in &outputs {
+            script.post_build(target, path)?;
+        }
+    }
     
+    println!();
+    println!(" Build complete!");
+    Ok(())
+}
+
+/// Compile `source` for each of `targets`, writing `stem` with each target's
+/// extension to its [`target_dir`] in `output_dir`. The
+/// program is parsed, type checked and monomorphized once and shared by every
+/// target whose lowering is the same. A target whose [`incremental::Record`]
+/// shows nothing it was built from changed is not built again. Returns the
+/// targets that built or were up to date, with their output paths.
+pub fn compile_targets(source: &str, output_dir: &Path, stem: &str, targets: &[String], profile: &Profile, options: &crate::CompileOptions) -> KainResult<Vec<(String, PathBuf)>> {
+    use crate::codegen::backend;
+    use crate::{CompileSession, CompileTarget};
+
+    enum Backend {
+        BuiltIn(CompileTarget),
+        Plugin(std::sync::Arc<dyn backend::CodegenBackend>),
+    }
+
+    // Parsed when a target has to be built
+    let mut session = None;
+    let mut outputs = Vec::new();
     for target_str in targets {
-        let target = parse_target(target_str)?;
-        let ext = target_extension(target);
-        let out_path = output_dir.join(file_stem).with_extension(ext);
+        // Built-in targets first, then backends registered by plugins
+        let (ext, backend) = match (CompileTarget::parse(target_str), backend::find(target_str)) {
+            (Some(target), _) => (target_extension(target).to_string(), Backend::BuiltIn(target)),
+            (None, Some(plugin)) => (plugin.file_extension().to_string(), Backend::Plugin(plugin)),
+            (None, None) => return Err(KainError::runtime(format!("Unknown target: {}", target_str))),
+        };
+        let settings = profile.for_target(target_str);
+        let dir = target_dir(output_dir, target_str, &settings);
+        fs::create_dir_all(&dir).map_err(KainError::Io)?;
+        let out_path = dir.join(stem).with_extension(ext);
+
+        let key = incremental::options_key(target_str, options, profile);
+        let record_path = incremental::Record::path(output_dir, target_str, stem);
+        let linked = target_str != "llvm" || linked_path(&out_path, options.crate_type).exists();
+        if linked && incremental::Record::load(&record_path).is_some_and(|record| record.is_current(Path::new(""), source, key, &out_path)) {
+            println!(" [{}] -> {} (up to date)", target_str, out_path.display());
+            outputs.push((target_str.clone(), out_path));
+            continue;
+        }
+
+        let session = match &mut session {
+            Some(session) => session,
+            None => session.insert(CompileSession::new(source, options.clone())?),
+        };
+        let result = match backend {
+            Backend::BuiltIn(target) => {
+                crate::crash::set_target(target);
+                session.compile(target)
+            }
+            Backend::Plugin(plugin) => session.compile_with_backend(plugin.as_ref()),
+        };
         
-        match compile(&source, target) {
+        match result {
             Ok(output) => {
-                fs::write(&out_path, &output).map_err(|e| KainError::Io(e))?;
+                let output = match target_str.as_str() {
+                    "wasm" if profile.wasm_opt => optimize_wasm(output, &out_path, options.simd),
+                    _ => output,
+                };
+                crate::write_output(&out_path, &output, options).map_err(KainError::Io)?;
                 println!(" [{}] -> {} ({} bytes)", target_str, out_path.display(), output.len());
+                if target_str == "wasm" && options.size_report {
+                    match crate::codegen::wasm_size::report(&output) {
+                        Ok(report) => print!("{}", report),
+                        Err(e) => eprintln!(" [{}] No size report: {}", target_str, e),
+                    }
+                }
+                if target_str == "llvm" && options.crate_type.is_library() {
+                    let header_path = out_path.with_extension("h");
+                    crate::write_output(&header_path, session.header()?.as_bytes(), options).map_err(KainError::Io)?;
+                    println!(" [{}] -> {}", target_str, header_path.display());
+                }
+                finish_target(target_str, &out_path, profile, &settings, options.crate_type)?;
+                let dependencies = incremental::Handle::default().dependencies(session.program(), options.edition);
+                if let Err(e) = incremental::Record::new(source, dependencies, key, &output).save(&record_path) {
+                    eprintln!(" [{}] Could not record the build, so the next one starts over: {}", target_str, e);
+                }
+                outputs.push((target_str.clone(), out_path));
             }
             Err(e) => {
                 eprintln!(" [{}] FAILED: {}", target_str, e);
             }
         }
     }
-    
-    println!();
-    println!(" Build complete!");
+    Ok(outputs)
+}
+
+/// Where `target`'s output goes in a profile's directory: a subdirectory named
+/// for the target, unless the profile gives the target a directory of its own
+pub fn target_dir(profile_dir: &Path, target: &str, settings: &TargetProfile) -> PathBuf {
+    settings.output.clone().unwrap_or_else(|| profile_dir.join(target))
+}
+
+/// The extension of `target`'s output, for built-in targets and those plugins register
+pub(crate) fn output_extension(target: &str) -> Option<String> {
+    match crate::CompileTarget::parse(target) {
+        Some(target) => Some(target_extension(target).to_string()),
+        None => crate::codegen::backend::find(target).map(|plugin| plugin.file_extension().to_string()),
+    }
+}
+
+/// Marks a directory as build output, so `kain clean` knows it may delete it
+/// and backup tools know to skip it (<https://bford.info/cachedir/>)
+const OUTPUT_TAG: &str = "CACHEDIR.TAG";
+const OUTPUT_TAG_TEXT: &str = "Signature: 8a477f597d28d172789f06886806bc55\n# This directory holds build output from kain, which `kain clean` deletes.\n";
+
+/// Create the output directory `root` and tag it as build output
+pub fn create_output_root(root: &Path) -> std::io::Result<()> {
+    fs::create_dir_all(root)?;
+    let tag = root.join(OUTPUT_TAG);
+    if !tag.exists() {
+        fs::write(tag, OUTPUT_TAG_TEXT)?;
+    }
     Ok(())
 }
 
-fn parse_target(s: &str) -> KainResult<crate::CompileTarget> {
-    use crate::CompileTarget;
-    match s.to_lowercase().as_str() {
-        "wasm" | "w" => Ok(CompileTarget::Wasm),
-        "llvm" | "native" | "n" => Ok(CompileTarget::Llvm),
-        "spirv" | "gpu" | "s" => Ok(CompileTarget::SpirV),
-        "hlsl" | "h" => Ok(CompileTarget::Hlsl),
-        "usf" | "ue5" => Ok(CompileTarget::Usf),
-        "js" | "javascript" => Ok(CompileTarget::Js),
-        "rust" | "rs" => Ok(CompileTarget::Rust),
-        "hybrid" => Ok(CompileTarget::Hybrid),
-        _ => Err(KainError::runtime(format!("Unknown target: {}", s)))
+/// Where a file compiled on its own (`kain file.kn`, `kain build file.kn`)
+/// writes its outputs with the profile `profile_name`: the profile's directory
+/// of the project the file is in, or under a `target` directory next to the
+/// file when it is in no project
+pub fn file_output_dir(input: &Path, profile_name: &str) -> KainResult<PathBuf> {
+    let (dir, project) = file_project(input);
+    let (root, profile_dir) = match project {
+        Some((project, manifest)) => {
+            let profile = manifest.profile(profile_name)?;
+            (project.join(&manifest.build.output), manifest.profile_dir(&project, profile_name, &profile))
+        }
+        None => (dir.join(default_output()), dir.join(default_output()).join(profile_name)),
+    };
+    if profile_dir.starts_with(&root) {
+        create_output_root(&root).map_err(KainError::Io)?;
+    }
+    Ok(profile_dir)
+}
+
+/// The profile `profile_name` for a file compiled on its own: the one the
+/// project the file is in declares, or a built-in one when it is in no project
+pub fn file_profile(input: &Path, profile_name: &str) -> KainResult<Profile> {
+    match file_project(input).1 {
+        Some((_, manifest)) => manifest.profile(profile_name),
+        None => PackageManifest::default("").profile(profile_name).map_err(|_| {
+            KainError::runtime(format!("Unknown profile '{}', outside a project only debug and release exist", profile_name))
+        }),
+    }
+}
+
+/// The edition `input` is read under: its project's `language_version`, or
+/// `edition` when it is in no project or the project doesn't say
+pub fn file_edition(input: &Path, edition: Edition) -> KainResult<Edition> {
+    match file_project(input).1 {
+        Some((_, manifest)) => Ok(manifest.edition()?.unwrap_or(edition)),
+        None => Ok(edition),
+    }
+}
+
+/// The directory `input` is in, and the project that directory is part of,
+/// if there is one whose manifest loads
+fn file_project(input: &Path) -> (PathBuf, Option<(PathBuf, PackageManifest)>) {
+    let dir = input.parent().filter(|dir| !dir.as_os_str().is_empty()).unwrap_or(Path::new("."));
+    let dir = dir.canonicalize().unwrap_or_else(|_| dir.to_path_buf());
+    let project = dir.ancestors().find(|dir| dir.join("KAIN.toml").exists()).map(Path::to_path_buf);
+    let project = project.and_then(|project| load_manifest(&project).ok().map(|manifest| (project, manifest)));
+    (dir, project)
+}
+
+/// Delete what builds in the current directory wrote. In a project that is
+/// the output directory, or with `profile` just that profile's, and the
+/// outputs of targets a profile sends somewhere else. Outside a project it is
+/// the `target` directory, if kain made it.
+pub fn clean(profile: Option<&str>, crate_type: CrateType) -> KainResult<()> {
+    let cwd = std::env::current_dir().unwrap_or_else(|_| PathBuf::from("."));
+    if !cwd.join("KAIN.toml").exists() {
+        let root = cwd.join(default_output());
+        if !root.exists() {
+            println!(" Nothing to clean");
+            return Ok(());
+        }
+        // Cargo tags its `target` the same way, with its own name in the tag
+        let ours = fs::read_to_string(root.join(OUTPUT_TAG)).is_ok_and(|tag| tag.contains("kain"));
+        if !ours {
+            return Err(KainError::runtime(format!("{} was not made by kain; leaving it alone", root.display())));
+        }
+        let dir = profile.map_or_else(|| root.clone(), |name| root.join(name));
+        if !dir.exists() {
+            println!(" Nothing to clean");
+        }
+        return remove_output(&dir);
+    }
+
+    let manifest = load_manifest(&cwd)?;
+    let root = cwd.join(&manifest.build.output);
+    if cwd.starts_with(&root) || cwd.join(&manifest.build.entry).starts_with(&root) {
+        return Err(KainError::runtime(format!("Refusing to delete {}: [build].output holds the project's sources", root.display())));
+    }
+    let names: Vec<String> = match profile {
+        Some(name) => vec![name.to_string()],
+        None => {
+            let mut names: Vec<String> = manifest.profile.keys().cloned().collect();
+            names.extend(["debug".to_string(), "release".to_string()]);
+            names.sort_unstable();
+            names.dedup();
+            names
+        }
+    };
+    let stem = manifest.build.entry.file_stem().and_then(|s| s.to_str()).unwrap_or("output");
+    let mut removed = false;
+    for name in &names {
+        let settings = manifest.profile(name)?;
+        let profile_dir = manifest.profile_dir(&cwd, name, &settings);
+        if profile.is_some() && profile_dir.starts_with(&root) {
+            removed |= profile_dir.exists();
+            remove_output(&profile_dir)?;
+        }
+        // Directories a profile names may hold other files, so only its outputs go
+        let targets = settings.targets.clone().unwrap_or_else(|| manifest.build.targets.clone());
+        for target in &targets {
+            let target_settings = settings.for_target(target);
+            let dir = cwd.join(target_dir(&profile_dir, target, &target_settings));
+            if dir.starts_with(&root) {
+                continue;
+            }
+            let Some(ext) = output_extension(target) else { continue };
+            let output = dir.join(stem).with_extension(ext);
+            let mut files = match target.as_str() {
+                "wasm" => vec![output.with_extension("unopt.wasm")],
+                "llvm" => vec![linked_path(&output, crate_type), object_path(&output), output.with_extension("h")],
+                _ => Vec::new(),
+            };
+            files.push(output);
+            for file in files.iter().filter(|file| file.exists()) {
+                fs::remove_file(file).map_err(KainError::Io)?;
+                println!(" Removed {}", file.display());
+                removed = true;
+            }
+        }
+    }
+    if profile.is_none() && root.exists() {
+        removed = true;
+        remove_output(&root)?;
+    }
+    if !removed {
+        println!(" Nothing to clean");
+    }
+    Ok(())
+}
+
+fn remove_output(dir: &Path) -> KainResult<()> {
+    if !dir.exists() {
+        return Ok(());
+    }
+    fs::remove_dir_all(dir).map_err(|e| KainError::runtime(format!("Failed to delete {}: {}", dir.display(), e)))?;
+    println!(" Removed {}", dir.display());
+    Ok(())
+}
+
+/// `wasm` shrunk by `wasm-opt -Oz`, or as it was if wasm-opt is missing or
+/// fails, which is reported. `out_path` is where the module is written.
+pub fn optimize_wasm(wasm: Vec<u8>, out_path: &Path, simd: bool) -> Vec<u8> {
+    match run_wasm_opt(crate::toolchain::wasm_opt(), &wasm, out_path, simd) {
+        Ok(optimized) => {
+            println!(" [wasm] wasm-opt -Oz: {} -> {} bytes", wasm.len(), optimized.len());
+            optimized
+        }
+        Err(e) => {
+            eprintln!(" [wasm] wasm-opt skipped, left the module unoptimized: {}", e);
+            wasm
+        }
+    }
+}
+
+/// `wasm` after `tool` optimized it into `out_path`
+fn run_wasm_opt(tool: KainResult<crate::toolchain::Tool>, wasm: &[u8], out_path: &Path, simd: bool) -> KainResult<Vec<u8>> {
+    let tool = tool?;
+    let input = out_path.with_extension("unopt.wasm");
+    fs::write(&input, wasm).map_err(KainError::Io)?;
+    let mut cmd = tool.command();
+    cmd.arg("-Oz").arg(&input).arg("-o").arg(out_path);
+    if simd {
+        cmd.arg("--enable-simd");
+    }
+    let status = cmd.status();
+    let _ = fs::remove_file(&input);
+    match status {
+        Ok(status) if status.success() => fs::read(out_path).map_err(KainError::Io),
+        Ok(status) => Err(KainError::runtime(format!("{} exited with {}", tool, status))),
+        Err(e) => Err(KainError::runtime(format!("Failed to run {}: {}", tool, e))),
+    }
+}
+
+/// What a target's output needs after it is written: linking for llvm, into
+/// an executable or the library `crate_type` asks for, and copying into the
+/// UE5 plugin the profile names for shaders
+fn finish_target(target_str: &str, out_path: &Path, profile: &Profile, settings: &TargetProfile, crate_type: CrateType) -> KainResult<()> {
+    if target_str == "llvm" {
+        let linked = linked_path(out_path, crate_type);
+        let flags: Vec<String> = profile.link_flags.iter().chain(&settings.link_flags).cloned().collect();
+        match link_crate(out_path, &linked, crate_type, &flags) {
+            Ok(()) => println!(" [{}] -> {}", target_str, linked.display()),
+            Err(e) => eprintln!(" [{}] FAILED, left the IR unlinked: {}", target_str, e),
+        }
+    }
+    if let (Some(plugin), "hlsl" | "usf") = (&settings.plugin, target_str) {
+        let shaders = settings.plugins_dir.clone().unwrap_or_else(|| PathBuf::from("src-plugins")).join(plugin).join("Shaders");
+        fs::create_dir_all(&shaders).map_err(KainError::Io)?;
+        let copy = shaders.join(out_path.file_name().unwrap_or_default());
+        fs::copy(out_path, &copy).map_err(KainError::Io)?;
+        println!(" [{}] -> {}", target_str, copy.display());
+    }
+    Ok(())
+}
+
+/// The C runtime every llvm build links against, carried in the compiler so
+/// an installed `kain` needs no source tree next to it
+pub(crate) const RUNTIME_C: &str = include_str!("../runtime/KAIN_runtime.c");
+
+/// Where compiled artifacts the compiler reuses between runs are kept:
+/// `KAIN_CACHE_DIR`, or `kain` under the platform's cache directory
+pub fn cache_dir() -> PathBuf {
+    if let Some(dir) = std::env::var_os("KAIN_CACHE_DIR") {
+        return PathBuf::from(dir);
+    }
+    let base = if cfg!(windows) {
+        std::env::var_os("LOCALAPPDATA").map(PathBuf::from)
+    } else if cfg!(target_os = "macos") {
+        std::env::var_os("HOME").map(|home| PathBuf::from(home).join("Library/Caches"))
+    } else {
+        std::env::var_os("XDG_CACHE_HOME")
+            .map(PathBuf::from)
+            .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".cache")))
+    };
+    base.unwrap_or_else(std::env::temp_dir).join("kain")
+}
+
+/// Compile the bundled C runtime with `cc`, adding `flags`. The source is
+/// extracted to a cache directory named for its contents, the compiler and
+/// the flags, so the object is built once and reused after that.
+fn compile_runtime(cc: &Tool, flags: &[&str]) -> KainResult<PathBuf> {
+    use std::hash::{Hash, Hasher};
+
+    let mut hasher = std::collections::hash_map::DefaultHasher::new();
+    RUNTIME_C.hash(&mut hasher);
+    cc.to_string().hash(&mut hasher);
+    flags.hash(&mut hasher);
+    let dir = cache_dir().join(format!("runtime-{}-{:016x}", crate::VERSION, hasher.finish()));
+    let object = object_path(&dir.join("KAIN_runtime"));
+    if object.exists() {
+        return Ok(object);
+    }
+
+    let source = dir.join("KAIN_runtime.c");
+    fs::create_dir_all(&dir).and_then(|_| fs::write(&source, RUNTIME_C)).map_err(|e| {
+        KainError::runtime(format!("Failed to extract the runtime library to {}: {}", dir.display(), e))
+    })?;
+    // Built under a temporary name, so a build running alongside never links half an object
+    let partial = object.with_extension(format!("{}.tmp", std::process::id()));
+    let mut cmd = cc.command();
+    cmd.arg("-c").arg("-O2").arg(&source).args(flags).arg("-o").arg(&partial);
+    if let Err(e) = run_tool(cmd, "Compiling the runtime library") {
+        let _ = fs::remove_file(&partial);
+        return Err(e);
     }
+    fs::rename(&partial, &object).map_err(KainError::Io)?;
+    Ok(object)
 }
 
-fn target_extension(target: crate::CompileTarget) -> &'static str {
+/// Run a toolchain command; `what` names the step when it fails
+fn run_tool(mut cmd: std::process::Command, what: &str) -> KainResult<()> {
+    crate::log::debug("toolchain", &format!("{:?}", cmd));
+    match cmd.status() {
+        Ok(status) if status.success() => Ok(()),
+        Ok(status) => Err(KainError::runtime(format!("{} failed ({}): {:?}", what, status, cmd))),
+        Err(e) => Err(KainError::runtime(format!("{} failed, could not run {:?}: {}", what, cmd.get_program(), e))),
+    }
+}
+
+/// `path` with the platform's object file extension
+fn object_path(path: &Path) -> PathBuf {
+    path.with_extension(if cfg!(windows) { "obj" } else { "o" })
+}
+
+/// Compile LLVM IR to an object file with the toolchain's C compiler
+fn compile_ir(toolchain: &Toolchain, ir_path: &Path, object: &Path, flags: &[&str]) -> KainResult<()> {
+    let mut cmd = toolchain.cc.command();
+    cmd.arg("-c")
+        .arg(ir_path)
+        .args(flags)
+        .arg("-o")
+        .arg(object)
+        .arg("-Wno-override-module")
+        .arg("-g"); // Debug info
+    run_tool(cmd, "Compiling the IR")
+}
+
+/// Libraries everything linked with the runtime needs
+fn link_system_libs(cmd: &mut std::process::Command) {
+    if cfg!(windows) {
+        cmd.arg("-llegacy_stdio_definitions");
+    } else {
+        cmd.arg("-pthread"); // Actors run on threads
+    }
+}
+
+/// Link LLVM IR into an executable, along with the bundled C runtime, using
+/// the tools [`Toolchain::discover`] finds
+pub fn link_executable(ir_path: &Path, exe_path: &Path, link_flags: &[String]) -> KainResult<()> {
+    let toolchain = Toolchain::discover()?;
+    let object = object_path(ir_path);
+    compile_ir(&toolchain, ir_path, &object, &[])?;
+    let runtime = compile_runtime(&toolchain.cc, &[])?;
+
+    let mut cmd = toolchain.linker.command();
+    cmd.arg(&object).arg(&runtime).arg("-o").arg(exe_path).arg("-g");
+    link_system_libs(&mut cmd);
+    cmd.args(link_flags);
+    run_tool(cmd, "Linking")
+}
+
+/// Where linking the IR at `ir_path` as `crate_type` puts the result, named the
+/// way the platform expects executables, objects and libraries to be
+pub fn linked_path(ir_path: &Path, crate_type: CrateType) -> PathBuf {
+    let ext = match (crate_type, cfg!(windows), cfg!(target_os = "macos")) {
+        (CrateType::Bin, true, _) => "exe",
+        (CrateType::Bin, false, _) => "",
+        (CrateType::Lib, true, _) => "obj",
+        (CrateType::Lib, false, _) => "o",
+        (CrateType::Staticlib, true, _) => "lib",
+        (CrateType::Staticlib, false, _) => "a",
+        (CrateType::Cdylib, true, _) => "dll",
+        (CrateType::Cdylib, false, true) => "dylib",
+        (CrateType::Cdylib, false, false) => "so",
+    };
+    let path = ir_path.with_extension(ext);
+    let stem = path.file_stem().and_then(|s| s.to_str()).unwrap_or_default();
+    // `-lname` finds libname.a and libname.so outside Windows
+    if matches!(crate_type, CrateType::Staticlib | CrateType::Cdylib) && !cfg!(windows) && !stem.starts_with("lib") {
+        path.with_file_name(format!("lib{}.{}", stem, ext))
+    } else {
+        path
+    }
+}
+
+/// Link LLVM IR as `crate_type`:
+///
+/// - `bin`: an executable, see [`link_executable`]
+/// - `lib`: an object file of the IR alone
+/// - `staticlib`: an archive of the IR and the C runtime
+/// - `cdylib`: a shared library of both, exporting only what the program exports
+pub fn link_crate(ir_path: &Path, out_path: &Path, crate_type: CrateType, link_flags: &[String]) -> KainResult<()> {
+    if crate_type == CrateType::Bin {
+        return link_executable(ir_path, out_path, link_flags);
+    }
+    let toolchain = Toolchain::discover()?;
+    match crate_type {
+        CrateType::Bin | CrateType::Lib => compile_ir(&toolchain, ir_path, out_path, &[]),
+        CrateType::Staticlib => {
+            let object = object_path(ir_path);
+            compile_ir(&toolchain, ir_path, &object, &[])?;
+            let runtime = compile_runtime(&toolchain.cc, &[])?;
+            let archiver = toolchain.archiver()?;
+            // Archiving adds to an existing file, which may hold members of an older build
+            let _ = fs::remove_file(out_path);
+            let mut cmd = archiver.command();
+            cmd.arg("rcs").arg(out_path).arg(&object).arg(&runtime);
+            run_tool(cmd, "Archiving")
+        }
+        CrateType::Cdylib => {
+            let pic: &[&str] = if cfg!(windows) { &[] } else { &["-fPIC"] };
+            let object = object_path(ir_path);
+            compile_ir(&toolchain, ir_path, &object, pic)?;
+            // The runtime's own functions stay inside the library
+            let runtime_flags: Vec<&str> = pic.iter().copied().chain(["-fvisibility=hidden"]).collect();
+            let runtime = compile_runtime(&toolchain.cc, &runtime_flags)?;
+
+            let mut cmd = toolchain.linker.command();
+            cmd.arg("-shared").args(pic).arg(&object).arg(&runtime).arg("-o").arg(out_path).arg("-g");
+            link_system_libs(&mut cmd);
+            cmd.args(link_flags);
+            run_tool(cmd, "Linking")
+        }
+    }
+}
+
+pub fn target_extension(target: crate::CompileTarget) -> &'static str {
     use crate::CompileTarget;
     match target {
         CompileTarget::Wasm => "wasm",
+        CompileTarget::Wat => "wat",
         CompileTarget::Llvm => "ll",
         CompileTarget::SpirV => "spv",
         CompileTarget::Hlsl => "hlsl",
@@ -353,3 +1006,116 @@ fn target_extension(target: crate::CompileTarget) -> &'static str {
     }
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::toolchain::{resolve_wasm_opt, ToolchainConfig};
+    use crate::CompileOptions;
+
+    #[cfg(unix)]
+    #[test]
+    fn test_release_profile_runs_wasm_opt() {
+        use std::os::unix::fs::PermissionsExt;
+        let dir = std::env::temp_dir().join(format!("kain-wasm-opt-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let dir = dir.canonicalize().unwrap();
+        // Stands in for wasm-opt: `-Oz <input> -o <output>` writes the output
+        let script = dir.join("wasm-opt");
+        fs::write(&script, "#!/bin/sh\nif [ \"$1\" = --version ]; then echo 'wasm-opt version 116'; exit 0; fi\nprintf optimized > \"$4\"\n").unwrap();
+        fs::set_permissions(&script, fs::Permissions::from_mode(0o755)).unwrap();
+
+        assert!(file_profile(&dir.join("game.kn"), "release").unwrap().wasm_opt);
+        assert!(!file_profile(&dir.join("game.kn"), "debug").unwrap().wasm_opt);
+        let config = ToolchainConfig { wasm_opt: Some(script.display().to_string()), ..Default::default() };
+        let out = dir.join("game.wasm");
+        assert_eq!(run_wasm_opt(resolve_wasm_opt(&config, |_| None), b"\0asm", &out, false).unwrap(), b"optimized");
+        assert!(!dir.join("game.unopt.wasm").exists());
+
+        // Without wasm-opt the build goes on with the module as it was, saying why
+        let config = ToolchainConfig { wasm_opt: Some("kain-no-such-wasm-opt".to_string()), ..Default::default() };
+        let err = run_wasm_opt(resolve_wasm_opt(&config, |_| None), b"\0asm", &out, false).unwrap_err().to_string();
+        assert!(err.contains("No usable wasm-opt") && err.contains("kain-no-such-wasm-opt"), "{}", err);
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_bundled_runtime_defines_the_llvm_externs() {
+        let externs = [
+            "print_i64", "print_f64", "print_bool", "print_str", "to_string", "str_concat", "clock_wrapper",
+            "KAIN_alloc", "KAIN_region_alloc", "KAIN_region_enter", "KAIN_region_exit", "rc_retain", "rc_release",
+            "string_new", "array_new", "array_push", "array_get", "array_set", "array_len", "mq_new", "mq_push",
+            "mq_pop", "KAIN_spawn", "KAIN_set_destructor", "KAIN_sleep", "deep_eq", "spawn_cube",
+        ];
+        for name in externs {
+            let defined = RUNTIME_C.lines().any(|line| !line.starts_with(' ') && !line.starts_with("static") && line.contains(&format!(" {}(", name)));
+            assert!(defined, "the C runtime does not define `{}`", name);
+        }
+        assert!(!RUNTIME_C.contains("int main("), "the runtime must link into libraries too");
+    }
+
+    #[test]
+    fn test_build_profiles_layer_over_the_manifest() {
+        let manifest: PackageManifest = toml::from_str("[package]\nname = \"game\"\nversion = \"0.1.0\"\n\n[profile.release]\ntargets = [\"wasm\", \"usf\"]\noutput = \"dist/release\"\nfeatures = [\"fast\"]\nlink_flags = [\"-O2\"]\n\n[profile.release.target.usf]\nplugin = \"Water\"\nplugins_dir = \"../plugins\"\n").unwrap();
+        let release = manifest.profile("release").unwrap();
+        assert_eq!(release.targets.as_deref(), Some(&["wasm".to_string(), "usf".to_string()][..]));
+        assert_eq!((release.features, release.link_flags), (vec!["fast".to_string()], vec!["-O2".to_string()]));
+        assert_eq!(release.target["usf"].plugin.as_deref(), Some("Water"));
+        assert!(!release.deterministic);
+
+        // debug and release exist without being declared
+        assert!(manifest.profile("debug").unwrap().targets.is_none());
+        let empty = PackageManifest::default("game");
+        assert!(empty.profile("release").unwrap().deterministic);
+        let err = manifest.profile("bench").unwrap_err().to_string();
+        assert!(err.contains("Unknown profile 'bench' in KAIN.toml, expected one of: debug, release"), "{}", err);
+    }
+
+    #[test]
+    fn test_outputs_go_under_the_profile_and_target_directories() {
+        let dir = std::env::temp_dir().join(format!("kain-outputs-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("game/src")).unwrap();
+        std::fs::write(dir.join("game/KAIN.toml"), "[package]\nname = \"game\"\nversion = \"0.1.0\"\n\n[profile.web]\noutput = \"www\"\n").unwrap();
+        let dir = dir.canonicalize().unwrap();
+
+        // A file in a project builds into the project's output directory, tagged for `kain clean`
+        let debug = file_output_dir(&dir.join("game/src/tool.kn"), "debug").unwrap();
+        assert_eq!(debug, dir.join("game/target/debug"));
+        assert!(dir.join("game/target/CACHEDIR.TAG").exists());
+        assert_eq!(file_output_dir(&dir.join("game/src/tool.kn"), "web").unwrap(), dir.join("game/www"));
+        assert!(file_output_dir(&dir.join("game/src/tool.kn"), "bench").is_err());
+
+        // Anywhere else, into `target` next to it
+        assert_eq!(file_output_dir(&dir.join("loose.kn"), "release").unwrap(), dir.join("target/release"));
+
+        // A file built on its own uses its project's profiles, or the built-in ones
+        assert!(file_profile(&dir.join("game/src/tool.kn"), "web").unwrap().output.is_some());
+        let release = file_profile(&dir.join("loose.kn"), "release").unwrap();
+        assert!(release.deterministic && release.wasm_opt);
+        let mut options = CompileOptions { features: vec!["fast".to_string()], ..Default::default() };
+        Profile { features: vec!["fast".to_string(), "gpu".to_string()], ..release }.apply(&mut options);
+        assert!(options.deterministic);
+        assert_eq!(options.features, ["fast", "gpu"]);
+        let err = file_profile(&dir.join("loose.kn"), "web").unwrap_err().to_string();
+        assert!(err.contains("Unknown profile 'web', outside a project only debug and release exist"), "{}", err);
+        let settings = TargetProfile { output: Some("shaders".into()), ..Default::default() };
+        assert_eq!(target_dir(&debug, "usf", &settings), std::path::PathBuf::from("shaders"));
+        assert_eq!(target_dir(&debug, "wasm", &Default::default()), debug.join("wasm"));
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_files_take_their_project_edition() {
+        let dir = std::env::temp_dir().join(format!("kain-editions-{}", std::process::id()));
+        for (project, manifest) in [("pinned", "language_version = \"0.3\"\n"), ("unpinned", ""), ("broken", "language_version = \"9\"\n")] {
+            fs::create_dir_all(dir.join(project).join("src")).unwrap();
+            fs::write(dir.join(project).join("KAIN.toml"), format!("[package]\nname = \"{}\"\nversion = \"0.1.0\"\n{}", project, manifest)).unwrap();
+        }
+
+        assert_eq!(file_edition(&dir.join("pinned/src/main.kn"), Edition::V0_1).unwrap(), Edition::V0_3);
+        assert_eq!(file_edition(&dir.join("unpinned/src/main.kn"), Edition::V0_1).unwrap(), Edition::V0_1);
+        assert_eq!(file_edition(&dir.join("loose.kn"), Edition::V0_2).unwrap(), Edition::V0_2);
+        let err = file_edition(&dir.join("broken/src/main.kn"), Edition::V0_1).unwrap_err().to_string();
+        assert!(err.contains("Unknown language_version '9'"), "{}", err);
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
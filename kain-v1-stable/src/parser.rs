@@ -1,19 +1,114 @@
 //! KAIN Parser - Python-style indentation with Rust semantics
 
-use crate::lexer::{Lexer, Token, TokenKind};
+use crate::lexer::{unescape, Lexer, Token, TokenKind};
 use crate::ast::*;
 use crate::span::Span;
 use crate::effects::Effect;
 use crate::error::{KainError, KainResult};
+use crate::stack;
 
 pub struct Parser<'a> {
     tokens: &'a [Token],
     pos: usize,
+    /// Current nesting of expressions/blocks/types/patterns, see `nested`
+    depth: usize,
+    /// Labels of the enclosing loops, innermost last
+    labels: Vec<String>,
+    /// Errors recovered from so far, when parsing with `parse_recovering`
+    errors: Option<Vec<KainError>>,
 }
 
 impl<'a> Parser<'a> {
     pub fn new(tokens: &'a [Token]) -> Self {
-        Self { tokens, pos: 0 }
+        Self { tokens, pos: 0, depth: 0, labels: Vec::new(), errors: None }
+    }
+
+    /// Parse the whole file even when parts of it are malformed. An item or
+    /// statement that fails to parse becomes an [`Item::Error`] or
+    /// [`Expr::Error`] spanning the tokens skipped over, and parsing resumes
+    /// with the next one, so the LSP keeps analysing the rest of the file.
+    pub fn parse_recovering(&mut self) -> (Program, Vec<KainError>) {
+        self.errors = Some(Vec::new());
+        let result = self.parse();
+        let mut errors = self.errors.take().unwrap_or_default();
+        let program = result.unwrap_or_else(|e| {
+            errors.push(e);
+            Program { items: Vec::new(), span: Span::new(0, 0) }
+        });
+        (program, errors)
+    }
+
+    /// [`Self::parse_item`], or when recovering an [`Item::Error`] in place of a malformed item
+    fn item_or_error(&mut self) -> KainResult<Item> {
+        let (from, labels) = (self.pos, self.labels.len());
+        match self.parse_item() {
+            Ok(item) => Ok(item),
+            Err(e) => Ok(Item::Error(self.recover(e, from, labels)?)),
+        }
+    }
+
+    /// [`Self::parse_stmt`], or when recovering an [`Expr::Error`] in place of a malformed statement
+    fn stmt_or_error(&mut self) -> KainResult<Stmt> {
+        let (from, labels) = (self.pos, self.labels.len());
+        match self.parse_stmt() {
+            Ok(stmt) => Ok(stmt),
+            Err(e) => Ok(Stmt::Expr(Expr::Error(self.recover(e, from, labels)?))),
+        }
+    }
+
+    /// Record `error`, raised parsing from token `from`, and skip past the
+    /// construct it broke. Returns the span skipped, or the error itself when
+    /// not recovering.
+    fn recover(&mut self, error: KainError, from: usize, labels: usize) -> KainResult<Span> {
+        let Some(errors) = &mut self.errors else {
+            return Err(error);
+        };
+        errors.push(error);
+        self.labels.truncate(labels);
+        self.synchronize(from);
+        let last = self.pos.saturating_sub(1).max(from);
+        Ok(self.span_at(from).merge(self.span_at(last)))
+    }
+
+    /// Skip to the start of the next line at the indentation the construct
+    /// starting at token `from` began on, stopping at the dedent that closes
+    /// its enclosing block
+    fn synchronize(&mut self, from: usize) {
+        let mut depth: isize = self.tokens[from..self.pos]
+            .iter()
+            .map(|t| match t.kind {
+                TokenKind::Indent => 1,
+                TokenKind::Dedent => -1,
+                _ => 0,
+            })
+            .sum();
+        while !self.at_end() {
+            match self.peek_kind() {
+                TokenKind::Indent => depth += 1,
+                TokenKind::Dedent if depth <= 0 => break,
+                TokenKind::Dedent => {
+                    depth -= 1;
+                    self.advance();
+                    if depth == 0 && !self.check(TokenKind::Dedent) {
+                        break;
+                    }
+                    continue;
+                }
+                TokenKind::Newline(_) if depth <= 0 => {
+                    self.advance();
+                    if !self.check(TokenKind::Indent) {
+                        break;
+                    }
+                    continue;
+                }
+                _ => {}
+            }
+            self.advance();
+        }
+        // Always make progress, or the caller would fail on the same token again
+        if self.pos == from {
+            self.advance();
+        }
     }
 
     pub fn parse(&mut self) -> KainResult<Program> {
@@ -39,12 +134,14 @@ impl<'a> Parser<'a> {
                 TokenKind::Comptime |
                 TokenKind::Macro |
                 TokenKind::Use |
+                TokenKind::Trait |
                 TokenKind::Impl |
                 TokenKind::Test => {
-                    items.push(self.parse_item()?);
+                    items.push(self.item_or_error()?);
                 }
+                _ if self.at_static() || self.at_effect() => items.push(self.item_or_error()?),
                 _ => {
-                    top_level_stmts.push(self.parse_stmt()?);
+                    top_level_stmts.push(self.stmt_or_error()?);
                 }
             }
         }
@@ -59,6 +156,7 @@ impl<'a> Parser<'a> {
                 body: Block { stmts: top_level_stmts, span: start.merge(self.current_span()) },
                 visibility: Visibility::Public,
                 attributes: vec![],
+                doc: None,
                 span: start.merge(self.current_span()),
             });
             items.push(main_fn);
@@ -69,26 +167,39 @@ impl<'a> Parser<'a> {
     }
 
     fn parse_item(&mut self) -> KainResult<Item> {
-        // Collect any @attr decorators first
-        let attributes = self.parse_attributes()?;
+        // Doc comments ride on the first token of the item (before any @attr)
+        let doc = self.current_doc();
+        // Collect any @attr decorators first; @cfg wraps whatever item follows
+        let (conditions, attributes): (Vec<_>, Vec<_>) =
+            self.parse_attributes()?.into_iter().partition(|a| a.name == "cfg");
         let vis = self.parse_visibility();
         
-        match self.peek_kind() {
+        let mut item = match self.peek_kind() {
             TokenKind::Fn => self.parse_function_with_attrs(vis, attributes),
             TokenKind::AsyncKw => self.parse_async_function(vis),
             TokenKind::Component => self.parse_component_with_attrs(vis, attributes),
             TokenKind::Shader => self.parse_shader(),
-            TokenKind::Struct => self.parse_struct(vis),
-            TokenKind::Enum => self.parse_enum(vis),
+            TokenKind::Struct => self.parse_struct(vis, attributes),
+            TokenKind::Enum => self.parse_enum(vis, attributes),
             TokenKind::Actor => self.parse_actor(),
             TokenKind::Const => self.parse_const(vis),
+            TokenKind::TypeKw => self.parse_type_alias(vis),
             TokenKind::Comptime => self.parse_comptime_block(),
             TokenKind::Macro => self.parse_macro(),
             TokenKind::Test => self.parse_test(),
             TokenKind::Use => self.parse_use(),
+            TokenKind::Trait => self.parse_trait(vis),
             TokenKind::Impl => self.parse_impl(),
+            _ if self.at_static() => self.parse_static(vis),
+            _ if self.at_effect() => self.parse_effect_decl(vis),
             _ => Err(KainError::parser("Expected item", self.current_span())),
+        }?;
+        item.set_doc(doc);
+        if let Some(first) = conditions.first() {
+            let span = first.span.merge(self.current_span());
+            item = Item::Cfg(CfgItem { conditions, item: Box::new(item), span });
         }
+        Ok(item)
     }
 
     // Parse @wasm, @js, @inline etc decorators
@@ -128,27 +239,44 @@ impl<'a> Parser<'a> {
         // Parse impl-level generics: impl<T>
         let generics = self.parse_generics()?;
         
-        // Parse target type: Option<T>
-        // Note: Currently assumes "impl Type". To support "impl Trait for Type", we'd check for 'for'.
-        let target_type = self.parse_type()?;
+        // Parse target type: Option<T>, or the trait of `impl Trait for Type`
+        let mut target_type = self.parse_type()?;
+        let mut trait_name = None;
+        if self.check(TokenKind::For) {
+            self.advance();
+            let Type::Named { name, .. } = &target_type else {
+                return Err(KainError::parser("Expected a trait name before `for`", target_type.span()));
+            };
+            trait_name = Some(name.clone());
+            target_type = self.parse_type()?;
+        }
         
         self.expect(TokenKind::Colon)?;
         self.skip_newlines();
         self.expect(TokenKind::Indent)?;
         
         let mut methods = Vec::new();
+        let mut consts = Vec::new();
         
         while !self.check(TokenKind::Dedent) && !self.at_end() {
             self.skip_newlines();
             if self.check(TokenKind::Dedent) { break; }
             
+            let doc = self.current_doc();
+            let attributes = self.parse_attributes()?;
             let vis = self.parse_visibility();
             if self.check(TokenKind::Fn) {
-                if let Item::Function(f) = self.parse_function(vis)? {
+                if let Item::Function(mut f) = self.parse_function_with_attrs(vis, attributes)? {
+                    f.doc = doc;
                     methods.push(f);
                 }
+            } else if self.check(TokenKind::Const) {
+                if let Item::Const(mut c) = self.parse_const(vis)? {
+                    c.doc = doc;
+                    consts.push(c);
+                }
             } else {
-                return Err(KainError::parser("Expected fn in impl block", self.current_span()));
+                return Err(KainError::parser("Expected fn or const in impl block", self.current_span()));
             }
             self.skip_newlines();
         }
@@ -159,9 +287,70 @@ impl<'a> Parser<'a> {
         
         Ok(Item::Impl(Impl {
             generics,
-            trait_name: None,
+            trait_name,
             target_type,
             methods,
+            consts,
+            span: start.merge(self.current_span()),
+        }))
+    }
+
+    /// `trait Name<T>:` and its method signatures, each with an optional default body
+    fn parse_trait(&mut self, vis: Visibility) -> KainResult<Item> {
+        let start = self.current_span();
+        self.expect(TokenKind::Trait)?;
+        let name = self.parse_ident()?;
+        let generics = self.parse_generics()?;
+
+        self.expect(TokenKind::Colon)?;
+        self.skip_newlines();
+        self.expect(TokenKind::Indent)?;
+
+        let mut methods = Vec::new();
+        while !self.check(TokenKind::Dedent) && !self.at_end() {
+            self.skip_newlines();
+            if self.check(TokenKind::Dedent) { break; }
+
+            let method_start = self.current_span();
+            if !self.check(TokenKind::Fn) {
+                return Err(KainError::parser("Expected fn in trait", method_start));
+            }
+            self.advance();
+            let method_name = self.parse_ident()?;
+            self.expect(TokenKind::LParen)?;
+            let params = self.parse_params()?;
+            self.expect(TokenKind::RParen)?;
+            let return_type = if self.check(TokenKind::Arrow) {
+                self.advance();
+                Some(self.parse_type()?)
+            } else { None };
+            let effects = self.parse_effects()?;
+            // A body after `:` is the default for impls that leave the method out
+            let default_impl = if self.check(TokenKind::Colon) {
+                self.advance();
+                Some(self.parse_block()?)
+            } else { None };
+            methods.push(TraitMethod {
+                name: method_name,
+                params,
+                return_type,
+                effects,
+                default_impl,
+                span: method_start.merge(self.current_span()),
+            });
+            self.skip_newlines();
+        }
+
+        if self.check(TokenKind::Dedent) {
+            self.advance();
+        }
+
+        Ok(Item::Trait(Trait {
+            name,
+            generics,
+            methods,
+            visibility: vis,
+            doc: None,
             span: start.merge(self.current_span()),
         }))
     }
@@ -184,6 +373,7 @@ impl<'a> Parser<'a> {
                     path, 
                     alias: None, 
                     glob: true, 
+                    items: None,
                     span: start.merge(self.current_span()) 
                 }));
             }
@@ -198,11 +388,29 @@ impl<'a> Parser<'a> {
         } else {
             None
         };
+
+        // Check for selected items: use foo/bar: {a, b}
+        let items = if alias.is_none() && self.check(TokenKind::Colon) {
+            self.advance();
+            self.expect(TokenKind::LBrace)?;
+            let mut items = Vec::new();
+            while !self.check(TokenKind::RBrace) {
+                items.push(self.parse_ident()?);
+                if !self.check(TokenKind::RBrace) {
+                    self.expect(TokenKind::Comma)?;
+                }
+            }
+            self.expect(TokenKind::RBrace)?;
+            Some(items)
+        } else {
+            None
+        };
         
         Ok(Item::Use(Use { 
             path, 
             alias, 
             glob: false, 
+            items,
             span: start.merge(self.current_span()) 
         }))
     }
@@ -287,6 +495,7 @@ impl<'a> Parser<'a> {
         Ok(Item::Function(Function {
             name, generics, params, return_type, effects, body, visibility: vis,
             attributes: vec![],
+            doc: None,
             span: start.merge(body_span),
         }))
     }
@@ -311,6 +520,7 @@ impl<'a> Parser<'a> {
         Ok(Item::Function(Function {
             name, generics, params, return_type, effects, body, visibility: vis,
             attributes: attrs,
+            doc: None,
             span: start.merge(body_span),
         }))
     }
@@ -344,6 +554,7 @@ impl<'a> Parser<'a> {
         Ok(Item::Function(Function {
             name, generics, params, return_type, effects, body, visibility: vis,
             attributes: vec![],
+            doc: None,
             span: start.merge(body_span),
         }))
     }
@@ -439,6 +650,7 @@ impl<'a> Parser<'a> {
         Ok(Item::Component(Component {
             name, props, state, methods, effects, body, visibility: vis,
             attributes: vec![],
+            doc: None,
             span: start.merge(self.current_span()),
         }))
     }
@@ -507,6 +719,7 @@ impl<'a> Parser<'a> {
         Ok(Item::Component(Component {
             name, props, state, methods, effects, body, visibility: vis,
             attributes: attrs,
+            doc: None,
             span: start.merge(self.current_span()),
         }))
     }
@@ -584,11 +797,12 @@ impl<'a> Parser<'a> {
         
         Ok(Item::Shader(Shader {
             name, stage, inputs, outputs, uniforms, body,
+            doc: None,
             span: start.merge(body_span),
         }))
     }
 
-    fn parse_struct(&mut self, vis: Visibility) -> KainResult<Item> {
+    fn parse_struct(&mut self, vis: Visibility, attributes: Vec<Attribute>) -> KainResult<Item> {
         let start = self.current_span();
         self.expect(TokenKind::Struct)?;
         let name = self.parse_ident()?;
@@ -621,10 +835,10 @@ impl<'a> Parser<'a> {
         }
         if self.check(TokenKind::Dedent) { self.advance(); }
         
-        Ok(Item::Struct(Struct { name, generics, fields, visibility: vis, span: start.merge(self.current_span()) }))
+        Ok(Item::Struct(Struct { name, generics, fields, visibility: vis, attributes, doc: None, span: start.merge(self.current_span()) }))
     }
 
-    fn parse_enum(&mut self, vis: Visibility) -> KainResult<Item> {
+    fn parse_enum(&mut self, vis: Visibility, attributes: Vec<Attribute>) -> KainResult<Item> {
         let start = self.current_span();
         self.expect(TokenKind::Enum)?;
         let name = self.parse_ident()?;
@@ -719,7 +933,7 @@ impl<'a> Parser<'a> {
         }
         if self.check(TokenKind::Dedent) { self.advance(); }
         
-        Ok(Item::Enum(Enum { name, generics, variants, visibility: vis, span: start.merge(self.current_span()) }))
+        Ok(Item::Enum(Enum { name, generics, variants, visibility: vis, attributes, doc: None, span: start.merge(self.current_span()) }))
     }
 
     fn parse_actor(&mut self) -> KainResult<Item> {
@@ -733,6 +947,7 @@ impl<'a> Parser<'a> {
         
         let mut state = Vec::new();
         let mut handlers = Vec::new();
+        let mut mailbox = None;
         
         while !self.check(TokenKind::Dedent) && !self.at_end() {
             self.skip_newlines();
@@ -747,8 +962,8 @@ impl<'a> Parser<'a> {
                     let ty = self.parse_type()?;
                     self.expect(TokenKind::Eq)?;
                     let initial = self.parse_expr()?;
-                    state.push(StateDecl { name, ty, initial, weak: false, span: self.current_span() });
-                } else if s == "weak" {
+                    state.push(StateDecl { name, ty, initial, weak: false, persist: false, span: self.current_span() });
+                } else if s == "weak" || s == "persist" {
                     self.advance();
                     if self.check(TokenKind::Ident("state".to_string())) {
                         self.advance();
@@ -757,10 +972,16 @@ impl<'a> Parser<'a> {
                         let ty = self.parse_type()?;
                         self.expect(TokenKind::Eq)?;
                         let initial = self.parse_expr()?;
-                        state.push(StateDecl { name, ty, initial, weak: true, span: self.current_span() });
+                        let (weak, persist) = (s == "weak", s == "persist");
+                        state.push(StateDecl { name, ty, initial, weak, persist, span: self.current_span() });
                     } else {
-                         return Err(KainError::parser("Expected 'state' after 'weak' in actor", self.current_span()));
+                         return Err(KainError::parser(format!("Expected 'state' after '{}' in actor", s), self.current_span()));
                     }
+                } else if s == "mailbox" {
+                    if mailbox.is_some() {
+                        return Err(KainError::parser("An actor has at most one mailbox declaration", self.current_span()));
+                    }
+                    mailbox = Some(self.parse_mailbox()?);
                 } else if s == "on" {
                     self.advance();
                     let message_type = self.parse_ident()?;
@@ -782,7 +1003,45 @@ impl<'a> Parser<'a> {
         if self.check(TokenKind::Dedent) { self.advance(); }
         
         let span = start.merge(self.current_span());
-        Ok(Item::Actor(Actor { name, state, handlers, span }))
+        Ok(Item::Actor(Actor { name, state, handlers, mailbox, doc: None, span }))
+    }
+
+    /// `mailbox(capacity = 1024, policy = Block)`; both arguments are optional,
+    /// a capacity of 1024 and the `Block` policy being the defaults
+    fn parse_mailbox(&mut self) -> KainResult<Mailbox> {
+        let start = self.current_span();
+        self.advance();
+        self.expect(TokenKind::LParen)?;
+        let mut mailbox = Mailbox { capacity: 1024, policy: MailboxPolicy::default(), span: start };
+        while !self.check(TokenKind::RParen) {
+            let arg_span = self.current_span();
+            let arg = self.parse_ident()?;
+            self.expect(TokenKind::Eq)?;
+            match arg.as_str() {
+                "capacity" => match self.peek_kind() {
+                    TokenKind::Int(n) if n > 0 => {
+                        mailbox.capacity = n as usize;
+                        self.advance();
+                    }
+                    _ => return Err(KainError::parser("Mailbox capacity must be a positive integer", self.current_span())),
+                },
+                "policy" => {
+                    let span = self.current_span();
+                    let name = self.parse_ident()?;
+                    mailbox.policy = MailboxPolicy::from_name(&name).ok_or_else(|| {
+                        KainError::parser(format!("Unknown mailbox policy `{}`; expected Block, DropOldest or Fail", name), span)
+                    })?;
+                }
+                _ => return Err(KainError::parser(format!("Unknown mailbox argument `{}`; expected capacity or policy", arg), arg_span)),
+            }
+            if !self.check(TokenKind::Comma) {
+                break;
+            }
+            self.advance();
+        }
+        self.expect(TokenKind::RParen)?;
+        mailbox.span = start.merge(self.current_span());
+        Ok(mailbox)
     }
 
     fn parse_const(&mut self, vis: Visibility) -> KainResult<Item> {
@@ -793,7 +1052,82 @@ impl<'a> Parser<'a> {
         let ty = self.parse_type()?;
         self.expect(TokenKind::Eq)?;
         let value = self.parse_expr()?;
-        Ok(Item::Const(Const { name, ty, value, visibility: vis, span: start.merge(self.current_span()) }))
+        Ok(Item::Const(Const { name, ty, value, visibility: vis, doc: None, span: start.merge(self.current_span()) }))
+    }
+
+    /// `type Name<T> = Target`, or `type Name = new Target` for a newtype
+    fn parse_type_alias(&mut self, vis: Visibility) -> KainResult<Item> {
+        let start = self.current_span();
+        self.expect(TokenKind::TypeKw)?;
+        let name = self.parse_ident()?;
+        let generics = self.parse_generics()?;
+        self.expect(TokenKind::Eq)?;
+        let newtype = matches!(self.peek_kind(), TokenKind::Ident(ref s) if s == "new");
+        if newtype {
+            self.advance();
+        }
+        let target = self.parse_type()?;
+        Ok(Item::TypeAlias(TypeAlias {
+            name,
+            generics,
+            target,
+            newtype,
+            visibility: vis,
+            doc: None,
+            span: start.merge(self.current_span()),
+        }))
+    }
+
+    /// `static` is only a keyword where an item starts, followed by `mut` or a name
+    fn at_static(&self) -> bool {
+        matches!(self.peek_kind(), TokenKind::Ident(ref s) if s == "static")
+            && self.tokens.get(self.pos + 1).is_some_and(|t| matches!(t.kind, TokenKind::Mut | TokenKind::Ident(_)))
+    }
+
+    fn parse_static(&mut self, vis: Visibility) -> KainResult<Item> {
+        let start = self.current_span();
+        self.advance(); // static
+        if !self.check(TokenKind::Mut) {
+            return Err(KainError::parser(
+                "Expected `mut` after `static`; use `const` for a value that never changes",
+                self.current_span(),
+            ));
+        }
+        self.advance();
+        let name = self.parse_ident()?;
+        self.expect(TokenKind::Colon)?;
+        let ty = self.parse_type()?;
+        self.expect(TokenKind::Eq)?;
+        let value = self.parse_expr()?;
+        Ok(Item::Static(Static { name, ty, value, visibility: vis, doc: None, span: start.merge(self.current_span()) }))
+    }
+
+    /// `effect` is only a keyword in front of the name it declares
+    fn at_effect(&self) -> bool {
+        matches!(self.peek_kind(), TokenKind::Ident(ref s) if s == "effect")
+            && self.tokens.get(self.pos + 1).is_some_and(|t| matches!(t.kind, TokenKind::Ident(_)))
+    }
+
+    /// `effect Name`, or `effect Name = IO + Other` for an alias
+    fn parse_effect_decl(&mut self, vis: Visibility) -> KainResult<Item> {
+        let start = self.current_span();
+        self.advance(); // effect
+        let name = self.parse_ident()?;
+        let alias = if self.check(TokenKind::Eq) {
+            self.advance();
+            let mut members = Vec::new();
+            loop {
+                let member = self.parse_effect_name()
+                    .ok_or_else(|| KainError::parser("Expected an effect name", self.current_span()))?;
+                members.push(member);
+                if !self.check(TokenKind::Plus) { break; }
+                self.advance();
+            }
+            Some(members)
+        } else {
+            None
+        };
+        Ok(Item::Effect(EffectDecl { name, alias, visibility: vis, doc: None, span: start.merge(self.current_span()) }))
     }
 
     fn parse_comptime_block(&mut self) -> KainResult<Item> {
@@ -808,6 +1142,11 @@ impl<'a> Parser<'a> {
         let mut params = Vec::new();
         self.skip_newlines();
         while !self.check(TokenKind::RParen) && !self.at_end() {
+            let start = self.current_span();
+            let variadic = self.check(TokenKind::DotDotDot);
+            if variadic {
+                self.advance();
+            }
             let mutable = if self.check(TokenKind::Mut) {
                 self.advance();
                 true
@@ -822,7 +1161,7 @@ impl<'a> Parser<'a> {
             } else {
                 Type::Infer(self.current_span())
             };
-            params.push(Param { name, ty, mutable, default: None, span: self.current_span() });
+            params.push(Param { name, ty, mutable, default: None, variadic, span: start.merge(self.current_span()) });
             
             self.skip_newlines();
             if !self.check(TokenKind::RParen) { 
@@ -875,22 +1214,7 @@ impl<'a> Parser<'a> {
         if self.check(TokenKind::With) {
             self.advance();
             loop {
-                // Effects are keywords, not identifiers
-                let effect = match self.peek_kind() {
-                    TokenKind::Pure => { self.advance(); Some(Effect::Pure) }
-                    TokenKind::Io => { self.advance(); Some(Effect::IO) }
-                    TokenKind::Async => { self.advance(); Some(Effect::Async) }
-                    TokenKind::Gpu => { self.advance(); Some(Effect::GPU) }
-                    TokenKind::Reactive => { self.advance(); Some(Effect::Reactive) }
-                    TokenKind::Unsafe => { self.advance(); Some(Effect::Unsafe) }
-                    TokenKind::Ident(ref s) => {
-                        let e = Effect::from_str(s);
-                        self.advance();
-                        e
-                    }
-                    _ => None,
-                };
-                if let Some(e) = effect {
+                if let Some(e) = self.parse_effect_name() {
                     effects.push(e);
                 }
                 if !self.check(TokenKind::Comma) { break; }
@@ -900,8 +1224,47 @@ impl<'a> Parser<'a> {
         Ok(effects)
     }
 
+    /// One effect of a `with` clause or an effect alias. The common built-in
+    /// effects are keywords; any other name is one the program declares.
+    fn parse_effect_name(&mut self) -> Option<Effect> {
+        let effect = match self.peek_kind() {
+            TokenKind::Pure => Effect::Pure,
+            TokenKind::Io => Effect::IO,
+            TokenKind::Async => Effect::Async,
+            TokenKind::Gpu => Effect::GPU,
+            TokenKind::Reactive => Effect::Reactive,
+            TokenKind::Unsafe => Effect::Unsafe,
+            TokenKind::Ident(s) => Effect::from_str(&s).unwrap_or(Effect::Named(s)),
+            _ => return None,
+        };
+        self.advance();
+        Some(effect)
+    }
+
     fn parse_type(&mut self) -> KainResult<Type> {
+        let mut ty = self.nested(Self::parse_type_inner)?;
+        // `T?` is `Option<T>`
+        while self.check(TokenKind::Question) {
+            let span = ty.span().merge(self.current_span());
+            self.advance();
+            ty = Type::Option(Box::new(ty), span);
+        }
+        Ok(ty)
+    }
+
+    fn parse_type_inner(&mut self) -> KainResult<Type> {
         let span = self.current_span();
+
+        // References: &T, &mut T
+        if self.check(TokenKind::Amp) {
+            self.advance();
+            let mutable = self.check(TokenKind::Mut);
+            if mutable {
+                self.advance();
+            }
+            let inner = self.parse_type()?;
+            return Ok(Type::Ref { mutable, inner: Box::new(inner), lifetime: None, span: span.merge(self.current_span()) });
+        }
         
         // Handle tuple types: (A, B) or unit type: ()
         if self.check(TokenKind::LParen) {
@@ -1010,6 +1373,10 @@ impl<'a> Parser<'a> {
     }
 
     fn parse_block(&mut self) -> KainResult<Block> {
+        self.nested(Self::parse_block_inner)
+    }
+
+    fn parse_block_inner(&mut self) -> KainResult<Block> {
         self.skip_newlines();
         let start = self.current_span();
         self.expect(TokenKind::Indent)?;
@@ -1018,7 +1385,7 @@ impl<'a> Parser<'a> {
         while !self.check(TokenKind::Dedent) && !self.at_end() {
             self.skip_newlines();
             if self.check(TokenKind::Dedent) { break; }
-            stmts.push(self.parse_stmt()?);
+            stmts.push(self.stmt_or_error()?);
             self.skip_newlines();
         }
         if self.check(TokenKind::Dedent) { self.advance(); }
@@ -1026,6 +1393,37 @@ impl<'a> Parser<'a> {
         Ok(Block { stmts, span: start.merge(self.current_span()) })
     }
 
+    /// Like a block, but items may appear alongside statements
+    fn parse_quote_body(&mut self) -> KainResult<Block> {
+        self.skip_newlines();
+        let start = self.current_span();
+        self.expect(TokenKind::Indent)?;
+
+        let mut stmts = Vec::new();
+        while !self.check(TokenKind::Dedent) && !self.at_end() {
+            self.skip_newlines();
+            if self.check(TokenKind::Dedent) { break; }
+            // `fn(` is a lambda, `fn name(` a function
+            let fn_item = self.check(TokenKind::Fn)
+                && self.tokens.get(self.pos + 1).is_some_and(|t| matches!(t.kind, TokenKind::Ident(_)));
+            let item_start = matches!(
+                self.peek_kind(),
+                TokenKind::Pub | TokenKind::At | TokenKind::AsyncKw | TokenKind::Component | TokenKind::Shader
+                    | TokenKind::Struct | TokenKind::Enum | TokenKind::Actor | TokenKind::Const | TokenKind::Impl
+                    | TokenKind::Test
+            );
+            if fn_item || item_start {
+                stmts.push(Stmt::Item(Box::new(self.parse_item()?)));
+            } else {
+                stmts.push(self.parse_stmt()?);
+            }
+            self.skip_newlines();
+        }
+        if self.check(TokenKind::Dedent) { self.advance(); }
+
+        Ok(Block { stmts, span: start.merge(self.current_span()) })
+    }
+
     fn parse_stmt(&mut self) -> KainResult<Stmt> {
         match self.peek_kind() {
             TokenKind::Let => self.parse_let(),
@@ -1036,10 +1434,105 @@ impl<'a> Parser<'a> {
             TokenKind::Loop => self.parse_loop(),
             TokenKind::Break => self.parse_break(),
             TokenKind::Continue => self.parse_continue(),
+            TokenKind::Unless => self.parse_unless(),
+            TokenKind::Label(_) => self.parse_labeled_loop(),
+            TokenKind::With if matches!(self.tokens.get(self.pos + 1).map(|t| &t.kind), Some(TokenKind::Ident(s)) if s == "handler") => {
+                Ok(Stmt::Expr(self.parse_handle()?))
+            }
+            // `guard` stays an ordinary identifier everywhere else
+            TokenKind::Ident(ref s) if s == "guard" && matches!(self.tokens.get(self.pos + 1).map(|t| &t.kind), Some(TokenKind::Let)) => {
+                self.parse_guard_let()
+            }
+            // So does `region`
+            TokenKind::Ident(ref s) if s == "region" && matches!(self.tokens.get(self.pos + 1).map(|t| &t.kind), Some(TokenKind::Colon)) => {
+                self.parse_region()
+            }
             _ => Ok(Stmt::Expr(self.parse_expr()?)),
         }
     }
 
+    /// `with handler value for Effect: body`
+    fn parse_handle(&mut self) -> KainResult<Expr> {
+        let start = self.current_span();
+        self.expect(TokenKind::With)?;
+        self.advance(); // handler
+        let handler = self.parse_expr()?;
+        self.expect(TokenKind::For)?;
+        let effect = self.parse_effect_name()
+            .ok_or_else(|| KainError::parser("Expected the effect to handle after `for`", self.current_span()))?;
+        self.expect(TokenKind::Colon)?;
+        let body = self.parse_clause_body(start)?;
+        Ok(Expr::Handle { handler: Box::new(handler), effect, body, span: start.merge(self.current_span()) })
+    }
+
+    /// `unless cond: body` is `if !cond: body`
+    fn parse_unless(&mut self) -> KainResult<Stmt> {
+        let start = self.current_span();
+        self.expect(TokenKind::Unless)?;
+        let condition = self.parse_expr()?;
+        self.expect(TokenKind::Colon)?;
+        let then_branch = self.parse_clause_body(start)?;
+        let span = start.merge(self.current_span());
+        let condition = Expr::Unary { op: UnaryOp::Not, operand: Box::new(condition), span };
+        Ok(Stmt::Expr(Expr::If { condition: Box::new(condition), then_branch, else_branch: None, span }))
+    }
+
+    /// `guard let PAT = value else: body` binds PAT's names in the enclosing
+    /// scope, running the diverging `body` when the value doesn't match. It is
+    /// `let PAT = match value: __guarded => match __guarded: PAT => __guarded, _ => body`.
+    fn parse_guard_let(&mut self) -> KainResult<Stmt> {
+        let start = self.current_span();
+        self.advance();
+        self.expect(TokenKind::Let)?;
+        let pattern = self.parse_pattern()?;
+        self.expect(TokenKind::Eq)?;
+        let value = self.parse_expr()?;
+        self.expect(TokenKind::Else)?;
+        self.expect(TokenKind::Colon)?;
+        let else_block = self.parse_clause_body(start)?;
+        let diverges = match else_block.stmts.last() {
+            Some(Stmt::Return(..) | Stmt::Break(..) | Stmt::Continue(..)) => true,
+            Some(Stmt::Expr(Expr::Return(..) | Expr::Break(..) | Expr::Continue(..))) => true,
+            Some(Stmt::Expr(Expr::Call { callee, .. })) => matches!(callee.as_ref(), Expr::Ident(name, _) if name == "panic" || name == "exit"),
+            _ => false,
+        };
+        if !diverges {
+            return Err(KainError::parser("guard's else branch must return, break, continue or panic", else_block.span));
+        }
+
+        let span = start.merge(self.current_span());
+        let guarded = || Expr::Ident("__guarded".to_string(), span);
+        let checked = Expr::Match {
+            scrutinee: Box::new(guarded()),
+            arms: vec![
+                MatchArm { pattern: pattern.clone(), guard: None, body: guarded(), span },
+                MatchArm { pattern: Pattern::Wildcard(span), guard: None, body: Expr::Block(else_block, span), span },
+            ],
+            span,
+        };
+        let value = Expr::Match {
+            scrutinee: Box::new(value),
+            arms: vec![MatchArm {
+                pattern: Pattern::Binding { name: "__guarded".to_string(), mutable: false, span },
+                guard: None,
+                body: checked,
+                span,
+            }],
+            span,
+        };
+        Ok(Stmt::Let { pattern, ty: None, value: Some(value), span })
+    }
+
+    /// The body after a `:`, either an indented block or a single statement on the same line
+    fn parse_clause_body(&mut self, start: Span) -> KainResult<Block> {
+        if matches!(self.peek_kind(), TokenKind::Newline(_) | TokenKind::Indent) {
+            self.parse_block()
+        } else {
+            let stmt = self.parse_stmt()?;
+            Ok(Block { stmts: vec![stmt], span: start.merge(self.current_span()) })
+        }
+    }
+
     fn parse_let(&mut self) -> KainResult<Stmt> {
         let start = self.current_span();
         self.expect(TokenKind::Let)?;
@@ -1072,12 +1565,13 @@ impl<'a> Parser<'a> {
     fn parse_for(&mut self) -> KainResult<Stmt> {
         let start = self.current_span();
         self.expect(TokenKind::For)?;
-        let name = self.parse_ident()?;
+        let binding = self.parse_pattern()?;
         self.expect(TokenKind::In)?;
         let iter = self.parse_expr()?;
         self.expect(TokenKind::Colon)?;
         let body = self.parse_block()?;
-        Ok(Stmt::For { binding: Pattern::Binding { name, mutable: false, span: start }, iter, body, span: start.merge(self.current_span()) })
+        let else_branch = self.parse_loop_else()?;
+        Ok(Stmt::For { label: None, binding, iter, body, else_branch, span: start.merge(self.current_span()) })
     }
 
     fn parse_while(&mut self) -> KainResult<Stmt> {
@@ -1086,7 +1580,28 @@ impl<'a> Parser<'a> {
         let condition = self.parse_expr()?;
         self.expect(TokenKind::Colon)?;
         let body = self.parse_block()?;
-        Ok(Stmt::While { condition, body, span: start.merge(self.current_span()) })
+        let else_branch = self.parse_loop_else()?;
+        Ok(Stmt::While { label: None, condition, body, else_branch, span: start.merge(self.current_span()) })
+    }
+
+    /// Python's loop `else:`, run when the loop ends without `break`
+    fn parse_loop_else(&mut self) -> KainResult<Option<Block>> {
+        if !self.check(TokenKind::Else) {
+            return Ok(None);
+        }
+        let start = self.current_span();
+        self.advance();
+        self.expect(TokenKind::Colon)?;
+        Ok(Some(self.parse_clause_body(start)?))
+    }
+
+    /// `region: body`
+    fn parse_region(&mut self) -> KainResult<Stmt> {
+        let start = self.current_span();
+        self.advance(); // region
+        self.expect(TokenKind::Colon)?;
+        let body = self.parse_block()?;
+        Ok(Stmt::Region { body, span: start.merge(self.current_span()) })
     }
 
     fn parse_loop(&mut self) -> KainResult<Stmt> {
@@ -1094,30 +1609,70 @@ impl<'a> Parser<'a> {
         self.expect(TokenKind::Loop)?;
         self.expect(TokenKind::Colon)?;
         let body = self.parse_block()?;
-        Ok(Stmt::Loop { body, span: start.merge(self.current_span()) })
+        Ok(Stmt::Loop { label: None, body, span: start.merge(self.current_span()) })
+    }
+
+    /// `'outer: for ...`, `'outer: while ...` or `'outer: loop: ...`
+    fn parse_labeled_loop(&mut self) -> KainResult<Stmt> {
+        let start = self.current_span();
+        let TokenKind::Label(name) = self.peek_kind() else {
+            return Err(KainError::parser("Expected a loop label", start));
+        };
+        self.advance();
+        self.expect(TokenKind::Colon)?;
+        if self.labels.contains(&name) {
+            return Err(KainError::parser(format!("Label '{} shadows an enclosing loop's label", name), start));
+        }
+        self.labels.push(name.clone());
+        let stmt = match self.peek_kind() {
+            TokenKind::For => self.parse_for(),
+            TokenKind::While => self.parse_while(),
+            TokenKind::Loop => self.parse_loop(),
+            _ => Err(KainError::parser("Expected for, while or loop after a label", self.current_span())),
+        };
+        self.labels.pop();
+        let mut stmt = stmt?;
+        if let Stmt::For { label, .. } | Stmt::While { label, .. } | Stmt::Loop { label, .. } = &mut stmt {
+            *label = Some(name);
+        }
+        Ok(stmt)
+    }
+
+    /// The `'label` after `break`/`continue`, which must name an enclosing loop
+    fn parse_jump_label(&mut self) -> KainResult<Option<String>> {
+        let TokenKind::Label(name) = self.peek_kind() else {
+            return Ok(None);
+        };
+        if !self.labels.contains(&name) {
+            return Err(KainError::parser(format!("No enclosing loop is labeled '{}", name), self.current_span()));
+        }
+        self.advance();
+        Ok(Some(name))
     }
 
     fn parse_break(&mut self) -> KainResult<Stmt> {
         let start = self.current_span();
         self.expect(TokenKind::Break)?;
+        let label = self.parse_jump_label()?;
         // Optional value: break expr
         let value = if !self.check_line_end() && !self.check(TokenKind::Dedent) {
             Some(self.parse_expr()?)
         } else {
             None
         };
-        Ok(Stmt::Break(value, start.merge(self.current_span())))
+        Ok(Stmt::Break(value, label, start.merge(self.current_span())))
     }
 
     fn parse_continue(&mut self) -> KainResult<Stmt> {
         let start = self.current_span();
         self.expect(TokenKind::Continue)?;
-        Ok(Stmt::Continue(start.merge(self.current_span())))
+        let label = self.parse_jump_label()?;
+        Ok(Stmt::Continue(label, start.merge(self.current_span())))
     }
     fn parse_expr(&mut self) -> KainResult<Expr> { self.parse_assignment() }
 
     fn parse_assignment(&mut self) -> KainResult<Expr> {
-        let expr = self.parse_binary(0)?;
+        let expr = self.parse_pipeline()?;
         
         if self.check(TokenKind::Eq) {
             self.advance();
@@ -1129,23 +1684,107 @@ impl<'a> Parser<'a> {
         }
     }
 
+    /// `x |> f |> g(y)` desugars to `g(f(x), y)`; binds looser than any binary operator
+    fn parse_pipeline(&mut self) -> KainResult<Expr> {
+        let mut value = self.parse_range()?;
+        while self.check(TokenKind::PipeGt) {
+            self.advance();
+            let stage = self.parse_range()?;
+            let span = value.span().merge(stage.span());
+            let arg = CallArg { name: None, span: value.span(), value, spread: false };
+            value = match stage {
+                Expr::Call { callee, mut args, .. } => {
+                    args.insert(0, arg);
+                    Expr::Call { callee, args, span }
+                }
+                Expr::MethodCall { receiver, method, mut args, .. } => {
+                    args.insert(0, arg);
+                    Expr::MethodCall { receiver, method, args, span }
+                }
+                callee => Expr::Call { callee: Box::new(callee), args: vec![arg], span },
+            };
+        }
+        Ok(value)
+    }
+
+    /// `a..b`, `a..=b`, `a..` and `..b`; looser than `||`, so `0..n + 1` ends at `n + 1`
+    fn parse_range(&mut self) -> KainResult<Expr> {
+        let start_span = self.current_span();
+        let start = if matches!(self.peek_kind(), TokenKind::DotDot | TokenKind::DotDotEq) {
+            None
+        } else {
+            Some(Box::new(self.parse_binary(0)?))
+        };
+        let inclusive = match self.peek_kind() {
+            TokenKind::DotDot => false,
+            TokenKind::DotDotEq => true,
+            _ => return start.map(|e| *e).ok_or_else(|| KainError::parser("Expected an expression", start_span)),
+        };
+        self.advance();
+        let end = if inclusive || !self.check_range_end() {
+            Some(Box::new(self.parse_binary(0)?))
+        } else {
+            None
+        };
+        let span = start_span.merge(self.current_span());
+        Ok(Expr::Range { start, end, inclusive, span })
+    }
+
+    /// Whether an open-ended `a..` stops here
+    fn check_range_end(&self) -> bool {
+        self.check_line_end()
+            || matches!(
+                self.peek_kind(),
+                TokenKind::Colon | TokenKind::RParen | TokenKind::RBracket | TokenKind::Comma | TokenKind::FatArrow | TokenKind::PipeGt
+            )
+    }
+
     fn parse_binary(&mut self, min_prec: u8) -> KainResult<Expr> {
         let mut left = self.parse_unary()?;
-        
-        while let Some((op, prec)) = self.get_binary_op() {
-            if prec < min_prec { break; }
+        // The loop builds a left-deep tree, so every operator in the chain is one
+        // more level for the passes that walk it recursively.
+        let base = self.depth;
+        let result = loop {
+            let Some((op, prec)) = self.get_binary_op() else { break Ok(left) };
+            if prec < min_prec { break Ok(left); }
+            if self.depth >= stack::MAX_NESTING_DEPTH {
+                break Err(KainError::parser(
+                    format!("Nesting too deep (more than {} levels)", stack::MAX_NESTING_DEPTH),
+                    self.current_span(),
+                ));
+            }
+            self.depth += 1;
             self.advance();
-            let right = self.parse_binary(prec + 1)?;
+            let right = match self.parse_binary(prec + 1) {
+                Ok(right) => right,
+                Err(e) => break Err(e),
+            };
             let span = left.span().merge(right.span());
             left = Expr::Binary { left: Box::new(left), op, right: Box::new(right), span };
-        }
-        Ok(left)
+        };
+        self.depth = base;
+        result
     }
 
     fn parse_unary(&mut self) -> KainResult<Expr> {
+        self.nested(Self::parse_unary_inner)
+    }
+
+    fn parse_unary_inner(&mut self) -> KainResult<Expr> {
         match self.peek_kind() {
             TokenKind::Minus => { let s = self.current_span(); self.advance(); Ok(Expr::Unary { op: UnaryOp::Neg, operand: Box::new(self.parse_unary()?), span: s }) }
             TokenKind::Not => { let s = self.current_span(); self.advance(); Ok(Expr::Unary { op: UnaryOp::Not, operand: Box::new(self.parse_unary()?), span: s }) }
+            // `&value` and `&mut value` borrow it
+            TokenKind::Amp => {
+                let start = self.current_span();
+                self.advance();
+                let mutable = self.check(TokenKind::Mut);
+                if mutable {
+                    self.advance();
+                }
+                let value = self.parse_unary()?;
+                Ok(Expr::Ref { mutable, span: start.merge(value.span()), value: Box::new(value) })
+            }
             TokenKind::Await => {
                 let start = self.current_span();
                 self.advance();
@@ -1157,23 +1796,23 @@ impl<'a> Parser<'a> {
                 self.advance();
                 let expr = self.parse_postfix()?;
                 
-                if let Expr::Call { callee, args, span } = expr {
-                    if let Expr::Field { object, field, span: _ } = *callee {
-                        let mut data = Vec::new();
-                        for arg in args {
-                            if let Some(name) = arg.name {
-                                data.push((name, arg.value));
-                            } else {
-                                return Err(KainError::parser("Send requires named arguments", arg.span));
-                            }
-                        }
-                        Ok(Expr::SendMsg { target: object, message: field, data, span: start.merge(span) })
+                let (target, message, args, span) = match expr {
+                    Expr::MethodCall { receiver, method, args, span } => (receiver, method, args, span),
+                    Expr::Call { callee, args, span } => match *callee {
+                        Expr::Field { object, field, .. } => (object, field, args, span),
+                        _ => return Err(KainError::parser("Expected method call after send (e.g., actor.message())", span)),
+                    },
+                    _ => return Err(KainError::parser("Expected message call after send", expr.span())),
+                };
+                let mut data = Vec::new();
+                for arg in args {
+                    if let Some(name) = arg.name {
+                        data.push((name, arg.value));
                     } else {
-                        Err(KainError::parser("Expected method call after send (e.g., actor.message())", span))
+                        return Err(KainError::parser("Send requires named arguments", arg.span));
                     }
-                } else {
-                    Err(KainError::parser("Expected message call after send", expr.span()))
                 }
+                Ok(Expr::SendMsg { target, message, data, span: start.merge(span) })
             }
             _ => self.parse_postfix(),
         }
@@ -1195,7 +1834,7 @@ impl<'a> Parser<'a> {
                         expr = Expr::Call { callee: Box::new(expr), args, span: s }; 
                     }
                 }
-                TokenKind::Dot => { self.advance(); let field = self.parse_ident()?; let s = expr.span().merge(self.current_span()); expr = Expr::Field { object: Box::new(expr), field, span: s }; }
+                TokenKind::Dot => { self.advance(); let field = self.parse_field_name()?; let s = expr.span().merge(self.current_span()); expr = Expr::Field { object: Box::new(expr), field, span: s }; }
             TokenKind::As => {
                 self.advance();
                 let target = self.parse_type()?;
@@ -1249,11 +1888,15 @@ impl<'a> Parser<'a> {
                 let mut parts = Vec::new();
                 let mut last_idx = 0;
                 let mut chars = s.char_indices().peekable();
+                // The lexer checked the escapes, so the literal parts only need resolving
+                let text = |part: &str| Expr::String(unescape(part, true).unwrap_or_else(|_| part.to_string()), span);
                 
                 while let Some((idx, c)) = chars.next() {
-                    if c == '{' {
+                    if c == '\\' {
+                        chars.next();
+                    } else if c == '{' {
                         if idx > last_idx {
-                            parts.push(Expr::String(s[last_idx..idx].to_string(), span));
+                            parts.push(text(&s[last_idx..idx]));
                         }
                         
                         let expr_start = idx + 1;
@@ -1285,11 +1928,12 @@ impl<'a> Parser<'a> {
                 }
                 
                 if last_idx < s.len() {
-                    parts.push(Expr::String(s[last_idx..].to_string(), span));
+                    parts.push(text(&s[last_idx..]));
                 }
                 
                 Ok(Expr::FString(parts, span))
             }
+            TokenKind::Char(_) => self.parse_char_literal(),
             TokenKind::True => { self.advance(); Ok(Expr::Bool(true, span)) }
             TokenKind::False => { self.advance(); Ok(Expr::Bool(false, span)) }
             TokenKind::None => { self.advance(); Ok(Expr::None(span)) }
@@ -1372,6 +2016,7 @@ impl<'a> Parser<'a> {
                 if self.check(TokenKind::LBrace) {
                     self.advance(); // consume {
                     let mut fields = Vec::new();
+                    let mut base = None;
                     
                     self.skip_newlines();
                     let indented = if self.check(TokenKind::Indent) {
@@ -1386,10 +2031,19 @@ impl<'a> Parser<'a> {
                             break;
                         }
                         
-                        let field_name = self.parse_ident()?;
-                        self.expect(TokenKind::Colon)?;
-                        let field_value = self.parse_expr()?;
-                        fields.push((field_name, field_value));
+                        if self.check(TokenKind::DotDot) {
+                            let spread = self.current_span();
+                            self.advance();
+                            if base.is_some() {
+                                return Err(KainError::parser("A struct literal copies from at most one `..base`", spread));
+                            }
+                            base = Some(Box::new(self.parse_expr()?));
+                        } else {
+                            let field_name = self.parse_ident()?;
+                            self.expect(TokenKind::Colon)?;
+                            let field_value = self.parse_expr()?;
+                            fields.push((field_name, field_value));
+                        }
                         
                         // Optional comma if not closing
                         if !self.check(TokenKind::RBrace) && (!indented || !self.check(TokenKind::Dedent)) {
@@ -1408,6 +2062,7 @@ impl<'a> Parser<'a> {
                     Ok(Expr::Struct { 
                         name, 
                         fields, 
+                        base,
                         span: span.merge(self.current_span()) 
                     })
                 } else {
@@ -1490,6 +2145,14 @@ impl<'a> Parser<'a> {
                 let body = self.parse_block()?;
                 Ok(Expr::Comptime(Box::new(Expr::Block(body, span)), span))
             }
+            // `quote:` is the builtin `quote!` macro applied to a block of code
+            TokenKind::Quote => {
+                self.advance();
+                self.expect(TokenKind::Colon)?;
+                let body = self.nested(Self::parse_quote_body)?;
+                let span = span.merge(body.span);
+                Ok(Expr::MacroCall { name: "quote".to_string(), args: vec![Expr::Block(body, span)], span })
+            }
             TokenKind::Pipe => {
                 self.advance();
                 let mut params = Vec::new();
@@ -1500,6 +2163,7 @@ impl<'a> Parser<'a> {
                         ty: Type::Infer(span),
                         mutable: false,
                         default: None,
+                        variadic: false,
                         span,
                     });
                     if !self.check(TokenKind::Pipe) { self.expect(TokenKind::Comma)?; }
@@ -1571,6 +2235,7 @@ impl<'a> Parser<'a> {
                         ty,
                         mutable: false,
                         default: None,
+                        variadic: false,
                         span: p_span,
                     });
                     
@@ -1616,10 +2281,11 @@ impl<'a> Parser<'a> {
             TokenKind::Continue => {
                 self.advance();
                 // Continue as an expression wraps in a block that continues
-                Ok(Expr::Continue(span))
+                Ok(Expr::Continue(self.parse_jump_label()?, span))
             }
             TokenKind::Break => {
                 self.advance();
+                let label = self.parse_jump_label()?;
                 // Optional break value
                 let value = if !self.check_line_end() && !self.check(TokenKind::Dedent) 
                     && !self.check(TokenKind::Comma) && !self.check(TokenKind::RParen) {
@@ -1627,7 +2293,7 @@ impl<'a> Parser<'a> {
                 } else {
                     None
                 };
-                Ok(Expr::Break(value, span))
+                Ok(Expr::Break(value, label, span))
             }
             _ => Err(KainError::parser(format!("Unexpected token: {:?}", self.peek_kind()), span)),
         }
@@ -1745,9 +2411,22 @@ impl<'a> Parser<'a> {
     }
 
     fn parse_pattern(&mut self) -> KainResult<Pattern> {
+        self.nested(Self::parse_pattern_inner)
+    }
+
+    fn parse_pattern_inner(&mut self) -> KainResult<Pattern> {
         let span = self.current_span();
         match self.peek_kind() {
             TokenKind::Ident(ref s) if s == "_" => { self.advance(); Ok(Pattern::Wildcard(span)) }
+            // `None` is the empty Option, not a name to bind
+            TokenKind::Ident(ref s) if s == "None" && !matches!(self.tokens.get(self.pos + 1).map(|t| &t.kind), Some(TokenKind::ColonColon)) => {
+                self.advance();
+                Ok(Pattern::Variant { enum_name: None, variant: "None".to_string(), fields: VariantPatternFields::Unit, span })
+            }
+            TokenKind::None => {
+                self.advance();
+                Ok(Pattern::Variant { enum_name: None, variant: "None".to_string(), fields: VariantPatternFields::Unit, span })
+            }
             TokenKind::Ident(ref s) => { 
                 let name = s.clone(); 
                 self.advance(); 
@@ -1819,7 +2498,8 @@ impl<'a> Parser<'a> {
                 let name = self.parse_ident()?;
                 Ok(Pattern::Binding { name, mutable: true, span: span.merge(self.current_span()) })
             }
-            TokenKind::Int(n) => { self.advance(); Ok(Pattern::Literal(Expr::Int(n, span))) }
+            TokenKind::Int(_) | TokenKind::Minus | TokenKind::DotDot | TokenKind::DotDotEq => self.parse_int_pattern(),
+            TokenKind::Char(_) => self.parse_char_pattern(),
             TokenKind::String(ref s) => { 
                 let string_val = s.clone();
                 self.advance(); 
@@ -1851,6 +2531,82 @@ impl<'a> Parser<'a> {
         }
     }
 
+    /// An integer literal pattern, or a range of them: `3`, `-1`, `1..=9`, `10..`, `..0`
+    fn parse_int_pattern(&mut self) -> KainResult<Pattern> {
+        let span = self.current_span();
+        let start = if matches!(self.peek_kind(), TokenKind::DotDot | TokenKind::DotDotEq) {
+            None
+        } else {
+            Some(self.parse_int_literal()?)
+        };
+        let inclusive = match self.peek_kind() {
+            TokenKind::DotDot => false,
+            TokenKind::DotDotEq => true,
+            _ => return start.map(Pattern::Literal).ok_or_else(|| KainError::parser("Expected a pattern", span)),
+        };
+        self.advance();
+        let end = if matches!(self.peek_kind(), TokenKind::Int(_) | TokenKind::Minus) {
+            Some(Box::new(self.parse_int_literal()?))
+        } else if inclusive || start.is_none() {
+            return Err(KainError::parser("Expected the end of the range pattern", self.current_span()));
+        } else {
+            None
+        };
+        Ok(Pattern::Range { start: start.map(Box::new), end, inclusive, span: span.merge(self.current_span()) })
+    }
+
+    fn parse_int_literal(&mut self) -> KainResult<Expr> {
+        let span = self.current_span();
+        let negative = self.check(TokenKind::Minus);
+        if negative {
+            self.advance();
+        }
+        match self.peek_kind() {
+            TokenKind::Int(n) => {
+                self.advance();
+                let value = if negative { n.wrapping_neg() } else { n };
+                Ok(Expr::Int(value, span.merge(self.current_span())))
+            }
+            _ => Err(KainError::parser("Expected an integer", self.current_span())),
+        }
+    }
+
+    /// A char literal pattern, or an inclusive or half-open range of them:
+    /// `'a'`, `'a'..='z'`, `'0'..'9'`
+    fn parse_char_pattern(&mut self) -> KainResult<Pattern> {
+        let span = self.current_span();
+        let start = self.parse_char_literal()?;
+        let inclusive = match self.peek_kind() {
+            TokenKind::DotDot => false,
+            TokenKind::DotDotEq => true,
+            _ => return Ok(Pattern::Literal(start)),
+        };
+        self.advance();
+        if !matches!(self.peek_kind(), TokenKind::Char(_)) {
+            return Err(KainError::parser("Expected a char to end the range pattern", self.current_span()));
+        }
+        let end = self.parse_char_literal()?;
+        Ok(Pattern::Range { start: Some(Box::new(start)), end: Some(Box::new(end)), inclusive, span: span.merge(self.current_span()) })
+    }
+
+    fn parse_char_literal(&mut self) -> KainResult<Expr> {
+        let span = self.current_span();
+        let TokenKind::Char(text) = self.peek_kind() else {
+            return Err(KainError::parser("Expected a char literal", span));
+        };
+        let mut chars = text.chars();
+        match (chars.next(), chars.next()) {
+            (Some(c), None) => {
+                self.advance();
+                Ok(Expr::Char(c, span))
+            }
+            _ => Err(KainError::parser(
+                format!("A char literal holds exactly one character, found '{}'; use double quotes for a string", text),
+                span,
+            )),
+        }
+    }
+
     #[allow(dead_code)]
     fn parse_jsx(&mut self) -> KainResult<JSXNode> {
         self.skip_newlines();
@@ -1863,6 +2619,10 @@ impl<'a> Parser<'a> {
     }
 
     fn parse_jsx_element(&mut self) -> KainResult<JSXNode> {
+        self.nested(Self::parse_jsx_element_inner)
+    }
+
+    fn parse_jsx_element_inner(&mut self) -> KainResult<JSXNode> {
         let start = self.current_span();
         self.expect(TokenKind::Lt)?;
         let tag = self.parse_ident()?;
@@ -1988,6 +2748,7 @@ impl<'a> Parser<'a> {
                      TokenKind::Match => consumed_text = Some("match".to_string()),
                      TokenKind::For => consumed_text = Some("for".to_string()),
                      TokenKind::While => consumed_text = Some("while".to_string()),
+                     TokenKind::Unless => consumed_text = Some("unless".to_string()),
                      TokenKind::Loop => consumed_text = Some("loop".to_string()),
                      TokenKind::Break => consumed_text = Some("break".to_string()),
                      TokenKind::Continue => consumed_text = Some("continue".to_string()),
@@ -2053,8 +2814,12 @@ impl<'a> Parser<'a> {
         self.skip_formatting();
         while !self.check(TokenKind::RParen) && !self.at_end() {
             let mut name = None;
-            // Check for named argument: ident = expr
-            if let TokenKind::Ident(s) = self.peek_kind() {
+            // `...xs` passes the elements of `xs` as arguments
+            let spread = self.check(TokenKind::DotDotDot);
+            if spread {
+                self.advance();
+            } else if let TokenKind::Ident(s) = self.peek_kind() {
+                // Check for named argument: ident = expr
                 // Look ahead for '='
                 if self.tokens.get(self.pos + 1).map(|t| t.kind == TokenKind::Eq).unwrap_or(false) {
                     name = Some(s);
@@ -2064,7 +2829,7 @@ impl<'a> Parser<'a> {
             }
             
             let value = self.parse_expr()?;
-            args.push(CallArg { name, value, span: self.current_span() });
+            args.push(CallArg { name, value, spread, span: self.current_span() });
             
             self.skip_formatting();
             if !self.check(TokenKind::RParen) { 
@@ -2088,6 +2853,14 @@ impl<'a> Parser<'a> {
         }
     }
 
+    /// A field after `.`: a name, or a tuple index like `pair.0`
+    fn parse_field_name(&mut self) -> KainResult<String> {
+        match self.peek_kind() {
+            TokenKind::Int(n) if n >= 0 => { self.advance(); Ok(n.to_string()) }
+            _ => self.parse_ident(),
+        }
+    }
+
     fn get_binary_op(&self) -> Option<(BinaryOp, u8)> {
         match self.peek_kind() {
             TokenKind::Or => Some((BinaryOp::Or, 1)),
@@ -2110,7 +2883,25 @@ impl<'a> Parser<'a> {
 
     // Helper methods
     fn peek_kind(&self) -> TokenKind { self.tokens.get(self.pos).map(|t| t.kind.clone()).unwrap_or(TokenKind::Eof) }
-    fn current_span(&self) -> Span { self.tokens.get(self.pos).map(|t| t.span).unwrap_or(Span::new(0, 0)) }
+    fn current_span(&self) -> Span { self.span_at(self.pos) }
+    fn span_at(&self, pos: usize) -> Span { self.tokens.get(pos).map(|t| t.span).unwrap_or(Span::new(0, 0)) }
+    /// Run one level of recursive descent, bounding how deep the source may nest.
+    /// Stack segments grow on demand, so the limit exists for a readable error
+    /// rather than to protect the native stack.
+    fn nested<T>(&mut self, f: impl FnOnce(&mut Self) -> KainResult<T>) -> KainResult<T> {
+        if self.depth >= stack::MAX_NESTING_DEPTH {
+            return Err(KainError::parser(
+                format!("Nesting too deep (more than {} levels)", stack::MAX_NESTING_DEPTH),
+                self.current_span(),
+            ));
+        }
+        self.depth += 1;
+        let result = stack::grow(|| f(self));
+        self.depth -= 1;
+        result
+    }
+
+    fn current_doc(&self) -> Option<String> { self.tokens.get(self.pos).and_then(|t| t.doc.clone()) }
     fn at_end(&self) -> bool { matches!(self.peek_kind(), TokenKind::Eof) }
     fn check(&self, k: TokenKind) -> bool { std::mem::discriminant(&self.peek_kind()) == std::mem::discriminant(&k) }
     fn check_line_end(&self) -> bool { matches!(self.peek_kind(), TokenKind::Newline(_) | TokenKind::Dedent | TokenKind::Eof) }
@@ -2129,3 +2920,79 @@ impl<'a> Parser<'a> {
     }
 }
 
+#[cfg(test)]
+mod tests {
+    use crate::{compile_with_options, edition::Edition, eval_snippet, parse_recoverable, runtime, CompileOptions, CompileTarget};
+
+    #[test]
+    fn test_deep_nesting_reports_instead_of_overflowing() {
+        // A small stack makes an unguarded recursion overflow quickly
+        let handle = std::thread::Builder::new()
+            .stack_size(1024 * 1024)
+            .spawn(|| {
+                let deep = format!("fn main():\n    let x = {}1{}\n", "(".repeat(50_000), ")".repeat(50_000));
+                let outcome = parse_recoverable(&deep);
+                assert!(outcome.diagnostics[0].to_string().contains("Nesting too deep"));
+
+                let ok = format!("fn main():\n    let x = {}1{}\n", "(".repeat(200), ")".repeat(200));
+                assert!(parse_recoverable(&ok).diagnostics.is_empty());
+
+                // Operator chains parse in a loop but still build a tree every later pass recurses into
+                let chain = |terms: usize| format!("pub fn main() -> Int:\n    return {}\n", vec!["1"; terms].join(" + "));
+                for target in [CompileTarget::Interpret, CompileTarget::Wasm] {
+                    let err = compile_with_options(&chain(1000), target, &CompileOptions::default()).unwrap_err();
+                    assert!(err.to_string().contains("Nesting too deep"), "{target:?}: {err}");
+                    compile_with_options(&chain(250), target, &CompileOptions::default()).unwrap();
+                }
+                let result = eval_snippet(&chain(250), &CompileOptions::default());
+                assert!(matches!(result.value, Some(runtime::Value::Int(250))), "{:?}", result.diagnostics);
+            })
+            .unwrap();
+        handle.join().unwrap();
+    }
+
+    #[test]
+    fn test_pipeline_desugars_to_calls() {
+        let source = "fn add(x: Int, y: Int) -> Int:\n    return x + y\n\nfn main():\n    println(3 + 1 |> add(10) |> str, [3, 1, 2] |> sort |> |xs| xs[0])\n";
+        let result = eval_snippet(source, &CompileOptions::default());
+        assert!(result.diagnostics.is_empty(), "{:?}", result.diagnostics);
+        assert_eq!(result.stdout.trim(), "14 1");
+
+        let mistyped = eval_snippet("fn main():\n    println(\"a\" |> sqrt)\n", &CompileOptions::default());
+        assert!(mistyped.diagnostics[0].to_string().contains("sqrt: argument 'x' must be Float, found String"));
+    }
+
+    #[test]
+    fn test_guard_let_and_unless() {
+        let source = "fn half(o) -> Result:\n    guard let Some(x) = o else: return err(\"missing\")\n    unless x % 2 == 0: return err(\"odd\")\n    return ok(x / 2)\n\nfn main():\n    println(half(Some(8)), half(None), half(Some(3)))\n    for item in [1, (2, 3)]:\n        guard let (a, b) = item else:\n            continue\n        println(a + b)\n";
+        let options = CompileOptions { edition: Edition::V0_5, ..Default::default() };
+        let result = eval_snippet(source, &options);
+        assert!(result.diagnostics.is_empty(), "{:?}", result.diagnostics);
+        let lines: Vec<&str> = result.stdout.lines().map(str::trim).collect();
+        assert_eq!(lines, ["Ok(4) Err(missing) Err(odd)", "5"]);
+        let unless_is_a_name = eval_snippet("fn main():\n    let unless = 2\n    println(unless)\n", &CompileOptions::default());
+        assert_eq!(unless_is_a_name.stdout.trim(), "2");
+
+        let falls_through = eval_snippet("fn main():\n    guard let Some(x) = None else: println(1)\n", &CompileOptions::default());
+        assert!(falls_through.diagnostics[0].to_string().contains("guard's else branch must return, break, continue or panic"));
+    }
+
+    #[test]
+    fn test_loop_else_runs_without_break() {
+        let source = "fn find(xs, target) -> String:\n    for x in xs:\n        if x == target:\n            break\n    else:\n        return \"missing\"\n    return \"found\"\n\nfn main():\n    var i = 0\n    while i < 3:\n        i = i + 1\n    else:\n        println(find([1, 2], 2), find([1, 2], 5), i)\n";
+        let result = eval_snippet(source, &CompileOptions::default());
+        assert!(result.diagnostics.is_empty(), "{:?}", result.diagnostics);
+        assert_eq!(result.stdout.trim(), "found missing 3");
+    }
+
+    #[test]
+    fn test_labeled_break_and_continue() {
+        let source = "fn main():\n    var hits = 0\n    'rows: for row in [[1, 0, 5], [2, 3], [-1, 9], [4]]:\n        for cell in row:\n            if cell == 0:\n                continue 'rows\n            if cell < 0:\n                break 'rows\n            hits = hits + 1\n    else:\n        println(\"unreachable\")\n    'spin: loop:\n        while true:\n            break 'spin\n    println(hits)\n";
+        let result = eval_snippet(source, &CompileOptions::default());
+        assert!(result.diagnostics.is_empty(), "{:?}", result.diagnostics);
+        assert_eq!(result.stdout.trim(), "3");
+
+        let result = eval_snippet("fn main():\n    for x in [1]:\n        break 'outer\n", &CompileOptions::default());
+        assert!(result.diagnostics[0].to_string().contains("No enclosing loop is labeled 'outer"));
+    }
+}
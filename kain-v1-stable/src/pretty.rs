@@ -0,0 +1,226 @@
+//! Printing interpreter values for people
+//!
+//! `print`, `println`, `dbg`, traces and runtime errors show values through
+//! [`pretty`], which stops descending past a depth, cuts long arrays short and
+//! marks a value that contains itself instead of recursing into it forever:
+//!
+//! ```text
+//! Node {value: 1, next: Node {value: 2, next: <cycle Node>}}
+//! [0, 1, 2, 3, ... 996 more]
+//! ```
+//!
+//! With an indent, containers are spread over one line per element, the way
+//! Rust's `{:#?}` lays them out. `Display for Value` is the same printer with
+//! no limits, for code that needs a value's full text.
+
+use std::fmt::Write;
+use std::sync::Arc;
+
+use crate::runtime::Value;
+
+/// How much of a value to print, and how to lay it out
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PrettyOptions {
+    /// Containers nested deeper than this print as `[...]`; `None` for no limit
+    pub max_depth: Option<usize>,
+    /// Elements of an array, tuple or struct shown before `... N more`; `None` for all of them
+    pub max_elements: Option<usize>,
+    /// Spaces per level to spread containers over several lines; `None` keeps the value on one line
+    pub indent: Option<usize>,
+}
+
+impl PrettyOptions {
+    /// Everything, on one line
+    pub const FULL: PrettyOptions = PrettyOptions { max_depth: None, max_elements: None, indent: None };
+
+    /// Short enough to quote in an error message or a trace line
+    pub const BRIEF: PrettyOptions = PrettyOptions { max_depth: Some(3), max_elements: Some(10), indent: None };
+}
+
+/// What `print`, `println` and `dbg` show unless told otherwise
+impl Default for PrettyOptions {
+    fn default() -> Self {
+        PrettyOptions { max_depth: Some(32), max_elements: Some(100), indent: None }
+    }
+}
+
+/// `value` as `options` lay it out
+pub fn pretty(value: &Value, options: &PrettyOptions) -> String {
+    let mut printer = Printer { options, out: String::new(), path: Vec::new() };
+    printer.value(value, 0);
+    printer.out
+}
+
+struct Printer<'a> {
+    options: &'a PrettyOptions,
+    out: String,
+    /// The arrays and structs being printed, outermost first, to spot one inside itself
+    path: Vec<usize>,
+}
+
+/// What a container holds: values, or named fields
+enum Shape<'v> {
+    List(&'v [Value]),
+    Fields(Vec<(&'v String, &'v Value)>),
+}
+
+impl Printer<'_> {
+    fn value(&mut self, value: &Value, depth: usize) {
+        crate::stack::grow(|| match value {
+            Value::Array(arr) => {
+                let id = Arc::as_ptr(arr) as *const () as usize;
+                if self.path.contains(&id) {
+                    self.out.push_str("<cycle>");
+                    return;
+                }
+                let items = arr.read().unwrap_or_else(|e| e.into_inner());
+                self.path.push(id);
+                self.container("[", "]", Shape::List(&items[..]), depth);
+                self.path.pop();
+            }
+            Value::Tuple(items) => self.container("(", ")", Shape::List(items), depth),
+            Value::Struct(name, fields) => {
+                let id = Arc::as_ptr(fields) as *const () as usize;
+                if self.path.contains(&id) {
+                    self.write(format_args!("<cycle {}>", name));
+                    return;
+                }
+                let fields = fields.read().unwrap_or_else(|e| e.into_inner());
+                self.path.push(id);
+                self.container(&format!("{} {{", name), "}", Shape::Fields(fields.iter().collect()), depth);
+                self.path.pop();
+            }
+            Value::EnumVariant(enum_name, variant, fields) => {
+                self.write(format_args!("{}::{}", enum_name, variant));
+                if !fields.is_empty() {
+                    self.container("(", ")", Shape::List(fields), depth);
+                }
+            }
+            Value::Result(ok, v) => {
+                self.out.push_str(if *ok { "Ok" } else { "Err" });
+                self.container("(", ")", Shape::List(std::slice::from_ref(&**v)), depth);
+            }
+            Value::Some(v) => {
+                self.out.push_str("Some");
+                self.container("(", ")", Shape::List(std::slice::from_ref(&**v)), depth);
+            }
+            Value::Poll(true, Some(v)) => {
+                self.out.push_str("Poll::Ready");
+                self.container("(", ")", Shape::List(std::slice::from_ref(&**v)), depth);
+            }
+            Value::Return(v) => self.value(v, depth),
+            Value::Break(_, Some(v)) => {
+                self.out.push_str("<break ");
+                self.value(v, depth);
+                self.out.push('>');
+            }
+            Value::Unit => self.out.push_str("()"),
+            Value::Bool(b) => self.out.push_str(if *b { "true" } else { "false" }),
+            Value::Int(i) => self.out.push_str(&i.to_string()),
+            Value::Float(fl) => self.out.push_str(&crate::float::format(*fl)),
+            Value::String(s) => self.out.push_str(s),
+            Value::Char(c) => self.out.push(*c),
+            Value::Range(r) => self.out.push_str(&r.to_string()),
+            Value::Function(name) => self.write(format_args!("<fn {}>", name)),
+            Value::NativeFn(name, _) => self.write(format_args!("<native fn {}>", name)),
+            Value::StructConstructor(name, _) => self.write(format_args!("<constructor {}>", name)),
+            Value::BoundMethod(_, name) => self.write(format_args!("<bound method {}>", name)),
+            Value::ActorRef(r) => self.write(format_args!("<actor {}>", r.id)),
+            Value::None => self.out.push_str("none"),
+            Value::Closure(_, _, _) => self.out.push_str("<closure>"),
+            Value::JSX(node) => self.out.push_str(&node.to_string()),
+            Value::Poll(true, None) => self.out.push_str("Poll::Ready(())"),
+            Value::Poll(false, _) => self.out.push_str("Poll::Pending"),
+            Value::Future(name, _) => self.write(format_args!("<future {}>", name)),
+            Value::Quote(_) => self.out.push_str("<quote>"),
+            Value::Module(path) => self.write(format_args!("<module {}>", path)),
+            Value::Break(_, None) => self.out.push_str("<break>"),
+            Value::Continue(_) => self.out.push_str("<continue>"),
+        })
+    }
+
+    fn container(&mut self, open: &str, close: &str, shape: Shape<'_>, depth: usize) {
+        self.out.push_str(open);
+        let len = match &shape {
+            Shape::List(items) => items.len(),
+            Shape::Fields(fields) => fields.len(),
+        };
+        if len == 0 {
+            self.out.push_str(close);
+            return;
+        }
+        if self.options.max_depth.is_some_and(|max| depth >= max) {
+            self.out.push_str("...");
+            self.out.push_str(close);
+            return;
+        }
+        let shown = self.options.max_elements.map_or(len, |max| len.min(max));
+        for i in 0..shown {
+            self.separator(i, depth + 1);
+            match &shape {
+                Shape::List(items) => self.value(&items[i], depth + 1),
+                Shape::Fields(fields) => {
+                    let (name, value) = fields[i];
+                    self.write(format_args!("{}: ", name));
+                    self.value(value, depth + 1);
+                }
+            }
+        }
+        if shown < len {
+            self.separator(shown, depth + 1);
+            self.write(format_args!("... {} more", len - shown));
+        }
+        if let Some(width) = self.options.indent {
+            self.out.push_str(",\n");
+            self.out.push_str(&" ".repeat(width * depth));
+        }
+        self.out.push_str(close);
+    }
+
+    fn write(&mut self, args: std::fmt::Arguments<'_>) {
+        // Writing to a String cannot fail
+        let _ = self.out.write_fmt(args);
+    }
+
+    /// What goes before the `i`th element of a container `depth` levels down
+    fn separator(&mut self, i: usize, depth: usize) {
+        match self.options.indent {
+            Some(width) => {
+                if i > 0 {
+                    self.out.push(',');
+                }
+                self.out.push('\n');
+                self.out.push_str(&" ".repeat(width * depth));
+            }
+            None if i > 0 => self.out.push_str(", "),
+            None => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{eval_snippet, CompileOptions};
+    use std::sync::RwLock;
+
+    #[test]
+    fn test_pretty_limits_depth_elements_and_cycles() {
+        let array = |items: Vec<Value>| Value::Array(Arc::new(RwLock::new(items)));
+        let long = array((0..150).map(Value::Int).collect());
+        let printed = pretty(&long, &PrettyOptions::default());
+        assert!(printed.starts_with("[0, 1, 2, ") && printed.ends_with(", 99, ... 50 more]"), "{}", printed);
+        assert_eq!(long.to_string().matches(", ").count(), 149);
+
+        let nested = array(vec![array(vec![array(vec![Value::Int(1)])]), Value::Tuple(vec![])]);
+        let options = PrettyOptions { max_depth: Some(2), ..PrettyOptions::default() };
+        assert_eq!(pretty(&nested, &options), "[[[...]], ()]");
+        let options = PrettyOptions { indent: Some(2), ..PrettyOptions::default() };
+        assert_eq!(pretty(&nested, &options), "[\n  [\n    [\n      1,\n    ],\n  ],\n  (),\n]");
+
+        let source = "struct Node:\n    next: Any\n\nfn main():\n    let mut n = Node { next: 0 }\n    n.next = n\n    println(n)\n";
+        let result = eval_snippet(source, &CompileOptions::default());
+        assert!(result.diagnostics.is_empty(), "{:?}", result.diagnostics);
+        assert_eq!(result.stdout.trim_end(), "Node {next: <cycle Node>}");
+    }
+}
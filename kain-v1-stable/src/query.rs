@@ -0,0 +1,349 @@
+//! On-demand, memoized front end queries
+//!
+//! The language server and `kain check` ask a [`Database`] for what they need
+//! instead of running the whole front end on every request:
+//!
+//! - [`Database::parse`]: a file's syntax tree, recovered past syntax errors
+//! - [`Database::typecheck`]: one item of a file, checked
+//! - [`Database::effects`]: the effects a function item was checked to have
+//!
+//! Each result is computed the first time it is asked for and kept. A file is
+//! parsed again only when its text changes, and an item is checked again only
+//! when its own text changes, or when the declarations of the rest of its file
+//! that checking depends on do (see [`ItemContext::fingerprint`]). Editing one
+//! function body rechecks that function and nothing else: the items it moves
+//! keep their results, with their errors moved to where the items now are.
+//! Their typed items keep the positions they were checked at.
+
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use crate::ast::Program;
+use crate::edition::Edition;
+use crate::effects::EffectSet;
+use crate::error::{KainError, KainResult};
+use crate::span::Span;
+use crate::types::{ItemContext, TypedItem};
+use crate::{guard_pass, packager, Lexer, Parser, Stage};
+
+/// A file's syntax tree and the syntax errors recovered from on the way
+#[derive(Debug)]
+pub struct Parsed {
+    /// `None` when lexing failed, so there was nothing to parse
+    pub program: Option<Program>,
+    pub diagnostics: Vec<KainError>,
+}
+
+/// One item checked, or `None` for items with nothing to check
+pub type Checked = KainResult<Option<TypedItem>>;
+
+/// How much work the database has done, for telling a memoized answer from a fresh one
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct QueryStats {
+    pub parses: usize,
+    pub item_checks: usize,
+}
+
+/// Memoized front end results for a set of files
+#[derive(Debug)]
+pub struct Database {
+    /// The edition of files in no project, or whose project doesn't say
+    edition: Edition,
+    files: HashMap<PathBuf, File>,
+    stats: QueryStats,
+}
+
+#[derive(Debug)]
+struct File {
+    /// Set by [`Database::set_text`]; other files are read through the shared source map
+    pinned: bool,
+    text: Arc<str>,
+    /// What the file was parsed under, its project's edition
+    edition: Option<Edition>,
+    parsed: Option<Arc<Parsed>>,
+    context: Option<Result<ItemContext, Arc<KainError>>>,
+    /// Each checked item, with where in the text it was when its result was last given out
+    items: HashMap<ItemKey, (usize, Arc<Checked>)>,
+}
+
+/// Everything an item's check depends on
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct ItemKey {
+    context: u64,
+    name: Option<String>,
+    /// Hash of the item's text
+    text: u64,
+}
+
+impl File {
+    fn new() -> Self {
+        File { pinned: false, text: "".into(), edition: None, parsed: None, context: None, items: HashMap::new() }
+    }
+
+    /// Replace the text, dropping what was derived from the old one. Checked
+    /// items are kept: an item that reads the same is still checked the same.
+    fn set_text(&mut self, text: Arc<str>) {
+        if *self.text != *text {
+            self.text = text;
+            self.parsed = None;
+            self.context = None;
+        }
+    }
+}
+
+impl Database {
+    pub fn new(edition: Edition) -> Self {
+        Database { edition, files: HashMap::new(), stats: QueryStats::default() }
+    }
+
+    /// Use `text` for `path` from now on, such as an editor's unsaved buffer
+    pub fn set_text(&mut self, path: impl Into<PathBuf>, text: impl Into<Arc<str>>) {
+        let file = self.files.entry(path.into()).or_insert_with(File::new);
+        file.pinned = true;
+        file.set_text(text.into());
+    }
+
+    /// Forget `path` and everything computed from it
+    pub fn remove(&mut self, path: &Path) {
+        self.files.remove(path);
+    }
+
+    pub fn stats(&self) -> QueryStats {
+        self.stats
+    }
+
+    /// The file at `path`, its text brought up to date
+    fn file(&mut self, path: &Path) -> KainResult<&mut File> {
+        let file = self.files.entry(path.to_path_buf()).or_insert_with(File::new);
+        if !file.pinned {
+            file.set_text(crate::vfs::read(path)?.text.clone());
+        }
+        Ok(file)
+    }
+
+    /// Lex and parse `path` under its project's edition, recovering from
+    /// syntax errors. Fails only if the file or its project's manifest cannot
+    /// be read.
+    pub fn parse(&mut self, path: &Path) -> KainResult<Arc<Parsed>> {
+        let edition = packager::file_edition(path, self.edition)?;
+        let file = self.file(path)?;
+        if file.edition != Some(edition) {
+            file.edition = Some(edition);
+            file.parsed = None;
+            file.context = None;
+        }
+        if let Some(parsed) = &file.parsed {
+            return Ok(parsed.clone());
+        }
+        let parsed = Arc::new(parse(&file.text, edition));
+        file.parsed = Some(parsed.clone());
+        self.stats.parses += 1;
+        Ok(parsed)
+    }
+
+    /// Type check item `index` of `path`; `None` if there is no such item or
+    /// the file's declarations could not be gathered (reported by [`Database::check`])
+    pub fn typecheck(&mut self, path: &Path, index: usize) -> KainResult<Option<Arc<Checked>>> {
+        let parsed = self.parse(path)?;
+        let Some(item) = parsed.program.as_ref().and_then(|program| program.items.get(index)) else {
+            return Ok(None);
+        };
+        let file = self.files.get_mut(path).expect("parsing keeps the file");
+        let text = &file.text;
+        let context = file.context.get_or_insert_with(|| {
+            let program = parsed.program.as_ref().expect("the item came from it");
+            guard_pass(Stage::TypeChecker, text, || ItemContext::new(program)).map_err(Arc::new)
+        });
+        let Ok(context) = context else {
+            return Ok(None);
+        };
+
+        let span = item.span();
+        let mut hasher = DefaultHasher::new();
+        text.get(span.start..span.end).unwrap_or_default().hash(&mut hasher);
+        let key = ItemKey { context: context.fingerprint(), name: item.name().map(str::to_string), text: hasher.finish() };
+        if let Some((start, checked)) = file.items.get_mut(&key) {
+            if *start != span.start {
+                *checked = Arc::new(relocate(checked, *start, span.start));
+                *start = span.start;
+            }
+            return Ok(Some(checked.clone()));
+        }
+        let checked = Arc::new(guard_pass(Stage::TypeChecker, text, || context.check(item)));
+        file.items.insert(key, (span.start, checked.clone()));
+        self.stats.item_checks += 1;
+        Ok(Some(checked))
+    }
+
+    /// Effects of function item `index` of `path`, if it is one and checks
+    pub fn effects(&mut self, path: &Path, index: usize) -> KainResult<Option<EffectSet>> {
+        Ok(self.typecheck(path, index)?.and_then(|checked| match &*checked {
+            Ok(Some(TypedItem::Function(f))) => Some(f.effects.clone()),
+            _ => None,
+        }))
+    }
+
+    /// Parse and check every item of `path`
+    pub fn check(&mut self, path: &Path) -> KainResult<FileCheck> {
+        let parsed = self.parse(path)?;
+        let count = parsed.program.as_ref().map_or(0, |program| program.items.len());
+        let mut items = Vec::new();
+        for index in 0..count {
+            items.extend(self.typecheck(path, index)?);
+        }
+
+        let file = self.files.get_mut(path).expect("parsing keeps the file");
+        // Results for items the file no longer has would otherwise pile up
+        file.items.retain(|_, (_, checked)| items.iter().any(|live| Arc::ptr_eq(live, checked)));
+        let context = match &file.context {
+            Some(Err(e)) => Some(e.clone()),
+            _ => None,
+        };
+        Ok(FileCheck { parsed, context, items })
+    }
+}
+
+/// Everything [`Database::check`] found in one file
+#[derive(Debug)]
+pub struct FileCheck {
+    pub parsed: Arc<Parsed>,
+    /// Why the file's declarations could not be gathered, leaving its items unchecked
+    pub context: Option<Arc<KainError>>,
+    pub items: Vec<Arc<Checked>>,
+}
+
+impl FileCheck {
+    /// Syntax errors, then declaration errors, then the errors of each item in order
+    pub fn errors(&self) -> impl Iterator<Item = &KainError> {
+        let items = self.items.iter().filter_map(|checked| match &**checked {
+            Err(e) => Some(e),
+            Ok(_) => None,
+        });
+        self.parsed.diagnostics.iter().chain(self.context.as_deref()).chain(items)
+    }
+}
+
+/// `checked` for an item checked at offset `from` that now starts at `to`
+fn relocate(checked: &Checked, from: usize, to: usize) -> Checked {
+    let moved = |span: Span| Span { start: (span.start + to).saturating_sub(from), end: (span.end + to).saturating_sub(from), ..span };
+    let error = match checked {
+        Ok(item) => return Ok(item.clone()),
+        Err(e) => e,
+    };
+    Err(match error {
+        KainError::Lexer { message, span } => KainError::Lexer { message: message.clone(), span: moved(*span) },
+        KainError::Parser { message, span } => KainError::Parser { message: message.clone(), span: moved(*span) },
+        KainError::Type { message, span } => KainError::Type { message: message.clone(), span: moved(*span) },
+        KainError::Effect { message, span } => KainError::Effect { message: message.clone(), span: moved(*span) },
+        KainError::Borrow { message, span, related } => KainError::Borrow {
+            message: message.clone(),
+            span: moved(*span),
+            related: related.as_ref().map(|(span, note)| (moved(*span), note.clone())),
+        },
+        KainError::Codegen { message, span } => KainError::Codegen { message: message.clone(), span: moved(*span) },
+        KainError::Internal { code, phase, message, span } => {
+            KainError::Internal { code: *code, phase: *phase, message: message.clone(), span: moved(*span) }
+        }
+        KainError::Runtime { message } => KainError::Runtime { message: message.clone() },
+        KainError::Io(e) => KainError::Io(std::io::Error::new(e.kind(), e.to_string())),
+    })
+}
+
+/// Like [`crate::parse_recoverable`], without the type check
+fn parse(text: &str, edition: Edition) -> Parsed {
+    let tokens = match guard_pass(Stage::Lexer, text, || Lexer::with_edition(text, edition).tokenize()) {
+        Ok(tokens) => tokens,
+        Err(e) => return Parsed { program: None, diagnostics: vec![e] },
+    };
+    match guard_pass(Stage::Parser, text, || Ok(Parser::new(&tokens).parse_recovering())) {
+        Ok((program, diagnostics)) => Parsed { program: Some(program), diagnostics },
+        Err(e) => Parsed { program: None, diagnostics: vec![e] },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_only_changed_items_are_checked_again() {
+        let path = Path::new("query_test.kn");
+        let mut db = Database::new(Edition::LATEST);
+        let source = "fn double(x: Int) -> Int:\n    return x * 2\n\nfn triple(x: Int) -> Int:\n    return x * 3\n\nfn main():\n    println(double(1) + triple(1))\n";
+        db.set_text(path, source);
+        let check = db.check(path).unwrap();
+        assert_eq!(check.errors().count(), 0);
+        assert_eq!(db.stats(), QueryStats { parses: 1, item_checks: 3 });
+
+        // The same text is neither parsed nor checked again
+        db.set_text(path, source);
+        db.check(path).unwrap();
+        assert_eq!(db.stats(), QueryStats { parses: 1, item_checks: 3 });
+
+        // Editing the last function leaves the two above it alone
+        db.set_text(path, source.replace("double(1) +", "double(2) +"));
+        db.check(path).unwrap();
+        assert_eq!(db.stats(), QueryStats { parses: 2, item_checks: 4 });
+
+        // Growing the first one moves the others, which keep their results
+        db.set_text(path, source.replace("x * 2", "x * 2 + 0"));
+        db.check(path).unwrap();
+        assert_eq!(db.stats(), QueryStats { parses: 3, item_checks: 5 });
+        assert!(db.effects(path, 2).unwrap().is_some());
+
+        // A new static changes what every item is checked against, even where none moved
+        let grown = source.replace("x * 2", "x * 2 + 0");
+        db.set_text(path, format!("{}\nstatic mut counter: Int = 0\n", grown));
+        let check = db.check(path).unwrap();
+        assert_eq!(check.errors().count(), 0);
+        assert_eq!(db.stats().item_checks, 9);
+
+        // The errors of an item that moved point at where it is now
+        let broken = "fn f() -> Int:\n    return 1\n\nfn g() -> Int:\n    return \"no\"\n";
+        db.set_text(path, broken);
+        let starts = |check: &FileCheck| -> Vec<usize> {
+            check.errors().map(|e| match e {
+                KainError::Type { span, .. } => span.start,
+                e => panic!("expected a type error, got {}", e),
+            }).collect()
+        };
+        let before = starts(&db.check(path).unwrap());
+        db.set_text(path, broken.replace("return 1", "return 1 + 0"));
+        let after = starts(&db.check(path).unwrap());
+        assert_eq!(db.stats().item_checks, 12);
+        assert_eq!(before.len(), 1);
+        assert_eq!(after, vec![before[0] + 4]);
+
+        // Syntax errors, declaration errors and item errors are all reported
+        db.set_text(path, "static mut counter: Int = 0\n\nfn bump() with IO:\n    counter = counter + 1\n\nfn main():\n    let = 1\n");
+        let check = db.check(path).unwrap();
+        assert!(check.context.is_none());
+        let errors: Vec<String> = check.errors().map(|e| e.to_string()).collect();
+        assert_eq!(errors.len(), 2, "{:?}", errors);
+        db.set_text(path, "fn f() with Network:\n    return\n");
+        let check = db.check(path).unwrap();
+        assert!(check.items.is_empty());
+        assert!(check.errors().any(|e| e.to_string().contains("Unknown effect `Network`")));
+    }
+
+    #[test]
+    fn test_files_are_parsed_under_their_project_edition() {
+        let dir = std::env::temp_dir().join(format!("kain-query-editions-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("src")).unwrap();
+        std::fs::write(dir.join("KAIN.toml"), "[package]\nname = \"app\"\nversion = \"0.1.0\"\nlanguage_version = \"0.5\"\n").unwrap();
+        let source = "fn main():\n    let unless = 1\n    println(unless)\n";
+        let mut db = Database::new(Edition::V0_4);
+
+        // `unless` is a keyword in the project's edition, not the database's
+        let in_project = dir.join("src/main.kn");
+        db.set_text(&in_project, source);
+        assert!(db.check(&in_project).unwrap().errors().count() > 0);
+        let loose = std::env::temp_dir().join(format!("kain-query-loose-{}.kn", std::process::id()));
+        db.set_text(&loose, source);
+        assert_eq!(db.check(&loose).unwrap().errors().count(), 0);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,515 @@
+//! Region escape checking
+//!
+//! `region:` frees the structs, arrays, tuples, vectors and enum payloads its
+//! body allocates when it exits, so none of them may outlive it. This pass rejects
+//! assigning such a value to a binding declared outside the region, or to a
+//! field or element of one, pushing or inserting it into one, returning it,
+//! sending it in a message and giving it to a spawned actor. Each error points
+//! at the region the value was allocated in.
+//!
+//! A value belongs to the innermost region whose body made it. Functions
+//! allocate outside of their caller's regions, so a call's result belongs to
+//! the innermost region of the arguments it was given. Numbers, booleans,
+//! characters and strings are copied out of a region freely, and so are
+//! fields and elements of those types. Closures are not analyzed, nor are the
+//! items of imported modules linked into the program.
+
+use crate::ast::*;
+use crate::ast::visit::{walk_expr, walk_pattern, walk_stmt, Visitor};
+use crate::error::{KainError, KainResult};
+use crate::modules;
+use crate::span::Span;
+use std::collections::{HashMap, HashSet};
+
+/// Report the first value in `program` that outlives the region it was allocated in
+pub fn check(program: &Program) -> KainResult<()> {
+    let declarations = Declarations::of(program);
+    let mut escapes = Vec::new();
+    for item in program.items.iter().filter(|item| !modules::is_linked(item)) {
+        check_item(&declarations, item, &mut escapes);
+    }
+    match escapes.into_iter().min_by_key(|e| e.span.start) {
+        Some(e) => Err(KainError::borrow_conflict(e.message, e.span, e.region, "allocated in this region, which frees it on exit")),
+        None => Ok(()),
+    }
+}
+
+/// A value leaving the region it was allocated in
+struct Escape {
+    message: String,
+    span: Span,
+    region: Span,
+}
+
+/// What the program declares that tells which values point into a region
+#[derive(Default)]
+struct Declarations {
+    /// Structs and enums with payloads, whose values are allocated
+    owned: HashSet<String>,
+    /// The type of each field of each struct
+    fields: HashMap<String, HashMap<String, Type>>,
+    /// The return type of each function and method
+    returns: HashMap<String, Type>,
+}
+
+impl Declarations {
+    fn of(program: &Program) -> Self {
+        let mut declarations = Declarations::default();
+        for item in &program.items {
+            let item = match item {
+                Item::Cfg(c) => &*c.item,
+                item => item,
+            };
+            match item {
+                Item::Struct(s) => {
+                    declarations.owned.insert(s.name.clone());
+                    let fields = s.fields.iter().map(|f| (f.name.clone(), f.ty.clone())).collect();
+                    declarations.fields.insert(s.name.clone(), fields);
+                }
+                Item::Enum(e) if e.variants.iter().any(|v| !matches!(v.fields, VariantFields::Unit)) => {
+                    declarations.owned.insert(e.name.clone());
+                }
+                Item::Function(f) => {
+                    if let Some(ty) = &f.return_type {
+                        declarations.returns.insert(f.name.clone(), ty.clone());
+                    }
+                }
+                Item::Impl(imp) => {
+                    for method in &imp.methods {
+                        if let Some(ty) = &method.return_type {
+                            declarations.returns.insert(method.name.clone(), ty.clone());
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+        declarations
+    }
+
+    /// Whether values of `ty` are pointers to what was allocated for them
+    fn owned_type(&self, ty: &Type) -> bool {
+        match ty {
+            Type::Named { name, generics, .. } => {
+                self.owned.contains(name) || matches!(name.as_str(), "Array" | "Map" | "Box") || generics.iter().any(|t| self.owned_type(t))
+            }
+            Type::Array(..) | Type::Slice(..) | Type::Tuple(..) | Type::Option(..) | Type::Result(..) => true,
+            _ => false,
+        }
+    }
+
+    /// The struct values of `ty` are, if it names one
+    fn struct_name(&self, ty: &Type) -> Option<String> {
+        match ty {
+            Type::Named { name, .. } if self.fields.contains_key(name) => Some(name.clone()),
+            _ => None,
+        }
+    }
+}
+
+/// Where a value lives, as far as its expression tells
+#[derive(Debug, Clone, Default)]
+struct Held {
+    /// Depth of the region it was allocated in, 0 when it outlives every region
+    region: usize,
+    /// The struct it is, when known
+    struct_name: Option<String>,
+    /// Whether its fields or elements are copied out of it rather than pointing into its region
+    copied_elements: bool,
+}
+
+impl Held {
+    fn copied() -> Self {
+        Held::default()
+    }
+
+    fn allocated(region: usize) -> Self {
+        Held { region, ..Held::default() }
+    }
+}
+
+struct Binding {
+    /// Depth of the regions enclosing its declaration
+    depth: usize,
+    held: Held,
+}
+
+fn check_item(declarations: &Declarations, item: &Item, escapes: &mut Vec<Escape>) {
+    let mut body = |params: &[Param], block: &Block| {
+        let mut checker = Checker::new(declarations);
+        for param in params {
+            let held = Held { struct_name: declarations.struct_name(&param.ty), ..Held::copied() };
+            checker.bind(&param.name, held);
+        }
+        checker.visit_block(block);
+        escapes.extend(checker.escapes);
+    };
+    match item {
+        Item::Function(f) => body(&f.params, &f.body),
+        Item::Impl(imp) => {
+            for method in &imp.methods {
+                body(&method.params, &method.body);
+            }
+        }
+        Item::Trait(def) => {
+            for method in &def.methods {
+                if let Some(block) = &method.default_impl {
+                    body(&method.params, block);
+                }
+            }
+        }
+        Item::Actor(actor) => {
+            for handler in &actor.handlers {
+                body(&handler.params, &handler.body);
+            }
+        }
+        Item::Component(c) => {
+            for method in &c.methods {
+                body(&method.params, &method.body);
+            }
+        }
+        Item::Test(t) => body(&[], &t.body),
+        Item::Cfg(c) => check_item(declarations, &c.item, escapes),
+        _ => {}
+    }
+}
+
+struct Checker<'a> {
+    declarations: &'a Declarations,
+    scopes: Vec<HashMap<String, Binding>>,
+    /// The regions enclosing the code being checked, innermost last
+    regions: Vec<Span>,
+    /// What the names a pattern binds hold
+    binding: Held,
+    escapes: Vec<Escape>,
+}
+
+impl<'a> Checker<'a> {
+    fn new(declarations: &'a Declarations) -> Self {
+        Checker { declarations, scopes: vec![HashMap::new()], regions: Vec::new(), binding: Held::copied(), escapes: Vec::new() }
+    }
+
+    fn bind(&mut self, name: &str, held: Held) {
+        let depth = self.regions.len();
+        if let Some(scope) = self.scopes.last_mut() {
+            scope.insert(name.to_string(), Binding { depth, held });
+        }
+    }
+
+    fn lookup(&self, name: &str) -> Option<&Binding> {
+        self.scopes.iter().rev().find_map(|scope| scope.get(name))
+    }
+
+    fn lookup_mut(&mut self, name: &str) -> Option<&mut Binding> {
+        self.scopes.iter_mut().rev().find_map(|scope| scope.get_mut(name))
+    }
+
+    /// Bind the names `pattern` declares to parts of a value held as `held`
+    fn bind_pattern(&mut self, pattern: &Pattern, held: Held) {
+        let previous = std::mem::replace(&mut self.binding, held);
+        self.visit_pattern(pattern);
+        self.binding = previous;
+    }
+
+    fn report(&mut self, message: String, span: Span, held: &Held) {
+        let region = self.regions.get(held.region - 1).copied().unwrap_or(span);
+        self.escapes.push(Escape { message, span, region });
+    }
+
+    /// Report `value` escaping into a binding, field or element rooted at `target`
+    fn store(&mut self, target: &Expr, value: &Expr, span: Span) {
+        let Some(name) = root(target) else { return };
+        let held = self.value(value);
+        let Some(binding) = self.lookup_mut(name) else { return };
+        if held.region > binding.depth {
+            let message = format!("value allocated in a region escapes into `{}`, which outlives it", name);
+            self.report(message, span, &held);
+        } else if matches!(target, Expr::Ident(..)) {
+            binding.held = held;
+        } else {
+            binding.held.region = binding.held.region.max(held.region);
+            binding.held.copied_elements &= held.region == 0;
+        }
+    }
+
+    /// Report `value` outliving every region, as what a function returns or an actor gets does
+    fn leave(&mut self, value: &Expr, what: &str) {
+        let held = self.value(value);
+        if held.region > 0 {
+            self.report(format!("cannot {} a value allocated in a region", what), value.span(), &held);
+        }
+    }
+
+    /// Whether calling `callee` stores its last argument in its first: the standard library's `push` and `insert`
+    fn pushes(&self, callee: &Expr) -> bool {
+        matches!(callee, Expr::Ident(name, _) if matches!(name.as_str(), "push" | "insert") && self.lookup(name).is_none())
+    }
+
+    /// Where the value `expr` evaluates to lives
+    fn value(&self, expr: &Expr) -> Held {
+        let depth = self.regions.len();
+        match expr {
+            Expr::Struct { name, .. } => Held { struct_name: Some(name.clone()), ..Held::allocated(depth) },
+            Expr::Array(items, _) | Expr::Tuple(items, _) => Held {
+                copied_elements: items.iter().all(|e| self.value(e).region == 0),
+                ..Held::allocated(depth)
+            },
+            Expr::EnumVariant { fields: EnumVariantFields::Unit, .. } => Held::copied(),
+            Expr::EnumVariant { .. } | Expr::JSX(..) => Held::allocated(depth),
+            Expr::Ident(name, _) => self.lookup(name).map(|b| b.held.clone()).unwrap_or_default(),
+            Expr::Field { object, field, .. } => {
+                let object = self.value(object);
+                let ty = object.struct_name.as_ref().and_then(|s| self.declarations.fields.get(s)?.get(field));
+                match ty {
+                    _ if object.region == 0 => Held::copied(),
+                    Some(ty) if !self.declarations.owned_type(ty) => Held::copied(),
+                    Some(ty) => Held { struct_name: self.declarations.struct_name(ty), ..Held::allocated(object.region) },
+                    None if field.parse::<usize>().is_ok() && object.copied_elements => Held::copied(),
+                    None => Held::allocated(object.region),
+                }
+            }
+            Expr::Index { object, .. } => {
+                let object = self.value(object);
+                if object.copied_elements { Held::copied() } else { Held::allocated(object.region) }
+            }
+            Expr::Call { callee, args, .. } => match &**callee {
+                Expr::Ident(name, _) if matches!(name.as_str(), "Some" | "Ok" | "Err") => Held {
+                    copied_elements: args.iter().all(|a| self.value(&a.value).region == 0),
+                    ..Held::allocated(depth)
+                },
+                Expr::Ident(name, _) if matches!(name.as_str(), "vec2" | "vec3" | "vec4") => Held {
+                    copied_elements: true,
+                    ..Held::allocated(depth)
+                },
+                Expr::Ident(name, _) if self.lookup(name).is_none() => self.returned(name, args.iter().map(|a| &a.value)),
+                _ => Held::copied(),
+            },
+            Expr::MethodCall { receiver, method, args, .. } => {
+                self.returned(method, std::iter::once(&**receiver).chain(args.iter().map(|a| &a.value)))
+            }
+            Expr::Paren(inner, _)
+            | Expr::Ref { value: inner, .. }
+            | Expr::Deref(inner, _)
+            | Expr::Cast { value: inner, .. }
+            | Expr::Try(inner, _)
+            | Expr::Await(inner, _) => self.value(inner),
+            Expr::If { then_branch, else_branch, .. } => {
+                let mut region = self.tail(then_branch).region;
+                let mut branch = else_branch.as_deref();
+                while let Some(b) = branch {
+                    branch = match b {
+                        ElseBranch::Else(block) => {
+                            region = region.max(self.tail(block).region);
+                            None
+                        }
+                        ElseBranch::ElseIf(_, block, next) => {
+                            region = region.max(self.tail(block).region);
+                            next.as_deref()
+                        }
+                    };
+                }
+                Held::allocated(region)
+            }
+            Expr::Match { arms, .. } => Held::allocated(arms.iter().map(|arm| self.value(&arm.body).region).max().unwrap_or(0)),
+            Expr::Block(block, _) => self.tail(block),
+            _ => Held::copied(),
+        }
+    }
+
+    /// Where the value a block ends with lives
+    fn tail(&self, block: &Block) -> Held {
+        match block.stmts.last() {
+            Some(Stmt::Expr(e)) => self.value(e),
+            _ => Held::copied(),
+        }
+    }
+
+    /// Where the result of calling `name` with `args` lives: functions
+    /// allocate outside of any region, but may give back what they were given
+    fn returned<'e>(&self, name: &str, args: impl Iterator<Item = &'e Expr>) -> Held {
+        let Some(ty) = self.declarations.returns.get(name).filter(|ty| self.declarations.owned_type(ty)) else {
+            return Held::copied();
+        };
+        let region = args.map(|e| self.value(e).region).max().unwrap_or(0);
+        Held { struct_name: self.declarations.struct_name(ty), ..Held::allocated(region) }
+    }
+}
+
+/// The binding an assignment target or `push` receiver is part of
+fn root(expr: &Expr) -> Option<&str> {
+    match expr {
+        Expr::Ident(name, _) => Some(name),
+        Expr::Field { object, .. } | Expr::Index { object, .. } | Expr::Paren(object, _) | Expr::Deref(object, _) => root(object),
+        _ => None,
+    }
+}
+
+impl Visitor for Checker<'_> {
+    fn visit_block(&mut self, block: &Block) {
+        self.scopes.push(HashMap::new());
+        for stmt in &block.stmts {
+            self.visit_stmt(stmt);
+        }
+        self.scopes.pop();
+    }
+
+    fn visit_stmt(&mut self, stmt: &Stmt) {
+        match stmt {
+            Stmt::Let { pattern, ty, value, .. } => {
+                if let Some(value) = value {
+                    self.visit_expr(value);
+                }
+                let mut held = value.as_ref().map(|v| self.value(v)).unwrap_or_default();
+                if let Some(name) = ty.as_ref().and_then(|ty| self.declarations.struct_name(ty)) {
+                    held.struct_name = Some(name);
+                }
+                self.bind_pattern(pattern, held);
+            }
+            Stmt::Return(Some(value), _) => {
+                self.visit_expr(value);
+                self.leave(value, "return");
+            }
+            Stmt::For { binding, iter, body, else_branch, .. } => {
+                self.visit_expr(iter);
+                let iter = self.value(iter);
+                let element = if iter.copied_elements { Held::copied() } else { Held::allocated(iter.region) };
+                self.scopes.push(HashMap::new());
+                self.bind_pattern(binding, element);
+                self.visit_block(body);
+                self.scopes.pop();
+                if let Some(b) = else_branch {
+                    self.visit_block(b);
+                }
+            }
+            Stmt::Region { body, span } => {
+                self.regions.push(*span);
+                self.visit_block(body);
+                self.regions.pop();
+            }
+            Stmt::Item(item) => {
+                let mut escapes = Vec::new();
+                check_item(self.declarations, item, &mut escapes);
+                self.escapes.extend(escapes);
+            }
+            _ => walk_stmt(self, stmt),
+        }
+    }
+
+    fn visit_expr(&mut self, expr: &Expr) {
+        match expr {
+            Expr::Assign { target, value, span } => {
+                walk_expr(self, expr);
+                self.store(target, value, *span);
+            }
+            Expr::Return(Some(value), _) => {
+                self.visit_expr(value);
+                self.leave(value, "return");
+            }
+            Expr::SendMsg { data, .. } => {
+                walk_expr(self, expr);
+                for (_, value) in data {
+                    self.leave(value, "send");
+                }
+            }
+            Expr::Spawn { init, .. } => {
+                walk_expr(self, expr);
+                for (_, value) in init {
+                    self.leave(value, "give a spawned actor");
+                }
+            }
+            Expr::Call { callee, args, span } if self.pushes(callee) && args.len() > 1 => {
+                walk_expr(self, expr);
+                self.store(&args[0].value, &args[args.len() - 1].value, *span);
+            }
+            Expr::MethodCall { receiver, method, args, span } if matches!(method.as_str(), "push" | "insert") => {
+                walk_expr(self, expr);
+                if let Some(value) = args.last() {
+                    self.store(receiver, &value.value, *span);
+                }
+            }
+            Expr::Match { scrutinee, arms, .. } => {
+                self.visit_expr(scrutinee);
+                let held = self.value(scrutinee);
+                let part = if held.copied_elements { Held::copied() } else { Held::allocated(held.region) };
+                for arm in arms {
+                    self.scopes.push(HashMap::new());
+                    self.bind_pattern(&arm.pattern, part.clone());
+                    if let Some(guard) = &arm.guard {
+                        self.visit_expr(guard);
+                    }
+                    self.visit_expr(&arm.body);
+                    self.scopes.pop();
+                }
+            }
+            // Closures are not analyzed
+            Expr::Lambda { .. } => {}
+            _ => walk_expr(self, expr),
+        }
+    }
+
+    fn visit_pattern(&mut self, pattern: &Pattern) {
+        match pattern {
+            Pattern::Binding { name, .. } => {
+                let held = self.binding.clone();
+                self.bind(name, held);
+            }
+            // Every alternative binds the same names to the same value
+            Pattern::Or(..) => walk_pattern(self, pattern),
+            Pattern::Wildcard(_) | Pattern::Literal(_) | Pattern::Range { .. } => {}
+            // The names the other patterns bind hold the value's fields and elements
+            _ => {
+                let part = if self.binding.copied_elements { Held::copied() } else { Held::allocated(self.binding.region) };
+                if let Pattern::Slice { rest: Some(rest), .. } = pattern {
+                    self.bind(rest, Held::allocated(self.binding.region));
+                }
+                let whole = std::mem::replace(&mut self.binding, part);
+                walk_pattern(self, pattern);
+                self.binding = whole;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Lexer, Parser};
+
+    fn check_source(source: &str) -> KainResult<()> {
+        let tokens = Lexer::new(source).tokenize()?;
+        let program = Parser::new(&tokens).parse()?;
+        check(&program)
+    }
+
+    #[test]
+    fn test_values_cannot_outlive_their_region() {
+        let point = "struct P:\n    x: Int\n    y: Int\n\nfn keep(p: P) -> P:\n    return p\n\nactor Sink:\n    on take(p: P):\n        println(p.x)\n\n";
+        let escapes = |body: &str| {
+            let source = format!("{}{}", point, body);
+            match check_source(&source).unwrap_err() {
+                KainError::Borrow { message, related: Some((related, _)), .. } => {
+                    assert_eq!(related.start, source.find("region:").unwrap());
+                    message
+                }
+                err => panic!("expected an escape, got {}", err),
+            }
+        };
+
+        let message = escapes("fn main():\n    var last = P { x: 0, y: 0 }\n    region:\n        last = P { x: 1, y: 2 }\n");
+        assert_eq!(message, "value allocated in a region escapes into `last`, which outlives it");
+        let message = escapes("fn main():\n    var all = [P { x: 0, y: 0 }]\n    region:\n        let p = keep(P { x: 1, y: 2 })\n        all.push(p)\n");
+        assert_eq!(message, "value allocated in a region escapes into `all`, which outlives it");
+        let message = escapes("fn make() -> P:\n    region:\n        let ps = [P { x: 1, y: 2 }]\n        return ps[0]\n    return P { x: 0, y: 0 }\n");
+        assert_eq!(message, "cannot return a value allocated in a region");
+        let message = escapes("fn main():\n    let sink = spawn Sink()\n    region:\n        send sink.take(p = P { x: 1, y: 2 })\n");
+        assert_eq!(message, "cannot send a value allocated in a region");
+
+        // Copies out of a region, values allocated outside it and what a
+        // function allocates on its own are fine
+        check_source(&format!(
+            "{}fn main():\n    var total = 0\n    var kept = P {{ x: 0, y: 0 }}\n    for i in 0..10:\n        region:\n            let p = P {{ x: i, y: i }}\n            let xs = [p.x, p.y]\n            total = total + p.y + xs[0]\n            kept = keep(kept)\n            region:\n                var inner = P {{ x: 0, y: 0 }}\n                inner = p\n    println(total, kept.x)\n",
+            point
+        ))
+        .unwrap();
+    }
+}
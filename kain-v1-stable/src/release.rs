@@ -0,0 +1,380 @@
+//! Compiler releases: installing other versions, switching between them and
+//! updating in place (`kain toolchain`, `kain self update`)
+//!
+//! Prebuilt compilers are listed in a release index, `KAIN_RELEASES_URL` or
+//! the one next to the package registry, by version and platform:
+//!
+//! ```json
+//! {"versions": {"0.1.3": {"x86_64-linux": {"url": "https://...", "sha256": "9f86d0..."}}}}
+//! ```
+//!
+//! Each build is a `.tar.gz` holding the `kain` binary, checked against its
+//! SHA-256 before anything is unpacked. Installed versions live under
+//! `~/.kain/toolchains/<version>` (`KAIN_HOME` moves the whole directory).
+//!
+//! A project pins a version with `compiler = "0.1.3"` under `[package]` in
+//! KAIN.toml. `kain` run in the project hands the command line to that
+//! version, and outside a project to the default `kain toolchain default`
+//! sets, so a team builds with the same compiler whichever one is on PATH.
+
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::fs;
+use std::io::Read;
+use std::path::PathBuf;
+
+use flate2::read::GzDecoder;
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+
+use crate::error::{KainError, KainResult};
+use crate::packager;
+
+const RELEASES_URL: &str = "https://greeble.co/KAIN/releases.json";
+
+/// Set for a compiler started by [`hand_off`], so it runs the command itself
+const ACTIVE_VAR: &str = "KAIN_TOOLCHAIN";
+
+#[derive(Debug, Deserialize)]
+pub struct ReleaseIndex {
+    /// Builds of each version, by [`platform`]
+    pub versions: HashMap<String, HashMap<String, Download>>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Download {
+    pub url: String,
+    /// Hex SHA-256 of the archive
+    pub sha256: String,
+}
+
+impl ReleaseIndex {
+    pub fn fetch(url: &str) -> KainResult<Self> {
+        let bytes = fetch(url)?;
+        serde_json::from_slice(&bytes).map_err(|e| KainError::runtime(format!("Failed to parse the release index {}: {}", url, e)))
+    }
+
+    /// The newest version built for this platform
+    pub fn latest(&self) -> Option<&str> {
+        self.versions
+            .iter()
+            .filter(|(_, builds)| builds.contains_key(&platform()))
+            .map(|(version, _)| version.as_str())
+            .max_by(|a, b| compare_versions(a, b))
+    }
+
+    /// The build of `version` for this platform
+    pub fn download(&self, version: &str) -> KainResult<&Download> {
+        let builds = self.versions.get(version).ok_or_else(|| {
+            KainError::runtime(format!("No kain {} in the release index, it has: {}", version, sorted(self.versions.keys().cloned()).join(", ")))
+        })?;
+        builds.get(&platform()).ok_or_else(|| KainError::runtime(format!("kain {} has no build for {}", version, platform())))
+    }
+}
+
+/// Where the release index is read from
+pub fn index_url() -> String {
+    std::env::var("KAIN_RELEASES_URL").unwrap_or_else(|_| RELEASES_URL.to_string())
+}
+
+/// This machine, as the release index names platforms: `x86_64-linux`, `aarch64-macos`, ...
+pub fn platform() -> String {
+    format!("{}-{}", std::env::consts::ARCH, std::env::consts::OS)
+}
+
+/// `KAIN_HOME`, or `.kain` in the home directory
+pub fn home() -> PathBuf {
+    if let Some(dir) = std::env::var_os("KAIN_HOME") {
+        return PathBuf::from(dir);
+    }
+    let user = std::env::var_os(if cfg!(windows) { "USERPROFILE" } else { "HOME" });
+    user.map(PathBuf::from).unwrap_or_else(std::env::temp_dir).join(".kain")
+}
+
+/// The compiler binary of an installed `version`
+pub fn binary(version: &str) -> KainResult<PathBuf> {
+    Ok(toolchain_dir(version)?.join(binary_name()))
+}
+
+/// Where `version` is installed, once it is known to name a directory under `toolchains`
+fn toolchain_dir(version: &str) -> KainResult<PathBuf> {
+    check_version(version)?;
+    Ok(home().join("toolchains").join(version))
+}
+
+/// Reject anything but a version: dotted numbers with an optional `-suffix`,
+/// so a version from the command line or KAIN.toml can't name another path
+pub fn check_version(version: &str) -> KainResult<()> {
+    let (numbers, suffix) = match version.split_once('-') {
+        Some((numbers, suffix)) => (numbers, Some(suffix)),
+        None => (version, None),
+    };
+    let numbers_ok = numbers.split('.').all(|part| !part.is_empty() && part.bytes().all(|b| b.is_ascii_digit()));
+    let suffix_ok = match suffix {
+        Some(suffix) => suffix.split(['.', '-']).all(|part| !part.is_empty() && part.bytes().all(|b| b.is_ascii_alphanumeric())),
+        None => true,
+    };
+    if numbers_ok && suffix_ok {
+        return Ok(());
+    }
+    Err(KainError::runtime(format!("`{}` is not a kain version, expected one like 0.1.3 or 0.2.0-beta.1", version)))
+}
+
+fn binary_name() -> &'static str {
+    if cfg!(windows) { "kain.exe" } else { "kain" }
+}
+
+/// Installed versions, oldest first
+pub fn installed() -> Vec<String> {
+    let Ok(entries) = fs::read_dir(home().join("toolchains")) else { return Vec::new() };
+    let versions = entries.flatten().filter_map(|entry| entry.file_name().into_string().ok()).filter(|version| binary(version).is_ok_and(|path| path.exists()));
+    sorted(versions)
+}
+
+/// Download `version` from `index` and install it next to the others
+pub fn install(index: &ReleaseIndex, version: &str) -> KainResult<PathBuf> {
+    let path = binary(version)?;
+    if path.exists() {
+        println!(" kain {} is already installed", version);
+        return Ok(path);
+    }
+    let compiler = fetch_binary(index.download(version)?)?;
+    let dir = path.parent().unwrap_or(&path).to_path_buf();
+    fs::create_dir_all(&dir).map_err(KainError::Io)?;
+    // Written under a temporary name, so a failed install leaves no half a binary to run
+    let partial = dir.join(format!("{}.partial", binary_name()));
+    write_executable(&partial, &compiler)?;
+    fs::rename(&partial, &path).map_err(KainError::Io)?;
+    println!(" Installed kain {} to {}", version, dir.display());
+    Ok(path)
+}
+
+pub fn uninstall(version: &str) -> KainResult<()> {
+    let dir = toolchain_dir(version)?;
+    if !dir.exists() {
+        return Err(KainError::runtime(format!("kain {} is not installed", version)));
+    }
+    fs::remove_dir_all(&dir).map_err(KainError::Io)?;
+    if default_version().as_deref() == Some(version) {
+        let _ = fs::remove_file(home().join("default"));
+    }
+    println!(" Uninstalled kain {}", version);
+    Ok(())
+}
+
+/// The version `kain` runs outside a project that pins one
+pub fn default_version() -> Option<String> {
+    let version = fs::read_to_string(home().join("default")).ok()?;
+    Some(version.trim().to_string()).filter(|version| !version.is_empty())
+}
+
+/// Make `version` the default; this compiler's own version clears it
+pub fn set_default(version: &str) -> KainResult<()> {
+    let file = home().join("default");
+    if version == crate::VERSION {
+        let _ = fs::remove_file(&file);
+    } else if !binary(version)?.exists() {
+        return Err(KainError::runtime(format!("kain {} is not installed, run `kain toolchain install {}` first", version, version)));
+    } else {
+        fs::create_dir_all(home()).and_then(|_| fs::write(&file, format!("{}\n", version))).map_err(KainError::Io)?;
+    }
+    println!(" Default compiler is now kain {}", version);
+    Ok(())
+}
+
+/// Pin the project in the current directory to `version`
+pub fn pin(version: &str) -> KainResult<()> {
+    check_version(version)?;
+    let cwd = std::env::current_dir().unwrap_or_else(|_| PathBuf::from("."));
+    let mut manifest = packager::load_manifest(&cwd)?;
+    manifest.package.compiler = Some(version.to_string());
+    let toml = toml::to_string_pretty(&manifest).map_err(|e| KainError::runtime(format!("Failed to serialize manifest: {}", e)))?;
+    fs::write(cwd.join("KAIN.toml"), toml).map_err(KainError::Io)?;
+    println!(" {} now builds with kain {}", manifest.package.name, version);
+    if version != crate::VERSION && !binary(version)?.exists() {
+        println!(" It is not installed yet: run `kain toolchain install {}`", version);
+    }
+    Ok(())
+}
+
+/// The version this command should run with, and why: the project's pin, or the default
+pub fn wanted() -> Option<(String, &'static str)> {
+    let cwd = std::env::current_dir().ok()?;
+    if cwd.join("KAIN.toml").exists() {
+        if let Some(version) = packager::load_manifest(&cwd).ok().and_then(|manifest| manifest.package.compiler) {
+            return Some((version, "pinned in KAIN.toml"));
+        }
+    }
+    default_version().map(|version| (version, "the default"))
+}
+
+/// Run the command line `args` (without the program name) with the compiler [`wanted`] picks, if that isn't
+/// this one. Returns its exit code, or `None` when this compiler should go on:
+/// it is the one wanted, it was started by another hand-off, or the wanted
+/// version isn't installed (which is warned about).
+pub fn hand_off(args: &[std::ffi::OsString]) -> Option<i32> {
+    if std::env::var_os(ACTIVE_VAR).is_some() {
+        return None;
+    }
+    let (version, why) = wanted()?;
+    if version == crate::VERSION {
+        return None;
+    }
+    let path = match binary(&version) {
+        Ok(path) => path,
+        Err(e) => {
+            eprintln!(" warning: {} ({}), building with {}", e, why, crate::VERSION);
+            return None;
+        }
+    };
+    if !path.exists() {
+        eprintln!(" warning: kain {} is {} but not installed, building with {}; run `kain toolchain install {}`", version, why, crate::VERSION, version);
+        return None;
+    }
+    let status = std::process::Command::new(&path).args(args).env(ACTIVE_VAR, &version).status();
+    match status {
+        Ok(status) => Some(status.code().unwrap_or(1)),
+        Err(e) => {
+            eprintln!(" warning: could not run kain {} ({}): {}", version, path.display(), e);
+            None
+        }
+    }
+}
+
+/// Replace the running compiler with the newest release. Returns the version
+/// updated to, or `None` when this one is already the newest.
+pub fn self_update(index: &ReleaseIndex) -> KainResult<Option<String>> {
+    let Some(latest) = index.latest().filter(|latest| compare_versions(latest, crate::VERSION) == Ordering::Greater) else {
+        return Ok(None);
+    };
+    let compiler = fetch_binary(index.download(latest)?)?;
+    let exe = std::env::current_exe().map_err(KainError::Io)?;
+    let staged = exe.with_extension("new");
+    write_executable(&staged, &compiler)?;
+
+    // A running binary can be renamed, even on Windows, but not overwritten
+    let old = exe.with_extension("old");
+    let _ = fs::remove_file(&old);
+    fs::rename(&exe, &old).map_err(|e| KainError::runtime(format!("Failed to move {} aside: {}", exe.display(), e)))?;
+    if let Err(e) = fs::rename(&staged, &exe) {
+        let _ = fs::rename(&old, &exe);
+        return Err(KainError::runtime(format!("Failed to replace {}: {}", exe.display(), e)));
+    }
+    // Windows keeps the old binary locked until this process exits; the next update removes it
+    let _ = fs::remove_file(&old);
+    Ok(Some(latest.to_string()))
+}
+
+/// The compiler binary in the archive `download` names, once its checksum matches
+fn fetch_binary(download: &Download) -> KainResult<Vec<u8>> {
+    println!(" Downloading {}...", download.url);
+    let archive = fetch(&download.url)?;
+    let digest = format!("{:x}", Sha256::digest(&archive));
+    if !digest.eq_ignore_ascii_case(download.sha256.trim()) {
+        return Err(KainError::runtime(format!("Checksum mismatch for {}: expected {}, got {}", download.url, download.sha256, digest)));
+    }
+    unpack_binary(&archive)
+}
+
+/// `url` over HTTP, or a local file for `file://` URLs and mirrors on disk
+fn fetch(url: &str) -> KainResult<Vec<u8>> {
+    if let Some(path) = url.strip_prefix("file://") {
+        return fs::read(path).map_err(|e| KainError::runtime(format!("Failed to read {}: {}", path, e)));
+    }
+    let response = reqwest::blocking::get(url)
+        .and_then(|response| response.error_for_status())
+        .map_err(|e| KainError::runtime(format!("Download failed: {}", e)))?;
+    let bytes = response.bytes().map_err(|e| KainError::runtime(format!("Failed to read bytes: {}", e)))?;
+    Ok(bytes.to_vec())
+}
+
+/// The `kain` binary inside a `.tar.gz`, at its root or in one directory
+fn unpack_binary(archive: &[u8]) -> KainResult<Vec<u8>> {
+    let mut archive = tar::Archive::new(GzDecoder::new(archive));
+    for entry in archive.entries().map_err(KainError::Io)? {
+        let mut entry = entry.map_err(KainError::Io)?;
+        let path = entry.path().map_err(KainError::Io)?.into_owned();
+        if path.file_name().is_some_and(|name| name == binary_name()) && path.components().count() <= 2 {
+            let mut compiler = Vec::new();
+            entry.read_to_end(&mut compiler).map_err(KainError::Io)?;
+            return Ok(compiler);
+        }
+    }
+    Err(KainError::runtime(format!("The release archive has no {}", binary_name())))
+}
+
+fn write_executable(path: &std::path::Path, bytes: &[u8]) -> KainResult<()> {
+    fs::write(path, bytes).map_err(KainError::Io)?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(path, fs::Permissions::from_mode(0o755)).map_err(KainError::Io)?;
+    }
+    Ok(())
+}
+
+/// Compare dotted versions by their numbers, so `0.10.0` is after `0.9.1`
+pub fn compare_versions(a: &str, b: &str) -> Ordering {
+    let numbers = |v: &str| v.split('.').map(|part| part.parse::<u64>().unwrap_or(0)).collect::<Vec<_>>();
+    numbers(a).cmp(&numbers(b)).then_with(|| a.cmp(b))
+}
+
+fn sorted(versions: impl Iterator<Item = String>) -> Vec<String> {
+    let mut versions: Vec<String> = versions.collect();
+    versions.sort_by(|a, b| compare_versions(a, b));
+    versions
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_releases_are_verified_and_unpacked() {
+        assert_eq!(compare_versions("0.10.0", "0.9.1"), Ordering::Greater);
+        assert_eq!(compare_versions("1.2", "1.2.0"), Ordering::Less);
+
+        let mut tar = tar::Builder::new(Vec::new());
+        let mut header = tar::Header::new_gnu();
+        header.set_size(7);
+        header.set_mode(0o755);
+        header.set_cksum();
+        tar.append_data(&mut header, format!("kain-0.2.0/{}", binary_name()), &b"\x7fELF..."[..]).unwrap();
+        let mut gz = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        std::io::Write::write_all(&mut gz, &tar.into_inner().unwrap()).unwrap();
+        let archive = gz.finish().unwrap();
+        assert_eq!(unpack_binary(&archive).unwrap(), b"\x7fELF...");
+
+        let path = std::env::temp_dir().join(format!("kain-release-{}.tar.gz", std::process::id()));
+        fs::write(&path, &archive).unwrap();
+        let url = format!("file://{}", path.display());
+        let sha256 = format!("{:x}", Sha256::digest(&archive));
+        let json = format!(
+            r#"{{"versions": {{"0.2.0": {{"{p}": {{"url": "{u}", "sha256": "{s}"}}}}, "0.10.0": {{"{p}": {{"url": "{u}", "sha256": "00"}}}}, "9.0.0": {{}}}}}}"#,
+            p = platform(),
+            u = url,
+            s = sha256
+        );
+        let index: ReleaseIndex = serde_json::from_str(&json).unwrap();
+        assert_eq!(index.latest(), Some("0.10.0"));
+        assert_eq!(fetch_binary(index.download("0.2.0").unwrap()).unwrap(), b"\x7fELF...");
+        let err = fetch_binary(index.download("0.10.0").unwrap()).unwrap_err().to_string();
+        assert!(err.contains("Checksum mismatch"), "{}", err);
+        assert!(index.download("9.0.0").unwrap_err().to_string().contains("has no build for"));
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn test_versions_cannot_name_other_paths() {
+        for version in ["0.1.3", "10.0", "0.2.0-beta.1", "1.0.0-rc-2"] {
+            assert!(check_version(version).is_ok(), "{}", version);
+        }
+        for version in ["", "..", "../../x", "0.1/../..", "0..1", "0.1.", "1.2-", "1.2-a/b", "1.2-..", "/usr/bin", "0.1\\..\\.."] {
+            let err = check_version(version).unwrap_err().to_string();
+            assert!(err.contains("is not a kain version"), "{}: {}", version, err);
+        }
+        assert!(binary("../../x").is_err());
+        assert!(uninstall("../..").unwrap_err().to_string().contains("`../..` is not a kain version"));
+        assert!(set_default("../../x").is_err());
+    }
+}
@@ -0,0 +1,293 @@
+//! Recording and replaying interpreter runs
+//!
+//! `kain run --record FILE` writes down everything a run takes from outside
+//! the program: its arguments, what the natives in [`RECORDED_NATIVES`]
+//! returned (clocks, `random`, stdin, environment variables and HTTP
+//! responses), and the order actors started handling their messages in.
+//! `kain run --replay FILE` runs the program again from the recording. Those
+//! natives return what they returned then, without touching the clock, stdin
+//! or network, and each actor waits its turn, so messages are handled in the
+//! recorded order. A race between actors then goes the same way on every replay.
+//!
+//! A recording is JSON lines, one [`Event`] each, written as the run goes so
+//! a run that crashes still leaves one. When a replay asks for something the
+//! recording doesn't have, because the program changed, it stops with an error.
+
+use std::collections::{HashMap, VecDeque};
+use std::io::{BufRead, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+
+use serde::{Deserialize, Serialize};
+
+use crate::error::{KainError, KainResult};
+use crate::runtime::Value;
+use crate::wire;
+
+/// Natives whose results a recording keeps
+pub const RECORDED_NATIVES: &[&str] = &["now", "time", "random", "read_line", "env", "http_get", "http_post_json"];
+
+/// Whether a run is recorded or replayed, and the recording's file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Recording {
+    Record(PathBuf),
+    Replay(PathBuf),
+}
+
+/// Where a message came from: the id of the actor that sent it (0 for the
+/// program itself), and how many messages that sender had sent before it
+pub type Origin = (u64, u64);
+
+/// One line of a recording
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Event {
+    /// The first line: the compiler that recorded the run, and the program's arguments
+    Start { version: String, args: Vec<String> },
+    /// A recorded native returned `result` to `actor` (0 for the program itself)
+    Native { actor: u64, name: String, result: Recorded },
+    /// `actor` started handling the message from `origin`
+    Deliver { actor: u64, origin: Origin },
+}
+
+/// What a recorded native returned
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Recorded {
+    Unit,
+    None,
+    Bool(bool),
+    Int(i64),
+    /// The float's bits, so it replays exactly
+    Float(u64),
+    String(String),
+    Result(bool, Box<Recorded>),
+    /// Any other value, in the [`wire`] format
+    Wire(Vec<u8>),
+    /// The call failed with this message
+    Error(String),
+}
+
+impl Recorded {
+    fn of(result: &KainResult<Value>) -> Recorded {
+        match result {
+            Ok(value) => Recorded::value(value).unwrap_or_else(|| match wire::encode(value) {
+                Ok(bytes) => Recorded::Wire(bytes),
+                Err(e) => Recorded::Error(format!("cannot record the value {}: {}", value, e)),
+            }),
+            Err(e) => Recorded::Error(e.to_string()),
+        }
+    }
+
+    fn value(value: &Value) -> Option<Recorded> {
+        Some(match value {
+            Value::Unit => Recorded::Unit,
+            Value::None => Recorded::None,
+            Value::Bool(b) => Recorded::Bool(*b),
+            Value::Int(i) => Recorded::Int(*i),
+            Value::Float(f) => Recorded::Float(f.to_bits()),
+            Value::String(s) => Recorded::String(s.clone()),
+            Value::Result(ok, inner) => Recorded::Result(*ok, Box::new(Recorded::value(inner)?)),
+            _ => return None,
+        })
+    }
+
+    fn into_result(self) -> KainResult<Value> {
+        Ok(match self {
+            Recorded::Unit => Value::Unit,
+            Recorded::None => Value::None,
+            Recorded::Bool(b) => Value::Bool(b),
+            Recorded::Int(i) => Value::Int(i),
+            Recorded::Float(bits) => Value::Float(f64::from_bits(bits)),
+            Recorded::String(s) => Value::String(s),
+            Recorded::Result(ok, inner) => Value::Result(ok, Box::new(inner.into_result()?)),
+            Recorded::Wire(bytes) => wire::decode(&bytes).map_err(|e| KainError::runtime(format!("replay: a recorded value {}", e)))?,
+            Recorded::Error(message) => return Err(KainError::runtime(message)),
+        })
+    }
+}
+
+/// A run's recording, shared by the program and its actors
+pub struct Journal {
+    mode: Mode,
+}
+
+enum Mode {
+    Record(Mutex<std::io::LineWriter<std::fs::File>>),
+    Replay { state: Mutex<Replay>, turn: Condvar },
+}
+
+/// What is left of a recording being replayed
+#[derive(Default)]
+struct Replay {
+    args: Vec<String>,
+    natives: HashMap<u64, VecDeque<(String, Recorded)>>,
+    /// Messages each actor handled, in order
+    deliveries: HashMap<u64, VecDeque<Origin>>,
+    /// Messages every actor handled, in the order they started
+    schedule: VecDeque<(u64, Origin)>,
+    /// Why the replay stopped following the recording
+    diverged: Option<String>,
+}
+
+impl Journal {
+    /// Start recording a run given `args`, or load a recording to replay
+    pub fn open(recording: &Recording, args: &[String]) -> KainResult<Arc<Journal>> {
+        let journal = match recording {
+            Recording::Record(path) => {
+                let file = std::fs::File::create(path)
+                    .map_err(|e| KainError::runtime(format!("cannot write recording {}: {}", path.display(), e)))?;
+                let journal = Journal { mode: Mode::Record(Mutex::new(std::io::LineWriter::new(file))) };
+                journal.write(&Event::Start { version: crate::VERSION.to_string(), args: args.to_vec() });
+                journal
+            }
+            Recording::Replay(path) => Journal { mode: Mode::Replay { state: Mutex::new(load(path)?), turn: Condvar::new() } },
+        };
+        Ok(Arc::new(journal))
+    }
+
+    /// The arguments the recorded run was given, when replaying
+    pub fn replayed_args(&self) -> Option<Vec<String>> {
+        match &self.mode {
+            Mode::Record(_) => None,
+            Mode::Replay { state, .. } => Some(lock(state).args.clone()),
+        }
+    }
+
+    /// Call the recorded native `name` for `actor`: run it and record what it
+    /// returned, or on replay return what it returned in the recording
+    pub fn native(&self, actor: u64, name: &str, call: impl FnOnce() -> KainResult<Value>) -> KainResult<Value> {
+        match &self.mode {
+            Mode::Record(_) => {
+                let result = call();
+                self.write(&Event::Native { actor, name: name.to_string(), result: Recorded::of(&result) });
+                result
+            }
+            Mode::Replay { state, turn } => {
+                let mut replay = lock(state);
+                match replay.natives.get_mut(&actor).and_then(VecDeque::pop_front) {
+                    Some((recorded, result)) if recorded == name => result.into_result(),
+                    Some((recorded, _)) => Err(diverge(&mut replay, turn, format!("the recording called `{}` where the program calls `{}`", recorded, name))),
+                    None => Err(diverge(&mut replay, turn, format!("the recording has no more calls of `{}`", name))),
+                }
+            }
+        }
+    }
+
+    /// The message `actor` handles next in the recording, if it is being
+    /// replayed and has one left
+    pub fn next_delivery(&self, actor: u64) -> Option<Origin> {
+        match &self.mode {
+            Mode::Record(_) => None,
+            Mode::Replay { state, .. } => lock(state).deliveries.get(&actor).and_then(|queue| queue.front().copied()),
+        }
+    }
+
+    /// `actor` is about to handle the message from `origin`. A recording
+    /// notes it; a replay waits until every message handled before it in the
+    /// recording has been started.
+    pub fn deliver(&self, actor: u64, origin: Origin) -> KainResult<()> {
+        let (state, turn) = match &self.mode {
+            Mode::Record(_) => {
+                self.write(&Event::Deliver { actor, origin });
+                return Ok(());
+            }
+            Mode::Replay { state, turn } => (state, turn),
+        };
+        let mut replay = lock(state);
+        if replay.deliveries.get(&actor).and_then(|queue| queue.front()) != Some(&origin) {
+            // Past the end of what the recording saw
+            return Ok(());
+        }
+        loop {
+            if let Some(why) = &replay.diverged {
+                return Err(diverged(why));
+            }
+            if replay.schedule.front() == Some(&(actor, origin)) {
+                replay.schedule.pop_front();
+                replay.deliveries.get_mut(&actor).and_then(VecDeque::pop_front);
+                turn.notify_all();
+                return Ok(());
+            }
+            replay = turn.wait(replay).unwrap_or_else(|e| e.into_inner());
+        }
+    }
+
+    fn write(&self, event: &Event) {
+        if let Mode::Record(file) = &self.mode {
+            let line = serde_json::to_string(event).unwrap_or_default();
+            // Like a failed trace, a failed recording must not stop the program
+            let _ = writeln!(lock(file), "{}", line);
+        }
+    }
+}
+
+fn load(path: &Path) -> KainResult<Replay> {
+    let file = std::fs::File::open(path).map_err(|e| KainError::runtime(format!("cannot read recording {}: {}", path.display(), e)))?;
+    let mut replay = Replay::default();
+    for (number, line) in std::io::BufReader::new(file).lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let event: Event = serde_json::from_str(&line)
+            .map_err(|e| KainError::runtime(format!("{}:{}: not a recording: {}", path.display(), number + 1, e)))?;
+        match event {
+            Event::Start { args, .. } => replay.args = args,
+            Event::Native { actor, name, result } => replay.natives.entry(actor).or_default().push_back((name, result)),
+            Event::Deliver { actor, origin } => {
+                replay.deliveries.entry(actor).or_default().push_back(origin);
+                replay.schedule.push_back((actor, origin));
+            }
+        }
+    }
+    Ok(replay)
+}
+
+/// Stop the replay, waking the actors waiting their turn so they stop too
+fn diverge(replay: &mut Replay, turn: &Condvar, why: String) -> KainError {
+    let error = diverged(&why);
+    replay.diverged.get_or_insert(why);
+    turn.notify_all();
+    error
+}
+
+fn diverged(why: &str) -> KainError {
+    KainError::runtime(format!("replay diverged from the recording: {}", why))
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{eval_snippet, replay, CompileOptions};
+
+    #[test]
+    fn test_replay_reproduces_a_recorded_run() {
+        let source = "fn main():\n    let a = random()\n    let b = now()\n    println(a, b, args())\n";
+        let file = std::env::temp_dir().join(format!("kain-replay-{}.jsonl", std::process::id()));
+        let run = |source: &str, recording, args: &[&str]| {
+            let options = CompileOptions { recording: Some(recording), program_args: args.iter().map(|a| a.to_string()).collect(), ..Default::default() };
+            eval_snippet(source, &options)
+        };
+
+        let recorded = run(source, replay::Recording::Record(file.clone()), &["--fast"]);
+        assert!(recorded.diagnostics.is_empty(), "{:?}", recorded.diagnostics);
+        let log = std::fs::read_to_string(&file).unwrap();
+        assert_eq!(log.lines().count(), 3, "{}", log);
+        assert!(log.lines().nth(1).unwrap().starts_with("{\"event\":\"native\",\"actor\":0,\"name\":\"random\""), "{}", log);
+
+        // The arguments come from the recording too
+        let replayed = run(source, replay::Recording::Replay(file.clone()), &[]);
+        assert!(replayed.diagnostics.is_empty(), "{:?}", replayed.diagnostics);
+        assert_eq!(replayed.stdout, recorded.stdout);
+        assert!(replayed.stdout.trim_end().ends_with("[--fast]"), "{}", replayed.stdout);
+
+        let changed = run(&source.replace("now()", "random()"), replay::Recording::Replay(file.clone()), &[]);
+        std::fs::remove_file(&file).ok();
+        let err = changed.diagnostics.first().map(|d| d.to_string()).unwrap_or_default();
+        assert!(err.contains("replay diverged from the recording: the recording called `now` where the program calls `random`"), "{}", err);
+    }
+}
@@ -0,0 +1,345 @@
+//! Local slot resolution
+//!
+//! The interpreter keeps each scope's bindings in slots, numbered in the order
+//! the scope first binds each name. This pass runs on programs lowered for the
+//! interpreter and rewrites every variable it can place into an
+//! [`Expr::Local`]: how many scopes out from the innermost it lives, and in
+//! which slot. Reading one is then two indexing operations instead of a hash
+//! lookup in every scope on the way out.
+//!
+//! The pass mirrors where the interpreter opens scopes: a call, each `for`
+//! iteration, a `match` arm, a closure, a `region`, an actor and its
+//! handlers, and a test. It only guesses slots, since a `let` an `if` skipped
+//! leaves the slots after it numbered differently. The interpreter checks the
+//! slot still holds the name and otherwise looks it up by name, as it does
+//! for every variable this pass leaves alone: names bound only later in the
+//! same scope, the locals of `main`, which runs in the global scope, and
+//! anything not bound in the enclosing function.
+
+use crate::ast::visit::{walk_expr, walk_expr_mut, walk_pattern, walk_stmt, walk_stmt_mut, Visitor, VisitorMut};
+use crate::ast::*;
+use crate::types::typed_visit::TypedVisitorMut;
+use crate::types::{TypedItem, TypedProgram};
+use std::collections::{HashMap, HashSet};
+
+/// Rewrite the variables of `program` the interpreter can read by slot
+pub fn resolve(program: &mut TypedProgram) {
+    Resolver::default().visit_typed_program_mut(program);
+}
+
+/// One scope the interpreter will open
+struct Frame {
+    /// Every name the scope binds, wherever in it that happens
+    bound: HashSet<String>,
+    /// Slots of the names bound so far
+    slots: HashMap<String, usize>,
+    /// `main`'s locals share the global scope, so their slots are unknown
+    dynamic: bool,
+}
+
+#[derive(Default)]
+struct Resolver {
+    frames: Vec<Frame>,
+}
+
+impl Resolver {
+    /// Open a scope that binds `names` in order, and whatever `contents` binds
+    fn enter(&mut self, names: Vec<String>, contents: impl FnOnce(&mut Bound)) {
+        let mut bound = Bound(names.iter().cloned().collect());
+        contents(&mut bound);
+        self.frames.push(Frame { bound: bound.0, slots: HashMap::new(), dynamic: false });
+        for name in names {
+            self.bind(name);
+        }
+    }
+
+    fn exit(&mut self) {
+        self.frames.pop();
+    }
+
+    fn bind(&mut self, name: String) {
+        if let Some(frame) = self.frames.last_mut() {
+            let next = frame.slots.len();
+            frame.slots.entry(name).or_insert(next);
+        }
+    }
+
+    fn bind_pattern(&mut self, pattern: &Pattern) {
+        for name in pattern_names(pattern) {
+            self.bind(name);
+        }
+    }
+
+    /// Depth and slot of `name` in the innermost scope that binds it, if it is already bound there
+    fn resolve(&self, name: &str) -> Option<(usize, usize)> {
+        let (depth, frame) = self.frames.iter().rev().enumerate().find(|(_, frame)| frame.bound.contains(name))?;
+        if frame.dynamic {
+            return None;
+        }
+        Some((depth, *frame.slots.get(name)?))
+    }
+
+    /// Resolve a function's body in a scope of its own; nothing outside it is visible
+    fn function(&mut self, function: &mut Function, dynamic: bool) {
+        let outer = std::mem::take(&mut self.frames);
+        let params = function.params.iter().map(|p| p.name.clone()).collect();
+        self.enter(params, |bound| bound.visit_block(&function.body));
+        if let Some(frame) = self.frames.last_mut() {
+            frame.dynamic = dynamic;
+        }
+        self.visit_block_mut(&mut function.body);
+        self.frames = outer;
+    }
+
+    fn actor(&mut self, actor: &mut Actor) {
+        let mut names = vec!["self".to_string()];
+        names.extend(actor.state.iter().map(|s| s.name.clone()));
+        self.frames.push(Frame { bound: names.into_iter().collect(), slots: HashMap::new(), dynamic: false });
+        self.bind("self".to_string());
+        for state in &mut actor.state {
+            self.visit_expr_mut(&mut state.initial);
+            self.bind(state.name.clone());
+        }
+        for handler in &mut actor.handlers {
+            let params = handler.params.iter().map(|p| p.name.clone()).collect();
+            self.enter(params, |bound| bound.visit_block(&handler.body));
+            self.visit_block_mut(&mut handler.body);
+            self.exit();
+        }
+        self.exit();
+    }
+}
+
+impl VisitorMut for Resolver {
+    fn visit_item_mut(&mut self, _item: &mut Item) {
+        // Nested items are not run where they are declared
+    }
+
+    fn visit_function_mut(&mut self, function: &mut Function) {
+        self.function(function, false);
+    }
+
+    fn visit_pattern_mut(&mut self, _pattern: &mut Pattern) {
+        // Patterns are matched, not evaluated
+    }
+
+    fn visit_stmt_mut(&mut self, stmt: &mut Stmt) {
+        match stmt {
+            Stmt::Let { pattern, value, .. } => {
+                if let Some(value) = value {
+                    self.visit_expr_mut(value);
+                }
+                self.bind_pattern(pattern);
+            }
+            Stmt::For { binding, iter, body, else_branch, .. } => {
+                self.visit_expr_mut(iter);
+                self.enter(pattern_names(binding), |bound| bound.visit_block(body));
+                self.visit_block_mut(body);
+                self.exit();
+                if let Some(block) = else_branch {
+                    self.visit_block_mut(block);
+                }
+            }
+            Stmt::Region { body, .. } => {
+                self.enter(Vec::new(), |bound| bound.visit_block(body));
+                self.visit_block_mut(body);
+                self.exit();
+            }
+            _ => walk_stmt_mut(self, stmt),
+        }
+    }
+
+    fn visit_expr_mut(&mut self, expr: &mut Expr) {
+        match expr {
+            Expr::Ident(name, span) => {
+                if let Some((depth, slot)) = self.resolve(name) {
+                    *expr = Expr::Local { name: std::mem::take(name), depth, slot, span: *span };
+                }
+            }
+            // `Type.method()` and `handle Type` look the type up by name
+            Expr::Call { callee, args, .. } if is_type_path(callee) => {
+                for arg in args {
+                    self.visit_expr_mut(&mut arg.value);
+                }
+            }
+            Expr::Handle { handler, body, .. } => {
+                if !matches!(**handler, Expr::Ident(..)) {
+                    self.visit_expr_mut(handler);
+                }
+                self.visit_block_mut(body);
+            }
+            Expr::Match { scrutinee, arms, .. } => {
+                self.visit_expr_mut(scrutinee);
+                for arm in arms {
+                    self.enter(pattern_names(&arm.pattern), |bound| bound.visit_expr(&arm.body));
+                    if let Some(guard) = &mut arm.guard {
+                        self.visit_expr_mut(guard);
+                    }
+                    self.visit_expr_mut(&mut arm.body);
+                    self.exit();
+                }
+            }
+            Expr::Lambda { params, body, .. } => {
+                let params = params.iter().map(|p| p.name.clone()).collect();
+                self.enter(params, |bound| bound.visit_expr(&**body));
+                self.visit_expr_mut(body);
+                self.exit();
+            }
+            _ => walk_expr_mut(self, expr),
+        }
+    }
+}
+
+impl TypedVisitorMut for Resolver {
+    fn visit_typed_item_mut(&mut self, item: &mut TypedItem) {
+        match item {
+            TypedItem::Function(f) => {
+                let main = f.ast.name == "main";
+                self.function(&mut f.ast, main);
+            }
+            TypedItem::Impl(i) => {
+                for method in &mut i.ast.methods {
+                    self.function(method, false);
+                }
+            }
+            TypedItem::Actor(a) => self.actor(&mut a.ast),
+            TypedItem::Test(t) => {
+                self.enter(Vec::new(), |bound| bound.visit_block(&t.ast.body));
+                self.visit_block_mut(&mut t.ast.body);
+                self.exit();
+            }
+            TypedItem::Const(c) => self.visit_expr_mut(&mut c.ast.value),
+            TypedItem::Static(s) => self.visit_expr_mut(&mut s.ast.value),
+            // Components and shaders are never interpreted, and comptime has already run
+            _ => {}
+        }
+    }
+}
+
+fn is_type_path(callee: &Expr) -> bool {
+    matches!(callee, Expr::Field { object, .. } if matches!(**object, Expr::Ident(..)))
+}
+
+/// Names a scope binds directly, leaving out the scopes nested in it
+struct Bound(HashSet<String>);
+
+impl Visitor for Bound {
+    fn visit_item(&mut self, _item: &Item) {}
+
+    fn visit_pattern(&mut self, _pattern: &Pattern) {}
+
+    fn visit_stmt(&mut self, stmt: &Stmt) {
+        match stmt {
+            Stmt::Let { pattern, value, .. } => {
+                self.0.extend(pattern_names(pattern));
+                if let Some(value) = value {
+                    self.visit_expr(value);
+                }
+            }
+            Stmt::For { iter, else_branch, .. } => {
+                self.visit_expr(iter);
+                if let Some(block) = else_branch {
+                    self.visit_block(block);
+                }
+            }
+            Stmt::Region { .. } => {}
+            _ => walk_stmt(self, stmt),
+        }
+    }
+
+    fn visit_expr(&mut self, expr: &Expr) {
+        match expr {
+            Expr::Match { scrutinee, .. } => self.visit_expr(scrutinee),
+            Expr::Lambda { .. } => {}
+            _ => walk_expr(self, expr),
+        }
+    }
+}
+
+/// Names `pattern` binds, in the order it binds them
+fn pattern_names(pattern: &Pattern) -> Vec<String> {
+    struct Names(Vec<String>);
+
+    impl Visitor for Names {
+        fn visit_pattern(&mut self, pattern: &Pattern) {
+            match pattern {
+                Pattern::Binding { name, .. } => self.0.push(name.clone()),
+                Pattern::Slice { rest: Some(rest), .. } => self.0.push(rest.clone()),
+                _ => {}
+            }
+            walk_pattern(self, pattern);
+        }
+
+        fn visit_expr(&mut self, _expr: &Expr) {}
+    }
+
+    let mut names = Names(Vec::new());
+    names.visit_pattern(pattern);
+    names.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ast::visit::walk_block;
+    use crate::{eval_snippet, CompileOptions, CompileSession, CompileTarget, Lexer, Parser};
+
+    /// `(name, depth, slot)` of each resolved local, and the names left unresolved
+    fn resolved(source: &str) -> (Vec<(String, usize, usize)>, Vec<String>) {
+        struct Collect(Vec<(String, usize, usize)>, Vec<String>);
+
+        impl Visitor for Collect {
+            fn visit_expr(&mut self, expr: &Expr) {
+                match expr {
+                    Expr::Local { name, depth, slot, .. } => self.0.push((name.clone(), *depth, *slot)),
+                    Expr::Ident(name, _) => self.1.push(name.clone()),
+                    _ => {}
+                }
+                walk_expr(self, expr);
+            }
+        }
+
+        let tokens = Lexer::new(source).tokenize().unwrap();
+        let mut program = crate::types::check(&Parser::new(&tokens).parse().unwrap()).unwrap();
+        resolve(&mut program);
+        let mut collect = Collect(Vec::new(), Vec::new());
+        for item in &program.items {
+            if let TypedItem::Function(f) = item {
+                walk_block(&mut collect, &f.ast.body);
+            }
+        }
+        (collect.0, collect.1)
+    }
+
+    #[test]
+    fn test_locals_get_their_depth_and_slot() {
+        let (locals, names) = resolved(
+            "fn f(a: Int, b: Int) -> Int:\n    let c = a + b\n    for i in 0..c:\n        let d = i * c\n        println(d)\n    let g = |x| x + c\n    return g(c)\n",
+        );
+        let expected = [("a", 0, 0), ("b", 0, 1), ("c", 0, 2), ("i", 0, 0), ("c", 1, 2), ("d", 0, 1), ("x", 0, 0), ("c", 1, 2), ("g", 0, 3), ("c", 0, 2)];
+        let expected: Vec<_> = expected.iter().map(|&(n, d, s)| (n.to_string(), d, s)).collect();
+        assert_eq!(locals, expected);
+        assert_eq!(names, ["println"]);
+
+        // A name is left alone until its scope binds it, and in `main`
+        let (locals, names) = resolved("fn f(n: Int) -> Int:\n    let m = n\n    let n = m + 1\n    return n\n\nfn main():\n    let k = 1\n    println(k)\n");
+        let expected: Vec<_> = [("n", 0, 0), ("m", 0, 1), ("n", 0, 0)].iter().map(|&(n, d, s)| (n.to_string(), d, s)).collect();
+        assert_eq!(locals, expected);
+        assert_eq!(names, ["println", "k"]);
+    }
+
+    #[test]
+    fn test_locals_read_by_slot_keep_their_scoping() {
+        let fib = "fn fib(n: Int) -> Int:\n    if n < 2:\n        return n\n    return fib(n - 1) + fib(n - 2)\n\n";
+        let source = format!("{}fn pick(flag: Bool) -> Int:\n    var total = 0\n    if flag:\n        let bonus = 10\n        total = bonus\n    let base = 1\n    return total + base\n\nfn shadow(x: Int) -> Int:\n    let y = x * 2\n    let x = y + 1\n    var sum = 0\n    for i in 0..x:\n        let x = i\n        sum = sum + x\n    let add = |a| a + sum\n    let total = match sum:\n        0 => 0\n        n => add(n)\n    return total\n\nfn main():\n    println(fib(15))\n    println(pick(true))\n    println(pick(false))\n    println(shadow(2))\n", fib);
+        let result = eval_snippet(&source, &CompileOptions::default());
+        assert!(result.diagnostics.is_empty(), "{:?}", result.diagnostics);
+        assert_eq!(result.stdout.lines().map(str::trim_end).collect::<Vec<_>>(), ["610", "11", "1", "20"]);
+
+        // Backends that share the monomorphized program never see the interpreter's slots
+        let mut session = CompileSession::new(&format!("{}fn main():\n    println(fib(15))\n", fib), CompileOptions::default()).unwrap();
+        session.compile(CompileTarget::Interpret).unwrap();
+        let wat = String::from_utf8(session.compile(CompileTarget::Wat).unwrap()).unwrap();
+        assert!(!wat.contains("invalid module"), "{}", wat);
+    }
+}
@@ -1,16 +1,28 @@
 //! KAIN Runtime - Interpreter and actor system
 
 use crate::ast::*;
+use crate::ast::visit::{walk_expr_mut, VisitorMut};
+use crate::edition::Edition;
+use crate::effects::Effect;
 use crate::error::{KainError, KainResult};
+use crate::span::Span;
+use crate::{argparse, bridge, image, log, replay, schedule, template, vfs, wire};
 use crate::lexer::Lexer;
 use crate::parser::Parser;
+use crate::pretty::{pretty, PrettyOptions};
 use crate::types::TypedProgram;
-use flume::Sender;
+use flume::{Receiver, Sender, TrySendError, WeakSender};
+use once_cell::sync::Lazy;
 use pyo3::prelude::*;
 use pyo3::types::{PyDict, PyList};
-use std::collections::HashMap;
+use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet};
 use std::fmt;
-use std::sync::{Arc, RwLock};
+use std::ops::ControlFlow;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant};
+use unicode_segmentation::UnicodeSegmentation;
 
 fn py_to_value(obj: &PyAny) -> PyResult<Value> {
     if let Ok(s) = obj.extract::<String>() {
@@ -36,6 +48,167 @@ fn py_to_value(obj: &PyAny) -> PyResult<Value> {
     Ok(Value::String(format!("{}", obj)))
 }
 
+/// The JSON form `json_string` writes; values JSON has no form for become strings
+pub fn value_to_json(v: &Value) -> serde_json::Value {
+    match v {
+        Value::Unit => serde_json::Value::Null,
+        Value::None => serde_json::Value::Null,
+        Value::Some(inner) => value_to_json(inner),
+        Value::Bool(b) => serde_json::Value::Bool(*b),
+        Value::Int(i) => serde_json::json!(i),
+        Value::Float(f) => serde_json::json!(f),
+        Value::String(s) => serde_json::Value::String(s.clone()),
+        Value::Char(c) => serde_json::Value::String(c.to_string()),
+        Value::Array(arr) => {
+            let arr = arr.read().unwrap();
+            serde_json::Value::Array(arr.iter().map(value_to_json).collect())
+        }
+        Value::Struct(_, fields) => {
+            let fields = fields.read().unwrap();
+            let mut map = serde_json::Map::new();
+            for (k, v) in fields.iter() {
+                map.insert(k.clone(), value_to_json(v));
+            }
+            serde_json::Value::Object(map)
+        }
+        Value::Tuple(items) => {
+            serde_json::Value::Array(items.iter().map(value_to_json).collect())
+        }
+        _ => serde_json::Value::String(format!("{}", v)), // Fallback
+    }
+}
+
+/// The value `json_parse` reads; objects become `Json` structs
+pub fn json_to_value(v: &serde_json::Value) -> Value {
+    match v {
+        serde_json::Value::Null => Value::None,
+        serde_json::Value::Bool(b) => Value::Bool(*b),
+        serde_json::Value::Number(n) => {
+            if let Some(i) = n.as_i64() {
+                Value::Int(i)
+            } else if let Some(f) = n.as_f64() {
+                Value::Float(f)
+            } else {
+                Value::Int(0) // Should match
+            }
+        }
+        serde_json::Value::String(s) => Value::String(s.clone()),
+        serde_json::Value::Array(arr) => {
+            let k_arr = arr.iter().map(json_to_value).collect();
+            Value::Array(Arc::new(RwLock::new(k_arr)))
+        }
+        serde_json::Value::Object(obj) => {
+            let mut map = HashMap::new();
+            for (k, v) in obj {
+                map.insert(k.clone(), json_to_value(v));
+            }
+            Value::Struct("Json".to_string(), Arc::new(RwLock::new(map)))
+        }
+    }
+}
+
+fn as_f64(native: &str, value: &Value) -> KainResult<f64> {
+    match value {
+        Value::Int(n) => Ok(*n as f64),
+        Value::Float(n) => Ok(*n),
+        _ => Err(KainError::runtime(format!("{}: expected a number", native))),
+    }
+}
+
+/// A one-argument float function; integers are converted first
+fn float_fn(native: &str, args: Vec<Value>, f: fn(f64) -> f64) -> KainResult<Value> {
+    match args.as_slice() {
+        [x] => Ok(Value::Float(f(as_f64(native, x)?))),
+        _ => Err(KainError::runtime(format!("{}: expected 1 argument", native))),
+    }
+}
+
+fn round_fn(native: &str, args: Vec<Value>, f: fn(f64) -> f64) -> KainResult<Value> {
+    match args.as_slice() {
+        [Value::Int(n)] => Ok(Value::Int(*n)),
+        [Value::Float(n)] => Ok(Value::Float(f(*n))),
+        _ => Err(KainError::runtime(format!("{}: expected 1 number", native))),
+    }
+}
+
+/// Open connections, indexed by the `id` of their `SqliteConnection`. Actors
+/// share their parent's registry, so a handle works in every actor.
+pub type Databases = Arc<Mutex<Vec<Arc<Mutex<rusqlite::Connection>>>>>;
+
+/// The connection to the KOS engine, made by the first bridge native that
+/// needs it. Actors share their parent's, so commands reach the engine in
+/// the order the program sent them.
+pub type KosBridge = Arc<Mutex<Option<bridge::Client>>>;
+
+fn poisoned(native: &str) -> KainError {
+    KainError::runtime(format!("{}: a thread panicked while holding the connection", native))
+}
+
+/// Unpack `(conn, sql, [params], ...)` for the sqlite natives
+fn sqlite_args(
+    env: &Env,
+    native: &str,
+    args: &[Value],
+    max: usize,
+) -> KainResult<(Arc<Mutex<rusqlite::Connection>>, String, Vec<rusqlite::types::Value>)> {
+    if args.len() < 2 || args.len() > max {
+        return Err(KainError::runtime(format!("{}: expected (conn, sql, [params])", native)));
+    }
+    let conn = match &args[0] {
+        Value::Struct(name, fields) if name == "SqliteConnection" => {
+            let id = match fields.read().map_err(|_| poisoned(native))?.get("id") {
+                Some(Value::Int(id)) => *id as usize,
+                _ => usize::MAX,
+            };
+            env.databases.lock().map_err(|_| poisoned(native))?.get(id).cloned()
+        }
+        _ => None,
+    };
+    let Some(conn) = conn else {
+        return Err(KainError::runtime(format!("{}: first argument must come from sqlite_open", native)));
+    };
+    let Value::String(sql) = &args[1] else {
+        return Err(KainError::runtime(format!("{}: sql must be a string", native)));
+    };
+    let params = match args.get(2) {
+        None => Vec::new(),
+        Some(Value::Array(values)) => sql_params(native, &values.read().map_err(|_| poisoned(native))?)?,
+        Some(_) => return Err(KainError::runtime(format!("{}: params must be an array", native))),
+    };
+    Ok((conn, sql.clone(), params))
+}
+
+/// Bind parameters for `?` placeholders; only scalars map onto SQL values
+fn sql_params(native: &str, params: &[Value]) -> KainResult<Vec<rusqlite::types::Value>> {
+    use rusqlite::types::Value as Sql;
+    params
+        .iter()
+        .map(|value| match value {
+            Value::Int(n) => Ok(Sql::Integer(*n)),
+            Value::Float(n) => Ok(Sql::Real(*n)),
+            Value::Bool(b) => Ok(Sql::Integer(*b as i64)),
+            Value::String(s) => Ok(Sql::Text(s.clone())),
+            Value::Char(c) => Ok(Sql::Text(c.to_string())),
+            Value::None | Value::Unit => Ok(Sql::Null),
+            Value::Some(inner) => sql_params(native, std::slice::from_ref(inner)).map(|mut one| one.remove(0)),
+            other => Err(KainError::runtime(format!("{}: cannot bind {} as a SQL parameter", native, other))),
+        })
+        .collect()
+}
+
+fn sql_to_value(value: rusqlite::types::ValueRef) -> Value {
+    use rusqlite::types::ValueRef;
+    match value {
+        ValueRef::Null => Value::None,
+        ValueRef::Integer(n) => Value::Int(n),
+        ValueRef::Real(n) => Value::Float(n),
+        ValueRef::Text(text) => Value::String(String::from_utf8_lossy(text).into_owned()),
+        ValueRef::Blob(bytes) => {
+            Value::Array(Arc::new(RwLock::new(bytes.iter().map(|b| Value::Int(*b as i64)).collect())))
+        }
+    }
+}
+
 /// Runtime VDOM Node
 #[derive(Clone, Debug)]
 pub enum VNode {
@@ -47,6 +220,82 @@ pub enum VNode {
     Text(String),
 }
 
+/// An integer range: `start..end` with `end` excluded, walked by `step`,
+/// which is negative once the range has been reversed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IntRange {
+    pub start: i64,
+    pub end: i64,
+    pub step: i64,
+}
+
+impl IntRange {
+    pub fn new(start: i64, end: i64) -> Self {
+        Self { start, end, step: 1 }
+    }
+
+    pub fn len(&self) -> i64 {
+        let (span, stride) = if self.step > 0 {
+            (self.end as i128 - self.start as i128, self.step as i128)
+        } else {
+            (self.start as i128 - self.end as i128, -(self.step as i128))
+        };
+        if span <= 0 {
+            0
+        } else {
+            ((span + stride - 1) / stride).min(i64::MAX as i128) as i64
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// No end was given (`start..`), so it can be walked but not collected
+    pub fn is_unbounded(&self) -> bool {
+        (self.step > 0 && self.end == i64::MAX) || (self.step < 0 && self.end == i64::MIN)
+    }
+
+    pub fn contains(&self, n: i64) -> bool {
+        let in_bounds = if self.step > 0 {
+            self.start <= n && n < self.end
+        } else {
+            self.end < n && n <= self.start
+        };
+        in_bounds && (n as i128 - self.start as i128) % self.step as i128 == 0
+    }
+
+    /// Every `n`th element, like Rust's `step_by`
+    pub fn step_by(&self, n: i64) -> Option<Self> {
+        (n > 0).then(|| Self { step: self.step.saturating_mul(n), ..*self })
+    }
+
+    /// The same elements, last to first
+    pub fn rev(&self) -> Self {
+        let len = self.len();
+        if len == 0 {
+            return Self { end: self.start, step: -self.step, ..*self };
+        }
+        let last = self.start as i128 + (len as i128 - 1) * self.step as i128;
+        Self { start: last as i64, end: self.start.saturating_sub(self.step.signum()), step: -self.step }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = i64> {
+        let range = *self;
+        (0..range.len()).map(move |i| range.start.wrapping_add(i.wrapping_mul(range.step)))
+    }
+}
+
+impl fmt::Display for IntRange {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}..{}", self.start, self.end)?;
+        if self.step != 1 {
+            write!(f, " step {}", self.step)?;
+        }
+        Ok(())
+    }
+}
+
 /// Runtime value
 #[derive(Clone)]
 pub enum Value {
@@ -55,25 +304,34 @@ pub enum Value {
     Int(i64),
     Float(f64),
     String(String),
+    /// `'a'`: one Unicode scalar value, which `+ 1` and `- 'a'` treat as its code point
+    Char(char),
     Array(Arc<RwLock<Vec<Value>>>),
     Tuple(Vec<Value>),
+    /// Lazy integer range from `a..b`, `a..=b`, `.step(n)` and `.rev()`
+    Range(IntRange),
     Struct(String, Arc<RwLock<HashMap<String, Value>>>),
     Function(String),
     NativeFn(String, fn(&mut Env, Vec<Value>) -> KainResult<Value>),
     ActorRef(ActorRef),
+    /// `None`, the absent Option
     None,
+    /// `Some(value)`, the present Option
+    Some(Box<Value>),
     /// Special value for return flow control
     Return(Box<Value>),
-    /// Break from loop with optional value
-    Break(Option<Box<Value>>),
-    /// Continue to next loop iteration
-    Continue,
+    /// Break from the loop with the given label (innermost if none), with optional value
+    Break(Option<String>, Option<Box<Value>>),
+    /// Continue to the next iteration of the labeled (or innermost) loop
+    Continue(Option<String>),
     /// Result: Ok(true, val) or Err(false, val)
     Result(bool, Box<Value>),
     /// Closure: params, body, captured_scopes
-    Closure(Vec<String>, Box<Expr>, Vec<HashMap<String, Value>>),
+    Closure(Vec<String>, Box<Expr>, Vec<Scope>),
     /// Struct Constructor: name, field_names
     StructConstructor(String, Vec<String>),
+    /// Method bound to its receiver: receiver, lowered Type_method name
+    BoundMethod(Box<Value>, String),
     /// JSX Element
     JSX(VNode),
     /// Enum variant: (enum_name, variant_name, fields)
@@ -82,6 +340,10 @@ pub enum Value {
     Poll(bool, Option<Box<Value>>),
     /// Future state machine: (struct_name, state_struct, poll_fn_name)
     Future(String, Arc<RwLock<HashMap<String, Value>>>),
+    /// Code produced by a `quote:` block, with its splices filled in
+    Quote(Arc<Block>),
+    /// A module imported with `use path as name`, whose items are `name.item`
+    Module(Arc<str>),
 }
 
 impl fmt::Debug for Value {
@@ -92,14 +354,18 @@ impl fmt::Debug for Value {
             Value::Int(i) => write!(f, "Int({})", i),
             Value::Float(fl) => write!(f, "Float({})", fl),
             Value::String(s) => write!(f, "String({:?})", s),
+            Value::Char(c) => write!(f, "Char({:?})", c),
             Value::Array(arr) => write!(f, "Array({:?})", arr),
             Value::Tuple(t) => write!(f, "Tuple({:?})", t),
+            Value::Range(r) => write!(f, "Range({})", r),
             Value::Struct(name, fields) => write!(f, "Struct({}, {:?})", name, fields),
             Value::Function(name) => write!(f, "Function({})", name),
             Value::NativeFn(name, _) => write!(f, "NativeFn({})", name),
             Value::StructConstructor(name, _) => write!(f, "StructConstructor({})", name),
+            Value::BoundMethod(receiver, name) => write!(f, "BoundMethod({:?}, {})", receiver, name),
             Value::ActorRef(r) => write!(f, "ActorRef({:?})", r),
             Value::None => write!(f, "None"),
+            Value::Some(v) => write!(f, "Some({:?})", v),
             Value::Return(v) => write!(f, "Return({:?})", v),
             Value::Result(ok, v) => {
                 if *ok {
@@ -119,8 +385,10 @@ impl fmt::Debug for Value {
                 }
             }
             Value::Future(name, _) => write!(f, "Future<{}>", name),
-            Value::Break(v) => write!(f, "Break({:?})", v),
-            Value::Continue => write!(f, "Continue"),
+            Value::Quote(block) => write!(f, "Quote({} stmts)", block.stmts.len()),
+            Value::Module(path) => write!(f, "Module({})", path),
+            Value::Break(label, v) => write!(f, "Break({:?}, {:?})", label, v),
+            Value::Continue(label) => write!(f, "Continue({:?})", label),
         }
     }
 }
@@ -154,94 +422,7 @@ impl fmt::Display for VNode {
 
 impl fmt::Display for Value {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        match self {
-            Value::Unit => write!(f, "()"),
-            Value::Bool(b) => write!(f, "{}", b),
-            Value::Int(i) => write!(f, "{}", i),
-            Value::Float(fl) => write!(f, "{}", fl),
-            Value::String(s) => write!(f, "{}", s),
-            Value::Array(arr) => {
-                write!(f, "[")?;
-                let arr = arr.read().unwrap();
-                for (i, v) in arr.iter().enumerate() {
-                    if i > 0 {
-                        write!(f, ", ")?;
-                    }
-                    write!(f, "{}", v)?;
-                }
-                write!(f, "]")
-            }
-            Value::Tuple(t) => {
-                write!(f, "(")?;
-                for (i, v) in t.iter().enumerate() {
-                    if i > 0 {
-                        write!(f, ", ")?;
-                    }
-                    write!(f, "{}", v)?;
-                }
-                write!(f, ")")
-            }
-            Value::Struct(name, fields) => {
-                write!(f, "{} {{", name)?;
-                let fields = fields.read().unwrap();
-                for (i, (k, v)) in fields.iter().enumerate() {
-                    if i > 0 {
-                        write!(f, ", ")?;
-                    }
-                    write!(f, "{}: {}", k, v)?;
-                }
-                write!(f, "}}")
-            }
-            Value::Function(name) => write!(f, "<fn {}>", name),
-            Value::NativeFn(name, _) => write!(f, "<native fn {}>", name),
-            Value::StructConstructor(name, _) => write!(f, "<constructor {}>", name),
-            Value::ActorRef(r) => write!(f, "<actor {}>", r.id),
-            Value::None => write!(f, "none"),
-            Value::Return(v) => write!(f, "{}", v),
-            Value::Result(ok, v) => {
-                if *ok {
-                    write!(f, "Ok({})", v)
-                } else {
-                    write!(f, "Err({})", v)
-                }
-            }
-            Value::Closure(_, _, _) => write!(f, "<closure>"),
-            Value::JSX(node) => write!(f, "{}", node),
-            Value::EnumVariant(enum_name, variant, fields) => {
-                if fields.is_empty() {
-                    write!(f, "{}::{}", enum_name, variant)
-                } else {
-                    write!(f, "{}::{}(", enum_name, variant)?;
-                    for (i, v) in fields.iter().enumerate() {
-                        if i > 0 {
-                            write!(f, ", ")?;
-                        }
-                        write!(f, "{}", v)?;
-                    }
-                    write!(f, ")")
-                }
-            }
-            Value::Poll(ready, val) => {
-                if *ready {
-                    if let Some(v) = val {
-                        write!(f, "Poll::Ready({})", v)
-                    } else {
-                        write!(f, "Poll::Ready(())")
-                    }
-                } else {
-                    write!(f, "Poll::Pending")
-                }
-            }
-            Value::Future(name, _) => write!(f, "<future {}>", name),
-            Value::Break(v) => {
-                if let Some(val) = v {
-                    write!(f, "<break {}>", val)
-                } else {
-                    write!(f, "<break>")
-                }
-            }
-            Value::Continue => write!(f, "<continue>"),
-        }
+        f.write_str(&pretty(self, &PrettyOptions::FULL))
     }
 }
 
@@ -250,6 +431,17 @@ impl fmt::Display for Value {
 pub struct ActorRef {
     pub id: u64,
     pub sender: Sender<Message>,
+    /// What a send does when the mailbox is full; `None` when it is unbounded
+    pub overflow: Option<Overflow>,
+}
+
+/// How a send to an actor's full bounded mailbox behaves, from its `mailbox(policy = ...)`
+#[derive(Debug, Clone)]
+pub enum Overflow {
+    Block,
+    /// Make room by taking the oldest message out through the actor's receiver
+    DropOldest(Receiver<Message>),
+    Fail,
 }
 
 /// Message for actor communication
@@ -257,39 +449,495 @@ pub struct ActorRef {
 pub struct Message {
     pub name: String,
     pub args: Vec<Value>,
+    /// Who sent it, which identifies it in a recording
+    pub origin: replay::Origin,
+}
+
+/// Natives that write to or read from the console
+pub const CONSOLE_NATIVES: &[&str] = &["print", "println", "read_line"];
+
+/// Natives that read or write files
+pub const FILE_NATIVES: &[&str] = &["read_file", "write_file", "copy_file", "file_exists", "sqlite_open", "sqlite_query", "sqlite_exec", "snapshot", "restore", "image_save_png"];
+
+/// Natives that open a window, read input devices or play sound, from `std/game`
+pub const GAME_NATIVES: &[&str] = &[
+    "game_open", "game_poll", "game_key_down", "game_mouse", "game_mouse_down", "game_present",
+    "game_gamepad_down", "game_gamepad_axis", "game_play_sound",
+];
+
+/// Natives that need the `Db` effect
+pub const DB_NATIVES: &[&str] = &["sqlite_open", "sqlite_query", "sqlite_exec"];
+
+/// Natives that talk to the network
+pub const NETWORK_NATIVES: &[&str] = &[
+    "http_get", "http_post_json", "kos_connect", "kos_spawn", "spawn_cube", "kos_transform", "kos_destroy", "kos_query",
+];
+
+/// Natives that touch the host process: environment, stdin, exit and embedded Python
+pub const PROCESS_NATIVES: &[&str] = &["env", "args", "exit", "read_line", "py_eval", "py_exec", "py_import"];
+
+/// Natives that only compute on their arguments: no IO, clock, randomness,
+/// threads or host state. Compile-time evaluation may call these and no others.
+pub const PURE_NATIVES: &[&str] = &[
+    "Some", "ok", "err", "assert", "panic", "type_of", "variant_of", "variant_field",
+    "int", "float", "bool", "str", "char", "chr", "ord", "to_int", "to_string",
+    "abs", "sqrt", "pow", "exp", "ln", "log2", "log10", "floor", "ceil", "round", "trunc", "clamp", "min", "max",
+    "sin", "cos", "tan", "asin", "acos", "atan", "atan2", "sinh", "cosh", "tanh", "is_nan", "is_inf",
+    "len", "byte_len", "char_len", "chars", "graphemes", "char_at", "substring", "split", "join", "trim", "upper",
+    "lower", "replace", "contains", "starts_with", "ends_with", "index_of", "concat", "html_escape",
+    "range", "first", "last", "push", "pop", "insert", "remove", "slice", "reverse", "flatten", "unique", "zip",
+    "map", "filter", "reduce", "foreach", "sum", "sort", "sort_by", "binary_search", "group_by", "min_by", "max_by",
+    "json_parse", "json_string", "serialize", "deserialize", "serialize_json", "deserialize_json",
+    "template_check", "template_render", "cli_spec",
+    "image_new", "image_new_float", "image_get_pixel", "image_set_pixel",
+];
+
+/// Resource limits for one interpreter run; `None` means unlimited
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct InterpretOptions {
+    /// Maximum number of statements and expressions evaluated
+    pub max_steps: Option<u64>,
+    /// Maximum depth of nested function and closure calls
+    pub max_call_depth: Option<usize>,
+    /// Maximum bytes of strings, arrays, tuples and structs allocated over the
+    /// run (an approximation that counts allocations, not live memory); what a
+    /// `region:` block allocated stops counting when it exits
+    pub max_heap: Option<usize>,
+    /// Wall-clock budget for the run, checked between evaluation steps
+    pub timeout: Option<Duration>,
+}
+
+/// The build a `build.kn` script runs in, read through the `build_*` natives
+#[derive(Debug, Clone, Default)]
+pub struct BuildContext {
+    /// Name of the profile being built
+    pub profile: String,
+    /// Every target the build produces
+    pub targets: Vec<String>,
+    /// Directory the outputs are written to
+    pub out_dir: String,
+    /// During `post_build`, the target whose output was just written
+    pub target: Option<String>,
+    /// During `post_build`, the path of that output
+    pub output: Option<String>,
+}
+
+/// What an interpreter run has consumed so far
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ResourceUsage {
+    pub steps: u64,
+    pub call_depth: usize,
+    pub heap: usize,
+}
+
+/// Messages an actor can have waiting before the program is warned that it
+/// receives them faster than it handles them
+pub const MAILBOX_WARNING: usize = 10_000;
+
+/// What an interpreter holds at one moment, from [`Env::runtime_stats`]
+#[derive(Debug, Clone, Default)]
+pub struct RuntimeStats {
+    /// Values reachable from the environment's scopes and `static mut`s
+    pub live_values: usize,
+    /// Actors whose event loops are still running, in the order they were spawned
+    pub actors: Vec<ActorStats>,
+    /// Heap allocations made in each function, most first; `<top level>`
+    /// counts those made outside any function
+    pub allocations: Vec<(String, u64)>,
+}
+
+#[derive(Debug, Clone)]
+pub struct ActorStats {
+    pub id: u64,
+    pub name: String,
+    /// Messages sent to the actor that it has not handled yet
+    pub mailbox: usize,
+}
+
+impl fmt::Display for RuntimeStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "runtime stats:")?;
+        writeln!(f, "  live values: {}", self.live_values)?;
+        writeln!(f, "  actors: {}", self.actors.len())?;
+        for actor in &self.actors {
+            writeln!(f, "    {}#{}: {} queued", actor.name, actor.id, actor.mailbox)?;
+        }
+        writeln!(f, "  allocations:")?;
+        for (scope, count) in &self.allocations {
+            writeln!(f, "    {}: {}", scope, count)?;
+        }
+        Ok(())
+    }
+}
+
+/// What `--trace` records; each mode records everything the one before it does
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum TraceMode {
+    /// Each call of a function, method, closure or native, with its arguments
+    Calls,
+    /// Each statement run as well, with where it is
+    Stmts,
+    /// What calls return and what `let` and expression statements evaluate to
+    Values,
+}
+
+impl TraceMode {
+    pub fn parse(name: &str) -> Option<TraceMode> {
+        match name {
+            "calls" => Some(TraceMode::Calls),
+            "stmts" => Some(TraceMode::Stmts),
+            "values" => Some(TraceMode::Values),
+            _ => None,
+        }
+    }
+}
+
+/// An execution trace for one interpreter run (`kain run --trace`)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TraceOptions {
+    pub mode: TraceMode,
+    /// File the trace is written to; stderr when `None`
+    pub file: Option<PathBuf>,
+    /// The file being run, read through [`vfs`]. Statements are located by
+    /// line and column in it; without it, by byte offset.
+    pub source: Option<PathBuf>,
+}
+
+/// Writes an environment's trace. Actors write to their program's sink,
+/// each line prefixed with the actor, and indent by their own call depth.
+#[derive(Clone)]
+pub struct Tracer {
+    mode: TraceMode,
+    sink: Arc<Mutex<Box<dyn std::io::Write + Send>>>,
+    source: Option<Arc<vfs::SourceFile>>,
+    prefix: String,
+    depth: usize,
+}
+
+/// Longest value a trace line shows before cutting it short
+const TRACE_VALUE_LIMIT: usize = 80;
+
+impl Tracer {
+    pub fn new(mode: TraceMode, sink: impl std::io::Write + Send + 'static) -> Tracer {
+        Tracer { mode, sink: Arc::new(Mutex::new(Box::new(sink))), source: None, prefix: String::new(), depth: 0 }
+    }
+
+    /// Open the file `options` names, or stderr
+    pub fn open(options: &TraceOptions) -> KainResult<Tracer> {
+        let mut tracer = match &options.file {
+            Some(path) => {
+                let file = std::fs::File::create(path)
+                    .map_err(|e| KainError::runtime(format!("cannot write trace to {}: {}", path.display(), e)))?;
+                // Whole lines reach the file even if the program calls `exit()`
+                Tracer::new(options.mode, std::io::LineWriter::new(file))
+            }
+            None => Tracer::new(options.mode, std::io::stderr()),
+        };
+        tracer.source = options.source.as_deref().and_then(|path| vfs::SourceMap::global().read().unwrap_or_else(|e| e.into_inner()).lookup(path));
+        Ok(tracer)
+    }
+
+    fn line(&self, text: &str) {
+        use std::io::Write;
+        let line = format!("{}{}{}\n", self.prefix, "  ".repeat(self.depth), text);
+        // A trace that can't be written must not stop the program
+        let _ = self.sink.lock().unwrap_or_else(|e| e.into_inner()).write_all(line.as_bytes());
+    }
+
+    /// `path:line:col` of `span` and the source line it starts on, or its byte offset when the file is unknown
+    fn locate(&self, span: Span) -> (String, Option<String>) {
+        match source_file(span, self.source.as_ref()) {
+            Some(file) => {
+                let (line, col) = file.line_col(span.start);
+                (format!("{}:{}:{}", file.path.display(), line, col), Some(file.line(line).trim().to_string()))
+            }
+            None => (format!("@{}", span.start), None),
+        }
+    }
+}
+
+/// The file `span` is in: its own, or `main` for spans of the program being run
+fn source_file(span: Span, main: Option<&Arc<vfs::SourceFile>>) -> Option<Arc<vfs::SourceFile>> {
+    match span.file {
+        Some(id) => vfs::SourceMap::global().read().unwrap_or_else(|e| e.into_inner()).get(id),
+        None => main.cloned(),
+    }
+}
+
+/// A value as a trace shows it: strings quoted, and long values cut short
+fn trace_repr(value: &Value) -> String {
+    let text = match value {
+        Value::String(s) => format!("{:?}", s),
+        Value::Return(v) => return trace_repr(v),
+        v => pretty(v, &PrettyOptions::BRIEF),
+    };
+    match text.char_indices().nth(TRACE_VALUE_LIMIT) {
+        Some((end, _)) => format!("{}...", &text[..end]),
+        None => text,
+    }
+}
+
+/// Every actor a program spawns, shared by the program and its actors so ids
+/// are unique and [`Env::runtime_stats`] sees them all
+#[derive(Debug, Default)]
+struct ActorRegistry {
+    next_id: u64,
+    /// Id, actor type and mailbox of each actor; weak, so an actor nothing
+    /// refers to can still stop
+    actors: Vec<(u64, String, WeakSender<Message>)>,
+    /// Actors the program was already warned about
+    warned: HashSet<u64>,
+}
+
+impl ActorRegistry {
+    fn register(&mut self, name: &str, mailbox: &Sender<Message>) -> u64 {
+        self.next_id += 1;
+        self.actors.push((self.next_id, name.to_string(), mailbox.downgrade()));
+        self.next_id
+    }
+
+    /// Type of the actor `id`, for messages about it
+    fn name(&self, id: u64) -> &str {
+        self.actors.iter().find(|(actor, ..)| *actor == id).map_or("actor", |(_, name, _)| name.as_str())
+    }
+
+    fn live(&self) -> impl Iterator<Item = (u64, &str, Sender<Message>)> {
+        self.actors
+            .iter()
+            .filter_map(|(id, name, mailbox)| Some((*id, name.as_str(), mailbox.upgrade()?)))
+            .filter(|(_, _, mailbox)| !mailbox.is_disconnected())
+    }
 }
 
 /// Interpreter environment
 #[derive(Clone)]
 pub struct Env {
-    scopes: Vec<HashMap<String, Value>>,
+    scopes: Vec<Scope>,
     functions: HashMap<String, Function>,
     components: HashMap<String, Component>,
     /// Methods: type_name -> method_name -> function
     methods: HashMap<String, HashMap<String, Function>>,
-    #[allow(dead_code)]
-    actors: HashMap<u64, Sender<Message>>,
-    #[allow(dead_code)]
-    next_actor_id: u64,
+    /// Actors spawned by the program, this environment's or another actor's
+    actors: Arc<Mutex<ActorRegistry>>,
     actor_defs: HashMap<String, Actor>,
     /// ID of the current actor if running inside one
     self_actor_id: Option<u64>,
     /// Python global scope
     python_scope: Option<PyObject>,
+    limits: InterpretOptions,
+    /// How `print`, `println` and `dbg` show values
+    print: PrettyOptions,
+    /// The file being run, which `dbg!` quotes its expressions from
+    source: Option<Arc<vfs::SourceFile>>,
+    usage: ResourceUsage,
+    /// Heap allocations charged in each function, for [`Env::runtime_stats`]
+    allocations: HashMap<String, u64>,
+    /// Function being run, `None` at the top level
+    function: Option<String>,
+    /// When `limits.timeout` runs out
+    deadline: Option<Instant>,
+    /// Natives this environment refuses to call, with the reason given to the user
+    denied_natives: HashMap<String, String>,
+    /// Where `print` and `println` write; stdout when `None`
+    output: Option<Arc<Mutex<String>>>,
+    /// Where errors and warnings that no caller can receive are written, such
+    /// as those from actor handlers; stderr when `None`
+    errors: Option<Arc<Mutex<String>>>,
+    /// Items handed to `emit_item` by compile-time code
+    emitted_items: Vec<Item>,
+    /// Open SQLite connections
+    databases: Databases,
+    /// Connection to the KOS engine
+    kos: KosBridge,
+    /// Command-line arguments for `args()`
+    program_args: Vec<String>,
+    /// Module each imported function came from, the log target of its records
+    function_modules: HashMap<String, Arc<str>>,
+    /// Module of the function being run; `None` for the program's own code
+    current_module: Option<Arc<str>>,
+    /// Syntax rules imported modules are lexed under, and whether their private items are hidden
+    edition: Edition,
+    /// Imported modules by the path they were first imported as
+    modules: HashMap<Arc<str>, ModuleScope>,
+    /// Module each loaded file was registered as, by canonical path
+    loaded_modules: HashMap<PathBuf, Arc<str>>,
+    /// Modules being imported, outermost first, to report import cycles
+    loading: Vec<(PathBuf, String)>,
+    /// Where each imported name of a namespace (the program's when `None`) came from
+    imports: HashMap<(Option<Arc<str>>, String), Import>,
+    /// `static mut` values, a module's own qualified with the module; shared
+    /// with every actor the program spawns
+    statics: Arc<RwLock<HashMap<String, Value>>>,
+    /// The build a build script runs in; `None` for other programs
+    build: Option<BuildContext>,
+    /// `with handler` blocks being run, innermost last
+    handlers: Vec<EffectHandler>,
+    /// Cancel tokens and deadline of the async task being run
+    task: TaskScope,
+    /// In an actor with `persist state`, what `snapshot()` saves
+    snapshot: Option<Snapshot>,
+    /// Where calls and statements are recorded under `--trace`
+    trace: Option<Tracer>,
+    /// The recording this run writes or follows (`--record`, `--replay`)
+    journal: Option<Arc<replay::Journal>>,
+    /// Messages this environment has sent, for their [`Message::origin`]
+    sent: u64,
+    /// Under `kain test --schedules`, what decides which actor handles a message next
+    scheduler: Option<Arc<schedule::Scheduler>>,
+}
+
+/// The `persist state` of the actor an environment runs
+#[derive(Clone)]
+struct Snapshot {
+    /// Where `snapshot()` and `restore()` go without a path: `<Actor>.snapshot`
+    path: PathBuf,
+    fields: Vec<(String, Type)>,
+}
+
+/// What stops the async task being run: the tokens it and the tasks that
+/// spawned it were given, and the earliest of their timeouts
+#[derive(Clone, Default)]
+struct TaskScope {
+    tokens: Vec<Value>,
+    deadline: Option<Instant>,
+}
+
+impl TaskScope {
+    fn cancelled(&self) -> bool {
+        self.tokens.iter().any(|token| matches!(token, Value::Struct(_, fields)
+            if matches!(fields.read().unwrap_or_else(|e| e.into_inner()).get("cancelled"), Some(Value::Bool(true)))))
+    }
+
+    fn timed_out(&self) -> bool {
+        self.deadline.is_some_and(|deadline| Instant::now() >= deadline)
+    }
+
+    /// Whether the task can run forever, so a future that never resolves is a bug
+    fn unbounded(&self) -> bool {
+        self.tokens.is_empty() && self.deadline.is_none()
+    }
+}
+
+/// A `with handler` block: while its body runs, operations of `effect` are
+/// calls to the methods of `type_name`, on `receiver` when the handler is a value
+#[derive(Clone)]
+struct EffectHandler {
+    effect: Effect,
+    type_name: String,
+    receiver: Option<Value>,
+}
+
+/// The bindings of one scope. A name keeps the slot it was first defined in,
+/// so a variable [`crate::resolve`] located ahead of time is read by index
+/// instead of by hashing its name.
+#[derive(Clone, Default)]
+pub struct Scope {
+    slots: HashMap<String, usize>,
+    /// Name held by each slot; empty once the name is removed
+    names: Vec<String>,
+    values: Vec<Value>,
+}
+
+impl Scope {
+    fn get(&self, name: &str) -> Option<&Value> {
+        self.slots.get(name).map(|&slot| &self.values[slot])
+    }
+
+    fn contains_key(&self, name: &str) -> bool {
+        self.slots.contains_key(name)
+    }
+
+    fn insert(&mut self, name: String, value: Value) {
+        match self.slots.get(&name) {
+            Some(&slot) => self.values[slot] = value,
+            None => {
+                self.slots.insert(name.clone(), self.values.len());
+                self.names.push(name);
+                self.values.push(value);
+            }
+        }
+    }
+
+    fn remove(&mut self, name: &str) -> Option<Value> {
+        let slot = self.slots.remove(name)?;
+        self.names[slot].clear();
+        Some(std::mem::replace(&mut self.values[slot], Value::Unit))
+    }
+
+    fn values(&self) -> impl Iterator<Item = &Value> {
+        self.slots.values().map(|&slot| &self.values[slot])
+    }
+
+    /// The value in `slot`, if that slot holds `name`
+    fn slot(&self, slot: usize, name: &str) -> Option<&Value> {
+        (self.names.get(slot)? == name).then(|| &self.values[slot])
+    }
+
+    fn slot_mut(&mut self, slot: usize, name: &str) -> Option<&mut Value> {
+        (self.names.get(slot)? == name).then(|| &mut self.values[slot])
+    }
+}
+
+/// The names an imported module's code sees, and the items it defines
+#[derive(Clone, Default)]
+struct ModuleScope {
+    /// Its own items and the names it imports
+    names: Scope,
+    /// Its own items, with their visibility
+    items: HashMap<String, (Value, Visibility)>,
+}
+
+/// An imported name: the module it came from, whether it was named explicitly
+/// (`use m: {name}` or `use m as name`), and whether two plain imports both
+/// provide it, which makes it an error to use
+#[derive(Clone)]
+struct Import {
+    from: Arc<str>,
+    explicit: bool,
+    ambiguous_with: Option<Arc<str>>,
 }
 
 impl Env {
     pub fn new() -> Self {
         let mut env = Self {
-            scopes: vec![HashMap::new()],
+            scopes: vec![Scope::default()],
             functions: HashMap::new(),
             components: HashMap::new(),
             methods: HashMap::new(),
-            actors: HashMap::new(),
-            next_actor_id: 1,
+            actors: Arc::default(),
             actor_defs: HashMap::new(),
             self_actor_id: None,
             python_scope: None,
+            limits: InterpretOptions::default(),
+            print: PrettyOptions::default(),
+            source: None,
+            usage: ResourceUsage::default(),
+            allocations: HashMap::new(),
+            function: None,
+            deadline: None,
+            denied_natives: HashMap::new(),
+            output: None,
+            errors: None,
+            emitted_items: Vec::new(),
+            databases: Databases::default(),
+            kos: KosBridge::default(),
+            program_args: Vec::new(),
+            function_modules: HashMap::new(),
+            current_module: None,
+            edition: Edition::default(),
+            modules: HashMap::new(),
+            loaded_modules: HashMap::new(),
+            loading: Vec::new(),
+            imports: HashMap::new(),
+            statics: Arc::default(),
+            build: None,
+            handlers: Vec::new(),
+            task: TaskScope::default(),
+            snapshot: None,
+            trace: None,
+            journal: None,
+            sent: 0,
+            scheduler: None,
         };
 
         // Initialize Python scope
@@ -302,44 +950,137 @@ impl Env {
             env.python_scope = Some(scope.into());
         });
 
-        env.register_stdlib();
-        env.register_net_stdlib();
         env.register_stdlib();
         env.register_net_stdlib();
         env.register_json_stdlib();
+        env.register_serialize_stdlib();
+        env.register_sqlite_stdlib();
+        env.register_template_stdlib();
+        env.register_cli_stdlib();
+        env.register_log_stdlib();
+        env.register_image_stdlib();
+        env.register_game_stdlib();
+        env.register_array_stdlib();
         env.register_kos_bridge();
         env
     }
 
+    /// Commands for a running KOS engine, see [`bridge`]
     pub fn register_kos_bridge(&mut self) {
-        self.define_native("spawn_cube", |_env, args| {
-            if args.len() != 2 {
-                return Err(KainError::runtime(
-                    "spawn_cube: expected 2 arguments (x, y)",
-                ));
+        /// Run `f` on the engine connection, connecting first if this is the first command
+        fn with_kos<T>(env: &Env, native: &str, f: impl FnOnce(&mut bridge::Client) -> Result<T, String>) -> KainResult<T> {
+            let mut kos = env.kos.lock().unwrap_or_else(|e| e.into_inner());
+            if kos.is_none() {
+                *kos = Some(bridge::Client::connect_default().map_err(|e| KainError::runtime(format!("{}: {}", native, e)))?);
+            }
+            f(kos.as_mut().expect("connected above")).map_err(|e| KainError::runtime(format!("{}: {}", native, e)))
+        }
+
+        fn number(native: &str, value: &Value) -> KainResult<f64> {
+            match value {
+                Value::Int(n) => Ok(*n as f64),
+                Value::Float(n) => Ok(*n),
+                _ => Err(KainError::runtime(format!("{}: expected a number, found {}", native, value))),
             }
-            let x = match args[0] {
-                Value::Int(n) => n as f64,
-                Value::Float(n) => n,
-                _ => return Err(KainError::runtime("spawn_cube: x must be number")),
+        }
+
+        /// An `(x, y, z)` tuple or `[x, y, z]` array; `None` when the argument is None
+        fn vec3(native: &str, value: Option<&Value>) -> KainResult<Option<[f64; 3]>> {
+            let items = match value {
+                None | Some(Value::None) => return Ok(None),
+                Some(Value::Tuple(items)) => items.clone(),
+                Some(Value::Array(items)) => items.read().unwrap().clone(),
+                Some(other) => return Err(KainError::runtime(format!("{}: expected (x, y, z), found {}", native, other))),
+            };
+            match items.as_slice() {
+                [x, y, z] => Ok(Some([number(native, x)?, number(native, y)?, number(native, z)?])),
+                _ => Err(KainError::runtime(format!("{}: expected (x, y, z), found {} components", native, items.len()))),
+            }
+        }
+
+        fn id(native: &str, value: Option<&Value>) -> KainResult<u64> {
+            match value {
+                Some(Value::Int(id)) if *id > 0 => Ok(*id as u64),
+                _ => Err(KainError::runtime(format!("{}: expected an entity id from kos_spawn", native))),
+            }
+        }
+
+        fn tuple3(v: [f64; 3]) -> Value {
+            Value::Tuple(v.iter().map(|n| Value::Float(*n)).collect())
+        }
+
+        // kos_connect([addr]) -> the engine's name; otherwise the first command
+        // connects to KAIN_KOS_BRIDGE or the default address
+        self.define_native("kos_connect", |env, args| {
+            let client = match args.as_slice() {
+                [] => bridge::Client::connect_default(),
+                [Value::String(addr)] => bridge::Client::connect(addr),
+                _ => return Err(KainError::runtime("kos_connect: expected an optional address, e.g. \"127.0.0.1:7878\"")),
             };
-            let y = match args[1] {
-                Value::Int(n) => n as f64,
-                Value::Float(n) => n,
-                _ => return Err(KainError::runtime("spawn_cube: y must be number")),
+            let client = client.map_err(|e| KainError::runtime(format!("kos_connect: {}", e)))?;
+            let engine = client.engine.clone();
+            *env.kos.lock().unwrap_or_else(|e| e.into_inner()) = Some(client);
+            Ok(Value::String(engine))
+        });
+
+        // kos_spawn(kind, x, y, [z]) -> id
+        self.define_native("kos_spawn", |env, args| {
+            let (kind, coords) = match args.as_slice() {
+                [Value::String(kind), coords @ ..] if coords.len() == 2 || coords.len() == 3 => (kind, coords),
+                _ => return Err(KainError::runtime("kos_spawn: expected (kind, x, y, [z])")),
             };
+            let z = coords.get(2).map_or(Ok(0.0), |z| number("kos_spawn", z))?;
+            let position = [number("kos_spawn", &coords[0])?, number("kos_spawn", &coords[1])?, z];
+            let id = with_kos(env, "kos_spawn", |kos| kos.spawn(kind, position))?;
+            Ok(Value::Int(id as i64))
+        });
 
-            println!(
-                " [KOS Bridge] Spawning Cube at {{ x: {:.2}, y: {:.2} }}",
-                x, y
-            );
+        self.define_native("spawn_cube", |env, args| {
+            let [x, y] = args.as_slice() else {
+                return Err(KainError::runtime("spawn_cube: expected 2 arguments (x, y)"));
+            };
+            let position = [number("spawn_cube", x)?, number("spawn_cube", y)?, 0.0];
+            with_kos(env, "spawn_cube", |kos| kos.spawn("cube", position))?;
             Ok(Value::Unit)
         });
+
+        // kos_transform(id, position, [rotation], [scale]); None leaves a part as it is
+        self.define_native("kos_transform", |env, args| {
+            if args.len() < 2 || args.len() > 4 {
+                return Err(KainError::runtime("kos_transform: expected (id, position, [rotation], [scale])"));
+            }
+            let id = id("kos_transform", args.first())?;
+            let position = vec3("kos_transform", args.get(1))?;
+            let rotation = vec3("kos_transform", args.get(2))?;
+            let scale = vec3("kos_transform", args.get(3))?;
+            with_kos(env, "kos_transform", |kos| kos.transform(id, position, rotation, scale))?;
+            Ok(Value::Unit)
+        });
+
+        self.define_native("kos_destroy", |env, args| {
+            let id = id("kos_destroy", args.first())?;
+            with_kos(env, "kos_destroy", |kos| kos.destroy(id))?;
+            Ok(Value::Unit)
+        });
+
+        // kos_query(id) -> KosEntity { id, kind, position, rotation, scale }
+        self.define_native("kos_query", |env, args| {
+            let id = id("kos_query", args.first())?;
+            let entity = with_kos(env, "kos_query", |kos| kos.query(id))?;
+            let fields = HashMap::from([
+                ("id".to_string(), Value::Int(entity.id as i64)),
+                ("kind".to_string(), Value::String(entity.kind)),
+                ("position".to_string(), tuple3(entity.position)),
+                ("rotation".to_string(), tuple3(entity.rotation)),
+                ("scale".to_string(), tuple3(entity.scale)),
+            ]);
+            Ok(Value::Struct("KosEntity".to_string(), Arc::new(RwLock::new(fields))))
+        });
     }
 
     pub fn register_net_stdlib(&mut self) {
         // === HTTP Operations ===
-        self.define_native("http_get", |_env, args| {
+        self.define_native("http_get", |env, args| {
             if args.len() != 1 {
                 return Err(KainError::runtime("http_get: expected 1 argument (url)"));
             }
@@ -348,7 +1089,7 @@ impl Env {
                 _ => return Err(KainError::runtime("http_get: argument must be string url")),
             };
 
-            let res = reqwest::blocking::get(&url);
+            let res = http_client(env)?.get(&url).send();
 
             match res {
                 Ok(resp) => match resp.text() {
@@ -358,14 +1099,14 @@ impl Env {
                         e
                     ))),
                 },
-                Err(e) => Err(KainError::runtime(format!(
-                    "http_get: request failed: {}",
-                    e
-                ))),
+                Err(e) => {
+                    env.time_left()?;
+                    Err(KainError::runtime(format!("http_get: request failed: {}", e)))
+                }
             }
         });
 
-        self.define_native("http_post_json", |_env, args| {
+        self.define_native("http_post_json", |env, args| {
             if args.len() != 2 {
                 return Err(KainError::runtime(
                     "http_post: expected 2 arguments (url, json_string)",
@@ -380,9 +1121,7 @@ impl Env {
                 _ => return Err(KainError::runtime("http_post: body must be string")),
             };
 
-            let client = reqwest::blocking::Client::new();
-
-            let res = client
+            let res = http_client(env)?
                 .post(&url)
                 .header("Content-Type", "application/json")
                 .body(body)
@@ -396,11 +1135,569 @@ impl Env {
                         e
                     ))),
                 },
-                Err(e) => Err(KainError::runtime(format!(
-                    "http_post: request failed: {}",
-                    e
+                Err(e) => {
+                    env.time_left()?;
+                    Err(KainError::runtime(format!("http_post: request failed: {}", e)))
+                }
+            }
+        });
+    }
+
+    /// SQLite through an embedded copy of the library, so scripts get a
+    /// database without any Python or system packages
+    pub fn register_sqlite_stdlib(&mut self) {
+        self.define_native("sqlite_open", |env, args| {
+            let [Value::String(path)] = args.as_slice() else {
+                return Err(KainError::runtime("sqlite_open: expected 1 argument (path or \":memory:\")"));
+            };
+            let conn = rusqlite::Connection::open(path)
+                .map_err(|e| KainError::runtime(format!("sqlite_open: {}", e)))?;
+
+            let mut databases = env.databases.lock().map_err(|_| poisoned("sqlite_open"))?;
+            let mut handle = HashMap::new();
+            handle.insert("id".to_string(), Value::Int(databases.len() as i64));
+            handle.insert("path".to_string(), Value::String(path.clone()));
+            databases.push(Arc::new(Mutex::new(conn)));
+            Ok(Value::Struct("SqliteConnection".to_string(), Arc::new(RwLock::new(handle))))
+        });
+
+        // sqlite_exec(conn, sql, [params]) -> rows changed
+        self.define_native("sqlite_exec", |env, args| {
+            let (conn, sql, params) = sqlite_args(env, "sqlite_exec", &args, 3)?;
+            let conn = conn.lock().map_err(|_| poisoned("sqlite_exec"))?;
+            let changed = conn
+                .execute(&sql, rusqlite::params_from_iter(params))
+                .map_err(|e| KainError::runtime(format!("sqlite_exec: {}", e)))?;
+            Ok(Value::Int(changed as i64))
+        });
+
+        // sqlite_query(conn, sql, [params], [struct_name]) -> rows as structs named
+        // after their columns; `struct_name` defaults to `Row`
+        self.define_native("sqlite_query", |env, args| {
+            let (conn, sql, params) = sqlite_args(env, "sqlite_query", &args, 4)?;
+            let struct_name = match args.get(3) {
+                None => "Row".to_string(),
+                Some(Value::String(name)) => name.clone(),
+                Some(_) => return Err(KainError::runtime("sqlite_query: struct name must be a string")),
+            };
+            let conn = conn.lock().map_err(|_| poisoned("sqlite_query"))?;
+            let query = || -> rusqlite::Result<Vec<Value>> {
+                let mut stmt = conn.prepare(&sql)?;
+                let columns: Vec<String> = stmt.column_names().into_iter().map(String::from).collect();
+                let mut result = stmt.query(rusqlite::params_from_iter(params))?;
+                let mut rows = Vec::new();
+                while let Some(row) = result.next()? {
+                    let mut fields = HashMap::new();
+                    for (i, column) in columns.iter().enumerate() {
+                        fields.insert(column.clone(), sql_to_value(row.get_ref(i)?));
+                    }
+                    rows.push(Value::Struct(struct_name.clone(), Arc::new(RwLock::new(fields))));
+                }
+                Ok(rows)
+            };
+            let rows = query().map_err(|e| KainError::runtime(format!("sqlite_query: {}", e)))?;
+            Ok(Value::Array(Arc::new(RwLock::new(rows))))
+        });
+    }
+
+    pub fn register_template_stdlib(&mut self) {
+        // template_render(source, data) -> String
+        self.define_native("template_render", |_env, args| {
+            let [Value::String(source), data] = args.as_slice() else {
+                return Err(KainError::runtime("template_render: expected (source, data)"));
+            };
+            let nodes = template::parse(source).map_err(|e| KainError::runtime(format!("template: {}", e)))?;
+            let text = template::render(&nodes, data).map_err(|e| KainError::runtime(format!("template: {}", e)))?;
+            Ok(Value::String(text))
+        });
+
+        // template_check(source, fields) -> source, failing on unknown placeholders;
+        // `fields` is `fields_of(T)` or an array of names
+        self.define_native("template_check", |_env, args| {
+            let [Value::String(source), Value::Array(fields)] = args.as_slice() else {
+                return Err(KainError::runtime("template_check: expected (source, fields_of(T))"));
+            };
+            let names = fields
+                .read()
+                .unwrap()
+                .iter()
+                .map(|field| match field {
+                    Value::String(name) => Ok(name.clone()),
+                    Value::Struct(_, info) => match info.read().unwrap().get("name") {
+                        Some(Value::String(name)) => Ok(name.clone()),
+                        _ => Err(KainError::runtime("template_check: field info without a name")),
+                    },
+                    _ => Err(KainError::runtime("template_check: fields must be names or fields_of(T)")),
+                })
+                .collect::<KainResult<Vec<_>>>()?;
+            template::parse(source)
+                .and_then(|nodes| template::check(&nodes, &names))
+                .map_err(|e| KainError::runtime(format!("template: {}", e)))?;
+            Ok(Value::String(source.clone()))
+        });
+
+        self.define_native("html_escape", |_env, args| match args.as_slice() {
+            [Value::String(text)] => Ok(Value::String(template::escape_html(text))),
+            _ => Err(KainError::runtime("html_escape: expected a string")),
+        });
+    }
+
+    pub fn register_cli_stdlib(&mut self) {
+        // cli_spec("Args", name, about, fields_of(Args), [variants_of(Command)]) -> CliSpec
+        self.define_native("cli_spec", |_env, args| {
+            let (ty, name, about, fields, commands) = match args.as_slice() {
+                [Value::String(ty), Value::String(name), Value::String(about), fields, rest @ ..] if rest.len() <= 1 => {
+                    (ty, name, about, fields, rest.first())
+                }
+                _ => return Err(KainError::runtime(
+                    "cli_spec: expected (\"Type\", name, about, fields_of(Type), [variants_of(Command)])",
+                )),
+            };
+            argparse::spec(ty, name, about, fields, commands).map_err(|e| KainError::runtime(format!("cli_spec: {}", e)))
+        });
+
+        // cli_parse(spec, argv) -> Ok(struct) or Err(CliExit { message, code })
+        self.define_native("cli_parse", |_env, args| {
+            let [spec, Value::Array(argv)] = args.as_slice() else {
+                return Err(KainError::runtime("cli_parse: expected (spec, argv)"));
+            };
+            let argv: Vec<String> = argv.read().unwrap().iter().map(|a| a.to_string()).collect();
+            match argparse::parse(spec, &argv) {
+                Ok(parsed) => Ok(Value::Result(true, Box::new(parsed))),
+                Err((message, code)) => {
+                    let mut exit = HashMap::new();
+                    exit.insert("message".to_string(), Value::String(message));
+                    exit.insert("code".to_string(), Value::Int(code));
+                    let exit = Value::Struct("CliExit".to_string(), Arc::new(RwLock::new(exit)));
+                    Ok(Value::Result(false, Box::new(exit)))
+                }
+            }
+        });
+    }
+
+    pub fn register_log_stdlib(&mut self) {
+        fn record(env: &mut Env, level: log::Level, args: Vec<Value>) -> KainResult<Value> {
+            let (message, fields) = match args.as_slice() {
+                [message] => (message, None),
+                [message, fields] => (message, Some(fields)),
+                _ => return Err(KainError::runtime(format!("log_{}: expected (message, [fields])", level))),
+            };
+            let target = env.current_module.as_deref().unwrap_or("main");
+            if !log::enabled(target, level) {
+                return Ok(Value::Unit);
+            }
+            let fields = match fields {
+                None => Vec::new(),
+                Some(Value::Struct(_, fields)) => {
+                    let mut fields: Vec<_> = fields.read().unwrap().iter().map(|(k, v)| (k.clone(), value_to_json(v))).collect();
+                    fields.sort_by(|a, b| a.0.cmp(&b.0));
+                    fields
+                }
+                Some(_) => return Err(KainError::runtime(format!("log_{}: fields must be a struct", level))),
+            };
+            let message = match message {
+                Value::String(s) => s.clone(),
+                other => other.to_string(),
+            };
+            log::log(level, target, &message, &fields);
+            Ok(Value::Unit)
+        }
+
+        self.define_native("log_debug", |env, args| record(env, log::Level::Debug, args));
+        self.define_native("log_info", |env, args| record(env, log::Level::Info, args));
+        self.define_native("log_warn", |env, args| record(env, log::Level::Warn, args));
+        self.define_native("log_error", |env, args| record(env, log::Level::Error, args));
+
+        // log_set_filter("warn,http=debug"), the same syntax as KAIN_LOG
+        self.define_native("log_set_filter", |_env, args| match args.as_slice() {
+            [Value::String(spec)] => {
+                let filter = log::Filter::parse(spec).map_err(|e| KainError::runtime(format!("log_set_filter: {}", e)))?;
+                log::set_filter(filter);
+                Ok(Value::Unit)
+            }
+            _ => Err(KainError::runtime("log_set_filter: expected a filter string")),
+        });
+
+        self.define_native("log_set_format", |_env, args| match args.as_slice() {
+            [Value::String(format)] if format == "json" || format == "text" => {
+                log::set_json(format == "json");
+                Ok(Value::Unit)
+            }
+            _ => Err(KainError::runtime("log_set_format: expected \"text\" or \"json\"")),
+        });
+    }
+
+    /// RGBA images and Float framebuffers, see [`image`]
+    pub fn register_image_stdlib(&mut self) {
+        fn new_image(native: &str, name: &str, args: &[Value], blank: Value) -> KainResult<Value> {
+            let [Value::Int(width), Value::Int(height)] = args else {
+                return Err(KainError::runtime(format!("{}: expected (width, height)", native)));
+            };
+            if *width <= 0 || *height <= 0 || width.saturating_mul(*height) > u32::MAX as i64 / 4 {
+                return Err(KainError::runtime(format!("{}: cannot make a {}x{} image", native, width, height)));
+            }
+            let mut pixels = Vec::with_capacity((width * height * 4) as usize);
+            for _ in 0..width * height {
+                pixels.extend([blank.clone(), blank.clone(), blank.clone(), blank.clone()]);
+            }
+            let fields = HashMap::from([
+                ("width".to_string(), Value::Int(*width)),
+                ("height".to_string(), Value::Int(*height)),
+                ("pixels".to_string(), new_array(pixels)),
+            ]);
+            Ok(Value::Struct(name.to_string(), Arc::new(RwLock::new(fields))))
+        }
+
+        /// The size and pixels of an image argument, and whether it is a Float framebuffer
+        fn image_arg(native: &str, value: &Value) -> KainResult<(i64, i64, Arc<RwLock<Vec<Value>>>, bool)> {
+            let Value::Struct(name, fields) = value else {
+                return Err(KainError::runtime(format!("{}: expected an image from image_new or image_new_float", native)));
+            };
+            let fields = fields.read().unwrap();
+            match (fields.get("width"), fields.get("height"), fields.get("pixels")) {
+                (Some(Value::Int(w)), Some(Value::Int(h)), Some(Value::Array(pixels)))
+                    if name == image::IMAGE || name == image::FLOAT_IMAGE =>
+                {
+                    Ok((*w, *h, pixels.clone(), name == image::FLOAT_IMAGE))
+                }
+                _ => Err(KainError::runtime(format!("{}: expected an image from image_new or image_new_float", native))),
+            }
+        }
+
+        /// Index of the first channel of pixel `(x, y)` in `pixels`
+        fn pixel_index(native: &str, (width, height): (i64, i64), x: &Value, y: &Value) -> KainResult<usize> {
+            match (x, y) {
+                (Value::Int(x), Value::Int(y)) if (0..width).contains(x) && (0..height).contains(y) => {
+                    Ok(((y * width + x) * 4) as usize)
+                }
+                (Value::Int(x), Value::Int(y)) => Err(KainError::runtime(format!(
+                    "{}: ({}, {}) is outside the {}x{} image",
+                    native, x, y, width, height
                 ))),
+                _ => Err(KainError::runtime(format!("{}: x and y must be Ints", native))),
+            }
+        }
+
+        self.define_native("image_new", |_env, args| new_image("image_new", image::IMAGE, &args, Value::Int(0)));
+        self.define_native("image_new_float", |_env, args| {
+            new_image("image_new_float", image::FLOAT_IMAGE, &args, Value::Float(0.0))
+        });
+
+        // image_set_pixel(img, x, y, r, g, b, [a]); alpha defaults to opaque
+        self.define_native("image_set_pixel", |_env, args| {
+            let (img, x, y, channels) = match args.as_slice() {
+                [img, x, y, channels @ ..] if channels.len() == 3 || channels.len() == 4 => (img, x, y, channels),
+                _ => return Err(KainError::runtime("image_set_pixel: expected (image, x, y, r, g, b, [a])")),
+            };
+            let (width, height, pixels, float) = image_arg("image_set_pixel", img)?;
+            let at = pixel_index("image_set_pixel", (width, height), x, y)?;
+            let opaque = if float { Value::Float(1.0) } else { Value::Int(255) };
+            let mut pixels = pixels.write().unwrap();
+            for (i, channel) in channels.iter().chain(std::iter::once(&opaque)).take(4).enumerate() {
+                pixels[at + i] = match (channel, float) {
+                    (Value::Int(n), true) => Value::Float(*n as f64),
+                    (Value::Int(n), false) => Value::Int((*n).clamp(0, 255)),
+                    (Value::Float(f), true) => Value::Float(*f),
+                    (Value::Float(f), false) => Value::Int(f.round().clamp(0.0, 255.0) as i64),
+                    _ => return Err(KainError::runtime("image_set_pixel: channels must be numbers")),
+                };
+            }
+            Ok(Value::Unit)
+        });
+
+        // image_get_pixel(img, x, y) -> (r, g, b, a)
+        self.define_native("image_get_pixel", |_env, args| {
+            let [img, x, y] = args.as_slice() else {
+                return Err(KainError::runtime("image_get_pixel: expected (image, x, y)"));
+            };
+            let (width, height, pixels, _) = image_arg("image_get_pixel", img)?;
+            let at = pixel_index("image_get_pixel", (width, height), x, y)?;
+            let pixels = pixels.read().unwrap();
+            Ok(Value::Tuple(pixels[at..at + 4].to_vec()))
+        });
+
+        self.define_native("image_save_png", |_env, args| {
+            let [img, Value::String(path)] = args.as_slice() else {
+                return Err(KainError::runtime("image_save_png: expected (image, path)"));
+            };
+            let (width, height, pixels, _) = image_arg("image_save_png", img)?;
+            let rgba = pixels
+                .read()
+                .unwrap()
+                .iter()
+                .map(image::channel_to_u8)
+                .collect::<Result<Vec<u8>, String>>()
+                .map_err(|e| KainError::runtime(format!("image_save_png: {}", e)))?;
+            if rgba.len() != (width * height * 4) as usize {
+                return Err(KainError::runtime(format!(
+                    "image_save_png: a {}x{} image needs {} channels, found {}",
+                    width, height, width * height * 4, rgba.len()
+                )));
+            }
+            std::fs::write(path, image::encode_png(width as u32, height as u32, &rgba))
+                .map_err(|e| KainError::runtime(format!("image_save_png: {}: {}", path, e)))?;
+            Ok(Value::Unit)
+        });
+    }
+
+    /// Windows, input and audio, see [`crate::game`]
+    #[cfg(feature = "game")]
+    pub fn register_game_stdlib(&mut self) {
+        use crate::game;
+
+        fn game_err(e: String) -> KainError {
+            KainError::runtime(e)
+        }
+
+        fn pad_arg(native: &str, args: &[Value]) -> KainResult<(usize, String)> {
+            match args {
+                [Value::Int(pad), Value::String(name)] if *pad >= 0 => Ok((*pad as usize, name.clone())),
+                _ => Err(KainError::runtime(format!("{}: expected (pad, name) with pad counting from 0", native))),
+            }
+        }
+
+        self.define_native("game_open", |_env, args| match args.as_slice() {
+            [Value::String(title), Value::Int(w), Value::Int(h)] if *w > 0 && *h > 0 && *w <= u32::MAX as i64 && *h <= u32::MAX as i64 => {
+                game::open(title, *w as u32, *h as u32).map_err(game_err)?;
+                Ok(Value::Unit)
+            }
+            _ => Err(KainError::runtime("game_open: expected (title, width, height)")),
+        });
+        self.define_native("game_poll", |_env, _args| Ok(Value::Bool(game::poll().map_err(game_err)?)));
+        self.define_native("game_key_down", |_env, args| match args.as_slice() {
+            [Value::String(key)] => Ok(Value::Bool(game::key_down(key).map_err(game_err)?)),
+            _ => Err(KainError::runtime("game_key_down: expected a key name")),
+        });
+        self.define_native("game_mouse", |_env, _args| {
+            let (x, y) = game::mouse().map_err(game_err)?;
+            Ok(Value::Tuple(vec![Value::Float(x), Value::Float(y)]))
+        });
+        self.define_native("game_mouse_down", |_env, args| match args.as_slice() {
+            [Value::String(button)] => Ok(Value::Bool(game::mouse_down(button).map_err(game_err)?)),
+            _ => Err(KainError::runtime("game_mouse_down: expected \"left\", \"right\" or \"middle\"")),
+        });
+        self.define_native("game_present", |_env, args| {
+            let [Value::Struct(name, fields)] = args.as_slice() else {
+                return Err(KainError::runtime("game_present: expected an image"));
+            };
+            let fields = fields.read().unwrap();
+            let (Some(Value::Int(w)), Some(Value::Int(h)), Some(Value::Array(pixels))) =
+                (fields.get("width"), fields.get("height"), fields.get("pixels"))
+            else {
+                return Err(KainError::runtime(format!("game_present: expected an image, found {}", name)));
+            };
+            let rgba = pixels
+                .read()
+                .unwrap()
+                .iter()
+                .map(image::channel_to_u8)
+                .collect::<Result<Vec<u8>, String>>()
+                .map_err(|e| KainError::runtime(format!("game_present: {}", e)))?;
+            if *w <= 0 || *h <= 0 || rgba.len() != (w * h * 4) as usize {
+                return Err(KainError::runtime(format!("game_present: a {}x{} image needs {} channels", w, h, w * h * 4)));
+            }
+            game::present(*w as usize, *h as usize, &rgba).map_err(game_err)?;
+            Ok(Value::Unit)
+        });
+        self.define_native("game_gamepad_down", |_env, args| {
+            let (pad, button) = pad_arg("game_gamepad_down", &args)?;
+            Ok(Value::Bool(game::gamepad_down(pad, &button).map_err(game_err)?))
+        });
+        self.define_native("game_gamepad_axis", |_env, args| {
+            let (pad, axis) = pad_arg("game_gamepad_axis", &args)?;
+            Ok(Value::Float(game::gamepad_axis(pad, &axis).map_err(game_err)?))
+        });
+        // game_play_sound(path, [volume])
+        self.define_native("game_play_sound", |_env, args| {
+            let (path, volume) = match args.as_slice() {
+                [Value::String(path)] => (path, 1.0),
+                [Value::String(path), Value::Float(volume)] => (path, *volume),
+                [Value::String(path), Value::Int(volume)] => (path, *volume as f64),
+                _ => return Err(KainError::runtime("game_play_sound: expected (path, [volume])")),
+            };
+            game::play_sound(path, volume as f32).map_err(game_err)?;
+            Ok(Value::Unit)
+        });
+    }
+
+    /// Without the `game` feature, the `std/game` natives say how to get them
+    #[cfg(not(feature = "game"))]
+    pub fn register_game_stdlib(&mut self) {
+        for name in GAME_NATIVES {
+            self.define_native(name, |_env, _args| {
+                Err(KainError::runtime("std/game is not compiled in. Rebuild with --features game"))
+            });
+        }
+    }
+
+    /// Array editing, sorting and searching. Sorts are stable, and comparators
+    /// return an Int that is negative, zero or positive like `a - b`.
+    pub fn register_array_stdlib(&mut self) {
+        self.define_native("sort", |_env, args| {
+            let [items] = args.as_slice() else {
+                return Err(KainError::runtime("sort: expected 1 argument (array)"));
+            };
+            let items = array_arg("sort", items)?;
+            Ok(new_array(merge_sort_by(items, &mut compare_values)?))
+        });
+
+        self.define_native("sort_by", |env, args| {
+            let [items, cmp] = args.as_slice() else {
+                return Err(KainError::runtime("sort_by: expected 2 arguments (array, comparator)"));
+            };
+            let items = array_arg("sort_by", items)?;
+            Ok(new_array(merge_sort_by(items, &mut |a, b| call_comparator(env, cmp, a, b))?))
+        });
+
+        // Ok(index) of a match, or Err(index) where the value would be inserted
+        self.define_native("binary_search", |env, args| {
+            let (items, target, cmp) = match args.as_slice() {
+                [items, target] => (items, target, None),
+                [items, target, cmp] => (items, target, Some(cmp)),
+                _ => return Err(KainError::runtime("binary_search: expected (sorted array, value, [comparator])")),
+            };
+            let items = array_arg("binary_search", items)?;
+            let (mut lo, mut hi) = (0, items.len());
+            while lo < hi {
+                let mid = lo + (hi - lo) / 2;
+                let order = match cmp {
+                    Some(cmp) => call_comparator(env, cmp, &items[mid], target)?,
+                    None => compare_values(&items[mid], target)?,
+                };
+                match order {
+                    Ordering::Less => lo = mid + 1,
+                    Ordering::Greater => hi = mid,
+                    Ordering::Equal => return Ok(Value::Result(true, Box::new(Value::Int(mid as i64)))),
+                }
+            }
+            Ok(Value::Result(false, Box::new(Value::Int(lo as i64))))
+        });
+
+        // Ties go to the first minimum and the last maximum, as a stable sort would
+        self.define_native("min_by", |env, args| extreme_by(env, "min_by", args, Ordering::Less));
+        self.define_native("max_by", |env, args| extreme_by(env, "max_by", args, Ordering::Greater));
+
+        self.define_native("unique", |_env, args| {
+            let [items] = args.as_slice() else {
+                return Err(KainError::runtime("unique: expected 1 argument (array)"));
+            };
+            let mut seen: Vec<Value> = Vec::new();
+            for item in array_arg("unique", items)? {
+                if !contains_value(&seen, &item)? {
+                    seen.push(item);
+                }
+            }
+            Ok(new_array(seen))
+        });
+
+        // [(key, [items])] in the order each key first appears
+        self.define_native("group_by", |env, args| {
+            let [items, key_fn] = args.as_slice() else {
+                return Err(KainError::runtime("group_by: expected 2 arguments (array, key function)"));
+            };
+            let mut groups: Vec<(Value, Vec<Value>)> = Vec::new();
+            for item in array_arg("group_by", items)? {
+                let key = call_function(env, key_fn.clone(), vec![item.clone()])?;
+                let mut found = None;
+                for (i, (existing, _)) in groups.iter().enumerate() {
+                    if compare_values(existing, &key)? == Ordering::Equal {
+                        found = Some(i);
+                        break;
+                    }
+                }
+                match found {
+                    Some(i) => groups[i].1.push(item),
+                    None => groups.push((key, vec![item])),
+                }
+            }
+            Ok(new_array(groups.into_iter().map(|(key, items)| Value::Tuple(vec![key, new_array(items)])).collect()))
+        });
+
+        // insert, remove and pop change the array in place, like push
+        self.define_native("insert", |_env, args| {
+            let [Value::Array(items), index, value] = args.as_slice() else {
+                return Err(KainError::runtime("insert: expected (array, index, value)"));
+            };
+            let mut items = items.write().unwrap();
+            let index = index_arg("insert", index, items.len())?;
+            items.insert(index, value.clone());
+            Ok(Value::Unit)
+        });
+
+        self.define_native("remove", |_env, args| {
+            let [Value::Array(items), index] = args.as_slice() else {
+                return Err(KainError::runtime("remove: expected (array, index)"));
+            };
+            let mut items = items.write().unwrap();
+            match index_arg("remove", index, items.len())? {
+                index if index < items.len() => Ok(items.remove(index)),
+                index => Err(KainError::runtime(format!("remove: index {} out of bounds for length {}", index, items.len()))),
+            }
+        });
+
+        self.define_native("pop", |_env, args| {
+            let [Value::Array(items)] = args.as_slice() else {
+                return Err(KainError::runtime("pop: expected 1 argument (array)"));
+            };
+            Ok(items.write().unwrap().pop().unwrap_or(Value::None))
+        });
+
+        self.define_native("slice", |_env, args| {
+            let (items, start, end) = match args.as_slice() {
+                [items, start] => (items, start, None),
+                [items, start, end] => (items, start, Some(end)),
+                _ => return Err(KainError::runtime("slice: expected (array, start, [end])")),
+            };
+            let items = array_arg("slice", items)?;
+            let len = items.len();
+            let end = end.map_or(Ok(len), |end| index_arg("slice", end, len))?;
+            let start = index_arg("slice", start, end)?;
+            Ok(new_array(items[start..end].to_vec()))
+        });
+
+        self.define_native("concat", |_env, args| {
+            let [a, b] = args.as_slice() else {
+                return Err(KainError::runtime("concat: expected 2 arguments (array, array)"));
+            };
+            let mut items = array_arg("concat", a)?;
+            items.extend(array_arg("concat", b)?);
+            Ok(new_array(items))
+        });
+
+        // One level: [[1, 2], [3]] becomes [1, 2, 3]; other elements are kept
+        self.define_native("flatten", |_env, args| {
+            let [items] = args.as_slice() else {
+                return Err(KainError::runtime("flatten: expected 1 argument (array)"));
+            };
+            let mut flat = Vec::new();
+            for item in array_arg("flatten", items)? {
+                match item {
+                    Value::Array(inner) => flat.extend(inner.read().unwrap().iter().cloned()),
+                    other => flat.push(other),
+                }
             }
+            Ok(new_array(flat))
+        });
+
+        // Pairs up to the length of the shorter array
+        self.define_native("zip", |_env, args| {
+            let [a, b] = args.as_slice() else {
+                return Err(KainError::runtime("zip: expected 2 arguments (array, array)"));
+            };
+            let pairs = array_arg("zip", a)?.into_iter().zip(array_arg("zip", b)?).map(|(x, y)| Value::Tuple(vec![x, y]));
+            Ok(new_array(pairs.collect()))
+        });
+
+        self.define_native("index_of", |_env, args| {
+            let [items, value] = args.as_slice() else {
+                return Err(KainError::runtime("index_of: expected 2 arguments (array, value)"));
+            };
+            for (i, item) in array_arg("index_of", items)?.iter().enumerate() {
+                if compare_values(item, value).is_ok_and(|order| order == Ordering::Equal) {
+                    return Ok(Value::Int(i as i64));
+                }
+            }
+            Ok(Value::None)
         });
     }
 
@@ -416,36 +1713,8 @@ impl Env {
                 _ => return Err(KainError::runtime("json_parse: argument must be string")),
             };
 
-            fn from_json(v: &serde_json::Value) -> Value {
-                match v {
-                    serde_json::Value::Null => Value::None,
-                    serde_json::Value::Bool(b) => Value::Bool(*b),
-                    serde_json::Value::Number(n) => {
-                        if let Some(i) = n.as_i64() {
-                            Value::Int(i)
-                        } else if let Some(f) = n.as_f64() {
-                            Value::Float(f)
-                        } else {
-                            Value::Int(0) // Should match
-                        }
-                    }
-                    serde_json::Value::String(s) => Value::String(s.clone()),
-                    serde_json::Value::Array(arr) => {
-                        let k_arr = arr.iter().map(from_json).collect();
-                        Value::Array(Arc::new(RwLock::new(k_arr)))
-                    }
-                    serde_json::Value::Object(obj) => {
-                        let mut map = HashMap::new();
-                        for (k, v) in obj {
-                            map.insert(k.clone(), from_json(v));
-                        }
-                        Value::Struct("Json".to_string(), Arc::new(RwLock::new(map)))
-                    }
-                }
-            }
-
             match serde_json::from_str::<serde_json::Value>(s) {
-                Ok(v) => Ok(from_json(&v)),
+                Ok(v) => Ok(json_to_value(&v)),
                 Err(e) => Err(KainError::runtime(format!(
                     "json_parse: invalid json: {}",
                     e
@@ -458,34 +1727,61 @@ impl Env {
                 return Err(KainError::runtime("json_string: expected 1 argument"));
             }
 
-            fn to_json(v: &Value) -> serde_json::Value {
-                match v {
-                    Value::Unit => serde_json::Value::Null,
-                    Value::None => serde_json::Value::Null,
-                    Value::Bool(b) => serde_json::Value::Bool(*b),
-                    Value::Int(i) => serde_json::json!(i),
-                    Value::Float(f) => serde_json::json!(f),
-                    Value::String(s) => serde_json::Value::String(s.clone()),
-                    Value::Array(arr) => {
-                        let arr = arr.read().unwrap();
-                        serde_json::Value::Array(arr.iter().map(to_json).collect())
-                    }
-                    Value::Struct(_, fields) => {
-                        let fields = fields.read().unwrap();
-                        let mut map = serde_json::Map::new();
-                        for (k, v) in fields.iter() {
-                            map.insert(k.clone(), to_json(v));
-                        }
-                        serde_json::Value::Object(map)
-                    }
-                    Value::Tuple(items) => {
-                        serde_json::Value::Array(items.iter().map(to_json).collect())
-                    }
-                    _ => serde_json::Value::String(format!("{}", v)), // Fallback
-                }
+            Ok(Value::String(value_to_json(&args[0]).to_string()))
+        });
+    }
+
+    /// `serialize` and `deserialize`, in the [`wire`] format or its JSON form
+    pub fn register_serialize_stdlib(&mut self) {
+        self.define_native("serialize", |_env, args| match args.as_slice() {
+            [value] => {
+                let bytes = wire::encode(value).map_err(|e| KainError::runtime(format!("serialize: {}", e)))?;
+                Ok(Value::Array(Arc::new(RwLock::new(bytes.into_iter().map(|b| Value::Int(b as i64)).collect()))))
             }
+            _ => Err(KainError::runtime("serialize: expected 1 argument")),
+        });
+
+        // deserialize: The value in `bytes`, failing unless it is a `type` when one is given
+        self.define_native("deserialize", |_env, args| {
+            let (bytes, expected) = match args.as_slice() {
+                [Value::Array(bytes)] => (bytes, None),
+                [Value::Array(bytes), Value::String(ty)] => (bytes, Some(ty)),
+                _ => return Err(KainError::runtime("deserialize: expected (bytes, type?)")),
+            };
+            let bytes = bytes
+                .read()
+                .unwrap()
+                .iter()
+                .map(|b| match b {
+                    Value::Int(n @ 0..=255) => Ok(*n as u8),
+                    other => Err(KainError::runtime(format!("deserialize: {} is not a byte", other))),
+                })
+                .collect::<KainResult<Vec<u8>>>()?;
+            let value = wire::decode(&bytes).map_err(|e| KainError::runtime(format!("deserialize: {}", e)))?;
+            expect_type("deserialize", value, expected)
+        });
 
-            Ok(Value::String(to_json(&args[0]).to_string()))
+        self.define_native("serialize_json", |_env, args| match args.as_slice() {
+            [value] => wire::to_json(value)
+                .map(|json| Value::String(json.to_string()))
+                .map_err(|e| KainError::runtime(format!("serialize_json: {}", e))),
+            _ => Err(KainError::runtime("serialize_json: expected 1 argument")),
+        });
+
+        self.define_native("deserialize_json", |_env, args| {
+            let (text, expected) = match args.as_slice() {
+                [Value::String(text)] => (text, None),
+                [Value::String(text), Value::String(ty)] => (text, Some(ty)),
+                _ => return Err(KainError::runtime("deserialize_json: expected (text, type?)")),
+            };
+            let json: serde_json::Value = serde_json::from_str(text)
+                .map_err(|e| KainError::runtime(format!("deserialize_json: invalid json: {}", e)))?;
+            let value = match (wire::from_json(&json), expected) {
+                // Plain JSON objects take the expected type's name
+                (Value::Struct(name, fields), Some(ty)) if name == "Json" => Value::Struct(ty.clone(), fields),
+                (value, _) => value,
+            };
+            expect_type("deserialize_json", value, expected)
         });
     }
 
@@ -494,28 +1790,26 @@ impl Env {
         self.define("None".to_string(), Value::None);
         self.define("none".to_string(), Value::None); // Also lowercase for convenience
 
-        // Some is just an identity function - returns its argument
-        // This lets code use Some(value) pattern even though we don't have proper Option types
         self.define_native("Some", |_env, args| {
             if args.len() != 1 {
                 return Err(KainError::runtime("Some: expected 1 argument"));
             }
-            Ok(args[0].clone())
+            Ok(Value::Some(Box::new(args[0].clone())))
         });
 
         // Register built-in functions
-        self.define_native("print", |_env, args| {
+        self.define_native("print", |env, args| {
             for arg in args {
-                print!("{} ", arg);
+                env.write_output(&format!("{} ", pretty(&arg, &env.print)));
             }
             Ok(Value::Unit)
         });
 
-        self.define_native("println", |_env, args| {
+        self.define_native("println", |env, args| {
             for arg in args {
-                print!("{} ", arg);
+                env.write_output(&format!("{} ", pretty(&arg, &env.print)));
             }
-            println!("");
+            env.write_output("\n");
             Ok(Value::Unit)
         });
 
@@ -553,17 +1847,6 @@ impl Env {
             }
         });
 
-        self.define_native("sqrt", |_env, args| {
-            if args.len() != 1 {
-                return Err(KainError::runtime("sqrt: expected 1 argument"));
-            }
-            match &args[0] {
-                Value::Int(n) => Ok(Value::Float((*n as f64).sqrt())),
-                Value::Float(n) => Ok(Value::Float(n.sqrt())),
-                _ => Err(KainError::runtime("sqrt: argument must be number")),
-            }
-        });
-
         // Random
         self.define_native("random", |_env, _args| {
             // Simple LCG for deterministic behavior in prototype
@@ -577,28 +1860,16 @@ impl Env {
             Ok(Value::Float(x))
         });
 
-        self.define_native("sleep", |_env, args| {
-            if args.len() != 1 {
-                return Err(KainError::runtime("sleep: expected 1 argument (ms)"));
-            }
-            match args[0] {
-                Value::Int(ms) => {
-                    std::thread::sleep(std::time::Duration::from_millis(ms as u64));
-                    Ok(Value::Unit)
-                }
-                _ => Err(KainError::runtime("sleep: argument must be int")),
-            }
-        });
-
         // Collections
         self.define_native("len", |_env, args| {
             if args.len() != 1 {
                 return Err(KainError::runtime("len: expected 1 argument"));
             }
             match &args[0] {
-                Value::String(s) => Ok(Value::Int(s.len() as i64)),
+                Value::String(s) => Ok(Value::Int(s.chars().count() as i64)),
                 Value::Array(arr) => Ok(Value::Int(arr.read().unwrap().len() as i64)),
-                _ => Err(KainError::runtime("len: argument must be string or array")),
+                Value::Range(r) => Ok(Value::Int(r.len())),
+                _ => Err(KainError::runtime("len: argument must be string, array or range")),
             }
         });
 
@@ -608,6 +1879,7 @@ impl Env {
                 return Err(KainError::runtime("ord: expected 1 argument"));
             }
             match &args[0] {
+                Value::Char(c) => Ok(Value::Int(*c as i64)),
                 Value::String(s) => {
                     if let Some(c) = s.chars().next() {
                         Ok(Value::Int(c as i64))
@@ -615,7 +1887,7 @@ impl Env {
                         Err(KainError::runtime("ord: empty string"))
                     }
                 }
-                _ => Err(KainError::runtime("ord: argument must be string")),
+                _ => Err(KainError::runtime("ord: argument must be a char or string")),
             }
         });
 
@@ -628,43 +1900,30 @@ impl Env {
                 Value::Int(n) => {
                     if let Some(c) = char::from_u32(*n as u32) {
                         Ok(Value::String(c.to_string()))
-                    } else {
-                        Err(KainError::runtime("chr: invalid code point"))
-                    }
-                }
-                _ => Err(KainError::runtime("chr: argument must be int")),
-            }
-        });
-
-        self.define_native("first", |_env, args| {
-            if args.len() != 1 {
-                return Err(KainError::runtime("first: expected 1 argument"));
-            }
-            match &args[0] {
-                Value::Array(arr) => {
-                    let arr = arr.read().unwrap();
-                    if arr.is_empty() {
-                        return Err(KainError::runtime("first: empty array"));
+                    } else {
+                        Err(KainError::runtime("chr: invalid code point"))
                     }
-                    Ok(arr[0].clone())
                 }
-                _ => Err(KainError::runtime("first: argument must be array")),
+                _ => Err(KainError::runtime("chr: argument must be int")),
             }
         });
 
-        self.define_native("last", |_env, args| {
+        // char: a Char from a code point or a one-character string
+        self.define_native("char", |_env, args| {
             if args.len() != 1 {
-                return Err(KainError::runtime("last: expected 1 argument"));
+                return Err(KainError::runtime("char: expected 1 argument"));
             }
             match &args[0] {
-                Value::Array(arr) => {
-                    let arr = arr.read().unwrap();
-                    if arr.is_empty() {
-                        return Err(KainError::runtime("last: empty array"));
-                    }
-                    Ok(arr[arr.len() - 1].clone())
-                }
-                _ => Err(KainError::runtime("last: argument must be array")),
+                Value::Char(c) => Ok(Value::Char(*c)),
+                Value::Int(n) => u32::try_from(*n)
+                    .ok()
+                    .and_then(char::from_u32)
+                    .map(Value::Char)
+                    .ok_or_else(|| KainError::runtime(format!("char: {} is not a code point", n))),
+                Value::String(s) => single(s.chars())
+                    .map(Value::Char)
+                    .ok_or_else(|| KainError::runtime(format!("char: {:?} is not one character", s))),
+                _ => Err(KainError::runtime("char: argument must be an int or string")),
             }
         });
 
@@ -751,7 +2010,8 @@ impl Env {
                     Ok(Value::Array(Arc::new(RwLock::new(reversed))))
                 }
                 Value::String(s) => Ok(Value::String(s.chars().rev().collect())),
-                _ => Err(KainError::runtime("reverse: expected array or string")),
+                Value::Range(r) => Ok(Value::Range(r.rev())),
+                _ => Err(KainError::runtime("reverse: expected array, string or range")),
             }
         });
 
@@ -772,6 +2032,7 @@ impl Env {
                     }
                     Ok(Value::Int(total))
                 }
+                Value::Range(r) => Ok(Value::Int(r.iter().fold(0i64, i64::wrapping_add))),
                 _ => Err(KainError::runtime("sum: expected array")),
             }
         });
@@ -787,23 +2048,27 @@ impl Env {
                 Value::Int(_) => "int",
                 Value::Float(_) => "float",
                 Value::String(_) => "string",
+                Value::Char(_) => "char",
                 Value::Array(_) => "array",
                 Value::Tuple(_) => "tuple",
+                Value::Range(_) => "range",
                 Value::Struct(name, _) => name.as_str(),
                 Value::Function(_) => "function",
                 Value::NativeFn(_, _) => "native_function",
                 Value::ActorRef(_) => "actor",
-                Value::None => "none",
+                Value::None | Value::Some(_) => "option",
                 Value::Return(_) => "return_value",
-                Value::Closure(_, _, _) => "function",
+                Value::Closure(_, _, _) | Value::BoundMethod(_, _) => "function",
                 Value::Result(_, _) => "result",
                 Value::StructConstructor(_, _) => "struct_constructor",
                 Value::JSX(_) => "jsx",
                 Value::EnumVariant(enum_name, _, _) => return Ok(Value::String(enum_name.clone())),
                 Value::Poll(_, _) => "poll",
                 Value::Future(name, _) => return Ok(Value::String(format!("Future<{}>", name))),
-                Value::Break(_) => "break",
-                Value::Continue => "continue",
+                Value::Quote(_) => "quote",
+                Value::Module(_) => "module",
+                Value::Break(..) => "break",
+                Value::Continue(_) => "continue",
             };
             Ok(Value::String(type_name.to_string()))
         });
@@ -868,45 +2133,6 @@ impl Env {
             }
         });
 
-        self.define_native("str", |_env, args| {
-            if args.len() != 1 {
-                return Err(KainError::runtime("str: expected 1 argument"));
-            }
-            Ok(Value::String(format!("{}", args[0])))
-        });
-
-        self.define_native("int", |_env, args| {
-            if args.len() != 1 {
-                return Err(KainError::runtime("int: expected 1 argument"));
-            }
-            match &args[0] {
-                Value::Int(n) => Ok(Value::Int(*n)),
-                Value::Float(n) => Ok(Value::Int(*n as i64)),
-                Value::String(s) => s
-                    .parse::<i64>()
-                    .map(Value::Int)
-                    .map_err(|_| KainError::runtime("Invalid int string")),
-                _ => Err(KainError::runtime("int: argument must be number or string")),
-            }
-        });
-
-        self.define_native("float", |_env, args| {
-            if args.len() != 1 {
-                return Err(KainError::runtime("float: expected 1 argument"));
-            }
-            match &args[0] {
-                Value::Int(n) => Ok(Value::Float(*n as f64)),
-                Value::Float(n) => Ok(Value::Float(*n)),
-                Value::String(s) => s
-                    .parse::<f64>()
-                    .map(Value::Float)
-                    .map_err(|_| KainError::runtime("Invalid float string")),
-                _ => Err(KainError::runtime(
-                    "float: argument must be number or string",
-                )),
-            }
-        });
-
         // === Result / Error Handling ===
         self.define_native("ok", |_env, args| {
             if args.len() != 1 {
@@ -922,23 +2148,6 @@ impl Env {
             Ok(Value::Result(false, Box::new(args[0].clone())))
         });
 
-        self.define_native("sleep", |_env, args| {
-            if args.len() != 1 {
-                return Err(KainError::runtime("sleep: expected 1 argument"));
-            }
-            match &args[0] {
-                Value::Int(n) => {
-                    std::thread::sleep(std::time::Duration::from_secs(*n as u64));
-                    Ok(Value::Unit)
-                }
-                Value::Float(n) => {
-                    std::thread::sleep(std::time::Duration::from_secs_f64(*n));
-                    Ok(Value::Unit)
-                }
-                _ => Err(KainError::runtime("sleep: expected number")),
-            }
-        });
-
         self.define_native("now", |_env, _args| {
             let start = std::time::SystemTime::now();
             let since_the_epoch = start
@@ -958,6 +2167,7 @@ impl Env {
             }
             let arr = match &args[0] {
                 Value::Array(a) => a.read().unwrap().clone(),
+                Value::Range(r) => range_items("map", r)?,
                 _ => return Err(KainError::runtime("map: first argument must be an array")),
             };
             let func = args[1].clone();
@@ -977,6 +2187,7 @@ impl Env {
             }
             let arr = match &args[0] {
                 Value::Array(a) => a.read().unwrap().clone(),
+                Value::Range(r) => range_items("filter", r)?,
                 _ => {
                     return Err(KainError::runtime(
                         "filter: first argument must be an array",
@@ -1004,6 +2215,7 @@ impl Env {
             }
             let arr = match &args[0] {
                 Value::Array(a) => a.read().unwrap().clone(),
+                Value::Range(r) => range_items("reduce", r)?,
                 _ => {
                     return Err(KainError::runtime(
                         "reduce: first argument must be an array",
@@ -1026,6 +2238,7 @@ impl Env {
             }
             let arr = match &args[0] {
                 Value::Array(a) => a.read().unwrap().clone(),
+                Value::Range(r) => range_items("foreach", r)?,
                 _ => {
                     return Err(KainError::runtime(
                         "foreach: first argument must be an array",
@@ -1088,6 +2301,17 @@ impl Env {
             }
         });
 
+        self.define_native("copy_file", |_env, args| match args.as_slice() {
+            [Value::String(from), Value::String(to)] => match std::fs::copy(from, to) {
+                Ok(_) => Ok(Value::Unit),
+                Err(e) => Ok(Value::Result(
+                    false,
+                    Box::new(Value::String(format!("Failed to copy {} to {}: {}", from, to, e))),
+                )),
+            },
+            _ => Err(KainError::runtime("copy_file: expected (from, to) paths")),
+        });
+
         // === String Functions ===
         self.define_native("split", |_env, args| {
             if args.len() != 2 {
@@ -1186,9 +2410,10 @@ impl Env {
                         }
                     })));
                 }
+                Value::Range(r) => return Ok(Value::Bool(matches!(args[1], Value::Int(n) if r.contains(n)))),
                 _ => {
                     return Err(KainError::runtime(
-                        "contains: first argument must be a string or array",
+                        "contains: first argument must be a string, array or range",
                     ))
                 }
             };
@@ -1287,7 +2512,7 @@ impl Env {
                 }
             };
             let start = match &args[1] {
-                Value::Int(n) => *n as usize,
+                Value::Int(n) => (*n).max(0) as usize,
                 _ => {
                     return Err(KainError::runtime(
                         "substring: second argument must be an integer",
@@ -1296,7 +2521,7 @@ impl Env {
             };
             let end = if args.len() == 3 {
                 match &args[2] {
-                    Value::Int(n) => *n as usize,
+                    Value::Int(n) => (*n).max(0) as usize,
                     _ => {
                         return Err(KainError::runtime(
                             "substring: third argument must be an integer",
@@ -1304,15 +2529,41 @@ impl Env {
                     }
                 }
             } else {
-                s.len()
+                usize::MAX
             };
-            let chars: String = s.chars().skip(start).take(end - start).collect();
+            // Indices count chars and are clamped, so an out-of-range or
+            // reversed span gives a shorter (or empty) string
+            let chars: String = s.chars().skip(start).take(end.saturating_sub(start)).collect();
             Ok(Value::String(chars))
         });
 
+        self.define_native("byte_len", |_env, args| match args.first() {
+            Some(Value::String(s)) if args.len() == 1 => Ok(Value::Int(s.len() as i64)),
+            _ => Err(KainError::runtime("byte_len: expected 1 string argument")),
+        });
+
+        self.define_native("char_len", |_env, args| match args.first() {
+            Some(Value::String(s)) if args.len() == 1 => Ok(Value::Int(s.chars().count() as i64)),
+            _ => Err(KainError::runtime("char_len: expected 1 string argument")),
+        });
+
+        self.define_native("chars", |_env, args| match args.first() {
+            Some(Value::String(s)) if args.len() == 1 => {
+                Ok(new_array(s.chars().map(|c| Value::String(c.to_string())).collect()))
+            }
+            _ => Err(KainError::runtime("chars: expected 1 string argument")),
+        });
+
+        self.define_native("graphemes", |_env, args| match args.first() {
+            Some(Value::String(s)) if args.len() == 1 => {
+                Ok(new_array(s.graphemes(true).map(|g| Value::String(g.to_string())).collect()))
+            }
+            _ => Err(KainError::runtime("graphemes: expected 1 string argument")),
+        });
+
         // === Actor System ===
 
-        self.define_native("send", |_env, args| {
+        self.define_native("send", |env, args| {
             if args.len() < 2 {
                 return Err(KainError::runtime(
                     "send: expected at least 2 arguments (actor, msg_name)",
@@ -1333,15 +2584,91 @@ impl Env {
 
             let msg_args = args[2..].to_vec();
 
-            let _ = actor_ref.sender.send(Message {
-                name: msg_name,
-                args: msg_args,
-            });
+            env.post(actor_ref, msg_name, msg_args)?;
 
             Ok(Value::Unit)
         });
 
-        self.define_native("sleep", |_env, args| {
+        // snapshot: Save the actor's `persist state` in the wire format, to its
+        // `<Actor>.snapshot` or `path`, and return where it went
+        self.define_native("snapshot", |env, args| {
+            let path = optional_path("snapshot", &args)?;
+            let snapshot = env
+                .snapshot
+                .clone()
+                .ok_or_else(|| KainError::runtime("snapshot: only actors with `persist state` have snapshots"))?;
+            let path = path.unwrap_or(snapshot.path);
+            let mut state = HashMap::new();
+            for (field, _) in &snapshot.fields {
+                let value = env.lookup(field).cloned().unwrap_or(Value::None);
+                state.insert(field.clone(), value);
+            }
+            let bytes = wire::encode(&Value::Struct("Snapshot".to_string(), Arc::new(RwLock::new(state))))
+                .map_err(|e| KainError::runtime(format!("snapshot: {}", e)))?;
+            // Write beside the snapshot and rename, so a crash never leaves half of one
+            let mut partial = path.clone().into_os_string();
+            partial.push(".partial");
+            std::fs::write(&partial, bytes)
+                .and_then(|_| std::fs::rename(&partial, &path))
+                .map_err(|e| KainError::runtime(format!("snapshot: cannot write {}: {}", path.display(), e)))?;
+            Ok(Value::String(path.display().to_string()))
+        });
+
+        // restore: Load the actor's `persist state` from its snapshot or
+        // `path`; false when there is no snapshot yet
+        self.define_native("restore", |env, args| {
+            let path = optional_path("restore", &args)?;
+            restore_snapshot(env, path).map(Value::Bool)
+        });
+
+        // mailbox_len: Messages waiting for `actor`, or inside an actor for itself
+        self.define_native("mailbox_len", |env, args| {
+            let actor = match args.as_slice() {
+                [Value::ActorRef(actor)] => actor.clone(),
+                [] => match env.lookup("self") {
+                    Some(Value::ActorRef(actor)) if env.self_actor_id == Some(actor.id) => actor.clone(),
+                    _ => return Err(KainError::runtime("mailbox_len: outside an actor, pass the actor to measure")),
+                },
+                _ => return Err(KainError::runtime("mailbox_len: expected 0 or 1 arguments (actor?)")),
+            };
+            Ok(Value::Int(actor.sender.len() as i64))
+        });
+
+        // runtime_stats: Live values, actors with their mailbox depths, and
+        // allocations per function, to track down leaks in long-running programs
+        self.define_native("runtime_stats", |env, args| {
+            if !args.is_empty() {
+                return Err(KainError::runtime("runtime_stats: expected 0 arguments"));
+            }
+            let record = |name: &str, fields: Vec<(&str, Value)>| {
+                let fields = fields.into_iter().map(|(k, v)| (k.to_string(), v)).collect();
+                Value::Struct(name.to_string(), Arc::new(RwLock::new(fields)))
+            };
+            let stats = env.runtime_stats();
+            let actors = stats
+                .actors
+                .into_iter()
+                .map(|a| {
+                    record("ActorStats", vec![
+                        ("id", Value::Int(a.id as i64)),
+                        ("name", Value::String(a.name)),
+                        ("mailbox", Value::Int(a.mailbox as i64)),
+                    ])
+                })
+                .collect();
+            let allocations = stats
+                .allocations
+                .into_iter()
+                .map(|(scope, count)| Value::Tuple(vec![Value::String(scope), Value::Int(count as i64)]))
+                .collect();
+            Ok(record("RuntimeStats", vec![
+                ("live_values", Value::Int(stats.live_values as i64)),
+                ("actors", Value::Array(Arc::new(RwLock::new(actors)))),
+                ("allocations", Value::Array(Arc::new(RwLock::new(allocations)))),
+            ]))
+        });
+
+        self.define_native("sleep", |env, args| {
             if args.len() != 1 {
                 return Err(KainError::runtime("sleep: expected 1 argument (ms)"));
             }
@@ -1349,7 +2676,15 @@ impl Env {
                 Value::Int(i) => i as u64,
                 _ => return Err(KainError::runtime("sleep: expected int")),
             };
-            std::thread::sleep(std::time::Duration::from_millis(ms));
+            // Under a schedule, a test sleeps to let its actors run
+            if let (Some(scheduler), None) = (&env.scheduler, env.self_actor_id) {
+                scheduler.run_actors(schedule::SETTLE_TIMEOUT);
+                return Ok(Value::Unit);
+            }
+            let ms = Duration::from_millis(ms);
+            // Wake at the deadline at the latest and report the timeout
+            std::thread::sleep(env.time_left()?.map_or(ms, |left| ms.min(left)));
+            env.time_left()?;
             Ok(Value::Unit)
         });
 
@@ -1374,6 +2709,11 @@ impl Env {
             std::process::exit(code);
         });
 
+        self.define_native("args", |env, _args| {
+            let args = env.program_args.iter().cloned().map(Value::String).collect();
+            Ok(Value::Array(Arc::new(RwLock::new(args))))
+        });
+
         self.define_native("env", |_env, args| {
             if args.len() != 1 {
                 return Err(KainError::runtime("env: expected 1 argument"));
@@ -1414,9 +2754,9 @@ impl Env {
         });
 
         // Debug
-        self.define_native("dbg", |_env, args| {
+        self.define_native("dbg", |env, args| {
             for arg in args {
-                println!("[DEBUG] {:?}", arg);
+                env.write_output(&format!("[DEBUG] {}\n", pretty(&arg, &env.print)));
             }
             Ok(Value::Unit)
         });
@@ -1434,6 +2774,7 @@ impl Env {
                     .map(Value::Int)
                     .map_err(|_| KainError::runtime(format!("Cannot parse '{}' as int", s))),
                 Value::Bool(b) => Ok(Value::Int(if *b { 1 } else { 0 })),
+                Value::Char(c) => Ok(Value::Int(*c as i64)),
                 _ => Err(KainError::runtime("int: cannot convert this type")),
             }
         });
@@ -1445,10 +2786,9 @@ impl Env {
             match &args[0] {
                 Value::Int(n) => Ok(Value::Float(*n as f64)),
                 Value::Float(n) => Ok(Value::Float(*n)),
-                Value::String(s) => s
-                    .parse::<f64>()
+                Value::String(s) => crate::float::parse(s)
                     .map(Value::Float)
-                    .map_err(|_| KainError::runtime(format!("Cannot parse '{}' as float", s))),
+                    .ok_or_else(|| KainError::runtime(format!("Cannot parse '{}' as float", s))),
                 _ => Err(KainError::runtime("float: cannot convert this type")),
             }
         });
@@ -1497,6 +2837,7 @@ impl Env {
                     .map(Value::Int)
                     .map_err(|_| KainError::runtime(format!("Cannot parse '{}' as int", s))),
                 Value::Bool(b) => Ok(Value::Int(if *b { 1 } else { 0 })),
+                Value::Char(c) => Ok(Value::Int(*c as i64)),
                 _ => Err(KainError::runtime("to_int: cannot convert this type")),
             }
         });
@@ -1546,13 +2887,87 @@ impl Env {
             }
         });
 
+        self.define("PI".to_string(), Value::Float(std::f64::consts::PI));
+        self.define("E".to_string(), Value::Float(std::f64::consts::E));
+        self.define("TAU".to_string(), Value::Float(std::f64::consts::TAU));
+
+        self.define_native("asin", |_env, args| float_fn("asin", args, f64::asin));
+        self.define_native("acos", |_env, args| float_fn("acos", args, f64::acos));
+        self.define_native("atan", |_env, args| float_fn("atan", args, f64::atan));
+        self.define_native("sinh", |_env, args| float_fn("sinh", args, f64::sinh));
+        self.define_native("cosh", |_env, args| float_fn("cosh", args, f64::cosh));
+        self.define_native("tanh", |_env, args| float_fn("tanh", args, f64::tanh));
+        self.define_native("exp", |_env, args| float_fn("exp", args, f64::exp));
+        self.define_native("ln", |_env, args| float_fn("ln", args, f64::ln));
+        self.define_native("log2", |_env, args| float_fn("log2", args, f64::log2));
+        self.define_native("log10", |_env, args| float_fn("log10", args, f64::log10));
+
+        self.define_native("atan2", |_env, args| match args.as_slice() {
+            [y, x] => Ok(Value::Float(as_f64("atan2", y)?.atan2(as_f64("atan2", x)?))),
+            _ => Err(KainError::runtime("atan2: expected 2 arguments (y, x)")),
+        });
+
+        // Whole numbers are already rounded; floats stay floats
+        self.define_native("floor", |_env, args| round_fn("floor", args, f64::floor));
+        self.define_native("ceil", |_env, args| round_fn("ceil", args, f64::ceil));
+        self.define_native("round", |_env, args| round_fn("round", args, f64::round));
+        self.define_native("trunc", |_env, args| round_fn("trunc", args, f64::trunc));
+
+        self.define_native("pow", |_env, args| match args.as_slice() {
+            [Value::Int(base), Value::Int(exp)] if *exp >= 0 => u32::try_from(*exp)
+                .ok()
+                .and_then(|exp| base.checked_pow(exp))
+                .map(Value::Int)
+                .ok_or_else(|| KainError::runtime("pow: integer overflow")),
+            [base, exp] => Ok(Value::Float(as_f64("pow", base)?.powf(as_f64("pow", exp)?))),
+            _ => Err(KainError::runtime("pow: expected 2 arguments (base, exp)")),
+        });
+
+        self.define_native("clamp", |_env, args| match args.as_slice() {
+            [Value::Int(x), Value::Int(lo), Value::Int(hi)] if lo <= hi => Ok(Value::Int(*x.clamp(lo, hi))),
+            [x, lo, hi] => {
+                let (lo, hi) = (as_f64("clamp", lo)?, as_f64("clamp", hi)?);
+                if lo > hi {
+                    return Err(KainError::runtime("clamp: lower bound is above upper bound"));
+                }
+                Ok(Value::Float(as_f64("clamp", x)?.clamp(lo, hi)))
+            }
+            _ => Err(KainError::runtime("clamp: expected 3 arguments (x, lo, hi)")),
+        });
+
+        self.define_native("is_nan", |_env, args| match args.as_slice() {
+            [Value::Float(n)] => Ok(Value::Bool(n.is_nan())),
+            [Value::Int(_)] => Ok(Value::Bool(false)),
+            _ => Err(KainError::runtime("is_nan: expected a number")),
+        });
+
+        self.define_native("is_inf", |_env, args| match args.as_slice() {
+            [Value::Float(n)] => Ok(Value::Bool(n.is_infinite())),
+            [Value::Int(_)] => Ok(Value::Bool(false)),
+            _ => Err(KainError::runtime("is_inf: expected a number")),
+        });
+
         // === I/O ===
-        self.define_native("read_line", |_env, _args| {
-            use std::io::{self, BufRead};
-            let stdin = io::stdin();
-            let mut line = String::new();
-            stdin.lock().read_line(&mut line).ok();
-            Ok(Value::String(line.trim_end().to_string()))
+        self.define_native("read_line", |env, _args| {
+            fn read() -> String {
+                use std::io::BufRead;
+                let mut line = String::new();
+                std::io::stdin().lock().read_line(&mut line).ok();
+                line.trim_end().to_string()
+            }
+            let Some(left) = env.time_left()? else {
+                return Ok(Value::String(read()));
+            };
+            // A read can't be interrupted, so wait on it from a helper thread
+            let (tx, rx) = std::sync::mpsc::channel();
+            std::thread::spawn(move || tx.send(read()));
+            match rx.recv_timeout(left) {
+                Ok(line) => Ok(Value::String(line)),
+                Err(_) => {
+                    env.time_left()?;
+                    Ok(Value::String(String::new()))
+                }
+            }
         });
 
         // Python FFI
@@ -1639,26 +3054,69 @@ impl Env {
 
         // block_on: Run a future to completion, blocking the current thread
         self.define_native("block_on", |env, args| {
-            if args.len() != 1 {
-                return Err(KainError::runtime("block_on: expected 1 argument (future)"));
+            if args.is_empty() || args.len() > 2 {
+                return Err(KainError::runtime("block_on: expected 1 or 2 arguments (future, token?)"));
             }
-
-            let future_val = args[0].clone();
-            poll_future_to_completion(env, future_val)
+            let token = args.get(1).map(|t| cancel_token_arg("block_on", t)).transpose()?;
+            run_task(env, args[0].clone(), token, None)
         });
 
         // spawn_task: Spawn an async task (runs it immediately in this simple executor)
         self.define_native("spawn_task", |env, args| {
-            if args.len() != 1 {
+            if args.is_empty() || args.len() > 2 {
                 return Err(KainError::runtime(
-                    "spawn_task: expected 1 argument (future)",
+                    "spawn_task: expected 1 or 2 arguments (future, token?)",
                 ));
             }
 
             // For this simple executor, spawn is just block_on
             // A real executor would add to a task queue
-            let future_val = args[0].clone();
-            poll_future_to_completion(env, future_val)
+            let token = args.get(1).map(|t| cancel_token_arg("spawn_task", t)).transpose()?;
+            run_task(env, args[0].clone(), token, None)
+        });
+
+        // with_timeout: Run a future for at most `ms` milliseconds, Err once they pass
+        self.define_native("with_timeout", |env, args| {
+            let [future, Value::Int(ms)] = args.as_slice() else {
+                return Err(KainError::runtime("with_timeout: expected (future, ms: Int)"));
+            };
+            let ms = u64::try_from(*ms).map_err(|_| KainError::runtime("with_timeout: ms must not be negative"))?;
+            match run_task(env, future.clone(), None, Some(Duration::from_millis(ms))) {
+                Err(e) if e.to_string().contains(TIMED_OUT) && !env.task.timed_out() => {
+                    Ok(Value::Result(false, Box::new(Value::String(format!("timed out after {} ms", ms)))))
+                }
+                result => Ok(Value::Result(true, Box::new(result?))),
+            }
+        });
+
+        // cancel_token: A token that stops the tasks it is passed to once cancelled
+        self.define_native("cancel_token", |_env, args| {
+            if !args.is_empty() {
+                return Err(KainError::runtime("cancel_token: expected 0 arguments"));
+            }
+            let fields = HashMap::from([("cancelled".to_string(), Value::Bool(false))]);
+            Ok(Value::Struct("CancelToken".to_string(), Arc::new(RwLock::new(fields))))
+        });
+
+        self.define_native("cancel", |_env, args| {
+            let [token] = args.as_slice() else {
+                return Err(KainError::runtime("cancel: expected 1 argument (token)"));
+            };
+            if let Value::Struct(_, fields) = cancel_token_arg("cancel", token)? {
+                fields.write().unwrap_or_else(|e| e.into_inner()).insert("cancelled".to_string(), Value::Bool(true));
+            }
+            Ok(Value::Unit)
+        });
+
+        // is_cancelled: Whether `token` was cancelled, or without one whether
+        // the running task should stop, so long loops can give up early
+        self.define_native("is_cancelled", |env, args| match args.as_slice() {
+            [] => Ok(Value::Bool(env.task.cancelled() || env.task.timed_out())),
+            [token] => {
+                let token = cancel_token_arg("is_cancelled", token)?;
+                Ok(Value::Bool(TaskScope { tokens: vec![token], deadline: None }.cancelled()))
+            }
+            _ => Err(KainError::runtime("is_cancelled: expected 0 or 1 arguments (token?)")),
         });
 
         // poll_once: Poll a future once and return the Poll result
@@ -1725,35 +3183,515 @@ impl Env {
         });
     }
 
-    fn define_native(&mut self, name: &str, func: fn(&mut Env, Vec<Value>) -> KainResult<Value>) {
-        self.scopes[0].insert(name.to_string(), Value::NativeFn(name.to_string(), func));
+    /// Refuse calls to the given natives, reporting `reason` when one is attempted
+    pub fn deny_natives(&mut self, names: &[&str], reason: &str) {
+        for name in names {
+            self.denied_natives.insert(name.to_string(), reason.to_string());
+        }
+    }
+
+    /// Refuse calls to every native defined here but not in `names`, so natives
+    /// added later are refused until someone decides they are safe
+    pub fn allow_only_natives(&mut self, names: &[&str], reason: &str) {
+        let denied: Vec<String> =
+            self.native_names().into_iter().filter(|name| !names.contains(name)).map(str::to_string).collect();
+        for name in denied {
+            self.denied_natives.insert(name, reason.to_string());
+        }
+    }
+
+    /// Resources consumed so far by this environment
+    pub fn usage(&self) -> ResourceUsage {
+        self.usage
+    }
+
+    /// Live values, running actors and their mailboxes, and where the heap was allocated
+    pub fn runtime_stats(&self) -> RuntimeStats {
+        let mut seen = HashSet::new();
+        let statics = self.statics.read().unwrap_or_else(|e| e.into_inner());
+        let live_values = self
+            .scopes
+            .iter()
+            .flat_map(|scope| scope.values())
+            .chain(statics.values())
+            .map(|value| count_values(value, &mut seen))
+            .sum();
+        let actors = self
+            .actors
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .live()
+            .map(|(id, name, mailbox)| ActorStats { id, name: name.to_string(), mailbox: mailbox.len() })
+            .collect();
+        let mut allocations: Vec<(String, u64)> = self.allocations.iter().map(|(k, v)| (k.clone(), *v)).collect();
+        allocations.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        RuntimeStats { live_values, actors, allocations }
+    }
+
+    /// Queue `message` for `actor`, applying the policy of a bounded mailbox
+    /// when it is full, and warning once when an unbounded one passes [`MAILBOX_WARNING`]
+    fn post(&mut self, actor: &ActorRef, name: String, args: Vec<Value>) -> KainResult<()> {
+        let origin = (self.self_actor_id.unwrap_or(0), self.sent);
+        let message = Message { name, args, origin };
+        self.sent += 1;
+        let full = |env: &Env, why: &str| {
+            let registry = env.actors.lock().unwrap_or_else(|e| e.into_inner());
+            KainError::runtime(format!("mailbox of {}#{} is full{}", registry.name(actor.id), actor.id, why))
+        };
+        let delivered = match &actor.overflow {
+            None => actor.sender.send(message).is_ok(),
+            // An actor waiting on its own mailbox would never wake
+            Some(Overflow::Block) if self.self_actor_id == Some(actor.id) && actor.sender.is_full() => {
+                return Err(full(self, "; an actor cannot wait for room in its own mailbox"));
+            }
+            Some(Overflow::Block) => match self.time_left()? {
+                Some(left) => {
+                    // Wake at the deadline at the latest and report the timeout
+                    let delivered = actor.sender.send_timeout(message, left).is_ok();
+                    if !delivered && !actor.sender.is_disconnected() {
+                        self.time_left()?;
+                    }
+                    delivered
+                }
+                None => actor.sender.send(message).is_ok(),
+            },
+            Some(Overflow::Fail) => match actor.sender.try_send(message) {
+                Err(TrySendError::Full(_)) => return Err(full(self, "")),
+                result => result.is_ok(),
+            },
+            Some(Overflow::DropOldest(receiver)) => {
+                let mut message = message;
+                loop {
+                    match actor.sender.try_send(message) {
+                        Ok(()) => break true,
+                        Err(TrySendError::Full(back)) => {
+                            if let (Ok(oldest), Some(scheduler)) = (receiver.try_recv(), &self.scheduler) {
+                                scheduler.dropped(actor.id, oldest.origin);
+                            }
+                            message = back;
+                        }
+                        Err(TrySendError::Disconnected(_)) => break false,
+                    }
+                }
+            }
+        };
+        if let (true, Some(scheduler)) = (delivered, &self.scheduler) {
+            scheduler.posted(actor.id, origin);
+        }
+        let queued = actor.sender.len();
+        if actor.overflow.is_some() || queued < MAILBOX_WARNING {
+            return Ok(());
+        }
+        let mut registry = self.actors.lock().unwrap_or_else(|e| e.into_inner());
+        if !registry.warned.insert(actor.id) {
+            return Ok(());
+        }
+        let warning = format!(
+            "warning: {}#{} has {} unhandled messages; it receives them faster than it handles them\n",
+            registry.name(actor.id), actor.id, queued
+        );
+        drop(registry);
+        self.write_error(&warning);
+        Ok(())
+    }
+
+    /// Apply resource limits; a timeout starts counting now
+    pub fn set_limits(&mut self, limits: InterpretOptions) {
+        self.limits = limits;
+        self.deadline = limits.timeout.map(|t| Instant::now() + t);
+    }
+
+    /// How `print`, `println` and `dbg` show values
+    pub fn set_print_options(&mut self, options: PrettyOptions) {
+        self.print = options;
+    }
+
+    /// The file being run, for `dbg!` to locate and quote its arguments in
+    pub fn set_source(&mut self, file: Arc<vfs::SourceFile>) {
+        self.source = Some(file);
+    }
+
+    /// Arguments the program sees from `args()`
+    pub fn set_program_args(&mut self, args: Vec<String>) {
+        self.program_args = args;
+    }
+
+    /// The `language_version` the program and the modules it imports follow
+    pub fn set_edition(&mut self, edition: Edition) {
+        self.edition = edition;
+    }
+
+    /// Record the run to `tracer`
+    pub fn set_trace(&mut self, tracer: Tracer) {
+        self.trace = Some(tracer);
+    }
+
+    /// Record the run to `journal`, or replay it from there. A replay runs
+    /// with the arguments the recorded run was given.
+    pub fn set_journal(&mut self, journal: Arc<replay::Journal>) {
+        if let Some(args) = journal.replayed_args() {
+            self.program_args = args;
+        }
+        self.journal = Some(journal);
+    }
+
+    /// Record a call of `name` with `args`, indenting what it runs under it
+    fn trace_call(&mut self, name: &str, args: Vec<String>) {
+        if let Some(tracer) = &mut self.trace {
+            tracer.line(&format!("call {}({})", name, args.join(", ")));
+            tracer.depth += 1;
+        }
+    }
+
+    /// Close the call [`Env::trace_call`] opened, recording what it returned under `values`
+    fn trace_return(&mut self, name: &str, result: &KainResult<Value>) {
+        if let Some(tracer) = &mut self.trace {
+            tracer.depth = tracer.depth.saturating_sub(1);
+            if tracer.mode >= TraceMode::Values {
+                match result {
+                    Ok(value) => tracer.line(&format!("{} returned {}", name, trace_repr(value))),
+                    Err(e) => tracer.line(&format!("{} failed: {}", name, e)),
+                }
+            }
+        }
+    }
+
+    /// Record that `stmt` is about to run, under `stmts` and `values`
+    fn trace_stmt(&self, stmt: &Stmt) {
+        let Some(tracer) = self.trace.as_ref().filter(|t| t.mode >= TraceMode::Stmts) else { return };
+        match tracer.locate(stmt.span()) {
+            (location, Some(line)) => tracer.line(&format!("{}: {}", location, line)),
+            (location, None) => tracer.line(&format!("{}: {}", location, stmt.kind())),
+        }
+    }
+
+    /// Record what a statement evaluated to, under `values`
+    fn trace_value(&self, value: &Value) {
+        if let Some(tracer) = self.trace.as_ref().filter(|t| t.mode >= TraceMode::Values) {
+            tracer.line(&format!("= {}", trace_repr(value)));
+        }
+    }
+
+    /// Collect `print` and `println` output in a buffer instead of writing to stdout
+    pub fn capture_output(&mut self) -> Arc<Mutex<String>> {
+        let buffer = Arc::new(Mutex::new(String::new()));
+        self.output = Some(buffer.clone());
+        buffer
+    }
+
+    /// Collect what [`Env::write_error`] writes in a buffer instead of writing to stderr
+    pub fn capture_errors(&mut self) -> Arc<Mutex<String>> {
+        let buffer = Arc::new(Mutex::new(String::new()));
+        self.errors = Some(buffer.clone());
+        buffer
+    }
+
+    fn write_output(&mut self, text: &str) {
+        match &self.output {
+            Some(buffer) => buffer.lock().unwrap_or_else(|e| e.into_inner()).push_str(text),
+            None => print!("{}", text),
+        }
+    }
+
+    /// Count one evaluation step against `max_steps`, checking the deadline every so often
+    fn tick(&mut self) -> KainResult<()> {
+        self.usage.steps += 1;
+        if let Some(max) = self.limits.max_steps {
+            if self.usage.steps > max {
+                return Err(limit_exceeded(format!("more than {} steps", max)));
+            }
+        }
+        if self.usage.steps.is_multiple_of(1024) {
+            self.time_left()?;
+        }
+        Ok(())
+    }
+
+    /// Time left before the timeout, or `None` without one. Natives that block
+    /// call this themselves, since no steps are counted while they wait.
+    fn time_left(&self) -> KainResult<Option<Duration>> {
+        match (self.deadline, self.limits.timeout) {
+            (Some(deadline), Some(timeout)) => {
+                let left = deadline.saturating_duration_since(Instant::now());
+                if left.is_zero() {
+                    return Err(limit_exceeded(format!("ran longer than {:?}", timeout)));
+                }
+                Ok(Some(left))
+            }
+            _ => Ok(None),
+        }
+    }
+
+    /// Like [`Env::write_output`], for errors and warnings rather than program output
+    fn write_error(&mut self, text: &str) {
+        match &self.errors {
+            Some(buffer) => buffer.lock().unwrap_or_else(|e| e.into_inner()).push_str(text),
+            None => eprint!("{}", text),
+        }
+    }
+
+    /// Charge a freshly allocated value against `max_heap`
+    fn charge_heap(&mut self, value: &Value) -> KainResult<()> {
+        self.usage.heap = self.usage.heap.saturating_add(shallow_size(value));
+        let scope = self.function.as_deref().unwrap_or("<top level>");
+        match self.allocations.get_mut(scope) {
+            Some(count) => *count += 1,
+            None => {
+                self.allocations.insert(scope.to_string(), 1);
+            }
+        }
+        match self.limits.max_heap {
+            Some(max) if self.usage.heap > max => Err(limit_exceeded(format!("more than {} bytes allocated", max))),
+            _ => Ok(()),
+        }
+    }
+
+    fn enter_call(&mut self) -> KainResult<()> {
+        self.usage.call_depth += 1;
+        match self.limits.max_call_depth {
+            Some(max) if self.usage.call_depth > max => {
+                self.usage.call_depth -= 1;
+                Err(limit_exceeded(format!("call depth above {}", max)))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn exit_call(&mut self) {
+        self.usage.call_depth -= 1;
+    }
+
+    /// Run as the build script of `context`, giving it the `build_*` natives.
+    /// Called again to move on to the next hook.
+    pub fn set_build_context(&mut self, context: BuildContext) {
+        fn context<'a>(env: &'a Env, native: &str) -> KainResult<&'a BuildContext> {
+            env.build
+                .as_ref()
+                .ok_or_else(|| KainError::runtime(format!("{}: only available in build scripts", native)))
+        }
+
+        self.build = Some(context);
+        self.define_native("build_profile", |env, _args| Ok(Value::String(context(env, "build_profile")?.profile.clone())));
+        self.define_native("build_out_dir", |env, _args| Ok(Value::String(context(env, "build_out_dir")?.out_dir.clone())));
+        self.define_native("build_targets", |env, _args| {
+            let targets = context(env, "build_targets")?.targets.iter().cloned().map(Value::String).collect();
+            Ok(new_array(targets))
+        });
+        self.define_native("build_target", |env, _args| {
+            Ok(context(env, "build_target")?.target.clone().map_or(Value::None, Value::String))
+        });
+        self.define_native("build_output", |env, _args| {
+            Ok(context(env, "build_output")?.output.clone().map_or(Value::None, Value::String))
+        });
+    }
+
+    /// Let `emit_item(quote: ...)` add items to the program being compiled
+    pub fn enable_item_emission(&mut self) {
+        self.define_native("emit_item", |env, args| {
+            let [Value::Quote(code)] = args.as_slice() else {
+                return Err(KainError::runtime("emit_item: expected the value of a quote: block"));
+            };
+            for stmt in &code.stmts {
+                let Stmt::Item(item) = stmt else {
+                    return Err(KainError::runtime("emit_item: quoted code may only contain items"));
+                };
+                env.emitted_items.push((**item).clone());
+            }
+            Ok(Value::Unit)
+        });
+    }
+
+    /// Make `function` callable by name, as one of the program's own functions
+    pub fn define_function(&mut self, function: &Function) {
+        self.functions.insert(function.name.clone(), function.clone());
+        self.define(function.name.clone(), Value::Function(function.name.clone()));
+    }
+
+    /// Bind the value of one of the program's consts
+    pub fn define_const(&mut self, name: &str, value: Value) {
+        self.define(name.to_string(), value);
+    }
+
+    /// Items emitted since the last call, in emission order
+    pub fn take_emitted_items(&mut self) -> Vec<Item> {
+        std::mem::take(&mut self.emitted_items)
+    }
+
+    /// Names of the natives defined in this environment, each of which must be
+    /// declared in the StdLib registry
+    pub fn native_names(&self) -> Vec<&str> {
+        self.scopes[0]
+            .values()
+            .filter_map(|value| match value {
+                Value::NativeFn(name, _) => Some(name.as_str()),
+                _ => None,
+            })
+            .collect()
+    }
+
+    fn define_native(&mut self, name: &str, func: fn(&mut Env, Vec<Value>) -> KainResult<Value>) {
+        self.scopes[0].insert(name.to_string(), Value::NativeFn(name.to_string(), func));
+    }
+
+    fn define(&mut self, name: String, value: Value) {
+        self.scopes.last_mut().unwrap().insert(name, value);
+    }
+
+    fn assign(&mut self, name: &str, value: Value) -> KainResult<()> {
+        for scope in self.scopes.iter_mut().rev() {
+            if scope.contains_key(name) {
+                scope.insert(name.to_string(), value);
+                return Ok(());
+            }
+        }
+        let key = self.static_key(name);
+        let mut statics = self.statics.write().unwrap_or_else(|e| e.into_inner());
+        if let Some(slot) = statics.get_mut(&key) {
+            *slot = value;
+            return Ok(());
+        }
+        Err(KainError::runtime(format!("Undefined variable '{}'", name)))
+    }
+
+    /// Statics of the module being run are its own; the program's are unqualified
+    fn static_key(&self, name: &str) -> String {
+        match &self.current_module {
+            Some(module) => format!("{}::{}", module, name),
+            None => name.to_string(),
+        }
+    }
+
+    fn static_value(&self, name: &str) -> Option<Value> {
+        let statics = self.statics.read().unwrap_or_else(|e| e.into_inner());
+        statics.get(&self.static_key(name)).cloned()
+    }
+
+    fn define_static(&mut self, name: &str, value: Value) {
+        let key = self.static_key(name);
+        self.statics.write().unwrap_or_else(|e| e.into_inner()).insert(key, value);
     }
 
-    fn define(&mut self, name: String, value: Value) {
-        self.scopes.last_mut().unwrap().insert(name, value);
+    /// The local `depth` scopes out from the innermost, in `slot`; `None` when
+    /// the scopes did not turn out as [`crate::resolve`] expected, such as a
+    /// `let` an `if` skipped, and the name has to be looked up instead
+    fn local(&self, depth: usize, slot: usize, name: &str) -> Option<&Value> {
+        match self.scopes.len().checked_sub(depth + 1)? {
+            0 => None,
+            index => self.scopes[index].slot(slot, name),
+        }
     }
 
-    fn assign(&mut self, name: &str, value: Value) -> KainResult<()> {
-        for scope in self.scopes.iter_mut().rev() {
-            if scope.contains_key(name) {
-                scope.insert(name.to_string(), value);
-                return Ok(());
+    /// Assign to a local where [`Env::local`] would read it, or else by name
+    fn assign_local(&mut self, depth: usize, slot: usize, name: &str, value: Value) -> KainResult<()> {
+        let target = match self.scopes.len().checked_sub(depth + 1) {
+            Some(0) | None => None,
+            Some(index) => self.scopes[index].slot_mut(slot, name),
+        };
+        match target {
+            Some(target) => {
+                *target = value;
+                Ok(())
             }
+            None => self.assign(name, value),
         }
-        Err(KainError::runtime(format!("Undefined variable '{}'", name)))
     }
 
+    /// Locals first, then the names of the module being run, then globals and natives
     fn lookup(&self, name: &str) -> Option<&Value> {
-        for scope in self.scopes.iter().rev() {
+        let (globals, locals) = self.scopes.split_first()?;
+        for scope in locals.iter().rev() {
             if let Some(v) = scope.get(name) {
                 return Some(v);
             }
         }
-        None
+        if let Some(module) = self.current_module.as_ref().and_then(|m| self.modules.get(m)) {
+            if let Some(v) = module.names.get(name) {
+                return Some(v);
+            }
+        }
+        globals.get(name)
+    }
+
+    /// Why `name` doesn't resolve: an ambiguous import, an imported module's
+    /// private item, or no such name at all
+    fn undefined(&self, name: &str) -> KainError {
+        let key = (self.current_module.clone(), name.to_string());
+        if let Some(Import { from, ambiguous_with: Some(other), .. }) = self.imports.get(&key) {
+            return KainError::runtime(format!(
+                "`{}` is ambiguous: both {} and {} export it; pick one with `use {}: {{{}}}` or import a module with `as`",
+                name, from, other, from, name
+            ));
+        }
+        if self.edition.enforce_visibility() {
+            let mut private = self.modules.iter().filter(|(_, scope)| scope.items.contains_key(name));
+            if let Some((module, scope)) = private.next() {
+                return private_item(name, &scope.items[name].0, module);
+            }
+        }
+        KainError::runtime(format!("Undefined: {}", name))
+    }
+
+    /// An item `module` lets other modules use
+    fn module_item(&self, module: &Arc<str>, name: &str) -> KainResult<Value> {
+        let item = self.modules.get(module).and_then(|scope| scope.items.get(name));
+        match item {
+            Some((value, Visibility::Private)) if self.edition.enforce_visibility() => Err(private_item(name, value, module)),
+            Some((value, _)) => Ok(value.clone()),
+            None => Err(KainError::runtime(format!("Module {} has no item `{}`", module, name))),
+        }
+    }
+
+    /// Bind `name` in the namespace of `importer` (the program's when `None`).
+    /// Names from two plain imports become ambiguous; an explicit import
+    /// replaces a plain one, and two explicit imports of one name are an error.
+    fn bind_import(&mut self, importer: &Option<Arc<str>>, name: &str, value: Value, from: &Arc<str>, explicit: bool) -> KainResult<()> {
+        let key = (importer.clone(), name.to_string());
+        let own_item = self.namespace(importer).get(name).is_some_and(|v| !matches!(v, Value::NativeFn(..)));
+        if own_item && !self.imports.contains_key(&key) {
+            return Ok(());
+        }
+        if let Some(previous) = self.imports.get(&key).cloned() {
+            if previous.from == *from {
+                return Ok(());
+            }
+            if previous.explicit && explicit {
+                return Err(KainError::runtime(format!(
+                    "`{}` is imported from both {} and {}; import one of them with `as`",
+                    name, previous.from, from
+                )));
+            }
+            if previous.explicit {
+                return Ok(());
+            }
+            if !explicit {
+                let ambiguous = Import { ambiguous_with: Some(from.clone()), ..previous };
+                self.imports.insert(key, ambiguous);
+                self.namespace(importer).remove(name);
+                return Ok(());
+            }
+        }
+        self.imports.insert(key, Import { from: from.clone(), explicit, ambiguous_with: None });
+        self.namespace(importer).insert(name.to_string(), value);
+        Ok(())
+    }
+
+    /// Define one of a module's own items, replacing any import of the same name
+    fn define_module_item(&mut self, module: &Arc<str>, name: &str, value: Value, visibility: Visibility) {
+        self.imports.remove(&(Some(module.clone()), name.to_string()));
+        let scope = self.modules.entry(module.clone()).or_default();
+        scope.names.insert(name.to_string(), value.clone());
+        scope.items.insert(name.to_string(), (value, visibility));
+    }
+
+    fn namespace(&mut self, module: &Option<Arc<str>>) -> &mut Scope {
+        match module {
+            Some(module) => &mut self.modules.entry(module.clone()).or_default().names,
+            None => &mut self.scopes[0],
+        }
     }
 
     fn push_scope(&mut self) {
-        self.scopes.push(HashMap::new());
+        self.scopes.push(Scope::default());
     }
 
     fn pop_scope(&mut self) {
@@ -1765,18 +3703,50 @@ impl Env {
 
 /// Interpret the program
 pub fn interpret(program: &TypedProgram) -> KainResult<Value> {
+    interpret_with_options(program, InterpretOptions::default())
+}
+
+/// Run `main` under the given resource limits
+pub fn interpret_with_options(program: &TypedProgram, options: InterpretOptions) -> KainResult<Value> {
     let mut env = Env::new();
+    env.set_limits(options);
+    interpret_in(&mut env, program)
+}
+
+/// Register the program's items in `env` and run `main`
+pub fn interpret_in(env: &mut Env, program: &TypedProgram) -> KainResult<Value> {
+    load_program(env, program)?;
+
+    // Find and run main
+    if let Some(main_fn) = env.functions.get("main").cloned() {
+        eval_block(env, &main_fn.body)
+    } else {
+        Ok(Value::Unit)
+    }
+}
+
+/// Call the program's function `name` without arguments; `None` when it has none by that name
+pub fn call_if_defined(env: &mut Env, name: &str) -> KainResult<Option<Value>> {
+    if !env.functions.contains_key(name) {
+        return Ok(None);
+    }
+    call_function(env, Value::Function(name.to_string()), Vec::new()).map(Some)
+}
+
+/// Register the program's items in `env`, without running anything but its globals
+pub fn load_program(env: &mut Env, program: &TypedProgram) -> KainResult<()> {
 
     // Register functions
     for item in &program.items {
         match item {
             crate::types::TypedItem::Use(u) => {
                 // Handle imports first
-                load_module(&mut env, &u.ast)?;
+                load_module(env, &u.ast, &None)?;
             }
             crate::types::TypedItem::Function(f) => {
-                env.functions.insert(f.ast.name.clone(), f.ast.clone());
-                env.define(f.ast.name.clone(), Value::Function(f.ast.name.clone()));
+                // The program's own function replaces an imported one of the same name
+                env.imports.remove(&(None, f.ast.name.clone()));
+                env.define_function(&f.ast);
             }
             crate::types::TypedItem::Actor(a) => {
                 env.actor_defs.insert(a.ast.name.clone(), a.ast.clone());
@@ -1784,10 +3754,6 @@ pub fn interpret(program: &TypedProgram) -> KainResult<Value> {
             crate::types::TypedItem::Component(c) => {
                 env.components.insert(c.ast.name.clone(), c.ast.clone());
             }
-            crate::types::TypedItem::Const(c) => {
-                let val = eval_expr(&mut env, &c.ast.value)?;
-                env.define(c.ast.name.clone(), val);
-            }
             crate::types::TypedItem::Impl(i) => {
                 // Get the type name
                 let type_name = match &i.ast.target_type {
@@ -1805,20 +3771,95 @@ pub fn interpret(program: &TypedProgram) -> KainResult<Value> {
         }
     }
 
-    // Find and run main
-    if let Some(main_fn) = env.functions.get("main").cloned() {
-        eval_block(&mut env, &main_fn.body)
-    } else {
-        Ok(Value::Unit)
+    define_globals(env, program)
+}
+
+/// Evaluate the consts comptime left as written, each after the consts it
+/// uses, once everything they may call is registered; then the statics
+fn define_globals(env: &mut Env, program: &TypedProgram) -> KainResult<()> {
+    let consts: Vec<&Const> = program
+        .items
+        .iter()
+        .filter_map(|item| match item {
+            crate::types::TypedItem::Const(c) => Some(&c.ast),
+            _ => None,
+        })
+        .collect();
+    let functions: Vec<&Function> = program
+        .items
+        .iter()
+        .filter_map(|item| match item {
+            crate::types::TypedItem::Function(f) => Some(&f.ast),
+            _ => None,
+        })
+        .collect();
+    for i in crate::consts::order(&consts, &functions)? {
+        let val = eval_expr(env, &consts[i].value)?;
+        env.define_const(&consts[i].name, val);
     }
+    // Statics start from their initial value, which may use the consts
+    for item in &program.items {
+        if let crate::types::TypedItem::Static(s) = item {
+            let val = eval_expr(env, &s.ast.value)?;
+            env.define_static(&s.ast.name, val);
+        }
+    }
+    Ok(())
 }
 
-fn load_module(env: &mut Env, u: &Use) -> KainResult<()> {
+/// Load the module `u` names, unless it already is, and bind what it imports
+/// in the namespace of `importer` (the program's when `None`)
+fn load_module(env: &mut Env, u: &Use, importer: &Option<Arc<str>>) -> KainResult<()> {
     let path = u.path.join("/");
 
+    // Next to the module importing it, or the program's own file
+    let importing = env.loading.last().map(|(file, _)| file.clone()).or_else(|| env.source.as_ref().map(|source| source.path.clone()));
     // Check if it's core stdlib (already loaded)
-    if path == "stdlib" {
+    let Some(file_path) = resolve_import(Path::new(""), importing.as_deref(), &path)? else {
         return Ok(());
+    };
+
+    if let Some(start) = env.loading.iter().position(|(p, _)| *p == file_path) {
+        let chain: Vec<&str> = env.loading[start..]
+            .iter()
+            .map(|(_, name)| name.as_str())
+            .chain(std::iter::once(path.as_str()))
+            .collect();
+        return Err(KainError::runtime(format!("Import cycle: {}", chain.join(" -> "))));
+    }
+    // A module imported again, directly or through another module, is only bound again
+    let module = match env.loaded_modules.get(&file_path) {
+        Some(module) => module.clone(),
+        None => {
+            let module: Arc<str> = Arc::from(path.as_str());
+            env.loaded_modules.insert(file_path.clone(), module.clone());
+            let program = parse_module(&file_path, &path, env.edition)?;
+            env.loading.push((file_path, path.clone()));
+            let result = register_module(env, &module, &program);
+            env.loading.pop();
+            result?;
+            module
+        }
+    };
+    import_module(env, u, &module, importer)
+}
+
+/// The file `use path` names in the file `importer`: `path` relative to the
+/// directory `importer` is in first, then wherever [`resolve_module_in`] looks from `root`
+pub(crate) fn resolve_import(root: &Path, importer: Option<&Path>, path: &str) -> KainResult<Option<PathBuf>> {
+    if let Some(dir) = importer.and_then(Path::parent) {
+        if let Ok(Some(file)) = resolve_module_in(dir, path) {
+            return Ok(Some(file));
+        }
+    }
+    resolve_module_in(root, path)
+}
+
+/// Find the file for module `path` (`a/b` for `use a::b`) from `root`,
+/// canonicalized; `None` for the core stdlib, which is always loaded
+pub(crate) fn resolve_module_in(root: &Path, path: &str) -> KainResult<Option<PathBuf>> {
+    if path == "stdlib" {
+        return Ok(None);
     }
 
     // Check for stdlib submodules: std/option, std/hashmap, std/result
@@ -1837,8 +3878,8 @@ fn load_module(env: &mut Env, u: &Use) -> KainResult<()> {
 
         possible_paths
             .into_iter()
-            .map(|p| std::path::PathBuf::from(p))
-            .find(|p| p.exists())
+            .map(|p| root.join(p))
+            .find(|p| vfs::exists(p))
             .ok_or_else(|| {
                 KainError::runtime(format!("Stdlib module not found: {}", module_name))
             })?
@@ -1848,15 +3889,15 @@ fn load_module(env: &mut Env, u: &Use) -> KainResult<()> {
 
         // Try various locations in order
         let possible_paths = [
-            base_path.with_extension("kn"), // ./compiler/lexer.kn
-            std::path::PathBuf::from(format!("src/{}.kn", path)), // src/compiler/lexer.kn
-            std::path::PathBuf::from(format!("{}.kn", path)), // compiler/lexer.kn
-            base_path.with_extension("god"), // legacy .god extension
+            root.join(base_path.with_extension("kn")), // ./compiler/lexer.kn
+            root.join(format!("src/{}.kn", path)), // src/compiler/lexer.kn
+            root.join(format!("{}.kn", path)), // compiler/lexer.kn
+            root.join(base_path.with_extension("god")), // legacy .god extension
         ];
 
         possible_paths
             .iter()
-            .find(|p| p.exists())
+            .find(|p| vfs::exists(p))
             .cloned()
             .ok_or_else(|| {
                 KainError::runtime(format!(
@@ -1869,48 +3910,128 @@ fn load_module(env: &mut Env, u: &Use) -> KainResult<()> {
                 ))
             })?
     };
+    Ok(Some(file_path.canonicalize().unwrap_or(file_path)))
+}
+
+/// Bind what `u` imports from `module` in the namespace of `importer`
+fn import_module(env: &mut Env, u: &Use, module: &Arc<str>, importer: &Option<Arc<str>>) -> KainResult<()> {
+    if let Some(alias) = &u.alias {
+        return env.bind_import(importer, alias, Value::Module(module.clone()), module, true);
+    }
+    if let Some(names) = &u.items {
+        for name in names {
+            let value = env.module_item(module, name)?;
+            env.bind_import(importer, name, value, module, true)?;
+        }
+        return Ok(());
+    }
+    let hide_private = env.edition.enforce_visibility();
+    let exported: Vec<(String, Value)> = env
+        .modules
+        .get(module)
+        .map(|scope| {
+            scope
+                .items
+                .iter()
+                .filter(|(_, (_, visibility))| !hide_private || *visibility != Visibility::Private)
+                .map(|(name, (value, _))| (name.clone(), value.clone()))
+                .collect()
+        })
+        .unwrap_or_default();
+    for (name, value) in exported {
+        env.bind_import(importer, &name, value, module, false)?;
+    }
+    Ok(())
+}
+
+fn private_item(name: &str, value: &Value, module: &str) -> KainError {
+    let kind = match value {
+        Value::Function(_) => "function ",
+        Value::StructConstructor(..) => "struct ",
+        _ => "",
+    };
+    KainError::runtime(format!("{}`{}` is private to module {}, consider making it pub", kind, name, module))
+}
+
+/// Parsed modules by canonical path and edition
+static MODULE_CACHE: Lazy<Mutex<HashMap<(PathBuf, Edition), CachedModule>>> = Lazy::new(Default::default);
+
+struct CachedModule {
+    source: Arc<vfs::SourceFile>,
+    program: Arc<Program>,
+}
 
-    let source = std::fs::read_to_string(&file_path)
+/// Parse a module file, reusing the last parse while its text is unchanged
+pub(crate) fn parse_module(file_path: &Path, path: &str, edition: Edition) -> KainResult<Arc<Program>> {
+    let source = vfs::read(file_path)
         .map_err(|e| KainError::runtime(format!("Failed to read module {}: {}", path, e)))?;
+    let key = (file_path.to_path_buf(), edition);
+    let cache = || MODULE_CACHE.lock().unwrap_or_else(|e| e.into_inner());
+    if let Some(cached) = cache().get(&key) {
+        // The source map hands out the same file for as long as the text is the same
+        if Arc::ptr_eq(&cached.source, &source) {
+            return Ok(cached.program.clone());
+        }
+    }
 
-    let lexer = Lexer::new(&source);
+    let lexer = Lexer::with_edition(&source.text, edition).in_file(source.id);
     let tokens = lexer.tokenize()?;
     let mut parser = Parser::new(&tokens);
-    let program = parser.parse()?;
+    let mut program = parser.parse()?;
+    crate::types::lower_associated_consts(&mut program.items);
+    crate::types::lower_module_type_aliases(&mut program.items);
+    let program = Arc::new(program);
+    cache().insert(key, CachedModule { source, program: program.clone() });
+    Ok(program)
+}
 
+/// Register a module's items in its own namespace; its code runs with that
+/// namespace in scope, so constants can refer to the module's functions
+fn register_module(env: &mut Env, module: &Arc<str>, program: &Program) -> KainResult<()> {
+    env.modules.entry(module.clone()).or_default();
+    let importer = std::mem::replace(&mut env.current_module, Some(module.clone()));
+    let result = register_module_items(env, module, program);
+    env.current_module = importer;
+    result
+}
+
+fn register_module_items(env: &mut Env, module: &Arc<str>, program: &Program) -> KainResult<()> {
     // Register items
-    for item in program.items {
+    for item in &program.items {
         match item {
             Item::Function(f) => {
-                env.functions.insert(f.name.clone(), f.clone());
-                env.define(f.name.clone(), Value::Function(f.name.clone()));
+                // Qualified, so functions of the same name in two modules both exist
+                let qualified = format!("{}::{}", module, f.name);
+                env.function_modules.insert(qualified.clone(), module.clone());
+                env.functions.insert(qualified.clone(), f.clone());
+                env.define_module_item(module, &f.name, Value::Function(qualified), f.visibility);
             }
             Item::Component(c) => {
-                env.components.insert(c.name.clone(), c);
+                env.components.insert(c.name.clone(), c.clone());
             }
             Item::Struct(s) => {
                 let field_names = s.fields.iter().map(|f| f.name.clone()).collect();
-                env.define(
-                    s.name.clone(),
+                env.define_module_item(
+                    module,
+                    &s.name,
                     Value::StructConstructor(s.name.clone(), field_names),
+                    s.visibility,
                 );
             }
             Item::Enum(e) => {
                 // Register enum variants as constructors
                 for variant in &e.variants {
                     let variant_name = format!("{}::{}", e.name, variant.name);
-                    env.define(
-                        variant_name,
-                        Value::Function(format!("{}::{}", e.name, variant.name)),
+                    env.define_module_item(
+                        module,
+                        &variant_name,
+                        Value::Function(variant_name.clone()),
+                        e.visibility,
                     );
                 }
             }
             Item::Actor(a) => {
-                env.actor_defs.insert(a.name.clone(), a);
-            }
-            Item::Const(c) => {
-                let val = eval_expr(env, &c.value)?;
-                env.define(c.name.clone(), val);
+                env.actor_defs.insert(a.name.clone(), a.clone());
             }
             Item::Impl(i) => {
                 if let Type::Named { name, .. } = &i.target_type {
@@ -1923,6 +4044,7 @@ fn load_module(env: &mut Env, u: &Use) -> KainResult<()> {
 
                     // Register lowered functions
                     for (lowered_name, method) in lowered_fns {
+                        env.function_modules.insert(lowered_name.clone(), module.clone());
                         env.functions.insert(lowered_name.clone(), method);
                         env.define(lowered_name.clone(), Value::Function(lowered_name));
                     }
@@ -1935,21 +4057,98 @@ fn load_module(env: &mut Env, u: &Use) -> KainResult<()> {
                 }
             }
             Item::Use(u) => {
-                load_module(env, &u)?;
+                load_module(env, u, &Some(module.clone()))?;
             }
             _ => {}
         }
     }
 
+    // Consts and statics last, each const after the consts it uses
+    let consts: Vec<&Const> = program
+        .items
+        .iter()
+        .filter_map(|item| match item {
+            Item::Const(c) => Some(c),
+            _ => None,
+        })
+        .collect();
+    let functions: Vec<&Function> = program
+        .items
+        .iter()
+        .filter_map(|item| match item {
+            Item::Function(f) => Some(f),
+            _ => None,
+        })
+        .collect();
+    for i in crate::consts::order(&consts, &functions)? {
+        let val = eval_expr(env, &consts[i].value)?;
+        env.define_module_item(module, &consts[i].name, val, consts[i].visibility);
+    }
+    for item in &program.items {
+        if let Item::Static(s) = item {
+            let val = eval_expr(env, &s.value)?;
+            env.define_static(&s.name, val);
+        }
+    }
+
     Ok(())
 }
 
 pub fn eval_block(env: &mut Env, block: &Block) -> KainResult<Value> {
+    crate::stack::grow(|| eval_block_inner(env, block))
+}
+
+/// An HTTP client that gives up when the interpreter's timeout runs out
+fn http_client(env: &Env) -> KainResult<reqwest::blocking::Client> {
+    let mut builder = reqwest::blocking::Client::builder();
+    if let Some(left) = env.time_left()? {
+        builder = builder.timeout(left);
+    }
+    builder.build().map_err(|e| KainError::runtime(format!("http: {}", e)))
+}
+
+fn limit_exceeded(what: String) -> KainError {
+    KainError::runtime(format!("Resource limit exceeded: {}", what))
+}
+
+/// `value` and the values it holds, each shared array or struct counted once
+fn count_values(value: &Value, seen: &mut HashSet<usize>) -> usize {
+    let nested = match value {
+        Value::Array(items) if seen.insert(Arc::as_ptr(items) as usize) => {
+            items.read().unwrap_or_else(|e| e.into_inner()).iter().map(|v| count_values(v, seen)).sum()
+        }
+        Value::Struct(_, fields) | Value::Future(_, fields) if seen.insert(Arc::as_ptr(fields) as usize) => {
+            fields.read().unwrap_or_else(|e| e.into_inner()).values().map(|v| count_values(v, seen)).sum()
+        }
+        Value::Tuple(items) | Value::EnumVariant(_, _, items) => items.iter().map(|v| count_values(v, seen)).sum(),
+        Value::Result(_, inner) | Value::Some(inner) | Value::Poll(_, Some(inner)) | Value::BoundMethod(inner, _) => {
+            count_values(inner, seen)
+        }
+        _ => 0,
+    };
+    1 + nested
+}
+
+/// Approximate bytes owned directly by a value (nested values are charged when created)
+fn shallow_size(value: &Value) -> usize {
+    let slot = std::mem::size_of::<Value>();
+    match value {
+        Value::String(s) => s.len(),
+        Value::Array(arr) => arr.read().map(|a| a.len() * slot).unwrap_or(0),
+        Value::Tuple(items) | Value::EnumVariant(_, _, items) => items.len() * slot,
+        Value::Struct(_, fields) | Value::Future(_, fields) => {
+            fields.read().map(|f| f.keys().map(|k| k.len() + slot).sum()).unwrap_or(0)
+        }
+        _ => 0,
+    }
+}
+
+fn eval_block_inner(env: &mut Env, block: &Block) -> KainResult<Value> {
     for stmt in &block.stmts {
         let result = eval_stmt(env, stmt)?;
         // Propagate control flow up
         match &result {
-            Value::Return(_) | Value::Break(_) | Value::Continue => return Ok(result),
+            Value::Return(_) | Value::Break(..) | Value::Continue(_) => return Ok(result),
             _ => {}
         }
     }
@@ -1957,13 +4156,17 @@ pub fn eval_block(env: &mut Env, block: &Block) -> KainResult<Value> {
 }
 
 fn eval_stmt(env: &mut Env, stmt: &Stmt) -> KainResult<Value> {
+    env.tick()?;
+    crate::crash::at(stmt.span());
+    env.trace_stmt(stmt);
     match stmt {
         Stmt::Expr(expr) => {
             let val = eval_expr(env, expr)?;
             // Propagate control flow
             match &val {
-                Value::Return(_) | Value::Break(_) | Value::Continue => return Ok(val),
-                _ => {}
+                Value::Return(_) | Value::Break(..) | Value::Continue(_) => return Ok(val),
+                Value::Unit => {}
+                _ => env.trace_value(&val),
             }
             Ok(Value::Unit)
         }
@@ -1973,14 +4176,13 @@ fn eval_stmt(env: &mut Env, stmt: &Stmt) -> KainResult<Value> {
             } else {
                 Value::None
             };
-            if let Value::Return(_) = val {
+            // A `guard let` else branch can leave the loop or function
+            if let Value::Return(_) | Value::Break(..) | Value::Continue(_) = val {
                 return Ok(val);
             }
 
-            // Simple binding
-            if let Pattern::Binding { name, .. } = pattern {
-                env.define(name.clone(), val);
-            }
+            env.trace_value(&val);
+            bind_irrefutable(env, pattern, &val)?;
             Ok(Value::Unit)
         }
         Stmt::Return(expr, _) => {
@@ -1995,9 +4197,11 @@ fn eval_stmt(env: &mut Env, stmt: &Stmt) -> KainResult<Value> {
             Ok(Value::Return(Box::new(val)))
         }
         Stmt::For {
+            label,
             binding,
             iter,
             body,
+            else_branch,
             ..
         } => {
             let iter_val = eval_expr(env, iter)?;
@@ -2005,20 +4209,39 @@ fn eval_stmt(env: &mut Env, stmt: &Stmt) -> KainResult<Value> {
                 return Ok(iter_val);
             }
 
+            let mut broke = false;
             if let Value::Array(arr) = iter_val {
                 let arr = arr.read().unwrap().clone();
                 for val in arr.iter() {
                     env.push_scope();
-                    if let Pattern::Binding { name, .. } = binding {
-                        env.define(name.clone(), val.clone());
+                    bind_irrefutable(env, binding, val)?;
+                    let res = eval_block(env, body)?;
+                    env.pop_scope();
+
+                    match res {
+                        Value::Break(ref target, _) if targets(target, label) => {
+                            broke = true;
+                            break;
+                        }
+                        Value::Continue(ref target) if targets(target, label) => continue,
+                        Value::Return(_) | Value::Break(..) | Value::Continue(_) => return Ok(res),
+                        _ => {}
                     }
+                }
+            } else if let Value::Range(range) = iter_val {
+                for n in range.iter() {
+                    env.push_scope();
+                    bind_irrefutable(env, binding, &Value::Int(n))?;
                     let res = eval_block(env, body)?;
                     env.pop_scope();
 
                     match res {
-                        Value::Return(_) => return Ok(res),
-                        Value::Break(_) => break,
-                        Value::Continue => continue,
+                        Value::Break(ref target, _) if targets(target, label) => {
+                            broke = true;
+                            break;
+                        }
+                        Value::Continue(ref target) if targets(target, label) => continue,
+                        Value::Return(_) | Value::Break(..) | Value::Continue(_) => return Ok(res),
                         _ => {}
                     }
                 }
@@ -2032,18 +4255,22 @@ fn eval_stmt(env: &mut Env, stmt: &Stmt) -> KainResult<Value> {
                     env.pop_scope();
 
                     match res {
-                        Value::Return(_) => return Ok(res),
-                        Value::Break(_) => break,
-                        Value::Continue => continue,
+                        Value::Break(ref target, _) if targets(target, label) => {
+                            broke = true;
+                            break;
+                        }
+                        Value::Continue(ref target) if targets(target, label) => continue,
+                        Value::Return(_) | Value::Break(..) | Value::Continue(_) => return Ok(res),
                         _ => {}
                     }
                 }
             }
-            Ok(Value::Unit)
+            eval_loop_else(env, else_branch, broke)
         }
         Stmt::While {
-            condition, body, ..
+            label, condition, body, else_branch, ..
         } => {
+            let mut broke = false;
             loop {
                 let cond = eval_expr(env, condition)?;
                 if let Value::Return(_) = cond {
@@ -2055,34 +4282,46 @@ fn eval_stmt(env: &mut Env, stmt: &Stmt) -> KainResult<Value> {
 
                 let res = eval_block(env, body)?;
                 match res {
-                    Value::Return(_) => return Ok(res),
-                    Value::Break(_) => break,
-                    Value::Continue => continue,
+                    Value::Break(ref target, _) if targets(target, label) => {
+                        broke = true;
+                        break;
+                    }
+                    Value::Continue(ref target) if targets(target, label) => continue,
+                    Value::Return(_) | Value::Break(..) | Value::Continue(_) => return Ok(res),
                     _ => {}
                 }
             }
-            Ok(Value::Unit)
+            eval_loop_else(env, else_branch, broke)
         }
-        Stmt::Loop { body, .. } => loop {
+        Stmt::Loop { label, body, .. } => loop {
             let res = eval_block(env, body)?;
             match res {
-                Value::Return(_) => return Ok(res),
-                Value::Break(val) => {
+                Value::Break(target, val) if targets(&target, label) => {
                     return Ok(val.map(|v| *v).unwrap_or(Value::Unit));
                 }
-                Value::Continue => continue,
+                Value::Continue(ref target) if targets(target, label) => continue,
+                Value::Return(_) | Value::Break(..) | Value::Continue(_) => return Ok(res),
                 _ => {}
             }
         },
-        Stmt::Break(expr, _) => {
+        Stmt::Break(expr, label, _) => {
             let val = if let Some(e) = expr {
                 Some(Box::new(eval_expr(env, e)?))
             } else {
                 None
             };
-            Ok(Value::Break(val))
+            Ok(Value::Break(label.clone(), val))
+        }
+        Stmt::Continue(label, _) => Ok(Value::Continue(label.clone())),
+        Stmt::Region { body, .. } => {
+            // What the body allocated goes with its scope, so it stops counting against `max_heap`
+            let mark = env.usage.heap;
+            env.push_scope();
+            let res = eval_block(env, body);
+            env.pop_scope();
+            env.usage.heap = env.usage.heap.min(mark);
+            res
         }
-        Stmt::Continue(_) => Ok(Value::Continue),
         _ => Ok(Value::Unit),
     }
 }
@@ -2090,6 +4329,7 @@ fn eval_stmt(env: &mut Env, stmt: &Stmt) -> KainResult<Value> {
 fn eval_assignment(env: &mut Env, target: &Expr, value: Value) -> KainResult<()> {
     match target {
         Expr::Ident(name, _) => env.assign(name, value),
+        Expr::Local { name, depth, slot, .. } => env.assign_local(*depth, *slot, name, value),
         Expr::Field { object, field, .. } => {
             let obj_val = eval_expr(env, object)?;
             if let Value::Struct(_, fields) = obj_val {
@@ -2133,7 +4373,59 @@ fn eval_assignment(env: &mut Env, target: &Expr, value: Value) -> KainResult<()>
     }
 }
 
+/// A variable, item, native or static, found by its name
+fn lookup_name(env: &Env, name: &str) -> KainResult<Value> {
+    match env.lookup(name) {
+        Some(value) => Ok(value.clone()),
+        None => env.static_value(name).ok_or_else(|| env.undefined(name)),
+    }
+}
+
+/// `dbg!(a, b)`: print `file:line: a = <value>` for each argument and give the
+/// value back, or a tuple of them for several, as Rust's `dbg!` does. Without
+/// the source, lines are located by byte offset and expressions shown as `<expr>`.
+fn eval_dbg(env: &mut Env, args: &[Expr], span: Span) -> KainResult<Value> {
+    let main = env.source.clone();
+    let location = |span: Span| match source_file(span, main.as_ref()) {
+        Some(file) => (format!("{}:{}", file.path.display(), file.line_col(span.start).0), Some(file)),
+        None => (format!("@{}", span.start), None),
+    };
+    if args.is_empty() {
+        let (at, _) = location(span);
+        env.write_output(&format!("{}\n", at));
+        return Ok(Value::Unit);
+    }
+    let mut values = Vec::with_capacity(args.len());
+    for arg in args {
+        let (at, file) = location(arg.span());
+        let text = file.as_ref().and_then(|f| f.text.get(arg.span().start..arg.span().end)).map_or("<expr>", str::trim);
+        let value = eval_expr(env, arg)?;
+        if let Value::Return(_) = value {
+            return Ok(value);
+        }
+        env.write_output(&format!("{}: {} = {}\n", at, text, pretty(&value, &env.print)));
+        values.push(value);
+    }
+    Ok(match values.len() {
+        1 => values.remove(0),
+        _ => Value::Tuple(values),
+    })
+}
+
 pub fn eval_expr(env: &mut Env, expr: &Expr) -> KainResult<Value> {
+    env.tick()?;
+    let value = crate::stack::grow(|| eval_expr_inner(env, expr))?;
+    // Only expressions that build a new value are charged
+    if matches!(
+        expr,
+        Expr::FString(..) | Expr::Binary { .. } | Expr::Array(..) | Expr::Tuple(..) | Expr::Struct { .. } | Expr::EnumVariant { .. }
+    ) {
+        env.charge_heap(&value)?;
+    }
+    Ok(value)
+}
+
+fn eval_expr_inner(env: &mut Env, expr: &Expr) -> KainResult<Value> {
     match expr {
         Expr::MethodCall {
             receiver,
@@ -2148,13 +4440,25 @@ pub fn eval_expr(env: &mut Env, expr: &Expr) -> KainResult<Value> {
             }
 
             // Evaluate arguments
-            let mut arg_vals = Vec::new();
-            for arg in args {
-                let v = eval_expr(env, &arg.value)?;
-                if let Value::Return(_) = v {
-                    return Ok(v);
+            let mut arg_vals = match eval_args(env, args)? {
+                ControlFlow::Continue(values) => values,
+                ControlFlow::Break(ret) => return Ok(ret),
+            };
+
+            // `m.item(args)` on a module imported with `use path as m`
+            if let Value::Module(module) = &obj_val {
+                let func = env.module_item(module, method)?;
+                return call_function(env, func, arg_vals);
+            }
+
+            // Extension methods from `impl Array<T>:` / `impl String:` blocks,
+            // lowered like struct methods to Type_method(obj, args)
+            if let Some(type_name) = builtin_type_name(&obj_val) {
+                let func_name = format!("{}_{}", type_name, method);
+                if env.functions.contains_key(&func_name) {
+                    arg_vals.insert(0, obj_val);
+                    return call_function(env, Value::Function(func_name), arg_vals);
                 }
-                arg_vals.push(v);
             }
 
             match obj_val {
@@ -2168,29 +4472,21 @@ pub fn eval_expr(env: &mut Env, expr: &Expr) -> KainResult<Value> {
                         env.define("self".to_string(), obj_val);
 
                         // Bind other params
-                        let param_iter = if func
-                            .params
-                            .first()
-                            .map(|p| p.name == "self")
-                            .unwrap_or(false)
-                        {
-                            func.params.iter().skip(1)
-                        } else {
-                            func.params.iter().skip(0)
-                        };
+                        let params = without_self(&func.params);
+                        let arg_vals = gather_rest(params, arg_vals);
 
-                        if param_iter.len() != arg_vals.len() {
+                        if params.len() != arg_vals.len() {
                             return Err(KainError::runtime(format!(
                                 "Method {} arg mismatch",
                                 func_name
                             )));
                         }
 
-                        for (param, arg) in param_iter.zip(arg_vals.into_iter()) {
+                        for (param, arg) in params.iter().zip(arg_vals.into_iter()) {
                             env.define(param.name.clone(), arg);
                         }
 
-                        let result = eval_block(env, &func.body)?;
+                        let result = eval_body(env, &func_name, &func)?;
                         env.pop_scope();
 
                         match result {
@@ -2234,9 +4530,61 @@ pub fn eval_expr(env: &mut Env, expr: &Expr) -> KainResult<Value> {
                     }
                 }
 
+                Value::Range(range) => match (method.as_str(), arg_vals.as_slice()) {
+                    ("step", [Value::Int(n)]) => range
+                        .step_by(*n)
+                        .map(Value::Range)
+                        .ok_or_else(|| KainError::runtime(format!("step must be positive, found {}", n))),
+                    ("rev", []) => Ok(Value::Range(range.rev())),
+                    ("contains", [Value::Int(n)]) => Ok(Value::Bool(range.contains(*n))),
+                    ("contains", [_]) => Ok(Value::Bool(false)),
+                    ("len", []) => Ok(Value::Int(range.len())),
+                    ("to_array", []) => Ok(new_array(range_items("to_array", &range)?)),
+                    ("step" | "rev" | "contains" | "len" | "to_array", _) => {
+                        Err(KainError::runtime(format!("Invalid arguments to Range.{}", method)))
+                    }
+                    _ => Err(KainError::runtime(format!("Method {} not found on Range", method))),
+                },
+
+                Value::Char(c) => match (method.as_str(), arg_vals.as_slice()) {
+                    ("is_digit", []) => Ok(Value::Bool(c.is_ascii_digit())),
+                    ("is_alpha", []) => Ok(Value::Bool(c.is_alphabetic())),
+                    ("is_alphanumeric", []) => Ok(Value::Bool(c.is_alphanumeric())),
+                    ("is_whitespace", []) => Ok(Value::Bool(c.is_whitespace())),
+                    ("is_upper", []) => Ok(Value::Bool(c.is_uppercase())),
+                    ("is_lower", []) => Ok(Value::Bool(c.is_lowercase())),
+                    // Only when the case maps to one char, so `'ß'.to_upper()` stays `'ß'`
+                    ("to_upper", []) => Ok(Value::Char(single(c.to_uppercase()).unwrap_or(c))),
+                    ("to_lower", []) => Ok(Value::Char(single(c.to_lowercase()).unwrap_or(c))),
+                    ("to_int", []) => Ok(Value::Int(c as i64)),
+                    ("to_string", []) => Ok(Value::String(c.to_string())),
+                    _ => Err(KainError::runtime(format!("Method {} not found on Char", method))),
+                },
+
+                Value::Some(_) | Value::None => {
+                    let inner = match obj_val {
+                        Value::Some(inner) => Some(*inner),
+                        _ => None,
+                    };
+                    match (method.as_str(), inner, arg_vals.as_slice()) {
+                        ("is_some", inner, []) => Ok(Value::Bool(inner.is_some())),
+                        ("is_none", inner, []) => Ok(Value::Bool(inner.is_none())),
+                        ("unwrap", Some(v), []) | ("expect", Some(v), [_]) | ("unwrap_or", Some(v), [_]) => Ok(v),
+                        ("unwrap", None, []) => Err(KainError::runtime("called unwrap on None")),
+                        ("expect", None, [message]) => Err(KainError::runtime(format!("{}", message))),
+                        ("unwrap_or", None, [default]) => Ok(default.clone()),
+                        ("map", Some(v), [f]) => Ok(Value::Some(Box::new(call_function(env, f.clone(), vec![v])?))),
+                        ("map", None, [_]) => Ok(Value::None),
+                        ("is_some" | "is_none" | "unwrap" | "expect" | "unwrap_or" | "map", _, _) => {
+                            Err(KainError::runtime(format!("Invalid arguments to Option.{}", method)))
+                        }
+                        _ => Err(KainError::runtime(format!("Method {} not found on Option", method))),
+                    }
+                }
+
                 _ => Err(KainError::runtime(format!(
-                    "Method calls not supported on this type: {:?}",
-                    obj_val
+                    "Method calls not supported on this type: {}",
+                    pretty(&obj_val, &PrettyOptions::BRIEF)
                 ))),
             }
         }
@@ -2255,21 +4603,17 @@ pub fn eval_expr(env: &mut Env, expr: &Expr) -> KainResult<Value> {
 
                     if let Some(method) = method {
                         // Evaluate arguments
-                        let mut arg_vals = Vec::new();
-                        for arg in args {
-                            let v = eval_expr(env, &arg.value)?;
-                            if let Value::Return(_) = v {
-                                return Ok(v);
-                            }
-                            arg_vals.push(v);
-                        }
+                        let arg_vals = match eval_args(env, args)? {
+                            ControlFlow::Continue(values) => gather_rest(&method.params, values),
+                            ControlFlow::Break(ret) => return Ok(ret),
+                        };
 
                         // Call the static method
                         env.push_scope();
                         for (param, arg) in method.params.iter().zip(arg_vals.into_iter()) {
                             env.define(param.name.clone(), arg);
                         }
-                        let result = eval_block(env, &method.body);
+                        let result = eval_body(env, &format!("{}_{}", type_name, field), &method);
                         env.pop_scope();
 
                         return match result? {
@@ -2300,35 +4644,23 @@ pub fn eval_expr(env: &mut Env, expr: &Expr) -> KainResult<Value> {
                         .cloned();
 
                     if let Some(method) = method {
+                        // Skip 'self' parameter if present in method definition
+                        let params = without_self(&method.params);
+
                         // Evaluate arguments
-                        let mut arg_vals = Vec::new();
-                        for arg in args {
-                            let v = eval_expr(env, &arg.value)?;
-                            if let Value::Return(_) = v {
-                                return Ok(v);
-                            }
-                            arg_vals.push(v);
-                        }
+                        let arg_vals = match eval_args(env, args)? {
+                            ControlFlow::Continue(values) => gather_rest(params, values),
+                            ControlFlow::Break(ret) => return Ok(ret),
+                        };
 
                         // Call the instance method with `self` bound
                         env.push_scope();
                         env.define("self".to_string(), obj_val);
 
-                        // Skip 'self' parameter if present in method definition
-                        let params_iter = if let Some(first) = method.params.first() {
-                            if first.name == "self" {
-                                method.params.iter().skip(1)
-                            } else {
-                                method.params.iter().skip(0)
-                            }
-                        } else {
-                            method.params.iter().skip(0)
-                        };
-
-                        for (param, arg) in params_iter.zip(arg_vals.into_iter()) {
+                        for (param, arg) in params.iter().zip(arg_vals.into_iter()) {
                             env.define(param.name.clone(), arg);
                         }
-                        let result = eval_block(env, &method.body);
+                        let result = eval_body(env, &format!("{}_{}", type_name, field), &method);
                         env.pop_scope();
 
                         return match result? {
@@ -2349,18 +4681,39 @@ pub fn eval_expr(env: &mut Env, expr: &Expr) -> KainResult<Value> {
             };
 
             // Evaluate arguments
-            let mut arg_vals = Vec::new();
-            for arg in args {
-                let v = eval_expr(env, &arg.value)?;
-                if let Value::Return(_) = v {
-                    return Ok(v);
-                }
-                arg_vals.push(v);
-            }
+            let arg_vals = match eval_args(env, args)? {
+                ControlFlow::Continue(values) => values,
+                ControlFlow::Break(ret) => return Ok(ret),
+            };
 
             call_function(env, func_val, arg_vals)
         }
 
+        Expr::Handle { handler, effect, body, .. } => {
+            let handler = match handler.as_ref() {
+                // A type handles the effect with its static methods
+                Expr::Ident(name, _) if env.methods.contains_key(name) => {
+                    EffectHandler { effect: effect.clone(), type_name: name.clone(), receiver: None }
+                }
+                expr => match eval_expr(env, expr)? {
+                    Value::Return(v) => return Ok(Value::Return(v)),
+                    Value::Struct(name, fields) => {
+                        EffectHandler { effect: effect.clone(), type_name: name.clone(), receiver: Some(Value::Struct(name, fields)) }
+                    }
+                    other => {
+                        return Err(KainError::runtime(format!(
+                            "A handler for {} must be a struct or a type with methods, found {}",
+                            effect, other
+                        )))
+                    }
+                },
+            };
+            env.handlers.push(handler);
+            let result = eval_block(env, body);
+            env.handlers.pop();
+            result
+        }
+
         Expr::Try(inner, _) => {
             let val = eval_expr(env, inner)?;
             if let Value::Return(_) = val {
@@ -2369,8 +4722,10 @@ pub fn eval_expr(env: &mut Env, expr: &Expr) -> KainResult<Value> {
             match val {
                 Value::Result(true, v) => Ok(*v),
                 Value::Result(false, e) => Ok(Value::Return(Box::new(Value::Result(false, e)))),
+                Value::Some(v) => Ok(*v),
+                Value::None => Ok(Value::Return(Box::new(Value::None))),
                 _ => Err(KainError::runtime(
-                    "Type error: expected Result for ? operator",
+                    "Type error: expected Result or Option for ? operator",
                 )),
             }
         }
@@ -2418,9 +4773,22 @@ pub fn eval_expr(env: &mut Env, expr: &Expr) -> KainResult<Value> {
             Ok(Value::Unit)
         }
 
-        Expr::MacroCall { name, args, .. } => {
+        Expr::MacroCall { name, args, span } => {
             // Built-in macros
             match name.as_str() {
+                "dbg" => eval_dbg(env, args, *span),
+                "quote" => {
+                    let [Expr::Block(code, _)] = args.as_slice() else {
+                        return Err(KainError::runtime("quote: expected a block of code"));
+                    };
+                    let mut code = code.clone();
+                    let mut splicer = Splicer { env, error: None };
+                    splicer.visit_block_mut(&mut code);
+                    match splicer.error {
+                        Some(e) => Err(e),
+                        None => Ok(Value::Quote(Arc::new(code))),
+                    }
+                }
                 "vec" => {
                     let mut vals = Vec::new();
                     for arg in args {
@@ -2450,17 +4818,20 @@ pub fn eval_expr(env: &mut Env, expr: &Expr) -> KainResult<Value> {
                             Value::Int(_) => "int",
                             Value::Float(_) => "float",
                             Value::String(_) => "string",
+                            Value::Char(_) => "char",
                             Value::Array(_) => "array",
                             Value::Tuple(_) => "tuple",
+                            Value::Range(_) => "range",
                             Value::Struct(name, _) => return Ok(Value::String(name.clone())),
                             Value::Function(_) => "function",
                             Value::NativeFn(_, _) => "native_fn",
                             Value::StructConstructor(_, _) => "struct_constructor",
                             Value::ActorRef(_) => "actor",
-                            Value::None => "none",
+                            Value::None | Value::Some(_) => "option",
                             Value::Return(_) => "return",
                             Value::Result(_, _) => "result",
                             Value::Closure(_, _, _) => "closure",
+                            Value::BoundMethod(_, _) => "bound_method",
                             Value::JSX(_) => "jsx",
                             Value::EnumVariant(enum_name, _, _) => {
                                 return Ok(Value::String(enum_name.clone()))
@@ -2469,8 +4840,10 @@ pub fn eval_expr(env: &mut Env, expr: &Expr) -> KainResult<Value> {
                             Value::Future(name, _) => {
                                 return Ok(Value::String(format!("Future<{}>", name)))
                             }
-                            Value::Break(_) => "break",
-                            Value::Continue => "continue",
+                            Value::Quote(_) => "quote",
+                            Value::Module(_) => "module",
+                            Value::Break(..) => "break",
+                            Value::Continue(_) => "continue",
                         };
                         Ok(Value::String(type_name.to_string()))
                     } else {
@@ -2503,19 +4876,26 @@ pub fn eval_expr(env: &mut Env, expr: &Expr) -> KainResult<Value> {
             Ok(Value::String(result))
         }
         Expr::Bool(b, _) => Ok(Value::Bool(*b)),
+        Expr::Char(c, _) => Ok(Value::Char(*c)),
         Expr::None(_) => Ok(Value::None),
         Expr::Lambda { params, body, .. } => {
             let param_names = params.iter().map(|p| p.name.clone()).collect();
+            let mut captured = env.scopes.clone();
+            // A closure made in a module keeps seeing the module's names wherever it's called
+            if let Some(module) = env.current_module.as_ref().and_then(|m| env.modules.get(m)) {
+                captured.insert(1, module.names.clone());
+            }
             Ok(Value::Closure(
                 param_names,
                 body.clone(),
-                env.scopes.clone(),
+                captured,
             ))
         }
-        Expr::Ident(name, _span) => env
-            .lookup(name)
-            .cloned()
-            .ok_or_else(|| KainError::runtime(format!("Undefined: {}", name))),
+        Expr::Ident(name, _span) => lookup_name(env, name),
+        Expr::Local { name, depth, slot, .. } => match env.local(*depth, *slot, name) {
+            Some(value) => Ok(value.clone()),
+            None => lookup_name(env, name),
+        },
 
         Expr::Binary {
             left, op, right, ..
@@ -2577,11 +4957,9 @@ pub fn eval_expr(env: &mut Env, expr: &Expr) -> KainResult<Value> {
                     }
                 }
                 (Value::String(s), Value::Int(i)) => {
-                    let i = i as usize;
-                    if i < s.len() {
-                        Ok(Value::String(s.chars().nth(i).unwrap().to_string()))
-                    } else {
-                        Err(KainError::runtime(format!("Index out of bounds: {}", i)))
+                    match usize::try_from(i).ok().and_then(|i| s.chars().nth(i)) {
+                        Some(c) => Ok(Value::String(c.to_string())),
+                        None => Err(KainError::runtime(format!("Index out of bounds: {}", i))),
                     }
                 }
                 _ => Err(KainError::runtime(
@@ -2591,8 +4969,18 @@ pub fn eval_expr(env: &mut Env, expr: &Expr) -> KainResult<Value> {
         }
 
         // Structure creation
-        Expr::Struct { name, fields, .. } => {
-            let mut field_vals = HashMap::new();
+        Expr::Struct { name, fields, base, .. } => {
+            // `..base` supplies the fields not given, copied so the base is left as it was
+            let mut field_vals = match base {
+                Some(base) => match eval_expr(env, base)? {
+                    v @ Value::Return(_) => return Ok(v),
+                    Value::Struct(_, base_fields) => base_fields.read().unwrap_or_else(|e| e.into_inner()).clone(),
+                    other => {
+                        return Err(KainError::runtime(format!("`..` in a {} literal needs a struct, found {}", name, other)))
+                    }
+                },
+                None => HashMap::new(),
+            };
             for (k, expr) in fields {
                 let v = eval_expr(env, expr)?;
                 if let Value::Return(_) = v {
@@ -2616,13 +5004,23 @@ pub fn eval_expr(env: &mut Env, expr: &Expr) -> KainResult<Value> {
             }
 
             match obj_val {
-                Value::Struct(_, fields) => {
-                    let fields = fields.read().unwrap();
-                    fields
-                        .get(field)
-                        .cloned()
-                        .ok_or_else(|| KainError::runtime(format!("Field not found: {}", field)))
+                Value::Struct(ref name, ref fields) => {
+                    if let Some(value) = fields.read().unwrap().get(field) {
+                        return Ok(value.clone());
+                    }
+                    // `p.length` without a call is the method bound to `p`
+                    let method = format!("{}_{}", name, field);
+                    if env.functions.contains_key(&method) {
+                        return Ok(Value::BoundMethod(Box::new(obj_val), method));
+                    }
+                    Err(KainError::runtime(format!("Field not found: {}", field)))
                 }
+                Value::Module(module) => env.module_item(&module, field),
+                Value::Tuple(items) => field
+                    .parse::<usize>()
+                    .ok()
+                    .and_then(|i| items.get(i).cloned())
+                    .ok_or_else(|| KainError::runtime(format!("No field {} on a tuple of {} elements", field, items.len()))),
                 Value::ActorRef(r) => {
                     // Check if it's the current actor (self)
                     if let Some(self_id) = env.self_actor_id {
@@ -2639,10 +5037,15 @@ pub fn eval_expr(env: &mut Env, expr: &Expr) -> KainResult<Value> {
                     }
                     Err(KainError::runtime("Actor fields not accessible"))
                 }
-                _ => Err(KainError::runtime(format!(
-                    "Field access on non-struct value: {:?}",
-                    obj_val
-                ))),
+                _ => match builtin_type_name(&obj_val).map(|type_name| format!("{}_{}", type_name, field)) {
+                    Some(method) if env.functions.contains_key(&method) => {
+                        Ok(Value::BoundMethod(Box::new(obj_val), method))
+                    }
+                    _ => Err(KainError::runtime(format!(
+                        "Field access on non-struct value: {}",
+                        pretty(&obj_val, &PrettyOptions::BRIEF)
+                    ))),
+                },
             }
         }
 
@@ -2658,6 +5061,29 @@ pub fn eval_expr(env: &mut Env, expr: &Expr) -> KainResult<Value> {
             Ok(Value::Tuple(vals))
         }
 
+        Expr::Range { start, end, inclusive, .. } => {
+            let mut bound = |e: &Option<Box<Expr>>, default: i64| -> KainResult<Result<i64, Value>> {
+                match e {
+                    None => Ok(Ok(default)),
+                    Some(e) => match eval_expr(env, e)? {
+                        Value::Int(n) => Ok(Ok(n)),
+                        flow @ Value::Return(_) => Ok(Err(flow)),
+                        other => Err(KainError::runtime(format!("Range bounds must be Int, found {}", other))),
+                    },
+                }
+            };
+            let start = match bound(start, 0)? {
+                Ok(n) => n,
+                Err(flow) => return Ok(flow),
+            };
+            let end = match bound(end, i64::MAX)? {
+                Ok(n) => n,
+                Err(flow) => return Ok(flow),
+            };
+            let end = if *inclusive { end.saturating_add(1) } else { end };
+            Ok(Value::Range(IntRange::new(start, end)))
+        }
+
         Expr::Spawn { actor, init, .. } => {
             // Find actor definition
             let actor_def = env
@@ -2677,11 +5103,17 @@ pub fn eval_expr(env: &mut Env, expr: &Expr) -> KainResult<Value> {
             }
 
             // Create channel
-            let (tx, rx) = flume::unbounded();
-            let id = env.next_actor_id;
-            env.next_actor_id += 1;
+            let (tx, rx) = match &actor_def.mailbox {
+                Some(mailbox) => flume::bounded(mailbox.capacity),
+                None => flume::unbounded(),
+            };
+            let overflow = actor_def.mailbox.as_ref().map(|mailbox| match mailbox.policy {
+                MailboxPolicy::Block => Overflow::Block,
+                MailboxPolicy::DropOldest => Overflow::DropOldest(rx.clone()),
+                MailboxPolicy::Fail => Overflow::Fail,
+            });
+            let id = env.actors.lock().unwrap_or_else(|e| e.into_inner()).register(actor, &tx);
             let sender = tx.clone();
-            env.actors.insert(id, sender.clone());
 
             // Spawn thread
             let functions = env.functions.clone();
@@ -2691,6 +5123,25 @@ pub fn eval_expr(env: &mut Env, expr: &Expr) -> KainResult<Value> {
             let global_scope = env.scopes.first().cloned().unwrap_or_default();
             let actor_name = actor.clone();
             let self_sender = tx.clone();
+            let self_overflow = overflow.clone();
+            let limits = env.limits;
+            let print = env.print;
+            let source = env.source.clone();
+            let deadline = env.deadline;
+            let denied_natives = env.denied_natives.clone();
+            let output = env.output.clone();
+            let errors = env.errors.clone();
+            let databases = env.databases.clone();
+            let kos = env.kos.clone();
+            let program_args = env.program_args.clone();
+            let function_modules = env.function_modules.clone();
+            let edition = env.edition;
+            let modules = env.modules.clone();
+            let statics = env.statics.clone();
+            let actors = env.actors.clone();
+            let trace = env.trace.clone().map(|tracer| Tracer { prefix: format!("[actor {}] ", id), depth: 0, ..tracer });
+            let journal = env.journal.clone();
+            let scheduler = env.scheduler.clone();
 
             std::thread::spawn(move || {
                 let mut actor_env = Env {
@@ -2698,11 +5149,40 @@ pub fn eval_expr(env: &mut Env, expr: &Expr) -> KainResult<Value> {
                     functions,
                     components,
                     methods,
-                    actors: HashMap::new(),
-                    next_actor_id: 0,
+                    actors,
                     actor_defs,
                     self_actor_id: Some(id),
                     python_scope: None,
+                    limits,
+                    print,
+                    source,
+                    usage: ResourceUsage::default(),
+                    allocations: HashMap::new(),
+                    function: None,
+                    deadline,
+                    denied_natives,
+                    output,
+                    errors,
+                    emitted_items: Vec::new(),
+                    databases,
+                    kos,
+                    program_args,
+                    function_modules,
+                    current_module: None,
+                    edition,
+                    modules,
+                    loaded_modules: HashMap::new(),
+                    loading: Vec::new(),
+                    imports: HashMap::new(),
+                    statics,
+                    build: None,
+                    handlers: Vec::new(),
+                    task: TaskScope::default(),
+                    snapshot: None,
+                    trace,
+                    journal,
+                    sent: 0,
+                    scheduler,
                 };
 
                 // Initialize Python scope
@@ -2723,6 +5203,7 @@ pub fn eval_expr(env: &mut Env, expr: &Expr) -> KainResult<Value> {
                 let actor_val = Value::ActorRef(ActorRef {
                     id,
                     sender: self_sender,
+                    overflow: self_overflow,
                 });
                 actor_env.define("self".to_string(), actor_val);
 
@@ -2735,17 +5216,63 @@ pub fn eval_expr(env: &mut Env, expr: &Expr) -> KainResult<Value> {
                         match eval_expr(&mut actor_env, &state_decl.initial) {
                             Ok(val) => actor_env.define(state_decl.name.clone(), val),
                             Err(e) => {
-                                eprintln!("Actor initialization error: {}", e);
+                                actor_env.write_error(&format!("Actor initialization error: {}\n", e));
                                 return;
                             }
                         }
                     }
-                }
-
-                // Event loop
-                while let Ok(msg) = rx.recv() {
+                }
+
+                // Persisted state picks up where the last snapshot left off
+                let persisted: Vec<(String, Type)> =
+                    actor_def.state.iter().filter(|s| s.persist).map(|s| (s.name.clone(), s.ty.clone())).collect();
+                if !persisted.is_empty() {
+                    let path = PathBuf::from(format!("{}.snapshot", actor_name));
+                    actor_env.snapshot = Some(Snapshot { path, fields: persisted });
+                    if let Err(e) = restore_snapshot(&mut actor_env, None) {
+                        actor_env.write_error(&format!("Actor initialization error: {}\n", e));
+                        return;
+                    }
+                }
+
+                // Event loop. A replay takes messages in the order the
+                // recording handled them, holding on to those that arrive early.
+                let mut early: Vec<Message> = Vec::new();
+                loop {
+                    let next = match &actor_env.scheduler {
+                        Some(scheduler) => match scheduler.wait_turn(id) {
+                            Some(origin) => Some(origin),
+                            None => break,
+                        },
+                        None => actor_env.journal.as_ref().and_then(|journal| journal.next_delivery(id)),
+                    };
+                    let msg = match next {
+                        Some(origin) => match early.iter().position(|m| m.origin == origin) {
+                            Some(i) => early.remove(i),
+                            None => match rx.recv() {
+                                Ok(msg) if msg.origin == origin => msg,
+                                Ok(msg) => {
+                                    early.push(msg);
+                                    continue;
+                                }
+                                Err(_) => break,
+                            },
+                        },
+                        None if !early.is_empty() => early.remove(0),
+                        None => match rx.recv() {
+                            Ok(msg) => msg,
+                            Err(_) => break,
+                        },
+                    };
+                    if let Some(journal) = actor_env.journal.clone() {
+                        if let Err(e) = journal.deliver(id, msg.origin) {
+                            actor_env.write_error(&format!("Actor {} stopped: {}\n", actor_name, e));
+                            break;
+                        }
+                    }
                     // Find handler
                     let mut handled = false;
+                    let mut failure = None;
                     for handler in &actor_def.handlers {
                         if handler.message_type == msg.name {
                             // Run handler
@@ -2758,7 +5285,8 @@ pub fn eval_expr(env: &mut Env, expr: &Expr) -> KainResult<Value> {
                             }
 
                             if let Err(e) = eval_block(&mut actor_env, &handler.body) {
-                                println!("Error in actor handler {}: {}", handler.message_type, e);
+                                actor_env.write_error(&format!("Error in actor handler {}: {}\n", handler.message_type, e));
+                                failure = Some(format!("{}#{} failed handling {}: {}", actor_name, id, handler.message_type, e));
                             }
                             actor_env.pop_scope();
                             handled = true;
@@ -2766,15 +5294,15 @@ pub fn eval_expr(env: &mut Env, expr: &Expr) -> KainResult<Value> {
                         }
                     }
                     if !handled {
-                        println!(
-                            "Actor {} received unknown message: {}",
-                            actor_name, msg.name
-                        );
+                        actor_env.write_error(&format!("Actor {} received unknown message: {}\n", actor_name, msg.name));
+                    }
+                    if let Some(scheduler) = &actor_env.scheduler {
+                        scheduler.done(id, failure);
                     }
                 }
             });
 
-            Ok(Value::ActorRef(ActorRef { id, sender }))
+            Ok(Value::ActorRef(ActorRef { id, sender, overflow }))
         }
 
         Expr::SendMsg {
@@ -2795,12 +5323,7 @@ pub fn eval_expr(env: &mut Env, expr: &Expr) -> KainResult<Value> {
                     msg_args.push(v);
                 }
 
-                let msg = Message {
-                    name: message.clone(),
-                    args: msg_args,
-                };
-
-                let _ = r.sender.send(msg);
+                env.post(&r, message.clone(), msg_args)?;
                 Ok(Value::Unit)
             } else {
                 Err(KainError::runtime("send target must be an actor"))
@@ -2822,6 +5345,11 @@ pub fn eval_expr(env: &mut Env, expr: &Expr) -> KainResult<Value> {
 
         Expr::Paren(inner, _) => eval_expr(env, inner),
 
+        // A reference is the value it refers to; arrays and structs are shared, so
+        // changes through `&mut` are seen by the owner. Comptime code gets here,
+        // compiled code has its references erased first
+        Expr::Ref { value, .. } => eval_expr(env, value),
+
         // Await expression: await future_expr
         // Uses the async runtime to poll the future to completion
         Expr::Await(future_expr, _span) => {
@@ -2841,6 +5369,15 @@ pub fn eval_expr(env: &mut Env, expr: &Expr) -> KainResult<Value> {
             fields,
             ..
         } => {
+            // `Point::length` names the method itself, callable as length(p)
+            let lowered_name = format!("{}_{}", enum_name, variant);
+            if matches!(fields, EnumVariantFields::Unit) {
+                let takes_self = env.functions.get(&lowered_name).and_then(|f| f.params.first()).is_some_and(|p| p.name == "self");
+                if takes_self {
+                    return Ok(Value::Function(lowered_name));
+                }
+            }
+
             // First, check if this is a static method call
             // Check if enum_name is a type with methods and variant is a method name
             if let Some(type_methods) = env.methods.get(enum_name).cloned() {
@@ -2877,7 +5414,7 @@ pub fn eval_expr(env: &mut Env, expr: &Expr) -> KainResult<Value> {
                     for (param, arg) in method.params.iter().zip(arg_vals.into_iter()) {
                         env.define(param.name.clone(), arg);
                     }
-                    let result = eval_block(env, &method.body)?;
+                    let result = eval_body(env, &lowered_name, &method)?;
                     env.pop_scope();
 
                     return match result {
@@ -2888,7 +5425,6 @@ pub fn eval_expr(env: &mut Env, expr: &Expr) -> KainResult<Value> {
             }
 
             // Check for lowered function name: Type_method (from monomorphization)
-            let lowered_name = format!("{}_{}", enum_name, variant);
             if let Some(func) = env.functions.get(&lowered_name).cloned() {
                 // This is a lowered method call (Type_method from monomorphization)
                 let arg_vals: Vec<Value> = match fields {
@@ -2922,7 +5458,7 @@ pub fn eval_expr(env: &mut Env, expr: &Expr) -> KainResult<Value> {
                 for (param, arg) in func.params.iter().zip(arg_vals.into_iter()) {
                     env.define(param.name.clone(), arg);
                 }
-                let result = eval_block(env, &func.body)?;
+                let result = eval_body(env, &lowered_name, &func)?;
                 env.pop_scope();
 
                 return match result {
@@ -2987,16 +5523,16 @@ pub fn eval_expr(env: &mut Env, expr: &Expr) -> KainResult<Value> {
             }
         }
 
-        Expr::Break(expr, _) => {
+        Expr::Break(expr, label, _) => {
             let val = if let Some(e) = expr {
                 Some(Box::new(eval_expr(env, e)?))
             } else {
                 None
             };
-            Ok(Value::Break(val))
+            Ok(Value::Break(label.clone(), val))
         }
 
-        Expr::Continue(_) => Ok(Value::Continue),
+        Expr::Continue(label, _) => Ok(Value::Continue(label.clone())),
 
         _ => Err(KainError::runtime(format!(
             "Expression not supported in runtime: {:?}",
@@ -3005,7 +5541,43 @@ pub fn eval_expr(env: &mut Env, expr: &Expr) -> KainResult<Value> {
     }
 }
 
+/// The type name an `impl` block uses to extend a built-in value
+fn builtin_type_name(value: &Value) -> Option<&'static str> {
+    match value {
+        Value::Array(_) => Some("Array"),
+        Value::Range(_) => Some("Range"),
+        Value::String(_) => Some("String"),
+        Value::Char(_) => Some("Char"),
+        Value::Int(_) => Some("Int"),
+        Value::Float(_) => Some("Float"),
+        Value::Bool(_) => Some("Bool"),
+        Value::Some(_) | Value::None => Some("Option"),
+        _ => None,
+    }
+}
+
+/// Run the body of a function or lowered method with the names of the module
+/// that defines it in scope
+fn eval_body(env: &mut Env, name: &str, f: &Function) -> KainResult<Value> {
+    if env.trace.is_some() {
+        // The parameters are bound by now
+        let args = f.params.iter().map(|p| env.lookup(&p.name).map_or_else(String::new, trace_repr)).collect();
+        env.trace_call(name, args);
+    }
+    let module = env.function_modules.get(name).cloned();
+    let caller_module = std::mem::replace(&mut env.current_module, module);
+    let caller = std::mem::replace(&mut env.function, Some(name.to_string()));
+    let result = eval_block(env, &f.body);
+    env.current_module = caller_module;
+    env.function = caller;
+    env.trace_return(name, &result);
+    result
+}
+
 fn call_function(env: &mut Env, func: Value, args: Vec<Value>) -> KainResult<Value> {
+    if let Some((depth, method)) = effect_handler_for(env, &func) {
+        return call_handler(env, depth, method, args);
+    }
     match func {
         Value::Function(name) => {
             let f = env
@@ -3013,20 +5585,29 @@ fn call_function(env: &mut Env, func: Value, args: Vec<Value>) -> KainResult<Val
                 .get(&name)
                 .cloned()
                 .ok_or_else(|| KainError::runtime(format!("Function not found: {}", name)))?;
+            let variadic = f.params.last().is_some_and(|p| p.variadic);
+            let args = gather_rest(&f.params, args);
             if f.params.len() != args.len() {
+                let expected = f.params.len() - usize::from(variadic);
                 return Err(KainError::runtime(format!(
-                    "Argument mismatch: expected {}, got {}",
-                    f.params.len(),
+                    "{} expects {}{} argument{}, found {}",
+                    name,
+                    if variadic { "at least " } else { "" },
+                    expected,
+                    if expected == 1 { "" } else { "s" },
                     args.len()
                 )));
             }
 
+            env.enter_call()?;
             env.push_scope();
             for (param, arg) in f.params.iter().zip(args.into_iter()) {
                 env.define(param.name.clone(), arg);
             }
 
-            let result = eval_block(env, &f.body)?;
+            let result = eval_body(env, &name, &f);
+            env.exit_call();
+            let result = result?;
             env.pop_scope();
 
             match result {
@@ -3034,7 +5615,28 @@ fn call_function(env: &mut Env, func: Value, args: Vec<Value>) -> KainResult<Val
                 v => Ok(v),
             }
         }
-        Value::NativeFn(_, f) => f(env, args),
+        Value::NativeFn(name, f) => {
+            if let Some(reason) = env.denied_natives.get(&name) {
+                return Err(KainError::runtime(format!("`{}` is not allowed {}", name, reason)));
+            }
+            if let Some(message) = crate::stdlib::stdlib().functions.get(name.as_str()).and_then(|b| b.arity_error(args.len())) {
+                return Err(KainError::runtime(message));
+            }
+            if env.trace.is_some() {
+                env.trace_call(&name, args.iter().map(trace_repr).collect());
+            }
+            let result = match env.journal.clone() {
+                Some(journal) if replay::RECORDED_NATIVES.contains(&name.as_str()) => {
+                    let actor = env.self_actor_id.unwrap_or(0);
+                    journal.native(actor, &name, || f(env, args))
+                }
+                _ => f(env, args),
+            };
+            env.trace_return(&name, &result);
+            let value = result?;
+            env.charge_heap(&value)?;
+            Ok(value)
+        }
         Value::Closure(params, body, captured) => {
             if params.len() != args.len() {
                 return Err(KainError::runtime(format!("Closure arg mismatch")));
@@ -3045,11 +5647,18 @@ fn call_function(env: &mut Env, func: Value, args: Vec<Value>) -> KainResult<Val
             env.scopes = captured;
             env.push_scope();
 
+            if env.trace.is_some() {
+                env.trace_call("<closure>", args.iter().map(trace_repr).collect());
+            }
             for (name, arg) in params.iter().zip(args.into_iter()) {
                 env.define(name.clone(), arg);
             }
 
-            let result = eval_expr(env, &body)?;
+            env.enter_call()?;
+            let result = eval_expr(env, &body);
+            env.exit_call();
+            env.trace_return("<closure>", &result);
+            let result = result?;
 
             env.pop_scope();
             env.scopes = old_scopes;
@@ -3059,6 +5668,11 @@ fn call_function(env: &mut Env, func: Value, args: Vec<Value>) -> KainResult<Val
                 v => Ok(v),
             }
         }
+        Value::BoundMethod(receiver, name) => {
+            let mut args = args;
+            args.insert(0, *receiver);
+            call_function(env, Value::Function(name), args)
+        }
         Value::StructConstructor(name, fields) => {
             if fields.len() != args.len() {
                 return Err(KainError::runtime(format!(
@@ -3080,24 +5694,279 @@ fn call_function(env: &mut Env, func: Value, args: Vec<Value>) -> KainResult<Val
     }
 }
 
+/// A call's arguments, with each `...xs` spread into one argument per element;
+/// `Break` with the value of a `return` reached while evaluating them
+fn eval_args(env: &mut Env, args: &[CallArg]) -> KainResult<ControlFlow<Value, Vec<Value>>> {
+    let mut values = Vec::with_capacity(args.len());
+    for arg in args {
+        let v = eval_expr(env, &arg.value)?;
+        if let Value::Return(_) = v {
+            return Ok(ControlFlow::Break(v));
+        }
+        if !arg.spread {
+            values.push(v);
+            continue;
+        }
+        match v {
+            Value::Array(items) => values.extend(items.read().unwrap().iter().cloned()),
+            Value::Tuple(items) => values.extend(items),
+            other => {
+                return Err(KainError::runtime(format!(
+                    "`...` spreads an Array or a tuple, found {}",
+                    pretty(&other, &PrettyOptions::BRIEF)
+                )))
+            }
+        }
+    }
+    Ok(ControlFlow::Continue(values))
+}
+
+/// A method's parameters after `self`
+fn without_self(params: &[Param]) -> &[Param] {
+    match params.first() {
+        Some(p) if p.name == "self" => &params[1..],
+        _ => params,
+    }
+}
+
+/// Gather the arguments a trailing `...rest` parameter takes into one array,
+/// so they bind to `params` one to one; too few are left for the caller to report
+fn gather_rest(params: &[Param], mut args: Vec<Value>) -> Vec<Value> {
+    if params.last().is_some_and(|p| p.variadic) && args.len() + 1 >= params.len() {
+        let rest = args.split_off(params.len() - 1);
+        args.push(new_array(rest));
+    }
+    args
+}
+
+/// The innermost `with handler` block that handles a call to `func`: its
+/// index in `env.handlers`, and the method standing in for `func`. A handler
+/// takes calls that perform its effect and that it has a method of the same
+/// name for: natives of the built-in effect, or functions declared `with` it.
+fn effect_handler_for(env: &Env, func: &Value) -> Option<(usize, Function)> {
+    if env.handlers.is_empty() {
+        return None;
+    }
+    let name = match func {
+        Value::NativeFn(name, _) | Value::Function(name) => name.as_str(),
+        _ => return None,
+    };
+    env.handlers.iter().enumerate().rev().find_map(|(depth, handler)| {
+        let performs = match func {
+            Value::NativeFn(..) => native_performs(name, &handler.effect),
+            _ => env.functions.get(name).is_some_and(|f| f.effects.contains(&handler.effect)),
+        };
+        if !performs {
+            return None;
+        }
+        Some((depth, env.methods.get(&handler.type_name)?.get(name)?.clone()))
+    })
+}
+
+/// Whether the native `name` performs the built-in `effect`
+fn native_performs(name: &str, effect: &Effect) -> bool {
+    match effect {
+        Effect::IO => [CONSOLE_NATIVES, FILE_NATIVES, NETWORK_NATIVES, GAME_NATIVES].iter().any(|natives| natives.contains(&name)),
+        Effect::Db => DB_NATIVES.contains(&name),
+        _ => false,
+    }
+}
+
+/// Run a handler's method in place of the call it handles. That handler and
+/// those installed inside it are set aside meanwhile, so the method can
+/// perform the effect itself on whatever handled it before.
+fn call_handler(env: &mut Env, depth: usize, method: Function, args: Vec<Value>) -> KainResult<Value> {
+    let inner = env.handlers.split_off(depth);
+    let handler = &inner[0];
+    env.push_scope();
+    let skip = match &handler.receiver {
+        Some(receiver) => {
+            env.define("self".to_string(), receiver.clone());
+            usize::from(method.params.first().is_some_and(|p| p.name == "self"))
+        }
+        None => 0,
+    };
+    let params = &method.params[skip.min(method.params.len())..];
+    for (param, arg) in params.iter().zip(gather_rest(params, args)) {
+        env.define(param.name.clone(), arg);
+    }
+    let result = eval_body(env, &format!("{}_{}", handler.type_name, method.name), &method);
+    env.pop_scope();
+    env.handlers.extend(inner);
+
+    match result? {
+        Value::Return(v) => Ok(*v),
+        v => Ok(v),
+    }
+}
+
+/// Total order over comparable values: numbers, strings, bools, and arrays
+/// or tuples of them compared element by element
+fn compare_values(a: &Value, b: &Value) -> KainResult<Ordering> {
+    match (a, b) {
+        (Value::Int(a), Value::Int(b)) => Ok(a.cmp(b)),
+        (Value::Int(_) | Value::Float(_), Value::Int(_) | Value::Float(_)) => {
+            let (a, b) = (as_f64("compare", a)?, as_f64("compare", b)?);
+            Ok(a.total_cmp(&b))
+        }
+        (Value::String(a), Value::String(b)) => Ok(a.cmp(b)),
+        (Value::Char(a), Value::Char(b)) => Ok(a.cmp(b)),
+        (Value::Bool(a), Value::Bool(b)) => Ok(a.cmp(b)),
+        (Value::Tuple(a), Value::Tuple(b)) => compare_sequences(a, b),
+        (Value::Array(a), Value::Array(b)) => compare_sequences(&a.read().unwrap(), &b.read().unwrap()),
+        (Value::None, Value::None) | (Value::Unit, Value::Unit) => Ok(Ordering::Equal),
+        // `None` sorts before every `Some`
+        (Value::None, Value::Some(_)) => Ok(Ordering::Less),
+        (Value::Some(_), Value::None) => Ok(Ordering::Greater),
+        (Value::Some(a), Value::Some(b)) => compare_values(a, b),
+        _ => Err(KainError::runtime(format!("cannot compare {} with {}", a, b))),
+    }
+}
+
+fn compare_sequences(a: &[Value], b: &[Value]) -> KainResult<Ordering> {
+    for (x, y) in a.iter().zip(b) {
+        match compare_values(x, y)? {
+            Ordering::Equal => {}
+            order => return Ok(order),
+        }
+    }
+    Ok(a.len().cmp(&b.len()))
+}
+
+fn contains_value(items: &[Value], value: &Value) -> KainResult<bool> {
+    for item in items {
+        if compare_values(item, value)? == Ordering::Equal {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+/// Stable merge sort that stops at the first error `cmp` returns. std's sorts
+/// may panic when the comparator is not a total order, which a user's closure
+/// needn't be; merging only ever asks which of two elements goes first, so
+/// any answers give some permutation of `items`.
+fn merge_sort_by(mut items: Vec<Value>, cmp: &mut dyn FnMut(&Value, &Value) -> KainResult<Ordering>) -> KainResult<Vec<Value>> {
+    if items.len() < 2 {
+        return Ok(items);
+    }
+    let right = items.split_off(items.len() / 2);
+    let mut left = merge_sort_by(items, cmp)?.into_iter().peekable();
+    let mut right = merge_sort_by(right, cmp)?.into_iter().peekable();
+    let mut merged = Vec::with_capacity(left.len() + right.len());
+    while let (Some(a), Some(b)) = (left.peek(), right.peek()) {
+        let next = if cmp(b, a)? == Ordering::Less { right.next() } else { left.next() };
+        merged.extend(next);
+    }
+    merged.extend(left);
+    merged.extend(right);
+    Ok(merged)
+}
+
+fn call_comparator(env: &mut Env, cmp: &Value, a: &Value, b: &Value) -> KainResult<Ordering> {
+    match call_function(env, cmp.clone(), vec![a.clone(), b.clone()])? {
+        Value::Int(n) => Ok(n.cmp(&0)),
+        other => Err(KainError::runtime(format!("comparator must return an Int, got {}", other))),
+    }
+}
+
+/// `min_by` / `max_by`: the element that is `wanted` against all others, or None
+fn extreme_by(env: &mut Env, native: &str, args: Vec<Value>, wanted: Ordering) -> KainResult<Value> {
+    let [items, cmp] = args.as_slice() else {
+        return Err(KainError::runtime(format!("{}: expected 2 arguments (array, comparator)", native)));
+    };
+    let mut best: Option<Value> = None;
+    for item in array_arg(native, items)? {
+        best = match best {
+            Some(current) => {
+                let order = call_comparator(env, cmp, &item, &current)?;
+                // Keep the first minimum but move to a later equal maximum
+                if order == wanted || (order == Ordering::Equal && wanted == Ordering::Greater) {
+                    Some(item)
+                } else {
+                    Some(current)
+                }
+            }
+            None => Some(item),
+        };
+    }
+    Ok(best.unwrap_or(Value::None))
+}
+
+fn array_arg(native: &str, value: &Value) -> KainResult<Vec<Value>> {
+    match value {
+        Value::Array(items) => Ok(items.read().unwrap().clone()),
+        Value::Range(r) => range_items(native, r),
+        _ => Err(KainError::runtime(format!("{}: expected an array", native))),
+    }
+}
+
+/// The elements of a range, for natives that work on arrays
+fn range_items(native: &str, range: &IntRange) -> KainResult<Vec<Value>> {
+    if range.is_unbounded() {
+        return Err(KainError::runtime(format!("{}: cannot collect the unbounded range {}", native, range)));
+    }
+    Ok(range.iter().map(Value::Int).collect())
+}
+
+/// An index in `0..=max`
+fn index_arg(native: &str, index: &Value, max: usize) -> KainResult<usize> {
+    match index {
+        Value::Int(i) if *i >= 0 && *i as usize <= max => Ok(*i as usize),
+        Value::Int(i) => Err(KainError::runtime(format!("{}: index {} out of bounds for length {}", native, i, max))),
+        _ => Err(KainError::runtime(format!("{}: index must be an Int", native))),
+    }
+}
+
+fn new_array(items: Vec<Value>) -> Value {
+    Value::Array(Arc::new(RwLock::new(items)))
+}
+
 fn eval_binop(op: BinaryOp, left: Value, right: Value) -> KainResult<Value> {
     match (op, &left, &right) {
-        (BinaryOp::Add, Value::Int(a), Value::Int(b)) => Ok(Value::Int(a + b)),
-        (BinaryOp::Sub, Value::Int(a), Value::Int(b)) => Ok(Value::Int(a - b)),
-        (BinaryOp::Mul, Value::Int(a), Value::Int(b)) => Ok(Value::Int(a * b)),
-        (BinaryOp::Div, Value::Int(a), Value::Int(b)) => Ok(Value::Int(a / b)),
-        (BinaryOp::Mod, Value::Int(a), Value::Int(b)) => Ok(Value::Int(a % b)),
+        (BinaryOp::Add, Value::Int(a), Value::Int(b)) => Ok(Value::Int(a.wrapping_add(*b))),
+        (BinaryOp::Sub, Value::Int(a), Value::Int(b)) => Ok(Value::Int(a.wrapping_sub(*b))),
+        (BinaryOp::Mul, Value::Int(a), Value::Int(b)) => Ok(Value::Int(a.wrapping_mul(*b))),
+        (BinaryOp::Div | BinaryOp::Mod, Value::Int(_), Value::Int(0)) => {
+            Err(KainError::runtime("Division by zero"))
+        }
+        (BinaryOp::Div, Value::Int(a), Value::Int(b)) => Ok(Value::Int(a.wrapping_div(*b))),
+        (BinaryOp::Mod, Value::Int(a), Value::Int(b)) => Ok(Value::Int(a.wrapping_rem(*b))),
         (BinaryOp::Add, Value::Float(a), Value::Float(b)) => Ok(Value::Float(a + b)),
         (BinaryOp::Sub, Value::Float(a), Value::Float(b)) => Ok(Value::Float(a - b)),
         (BinaryOp::Mul, Value::Float(a), Value::Float(b)) => Ok(Value::Float(a * b)),
         (BinaryOp::Div, Value::Float(a), Value::Float(b)) => Ok(Value::Float(a / b)),
         (BinaryOp::Add, Value::String(a), Value::String(b)) => Ok(Value::String(a.to_owned() + b)),
+        (BinaryOp::Add, Value::String(a), Value::Char(b)) => Ok(Value::String(format!("{}{}", a, b))),
+        (BinaryOp::Add, Value::Char(a), Value::String(b)) => Ok(Value::String(format!("{}{}", a, b))),
+        (BinaryOp::Add, Value::Char(c), Value::Int(n)) => char_offset(*c, *n),
+        (BinaryOp::Sub, Value::Char(c), Value::Int(n)) => char_offset(*c, n.wrapping_neg()),
+        (BinaryOp::Sub, Value::Char(a), Value::Char(b)) => Ok(Value::Int(*a as i64 - *b as i64)),
+        (BinaryOp::Eq | BinaryOp::Ne | BinaryOp::Lt | BinaryOp::Gt | BinaryOp::Le | BinaryOp::Ge, Value::Char(a), Value::Char(b)) => {
+            Ok(Value::Bool(match op {
+                BinaryOp::Eq => a == b,
+                BinaryOp::Ne => a != b,
+                BinaryOp::Lt => a < b,
+                BinaryOp::Gt => a > b,
+                BinaryOp::Le => a <= b,
+                _ => a >= b,
+            }))
+        }
+        // `char_at` and `chars` hand out one-char strings, which equal the char they hold
+        (BinaryOp::Eq, Value::Char(c), Value::String(s)) | (BinaryOp::Eq, Value::String(s), Value::Char(c)) => {
+            Ok(Value::Bool(is_char_string(s, *c)))
+        }
+        (BinaryOp::Ne, Value::Char(c), Value::String(s)) | (BinaryOp::Ne, Value::String(s), Value::Char(c)) => {
+            Ok(Value::Bool(!is_char_string(s, *c)))
+        }
         (BinaryOp::Eq, Value::Int(a), Value::Int(b)) => Ok(Value::Bool(a == b)),
         (BinaryOp::Ne, Value::Int(a), Value::Int(b)) => Ok(Value::Bool(a != b)),
         (BinaryOp::Eq, Value::String(a), Value::String(b)) => Ok(Value::Bool(a == b)),
         (BinaryOp::Ne, Value::String(a), Value::String(b)) => Ok(Value::Bool(a != b)),
         (BinaryOp::Eq, Value::Bool(a), Value::Bool(b)) => Ok(Value::Bool(a == b)),
         (BinaryOp::Ne, Value::Bool(a), Value::Bool(b)) => Ok(Value::Bool(a != b)),
+        (BinaryOp::Eq, Value::Range(a), Value::Range(b)) => Ok(Value::Bool(a == b)),
+        (BinaryOp::Ne, Value::Range(a), Value::Range(b)) => Ok(Value::Bool(a != b)),
 
         // Float comparisons
         (BinaryOp::Lt, Value::Float(a), Value::Float(b)) => Ok(Value::Bool(a < b)),
@@ -3119,6 +5988,9 @@ fn eval_binop(op: BinaryOp, left: Value, right: Value) -> KainResult<Value> {
         (BinaryOp::Or, Value::Bool(a), Value::Bool(b)) => Ok(Value::Bool(*a || *b)),
         (BinaryOp::Eq, Value::None, Value::None) => Ok(Value::Bool(true)),
         (BinaryOp::Ne, Value::None, Value::None) => Ok(Value::Bool(false)),
+        (BinaryOp::Eq | BinaryOp::Ne, Value::Some(a), Value::Some(b)) => {
+            eval_binop(op, a.as_ref().clone(), b.as_ref().clone())
+        }
         (BinaryOp::Eq, Value::Unit, Value::Unit) => Ok(Value::Bool(true)),
         (BinaryOp::Ne, Value::Unit, Value::Unit) => Ok(Value::Bool(false)),
         (BinaryOp::Eq, _, _) => Ok(Value::Bool(false)),
@@ -3126,12 +5998,35 @@ fn eval_binop(op: BinaryOp, left: Value, right: Value) -> KainResult<Value> {
 
         // Error on mismatch unless one is Any?
         _ => Err(KainError::runtime(format!(
-            "Type mismatch in binary operation: {:?} {:?} {:?}",
-            left, op, right
+            "Type mismatch in binary operation: {} {:?} {}",
+            pretty(&left, &PrettyOptions::BRIEF),
+            op,
+            pretty(&right, &PrettyOptions::BRIEF)
         ))),
     }
 }
 
+/// `c + n`, or an error when that is past the last char or inside the surrogate gap
+fn char_offset(c: char, n: i64) -> KainResult<Value> {
+    (c as i64)
+        .checked_add(n)
+        .and_then(|code| u32::try_from(code).ok())
+        .and_then(char::from_u32)
+        .map(Value::Char)
+        .ok_or_else(|| KainError::runtime(format!("{:?} + {} is not a char", c, n)))
+}
+
+/// The only item of `items`, if there is exactly one
+fn single<T>(mut items: impl Iterator<Item = T>) -> Option<T> {
+    let first = items.next()?;
+    items.next().is_none().then_some(first)
+}
+
+/// Whether `s` is exactly the one char `c`
+fn is_char_string(s: &str, c: char) -> bool {
+    single(s.chars()) == Some(c)
+}
+
 fn pattern_matches(pattern: &Pattern, value: &Value) -> bool {
     match pattern {
         Pattern::Wildcard(_) => true,
@@ -3139,6 +6034,39 @@ fn pattern_matches(pattern: &Pattern, value: &Value) -> bool {
         Pattern::Literal(Expr::Int(n, _)) => matches!(value, Value::Int(v) if *v == *n),
         Pattern::Literal(Expr::String(s, _)) => matches!(value, Value::String(v) if v == s),
         Pattern::Literal(Expr::Bool(b, _)) => matches!(value, Value::Bool(v) if *v == *b),
+        Pattern::Literal(Expr::Char(c, _)) => match value {
+            Value::Char(v) => v == c,
+            Value::String(s) => is_char_string(s, *c),
+            _ => false,
+        },
+        Pattern::Range { start, end, inclusive, .. } => {
+            let bound = |e: &Option<Box<Expr>>| match e.as_deref() {
+                Some(Expr::Int(n, _)) => Some(*n),
+                Some(Expr::Char(c, _)) => Some(*c as i64),
+                _ => None,
+            };
+            // Char ranges match chars, and the one-char strings `char_at` returns
+            let char_range = matches!(start.as_deref(), Some(Expr::Char(..)));
+            let n = match value {
+                Value::Int(n) if !char_range => *n,
+                Value::Char(c) if char_range => *c as i64,
+                Value::String(s) if char_range => match single(s.chars()) {
+                    Some(c) => c as i64,
+                    None => return false,
+                },
+                _ => return false,
+            };
+            let above = match bound(start) {
+                Some(lo) => n >= lo,
+                None => true,
+            };
+            let below = match bound(end) {
+                Some(hi) if *inclusive => n <= hi,
+                Some(hi) => n < hi,
+                None => true,
+            };
+            above && below
+        }
         Pattern::Variant {
             variant, fields, ..
         } => {
@@ -3162,6 +6090,20 @@ fn pattern_matches(pattern: &Pattern, value: &Value) -> bool {
                 }
                 return false;
             }
+            if let Value::Result(ok, inner) = value {
+                return match (variant.as_str(), fields) {
+                    ("Ok", VariantPatternFields::Tuple(pats)) | ("Err", VariantPatternFields::Tuple(pats)) => {
+                        *ok == (variant == "Ok") && pats.len() == 1 && pattern_matches(&pats[0], inner)
+                    }
+                    _ => false,
+                };
+            }
+            if let Value::Some(inner) = value {
+                return match (variant.as_str(), fields) {
+                    ("Some", VariantPatternFields::Tuple(pats)) => pats.len() == 1 && pattern_matches(&pats[0], inner),
+                    _ => false,
+                };
+            }
             if let Value::EnumVariant(_, v_name, v_fields) = value {
                 if variant != v_name {
                     return false;
@@ -3179,9 +6121,22 @@ fn pattern_matches(pattern: &Pattern, value: &Value) -> bool {
                     _ => false,
                 }
             } else {
-                false
+                // Natives hand back optional values unboxed, so `Some(x)` also takes any present value
+                match (variant.as_str(), fields) {
+                    ("Some", VariantPatternFields::Tuple(pats)) if pats.len() == 1 => {
+                        !matches!(value, Value::None) && pattern_matches(&pats[0], value)
+                    }
+                    ("None", VariantPatternFields::Unit) => matches!(value, Value::None),
+                    _ => false,
+                }
             }
         }
+        Pattern::Tuple(pats, _) => match value {
+            Value::Tuple(items) => {
+                pats.len() == items.len() && pats.iter().zip(items).all(|(p, v)| pattern_matches(p, v))
+            }
+            _ => false,
+        },
         _ => false,
     }
 }
@@ -3204,6 +6159,10 @@ fn bind_pattern(env: &mut Env, pattern: &Pattern, value: &Value) {
                         }
                     }
                 }
+            } else if let (Value::Result(_, inner) | Value::Some(inner), VariantPatternFields::Tuple(pats)) = (value, fields) {
+                if let [pat] = pats.as_slice() {
+                    bind_pattern(env, pat, inner);
+                }
             } else if let Value::EnumVariant(_, _, v_fields) = value {
                 match fields {
                     VariantPatternFields::Tuple(pats) => {
@@ -3213,12 +6172,51 @@ fn bind_pattern(env: &mut Env, pattern: &Pattern, value: &Value) {
                     }
                     _ => {}
                 }
+            } else if let (true, VariantPatternFields::Tuple(pats)) = (variant == "Some", fields) {
+                if let [pat] = pats.as_slice() {
+                    bind_pattern(env, pat, value);
+                }
+            }
+        }
+        Pattern::Tuple(pats, _) => {
+            if let Value::Tuple(items) = value {
+                for (p, v) in pats.iter().zip(items) {
+                    bind_pattern(env, p, v);
+                }
             }
         }
         _ => {}
     }
 }
 
+/// Whether a `break`/`continue` aimed at `target` stops at a loop labeled
+/// `label`: unlabeled jumps hit the innermost loop, labeled ones keep
+/// propagating until they reach the loop with that name.
+fn targets(target: &Option<String>, label: &Option<String>) -> bool {
+    target.is_none() || target == label
+}
+
+/// A loop's `else:` block runs only when the loop wasn't broken out of. Its
+/// own `break`/`continue`/`return` apply to the enclosing loop or function.
+fn eval_loop_else(env: &mut Env, else_branch: &Option<Block>, broke: bool) -> KainResult<Value> {
+    match else_branch {
+        Some(block) if !broke => match eval_block(env, block)? {
+            flow @ (Value::Return(_) | Value::Break(..) | Value::Continue(_)) => Ok(flow),
+            _ => Ok(Value::Unit),
+        },
+        _ => Ok(Value::Unit),
+    }
+}
+
+/// `let` and `for` bindings must match, unlike a `match` arm there's no fallback
+fn bind_irrefutable(env: &mut Env, pattern: &Pattern, value: &Value) -> KainResult<()> {
+    if !pattern_matches(pattern, value) {
+        return Err(KainError::runtime(format!("Pattern does not match value {}", value)));
+    }
+    bind_pattern(env, pattern, value);
+    Ok(())
+}
+
 fn eval_jsx(env: &mut Env, node: &JSXNode) -> KainResult<Value> {
     match node {
         JSXNode::Element {
@@ -3250,7 +6248,7 @@ fn eval_jsx(env: &mut Env, node: &JSXNode) -> KainResult<Value> {
                     Value::JSX(node) => child_vals.push(node),
                     Value::String(s) => child_vals.push(VNode::Text(s)),
                     Value::Int(n) => child_vals.push(VNode::Text(n.to_string())),
-                    Value::Float(n) => child_vals.push(VNode::Text(n.to_string())),
+                    Value::Float(n) => child_vals.push(VNode::Text(crate::float::format(n))),
                     _ => {}
                 }
             }
@@ -3268,7 +6266,33 @@ fn eval_jsx(env: &mut Env, node: &JSXNode) -> KainResult<Value> {
 }
 
 /// Run all tests in the program
-pub fn run_tests(program: &TypedProgram) -> KainResult<()> {
+fn run_test(env: &mut Env, body: &Block) -> KainResult<Value> {
+    // Isolate test scope
+    env.push_scope();
+    let result = eval_block(env, body);
+    env.pop_scope();
+    result
+}
+
+/// Run a test with its actors under schedule `number`, and say why it failed
+fn run_scheduled_test(env: &mut Env, body: &Block, options: &schedule::ScheduleOptions, number: u64) -> Option<String> {
+    let scheduler = Arc::new(schedule::Scheduler::new(options, number));
+    env.scheduler = Some(scheduler.clone());
+    let result = run_test(env, body);
+    let (failures, waiting) = scheduler.settle(schedule::SETTLE_TIMEOUT);
+    // Actors the test spawned stop here rather than live on into the next schedule
+    scheduler.close();
+    env.scheduler = None;
+    match result {
+        Err(e) => Some(e.to_string()),
+        Ok(_) => failures.into_iter().next().or_else(|| {
+            waiting.then(|| format!("messages were still waiting {:?} after the test ended", schedule::SETTLE_TIMEOUT))
+        }),
+    }
+}
+
+/// Run the program's `test` blocks, each under every schedule of `schedules` if given
+pub fn run_tests(program: &TypedProgram, schedules: Option<&schedule::ScheduleOptions>) -> KainResult<()> {
     println!("\n Running Tests...\n");
     let mut passed = 0;
     let mut failed = 0;
@@ -3290,10 +6314,6 @@ pub fn run_tests(program: &TypedProgram) -> KainResult<()> {
             crate::types::TypedItem::Component(c) => {
                 env.components.insert(c.ast.name.clone(), c.ast.clone());
             }
-            crate::types::TypedItem::Const(c) => {
-                let val = eval_expr(&mut env, &c.ast.value)?;
-                env.define(c.ast.name.clone(), val);
-            }
             crate::types::TypedItem::Impl(i) => {
                 let type_name = match &i.ast.target_type {
                     Type::Named { name, .. } => name.clone(),
@@ -3305,33 +6325,38 @@ pub fn run_tests(program: &TypedProgram) -> KainResult<()> {
                 }
             }
             crate::types::TypedItem::Use(u) => {
-                load_module(&mut env, &u.ast)?;
+                load_module(&mut env, &u.ast, &None)?;
             }
             _ => {}
         }
     }
+    define_globals(&mut env, program)?;
 
     // Run tests
     for item in &program.items {
         if let crate::types::TypedItem::Test(test) = item {
             print!("test {} ... ", test.ast.name);
 
-            // Isolate test scope
-            env.push_scope();
-
-            match eval_block(&mut env, &test.ast.body) {
-                Ok(_) => {
+            let failure = match schedules {
+                None => run_test(&mut env, &test.ast.body).err().map(|e| e.to_string()),
+                Some(options) => options.schedules().find_map(|number| {
+                    run_scheduled_test(&mut env, &test.ast.body, options, number).map(|why| {
+                        let systematic = if options.systematic { " --systematic" } else { "" };
+                        format!("{}\n  Schedule: {} (rerun it with --schedules 1 --schedule-seed {}{})", why, number, number, systematic)
+                    })
+                }),
+            };
+            match failure {
+                None => {
                     println!("ok");
                     passed += 1;
                 }
-                Err(e) => {
+                Some(why) => {
                     println!("FAILED");
-                    println!("  Error: {}", e);
+                    println!("  Error: {}", why);
                     failed += 1;
                 }
             }
-
-            env.pop_scope();
         }
     }
 
@@ -3352,16 +6377,111 @@ pub fn run_tests(program: &TypedProgram) -> KainResult<()> {
 // === ASYNC RUNTIME HELPERS ===
 
 /// Poll a future repeatedly until it returns Ready
+/// The path argument of `snapshot` and `restore`, when one is given
+fn optional_path(native: &str, args: &[Value]) -> KainResult<Option<PathBuf>> {
+    match args {
+        [] => Ok(None),
+        [Value::String(path)] => Ok(Some(PathBuf::from(path))),
+        _ => Err(KainError::runtime(format!("{}: expected an optional path", native))),
+    }
+}
+
+/// `value`, if it is an `expected` or nothing was expected
+fn expect_type(native: &str, value: Value, expected: Option<&String>) -> KainResult<Value> {
+    match expected {
+        Some(ty) if wire::type_name(&value) != ty => Err(KainError::runtime(format!(
+            "{}: expected {}, found {}",
+            native,
+            ty,
+            wire::type_name(&value)
+        ))),
+        _ => Ok(value),
+    }
+}
+
+/// Set the actor's `persist state` from its snapshot, or the one at `path`.
+/// False, changing nothing, when the snapshot does not exist.
+fn restore_snapshot(env: &mut Env, path: Option<PathBuf>) -> KainResult<bool> {
+    let snapshot = env
+        .snapshot
+        .clone()
+        .ok_or_else(|| KainError::runtime("restore: only actors with `persist state` have snapshots"))?;
+    let path = path.unwrap_or(snapshot.path);
+    let bytes = match std::fs::read(&path) {
+        Ok(bytes) => bytes,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(false),
+        Err(e) => return Err(KainError::runtime(format!("restore: cannot read {}: {}", path.display(), e))),
+    };
+    let not_a_snapshot = |e: String| KainError::runtime(format!("restore: {} is not a snapshot: {}", path.display(), e));
+    let state: HashMap<String, Value> = if wire::is_encoded(&bytes) {
+        match wire::decode(&bytes).map_err(not_a_snapshot)? {
+            Value::Struct(_, fields) => fields.read().unwrap().clone(),
+            other => return Err(not_a_snapshot(format!("found {}", wire::type_name(&other)))),
+        }
+    } else {
+        // Snapshots used to be JSON objects
+        let json: serde_json::Value = serde_json::from_slice(&bytes).map_err(|e| not_a_snapshot(e.to_string()))?;
+        match json_to_value(&json) {
+            Value::Struct(_, fields) => fields.read().unwrap().clone(),
+            _ => return Err(not_a_snapshot("expected a JSON object".to_string())),
+        }
+    };
+    for (field, ty) in &snapshot.fields {
+        // Fields added since the snapshot was taken keep their initial value
+        let Some(saved) = state.get(field).cloned() else { continue };
+        let value = match (saved, ty) {
+            // JSON does not record struct names, so take it from the declaration
+            (Value::Struct(name, fields), Type::Named { name: declared, .. }) if name == "Json" => {
+                Value::Struct(declared.clone(), fields)
+            }
+            (value, _) => value,
+        };
+        env.assign(field, value)?;
+    }
+    Ok(true)
+}
+
+const TIMED_OUT: &str = "Async task timed out";
+
+/// `token` if it is a `CancelToken`
+fn cancel_token_arg(native: &str, token: &Value) -> KainResult<Value> {
+    match token {
+        Value::Struct(name, _) if name == "CancelToken" => Ok(token.clone()),
+        other => Err(KainError::runtime(format!("{}: expected a CancelToken, found {}", native, other))),
+    }
+}
+
+/// Run `future` to completion as a task that also stops when `token` is
+/// cancelled or `timeout` passes. Tasks it spawns inherit both.
+fn run_task(env: &mut Env, future: Value, token: Option<Value>, timeout: Option<Duration>) -> KainResult<Value> {
+    let outer = env.task.clone();
+    env.task.tokens.extend(token);
+    if let Some(timeout) = timeout {
+        let deadline = Instant::now() + timeout;
+        env.task.deadline = Some(env.task.deadline.map_or(deadline, |outer| outer.min(deadline)));
+    }
+    let result = poll_future_to_completion(env, future);
+    env.task = outer;
+    result
+}
+
 fn poll_future_to_completion(env: &mut Env, future_val: Value) -> KainResult<Value> {
-    let max_iterations = 100000; // Prevent infinite loops
+    let max_iterations = 100000; // Prevent infinite loops in tasks nothing can stop
     let mut iterations = 0;
     let current_future = future_val;
 
     loop {
         iterations += 1;
-        if iterations > max_iterations {
+        if iterations > max_iterations && env.task.unbounded() {
             return Err(KainError::runtime("Async timeout: future did not complete"));
         }
+        if env.task.cancelled() {
+            return Err(KainError::runtime("Async task cancelled"));
+        }
+        if env.task.timed_out() {
+            return Err(KainError::runtime(TIMED_OUT));
+        }
+        env.time_left()?;
 
         let poll_result = poll_future_once(env, current_future.clone())?;
 
@@ -3400,7 +6520,7 @@ fn poll_future_once(env: &mut Env, future_val: Value) -> KainResult<Value> {
                     env.define(first_param.name.clone(), struct_val);
                 }
 
-                let result = eval_block(env, &poll_fn.body)?;
+                let result = eval_body(env, &poll_fn_name, &poll_fn)?;
                 env.pop_scope();
 
                 // Unwrap Value::Return if present
@@ -3430,7 +6550,7 @@ fn poll_future_once(env: &mut Env, future_val: Value) -> KainResult<Value> {
                     env.define(first_param.name.clone(), future_val.clone());
                 }
 
-                let result = eval_block(env, &poll_fn.body)?;
+                let result = eval_body(env, &poll_fn_name, &poll_fn)?;
                 env.pop_scope();
 
                 // Unwrap Value::Return if present
@@ -3535,3 +6655,411 @@ fn normalize_poll_result(val: Value) -> Value {
         PollState::NotAPoll => val, // Keep as-is
     }
 }
+
+/// Replaces each `splice(expr)` in quoted code with the value of `expr`
+struct Splicer<'a> {
+    env: &'a mut Env,
+    error: Option<KainError>,
+}
+
+impl VisitorMut for Splicer<'_> {
+    fn visit_expr_mut(&mut self, expr: &mut Expr) {
+        match expr {
+            _ if self.error.is_some() => {}
+            // Splices in a nested quote belong to that quote
+            Expr::MacroCall { name, .. } if name == "quote" => {}
+            Expr::Call { callee, args, span } if matches!(&**callee, Expr::Ident(n, _) if n == "splice") => {
+                let [arg] = args.as_slice() else {
+                    self.error = Some(KainError::runtime("splice: expected exactly one argument"));
+                    return;
+                };
+                let span = *span;
+                match eval_expr(self.env, &arg.value) {
+                    Ok(value) => match value_to_expr(value, span) {
+                        Ok(value) => *expr = value,
+                        Err(e) => self.error = Some(e),
+                    },
+                    Err(e) => self.error = Some(e),
+                }
+            }
+            _ => walk_expr_mut(self, expr),
+        }
+    }
+}
+
+/// Turn a value back into source: literals for scalars, the code itself for a
+/// quote. Values with no literal form (closures, actors, stepped ranges) are an
+/// error at the comptime site rather than a placeholder the program computes with.
+pub fn value_to_expr(val: Value, span: Span) -> KainResult<Expr> {
+    let all = |values: Vec<Value>| values.into_iter().map(|v| value_to_expr(v, span)).collect::<KainResult<Vec<_>>>();
+    let call = |name: &str, args: Vec<Expr>| Expr::Call {
+        callee: Box::new(Expr::Ident(name.to_string(), span)),
+        args: args.into_iter().map(|value| CallArg { name: None, value, spread: false, span }).collect(),
+        span,
+    };
+    Ok(match val {
+        Value::Int(n) => Expr::Int(n, span),
+        Value::Float(n) => Expr::Float(n, span),
+        Value::Bool(b) => Expr::Bool(b, span),
+        Value::String(s) => Expr::String(s, span),
+        Value::Char(c) => Expr::Char(c, span),
+        Value::Unit => Expr::Block(Block { stmts: vec![], span }, span), // Unit is empty block?
+        // `return` ends a comptime block with its value
+        Value::Return(inner) => value_to_expr(*inner, span)?,
+        Value::Array(items) => {
+            let items = items.read().unwrap_or_else(|e| e.into_inner()).clone();
+            Expr::Array(all(items)?, span)
+        }
+        Value::Tuple(items) => Expr::Tuple(all(items)?, span),
+        Value::Range(r) if r.step == 1 => Expr::Range {
+            start: Some(Box::new(Expr::Int(r.start, span))),
+            end: Some(Box::new(Expr::Int(r.end, span))),
+            inclusive: false,
+            span,
+        },
+        Value::Struct(name, fields) => {
+            let fields = fields.read().unwrap_or_else(|e| e.into_inner());
+            let mut fields = fields
+                .iter()
+                .map(|(k, v)| Ok((k.clone(), value_to_expr(v.clone(), span)?)))
+                .collect::<KainResult<Vec<_>>>()?;
+            fields.sort_by(|a, b| a.0.cmp(&b.0));
+            Expr::Struct { name, fields, base: None, span }
+        }
+        // Variant fields are kept by position, which the tuple form writes back
+        Value::EnumVariant(enum_name, variant, fields) => {
+            let fields = if fields.is_empty() { EnumVariantFields::Unit } else { EnumVariantFields::Tuple(all(fields)?) };
+            Expr::EnumVariant { enum_name, variant, fields, span }
+        }
+        Value::None => Expr::None(span),
+        Value::Some(inner) => call("Some", vec![value_to_expr(*inner, span)?]),
+        Value::Result(ok, inner) => call(if ok { "Ok" } else { "Err" }, vec![value_to_expr(*inner, span)?]),
+        Value::Function(name) => Expr::Ident(name, span),
+        Value::Quote(code) => match code.stmts.as_slice() {
+            [Stmt::Expr(e)] => e.clone(),
+            _ => Expr::Block((*code).clone(), span),
+        },
+        _ => {
+            return Err(KainError::type_error(
+                format!("comptime produced `{}`, which has no literal form to compile into the program", val),
+                span,
+            ))
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{compile, compile_with_options, eval_snippet, runtime, vfs, CompileOptions, CompileTarget, KainError};
+
+    #[test]
+    fn test_interpreter_resource_limits() {
+        let run = |source: &str, limits: runtime::InterpretOptions| {
+            let options = CompileOptions { limits, ..Default::default() };
+            compile_with_options(source, CompileTarget::Interpret, &options)
+        };
+        let limit_hit = |result: Result<Vec<u8>, KainError>| {
+            result.unwrap_err().to_string().contains("Resource limit exceeded")
+        };
+
+        let spin = "fn main():\n    while true:\n        let x = 1\n";
+        assert!(limit_hit(run(spin, runtime::InterpretOptions { max_steps: Some(10_000), ..Default::default() })));
+
+        let recurse = "fn f(n: Int) -> Int:\n    return f(n + 1)\n\nfn main():\n    f(0)\n";
+        assert!(limit_hit(run(recurse, runtime::InterpretOptions { max_call_depth: Some(50), ..Default::default() })));
+
+        let grow = "fn main():\n    var s = \"x\"\n    while true:\n        s = s + s\n";
+        assert!(limit_hit(run(grow, runtime::InterpretOptions { max_heap: Some(1 << 20), ..Default::default() })));
+
+        let fine = "fn main():\n    let x = 1 + 2\n";
+        assert!(run(fine, runtime::InterpretOptions { max_steps: Some(100), max_call_depth: Some(4), max_heap: Some(1024), timeout: None }).is_ok());
+    }
+
+    #[test]
+    fn test_regions_free_their_allocations_on_exit() {
+        let point = "struct P:\n    x: Int\n    y: Int\n\n";
+        let limits = runtime::InterpretOptions { max_heap: Some(16 * 1024), ..Default::default() };
+        let options = CompileOptions { limits, ..Default::default() };
+        let leaky = format!("{}fn main():\n    for i in 0..1000:\n        let p = P {{ x: i, y: i }}\n", point);
+        let err = compile_with_options(&leaky, CompileTarget::Interpret, &options).unwrap_err();
+        assert!(err.to_string().contains("Resource limit exceeded"), "{}", err);
+        let bounded = format!("{}fn main():\n    var total = 0\n    for i in 0..1000:\n        region:\n            let p = P {{ x: i, y: i }}\n            total = total + p.y\n    println(total)\n", point);
+        let result = eval_snippet(&bounded, &options);
+        assert!(result.diagnostics.is_empty(), "{:?}", result.diagnostics);
+        assert_eq!(result.stdout.trim_end(), "499500");
+
+        // A value kept past the region's exit would point into freed memory, on every target
+        let escaping = format!("{}fn main():\n    var last = P {{ x: 0, y: 0 }}\n    for i in 0..1000:\n        region:\n            let p = P {{ x: i, y: i }}\n            last = p\n    println(last.y)\n", point);
+        let result = eval_snippet(&escaping, &options);
+        assert!(result.diagnostics.iter().any(|d| d.to_string().contains("escapes into `last`")), "{:?}", result.diagnostics);
+        for target in [CompileTarget::Wasm, CompileTarget::Js] {
+            let err = compile(&escaping, target).unwrap_err();
+            assert!(err.to_string().contains("value allocated in a region escapes into `last`"), "{}", err);
+        }
+        let source = format!("{}fn main():\n    region:\n        let p = P {{ x: 1, y: 2 }}\n        println(p.x + p.y)\n", point);
+        let js = String::from_utf8(compile(&source, CompileTarget::Js).unwrap()).unwrap();
+        assert!(js.contains("new P(1, 2)"));
+    }
+
+    #[test]
+    fn test_dbg_macro_prints_expression_and_location() {
+        let source = "fn main() -> Int:\n    let x = 3\n    let y = dbg!(x * 2) + 1\n    let pair = dbg!(x, \"s\")\n    dbg!()\n    return y\n";
+        let result = eval_snippet(source, &CompileOptions::default());
+        assert!(result.diagnostics.is_empty(), "{:?}", result.diagnostics);
+        assert_eq!(result.stdout, "<snippet>:3: x * 2 = 6\n<snippet>:4: x = 3\n<snippet>:4: \"s\" = s\n<snippet>:5\n");
+        assert!(matches!(result.value, Some(runtime::Value::Int(7))));
+    }
+
+    #[test]
+    fn test_sqlite_binds_params_and_tracks_db_effect() {
+        let source = "fn scores(conn) -> Array<Score> with IO, Db:\n    return sqlite_query(conn, \"SELECT name, points FROM scores WHERE points > ? ORDER BY points\", [1], \"Score\")\n\nfn main():\n    let conn = sqlite_open(\":memory:\")\n    sqlite_exec(conn, \"CREATE TABLE scores (name TEXT, points INTEGER)\")\n    println(str(sqlite_exec(conn, \"INSERT INTO scores VALUES (?, ?), (?, ?)\", [\"ada\", 3, \"bob\", 1])))\n    for s in scores(conn):\n        println(s.name + \"=\" + str(s.points))\n";
+        let result = eval_snippet(source, &CompileOptions::default());
+        assert!(result.diagnostics.is_empty(), "{:?}", result.diagnostics);
+        let lines: Vec<&str> = result.stdout.lines().map(str::trim).collect();
+        assert_eq!(lines, ["2", "ada=3"]);
+
+        let missing = eval_snippet("fn scores(conn) with IO:\n    return sqlite_query(conn, \"SELECT 1\")\n\nfn main():\n    return\n", &CompileOptions::default());
+        assert!(missing.diagnostics[0].to_string().contains("does not declare the Db effect"));
+
+        // Actors share the connections of the program that spawned them
+        let actor = "actor Writer:\n    on record(conn: SqliteConnection, name: String):\n        sqlite_exec(conn, \"INSERT INTO log VALUES (?)\", [name])\n\nfn main() with IO, Db:\n    let conn = sqlite_open(\":memory:\")\n    sqlite_exec(conn, \"CREATE TABLE log (name TEXT)\")\n    let writer = spawn Writer()\n    send writer.record(conn=conn, name=\"from actor\")\n    sleep(200)\n    for r in sqlite_query(conn, \"SELECT name FROM log\"):\n        println(r.name)\n";
+        let result = eval_snippet(actor, &CompileOptions::default());
+        assert!(result.diagnostics.is_empty(), "{:?}", result.diagnostics);
+        assert_eq!(result.stdout.trim(), "from actor");
+    }
+
+    #[test]
+    fn test_math_natives() {
+        let source = "fn main():\n    println(floor(2.7), round(-2.5), trunc(3), pow(2, 10))\n    println(atan2(1, 1) * 4.0 == PI, ln(E), log10(1000.0), TAU > 6.28)\n    println(is_nan(sqrt(-1.0)), is_inf(1.0 / 0.0), is_nan(1), clamp(15, 0, 10), clamp(-0.5, 0, 1))\n";
+        let result = eval_snippet(source, &CompileOptions::default());
+        assert!(result.diagnostics.is_empty(), "{:?}", result.diagnostics);
+        let lines: Vec<String> = result.stdout.lines().map(|l| l.split_whitespace().collect::<Vec<_>>().join(" ")).collect();
+        assert_eq!(lines, ["2 -3 3 1024", "true 1 3 true", "true true false 10 0"]);
+
+        let overflow = eval_snippet("fn main():\n    println(pow(10, 40))\n", &CompileOptions::default());
+        assert!(overflow.diagnostics[0].to_string().contains("pow: integer overflow"));
+    }
+
+    #[test]
+    fn test_sort_search_and_group() {
+        let source = "fn main():\n    let words = [\"pear\", \"fig\", \"apple\", \"kiwi\"]\n    println(sort([3, 1.5, 2, -1]), sort_by(words, |a, b| len(a) - len(b)))\n    println(binary_search([1, 3, 5], 5), binary_search([1, 3, 5], 4), unique([3, 1, 3, 2, 1]))\n    println(min_by(words, |a, b| len(a) - len(b)), max_by(words, |a, b| len(a) - len(b)), min_by([], |a, b| 0))\n    println(group_by(words, |w| len(w)))\n";
+        let result = eval_snippet(source, &CompileOptions::default());
+        assert!(result.diagnostics.is_empty(), "{:?}", result.diagnostics);
+        let lines: Vec<&str> = result.stdout.lines().map(str::trim).collect();
+        assert_eq!(lines, [
+            "[-1, 1.5, 2, 3] [fig, pear, kiwi, apple]",
+            "Ok(2) Err(2) [3, 1, 2]",
+            "fig apple none",
+            "[(4, [pear, kiwi]), (3, [fig]), (5, [apple])]",
+        ]);
+
+        let mixed = eval_snippet("fn main():\n    println(sort([1, \"a\"]))\n", &CompileOptions::default());
+        assert!(mixed.diagnostics[0].to_string().contains("cannot compare"));
+
+        // A comparator that is no order at all still gives a permutation, and its errors stop the sort
+        let rock_paper_scissors = "fn beats(a: Int, b: Int) -> Int:\n    if (a + 1) % 3 == b % 3:\n        return 1\n    if a % 3 == b % 3:\n        return 0\n    return -1\n\nfn main():\n    let xs = sort_by(range(0, 50), |a, b| beats(a, b))\n    println(len(xs), sum(xs))\n    println(len(sort_by(range(0, 50), |a, b| 1)))\n";
+        let result = eval_snippet(rock_paper_scissors, &CompileOptions::default());
+        assert!(result.diagnostics.is_empty(), "{:?}", result.diagnostics);
+        let lines: Vec<&str> = result.stdout.lines().map(str::trim).collect();
+        assert_eq!(lines, ["50 1225", "50"]);
+        let failing = eval_snippet("fn main():\n    println(sort_by([3, 1, 2], |a, b| \"no\"))\n", &CompileOptions::default());
+        assert!(failing.diagnostics[0].to_string().contains("comparator must return an Int"), "{:?}", failing.diagnostics);
+    }
+
+    #[test]
+    fn test_array_editing() {
+        let source = "fn main():\n    let xs = [1, 2, 3]\n    insert(xs, 1, 9)\n    println(remove(xs, 0), pop(xs), xs, pop([]))\n    println(slice([1, 2, 3, 4], 1, 3), slice([1, 2, 3], 1), concat([1], [2, 3]), flatten([[1, 2], [3], 4]))\n    println(zip([1, 2, 3], [\"a\", \"b\"]), index_of([\"a\", \"b\"], \"b\"), index_of([1], 5))\n";
+        let result = eval_snippet(source, &CompileOptions::default());
+        assert!(result.diagnostics.is_empty(), "{:?}", result.diagnostics);
+        let lines: Vec<&str> = result.stdout.lines().map(str::trim).collect();
+        assert_eq!(lines, ["1 3 [9, 2] none", "[2, 3] [2, 3] [1, 2, 3] [1, 2, 3, 4]", "[(1, a), (2, b)] 1 none"]);
+
+        let out_of_bounds = eval_snippet("fn main():\n    println(remove([1], 1))\n", &CompileOptions::default());
+        assert!(out_of_bounds.diagnostics[0].to_string().contains("remove: index 1 out of bounds for length 1"));
+    }
+
+    #[test]
+    fn test_tuple_fields_and_patterns() {
+        let source = "fn main():\n    let p = (1, \"a\")\n    let nested = ((1, 2), (3, (4, 5)))\n    println(p.0, p.1, nested.0.1, nested.1.1.0)\n    let (a, b) = p\n    println(a, b)\n    match nested:\n        ((x, _), (y, (_, z))) => println(x, y, z)\n    for (k, v) in [(1, \"x\"), (2, \"y\")]:\n        println(k, v)\n";
+        let result = eval_snippet(source, &CompileOptions::default());
+        assert!(result.diagnostics.is_empty(), "{:?}", result.diagnostics);
+        let lines: Vec<&str> = result.stdout.lines().map(str::trim).collect();
+        assert_eq!(lines, ["1 a 2 4", "1 a", "1 3 5", "1 x", "2 y"]);
+
+        let out_of_range = eval_snippet("fn main():\n    println((1, 2).2)\n", &CompileOptions::default());
+        assert!(out_of_range.diagnostics[0].to_string().contains("No field 2 on a tuple of 2 elements"));
+    }
+
+    #[test]
+    fn test_strings_index_by_char() {
+        let source = "fn main():\n    let s = \"héllo 👋🏽\"\n    println(len(s), char_len(s), byte_len(s), s[1], char_at(s, 7))\n    println(substring(s, 1, 4), substring(s, 6), substring(s, 4, 2) == \"\", len(graphemes(s)))\n";
+        let result = eval_snippet(source, &CompileOptions::default());
+        assert!(result.diagnostics.is_empty(), "{:?}", result.diagnostics);
+        let lines: Vec<&str> = result.stdout.lines().map(str::trim).collect();
+        assert_eq!(lines, ["8 8 15 é 🏽", "éll 👋🏽 true 7"]);
+    }
+
+    #[test]
+    fn test_impl_blocks_extend_builtin_types() {
+        let source = "impl Array<T>:\n    fn second(self) -> T:\n        return self[1]\n\nimpl String:\n    fn shout(self) -> String:\n        return upper(self) + \"!\"\n\nfn main():\n    let xs = [1, 2, 3]\n    let s = \"hi\"\n    println(xs.second(), xs.len(), s.shout(), \"yo\".shout())\n";
+        let result = eval_snippet(source, &CompileOptions::default());
+        assert!(result.diagnostics.is_empty(), "{:?}", result.diagnostics);
+        assert_eq!(result.stdout.trim(), "2 3 HI! YO!");
+    }
+
+    #[test]
+    fn test_builtins_and_methods_as_values() {
+        let source = "struct Point:\n    x: Float\n    y: Float\n\nimpl Point:\n    fn length(self) -> Float:\n        return sqrt(self.x * self.x + self.y * self.y)\n\n    fn scale(self, k: Float) -> Point:\n        return Point { x: self.x * k, y: self.y * k }\n\nfn main():\n    let p = Point { x: 3.0, y: 4.0 }\n    let grow = p.scale\n    println(map([4.0, 9.0], sqrt), map([p], Point::length), grow(2.0).length())\n    println(map([p], Point::scale))\n";
+        let result = eval_snippet(source, &CompileOptions::default());
+        assert_eq!(result.stdout.lines().next().map(str::trim), Some("[2, 3] [5] 10"));
+        assert!(result.diagnostics[0].to_string().contains("Point_scale expects 2 arguments, found 1"), "{:?}", result.diagnostics);
+    }
+
+    #[test]
+    fn test_ranges_are_values() {
+        let source = "fn sign(n: Int) -> String:\n    return match n:\n        ..0 => \"neg\"\n        0 => \"zero\"\n        1..=9 => \"digit\"\n        10.. => \"big\"\n        _ => \"?\"\n\nfn main():\n    let evens = (0..10).step(2)\n    println(evens, evens.to_array(), evens.contains(4), evens.contains(5), evens.len())\n    println((0..10).step(3).rev().to_array(), (10..0).rev().len(), (1..=3).to_array())\n    var total = 0\n    for i in (1..=100).rev():\n        total = total + i\n    println(total, sign(-4), sign(0), sign(7), sign(10))\n";
+        let result = eval_snippet(source, &CompileOptions::default());
+        assert!(result.diagnostics.is_empty(), "{:?}", result.diagnostics);
+        assert_eq!(
+            result.stdout.trim(),
+            "0..10 step 2 [0, 2, 4, 6, 8] true false 5 \n[9, 6, 3, 0] 0 [1, 2, 3] \n5050 neg zero digit big"
+        );
+
+        let result = eval_snippet("fn main():\n    println((0..5).step(0))\n", &CompileOptions::default());
+        assert!(result.diagnostics[0].to_string().contains("step must be positive, found 0"));
+
+        // JS gets the range class once, after the header
+        let js = compile("fn main():\n    let a = 0..3\n    let b = 1..=4\n    println(a.len(), b.len())\n", CompileTarget::Js).unwrap();
+        let js = String::from_utf8(js).unwrap();
+        assert!(js.starts_with("// Generated by KAIN compiler\n// Target: JavaScript (ES6+)\n\nclass KainRange {"), "{}", js);
+        assert_eq!(js.matches("class KainRange").count(), 1);
+    }
+
+    #[test]
+    fn test_chars_are_values() {
+        let source = "fn kind(c: Char) -> String:\n    return match c:\n        '0'..='9' => \"digit\"\n        'a'..='z' => \"lower\"\n        'A'..='Z' => \"upper\"\n        ' ' => \"space\"\n        _ => \"other\"\n\nfn main():\n    let c = 'a'\n    println(c, c + 1, 'z' - c, c < 'b', int(c), char(98), c.to_upper(), ord('\\n'), '\\'')\n    println(kind('7'), kind(c), kind('Q'), kind(' '), kind(char_at(\"x!\", 1)), char_at(\"abc\", 0) == c)\n    println(\"ab\" + 'c', type_of(c), c.is_alpha(), '5'.is_digit(), '5' - '0')\n";
+        let result = eval_snippet(source, &CompileOptions::default());
+        assert!(result.diagnostics.is_empty(), "{:?}", result.diagnostics);
+        assert_eq!(
+            result.stdout.trim(),
+            "a b 25 true 97 b A 10 ' \ndigit lower upper space other true \nabc char true true 5"
+        );
+
+        let result = eval_snippet("fn main():\n    println('ab')\n", &CompileOptions::default());
+        assert!(result.diagnostics[0].to_string().contains("A char literal holds exactly one character"));
+        let result = eval_snippet("fn main():\n    println(char(-1))\n", &CompileOptions::default());
+        assert!(result.diagnostics[0].to_string().contains("char: -1 is not a code point"));
+    }
+
+    #[test]
+    fn test_effect_handlers_stand_in_for_operations() {
+        let source = "effect Clock\n\nfn now() -> Int with Clock:\n    return 1700000000\n\nfn stamp() -> Int with Clock:\n    return now()\n\nstruct FixedClock:\n    time: Int\n\nimpl FixedClock:\n    fn now(self) -> Int:\n        return self.time\n\nstruct Tagged:\n    tag: String\n\nimpl Tagged:\n    fn println(self, text: String):\n        println(self.tag + text)\n\nfn main():\n    println(str(stamp()))\n    with handler FixedClock { time: 42 } for Clock:\n        println(str(stamp()))\n        with handler Tagged { tag: \"[mock] \" } for IO:\n            println(str(stamp()))\n    println(\"done\")\n";
+        let result = eval_snippet(source, &CompileOptions::default());
+        assert!(result.diagnostics.is_empty(), "{:?}", result.diagnostics);
+        let lines: Vec<&str> = result.stdout.lines().map(str::trim_end).collect();
+        assert_eq!(lines, vec!["1700000000", "42", "[mock] 42", "done"]);
+
+        // A function that does not declare Clock may still call into it under a handler
+        let handled = source.replace("fn stamp() -> Int with Clock:", "fn stamp() -> Int with Pure:\n    with handler FixedClock { time: 7 } for Clock:\n        return now()\n\nfn unused() -> Int with Clock:");
+        let result = eval_snippet(&handled, &CompileOptions::default());
+        assert!(result.diagnostics.is_empty(), "{:?}", result.diagnostics);
+        let err = compile(source, CompileTarget::Js).unwrap_err();
+        assert!(err.to_string().contains("effect handlers are not supported by the js target"), "{}", err);
+    }
+
+    #[test]
+    fn test_runtime_stats_report_actors_and_allocations() {
+        let source = "actor Sink:\n    on ping(n: Int):\n        sleep(1000)\n\nfn main():\n    let keep = [1, 2, 3]\n    let s = spawn Sink()\n    for i in 0..10001:\n        send s.ping(n = i)\n    let stats = runtime_stats()\n    println(len(stats.actors), stats.actors[0].name, stats.actors[0].mailbox > 9000)\n    println(stats.live_values > 3, stats.allocations[0].0)\n";
+        let result = eval_snippet(source, &CompileOptions::default());
+        assert!(result.diagnostics.is_empty(), "{:?}", result.diagnostics);
+        assert!(result.stderr.starts_with("warning: Sink#1 has 10000 unhandled messages"), "{}", result.stderr);
+        let lines: Vec<&str> = result.stdout.lines().map(str::trim_end).collect();
+        assert_eq!(lines, ["1 Sink true", "true main"]);
+    }
+
+    #[test]
+    fn test_trace_records_calls_statements_and_values() {
+        let source = "fn add(a: Int, b: Int) -> Int:\n    return a + b\n\nfn main():\n    let x = add(1, 2)\n    println(x)\n";
+        vfs::SourceMap::global().write().unwrap().add("trace_demo.kn", source);
+        let trace = |mode| {
+            let file = std::env::temp_dir().join(format!("kain-trace-{}-{:?}.log", std::process::id(), mode));
+            let options = CompileOptions {
+                trace: Some(runtime::TraceOptions { mode, file: Some(file.clone()), source: Some("trace_demo.kn".into()) }),
+                ..Default::default()
+            };
+            let result = eval_snippet(source, &options);
+            assert!(result.diagnostics.is_empty(), "{:?}", result.diagnostics);
+            assert_eq!(result.stdout.trim_end(), "3");
+            let log = std::fs::read_to_string(&file).unwrap();
+            std::fs::remove_file(&file).ok();
+            log
+        };
+
+        let calls = trace(runtime::TraceMode::Calls);
+        assert!(calls.contains("call add(1, 2)\n"), "{}", calls);
+        assert!(!calls.contains("trace_demo.kn") && !calls.contains("returned"), "{}", calls);
+
+        let values = trace(runtime::TraceMode::Values);
+        let lines: Vec<&str> = values.lines().collect();
+        let indent = |line: &str| line.len() - line.trim_start().len();
+        let stmt = lines.iter().position(|l| l.trim_start() == "trace_demo.kn:5:5: let x = add(1, 2)").unwrap_or_else(|| panic!("{}", values));
+        let call = lines.iter().position(|l| l.trim_start() == "call add(1, 2)").unwrap_or_else(|| panic!("{}", values));
+        assert!(stmt < call && indent(lines[stmt]) == indent(lines[call]), "{}", values);
+        assert!(lines[call + 1].trim_start() == "trace_demo.kn:2:5: return a + b" && indent(lines[call + 1]) > indent(lines[call]), "{}", values);
+        assert!(lines.iter().any(|l| l.trim_start() == "add returned 3"), "{}", values);
+        assert!(lines.iter().any(|l| l.trim_start() == "= 3"), "{}", values);
+    }
+
+    #[test]
+    fn test_actor_handler_errors_go_to_stderr() {
+        let source = "actor Worker:\n    on fail():\n        panic(\"boom\")\n\nfn main():\n    let w = spawn Worker()\n    send w.fail()\n    sleep(200)\n    println(\"done\")\n";
+        let result = eval_snippet(source, &CompileOptions::default());
+        assert_eq!(result.stdout, "done\n");
+        assert!(result.stderr.contains("Error in actor handler fail: ") && result.stderr.contains("boom"), "{}", result.stderr);
+    }
+
+    #[test]
+    fn test_bounded_mailboxes_apply_their_policy() {
+        let source = "actor Slow:\n    mailbox(capacity = 2, policy = DropOldest)\n    on work(n: Int):\n        sleep(1000)\n\nfn main():\n    let s = spawn Slow()\n    for i in 0..10:\n        send s.work(n = i)\n    println(mailbox_len(s) <= 2)\n";
+        let result = eval_snippet(source, &CompileOptions::default());
+        assert!(result.diagnostics.is_empty(), "{:?}", result.diagnostics);
+        assert_eq!(result.stdout.trim_end(), "true");
+
+        let failing = eval_snippet(&source.replace("DropOldest", "Fail"), &CompileOptions::default());
+        let err = failing.diagnostics.first().map(|d| d.to_string()).unwrap_or_default();
+        assert!(err.contains("mailbox of Slow#1 is full"), "{:?}", failing.diagnostics);
+
+        let err = compile(source, CompileTarget::Wasm).unwrap_err();
+        assert!(err.to_string().contains("bounded mailboxes are not supported by the wasm target"), "{}", err);
+        let err = compile(&source.replace("DropOldest", "Never"), CompileTarget::Interpret).unwrap_err();
+        assert!(err.to_string().contains("Unknown mailbox policy `Never`"), "{}", err);
+    }
+
+    #[test]
+    fn test_persisted_actor_state_survives_a_restart() {
+        let path = std::env::temp_dir().join(format!("kain-snapshot-{}.json", std::process::id()));
+        let actor = format!(
+            "actor Counter:\n    persist state count: Int = 0\n    on add(n: Int):\n        count = count + n\n        snapshot(\"{path}\")\n    on load():\n        println(restore(\"{path}\"), count)\n\n",
+            path = path.display()
+        );
+        let first = eval_snippet(&format!("{}fn main():\n    let c = spawn Counter()\n    send c.add(n = 2)\n    send c.add(n = 3)\n    sleep(200)\n", actor), &CompileOptions::default());
+        assert!(first.diagnostics.is_empty() && first.stdout.is_empty(), "{:?} {}", first.diagnostics, first.stdout);
+        let second = eval_snippet(&format!("{}fn main():\n    let c = spawn Counter()\n    send c.load()\n    sleep(200)\n", actor), &CompileOptions::default());
+        assert!(second.diagnostics.is_empty(), "{:?}", second.diagnostics);
+        assert_eq!(second.stdout.trim_end(), "true 5");
+        let _ = std::fs::remove_file(&path);
+
+        let err = compile(&actor, CompileTarget::Wasm).unwrap_err();
+        assert!(err.to_string().contains("persisted actor states are not supported by the wasm target"), "{}", err);
+    }
+
+    #[test]
+    fn test_tasks_stop_on_timeout_and_cancellation() {
+        let source = "struct Spinner:\n    left: Int\n    token: Any\n\nfn Spinner_poll(s: Spinner):\n    if s.left == 0:\n        return (\"Ready\", \"done\")\n    if s.left == 2:\n        cancel(s.token)\n    s.left = s.left - 1\n    return (\"Pending\", 0)\n\nfn main():\n    let token = cancel_token()\n    println(block_on(Spinner { left: 3, token: cancel_token() }, token))\n    match with_timeout(Spinner { left: -1, token: token }, 20):\n        Ok(v) => println(v)\n        Err(e) => println(e)\n    println(is_cancelled(token))\n    spawn_task(Spinner { left: 5, token: token }, token)\n    println(\"unreachable\")\n";
+        let result = eval_snippet(source, &CompileOptions::default());
+        let lines: Vec<&str> = result.stdout.lines().map(str::trim_end).collect();
+        assert_eq!(lines, vec!["done", "timed out after 20 ms", "false"]);
+        let err = result.diagnostics.first().map(|d| d.to_string()).unwrap_or_default();
+        assert!(err.contains("Async task cancelled"), "{:?}", result.diagnostics);
+    }
+}
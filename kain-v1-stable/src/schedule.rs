@@ -0,0 +1,223 @@
+//! Actor scheduling for concurrency tests
+//!
+//! Interpreted actors run on their own threads, so the order they handle
+//! messages in is up to the OS and a test passes or fails with it.
+//! `kain test --schedules N` runs each test under N schedules.
+//!
+//! A [`Scheduler`] runs one thing at a time: the test itself, or a handler.
+//! The test runs until it sleeps or ends; then the actors handle what was
+//! sent, one message after another, until nothing is waiting, and the sleep
+//! returns at once. Which waiting message goes next is the scheduler's
+//! choice, keeping only each sender's messages to an actor in the order they
+//! were sent. Choices come from a seeded generator, or with `--systematic`
+//! schedule `k` takes the `k`th combination of choices, so schedule 0 handles
+//! everything in the order it was sent.
+//!
+//! A schedule fails when the test fails, a handler fails, or messages are
+//! still waiting [`SETTLE_TIMEOUT`] after the test ends. Its number is the
+//! one to pass to `--schedule-seed` with `--schedules 1` to run it again.
+
+use std::sync::{Condvar, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+use crate::replay::Origin;
+
+/// How long a test's actors may take to handle what is left when it ends
+pub const SETTLE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// `kain test --schedules`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ScheduleOptions {
+    /// Schedules each test runs under
+    pub count: u64,
+    /// Number of the first schedule; each next one adds 1
+    pub seed: u64,
+    /// Enumerate choices instead of drawing them at random
+    pub systematic: bool,
+}
+
+impl ScheduleOptions {
+    /// The numbers of the schedules to run
+    pub fn schedules(&self) -> impl Iterator<Item = u64> {
+        let seed = self.seed;
+        (0..self.count).map(move |k| seed.wrapping_add(k))
+    }
+}
+
+/// Picks which actor handles a message next, one handler at a time
+pub struct Scheduler {
+    state: Mutex<State>,
+    turn: Condvar,
+}
+
+struct State {
+    choose: Chooser,
+    /// Messages sent and not yet handed to their actor, oldest first
+    pending: Vec<(u64, Origin)>,
+    /// The message the scheduler picked, until its actor takes it
+    granted: Option<(u64, Origin)>,
+    /// Actor whose handler is running
+    running: Option<u64>,
+    /// Whether the test is running, which holds the actors back
+    program: bool,
+    /// Handlers that failed
+    failures: Vec<String>,
+    closed: bool,
+}
+
+enum Chooser {
+    /// SplitMix64 state
+    Random(u64),
+    /// What is left of the schedule number, read as a choice per digit
+    Systematic(u64),
+}
+
+impl Chooser {
+    fn pick(&mut self, options: usize) -> usize {
+        let options = options as u64;
+        match self {
+            Chooser::Random(state) => {
+                *state = state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+                let mut z = *state;
+                z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+                z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+                ((z ^ (z >> 31)) % options) as usize
+            }
+            Chooser::Systematic(rest) => {
+                let choice = *rest % options;
+                *rest /= options;
+                choice as usize
+            }
+        }
+    }
+}
+
+impl Scheduler {
+    /// The scheduler for schedule `number`
+    pub fn new(options: &ScheduleOptions, number: u64) -> Scheduler {
+        let choose = if options.systematic { Chooser::Systematic(number) } else { Chooser::Random(number) };
+        Scheduler {
+            state: Mutex::new(State { choose, pending: Vec::new(), granted: None, running: None, program: true, failures: Vec::new(), closed: false }),
+            turn: Condvar::new(),
+        }
+    }
+
+    /// A message from `origin` was put in `actor`'s mailbox
+    pub fn posted(&self, actor: u64, origin: Origin) {
+        let mut state = self.lock();
+        state.pending.push((actor, origin));
+        self.dispatch(&mut state);
+    }
+
+    /// A full mailbox dropped the message from `origin` before `actor` saw it
+    pub fn dropped(&self, actor: u64, origin: Origin) {
+        let mut state = self.lock();
+        state.pending.retain(|&pending| pending != (actor, origin));
+        if state.granted == Some((actor, origin)) {
+            state.granted = None;
+            state.running = None;
+        }
+        self.dispatch(&mut state);
+    }
+
+    /// Wait until `actor` may handle a message, and return which. `None`
+    /// once the scheduler is closed: the actor should stop.
+    pub fn wait_turn(&self, actor: u64) -> Option<Origin> {
+        let mut state = self.lock();
+        loop {
+            if state.closed {
+                return None;
+            }
+            if let Some((granted, origin)) = state.granted {
+                if granted == actor {
+                    state.granted = None;
+                    return Some(origin);
+                }
+            }
+            state = self.turn.wait(state).unwrap_or_else(|e| e.into_inner());
+        }
+    }
+
+    /// `actor`'s handler finished, with `error` if it failed
+    pub fn done(&self, actor: u64, error: Option<String>) {
+        let mut state = self.lock();
+        if state.running == Some(actor) {
+            state.running = None;
+        }
+        state.failures.extend(error);
+        self.dispatch(&mut state);
+    }
+
+    /// Pause the test while the actors handle every message sent, up to
+    /// `timeout`. Returns whether they finished.
+    pub fn run_actors(&self, timeout: Duration) -> bool {
+        let deadline = Instant::now() + timeout;
+        let mut state = self.lock();
+        state.program = false;
+        self.dispatch(&mut state);
+        loop {
+            let idle = state.pending.is_empty() && state.granted.is_none() && state.running.is_none();
+            let left = deadline.saturating_duration_since(Instant::now());
+            if idle || left.is_zero() {
+                state.program = true;
+                return idle;
+            }
+            state = self.turn.wait_timeout(state, left).unwrap_or_else(|e| e.into_inner()).0;
+        }
+    }
+
+    /// Once the test has ended, let the actors finish. Returns the handlers
+    /// that failed, and whether anything was left waiting.
+    pub fn settle(&self, timeout: Duration) -> (Vec<String>, bool) {
+        let finished = self.run_actors(timeout);
+        (std::mem::take(&mut self.lock().failures), !finished)
+    }
+
+    /// Stop the actors waiting for a turn
+    pub fn close(&self) {
+        self.lock().closed = true;
+        self.turn.notify_all();
+    }
+
+    /// With the test paused and no handler running, hand one waiting message to its actor
+    fn dispatch(&self, state: &mut State) {
+        if state.program || state.running.is_some() || state.granted.is_some() || state.pending.is_empty() {
+            self.turn.notify_all();
+            return;
+        }
+        // The oldest message of each sender to each actor
+        let candidates: Vec<usize> = (0..state.pending.len())
+            .filter(|&i| {
+                let (actor, (sender, _)) = state.pending[i];
+                !state.pending[..i].iter().any(|&(a, (s, _))| a == actor && s == sender)
+            })
+            .collect();
+        let pick = candidates[state.choose.pick(candidates.len())];
+        let (actor, origin) = state.pending.remove(pick);
+        state.granted = Some((actor, origin));
+        state.running = Some(actor);
+        self.turn.notify_all();
+    }
+
+    fn lock(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{compile_with_options, schedule, CompileOptions, CompileTarget};
+
+    #[test]
+    fn test_schedules_find_a_message_order_that_fails() {
+        // `walk` passes only if the door handles its own `unlock` first
+        let source = "actor Door:\n    state unlocked: Bool = false\n    on start():\n        send self.unlock()\n    on unlock():\n        unlocked = true\n    on walk():\n        assert(unlocked)\n\ntest \"door\":\n    let d = spawn Door()\n    send d.start()\n    send d.walk()\n    sleep(10)\n";
+        let run = |count, seed| {
+            let schedules = schedule::ScheduleOptions { count, seed, systematic: true };
+            compile_with_options(source, CompileTarget::Test, &CompileOptions { schedules: Some(schedules), ..Default::default() })
+        };
+        // Schedule 0 keeps the order messages were sent in; 1 takes the other choice
+        assert!(run(2, 0).is_err());
+        assert!(run(1, 1).is_ok());
+    }
+}
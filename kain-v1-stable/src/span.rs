@@ -2,25 +2,43 @@
 
 use std::ops::Range;
 
-/// A span of source code
+use crate::vfs::FileId;
+
+/// A span of source code: a byte range, and the file it is in. Spans without
+/// a file are in the file being compiled; those of imported modules carry
+/// the id the [`crate::vfs::SourceMap`] gave the module.
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
 pub struct Span {
     pub start: usize,
     pub end: usize,
+    pub file: Option<FileId>,
 }
 
 impl Span {
     pub fn new(start: usize, end: usize) -> Self {
-        Self { start, end }
+        Self { start, end, file: None }
+    }
+
+    /// The same range in `file`
+    pub fn in_file(self, file: FileId) -> Self {
+        Self { file: Some(file), ..self }
     }
 
     pub fn merge(self, other: Span) -> Span {
         Span {
             start: self.start.min(other.start),
             end: self.end.max(other.end),
+            file: self.file.or(other.file),
         }
     }
 
+    /// Whether this is the empty span at the start of an unknown file, which
+    /// locates nothing. Diagnostics must not be reported with it, except
+    /// internal compiler errors raised where no source is at hand.
+    pub fn is_placeholder(self) -> bool {
+        self == Span::default()
+    }
+
     pub fn to_range(self) -> Range<usize> {
         self.start..self.end
     }
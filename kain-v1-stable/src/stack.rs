@@ -0,0 +1,21 @@
+//! Stack safety for the recursive compiler passes
+//!
+//! The parser, comptime evaluator and interpreter recurse on the shape of the
+//! source, so deeply nested input used to overflow the native stack. Each
+//! recursive step goes through `grow`, which switches to a fresh heap-allocated
+//! stack segment when the current one is nearly exhausted.
+
+/// Deepest nesting of expressions, blocks, types, patterns or JSX the parser accepts
+pub const MAX_NESTING_DEPTH: usize = 256;
+
+/// Remaining stack below which a new segment is allocated
+const RED_ZONE: usize = 128 * 1024;
+
+/// Size of each additional stack segment
+const SEGMENT_SIZE: usize = 2 * 1024 * 1024;
+
+/// Run `f`, first moving to a new stack segment if this one is nearly full
+#[inline]
+pub fn grow<R>(f: impl FnOnce() -> R) -> R {
+    stacker::maybe_grow(RED_ZONE, SEGMENT_SIZE, f)
+}
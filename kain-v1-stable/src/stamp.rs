@@ -0,0 +1,235 @@
+//! Build stamps: which compiler built an artifact, and from what
+//!
+//! Every wasm, llvm, spirv, js and hybrid artifact carries one line naming the
+//! compiler version, the target, the build profile and a hash of the source:
+//!
+//! ```text
+//! kain-build: compiler=0.1.2 target=wasm profile=release source=fnv1a64:5f2d0c1e8a9b3c47
+//! ```
+//!
+//! It goes where each format keeps such things: a `kain.build` custom section
+//! in wasm, `!kain.build` named metadata in LLVM IR, an `OpSourceExtension`
+//! in SPIR-V and a banner comment in JS. Nothing in it depends on the machine
+//! or the time, so deterministic builds stay byte-identical. `kain inspect
+//! artifact` reads it back with [`read`].
+
+use std::fmt;
+
+use crate::CompileTarget;
+
+/// What every stamp starts with, to find it in text and SPIR-V
+const MARKER: &str = "kain-build:";
+
+/// The wasm custom section holding the stamp
+const WASM_SECTION: &str = "kain.build";
+
+const SPIRV_MAGIC: u32 = 0x0723_0203;
+const OP_SOURCE_EXTENSION: u32 = 4;
+/// Capabilities, extensions, imports, the memory model, entry points and
+/// execution modes: what comes before the debug instructions in a SPIR-V module
+const SPIRV_PREAMBLE: &[u32] = &[17, 10, 11, 14, 15, 16, 331];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Stamp {
+    pub compiler: String,
+    pub target: String,
+    pub profile: String,
+    /// `fnv1a64:` and the hash of the source text, when the source was compiled from text
+    pub source: Option<String>,
+}
+
+impl Stamp {
+    /// The stamp of `source` compiled by this compiler
+    pub fn new(target: CompileTarget, profile: Option<&str>, source: Option<&str>) -> Self {
+        Stamp {
+            compiler: env!("CARGO_PKG_VERSION").to_string(),
+            target: target.cfg_names()[0].to_string(),
+            profile: profile.unwrap_or("debug").to_string(),
+            source: source.map(|text| format!("fnv1a64:{:016x}", fnv1a(text.as_bytes()))),
+        }
+    }
+
+    /// Read a stamp line back
+    pub fn parse(line: &str) -> Option<Self> {
+        let rest = line.trim().strip_prefix(MARKER)?;
+        let mut stamp = Stamp { compiler: String::new(), target: String::new(), profile: String::new(), source: None };
+        for field in rest.split_whitespace() {
+            let (key, value) = field.split_once('=')?;
+            match key {
+                "compiler" => stamp.compiler = value.to_string(),
+                "target" => stamp.target = value.to_string(),
+                "profile" => stamp.profile = value.to_string(),
+                "source" => stamp.source = Some(value.to_string()),
+                // Fields from newer compilers
+                _ => {}
+            }
+        }
+        Some(stamp)
+    }
+}
+
+impl fmt::Display for Stamp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} compiler={} target={} profile={}", MARKER, self.compiler, self.target, self.profile)?;
+        if let Some(source) = &self.source {
+            write!(f, " source={}", source)?;
+        }
+        Ok(())
+    }
+}
+
+/// 64-bit FNV-1a, which unlike std's hasher is the same for every build of the compiler
+pub(crate) fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, &b| (hash ^ b as u64).wrapping_mul(0x0100_0000_01b3))
+}
+
+/// Add `stamp` to an artifact built for `target`; targets without a place for one are left alone
+pub fn apply(artifact: &mut Vec<u8>, target: CompileTarget, stamp: &Stamp) {
+    match target {
+        CompileTarget::Wasm => wasm_custom_section(artifact, WASM_SECTION, stamp.to_string().as_bytes()),
+        CompileTarget::Llvm => llvm_metadata(artifact, stamp),
+        CompileTarget::SpirV => spirv_source_extension(artifact, stamp),
+        CompileTarget::Js | CompileTarget::Hybrid => *artifact = [format!("// {}\n", stamp).as_bytes(), artifact].concat(),
+        _ => {}
+    }
+}
+
+/// The stamp of an artifact of any format, if it has one
+pub fn read(artifact: &[u8]) -> Option<Stamp> {
+    if artifact.starts_with(b"\0asm") {
+        return read_wasm(artifact);
+    }
+    if words(artifact).first() == Some(&SPIRV_MAGIC) {
+        return read_spirv(artifact);
+    }
+    let text = String::from_utf8_lossy(artifact);
+    let start = text.find(MARKER)?;
+    let line = text[start..].split(['\n', '"']).next()?;
+    Stamp::parse(line)
+}
+
+fn wasm_custom_section(wasm: &mut Vec<u8>, name: &str, payload: &[u8]) {
+    let mut content = Vec::new();
+    leb128(&mut content, name.len());
+    content.extend_from_slice(name.as_bytes());
+    content.extend_from_slice(payload);
+    wasm.push(0);
+    leb128(wasm, content.len());
+    wasm.extend(content);
+}
+
+fn leb128(out: &mut Vec<u8>, mut value: usize) {
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        if value == 0 {
+            out.push(byte);
+            return;
+        }
+        out.push(byte | 0x80);
+    }
+}
+
+fn read_wasm(wasm: &[u8]) -> Option<Stamp> {
+    for payload in wasmparser::Parser::new(0).parse_all(wasm) {
+        if let wasmparser::Payload::CustomSection(reader) = payload.ok()? {
+            if reader.name() == WASM_SECTION {
+                return Stamp::parse(std::str::from_utf8(reader.data()).ok()?);
+            }
+        }
+    }
+    None
+}
+
+/// Named metadata after the module's own, numbered past the highest `!N` it uses
+fn llvm_metadata(ir: &mut Vec<u8>, stamp: &Stamp) {
+    let mut next = 0;
+    let mut rest = &ir[..];
+    while let Some(at) = rest.iter().position(|&b| b == b'!') {
+        let digits = rest[at + 1..].iter().take_while(|b| b.is_ascii_digit()).count();
+        if let Some(id) = std::str::from_utf8(&rest[at + 1..at + 1 + digits]).ok().and_then(|d| d.parse::<u64>().ok()) {
+            next = next.max(id + 1);
+        }
+        rest = &rest[at + 1 + digits..];
+    }
+    if !ir.ends_with(b"\n") {
+        ir.push(b'\n');
+    }
+    ir.extend(format!("\n!kain.build = !{{!{id}}}\n!{id} = !{{!\"{}\"}}\n", stamp, id = next).into_bytes());
+}
+
+fn words(bytes: &[u8]) -> Vec<u32> {
+    bytes.chunks_exact(4).map(|w| u32::from_le_bytes([w[0], w[1], w[2], w[3]])).collect()
+}
+
+/// An `OpSourceExtension` holding the stamp, at the start of the debug instructions
+fn spirv_source_extension(spirv: &mut Vec<u8>, stamp: &Stamp) {
+    let module = words(spirv);
+    if module.len() < 5 || module[0] != SPIRV_MAGIC {
+        return;
+    }
+    let mut at = 5;
+    while at < module.len() && SPIRV_PREAMBLE.contains(&(module[at] & 0xffff)) {
+        at += ((module[at] >> 16) as usize).max(1);
+    }
+
+    // A nul-terminated string, padded to whole words
+    let mut text = stamp.to_string().into_bytes();
+    text.push(0);
+    text.resize(text.len().div_ceil(4) * 4, 0);
+    let count = 1 + text.len() / 4;
+    let mut instruction = (((count as u32) << 16) | OP_SOURCE_EXTENSION).to_le_bytes().to_vec();
+    instruction.extend(text);
+    let offset = at.min(module.len()) * 4;
+    *spirv = [&spirv[..offset], &instruction, &spirv[offset..]].concat();
+}
+
+fn read_spirv(spirv: &[u8]) -> Option<Stamp> {
+    let module = words(spirv);
+    let mut at = 5;
+    while at < module.len() {
+        let count = ((module[at] >> 16) as usize).max(1);
+        if module[at] & 0xffff == OP_SOURCE_EXTENSION {
+            let bytes: Vec<u8> = module.get(at + 1..at + count)?.iter().flat_map(|w| w.to_le_bytes()).collect();
+            let text = String::from_utf8_lossy(&bytes);
+            if let Some(stamp) = Stamp::parse(text.trim_end_matches('\0')) {
+                return Some(stamp);
+            }
+        }
+        at += count;
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{compile_with_options, CompileOptions};
+
+    #[test]
+    fn test_stamps_are_embedded_and_read_back() {
+        let source = "fn add(a: Int, b: Int) -> Int:\n    return a + b\n\nfn main():\n    println(add(1, 2))\n";
+        let options = CompileOptions { profile: Some("release".to_string()), ..CompileOptions::default() };
+        for target in [CompileTarget::Wasm, CompileTarget::Js] {
+            let artifact = compile_with_options(source, target, &options).unwrap();
+            let stamp = read(&artifact).unwrap_or_else(|| panic!("no stamp for {:?}", target));
+            assert_eq!(stamp, Stamp::new(target, Some("release"), Some(source)));
+            assert_eq!(stamp.compiler, env!("CARGO_PKG_VERSION"));
+        }
+        let wasm = compile_with_options(source, CompileTarget::Wasm, &options).unwrap();
+        assert!(crate::codegen::wat::validate(&wasm).is_ok());
+
+        let shader = "shader fragment Tint(uv: Vec2) -> Vec4:\n    uniform tint: Vec4 @0\n    return tint\n";
+        let spirv = compile_with_options(shader, CompileTarget::SpirV, &options).unwrap();
+        assert_eq!(read(&spirv).map(|s| s.target), Some("spirv".to_string()));
+
+        let mut ir = b"define i64 @main() {\n  ret i64 0, !dbg !7\n}\n!7 = !{}\n".to_vec();
+        apply(&mut ir, CompileTarget::Llvm, &Stamp::new(CompileTarget::Llvm, None, Some(source)));
+        let ir = String::from_utf8(ir).unwrap();
+        assert!(ir.contains("!kain.build = !{!8}\n!8 = !{!\"kain-build: "), "{}", ir);
+        assert_eq!(read(ir.as_bytes()).map(|s| s.profile), Some("debug".to_string()));
+
+        assert_ne!(Stamp::new(CompileTarget::Js, None, Some("a")).source, Stamp::new(CompileTarget::Js, None, Some("b")).source);
+        assert_eq!(read(b"plain text"), None);
+    }
+}
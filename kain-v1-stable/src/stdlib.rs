@@ -1,126 +1,368 @@
 //! KAIN Standard Library
+//!
+//! The registry of built-in functions and constants. Every interpreter native
+//! is declared here with its signature; the type checker validates builtin
+//! calls against it, the LSP offers it for completion, the LLVM backend
+//! declares its externs from it and the JS, Rust and WASM backends only
+//! special-case calls that resolve to it. Comptime builtins only exist while `comptime:`
+//! code runs, and shader intrinsics (`vec3`, `dot`, ...) are only lowered by
+//! the GPU backends.
+//!
+//! In a signature, a parameter type ending in `?` is optional and one ending
+//! in `...` takes any number of remaining arguments.
 
 use crate::types::ResolvedType;
+use once_cell::sync::Lazy;
 use std::collections::HashMap;
 
 /// Built-in function registry
 pub struct StdLib {
     pub functions: HashMap<String, BuiltinFn>,
+    pub constants: HashMap<String, BuiltinConst>,
     pub types: HashMap<String, ResolvedType>,
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BuiltinKind {
+    /// Implemented by the interpreter (`Env::register_stdlib` and friends)
+    Native,
+    /// Only callable from `comptime:` code
+    Comptime,
+    /// Only callable from a project's build script
+    Build,
+    /// Lowered directly by the shader backends
+    Intrinsic,
+}
+
 pub struct BuiltinFn {
     pub name: &'static str,
     pub params: Vec<(&'static str, &'static str)>,
     pub return_type: &'static str,
     pub doc: &'static str,
+    pub kind: BuiltinKind,
+}
+
+pub struct BuiltinConst {
+    pub name: &'static str,
+    pub ty: &'static str,
+    pub doc: &'static str,
+}
+
+static STDLIB: Lazy<StdLib> = Lazy::new(StdLib::new);
+
+/// The shared registry
+pub fn stdlib() -> &'static StdLib {
+    &STDLIB
+}
+
+impl BuiltinFn {
+    /// Minimum and maximum argument count (`None` when variadic)
+    pub fn arity(&self) -> (usize, Option<usize>) {
+        let required = self.params.iter().filter(|(_, ty)| !ty.ends_with('?') && !ty.ends_with("...")).count();
+        if self.params.iter().any(|(_, ty)| ty.ends_with("...")) {
+            (required, None)
+        } else {
+            (required, Some(self.params.len()))
+        }
+    }
+
+    /// The message for a call with the wrong number of arguments
+    pub fn arity_error(&self, found: usize) -> Option<String> {
+        let (min, max) = self.arity();
+        if found >= min && max.is_none_or(|max| found <= max) {
+            return None;
+        }
+        let expected = match max {
+            Some(max) if max == min => min.to_string(),
+            Some(max) => format!("{} to {}", min, max),
+            None => format!("at least {}", min),
+        };
+        let plural = if expected == "1" { "" } else { "s" };
+        Some(format!("{} expects {} argument{}, found {}: {}", self.name, expected, plural, found, self.signature()))
+    }
+
+    /// `name(a: Int, b: String?) -> Bool`
+    pub fn signature(&self) -> String {
+        let params: Vec<String> = self.params.iter().map(|(name, ty)| format!("{}: {}", name, ty)).collect();
+        format!("{}({}) -> {}", self.name, params.join(", "), self.return_type)
+    }
 }
 
 impl StdLib {
     pub fn new() -> Self {
         let mut lib = Self {
             functions: HashMap::new(),
+            constants: HashMap::new(),
             types: HashMap::new(),
         };
-        
+
+        // Constants
+        lib.add_const("None", "Option", "The absent value");
+        lib.add_const("none", "Option", "The absent value");
+        lib.add_const("PI", "Float", "Ratio of a circle's circumference to its diameter");
+        lib.add_const("E", "Float", "Euler's number");
+        lib.add_const("TAU", "Float", "Ratio of a circle's circumference to its radius");
+
         // I/O
-        lib.add_fn("print", &[("value", "Any")], "Unit", "Print value to console");
-        lib.add_fn("println", &[("value", "Any")], "Unit", "Print value with newline");
+        lib.add_fn("print", &[("values", "Any...")], "Unit", "Print values to console");
+        lib.add_fn("println", &[("values", "Any...")], "Unit", "Print values with newline");
         lib.add_fn("read_line", &[], "String", "Read line from stdin");
         lib.add_fn("read_file", &[("path", "String")], "String", "Read file contents");
         lib.add_fn("write_file", &[("path", "String"), ("content", "String")], "Unit", "Write to file");
-        
+        lib.add_fn("copy_file", &[("from", "String"), ("to", "String")], "Unit", "Copy a file, replacing the destination");
+        lib.add_fn("file_exists", &[("path", "String")], "Bool", "Check that a path exists");
+
         // Math
-        lib.add_fn("abs", &[("x", "Int")], "Int", "Absolute value");
+        lib.add_fn("abs", &[("x", "Number")], "Number", "Absolute value");
         lib.add_fn("sqrt", &[("x", "Float")], "Float", "Square root");
-        lib.add_fn("pow", &[("base", "Float"), ("exp", "Float")], "Float", "Power");
+        lib.add_fn("pow", &[("base", "Number"), ("exp", "Number")], "Number", "Power; Int for non-negative Int exponents");
         lib.add_fn("sin", &[("x", "Float")], "Float", "Sine");
         lib.add_fn("cos", &[("x", "Float")], "Float", "Cosine");
         lib.add_fn("tan", &[("x", "Float")], "Float", "Tangent");
-        lib.add_fn("floor", &[("x", "Float")], "Int", "Floor");
-        lib.add_fn("ceil", &[("x", "Float")], "Int", "Ceiling");
-        lib.add_fn("round", &[("x", "Float")], "Int", "Round");
-        lib.add_fn("min", &[("a", "Int"), ("b", "Int")], "Int", "Minimum");
-        lib.add_fn("max", &[("a", "Int"), ("b", "Int")], "Int", "Maximum");
-        lib.add_fn("clamp", &[("x", "Int"), ("lo", "Int"), ("hi", "Int")], "Int", "Clamp between bounds");
-        
+        lib.add_fn("asin", &[("x", "Float")], "Float", "Arcsine");
+        lib.add_fn("acos", &[("x", "Float")], "Float", "Arccosine");
+        lib.add_fn("atan", &[("x", "Float")], "Float", "Arctangent");
+        lib.add_fn("atan2", &[("y", "Float"), ("x", "Float")], "Float", "Angle of the point (x, y)");
+        lib.add_fn("sinh", &[("x", "Float")], "Float", "Hyperbolic sine");
+        lib.add_fn("cosh", &[("x", "Float")], "Float", "Hyperbolic cosine");
+        lib.add_fn("tanh", &[("x", "Float")], "Float", "Hyperbolic tangent");
+        lib.add_fn("exp", &[("x", "Float")], "Float", "e raised to x");
+        lib.add_fn("ln", &[("x", "Float")], "Float", "Natural logarithm");
+        lib.add_fn("log2", &[("x", "Float")], "Float", "Base 2 logarithm");
+        lib.add_fn("log10", &[("x", "Float")], "Float", "Base 10 logarithm");
+        lib.add_fn("floor", &[("x", "Float")], "Float", "Floor");
+        lib.add_fn("ceil", &[("x", "Float")], "Float", "Ceiling");
+        lib.add_fn("round", &[("x", "Float")], "Float", "Round half away from zero");
+        lib.add_fn("trunc", &[("x", "Float")], "Float", "Round toward zero");
+        lib.add_fn("is_nan", &[("x", "Float")], "Bool", "Check for NaN");
+        lib.add_fn("is_inf", &[("x", "Float")], "Bool", "Check for positive or negative infinity");
+        lib.add_fn("min", &[("a", "Number"), ("b", "Number")], "Number", "Minimum");
+        lib.add_fn("max", &[("a", "Number"), ("b", "Number")], "Number", "Maximum");
+        lib.add_fn("clamp", &[("x", "Number"), ("lo", "Number"), ("hi", "Number")], "Number", "Clamp between bounds");
+        lib.add_fn("random", &[], "Float", "Pseudo-random number in [0, 1)");
+
         // Vector math (for shaders)
-        lib.add_fn("vec2", &[("x", "Float"), ("y", "Float")], "Vec2", "Create 2D vector");
-        lib.add_fn("vec3", &[("x", "Float"), ("y", "Float"), ("z", "Float")], "Vec3", "Create 3D vector");
-        lib.add_fn("vec4", &[("x", "Float"), ("y", "Float"), ("z", "Float"), ("w", "Float")], "Vec4", "Create 4D vector");
-        lib.add_fn("dot", &[("a", "Vec3"), ("b", "Vec3")], "Float", "Dot product");
-        lib.add_fn("cross", &[("a", "Vec3"), ("b", "Vec3")], "Vec3", "Cross product");
-        lib.add_fn("normalize", &[("v", "Vec3")], "Vec3", "Normalize vector");
-        lib.add_fn("length", &[("v", "Vec3")], "Float", "Vector length");
-        lib.add_fn("distance", &[("a", "Vec3"), ("b", "Vec3")], "Float", "Distance between points");
-        lib.add_fn("mix", &[("a", "Float"), ("b", "Float"), ("t", "Float")], "Float", "Linear interpolation");
-        lib.add_fn("smoothstep", &[("edge0", "Float"), ("edge1", "Float"), ("x", "Float")], "Float", "Smooth step");
-        
+        // Constructors take scalars or smaller vectors, e.g. `vec4(color, 1.0)` or `vec3(0.5)`
+        lib.add_intrinsic("vec2", &[("components", "Any...")], "Vec2", "Create 2D vector");
+        lib.add_intrinsic("vec3", &[("components", "Any...")], "Vec3", "Create 3D vector");
+        lib.add_intrinsic("vec4", &[("components", "Any...")], "Vec4", "Create 4D vector");
+        lib.add_intrinsic("dot", &[("a", "Vec3"), ("b", "Vec3")], "Float", "Dot product");
+        lib.add_intrinsic("cross", &[("a", "Vec3"), ("b", "Vec3")], "Vec3", "Cross product");
+        lib.add_intrinsic("normalize", &[("v", "Vec3")], "Vec3", "Normalize vector");
+        lib.add_intrinsic("length", &[("v", "Vec3")], "Float", "Vector length");
+        lib.add_intrinsic("distance", &[("a", "Vec3"), ("b", "Vec3")], "Float", "Distance between points");
+        lib.add_intrinsic("mix", &[("a", "Float"), ("b", "Float"), ("t", "Float")], "Float", "Linear interpolation");
+        lib.add_intrinsic("smoothstep", &[("edge0", "Float"), ("edge1", "Float"), ("x", "Float")], "Float", "Smooth step");
+
         // Collections
-        lib.add_fn("len", &[("collection", "Any")], "Int", "Get length");
+        lib.add_fn("len", &[("collection", "Any")], "Int", "Element count, or chars for a string");
         lib.add_fn("push", &[("array", "Array"), ("value", "Any")], "Unit", "Push to array");
-        lib.add_fn("pop", &[("array", "Array")], "Any", "Pop from array");
+        lib.add_fn("pop", &[("array", "Array")], "Any", "Remove and return the last element, or None");
+        lib.add_fn("insert", &[("array", "Array"), ("index", "Int"), ("value", "Any")], "Unit", "Insert before an index");
+        lib.add_fn("remove", &[("array", "Array"), ("index", "Int")], "Any", "Remove and return the element at an index");
+        lib.add_fn("slice", &[("array", "Array"), ("start", "Int"), ("end", "Int?")], "Array", "Elements from start up to end");
+        lib.add_fn("concat", &[("a", "Array"), ("b", "Array")], "Array", "Elements of a then b");
+        lib.add_fn("flatten", &[("array", "Array")], "Array", "Splice nested arrays one level");
+        lib.add_fn("zip", &[("a", "Array"), ("b", "Array")], "Array", "Pairs (a[i], b[i]) up to the shorter length");
+        lib.add_fn("index_of", &[("array", "Array"), ("value", "Any")], "Any", "Index of the first equal element, or None");
+        lib.add_fn("first", &[("items", "Any")], "Any", "First element of an array or string");
+        lib.add_fn("last", &[("items", "Any")], "Any", "Last element of an array or string");
+        lib.add_fn("reverse", &[("items", "Any")], "Any", "Reverse an array or string");
+        lib.add_fn("sum", &[("array", "Array")], "Number", "Sum of an array of numbers");
         lib.add_fn("map", &[("array", "Array"), ("fn", "Function")], "Array", "Map over array");
         lib.add_fn("filter", &[("array", "Array"), ("fn", "Function")], "Array", "Filter array");
         lib.add_fn("reduce", &[("array", "Array"), ("initial", "Any"), ("fn", "Function")], "Any", "Reduce array");
+        lib.add_fn("foreach", &[("array", "Array"), ("fn", "Function")], "Unit", "Call a function for each element");
         lib.add_fn("range", &[("start", "Int"), ("end", "Int")], "Array", "Create range");
-        
-        // HashMap
-        lib.add_fn("map_new", &[], "Any", "Create new map");
-        lib.add_fn("map_set", &[("map", "Any"), ("key", "String"), ("value", "Any")], "Unit", "Set map key");
-        lib.add_fn("map_get", &[("map", "Any"), ("key", "String")], "Any", "Get map value");
-        
-        // Sockets
-        lib.add_fn("socket_connect", &[("host", "String"), ("port", "Int")], "Int", "Connect TCP socket");
-        lib.add_fn("socket_send", &[("sock", "Int"), ("data", "String")], "Unit", "Send data");
-        lib.add_fn("socket_recv", &[("sock", "Int")], "String", "Receive data");
-        
+        lib.add_fn("sort", &[("array", "Array")], "Array", "Sorted copy, stable");
+        lib.add_fn("sort_by", &[("array", "Array"), ("cmp", "Function")], "Array", "Sorted copy by a comparator returning <0, 0 or >0, stable");
+        lib.add_fn("binary_search", &[("array", "Array"), ("value", "Any"), ("cmp", "Function?")], "Result", "Ok(index) of a match or Err(insertion index)");
+        lib.add_fn("min_by", &[("array", "Array"), ("cmp", "Function")], "Any", "First minimum by a comparator, or None");
+        lib.add_fn("max_by", &[("array", "Array"), ("cmp", "Function")], "Any", "Last maximum by a comparator, or None");
+        lib.add_fn("unique", &[("array", "Array")], "Array", "First occurrence of each value, in order");
+        lib.add_fn("group_by", &[("array", "Array"), ("key", "Function")], "Array", "(key, items) pairs in order of first appearance");
+
         // String
         lib.add_fn("split", &[("s", "String"), ("sep", "String")], "Array", "Split string");
         lib.add_fn("join", &[("arr", "Array"), ("sep", "String")], "String", "Join array to string");
         lib.add_fn("trim", &[("s", "String")], "String", "Trim whitespace");
-        lib.add_fn("to_upper", &[("s", "String")], "String", "To uppercase");
-        lib.add_fn("to_lower", &[("s", "String")], "String", "To lowercase");
-        lib.add_fn("contains", &[("s", "String"), ("sub", "String")], "Bool", "Check contains");
+        lib.add_fn("upper", &[("s", "String")], "String", "To uppercase");
+        lib.add_fn("lower", &[("s", "String")], "String", "To lowercase");
+        lib.add_fn("contains", &[("haystack", "Any"), ("needle", "Any")], "Bool", "Check contains");
+        lib.add_fn("starts_with", &[("s", "String"), ("prefix", "String")], "Bool", "Check prefix");
+        lib.add_fn("ends_with", &[("s", "String"), ("suffix", "String")], "Bool", "Check suffix");
         lib.add_fn("replace", &[("s", "String"), ("from", "String"), ("to", "String")], "String", "Replace substring");
-        
+        lib.add_fn("char_at", &[("s", "String"), ("index", "Int")], "String", "Character at a char index, or None");
+        lib.add_fn("substring", &[("s", "String"), ("start", "Int"), ("end", "Int?")], "String", "Chars from start up to end, clamped to the string");
+        lib.add_fn("byte_len", &[("s", "String")], "Int", "Length in UTF-8 bytes");
+        lib.add_fn("char_len", &[("s", "String")], "Int", "Length in chars (what len and indexing count)");
+        lib.add_fn("chars", &[("s", "String")], "Array", "The chars of a string");
+        lib.add_fn("graphemes", &[("s", "String")], "Array", "The user-perceived characters of a string");
+        lib.add_fn("ord", &[("c", "Any")], "Int", "Code point of a Char, or of a string's first character");
+        lib.add_fn("chr", &[("code", "Int")], "String", "Character for a code point");
+        lib.add_fn("char", &[("value", "Any")], "Char", "Char for a code point or a one-character string");
+
         // Conversion
+        lib.add_fn("str", &[("value", "Any")], "String", "Convert to string");
         lib.add_fn("to_string", &[("value", "Any")], "String", "Convert to string");
+        lib.add_fn("int", &[("value", "Any")], "Int", "Convert to int");
         lib.add_fn("to_int", &[("value", "Any")], "Int", "Convert to int");
-        lib.add_fn("to_float", &[("value", "Any")], "Float", "Convert to float");
-        
+        lib.add_fn("float", &[("value", "Any")], "Float", "Convert to float");
+        lib.add_fn("bool", &[("value", "Any")], "Bool", "Convert to bool");
+        lib.add_fn("type_of", &[("value", "Any")], "String", "Name of a value's type");
+
+        // Option / Result / enums
+        lib.add_fn("Some", &[("value", "Any")], "Option", "Wrap a present value");
+        lib.add_fn("ok", &[("value", "Any")], "Result", "Successful result");
+        lib.add_fn("err", &[("error", "Any")], "Result", "Failed result");
+        lib.add_fn("variant_of", &[("value", "Any")], "String", "Variant name of an enum value");
+        lib.add_fn("variant_field", &[("value", "Any"), ("index", "Int")], "Any", "Payload field of an enum value");
+
         // Debug
-        lib.add_fn("dbg", &[("value", "Any")], "Any", "Debug print and return");
-        lib.add_fn("assert", &[("condition", "Bool"), ("message", "String")], "Unit", "Assert condition");
-        lib.add_fn("panic", &[("message", "String")], "Never", "Panic with message");
-        
-        // Time
+        lib.add_fn("dbg", &[("values", "Any...")], "Unit", "Debug print");
+        lib.add_fn("assert", &[("condition", "Bool"), ("message", "String?")], "Unit", "Assert condition");
+        lib.add_fn("panic", &[("message", "String?")], "Never", "Panic with message");
+
+        // Time and process
         lib.add_fn("now", &[], "Float", "Current time in seconds");
-        lib.add_fn("sleep", &[("seconds", "Float")], "Unit", "Sleep for seconds");
-        
+        lib.add_fn("time", &[], "Float", "Current time in seconds");
+        lib.add_fn("sleep", &[("ms", "Int")], "Unit", "Sleep for milliseconds");
+        lib.add_fn("exit", &[("code", "Int?")], "Never", "Exit the process");
+        lib.add_fn("args", &[], "Array", "Arguments passed to the program");
+        lib.add_fn("env", &[("key", "String")], "Any", "Environment variable, or None");
+
         // Actors
-        lib.add_fn("spawn", &[("actor", "Actor")], "ActorRef", "Spawn actor");
-        lib.add_fn("send", &[("actor", "ActorRef"), ("message", "Message")], "Unit", "Send message");
-        
+        lib.add_fn("send", &[("actor", "ActorRef"), ("message", "String"), ("args", "Any...")], "Unit", "Send message");
+        lib.add_fn("snapshot", &[("path", "String?")], "String", "Save an actor's persist state in the wire format; returns the file written");
+        lib.add_fn("restore", &[("path", "String?")], "Bool", "Load an actor's persist state from its snapshot; false when there is none");
+        lib.add_fn("mailbox_len", &[("actor", "ActorRef?")], "Int", "Messages waiting in an actor's mailbox, its own inside an actor");
+        lib.add_fn("runtime_stats", &[], "RuntimeStats", "Live values, actor mailbox depths and allocations per function");
+        lib.add_fn("spawn_cube", &[("x", "Float"), ("y", "Float")], "Unit", "Spawn a cube in the KOS engine");
+        lib.add_fn("kos_connect", &[("addr", "String?")], "String", "Connect to the KOS engine, returning its name");
+        lib.add_fn("kos_spawn", &[("kind", "String"), ("x", "Float"), ("y", "Float"), ("z", "Float?")], "Int", "Spawn an entity in the KOS engine, returning its id");
+        lib.add_fn("kos_transform", &[("id", "Int"), ("position", "Any"), ("rotation", "Any?"), ("scale", "Any?")], "Unit", "Move, rotate or scale a KOS entity");
+        lib.add_fn("kos_destroy", &[("id", "Int")], "Unit", "Remove a KOS entity");
+        lib.add_fn("kos_query", &[("id", "Int")], "KosEntity", "A KOS entity's kind and transform");
+
+        // Async
+        lib.add_fn("block_on", &[("future", "Any"), ("token", "CancelToken?")], "Any", "Run a future to completion, or until the token is cancelled");
+        lib.add_fn("spawn_task", &[("future", "Any"), ("token", "CancelToken?")], "Any", "Start a future in the background, stopped when the token is cancelled");
+        lib.add_fn("with_timeout", &[("future", "Any"), ("ms", "Int")], "Result", "Run a future, or Err once ms milliseconds pass");
+        lib.add_fn("cancel_token", &[], "CancelToken", "Create a token that cancels the tasks it is passed to");
+        lib.add_fn("cancel", &[("token", "CancelToken")], "Unit", "Cancel a token's tasks at their next poll");
+        lib.add_fn("is_cancelled", &[("token", "CancelToken?")], "Bool", "Check a token, or the running task, was cancelled or timed out");
+        lib.add_fn("poll_once", &[("future", "Any")], "Any", "Poll a future once");
+        lib.add_fn("is_ready", &[("poll", "Any")], "Bool", "Check a poll result is ready");
+        lib.add_fn("is_pending", &[("poll", "Any")], "Bool", "Check a poll result is pending");
+        lib.add_fn("unwrap_ready", &[("poll", "Any")], "Any", "Value of a ready poll result");
+
         // Python FFI
         lib.add_fn("py_eval", &[("code", "String")], "Any", "Evaluate Python expression");
         lib.add_fn("py_exec", &[("code", "String")], "Unit", "Execute Python code");
         lib.add_fn("py_import", &[("module", "String")], "Any", "Import Python module");
 
-        // UI
-        lib.add_fn("mount", &[("component", "Any"), ("selector", "String")], "Unit", "Mount component to DOM");
+        // Network
+        lib.add_fn("http_get", &[("url", "String")], "String", "HTTP GET request");
+        lib.add_fn("http_post_json", &[("url", "String"), ("body", "String")], "String", "HTTP POST with a JSON body");
+
+        // JSON
+        lib.add_fn("json_parse", &[("text", "String")], "Any", "Parse JSON");
+        lib.add_fn("json_string", &[("value", "Any")], "String", "Serialize to JSON");
+
+        // Serialization (`@derive(Serialize, Deserialize)`)
+        lib.add_fn("serialize", &[("value", "Any")], "Array", "Encode a value in the wire format, as bytes");
+        lib.add_fn("deserialize", &[("bytes", "Array"), ("type", "String?")], "Any", "Decode a value, checking its type when one is named");
+        lib.add_fn("serialize_json", &[("value", "Any")], "String", "Encode a value as JSON that keeps its type names");
+        lib.add_fn("deserialize_json", &[("text", "String"), ("type", "String?")], "Any", "Decode JSON, checking or naming its type");
+
+        // SQLite
+        lib.add_fn("sqlite_open", &[("path", "String")], "SqliteConnection", "Open a database");
+        lib.add_fn("sqlite_exec", &[("conn", "SqliteConnection"), ("sql", "String"), ("params", "Array?")], "Int", "Run a statement, returning the changed row count");
+        lib.add_fn("sqlite_query", &[("conn", "SqliteConnection"), ("sql", "String"), ("params", "Array?"), ("row", "String?")], "Array", "Run a query, returning its rows");
+
+        // Templates
+        lib.add_fn("template_render", &[("source", "String"), ("data", "Any")], "String", "Render a template");
+        lib.add_fn("template_check", &[("source", "String"), ("fields", "Array")], "String", "Check a template's placeholders");
+        lib.add_fn("html_escape", &[("text", "String")], "String", "Escape HTML special characters");
+
+        // CLI
+        lib.add_fn("cli_spec", &[("type", "String"), ("name", "String"), ("about", "String"), ("fields", "Array"), ("commands", "Array?")], "CliSpec", "Describe a command line from a struct");
+        lib.add_fn("cli_parse", &[("spec", "CliSpec"), ("argv", "Array")], "Result", "Parse arguments against a spec");
+
+        // Logging
+        lib.add_fn("log_debug", &[("message", "Any"), ("fields", "Any?")], "Unit", "Log at debug level");
+        lib.add_fn("log_info", &[("message", "Any"), ("fields", "Any?")], "Unit", "Log at info level");
+        lib.add_fn("log_warn", &[("message", "Any"), ("fields", "Any?")], "Unit", "Log at warn level");
+        lib.add_fn("log_error", &[("message", "Any"), ("fields", "Any?")], "Unit", "Log at error level");
+        lib.add_fn("log_set_filter", &[("filter", "String")], "Unit", "Replace the log filter");
+        lib.add_fn("log_set_format", &[("format", "String")], "Unit", "\"text\" or \"json\" records");
+
+        // Images
+        lib.add_fn("image_new", &[("width", "Int"), ("height", "Int")], "Image", "A black, transparent 8-bit RGBA image");
+        lib.add_fn("image_new_float", &[("width", "Int"), ("height", "Int")], "FloatImage", "A Float RGBA framebuffer");
+        lib.add_fn("image_set_pixel", &[("image", "Any"), ("x", "Int"), ("y", "Int"), ("r", "Any"), ("g", "Any"), ("b", "Any"), ("a", "Any?")], "Unit", "Set a pixel's channels, alpha defaulting to opaque");
+        lib.add_fn("image_get_pixel", &[("image", "Any"), ("x", "Int"), ("y", "Int")], "Tuple", "A pixel's (r, g, b, a)");
+        lib.add_fn("image_save_png", &[("image", "Any"), ("path", "String")], "Unit", "Write an image as a PNG, clamping Float channels to 0..1");
+
+        // Games (`--features game`)
+        lib.add_fn("game_open", &[("title", "String"), ("width", "Int"), ("height", "Int")], "Unit", "Open this thread's window");
+        lib.add_fn("game_poll", &[], "Bool", "Handle window events; false once the window is closed");
+        lib.add_fn("game_key_down", &[("key", "String")], "Bool", "Whether a key is held, e.g. \"w\" or \"space\"");
+        lib.add_fn("game_mouse", &[], "Tuple", "The cursor's (x, y) in the window");
+        lib.add_fn("game_mouse_down", &[("button", "String")], "Bool", "Whether \"left\", \"right\" or \"middle\" is held");
+        lib.add_fn("game_present", &[("image", "Any")], "Unit", "Draw an image, scaled to fill the window");
+        lib.add_fn("game_gamepad_down", &[("pad", "Int"), ("button", "String")], "Bool", "Whether a gamepad button is held, e.g. \"south\"");
+        lib.add_fn("game_gamepad_axis", &[("pad", "Int"), ("axis", "String")], "Float", "A gamepad axis from -1.0 to 1.0, e.g. \"leftstickx\"");
+        lib.add_fn("game_play_sound", &[("path", "String"), ("volume", "Float?")], "Unit", "Start playing a WAV, OGG, FLAC or MP3 file");
+
+        // Comptime
+        lib.add_comptime("emit_item", &[("code", "Quote")], "Unit", "Add the items of a quote: block to the program");
+        lib.add_comptime("fields_of", &[("type", "Type")], "Array", "FieldInfo for each field of a struct");
+        lib.add_comptime("variants_of", &[("type", "Type")], "Array", "VariantInfo for each variant of an enum");
+        lib.add_comptime("functions_in_module", &[], "Array", "FunctionInfo for each function in the module");
+        lib.add_comptime("target", &[], "String", "Name of the target being compiled, e.g. \"wasm\"");
+        lib.add_comptime("word_size", &[], "Int", "Pointer width of the target in bits");
+        lib.add_comptime("features", &[], "Array", "Features enabled for this build");
+
+        // Build scripts
+        lib.add_build("build_profile", &[], "String", "Name of the profile being built");
+        lib.add_build("build_targets", &[], "Array", "Targets the build produces");
+        lib.add_build("build_out_dir", &[], "String", "Directory the build writes to");
+        lib.add_build("build_target", &[], "Any", "In post_build, the target just written, otherwise None");
+        lib.add_build("build_output", &[], "Any", "In post_build, the path of its output, otherwise None");
 
         lib
     }
-    
+
     fn add_fn(&mut self, name: &'static str, params: &[(&'static str, &'static str)], ret: &'static str, doc: &'static str) {
+        self.insert(name, params, ret, doc, BuiltinKind::Native);
+    }
+
+    fn add_comptime(&mut self, name: &'static str, params: &[(&'static str, &'static str)], ret: &'static str, doc: &'static str) {
+        self.insert(name, params, ret, doc, BuiltinKind::Comptime);
+    }
+
+    fn add_build(&mut self, name: &'static str, params: &[(&'static str, &'static str)], ret: &'static str, doc: &'static str) {
+        self.insert(name, params, ret, doc, BuiltinKind::Build);
+    }
+
+    fn add_intrinsic(&mut self, name: &'static str, params: &[(&'static str, &'static str)], ret: &'static str, doc: &'static str) {
+        self.insert(name, params, ret, doc, BuiltinKind::Intrinsic);
+    }
+
+    fn insert(&mut self, name: &'static str, params: &[(&'static str, &'static str)], ret: &'static str, doc: &'static str, kind: BuiltinKind) {
         self.functions.insert(name.to_string(), BuiltinFn {
             name,
             params: params.to_vec(),
             return_type: ret,
             doc,
+            kind,
         });
     }
+
+    fn add_const(&mut self, name: &'static str, ty: &'static str, doc: &'static str) {
+        self.constants.insert(name.to_string(), BuiltinConst { name, ty, doc });
+    }
 }
 
 impl Default for StdLib {
@@ -129,3 +371,52 @@ impl Default for StdLib {
     }
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{eval_snippet, CompileOptions};
+
+    #[test]
+    fn test_registry_matches_natives_and_checks_calls() {
+        // Every interpreter native and every builtin a backend lowers is declared
+        let mut env = crate::runtime::Env::new();
+        env.enable_item_emission();
+        let undeclared: Vec<&str> = env
+            .native_names()
+            .into_iter()
+            .filter(|name| stdlib().functions.get(*name).is_none_or(|f| f.kind == BuiltinKind::Intrinsic))
+            .collect();
+        assert!(undeclared.is_empty(), "natives missing from the registry: {:?}", undeclared);
+        let lowered = [crate::codegen::js::LOWERED, crate::codegen::rust::LOWERED, crate::codegen::wasm::LOWERED];
+        let unknown: Vec<&str> = lowered.concat().into_iter().filter(|name| !stdlib().functions.contains_key(*name)).collect();
+        assert!(unknown.is_empty(), "backend intrinsics missing from the registry: {:?}", unknown);
+        // A function the program defines is called, not lowered as the builtin
+        let own_pop = "fn pop(a: Array<Int>) -> Int:\n    return 7\n\nfn main():\n    println(pop([1]))\n";
+        for target in [crate::CompileTarget::Js, crate::CompileTarget::Rust] {
+            let code = String::from_utf8(crate::compile(own_pop, target).unwrap()).unwrap();
+            assert!(code.contains("pop(") && !code.contains(".pop()"), "{:?}: {}", target, code);
+        }
+
+        // And the other way round, every declared native exists.
+        // `send` lexes as a keyword, so it can't be named on its own.
+        let mut natives: Vec<&str> = stdlib()
+            .functions
+            .values()
+            .filter(|f| f.kind == BuiltinKind::Native && f.name != "send")
+            .map(|f| f.name)
+            .collect();
+        natives.sort();
+        let body: String = natives.iter().map(|name| format!("    println(type_of({}))\n", name)).collect();
+        let result = eval_snippet(&format!("fn main():\n{}", body), &CompileOptions::default());
+        assert!(result.diagnostics.is_empty(), "{:?}", result.diagnostics);
+        let kinds: Vec<&str> = result.stdout.lines().map(str::trim).collect();
+        let missing: Vec<_> = natives.iter().zip(&kinds).filter(|(_, kind)| **kind != "native_function").collect();
+        assert!(missing.is_empty() && kinds.len() == natives.len(), "not interpreter natives: {:?}", missing);
+
+        let check = |source: &str| eval_snippet(source, &CompileOptions::default()).diagnostics.first().map(|d| d.to_string());
+        assert!(check("fn main():\n    println(sqrt(1, 2))\n").unwrap().contains("sqrt expects 1 argument, found 2: sqrt(x: Float) -> Float"));
+        assert!(check("fn main():\n    println(substring(\"abc\"))\n").unwrap().contains("substring expects 2 to 3 arguments, found 1"));
+        assert!(check("fn main():\n    println(sqrt(\"4\"))\n").unwrap().contains("sqrt: argument 'x' must be Float, found String"));
+        assert_eq!(check("fn sqrt(a, b):\n    return a\n\nfn main():\n    println(sqrt(1, 2), abs(-2.5))\n"), None);
+    }
+}
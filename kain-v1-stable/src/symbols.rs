@@ -0,0 +1,274 @@
+//! Linker-visible names
+//!
+//! Inside the compiler a method goes by `Type_method`, the name the
+//! interpreter and backends look it up under. What a compiled artifact exposes
+//! to its host (WASM exports, ES module exports, LLVM symbols) follows one
+//! scheme on every backend instead:
+//!
+//! - functions, consts and statics keep their own names
+//! - a method is `_K` followed by its type and method names, each prefixed
+//!   with its length in bytes: `Point.len` is `_K5Point3len`
+//! - an instance of a generic function is mangled the same way, with its type
+//!   arguments between `I` and `E`: `max<Int>` is `_K3maxIxE`. This is also the
+//!   name monomorphization gives the instance.
+//!
+//! Type arguments are encoded as
+//!
+//! | code | type | code | type |
+//! |------|------|------|------|
+//! | `u` | `()` | `b` | `Bool` |
+//! | `c` | `Char` | `e` | `String` |
+//! | `x` | `Int` | `d` | `Float` |
+//! | `a` `s` `l` `n` `i` | `i8` `i16` `i32` `i128` `isize` | `h` `t` `m` `y` `o` `j` | `u8` `u16` `u32` `u64` `u128` `usize` |
+//! | `f` | `f32` | `z` | `!` |
+//! | `A` *T* *n* `_` | `[T; n]` | `S` *T* | `[T]` |
+//! | `T` *T..* `E` | tuple | `F` *T..* `E` *R* | `fn(T..) -> R` |
+//! | `O` *T* | `Option<T>` | `R` *T* *E* | `Result<T, E>` |
+//! | `P` *T* | `&T` | `Q` *T* | `&mut T` |
+//! | *len* *name* | struct, enum or type parameter | `p` | not inferred |
+//!
+//! `@no_mangle` exports a method under its bare name, and
+//! `@export(name = "..")` exports a function under the name given. Either one
+//! exports the function even if it isn't `pub`. Neither applies to generic
+//! functions, whose instances each need a name of their own.
+
+use std::collections::HashSet;
+use std::fmt::Write;
+
+use serde::Serialize;
+
+use crate::ast::{Expr, Function, Visibility};
+use crate::error::{KainError, KainResult};
+use crate::types::{FloatSize, IntSize, ResolvedType, TypedItem, TypedProgram};
+
+/// Mangle the item at `path`, instantiated with `type_args`
+pub fn mangle(path: &[&str], type_args: &[ResolvedType]) -> String {
+    let mut out = String::from("_K");
+    for segment in path {
+        write!(out, "{}{}", segment.len(), segment).ok();
+    }
+    if !type_args.is_empty() {
+        out.push('I');
+        for ty in type_args {
+            mangle_type(ty, &mut out);
+        }
+        out.push('E');
+    }
+    out
+}
+
+fn mangle_type(ty: &ResolvedType, out: &mut String) {
+    match ty {
+        ResolvedType::Unit => out.push('u'),
+        ResolvedType::Bool => out.push('b'),
+        ResolvedType::Char => out.push('c'),
+        ResolvedType::String => out.push('e'),
+        ResolvedType::Never => out.push('z'),
+        ResolvedType::Unknown => out.push('p'),
+        ResolvedType::Int(size) => out.push(match size {
+            IntSize::I8 => 'a',
+            IntSize::I16 => 's',
+            IntSize::I32 => 'l',
+            IntSize::I64 => 'x',
+            IntSize::I128 => 'n',
+            IntSize::Isize => 'i',
+            IntSize::U8 => 'h',
+            IntSize::U16 => 't',
+            IntSize::U32 => 'm',
+            IntSize::U64 => 'y',
+            IntSize::U128 => 'o',
+            IntSize::Usize => 'j',
+        }),
+        ResolvedType::Float(FloatSize::F32) => out.push('f'),
+        ResolvedType::Float(FloatSize::F64) => out.push('d'),
+        ResolvedType::Array(inner, len) => {
+            out.push('A');
+            mangle_type(inner, out);
+            write!(out, "{}_", len).ok();
+        }
+        ResolvedType::Slice(inner) => {
+            out.push('S');
+            mangle_type(inner, out);
+        }
+        ResolvedType::Tuple(items) => {
+            out.push('T');
+            items.iter().for_each(|ty| mangle_type(ty, out));
+            out.push('E');
+        }
+        ResolvedType::Function { params, ret, .. } => {
+            out.push('F');
+            params.iter().for_each(|ty| mangle_type(ty, out));
+            out.push('E');
+            mangle_type(ret, out);
+        }
+        ResolvedType::Option(inner) => {
+            out.push('O');
+            mangle_type(inner, out);
+        }
+        ResolvedType::Result(ok, err) => {
+            out.push('R');
+            mangle_type(ok, out);
+            mangle_type(err, out);
+        }
+        ResolvedType::Ref { mutable, inner } => {
+            out.push(if *mutable { 'Q' } else { 'P' });
+            mangle_type(inner, out);
+        }
+        ResolvedType::Struct(name, _) | ResolvedType::Enum(name, _) | ResolvedType::Generic(name) => {
+            write!(out, "{}{}", name.len(), name).ok();
+        }
+    }
+}
+
+/// The name `@export` or `@no_mangle` gives `function`, if either does
+pub fn declared_name(function: &Function) -> KainResult<Option<String>> {
+    let mut declared = None;
+    for attr in &function.attributes {
+        let name = match (attr.name.as_str(), attr.args.as_slice()) {
+            ("no_mangle", []) => function.name.clone(),
+            // Exported under its usual name
+            ("export", []) => continue,
+            ("export", [Expr::String(name, _)]) => name.clone(),
+            ("export", [Expr::Assign { target, value, .. }]) => match (&**target, &**value) {
+                (Expr::Ident(key, _), Expr::String(name, _)) if key == "name" => name.clone(),
+                _ => return Err(malformed_export(attr.span)),
+            },
+            ("export", _) => return Err(malformed_export(attr.span)),
+            ("no_mangle", _) => return Err(KainError::parser("`@no_mangle` takes no arguments", attr.span)),
+            _ => continue,
+        };
+        let valid = name.chars().next().is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+            && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+        if !valid {
+            return Err(KainError::parser(
+                format!("`{}` is not a valid symbol name; use letters, digits and `_`, not starting with a digit", name),
+                attr.span,
+            ));
+        }
+        declared = Some(name);
+    }
+    Ok(declared)
+}
+
+fn malformed_export(span: crate::span::Span) -> KainError {
+    KainError::parser("Malformed export attribute, expected `@export` or `@export(name = \"..\")`", span)
+}
+
+/// Whether the compiled artifact exposes `function` to its host
+pub fn exported(function: &Function) -> bool {
+    function.visibility == Visibility::Public
+        || function.attributes.iter().any(|attr| matches!(attr.name.as_str(), "export" | "no_mangle"))
+}
+
+/// One name a compiled artifact exposes, as `--emit=symbols` lists it
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Export {
+    pub symbol: String,
+    pub kind: ExportKind,
+    /// `fn(Int, Int) -> Int` for functions, the type for consts and statics
+    pub signature: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ExportKind {
+    Function,
+    Const,
+    Static,
+}
+
+/// Everything a lowered program exports, in program order. Two items exported
+/// under one name are an error, since the host could only reach one of them.
+pub fn exports(program: &TypedProgram) -> KainResult<Vec<Export>> {
+    let mut exports = Vec::new();
+    let mut seen = HashSet::new();
+    for item in &program.items {
+        let (symbol, kind, ty, span) = match item {
+            TypedItem::Function(f) if exported(&f.ast) => (&f.symbol, ExportKind::Function, &f.resolved_type, f.ast.span),
+            TypedItem::Const(c) if c.ast.visibility == Visibility::Public => (&c.ast.name, ExportKind::Const, &c.ty, c.ast.span),
+            TypedItem::Static(s) if s.ast.visibility == Visibility::Public => (&s.ast.name, ExportKind::Static, &s.ty, s.ast.span),
+            _ => continue,
+        };
+        if !seen.insert(symbol.clone()) {
+            return Err(KainError::type_error(format!("Two items are exported as `{}`", symbol), span));
+        }
+        exports.push(Export { symbol: symbol.clone(), kind, signature: type_name(ty) });
+    }
+    Ok(exports)
+}
+
+/// `exports` as the JSON document `--emit=symbols` writes
+pub fn to_json(exports: &[Export]) -> String {
+    serde_json::to_string_pretty(exports).unwrap_or_else(|_| "[]".to_string())
+}
+
+/// How a signature spells `ty`
+fn type_name(ty: &ResolvedType) -> String {
+    let list = |types: &[ResolvedType]| types.iter().map(type_name).collect::<Vec<_>>().join(", ");
+    match ty {
+        ResolvedType::Unit => "()".to_string(),
+        ResolvedType::Bool => "Bool".to_string(),
+        ResolvedType::Char => "Char".to_string(),
+        ResolvedType::String => "String".to_string(),
+        ResolvedType::Never => "!".to_string(),
+        ResolvedType::Unknown => "_".to_string(),
+        ResolvedType::Int(IntSize::I64) => "Int".to_string(),
+        ResolvedType::Int(size) => format!("{:?}", size).to_lowercase(),
+        ResolvedType::Float(FloatSize::F64) => "Float".to_string(),
+        ResolvedType::Float(FloatSize::F32) => "f32".to_string(),
+        ResolvedType::Array(inner, len) => format!("[{}; {}]", type_name(inner), len),
+        ResolvedType::Slice(inner) => format!("[{}]", type_name(inner)),
+        ResolvedType::Tuple(items) => format!("({})", list(items)),
+        ResolvedType::Option(inner) => format!("Option<{}>", type_name(inner)),
+        ResolvedType::Result(ok, err) => format!("Result<{}, {}>", type_name(ok), type_name(err)),
+        ResolvedType::Ref { mutable: true, inner } => format!("&mut {}", type_name(inner)),
+        ResolvedType::Ref { mutable: false, inner } => format!("&{}", type_name(inner)),
+        ResolvedType::Function { params, ret, .. } => match **ret {
+            ResolvedType::Unit => format!("fn({})", list(params)),
+            _ => format!("fn({}) -> {}", list(params), type_name(ret)),
+        },
+        ResolvedType::Struct(name, _) | ResolvedType::Enum(name, _) | ResolvedType::Generic(name) => name.clone(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{compile, compile_with, symbols, types, CompileOptions, CompileTarget};
+
+    #[test]
+    fn test_exports_use_stable_symbol_names() {
+        let source = "struct Point:\n    x: Int\n\nimpl Point:\n    pub fn origin(self) -> Int:\n        return 0\n\n    @no_mangle\n    fn raw(self) -> Int:\n        return 1\n\npub fn id<T>(x: T) -> T:\n    return x\n\n@export(name = \"kain_add\")\nfn add(a: Int, b: Int) -> Int:\n    return a + b\n\npub fn main():\n    println(id(3))\n    let f = id(1.5)\n    println(add(1, 2))\n";
+        let output = compile_with(source, CompileTarget::Wasm, &CompileOptions::default()).unwrap();
+        let exports: Vec<_> = output.exports.iter().map(|e| (e.symbol.as_str(), e.signature.as_str())).collect();
+        assert_eq!(
+            exports,
+            vec![
+                ("_K5Point6origin", "fn(Point) -> Int"),
+                ("raw", "fn(Point) -> Int"),
+                ("kain_add", "fn(Int, Int) -> Int"),
+                ("main", "fn()"),
+                ("_K2idIxE", "fn(Int) -> Int"),
+                ("_K2idIdE", "fn(Float) -> Float"),
+            ]
+        );
+        let wat = String::from_utf8(compile(source, CompileTarget::Wat).unwrap()).unwrap();
+        for symbol in ["_K5Point6origin", "raw", "kain_add", "_K2idIxE"] {
+            assert!(wat.contains(&format!("(export \"{}\"", symbol)), "{} not exported:\n{}", symbol, wat);
+        }
+        assert!(symbols::to_json(&output.exports).contains("\"kind\": \"function\""));
+
+        // The ES module exports the function under the same name
+        let js = String::from_utf8(compile("@export(name = \"kain_add\")\nfn add(a: Int, b: Int) -> Int:\n    return a + b\n", CompileTarget::Js).unwrap()).unwrap();
+        assert!(js.contains("export { add as kain_add };"), "{}", js);
+
+        let nested = vec![types::ResolvedType::Tuple(vec![types::ResolvedType::Int(types::IntSize::I64), types::ResolvedType::String]), types::ResolvedType::Array(Box::new(types::ResolvedType::Bool), 3)];
+        assert_eq!(symbols::mangle(&["max"], &nested), "_K3maxITxeEAb3_E");
+
+        let generic = compile("@no_mangle\npub fn id<T>(x: T) -> T:\n    return x\n", CompileTarget::Js).unwrap_err();
+        assert!(generic.to_string().contains("is generic"), "{}", generic);
+        let clash = compile("@export(name = \"main\")\nfn other():\n    return\n\npub fn main():\n    return\n", CompileTarget::Js).unwrap_err();
+        assert!(clash.to_string().contains("Two items are exported as `main`"), "{}", clash);
+        let malformed = compile("@export(1)\nfn f():\n    return\n", CompileTarget::Js).unwrap_err();
+        assert!(malformed.to_string().contains("Malformed export attribute"), "{}", malformed);
+    }
+}
@@ -0,0 +1,270 @@
+//! Text templates for `std/template`
+//!
+//! - `{{ user.name }}` inserts a value, HTML-escaped; `{{ body | raw }}` doesn't escape
+//! - `{% if path %}..{% else %}..{% end %}`, also `{% if not path %}`
+//! - `{% for post in posts %}..{% end %}`
+//!
+//! Paths start at a field of the data struct or a loop variable. [`check`]
+//! validates a template against the fields of a struct, which comptime code
+//! gets from `fields_of`, so typos in placeholders fail the build:
+//!
+//! ```ignore
+//! const PAGE: String = comptime:
+//!     return template_check("<h1>{{ title }}</h1>", fields_of(Page))
+//! ```
+
+use std::collections::HashSet;
+
+use crate::runtime::Value;
+
+#[derive(Debug, Clone)]
+pub enum Node {
+    Text(String),
+    Value { path: Vec<String>, raw: bool },
+    If { negated: bool, path: Vec<String>, then: Vec<Node>, otherwise: Vec<Node> },
+    For { var: String, path: Vec<String>, body: Vec<Node> },
+}
+
+enum Token {
+    Text(String),
+    Output(String, usize),
+    Tag(String, usize),
+}
+
+/// Parse a template, reporting syntax errors with their line
+pub fn parse(source: &str) -> Result<Vec<Node>, String> {
+    let mut tokens = tokenize(source)?.into_iter();
+    let (nodes, end) = parse_nodes(&mut tokens)?;
+    match end {
+        None => Ok(nodes),
+        Some((tag, line)) => Err(format!("line {}: `{{% {} %}}` without an open block", line, tag)),
+    }
+}
+
+fn tokenize(source: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let mut rest = source;
+    while let Some(start) = rest.find("{{").into_iter().chain(rest.find("{%")).min() {
+        if start > 0 {
+            tokens.push(Token::Text(rest[..start].to_string()));
+        }
+        let line = source[..source.len() - rest.len() + start].matches('\n').count() + 1;
+        let (close, is_tag) = if rest[start..].starts_with("{{") { ("}}", false) } else { ("%}", true) };
+        let body = &rest[start + 2..];
+        let end = body.find(close).ok_or_else(|| format!("line {}: unclosed `{}`", line, &rest[start..start + 2]))?;
+        let inner = body[..end].trim().to_string();
+        tokens.push(if is_tag { Token::Tag(inner, line) } else { Token::Output(inner, line) });
+        rest = &body[end + 2..];
+    }
+    if !rest.is_empty() {
+        tokens.push(Token::Text(rest.to_string()));
+    }
+    Ok(tokens)
+}
+
+/// Nodes up to a closing tag, and that `else` / `end` tag with its line
+type Section = (Vec<Node>, Option<(String, usize)>);
+
+fn parse_nodes(tokens: &mut impl Iterator<Item = Token>) -> Result<Section, String> {
+    let mut nodes = Vec::new();
+    while let Some(token) = tokens.next() {
+        match token {
+            Token::Text(text) => nodes.push(Node::Text(text)),
+            Token::Output(inner, line) => {
+                let (expr, raw) = match inner.split_once('|') {
+                    Some((expr, filter)) if filter.trim() == "raw" => (expr.trim(), true),
+                    Some((_, filter)) => return Err(format!("line {}: unknown filter `{}`, only `raw` is supported", line, filter.trim())),
+                    None => (inner.as_str(), false),
+                };
+                nodes.push(Node::Value { path: parse_path(expr, line)?, raw });
+            }
+            Token::Tag(inner, line) => {
+                let words: Vec<&str> = inner.split_whitespace().collect();
+                match words.as_slice() {
+                    ["if", rest @ ..] => {
+                        let (negated, path) = match rest {
+                            ["not", path] => (true, *path),
+                            [path] => (false, *path),
+                            _ => return Err(format!("line {}: expected `{{% if path %}}` or `{{% if not path %}}`", line)),
+                        };
+                        let path = parse_path(path, line)?;
+                        let (then, end) = parse_nodes(tokens)?;
+                        let otherwise = match end {
+                            Some((tag, _)) if tag == "else" => match parse_nodes(tokens)? {
+                                (otherwise, Some((tag, _))) if tag == "end" => otherwise,
+                                _ => return Err(format!("line {}: `{{% if %}}` is missing `{{% end %}}`", line)),
+                            },
+                            Some((tag, _)) if tag == "end" => Vec::new(),
+                            _ => return Err(format!("line {}: `{{% if %}}` is missing `{{% end %}}`", line)),
+                        };
+                        nodes.push(Node::If { negated, path, then, otherwise });
+                    }
+                    ["for", var, "in", path] => {
+                        let path = parse_path(path, line)?;
+                        match parse_nodes(tokens)? {
+                            (body, Some((tag, _))) if tag == "end" => {
+                                nodes.push(Node::For { var: var.to_string(), path, body })
+                            }
+                            _ => return Err(format!("line {}: `{{% for %}}` is missing `{{% end %}}`", line)),
+                        }
+                    }
+                    ["else"] | ["end"] => return Ok((nodes, Some((words[0].to_string(), line)))),
+                    _ => return Err(format!("line {}: unknown tag `{{% {} %}}`", line, inner)),
+                }
+            }
+        }
+    }
+    Ok((nodes, None))
+}
+
+fn parse_path(expr: &str, line: usize) -> Result<Vec<String>, String> {
+    let path: Vec<String> = expr.trim().split('.').map(str::to_string).collect();
+    let valid = |s: &String| !s.is_empty() && s.chars().all(|c| c.is_alphanumeric() || c == '_');
+    if path.iter().all(valid) {
+        Ok(path)
+    } else {
+        Err(format!("line {}: `{}` is not a path like `user.name`", line, expr.trim()))
+    }
+}
+
+/// Check that every path starts at one of `fields` or at a loop variable
+pub fn check(nodes: &[Node], fields: &[String]) -> Result<(), String> {
+    let mut scope: HashSet<&str> = fields.iter().map(String::as_str).collect();
+    check_nodes(nodes, &mut scope, fields)
+}
+
+fn check_nodes<'a>(nodes: &'a [Node], scope: &mut HashSet<&'a str>, fields: &[String]) -> Result<(), String> {
+    let check_root = |path: &[String], scope: &HashSet<&str>| {
+        if scope.contains(path[0].as_str()) {
+            Ok(())
+        } else {
+            Err(format!("`{}` is not a field of the data (fields: {})", path.join("."), fields.join(", ")))
+        }
+    };
+    for node in nodes {
+        match node {
+            Node::Text(_) => {}
+            Node::Value { path, .. } => check_root(path, scope)?,
+            Node::If { path, then, otherwise, .. } => {
+                check_root(path, scope)?;
+                check_nodes(then, scope, fields)?;
+                check_nodes(otherwise, scope, fields)?;
+            }
+            Node::For { var, path, body } => {
+                check_root(path, scope)?;
+                let fresh = scope.insert(var.as_str());
+                check_nodes(body, scope, fields)?;
+                if fresh {
+                    scope.remove(var.as_str());
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Render `nodes` with paths looked up in `data`
+pub fn render(nodes: &[Node], data: &Value) -> Result<String, String> {
+    let mut out = String::new();
+    render_nodes(nodes, data, &mut Vec::new(), &mut out)?;
+    Ok(out)
+}
+
+fn render_nodes(nodes: &[Node], data: &Value, locals: &mut Vec<(String, Value)>, out: &mut String) -> Result<(), String> {
+    for node in nodes {
+        match node {
+            Node::Text(text) => out.push_str(text),
+            Node::Value { path, raw } => {
+                let text = match lookup(path, data, locals)? {
+                    Value::None | Value::Unit => String::new(),
+                    Value::Some(inner) => inner.to_string(),
+                    value => value.to_string(),
+                };
+                out.push_str(&if *raw { text } else { escape_html(&text) });
+            }
+            Node::If { negated, path, then, otherwise } => {
+                let branch = if truthy(&lookup(path, data, locals)?) != *negated { then } else { otherwise };
+                render_nodes(branch, data, locals, out)?;
+            }
+            Node::For { var, path, body } => {
+                let Value::Array(items) = lookup(path, data, locals)? else {
+                    return Err(format!("`{}` is not an array", path.join(".")));
+                };
+                let items = items.read().unwrap().clone();
+                for item in items {
+                    locals.push((var.clone(), item));
+                    let result = render_nodes(body, data, locals, out);
+                    locals.pop();
+                    result?;
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+fn lookup(path: &[String], data: &Value, locals: &[(String, Value)]) -> Result<Value, String> {
+    let missing = || format!("no value for `{}`", path.join("."));
+    let mut value = match locals.iter().rev().find(|(name, _)| *name == path[0]) {
+        Some((_, value)) => value.clone(),
+        None => field(data, &path[0]).ok_or_else(missing)?,
+    };
+    for segment in &path[1..] {
+        value = field(&value, segment).ok_or_else(missing)?;
+    }
+    Ok(value)
+}
+
+fn field(value: &Value, name: &str) -> Option<Value> {
+    match value {
+        Value::Struct(_, fields) => fields.read().unwrap().get(name).cloned(),
+        Value::Array(items) => name.parse::<usize>().ok().and_then(|i| items.read().unwrap().get(i).cloned()),
+        Value::Tuple(items) => name.parse::<usize>().ok().and_then(|i| items.get(i).cloned()),
+        _ => None,
+    }
+}
+
+fn truthy(value: &Value) -> bool {
+    match value {
+        Value::Bool(b) => *b,
+        Value::None | Value::Unit => false,
+        Value::Int(n) => *n != 0,
+        Value::String(s) => !s.is_empty(),
+        Value::Array(items) => !items.read().unwrap().is_empty(),
+        Value::Result(ok, _) => *ok,
+        _ => true,
+    }
+}
+
+pub fn escape_html(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{eval_snippet, CompileOptions};
+
+    #[test]
+    fn test_templates_escape_loop_and_check_fields() {
+        let source = "struct Item:\n    name: String\n\nstruct Page:\n    title: String\n    items: Array<Item>\n\nconst PAGE: String = comptime:\n    return template_check(\"<b>{{ title }}</b>{% for i in items %}[{{ i.name }}]{% end %}{% if not items %}empty{% else %}!{% end %}{{ title | raw }}\", fields_of(Page))\n\nfn main():\n    println(template_render(PAGE, Page { title: \"a<b\", items: [Item { name: \"x\" }, Item { name: \"y&z\" }] }))\n";
+        let result = eval_snippet(source, &CompileOptions::default());
+        assert!(result.diagnostics.is_empty(), "{:?}", result.diagnostics);
+        assert_eq!(result.stdout.trim(), "<b>a&lt;b</b>[x][y&amp;z]!a<b");
+
+        let typo = eval_snippet(&source.replace("{{ title }}", "{{ titel }}"), &CompileOptions::default());
+        assert!(typo.diagnostics[0].to_string().contains("`titel` is not a field of the data"));
+        let unclosed = eval_snippet(&source.replace("{% end %}{% if", "{% if"), &CompileOptions::default());
+        assert!(unclosed.diagnostics[0].to_string().contains("is missing `{% end %}`"));
+    }
+}
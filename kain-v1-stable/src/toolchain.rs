@@ -0,0 +1,266 @@
+//! Native toolchain discovery
+//!
+//! After codegen the llvm target needs up to three tools:
+//!
+//! - `cc` compiles the IR and the C runtime to objects. It has to read LLVM
+//!   IR, so it is clang or `zig cc`.
+//! - `linker` links objects into executables and shared libraries. It is `cc`
+//!   unless set, and gcc or a system `cc` work as well.
+//! - `ar` archives objects into static libraries: llvm-ar, `zig ar` or `ar`.
+//!
+//! Each tool is the first of: an environment variable (`KAIN_CC`,
+//! `KAIN_LINKER`, `KAIN_AR`), the `[toolchain]` table of the KAIN.toml in the
+//! working directory, or the first well-known command that runs. A setting is
+//! a command line, so `zig cc` runs `zig` with `cc` before its arguments.
+//! When nothing usable is found, the error lists every place that was tried.
+//!
+//! Profiles with `wasm_opt` also run `wasm-opt` on wasm output, found the same
+//! way from `KAIN_WASM_OPT` and `wasm_opt` in `[toolchain]`.
+
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use serde::{Deserialize, Serialize};
+
+use crate::error::{KainError, KainResult};
+
+/// The `[toolchain]` table of KAIN.toml
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ToolchainConfig {
+    /// Compiler for LLVM IR and C, e.g. `clang` or `zig cc`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cc: Option<String>,
+    /// Linker driver for executables and shared libraries; `cc` when unset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub linker: Option<String>,
+    /// Archiver for static libraries
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ar: Option<String>,
+    /// Binaryen's optimizer, for profiles with `wasm_opt`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub wasm_opt: Option<String>,
+}
+
+impl ToolchainConfig {
+    pub fn is_empty(&self) -> bool {
+        *self == ToolchainConfig::default()
+    }
+}
+
+/// A command the toolchain runs: a program, and the arguments that always lead (`cc` in `zig cc`)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Tool {
+    pub program: PathBuf,
+    pub args: Vec<String>,
+}
+
+impl Tool {
+    /// Read a setting: a path to a program, or a command line split on whitespace
+    pub fn parse(spec: &str) -> Option<Tool> {
+        let spec = spec.trim();
+        if Path::new(spec).is_file() {
+            return Some(Tool { program: PathBuf::from(spec), args: Vec::new() });
+        }
+        let mut words = spec.split_whitespace();
+        let program = PathBuf::from(words.next()?);
+        Some(Tool { program, args: words.map(str::to_string).collect() })
+    }
+
+    /// A command running the tool, ready for more arguments
+    pub fn command(&self) -> Command {
+        let mut cmd = Command::new(&self.program);
+        cmd.args(&self.args);
+        cmd
+    }
+
+    /// What `--version` prints, or `None` if the tool can't be run
+    fn version(&self) -> Option<String> {
+        let output = self.command().arg("--version").output().ok()?;
+        let text = if output.stdout.is_empty() { output.stderr } else { output.stdout };
+        Some(String::from_utf8_lossy(&text).lines().next().unwrap_or_default().trim().to_string())
+    }
+
+    /// Whether this is zig, whose `cc` and `ar` are clang's and LLVM's
+    fn is_zig(&self) -> bool {
+        self.program.file_stem().is_some_and(|stem| stem == "zig")
+    }
+}
+
+impl std::fmt::Display for Tool {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.program.display())?;
+        for arg in &self.args {
+            write!(f, " {}", arg)?;
+        }
+        Ok(())
+    }
+}
+
+/// The tools found for this machine and project
+#[derive(Debug, Clone)]
+pub struct Toolchain {
+    pub cc: Tool,
+    pub linker: Tool,
+    config: ToolchainConfig,
+    env: fn(&str) -> Option<String>,
+}
+
+impl Toolchain {
+    /// Find the tools from the environment and the KAIN.toml in the working directory
+    pub fn discover() -> KainResult<Toolchain> {
+        Self::resolve(project_config(), |name| std::env::var(name).ok())
+    }
+
+    /// Find the tools from `config` and the variables `env` reads
+    pub fn resolve(config: ToolchainConfig, env: fn(&str) -> Option<String>) -> KainResult<Toolchain> {
+        let mut tried = Vec::new();
+        let cc = pick(&mut tried, "KAIN_CC", env, config.cc.as_deref(), "cc", cc_candidates(), |tool, version| {
+            // A system cc is often gcc, which can't read IR
+            if tool.is_zig() || version.contains("clang") {
+                Ok(())
+            } else {
+                Err(format!("{}, which cannot compile LLVM IR", version))
+            }
+        })
+        .ok_or_else(|| missing("C compiler for the llvm target", &tried, "install clang, or set KAIN_CC (e.g. KAIN_CC=\"zig cc\")"))?;
+
+        let mut tried = Vec::new();
+        let linker = match pick(&mut tried, "KAIN_LINKER", env, config.linker.as_deref(), "linker", Vec::new(), |_, _| Ok(())) {
+            Some(linker) => linker,
+            None if tried.is_empty() => cc.clone(),
+            None => return Err(missing("linker for the llvm target", &tried, "fix KAIN_LINKER or `linker` in KAIN.toml, or unset it to link with the C compiler")),
+        };
+
+        crate::log::debug("toolchain", &format!("cc: {}, linker: {}", cc, linker));
+        Ok(Toolchain { cc, linker, config, env })
+    }
+
+    /// The archiver for static libraries, looked for the first time one is needed
+    pub fn archiver(&self) -> KainResult<Tool> {
+        let mut candidates = Vec::new();
+        // Beside an installed clang, then on PATH
+        if let Some(dir) = self.cc.program.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            candidates.push(Tool { program: dir.join(if cfg!(windows) { "llvm-ar.exe" } else { "llvm-ar" }), args: Vec::new() });
+        }
+        candidates.push(Tool { program: "llvm-ar".into(), args: Vec::new() });
+        if self.cc.is_zig() {
+            candidates.push(Tool { program: self.cc.program.clone(), args: vec!["ar".to_string()] });
+        }
+        candidates.push(Tool { program: "ar".into(), args: Vec::new() });
+
+        let mut tried = Vec::new();
+        pick(&mut tried, "KAIN_AR", self.env, self.config.ar.as_deref(), "ar", candidates, |_, _| Ok(()))
+            .ok_or_else(|| missing("archiver for the llvm target", &tried, "install llvm-ar or ar, or set KAIN_AR"))
+    }
+}
+
+/// `wasm-opt`, from the environment, the KAIN.toml in the working directory or PATH
+pub fn wasm_opt() -> KainResult<Tool> {
+    resolve_wasm_opt(&project_config(), |name| std::env::var(name).ok())
+}
+
+/// `wasm-opt` from `config`, the variables `env` reads or PATH
+pub fn resolve_wasm_opt(config: &ToolchainConfig, env: fn(&str) -> Option<String>) -> KainResult<Tool> {
+    let mut tried = Vec::new();
+    let candidates = vec![Tool { program: "wasm-opt".into(), args: Vec::new() }];
+    pick(&mut tried, "KAIN_WASM_OPT", env, config.wasm_opt.as_deref(), "wasm_opt", candidates, |_, _| Ok(()))
+        .ok_or_else(|| missing("wasm-opt", &tried, "install binaryen, or set KAIN_WASM_OPT"))
+}
+
+/// The `[toolchain]` table of the KAIN.toml in the working directory, if there is one
+fn project_config() -> ToolchainConfig {
+    std::fs::read_to_string("KAIN.toml")
+        .ok()
+        .and_then(|text| toml::from_str::<crate::packager::PackageManifest>(&text).ok())
+        .map(|manifest| manifest.toolchain)
+        .unwrap_or_default()
+}
+
+/// Commands tried for `cc` when none is set
+fn cc_candidates() -> Vec<Tool> {
+    let mut candidates: Vec<Tool> = ["clang", "cc", "zig cc"].iter().filter_map(|spec| Tool::parse(spec)).collect();
+    if cfg!(windows) {
+        let program_files = std::env::var("ProgramFiles").unwrap_or_else(|_| r"C:\Program Files".to_string());
+        candidates.push(Tool { program: Path::new(&program_files).join(r"LLVM\bin\clang.exe"), args: Vec::new() });
+    }
+    candidates
+}
+
+/// The first usable tool of: the variable `var`, the KAIN.toml setting
+/// `key`, then `candidates`. Each attempt is noted in `tried`. A tool that is
+/// set but unusable ends the search, so a typo isn't quietly replaced.
+fn pick(
+    tried: &mut Vec<String>,
+    var: &str,
+    env: fn(&str) -> Option<String>,
+    configured: Option<&str>,
+    key: &str,
+    candidates: Vec<Tool>,
+    accept: impl Fn(&Tool, &str) -> Result<(), String>,
+) -> Option<Tool> {
+    let check = |tool: &Tool| match tool.version() {
+        None => Err("not found".to_string()),
+        Some(version) => accept(tool, &version),
+    };
+
+    let set = [(var.to_string(), env(var)), (format!("`{}` in KAIN.toml [toolchain]", key), configured.map(str::to_string))];
+    for (origin, spec) in set {
+        let Some(spec) = spec else { continue };
+        let Some(tool) = Tool::parse(&spec) else {
+            tried.push(format!("{}: empty", origin));
+            return None;
+        };
+        return match check(&tool) {
+            Ok(()) => Some(tool),
+            Err(why) => {
+                tried.push(format!("{} = `{}`: {}", origin, spec, why));
+                None
+            }
+        };
+    }
+
+    for tool in candidates {
+        match check(&tool) {
+            Ok(()) => return Some(tool),
+            Err(why) => tried.push(format!("{}: {}", tool, why)),
+        }
+    }
+    None
+}
+
+fn missing(what: &str, tried: &[String], hint: &str) -> KainError {
+    let mut message = format!("No usable {}. Tried:", what);
+    for attempt in tried {
+        message.push_str("\n  ");
+        message.push_str(attempt);
+    }
+    message.push_str("\nTo fix this, ");
+    message.push_str(hint);
+    KainError::runtime(message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_settings_win_and_failures_list_what_was_tried() {
+        assert_eq!(Tool::parse("zig cc"), Some(Tool { program: "zig".into(), args: vec!["cc".to_string()] }));
+        assert_eq!(Tool::parse("  "), None);
+
+        // A set compiler that doesn't run is reported, not replaced by a probed one
+        let config = ToolchainConfig { cc: Some("kain-no-such-cc".to_string()), ..Default::default() };
+        let err = Toolchain::resolve(config, |_| None).unwrap_err().to_string();
+        assert!(err.contains("No usable C compiler"), "{}", err);
+        assert!(err.contains("`cc` in KAIN.toml [toolchain] = `kain-no-such-cc`: not found"), "{}", err);
+        assert!(!err.contains("clang:"), "{}", err);
+
+        let config = ToolchainConfig { cc: Some("kain-no-such-cc".to_string()), ..Default::default() };
+        let err = Toolchain::resolve(config, |var| (var == "KAIN_CC").then(|| "kain-env-cc --flag".to_string())).unwrap_err().to_string();
+        assert!(err.contains("KAIN_CC = `kain-env-cc --flag`: not found"), "{}", err);
+
+        let manifest: crate::packager::PackageManifest = toml::from_str("[package]\nname = \"game\"\nversion = \"0.1.0\"\n\n[toolchain]\ncc = \"zig cc\"\nlinker = \"gcc\"\n").unwrap();
+        assert_eq!(manifest.toolchain.cc.as_deref(), Some("zig cc"));
+        assert_eq!(manifest.toolchain.linker.as_deref(), Some("gcc"));
+    }
+}
@@ -1,10 +1,24 @@
 //! KAIN Type System - Rust-like with effect tracking
 
 use crate::ast::*;
-use crate::effects::EffectSet;
+use crate::ast::visit::{walk_expr, walk_expr_mut, walk_function, walk_item, walk_param, walk_pattern, Visitor, VisitorMut};
+use crate::effects::{Effect, EffectRegistry, EffectSet};
 use crate::span::Span;
 use crate::error::{KainError, KainResult};
-use std::collections::HashMap;
+use crate::stdlib::{stdlib, BuiltinFn};
+use std::collections::{HashMap, HashSet};
+
+pub mod typed_visit;
+mod alias;
+mod arguments;
+mod associated;
+mod newtype;
+mod option;
+mod traits;
+
+pub use associated::{lower_associated_calls, lower_associated_consts};
+pub use alias::{lower_module_type_aliases, lower_type_aliases};
+pub use option::{lower_options, OPTION};
 
 /// Type-checked AST node
 #[derive(Debug, Clone)]
@@ -12,6 +26,56 @@ pub struct TypedProgram {
     pub items: Vec<TypedItem>,
 }
 
+impl TypedProgram {
+    /// The program's named top-level items, in source order
+    pub fn symbols(&self) -> Vec<Symbol> {
+        let public = |v: &Visibility| matches!(v, Visibility::Public);
+        self.items
+            .iter()
+            .filter_map(|item| {
+                let (name, kind, span, ty, public) = match item {
+                    TypedItem::Function(f) => (&f.ast.name, SymbolKind::Function, f.ast.span, Some(f.resolved_type.clone()), public(&f.ast.visibility)),
+                    TypedItem::Struct(s) => (&s.ast.name, SymbolKind::Struct, s.ast.span, None, public(&s.ast.visibility)),
+                    TypedItem::Enum(e) => (&e.ast.name, SymbolKind::Enum, e.ast.span, None, public(&e.ast.visibility)),
+                    TypedItem::Component(c) => (&c.ast.name, SymbolKind::Component, c.ast.span, None, public(&c.ast.visibility)),
+                    TypedItem::Const(c) => (&c.ast.name, SymbolKind::Const, c.ast.span, Some(c.ty.clone()), public(&c.ast.visibility)),
+                    TypedItem::Static(s) => (&s.ast.name, SymbolKind::Static, s.ast.span, Some(s.ty.clone()), public(&s.ast.visibility)),
+                    TypedItem::Actor(a) => (&a.ast.name, SymbolKind::Actor, a.ast.span, None, false),
+                    TypedItem::Shader(s) => (&s.ast.name, SymbolKind::Shader, s.ast.span, None, false),
+                    TypedItem::Macro(m) => (&m.ast.name, SymbolKind::Macro, m.ast.span, None, false),
+                    TypedItem::Comptime(_) | TypedItem::Use(_) | TypedItem::Impl(_) | TypedItem::Test(_) => return None,
+                };
+                Some(Symbol { name: name.clone(), kind, span, ty, public })
+            })
+            .collect()
+    }
+}
+
+/// A named top-level item of a checked program
+#[derive(Debug, Clone, PartialEq)]
+pub struct Symbol {
+    pub name: String,
+    pub kind: SymbolKind,
+    pub span: Span,
+    /// Type of a function, const or static; `None` for type definitions and the rest
+    pub ty: Option<ResolvedType>,
+    /// Declared `pub`
+    pub public: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SymbolKind {
+    Function,
+    Struct,
+    Enum,
+    Component,
+    Const,
+    Static,
+    Actor,
+    Shader,
+    Macro,
+}
+
 // Comptime blocks should be empty/removed by now if fully evaluated, or we check them
 #[derive(Debug, Clone)]
 pub struct TypedComptime {
@@ -24,6 +88,12 @@ pub struct TypedConst {
     pub ty: ResolvedType,
 }
 
+#[derive(Debug, Clone)]
+pub struct TypedStatic {
+    pub ast: Static,
+    pub ty: ResolvedType,
+}
+
 #[derive(Debug, Clone)]
 pub struct TypedUse {
     pub ast: Use,
@@ -39,6 +109,7 @@ pub enum TypedItem {
     Enum(TypedEnum),
     Comptime(TypedComptime),
     Const(TypedConst),
+    Static(TypedStatic),
     Macro(TypedMacro),
     Use(TypedUse),
     Impl(TypedImpl),
@@ -71,6 +142,8 @@ pub struct TypedFunction {
     pub ast: Function,
     pub resolved_type: ResolvedType,
     pub effects: EffectSet,
+    /// Name the compiled artifact exposes the function under; see [`crate::symbols`]
+    pub symbol: String,
 }
 
 #[derive(Debug, Clone)]
@@ -128,19 +201,36 @@ pub enum IntSize { I8, I16, I32, I64, I128, Isize, U8, U16, U32, U64, U128, Usiz
 pub enum FloatSize { F32, F64 }
 
 /// Type environment for checking
+#[derive(Debug)]
 pub struct TypeEnv {
     scopes: Vec<HashMap<String, ResolvedType>>,
     types: HashMap<String, ResolvedType>,
+    /// Names of the program's `static mut` items
+    statics: HashSet<String>,
+    /// Effects and effect aliases the program declares
+    effects: EffectRegistry,
+    /// Effects of the functions that declare theirs, with aliases expanded
+    fn_effects: HashMap<String, EffectSet>,
+    /// Type aliases the program declares
+    aliases: alias::Aliases,
 }
 
 impl TypeEnv {
     pub fn new() -> Self {
-        let mut env = Self { scopes: vec![HashMap::new()], types: HashMap::new() };
+        let mut env = Self {
+            scopes: vec![HashMap::new()],
+            types: HashMap::new(),
+            statics: HashSet::new(),
+            effects: EffectRegistry::default(),
+            fn_effects: HashMap::new(),
+            aliases: alias::Aliases::default(),
+        };
         // Built-in types
         env.types.insert("Int".into(), ResolvedType::Int(IntSize::I64));
         env.types.insert("Float".into(), ResolvedType::Float(FloatSize::F64));
         env.types.insert("Bool".into(), ResolvedType::Bool);
         env.types.insert("String".into(), ResolvedType::String);
+        env.types.insert("Char".into(), ResolvedType::Char);
         env.types.insert("Vec2".into(), ResolvedType::Tuple(vec![
             ResolvedType::Float(FloatSize::F32),
             ResolvedType::Float(FloatSize::F32),
@@ -168,20 +258,172 @@ impl TypeEnv {
         }
         self.types.get(name)
     }
+
+    /// Resolve `ty` with the program's type aliases expanded
+    pub fn resolve(&self, ty: &Type) -> KainResult<ResolvedType> {
+        resolve_type(&self.aliases.expand(ty)?)
+    }
 }
 
 /// Main type checking entry point
 pub fn check(program: &Program) -> KainResult<TypedProgram> {
-    let mut env = TypeEnv::new();
+    let mut cx = ItemContext::new(program)?;
     let mut typed_items = Vec::new();
-    
     for item in &program.items {
-        typed_items.push(check_item(&mut env, item)?);
+        crate::crash::at(item.span());
+        typed_items.extend(cx.check(item)?);
     }
-    
     Ok(TypedProgram { items: typed_items })
 }
 
+/// What checking one item needs to know about the rest of its program: the
+/// statics, effects and structs it declares and the builtins it rebinds.
+/// Items are checked one at a time against it, so the query database can
+/// keep an item's result for as long as the item and its context are unchanged.
+#[derive(Debug)]
+pub struct ItemContext {
+    env: TypeEnv,
+    /// Builtins the program binds names over; `None` while it still imports
+    /// a module, since the module can redefine builtins and is only read
+    /// when the program is linked, which the editor's checks don't do
+    shadowed: Option<HashSet<String>>,
+    /// Fields of each struct the program declares
+    struct_fields: HashMap<String, Vec<String>>,
+    /// What `Type::name` can name on the program's types; `None` while it
+    /// still imports a module, whose impls may add to them
+    associated: Option<associated::Associated>,
+    /// The program's newtypes, if it declares any
+    newtypes: Option<newtype::Newtypes>,
+    /// The program's traits, which its impls implement and its type parameters are bound by
+    traits: traits::Traits,
+    /// Parameters of the program's functions, which calls to them are checked against
+    signatures: arguments::Signatures,
+    fingerprint: u64,
+}
+
+impl ItemContext {
+    /// Gather the program's declarations, reporting effects declared wrongly
+    pub fn new(program: &Program) -> KainResult<Self> {
+        use std::hash::{Hash, Hasher};
+
+        let mut env = TypeEnv::new();
+        env.aliases = alias::Aliases::of_items(&program.items, false);
+        env.aliases.validate()?;
+        for item in &program.items {
+            match item {
+                Item::Static(s) => {
+                    env.statics.insert(s.name.clone());
+                }
+                Item::Effect(e) => env.effects.declare(&e.name, e.alias.as_deref(), e.span)?,
+                _ => {}
+            }
+        }
+        // Aliases may name effects declared after them, so members are checked once all are known
+        for item in &program.items {
+            if let Item::Effect(EffectDecl { alias: Some(members), span, .. }) = item {
+                env.effects.expand(members, *span)?;
+            }
+        }
+        let mut declared_effects = Vec::new();
+        for item in &program.items {
+            match item {
+                Item::Function(f) if !f.effects.is_empty() => {
+                    let effects = env.effects.expand(&f.effects, f.span)?;
+                    env.fn_effects.insert(f.name.clone(), effects);
+                    declared_effects.push((&f.name, Some(&f.effects)));
+                }
+                Item::Effect(e) => declared_effects.push((&e.name, e.alias.as_ref())),
+                _ => {}
+            }
+        }
+
+        let shadowed = if program.items.iter().any(|item| matches!(item, Item::Use(_))) {
+            None
+        } else {
+            let mut bound = BoundNames::default();
+            bound.visit_program(program);
+            Some(bound.0.into_iter().filter(|name| stdlib().functions.contains_key(name)).collect::<HashSet<_>>())
+        };
+        let struct_fields = struct_fields(program.items.iter().filter_map(|item| match item {
+            Item::Struct(s) => Some(s),
+            _ => None,
+        }));
+        let associated = shadowed.is_some().then(|| associated::Associated::of_items(program.items.iter()));
+        let newtypes = newtype::Newtypes::of_program(program)?;
+        let traits = traits::Traits::of_program(program)?;
+        let signatures = arguments::Signatures::of_program(program);
+
+        fn sorted(names: &HashSet<String>) -> Vec<&String> {
+            let mut names: Vec<&String> = names.iter().collect();
+            names.sort();
+            names
+        }
+        let mut hasher = std::collections::hash_map::DefaultHasher::new();
+        sorted(&env.statics).hash(&mut hasher);
+        shadowed.as_ref().map(sorted).hash(&mut hasher);
+        let mut structs: Vec<_> = struct_fields.iter().collect();
+        structs.sort();
+        structs.hash(&mut hasher);
+        declared_effects.hash(&mut hasher);
+        associated.hash(&mut hasher);
+        newtypes.hash(&mut hasher);
+        traits.hash(&mut hasher);
+        signatures.hash(&mut hasher);
+        env.aliases.declarations().hash(&mut hasher);
+
+        Ok(ItemContext { env, shadowed, struct_fields, associated, newtypes, traits, signatures, fingerprint: hasher.finish() })
+    }
+
+    /// Equal for contexts any item checks the same way in
+    pub fn fingerprint(&self) -> u64 {
+        self.fingerprint
+    }
+
+    /// Check one of the program's items; `None` for those with nothing left to check
+    pub fn check(&mut self, item: &Item) -> KainResult<Option<TypedItem>> {
+        // Malformed items were already reported by the parser that recovered
+        // from them, and effect, type and trait declarations were checked with the context
+        if let Item::Error(_) | Item::Effect(_) | Item::TypeAlias(_) | Item::Trait(_) = item {
+            return Ok(None);
+        }
+        if let Some(shadowed) = &self.shadowed {
+            let mut checker = BuiltinCallChecker { shadowed, error: None };
+            checker.visit_item(item);
+            checker.error.map_or(Ok(()), Err)?;
+        }
+        let mut checker = StructUpdateChecker { fields: &self.struct_fields, error: None };
+        checker.visit_item(item);
+        checker.error.map_or(Ok(()), Err)?;
+        if let Some(types) = &self.associated {
+            let mut checker = associated::AssociatedItemChecker { types, self_type: None, error: None };
+            checker.visit_item(item);
+            checker.error.map_or(Ok(()), Err)?;
+        }
+        if let Some(newtypes) = &self.newtypes {
+            let mut checker = newtype::NewtypeChecker::new(newtypes);
+            checker.visit_item(item);
+            checker.error.map_or(Ok(()), Err)?;
+        }
+        let mut checker = traits::TraitChecker::new(&self.traits);
+        checker.visit_item(item);
+        checker.error.map_or(Ok(()), Err)?;
+        let mut checker = arguments::ArgumentChecker::new(&self.signatures);
+        checker.visit_item(item);
+        checker.error.map_or(Ok(()), Err)?;
+        // An item that fails part way leaves its scopes behind; the next one must not see them
+        let depth = self.env.scopes.len();
+        let mut checked = check_item(&mut self.env, item);
+        self.env.scopes.truncate(depth);
+        if let Ok(TypedItem::Impl(imp)) = &mut checked {
+            self.traits.add_defaults(&mut imp.ast);
+        }
+        if let Ok(item) = &mut checked {
+            self.signatures.order_named_args(item);
+        }
+        checked.map(Some)
+    }
+}
+
 fn check_item(env: &mut TypeEnv, item: &Item) -> KainResult<TypedItem> {
     match item {
         Item::Function(f) => Ok(TypedItem::Function(check_function(env, f)?)),
@@ -192,10 +434,13 @@ fn check_item(env: &mut TypeEnv, item: &Item) -> KainResult<TypedItem> {
         Item::Actor(a) => Ok(TypedItem::Actor(check_actor(env, a)?)),
         Item::Comptime(b) => Ok(TypedItem::Comptime(TypedComptime { ast: b.body.clone() })),
         Item::Const(c) => Ok(TypedItem::Const(check_const(env, c)?)),
+        Item::Static(s) => Ok(TypedItem::Static(TypedStatic { ast: s.clone(), ty: env.resolve(&s.ty)? })),
         Item::Macro(m) => Ok(TypedItem::Macro(TypedMacro { ast: m.clone() })),
         Item::Use(u) => Ok(TypedItem::Use(TypedUse { ast: u.clone() })),
         Item::Impl(i) => Ok(TypedItem::Impl(TypedImpl { ast: i.clone() })),
         Item::Test(t) => Ok(TypedItem::Test(TypedTest { ast: t.clone() })),
+        // Comptime resolves @cfg before checking; callers that skip it (the LSP) check every branch
+        Item::Cfg(c) => check_item(env, &c.item),
         _ => {
             // For now, ignore other items or provide dummy implementation
             // Since we are running in interpreter mode mostly, types are just for checking.
@@ -213,16 +458,16 @@ fn check_item(env: &mut TypeEnv, item: &Item) -> KainResult<TypedItem> {
     }
 }
 
-fn check_const(_env: &mut TypeEnv, c: &Const) -> KainResult<TypedConst> {
-    let ty = resolve_type(&c.ty)?;
+fn check_const(env: &mut TypeEnv, c: &Const) -> KainResult<TypedConst> {
+    let ty = env.resolve(&c.ty)?;
     // TODO: Check if value matches type
     Ok(TypedConst { ast: c.clone(), ty })
 }
 
-fn check_actor(_env: &mut TypeEnv, a: &Actor) -> KainResult<TypedActor> {
+fn check_actor(env: &mut TypeEnv, a: &Actor) -> KainResult<TypedActor> {
     let mut state_types = HashMap::new();
     for s in &a.state {
-        state_types.insert(s.name.clone(), resolve_type(&s.ty)?);
+        state_types.insert(s.name.clone(), env.resolve(&s.ty)?);
     }
     Ok(TypedActor { ast: a.clone(), state_types })
 }
@@ -231,37 +476,397 @@ fn check_function(env: &mut TypeEnv, f: &Function) -> KainResult<TypedFunction>
     env.push_scope();
     let mut param_types = Vec::new();
     for p in &f.params {
-        let ty = resolve_type(&p.ty)?;
+        let ty = env.resolve(&p.ty)?;
         env.define(p.name.clone(), ty.clone());
         param_types.push(ty);
     }
-    let ret = f.return_type.as_ref().map(|t| resolve_type(t)).transpose()?.unwrap_or(ResolvedType::Unit);
-    let effects = EffectSet::from(f.effects.clone());
+    let ret = f.return_type.as_ref().map(|t| env.resolve(t)).transpose()?.unwrap_or(ResolvedType::Unit);
+    let effects = env.effects.expand(&f.effects, f.span)?;
     env.pop_scope();
+    check_db_effect(f, &effects)?;
+    check_global_effect(f, &effects, &env.statics)?;
+    check_call_effects(f, &effects, &env.fn_effects)?;
+    check_try_returns(f, &ret)?;
+
+    let declared = crate::symbols::declared_name(f)?;
+    if declared.is_some() && !f.generics.is_empty() {
+        return Err(KainError::type_error(
+            format!("'{}' is generic, so its instances can't all be exported under one name", f.name),
+            f.span,
+        ));
+    }
     
     Ok(TypedFunction {
         ast: f.clone(),
         resolved_type: ResolvedType::Function { params: param_types, ret: Box::new(ret), effects: effects.clone() },
         effects,
+        symbol: declared.unwrap_or_else(|| f.name.clone()),
     })
 }
 
-fn check_struct(_env: &mut TypeEnv, s: &Struct) -> KainResult<TypedStruct> {
+/// A function that spells out its effects must list `Db` to reach a database
+fn check_db_effect(f: &Function, effects: &EffectSet) -> KainResult<()> {
+    if f.effects.is_empty() || effects.effects.contains(&Effect::Db) || effects.effects.contains(&Effect::Unsafe) {
+        return Ok(());
+    }
+    let mut finder = DbCallFinder(None);
+    finder.visit_block(&f.body);
+    match finder.0 {
+        Some((native, span)) => Err(KainError::effect_error(
+            format!("'{}' calls {} but does not declare the Db effect", f.name, native),
+            span,
+        )),
+        None => Ok(()),
+    }
+}
+
+struct DbCallFinder(Option<(String, Span)>);
+
+/// `?` returns `None` or the `Err` early, so a function using it must be
+/// able to return one; a function that leaves its return type out is not checked
+fn check_try_returns(f: &Function, ret: &ResolvedType) -> KainResult<()> {
+    let (Some(declared), false) = (&f.return_type, can_return_early(ret)) else {
+        return Ok(());
+    };
+    match first_try(&f.body) {
+        Some(span) => Err(KainError::type_error(
+            format!(
+                "`?` returns early with `None` or `Err`, so '{}' must return an Option or a Result, not {}",
+                f.name,
+                crate::lsp::format_type(declared)
+            ),
+            span,
+        )),
+        None => Ok(()),
+    }
+}
+
+fn can_return_early(ret: &ResolvedType) -> bool {
+    match ret {
+        ResolvedType::Option(_) | ResolvedType::Result(..) | ResolvedType::Generic(_) | ResolvedType::Unknown => true,
+        ResolvedType::Struct(name, _) => name == "Result",
+        _ => false,
+    }
+}
+
+/// The first `?` in a function body, outside the closures it defines
+pub(crate) fn first_try(body: &Block) -> Option<Span> {
+    let mut finder = TryFinder(None);
+    finder.visit_block(body);
+    finder.0
+}
+
+struct TryFinder(Option<Span>);
+
+impl Visitor for TryFinder {
+    fn visit_expr(&mut self, expr: &Expr) {
+        match expr {
+            _ if self.0.is_some() => {}
+            Expr::Try(_, span) => self.0 = Some(*span),
+            Expr::Lambda { .. } => {}
+            _ => walk_expr(self, expr),
+        }
+    }
+}
+
+impl Visitor for DbCallFinder {
+    fn visit_expr(&mut self, expr: &Expr) {
+        if self.0.is_some() {
+            return;
+        }
+        if let Expr::Call { callee, span, .. } = expr {
+            if let Expr::Ident(name, _) = &**callee {
+                if crate::runtime::DB_NATIVES.contains(&name.as_str()) {
+                    self.0 = Some((name.clone(), *span));
+                    return;
+                }
+            }
+        }
+        walk_expr(self, expr);
+    }
+}
+
+/// A function that spells out its effects may only call functions whose
+/// declared effects it has, directly or through an alias
+fn check_call_effects(f: &Function, effects: &EffectSet, fn_effects: &HashMap<String, EffectSet>) -> KainResult<()> {
+    if f.effects.is_empty() {
+        return Ok(());
+    }
+    let mut finder = EffectCallFinder { caller: effects.clone(), fn_effects, found: None };
+    finder.visit_block(&f.body);
+    match finder.found {
+        Some((callee, span)) => Err(KainError::effect_error(
+            format!("'{}' calls '{}' with {} but only declares {}", f.name, callee, fn_effects[&callee], effects),
+            span,
+        )),
+        None => Ok(()),
+    }
+}
+
+struct EffectCallFinder<'a> {
+    caller: EffectSet,
+    fn_effects: &'a HashMap<String, EffectSet>,
+    found: Option<(String, Span)>,
+}
+
+impl Visitor for EffectCallFinder<'_> {
+    fn visit_expr(&mut self, expr: &Expr) {
+        if self.found.is_some() {
+            return;
+        }
+        if let Expr::Handle { handler, effect, body, .. } = expr {
+            // The handler stands in for the effect, so the body may perform it
+            self.visit_expr(handler);
+            let outer = self.caller.clone();
+            self.caller.effects.insert(effect.clone());
+            self.visit_block(body);
+            self.caller = outer;
+            return;
+        }
+        if let Expr::Call { callee, span, .. } = expr {
+            if let Expr::Ident(name, _) = &**callee {
+                if self.fn_effects.get(name).is_some_and(|callee| !self.caller.can_call(callee)) {
+                    self.found = Some((name.clone(), *span));
+                    return;
+                }
+            }
+        }
+        walk_expr(self, expr);
+    }
+}
+
+/// A function that spells out its effects must list `Global` to use a `static mut`
+fn check_global_effect(f: &Function, effects: &EffectSet, statics: &HashSet<String>) -> KainResult<()> {
+    if f.effects.is_empty() || statics.is_empty() || effects.effects.contains(&Effect::Global) || effects.effects.contains(&Effect::Unsafe) {
+        return Ok(());
+    }
+    // Parameters and locals of the same name hide the static
+    let mut bound = BoundNames::default();
+    walk_function(&mut bound, f);
+    let mut finder = StaticFinder { statics, shadowed: bound.0, found: None };
+    finder.visit_block(&f.body);
+    match finder.found {
+        Some((name, span)) => Err(KainError::effect_error(
+            format!("'{}' uses static `{}` but does not declare the Global effect", f.name, name),
+            span,
+        )),
+        None => Ok(()),
+    }
+}
+
+struct StaticFinder<'a> {
+    statics: &'a HashSet<String>,
+    shadowed: HashSet<String>,
+    found: Option<(String, Span)>,
+}
+
+impl Visitor for StaticFinder<'_> {
+    fn visit_expr(&mut self, expr: &Expr) {
+        if self.found.is_some() {
+            return;
+        }
+        if let Expr::Ident(name, span) = expr {
+            if self.statics.contains(name) && !self.shadowed.contains(name) {
+                self.found = Some((name.clone(), *span));
+                return;
+            }
+        }
+        walk_expr(self, expr);
+    }
+}
+
+/// Every name the program binds: items, functions, parameters and patterns
+#[derive(Default)]
+pub(crate) struct BoundNames(pub(crate) HashSet<String>);
+
+impl Visitor for BoundNames {
+    fn visit_item(&mut self, item: &Item) {
+        if let Some(name) = item.name() {
+            self.0.insert(name.to_string());
+        }
+        walk_item(self, item);
+    }
+
+    fn visit_function(&mut self, function: &Function) {
+        self.0.insert(function.name.clone());
+        walk_function(self, function);
+    }
+
+    fn visit_param(&mut self, param: &Param) {
+        self.0.insert(param.name.clone());
+        walk_param(self, param);
+    }
+
+    fn visit_pattern(&mut self, pattern: &Pattern) {
+        if let Pattern::Binding { name, .. } = pattern {
+            self.0.insert(name.clone());
+        }
+        walk_pattern(self, pattern);
+    }
+}
+
+/// Checks calls to builtins against their `StdLib` signatures: argument
+/// count, and literal arguments of the wrong kind. Names the program binds
+/// itself are skipped.
+struct BuiltinCallChecker<'a> {
+    shadowed: &'a HashSet<String>,
+    error: Option<KainError>,
+}
+
+impl BuiltinCallChecker<'_> {
+    fn check_call(&self, builtin: &BuiltinFn, args: &[CallArg], span: Span) -> KainResult<()> {
+        // How many arguments `...xs` passes is known only at run time
+        if args.iter().any(|a| a.spread) {
+            return Ok(());
+        }
+        if let Some(message) = builtin.arity_error(args.len()) {
+            return Err(KainError::type_error(message, span));
+        }
+        for (arg, (param, ty)) in args.iter().zip(builtin.params.iter()) {
+            let ty = ty.trim_end_matches('?').trim_end_matches("...");
+            let found = match &arg.value {
+                Expr::Int(..) => "Int",
+                Expr::Float(..) => "Float",
+                Expr::String(..) | Expr::FString(..) => "String",
+                Expr::Bool(..) => "Bool",
+                Expr::Char(..) => "Char",
+                _ => continue,
+            };
+            let accepts = match ty {
+                "Int" | "String" | "Bool" | "Char" => found == ty,
+                "Float" | "Number" => found == "Int" || found == "Float",
+                _ => true,
+            };
+            if !accepts {
+                return Err(KainError::type_error(
+                    format!("{}: argument '{}' must be {}, found {}: {}", builtin.name, param, ty, found, builtin.signature()),
+                    arg.span,
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Visitor for BuiltinCallChecker<'_> {
+    fn visit_expr(&mut self, expr: &Expr) {
+        if self.error.is_some() {
+            return;
+        }
+        if let Expr::Call { callee, args, span } = expr {
+            if let Expr::Ident(name, _) = &**callee {
+                if let Some(builtin) = stdlib().functions.get(name).filter(|_| !self.shadowed.contains(name)) {
+                    if let Err(e) = self.check_call(builtin, args, *span) {
+                        self.error = Some(e);
+                        return;
+                    }
+                }
+            }
+        }
+        walk_expr(self, expr);
+    }
+}
+
+/// Fields of each struct the program declares
+fn struct_fields<'a>(items: impl Iterator<Item = &'a Struct>) -> HashMap<String, Vec<String>> {
+    items.map(|s| (s.name.clone(), s.fields.iter().map(|f| f.name.clone()).collect())).collect()
+}
+
+/// The fields given alongside `..base` must belong to the struct; structs
+/// from imported modules are not known here and are left to the interpreter
+struct StructUpdateChecker<'a> {
+    fields: &'a HashMap<String, Vec<String>>,
+    error: Option<KainError>,
+}
+
+impl Visitor for StructUpdateChecker<'_> {
+    fn visit_expr(&mut self, expr: &Expr) {
+        if self.error.is_some() {
+            return;
+        }
+        if let Expr::Struct { name, fields, base: Some(_), span } = expr {
+            if let Some(declared) = self.fields.get(name) {
+                if let Some((field, _)) = fields.iter().find(|(field, _)| !declared.contains(field)) {
+                    self.error = Some(KainError::type_error(format!("`{}` has no field `{}`", name, field), *span));
+                    return;
+                }
+            }
+        }
+        walk_expr(self, expr);
+    }
+}
+
+/// Rewrite `Point { ..p, x: 1 }` into a literal that names every field,
+/// `Point { x: 1, y: p.y }`, so backends only ever see plain struct literals.
+/// A base that is not a variable or field is bound to a temporary first, so
+/// it is evaluated once.
+pub fn expand_struct_updates(program: &mut TypedProgram) {
+    let structs = program.items.iter().filter_map(|item| match item {
+        TypedItem::Struct(s) => Some(&s.ast),
+        _ => None,
+    });
+    let mut expander = StructUpdateExpander { fields: struct_fields(structs), temps: 0 };
+    typed_visit::TypedVisitorMut::visit_typed_program_mut(&mut expander, program);
+}
+
+struct StructUpdateExpander {
+    fields: HashMap<String, Vec<String>>,
+    temps: usize,
+}
+
+impl VisitorMut for StructUpdateExpander {
+    fn visit_expr_mut(&mut self, expr: &mut Expr) {
+        walk_expr_mut(self, expr);
+        let Expr::Struct { name, fields, base, span } = expr else { return };
+        let Some(declared) = self.fields.get(name) else { return };
+        let Some(source) = base.take() else { return };
+        let span = *span;
+        fn is_place(expr: &Expr) -> bool {
+            match expr {
+                Expr::Ident(..) => true,
+                Expr::Field { object, .. } => is_place(object),
+                _ => false,
+            }
+        }
+        let (source, binding) = if is_place(&source) {
+            (*source, None)
+        } else {
+            let temp = format!("__update{}", self.temps);
+            self.temps += 1;
+            let pattern = Pattern::Binding { name: temp.clone(), mutable: false, span };
+            (Expr::Ident(temp, span), Some(Stmt::Let { pattern, ty: None, value: Some(*source), span }))
+        };
+        for field in declared {
+            if !fields.iter().any(|(given, _)| given == field) {
+                let copied = Expr::Field { object: Box::new(source.clone()), field: field.clone(), span };
+                fields.push((field.clone(), copied));
+            }
+        }
+        if let Some(binding) = binding {
+            let literal = std::mem::replace(expr, Expr::Error(span));
+            *expr = Expr::Block(Block { stmts: vec![binding, Stmt::Expr(literal)], span }, span);
+        }
+    }
+}
+
+impl typed_visit::TypedVisitorMut for StructUpdateExpander {}
+
+fn check_struct(env: &mut TypeEnv, s: &Struct) -> KainResult<TypedStruct> {
     let mut fields = HashMap::new();
     for f in &s.fields {
-        fields.insert(f.name.clone(), resolve_type(&f.ty)?);
+        fields.insert(f.name.clone(), env.resolve(&f.ty)?);
     }
     Ok(TypedStruct { ast: s.clone(), field_types: fields })
 }
 
-fn check_enum(_env: &mut TypeEnv, e: &Enum) -> KainResult<TypedEnum> {
+fn check_enum(env: &mut TypeEnv, e: &Enum) -> KainResult<TypedEnum> {
     let mut variant_payload_types: HashMap<String, Vec<ResolvedType>> = HashMap::new();
 
     for v in &e.variants {
         let payload_types = match &v.fields {
             VariantFields::Unit => Vec::new(),
-            VariantFields::Tuple(items) => items.iter().map(resolve_type).collect::<Result<Vec<_>, _>>()?,
-            VariantFields::Struct(fields) => fields.iter().map(|f| resolve_type(&f.ty)).collect::<Result<Vec<_>, _>>()?,
+            VariantFields::Tuple(items) => items.iter().map(|t| env.resolve(t)).collect::<Result<Vec<_>, _>>()?,
+            VariantFields::Struct(fields) => fields.iter().map(|f| env.resolve(&f.ty)).collect::<Result<Vec<_>, _>>()?,
         };
         variant_payload_types.insert(v.name.clone(), payload_types);
     }
@@ -272,27 +877,34 @@ fn check_enum(_env: &mut TypeEnv, e: &Enum) -> KainResult<TypedEnum> {
     })
 }
 
-fn check_component(_env: &mut TypeEnv, c: &Component) -> KainResult<TypedComponent> {
+fn check_component(env: &mut TypeEnv, c: &Component) -> KainResult<TypedComponent> {
     let mut props = HashMap::new();
     for p in &c.props {
-        props.insert(p.name.clone(), resolve_type(&p.ty)?);
+        props.insert(p.name.clone(), env.resolve(&p.ty)?);
     }
     Ok(TypedComponent { ast: c.clone(), prop_types: props })
 }
 
-fn check_shader(_env: &mut TypeEnv, s: &Shader) -> KainResult<TypedShader> {
-    let inputs: Vec<_> = s.inputs.iter().map(|p| resolve_type(&p.ty)).collect::<Result<_, _>>()?;
-    let output = resolve_type(&s.outputs)?;
+fn check_shader(env: &mut TypeEnv, s: &Shader) -> KainResult<TypedShader> {
+    let inputs: Vec<_> = s.inputs.iter().map(|p| env.resolve(&p.ty)).collect::<Result<_, _>>()?;
+    let output = env.resolve(&s.outputs)?;
     Ok(TypedShader { ast: s.clone(), input_types: inputs, output_type: output })
 }
 
 pub fn resolve_type(ty: &Type) -> KainResult<ResolvedType> {
     match ty {
+        Type::Named { name, generics, span } if name == "Option" => match generics.as_slice() {
+            [] => Ok(ResolvedType::Option(Box::new(ResolvedType::Unknown))),
+            [inner] => Ok(ResolvedType::Option(Box::new(resolve_type(inner)?))),
+            _ => Err(KainError::type_error(format!("`Option` takes one type argument, found {}", generics.len()), *span)),
+        },
+        Type::Option(inner, _) => Ok(ResolvedType::Option(Box::new(resolve_type(inner)?))),
         Type::Named { name, .. } => match name.as_str() {
             "Int" => Ok(ResolvedType::Int(IntSize::I64)),
             "Float" => Ok(ResolvedType::Float(FloatSize::F64)),
             "Bool" => Ok(ResolvedType::Bool),
             "String" => Ok(ResolvedType::String),
+            "Char" => Ok(ResolvedType::Char),
             _ => {
                 // Check if this is a generic type parameter (single uppercase letter or _T style)
                 if name.len() == 1 && name.chars().next().map(|c| c.is_uppercase()).unwrap_or(false) {
@@ -306,6 +918,8 @@ pub fn resolve_type(ty: &Type) -> KainResult<ResolvedType> {
                 }
             }
         },
+        // Ownership checks references; after that they are the values they refer to
+        Type::Ref { inner, .. } => resolve_type(inner),
         Type::Unit(_) => Ok(ResolvedType::Unit),
         Type::Never(_) => Ok(ResolvedType::Never),
         Type::Tuple(inner, _) => Ok(ResolvedType::Tuple(inner.iter().map(resolve_type).collect::<Result<_, _>>()?)),
@@ -323,21 +937,7 @@ pub fn resolve_type(ty: &Type) -> KainResult<ResolvedType> {
 }
 
 fn item_span(item: &Item) -> Span {
-    match item {
-        Item::Function(f) => f.span,
-        Item::Struct(s) => s.span,
-        Item::Enum(e) => e.span,
-        Item::Component(c) => c.span,
-        Item::Shader(s) => s.span,
-        Item::Actor(a) => a.span,
-        Item::Comptime(b) => b.span,
-        Item::Const(c) => c.span,
-        Item::Macro(m) => m.span,
-        Item::Use(u) => u.span,
-        Item::Impl(i) => i.span,
-        Item::Test(t) => t.span,
-        _ => Span::new(0, 0),
-    }
+    item.span()
 }
 
 impl From<Vec<crate::effects::Effect>> for EffectSet {
@@ -348,3 +948,20 @@ impl From<Vec<crate::effects::Effect>> for EffectSet {
     }
 }
 
+#[cfg(test)]
+mod tests {
+    use crate::{compile, eval_snippet, CompileOptions, CompileTarget};
+
+    #[test]
+    fn test_struct_update_copies_the_fields_not_given() {
+        let source = "struct Point:\n    x: Int\n    y: Int\n\nfn moved(p: Point) -> Point:\n    return Point { ..p, x: 5 }\n\nfn main():\n    let p = Point { x: 1, y: 2 }\n    let q = moved(p)\n    let r = Point { ..Point { x: 7, y: 8 }, y: 0 }\n    println(p.x, p.y, q.x, q.y, r.x, r.y)\n";
+        let result = eval_snippet(source, &CompileOptions::default());
+        assert!(result.diagnostics.is_empty(), "{:?}", result.diagnostics);
+        assert_eq!(result.stdout.trim_end(), "1 2 5 2 7 0");
+
+        let js = String::from_utf8(compile(source, CompileTarget::Js).unwrap()).unwrap();
+        assert!(js.contains("new Point(5, p.y)"), "{}", js);
+        let err = compile(&source.replace("x: 5 }", "z: 5 }"), CompileTarget::Js).unwrap_err();
+        assert!(err.to_string().contains("`Point` has no field `z`"), "{}", err);
+    }
+}
@@ -0,0 +1,289 @@
+//! Type aliases: `type Handler<T> = fn(Request, T) -> Response`
+//!
+//! An alias is another name for the type it stands for, with its parameters
+//! filled in where it is used: `Handler<Int>` resolves as
+//! `fn(Request, Int) -> Response`. Diagnostics, hovers and docs work from the
+//! source, so they show the alias as written. Once the program is checked its
+//! aliases are expanded in place, and its newtypes (see `newtype`) replaced by
+//! the types they wrap, so backends only ever see the types underneath.
+
+use std::collections::{HashMap, HashSet};
+
+use crate::ast::visit::{walk_expr_mut, walk_function_mut, walk_item_mut, walk_param_mut, walk_stmt_mut, VisitorMut};
+use crate::ast::*;
+use crate::error::{KainError, KainResult};
+
+use super::typed_visit::{walk_typed_item_mut, TypedVisitorMut};
+use super::{resolve_type, ResolvedType, TypedItem, TypedProgram};
+
+/// The program's items, with those behind `@cfg` in place of their conditions
+pub(super) fn items(items: &[Item]) -> impl Iterator<Item = &Item> {
+    items.iter().map(|item| match item {
+        Item::Cfg(c) => &*c.item,
+        item => item,
+    })
+}
+
+/// Aliases by name
+#[derive(Debug, Default)]
+pub(super) struct Aliases(HashMap<String, TypeAlias>);
+
+impl Aliases {
+    /// The aliases `items` declare, and their newtypes too if `newtypes`
+    pub(super) fn of_items(items: &[Item], newtypes: bool) -> Self {
+        Aliases(
+            self::items(items)
+                .filter_map(|item| match item {
+                    Item::TypeAlias(t) if newtypes || !t.newtype => Some((t.name.clone(), t.clone())),
+                    _ => None,
+                })
+                .collect(),
+        )
+    }
+
+    /// Reject aliases that stand for themselves, or use another alias wrongly
+    pub(super) fn validate(&self) -> KainResult<()> {
+        let mut names: Vec<&String> = self.0.keys().collect();
+        names.sort();
+        for name in names {
+            self.expand(&self.0[name].target)?;
+        }
+        Ok(())
+    }
+
+    /// Names, parameters and targets as written, for the checker's fingerprint
+    pub(super) fn declarations(&self) -> Vec<(&String, Vec<&String>, String)> {
+        let mut declarations: Vec<_> = self
+            .0
+            .values()
+            .map(|t| (&t.name, t.generics.iter().map(|g| &g.name).collect(), crate::lsp::format_type(&t.target)))
+            .collect();
+        declarations.sort();
+        declarations
+    }
+
+    /// `ty` with every alias in it replaced by the type it stands for
+    pub(super) fn expand(&self, ty: &Type) -> KainResult<Type> {
+        self.expand_within(ty, self.0.len())
+    }
+
+    /// `depth` is how many more aliases may be expanded inside one another,
+    /// which only runs out for an alias that stands for itself
+    fn expand_within(&self, ty: &Type, depth: usize) -> KainResult<Type> {
+        let expand_all = |types: &[Type]| types.iter().map(|t| self.expand_within(t, depth)).collect::<KainResult<Vec<_>>>();
+        let expand_box = |t: &Type| self.expand_within(t, depth).map(Box::new);
+        Ok(match ty {
+            Type::Named { name, generics, span } => {
+                let args = expand_all(generics)?;
+                let Some(alias) = self.0.get(name) else {
+                    return Ok(Type::Named { name: name.clone(), generics: args, span: *span });
+                };
+                if depth == 0 {
+                    return Err(KainError::type_error(format!("type alias `{}` stands for itself", name), *span));
+                }
+                if args.len() != alias.generics.len() {
+                    let plural = if alias.generics.len() == 1 { "" } else { "s" };
+                    return Err(KainError::type_error(
+                        format!("`{}` takes {} type argument{}, found {}", name, alias.generics.len(), plural, args.len()),
+                        *span,
+                    ));
+                }
+                let params: HashMap<&str, Type> = alias.generics.iter().map(|g| g.name.as_str()).zip(args).collect();
+                self.expand_within(&substitute(&alias.target, &params), depth - 1)?
+            }
+            Type::Tuple(types, span) => Type::Tuple(expand_all(types)?, *span),
+            Type::Array(inner, len, span) => Type::Array(expand_box(inner)?, *len, *span),
+            Type::Slice(inner, span) => Type::Slice(expand_box(inner)?, *span),
+            Type::Option(inner, span) => Type::Option(expand_box(inner)?, *span),
+            Type::Result(ok, err, span) => Type::Result(expand_box(ok)?, expand_box(err)?, *span),
+            Type::Ref { mutable, inner, lifetime, span } => {
+                Type::Ref { mutable: *mutable, inner: expand_box(inner)?, lifetime: lifetime.clone(), span: *span }
+            }
+            Type::Function { params, return_type, effects, span } => Type::Function {
+                params: expand_all(params)?,
+                return_type: expand_box(return_type)?,
+                effects: effects.clone(),
+                span: *span,
+            },
+            Type::Impl { trait_name, generics, span } => {
+                Type::Impl { trait_name: trait_name.clone(), generics: expand_all(generics)?, span: *span }
+            }
+            Type::Infer(_) | Type::Never(_) | Type::Unit(_) => ty.clone(),
+        })
+    }
+}
+
+/// `ty` with the alias's parameters replaced by the arguments it was given
+fn substitute(ty: &Type, params: &HashMap<&str, Type>) -> Type {
+    let all = |types: &[Type]| types.iter().map(|t| substitute(t, params)).collect();
+    let boxed = |t: &Type| Box::new(substitute(t, params));
+    match ty {
+        Type::Named { name, generics, .. } if generics.is_empty() && params.contains_key(name.as_str()) => {
+            params[name.as_str()].clone()
+        }
+        Type::Named { name, generics, span } => Type::Named { name: name.clone(), generics: all(generics), span: *span },
+        Type::Tuple(types, span) => Type::Tuple(all(types), *span),
+        Type::Array(inner, len, span) => Type::Array(boxed(inner), *len, *span),
+        Type::Slice(inner, span) => Type::Slice(boxed(inner), *span),
+        Type::Option(inner, span) => Type::Option(boxed(inner), *span),
+        Type::Result(ok, err, span) => Type::Result(boxed(ok), boxed(err), *span),
+        Type::Ref { mutable, inner, lifetime, span } => {
+            Type::Ref { mutable: *mutable, inner: boxed(inner), lifetime: lifetime.clone(), span: *span }
+        }
+        Type::Function { params: types, return_type, effects, span } => Type::Function {
+            params: all(types),
+            return_type: boxed(return_type),
+            effects: effects.clone(),
+            span: *span,
+        },
+        Type::Impl { trait_name, generics, span } => {
+            Type::Impl { trait_name: trait_name.clone(), generics: all(generics), span: *span }
+        }
+        Type::Infer(_) | Type::Never(_) | Type::Unit(_) => ty.clone(),
+    }
+}
+
+/// Expand the program's aliases and erase its newtypes: wraps and unwraps
+/// become the value inside, and the types in signatures the types underneath
+pub fn lower_type_aliases(program: &mut TypedProgram, items: &[Item]) {
+    let mut lowering = Lowering::of(items);
+    if !lowering.aliases.0.is_empty() {
+        lowering.visit_typed_program_mut(program);
+    }
+}
+
+/// The same for a module's items, which run without being checked
+pub fn lower_module_type_aliases(items: &mut [Item]) {
+    let mut lowering = Lowering::of(items);
+    if !lowering.aliases.0.is_empty() {
+        for item in items.iter_mut() {
+            lowering.visit_item_mut(item);
+        }
+    }
+}
+
+struct Lowering {
+    aliases: Aliases,
+    newtypes: HashSet<String>,
+}
+
+impl Lowering {
+    fn of(items: &[Item]) -> Self {
+        let aliases = Aliases::of_items(items, true);
+        let newtypes = aliases.0.values().filter(|t| t.newtype).map(|t| t.name.clone()).collect();
+        Lowering { aliases, newtypes }
+    }
+
+    /// Types that fail to expand were reported by the checker, or belong to a module that is never checked
+    fn expand(&self, ty: &mut Type) {
+        if let Ok(expanded) = self.aliases.expand(ty) {
+            *ty = expanded;
+        }
+    }
+}
+
+fn resolved(ty: &Type) -> ResolvedType {
+    resolve_type(ty).unwrap_or(ResolvedType::Unknown)
+}
+
+impl VisitorMut for Lowering {
+    fn visit_item_mut(&mut self, item: &mut Item) {
+        match item {
+            Item::Struct(s) => s.fields.iter_mut().for_each(|f| self.expand(&mut f.ty)),
+            Item::Const(c) => self.expand(&mut c.ty),
+            Item::Static(s) => self.expand(&mut s.ty),
+            _ => {}
+        }
+        walk_item_mut(self, item);
+    }
+
+    fn visit_function_mut(&mut self, function: &mut Function) {
+        if let Some(ty) = &mut function.return_type {
+            self.expand(ty);
+        }
+        walk_function_mut(self, function);
+    }
+
+    fn visit_param_mut(&mut self, param: &mut Param) {
+        self.expand(&mut param.ty);
+        walk_param_mut(self, param);
+    }
+
+    fn visit_stmt_mut(&mut self, stmt: &mut Stmt) {
+        if let Stmt::Let { ty: Some(ty), .. } = stmt {
+            self.expand(ty);
+        }
+        walk_stmt_mut(self, stmt);
+    }
+
+    fn visit_expr_mut(&mut self, expr: &mut Expr) {
+        walk_expr_mut(self, expr);
+        let inner = match expr {
+            Expr::Call { callee, args, .. } if args.len() == 1 && args[0].name.is_none() => match &**callee {
+                Expr::Ident(name, _) if self.newtypes.contains(name) => args.pop().map(|arg| arg.value),
+                _ => None,
+            },
+            Expr::EnumVariant { enum_name, variant, fields: EnumVariantFields::Tuple(args), .. }
+                if variant == "unwrap" && args.len() == 1 && self.newtypes.contains(enum_name) =>
+            {
+                args.pop()
+            }
+            _ => None,
+        };
+        if let Some(inner) = inner {
+            *expr = inner;
+        }
+    }
+}
+
+impl TypedVisitorMut for Lowering {
+    fn visit_typed_item_mut(&mut self, item: &mut TypedItem) {
+        walk_typed_item_mut(self, item);
+        // Types resolved from the signatures as written are resolved again from the expanded ones
+        match item {
+            TypedItem::Function(f) => {
+                if let ResolvedType::Function { params, ret, .. } = &mut f.resolved_type {
+                    *params = f.ast.params.iter().map(|p| resolved(&p.ty)).collect();
+                    **ret = f.ast.return_type.as_ref().map_or(ResolvedType::Unit, resolved);
+                }
+            }
+            TypedItem::Struct(s) => {
+                s.ast.fields.iter_mut().for_each(|f| self.expand(&mut f.ty));
+                s.field_types = s.ast.fields.iter().map(|f| (f.name.clone(), resolved(&f.ty))).collect();
+            }
+            TypedItem::Const(c) => {
+                self.expand(&mut c.ast.ty);
+                c.ty = resolved(&c.ast.ty);
+            }
+            TypedItem::Static(s) => {
+                self.expand(&mut s.ast.ty);
+                s.ty = resolved(&s.ast.ty);
+            }
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{compile, eval_snippet, CompileOptions, CompileSession, CompileTarget};
+
+    #[test]
+    fn test_generic_type_aliases_expand_where_used() {
+        let source = "type Pair<T> = (T, T)\ntype Handler<T> = fn(T) -> T\n\nfn apply(f: Handler<Int>, x: Int) -> Int:\n    return f(x)\n\nfn double(x: Int) -> Int:\n    return x * 2\n\nfn main():\n    let p: Pair<Int> = (1, 2)\n    println(apply(double, 21))\n";
+        let result = eval_snippet(source, &CompileOptions::default());
+        assert!(result.diagnostics.is_empty(), "{:?}", result.diagnostics);
+        assert_eq!(result.stdout.trim_end(), "42");
+        let mut session = CompileSession::new(source, CompileOptions::default()).unwrap();
+        for target in [CompileTarget::Rust, CompileTarget::Js, CompileTarget::Wasm] {
+            assert_eq!(session.compile(target).unwrap(), compile(source, target).unwrap(), "{:?}", target);
+        }
+        let rust = String::from_utf8(session.compile(CompileTarget::Rust).unwrap()).unwrap();
+        assert!(!rust.contains("Pair") && !rust.contains("Handler"), "{}", rust);
+
+        let err = compile(&source.replace("Handler<Int>", "Handler<Int, Int>"), CompileTarget::Interpret).unwrap_err();
+        assert!(err.to_string().contains("`Handler` takes 1 type argument, found 2"), "{}", err);
+        let err = compile(&format!("type Loop = Pair<Loop>\n{}", source), CompileTarget::Interpret).unwrap_err();
+        assert!(err.to_string().contains("type alias `Loop` stands for itself"), "{}", err);
+    }
+}
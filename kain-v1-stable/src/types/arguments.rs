@@ -0,0 +1,280 @@
+//! Call arguments: `...rest` parameters, `...xs` spreads and named arguments
+//!
+//! ```ignore
+//! fn log_call(f, ...args):
+//!     println("calling with", args)
+//!     return f(...args)
+//!
+//! fn clamp(value: Int, low: Int, high: Int) -> Int:
+//!     return min(max(value, low), high)
+//!
+//! log_call(clamp, 12, 0, 10)
+//! clamp(12, high = 10, low = 0)
+//! ```
+//!
+//! A function's last parameter may be `...rest`, which takes the arguments
+//! left after the others as an array, and `...xs` in a call passes the
+//! elements of `xs` as arguments of their own, so a wrapper forwards what it
+//! was given unchanged. A named argument gives the parameter of that name.
+//! Calls to the program's own functions are checked against their parameters
+//! here and their named arguments put in parameter order (and evaluated in
+//! it), so backends only see positional ones. How many arguments a spread
+//! passes is only known at run time, so calls that spread are not counted.
+
+use std::collections::{BTreeMap, HashSet};
+
+use crate::ast::visit::{walk_expr, walk_expr_mut, walk_function, walk_function_mut, Visitor, VisitorMut};
+use crate::ast::*;
+use crate::error::{KainError, KainResult};
+use crate::span::Span;
+
+use super::alias::items;
+use super::newtype::BindingCounter;
+use super::typed_visit::TypedVisitorMut;
+use super::TypedItem;
+
+/// A function's parameter names, and whether the last is `...rest`
+#[derive(Debug, Clone, PartialEq, Hash)]
+struct Signature {
+    params: Vec<String>,
+    variadic: bool,
+}
+
+impl Signature {
+    /// Parameters before `...rest`
+    fn fixed(&self) -> usize {
+        self.params.len() - usize::from(self.variadic)
+    }
+}
+
+/// The parameters of each function the program declares once
+#[derive(Debug, Default, Hash)]
+pub(super) struct Signatures(BTreeMap<String, Signature>);
+
+impl Signatures {
+    pub(super) fn of_program(program: &Program) -> Self {
+        let mut declared: BTreeMap<String, Option<Signature>> = BTreeMap::new();
+        for item in items(&program.items) {
+            let Item::Function(f) = item else { continue };
+            let signature = Signature {
+                params: f.params.iter().map(|p| p.name.clone()).collect(),
+                variadic: f.params.last().is_some_and(|p| p.variadic),
+            };
+            // One declared under several `@cfg`s is checked where it runs
+            declared
+                .entry(f.name.clone())
+                .and_modify(|known| {
+                    if known.as_ref() != Some(&signature) {
+                        *known = None;
+                    }
+                })
+                .or_insert(Some(signature));
+        }
+        Signatures(declared.into_iter().filter_map(|(name, s)| Some((name, s?))).collect())
+    }
+
+    /// Put the named arguments of an item's calls in parameter order
+    pub(super) fn order_named_args(&self, item: &mut TypedItem) {
+        NamedArgs { signatures: self, locals: HashSet::new() }.visit_typed_item_mut(item);
+    }
+
+    /// The function a call's callee names, unless a local binds the name over it
+    fn callee(&self, callee: &Expr, locals: &HashSet<String>) -> Option<(&str, &Signature)> {
+        let Expr::Ident(name, _) = callee else { return None };
+        let signature = self.0.get(name).filter(|_| !locals.contains(name))?;
+        Some((name, signature))
+    }
+}
+
+/// Names a function binds itself, which its calls may call instead
+fn locals(function: &Function) -> HashSet<String> {
+    let mut bound = BindingCounter::default();
+    walk_function(&mut bound, function);
+    bound.0.into_keys().collect()
+}
+
+fn arguments(n: usize) -> String {
+    format!("{} argument{}", n, if n == 1 { "" } else { "s" })
+}
+
+/// `...rest` comes last and gathers an Array; calls give each parameter once
+pub(super) struct ArgumentChecker<'a> {
+    signatures: &'a Signatures,
+    locals: HashSet<String>,
+    pub(super) error: Option<KainError>,
+}
+
+impl<'a> ArgumentChecker<'a> {
+    pub(super) fn new(signatures: &'a Signatures) -> Self {
+        ArgumentChecker { signatures, locals: HashSet::new(), error: None }
+    }
+
+    fn check_params(&self, function: &Function) -> KainResult<()> {
+        for (i, param) in function.params.iter().enumerate().filter(|(_, p)| p.variadic) {
+            if i + 1 != function.params.len() {
+                return Err(KainError::type_error(
+                    format!("`...{}` takes the arguments left after the others, so it must be the last parameter", param.name),
+                    param.span,
+                ));
+            }
+            match &param.ty {
+                Type::Infer(_) => {}
+                Type::Named { name, .. } if name == "Array" => {}
+                ty => {
+                    return Err(KainError::type_error(
+                        format!("`...{}` gathers its arguments into an Array, not {}", param.name, crate::lsp::format_type(ty)),
+                        param.span,
+                    ))
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn check_call(&self, name: &str, signature: &Signature, args: &[CallArg], span: Span) -> KainResult<()> {
+        let fixed = signature.fixed();
+        let mut given = vec![false; fixed];
+        let mut positional = 0;
+        let mut named = false;
+        for arg in args {
+            let Some(param) = &arg.name else {
+                if named {
+                    return Err(KainError::type_error("positional arguments must come before named ones", arg.span));
+                }
+                positional += 1;
+                continue;
+            };
+            named = true;
+            let Some(i) = signature.params.iter().position(|p| p == param) else {
+                return Err(KainError::type_error(format!("`{}` has no parameter `{}`", name, param), arg.span));
+            };
+            if i == fixed {
+                return Err(KainError::type_error(
+                    format!("`...{}` takes the arguments left over, so it can't be given by name", param),
+                    arg.span,
+                ));
+            }
+            if i < positional || given[i] {
+                return Err(KainError::type_error(format!("`{}` is given `{}` twice", name, param), arg.span));
+            }
+            if let Some(spread) = args.iter().find(|a| a.spread) {
+                return Err(KainError::type_error(
+                    "a call that spreads its arguments can't name them too, since which parameter each fills is known only at run time",
+                    spread.span,
+                ));
+            }
+            given[i] = true;
+        }
+        if args.iter().any(|a| a.spread) {
+            return Ok(());
+        }
+        let missing: Vec<String> = signature.params[..fixed]
+            .iter()
+            .enumerate()
+            .filter(|&(i, _)| i >= positional && !given[i])
+            .map(|(_, p)| format!("`{}`", p))
+            .collect();
+        if named && !missing.is_empty() {
+            return Err(KainError::type_error(format!("`{}` is not given {}", name, missing.join(", ")), span));
+        }
+        let found = positional + given.iter().filter(|&&g| g).count();
+        if found < fixed || (found > fixed && !signature.variadic) {
+            let expected = if signature.variadic { format!("at least {}", arguments(fixed)) } else { arguments(fixed) };
+            return Err(KainError::type_error(format!("`{}` takes {}, found {}", name, expected, found), span));
+        }
+        Ok(())
+    }
+}
+
+impl Visitor for ArgumentChecker<'_> {
+    fn visit_function(&mut self, function: &Function) {
+        if self.error.is_some() {
+            return;
+        }
+        if let Err(e) = self.check_params(function) {
+            self.error = Some(e);
+            return;
+        }
+        let outer = std::mem::replace(&mut self.locals, locals(function));
+        walk_function(self, function);
+        self.locals = outer;
+    }
+
+    fn visit_expr(&mut self, expr: &Expr) {
+        if self.error.is_some() {
+            return;
+        }
+        if let Expr::Call { callee, args, span } = expr {
+            if let Some((name, signature)) = self.signatures.callee(callee, &self.locals) {
+                if let Err(e) = self.check_call(name, signature, args, *span) {
+                    self.error = Some(e);
+                    return;
+                }
+            }
+        }
+        walk_expr(self, expr);
+    }
+}
+
+/// Moves named arguments to their parameters' places, once checked
+struct NamedArgs<'a> {
+    signatures: &'a Signatures,
+    locals: HashSet<String>,
+}
+
+impl VisitorMut for NamedArgs<'_> {
+    fn visit_function_mut(&mut self, function: &mut Function) {
+        let outer = std::mem::replace(&mut self.locals, locals(function));
+        walk_function_mut(self, function);
+        self.locals = outer;
+    }
+
+    fn visit_expr_mut(&mut self, expr: &mut Expr) {
+        walk_expr_mut(self, expr);
+        let Expr::Call { callee, args, .. } = expr else { return };
+        if args.iter().all(|a| a.name.is_none()) {
+            return;
+        }
+        let Some((_, signature)) = self.signatures.callee(callee, &self.locals) else { return };
+        let position = |arg: &CallArg| arg.name.as_ref().and_then(|n| signature.params.iter().position(|p| p == n));
+        // Positional arguments come first, so a stable sort keeps them in place
+        args.sort_by_key(|arg| position(arg).unwrap_or(0));
+        for arg in args.iter_mut() {
+            arg.name = None;
+        }
+    }
+}
+
+impl TypedVisitorMut for NamedArgs<'_> {}
+
+#[cfg(test)]
+mod tests {
+    use crate::{eval_snippet, CompileOptions};
+
+    #[test]
+    fn test_variadics_and_named_arguments() {
+        let functions = "fn sum(...nums: Array<Int>) -> Int:\n    let mut total = 0\n    for n in nums:\n        total = total + n\n    return total\n\nfn log_call(f, ...args):\n    println(\"calling with\", args)\n    return f(...args)\n\nfn span(low: Int, high: Int) -> Int:\n    return high - low\n\n";
+        let source = format!("{}fn main():\n    println(sum(), sum(1, 2, 3), log_call(sum, 4, 5), log_call(span, 1, 10))\n    println(span(high = 10, low = 4), span(2, high = 3), sum(...[1, 2], 3))\n", functions);
+        let result = eval_snippet(&source, &CompileOptions::default());
+        assert!(result.diagnostics.is_empty(), "{:?}", result.diagnostics);
+        assert_eq!(
+            result.stdout.lines().map(str::trim).collect::<Vec<_>>(),
+            ["calling with [4, 5]", "calling with [1, 10]", "0 6 9 9", "6 1 6"]
+        );
+
+        let errors = [
+            ("fn main():\n    println(span(1))\n", "`span` takes 2 arguments, found 1"),
+            ("fn main():\n    println(span(1, lo = 2))\n", "`span` has no parameter `lo`"),
+            ("fn main():\n    println(span(1, low = 2))\n", "`span` is given `low` twice"),
+            ("fn main():\n    println(span(high = 2))\n", "`span` is not given `low`"),
+            ("fn main():\n    println(sum(nums = 2))\n", "`...nums` takes the arguments left over, so it can't be given by name"),
+            ("fn first(...xs, last):\n    return last\n", "`...xs` takes the arguments left after the others, so it must be the last parameter"),
+            ("fn first(...xs: Int):\n    return xs\n", "`...xs` gathers its arguments into an Array, not Int"),
+        ];
+        for (program, expected) in errors {
+            let result = eval_snippet(&format!("{}{}", functions, program), &CompileOptions::default());
+            let message = result.diagnostics.first().map(|d| d.to_string()).unwrap_or_default();
+            assert!(message.contains(expected), "{}: {}", expected, message);
+        }
+    }
+}
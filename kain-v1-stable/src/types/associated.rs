@@ -0,0 +1,268 @@
+//! Associated functions and constants: `Lexer::new(source)`, `Color::RED`
+//!
+//! ```ignore
+//! impl Color:
+//!     const RED: Color = Color { r: 255, g: 0, b: 0 }
+//!
+//!     fn gray(level: Int) -> Color:
+//!         return Color { r: level, g: level, b: level }
+//! ```
+//!
+//! `Type::name` parses like an enum variant, so the checker resolves it
+//! against the type's variants first, then its `impl` blocks, and rejects
+//! names the type does not declare. Constants are then copied out of their
+//! impl as `Color_RED`, before comptime folds them, and on targets that
+//! mangle methods the calls become plain calls to `Color_gray`, so backends
+//! need no special case. Inside an impl, `Self::name` means the same.
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use crate::ast::visit::{walk_expr, walk_expr_mut, walk_item, Visitor, VisitorMut};
+use crate::ast::*;
+use crate::error::{KainError, KainResult};
+use crate::span::Span;
+
+use super::typed_visit::{walk_typed_item_mut, TypedVisitorMut};
+use super::{TypedItem, TypedProgram};
+
+/// What `Type::name` can name on one of the program's types
+#[derive(Debug, Default, Hash)]
+pub(super) struct AssociatedItems {
+    /// Associated functions, with how many arguments each takes (`self` included)
+    functions: BTreeMap<String, usize>,
+    consts: BTreeSet<String>,
+    variants: BTreeSet<String>,
+    is_enum: bool,
+}
+
+impl AssociatedItems {
+    fn check(&self, ty: &str, name: &str, fields: &EnumVariantFields, span: Span) -> KainResult<()> {
+        if self.variants.contains(name) {
+            return Ok(());
+        }
+        if let Some(&params) = self.functions.get(name) {
+            if let EnumVariantFields::Tuple(args) = fields {
+                if args.len() != params {
+                    let plural = if params == 1 { "" } else { "s" };
+                    return Err(KainError::type_error(
+                        format!("`{}::{}` takes {} argument{}, found {}", ty, name, params, plural, args.len()),
+                        span,
+                    ));
+                }
+            }
+            return Ok(());
+        }
+        if self.consts.contains(name) {
+            if matches!(fields, EnumVariantFields::Unit) {
+                return Ok(());
+            }
+            return Err(KainError::type_error(format!("`{}::{}` is a constant, not a function", ty, name), span));
+        }
+        let kinds = if self.is_enum { "variant, associated function or constant" } else { "associated function or constant" };
+        Err(KainError::type_error(format!("`{}` has no {} `{}`", ty, kinds, name), span))
+    }
+}
+
+/// The associated items of the program's types, by type name
+#[derive(Debug, Default, Hash)]
+pub(super) struct Associated(BTreeMap<String, AssociatedItems>);
+
+impl Associated {
+    pub(super) fn of_items<'a>(items: impl Iterator<Item = &'a Item>) -> Self {
+        let mut associated = Associated::default();
+        for item in items {
+            match item {
+                Item::Cfg(c) => associated.add(&c.item),
+                item => associated.add(item),
+            }
+        }
+        associated
+    }
+
+    fn of_program(program: &TypedProgram) -> Self {
+        let mut associated = Associated::default();
+        for item in &program.items {
+            match item {
+                TypedItem::Struct(s) => associated.add_struct(&s.ast),
+                TypedItem::Enum(e) => associated.add_enum(&e.ast),
+                TypedItem::Impl(i) => associated.add_impl(&i.ast),
+                _ => {}
+            }
+        }
+        associated
+    }
+
+    fn add(&mut self, item: &Item) {
+        match item {
+            Item::Struct(s) => self.add_struct(s),
+            Item::Enum(e) => self.add_enum(e),
+            Item::Impl(i) => self.add_impl(i),
+            // `UserId::unwrap(id)` takes a newtype's value back out
+            Item::TypeAlias(t) if t.newtype => {
+                self.0.entry(t.name.clone()).or_default().functions.insert("unwrap".to_string(), 1);
+            }
+            _ => {}
+        }
+    }
+
+    fn add_struct(&mut self, s: &Struct) {
+        self.0.entry(s.name.clone()).or_default();
+    }
+
+    fn add_enum(&mut self, e: &Enum) {
+        let items = self.0.entry(e.name.clone()).or_default();
+        items.is_enum = true;
+        items.variants.extend(e.variants.iter().map(|v| v.name.clone()));
+    }
+
+    fn add_impl(&mut self, imp: &Impl) {
+        let Some(ty) = named(&imp.target_type) else { return };
+        let items = self.0.entry(ty.to_string()).or_default();
+        items.functions.extend(imp.methods.iter().map(|m| (m.name.clone(), m.params.len())));
+        items.consts.extend(imp.consts.iter().map(|c| c.name.clone()));
+    }
+
+    /// The type `Type::name` refers to, with `Self` meaning `self_type`, and its items
+    fn get(&self, ty: &str, self_type: Option<&str>) -> Option<(&String, &AssociatedItems)> {
+        let ty = if ty == "Self" { self_type? } else { ty };
+        self.0.get_key_value(ty)
+    }
+}
+
+/// Rejects `Type::name` where the type declares nothing by that name
+pub(super) struct AssociatedItemChecker<'a> {
+    pub(super) types: &'a Associated,
+    /// The type `Self` means, inside an impl
+    pub(super) self_type: Option<String>,
+    pub(super) error: Option<KainError>,
+}
+
+impl Visitor for AssociatedItemChecker<'_> {
+    fn visit_item(&mut self, item: &Item) {
+        self.self_type = impl_type(item).map(str::to_string);
+        walk_item(self, item);
+    }
+
+    fn visit_expr(&mut self, expr: &Expr) {
+        if self.error.is_some() {
+            return;
+        }
+        if let Expr::EnumVariant { enum_name, variant, fields, span } = expr {
+            if let Some((ty, items)) = self.types.get(enum_name, self.self_type.as_deref()) {
+                if let Err(e) = items.check(ty, variant, fields, *span) {
+                    self.error = Some(e);
+                    return;
+                }
+            }
+        }
+        walk_expr(self, expr);
+    }
+}
+
+fn impl_type(item: &Item) -> Option<&str> {
+    match item {
+        Item::Impl(imp) => named(&imp.target_type),
+        _ => None,
+    }
+}
+
+fn named(ty: &Type) -> Option<&str> {
+    match ty {
+        Type::Named { name, .. } => Some(name),
+        _ => None,
+    }
+}
+
+/// The name an associated item is compiled under
+fn mangled(ty: &str, name: &str) -> String {
+    format!("{}_{}", ty, name)
+}
+
+/// Copy the consts in `items`' impls out as `Type_NAME` and point `Type::NAME` at them
+pub fn lower_associated_consts(items: &mut Vec<Item>) {
+    let types = Associated::of_items(items.iter());
+    let mut lowering = Lowering { types: &types, self_type: None, calls: false };
+    let mut hoisted = Vec::new();
+    for item in items.iter_mut() {
+        lowering.self_type = impl_type(item).map(str::to_string);
+        lowering.visit_item_mut(item);
+        if let (Item::Impl(imp), Some(ty)) = (item, &lowering.self_type) {
+            // The impl keeps its declarations, for the checker to resolve `Type::NAME` against
+            hoisted.extend(imp.consts.iter().map(|c| Item::Const(Const { name: mangled(ty, &c.name), ..c.clone() })));
+        }
+    }
+    items.extend(hoisted);
+}
+
+/// Turn `Type::function(args)` into a call to the `Type_function` the impl is compiled as
+pub fn lower_associated_calls(program: &mut TypedProgram) {
+    let types = Associated::of_program(program);
+    let mut lowering = Lowering { types: &types, self_type: None, calls: true };
+    lowering.visit_typed_program_mut(program);
+}
+
+struct Lowering<'a> {
+    types: &'a Associated,
+    self_type: Option<String>,
+    /// Whether calls are lowered too, or only constants
+    calls: bool,
+}
+
+impl VisitorMut for Lowering<'_> {
+    fn visit_expr_mut(&mut self, expr: &mut Expr) {
+        walk_expr_mut(self, expr);
+        let Expr::EnumVariant { enum_name, variant, fields, span } = expr else { return };
+        let Some((ty, items)) = self.types.get(enum_name, self.self_type.as_deref()) else { return };
+        if items.variants.contains(variant) {
+            return;
+        }
+        let span = *span;
+        let name = Expr::Ident(mangled(ty, variant), span);
+        let is_const = items.consts.contains(variant);
+        let is_function = self.calls && items.functions.contains_key(variant);
+        match std::mem::replace(fields, EnumVariantFields::Unit) {
+            EnumVariantFields::Unit if is_const || is_function => *expr = name,
+            EnumVariantFields::Tuple(args) if is_function => {
+                let args = args.into_iter().map(|value| CallArg { name: None, span: value.span(), value, spread: false }).collect();
+                *expr = Expr::Call { callee: Box::new(name), args, span };
+            }
+            unchanged => *fields = unchanged,
+        }
+    }
+}
+
+impl TypedVisitorMut for Lowering<'_> {
+    fn visit_typed_item_mut(&mut self, item: &mut TypedItem) {
+        self.self_type = match item {
+            TypedItem::Impl(i) => named(&i.ast.target_type).map(str::to_string),
+            _ => None,
+        };
+        walk_typed_item_mut(self, item);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{compile, eval_snippet, CompileOptions, CompileSession, CompileTarget};
+
+    #[test]
+    fn test_associated_functions_and_constants() {
+        let source = "struct Color:\n    r: Int\n    g: Int\n\nimpl Color:\n    const RED: Color = Color { r: 255, g: 0 }\n    const LEVELS: Int = 256\n\n    fn gray(level: Int) -> Color:\n        return Color { r: level, g: level }\n\n    fn brighter(self) -> Color:\n        return Self::gray(self.r + 1)\n\nfn main():\n    let c = Color::gray(7)\n    println(c.r, Color::RED.r, Color::LEVELS, c.brighter().g)\n";
+        let result = eval_snippet(source, &CompileOptions::default());
+        assert!(result.diagnostics.is_empty(), "{:?}", result.diagnostics);
+        assert_eq!(result.stdout.trim_end(), "7 255 256 8");
+
+        let err = compile(&source.replace("Color::gray(7)", "Color::grey(7)"), CompileTarget::Interpret).unwrap_err();
+        assert!(err.to_string().contains("`Color` has no associated function or constant `grey`"), "{}", err);
+        let err = compile(&source.replace("Color::gray(7)", "Color::gray(7, 8)"), CompileTarget::Interpret).unwrap_err();
+        assert!(err.to_string().contains("`Color::gray` takes 1 argument, found 2"), "{}", err);
+        let err = compile(&source.replace("Color::LEVELS", "Color::LEVELS(2)"), CompileTarget::Interpret).unwrap_err();
+        assert!(err.to_string().contains("`Color::LEVELS` is a constant, not a function"), "{}", err);
+
+        // Sessions lower them for the targets that monomorphize, as single compiles do
+        let mut session = CompileSession::new(source, CompileOptions::default()).unwrap();
+        for target in [CompileTarget::Js, CompileTarget::Wasm] {
+            assert_eq!(session.compile(target).unwrap(), compile(source, target).unwrap(), "{:?}", target);
+        }
+    }
+}
@@ -0,0 +1,325 @@
+//! Newtypes: `type UserId = new Int`
+//!
+//! ```ignore
+//! type UserId = new Int
+//!
+//! fn find(id: UserId) -> String:
+//!     return users[UserId::unwrap(id)]
+//!
+//! find(UserId(42))
+//! ```
+//!
+//! A newtype is a type of its own with the representation of the type it
+//! wraps. `UserId(42)` wraps a value and `UserId::unwrap(id)` takes it back
+//! out; passing a bare `Int`, or an `OrderId`, where a `UserId` is declared is
+//! a type error. The checker only knows the kinds of literals, wraps, calls to
+//! the program's functions and variables bound once with a known type, and
+//! lets everything else through. After checking, wraps and unwraps are erased
+//! and `UserId` in signatures becomes `Int`, so no backend ever sees one (see
+//! `alias`).
+
+use std::collections::{BTreeMap, HashMap};
+
+use crate::ast::visit::{walk_expr, walk_function, walk_param, walk_pattern, walk_stmt, Visitor};
+use crate::ast::*;
+use crate::error::{KainError, KainResult};
+
+use super::alias::items;
+
+/// Kinds the checker can tell apart: the primitives and the program's newtypes
+const PRIMITIVES: &[&str] = &["Int", "Float", "String", "Bool"];
+
+#[derive(Debug, Hash)]
+struct Signature {
+    params: Vec<(String, Option<String>)>,
+    ret: Option<String>,
+}
+
+/// The program's newtypes, and the signatures of the functions they may be passed to
+#[derive(Debug, Default, Hash)]
+pub(super) struct Newtypes {
+    /// Each newtype, with the kind it wraps
+    wraps: BTreeMap<String, Option<String>>,
+    /// Plain aliases, with the kind they name
+    aliases: BTreeMap<String, Option<String>>,
+    signatures: BTreeMap<String, Signature>,
+}
+
+impl Newtypes {
+    /// `None` for programs that declare no newtype, which have nothing to check
+    pub(super) fn of_program(program: &Program) -> KainResult<Option<Self>> {
+        let aliases: Vec<&TypeAlias> = items(&program.items)
+            .filter_map(|item| match item {
+                Item::TypeAlias(t) => Some(t),
+                _ => None,
+            })
+            .collect();
+        if !aliases.iter().any(|t| t.newtype) {
+            return Ok(None);
+        }
+
+        let mut newtypes = Newtypes::default();
+        for alias in aliases.iter().filter(|t| t.newtype) {
+            newtypes.wraps.insert(alias.name.clone(), None);
+        }
+        // Aliases may name each other in any order; each pass settles at least one more
+        for _ in 0..aliases.len() {
+            for alias in &aliases {
+                let kind = newtypes.kind(&alias.target);
+                let map = if alias.newtype { &mut newtypes.wraps } else { &mut newtypes.aliases };
+                map.insert(alias.name.clone(), kind);
+            }
+        }
+        for alias in aliases.iter().filter(|t| t.newtype) {
+            if newtypes.wraps.get(&alias.name).is_some_and(|kind| kind.as_deref() == Some(alias.name.as_str())) {
+                return Err(KainError::type_error(format!("`{}` wraps itself", alias.name), alias.span));
+            }
+        }
+
+        for item in items(&program.items) {
+            if let Item::Function(f) = item {
+                let params = f.params.iter().map(|p| (p.name.clone(), newtypes.kind(&p.ty))).collect();
+                let ret = f.return_type.as_ref().and_then(|t| newtypes.kind(t));
+                newtypes.signatures.insert(f.name.clone(), Signature { params, ret });
+            }
+        }
+        Ok(Some(newtypes))
+    }
+
+    /// The kind values of `ty` have, if the checker tracks it
+    fn kind(&self, ty: &Type) -> Option<String> {
+        match ty {
+            Type::Named { name, generics, .. } if generics.is_empty() => {
+                if self.wraps.contains_key(name) || PRIMITIVES.contains(&name.as_str()) {
+                    Some(name.clone())
+                } else {
+                    self.aliases.get(name).cloned().flatten()
+                }
+            }
+            _ => None,
+        }
+    }
+
+    fn is_newtype(&self, kind: &str) -> bool {
+        self.wraps.contains_key(kind)
+    }
+}
+
+/// Rejects values of the wrong kind where a newtype, or the type it wraps, is declared
+pub(super) struct NewtypeChecker<'a> {
+    pub(super) newtypes: &'a Newtypes,
+    /// Kinds of the current function's variables that are bound only once
+    locals: HashMap<String, Option<String>>,
+    /// Names bound more than once in the current function, whose kind depends on where they're read
+    rebound: Vec<String>,
+    ret: Option<String>,
+    pub(super) error: Option<KainError>,
+}
+
+impl<'a> NewtypeChecker<'a> {
+    pub(super) fn new(newtypes: &'a Newtypes) -> Self {
+        NewtypeChecker { newtypes, locals: HashMap::new(), rebound: Vec::new(), ret: None, error: None }
+    }
+
+    fn kind_of(&self, expr: &Expr) -> Option<String> {
+        match expr {
+            Expr::Int(..) => Some("Int".to_string()),
+            Expr::Float(..) => Some("Float".to_string()),
+            Expr::String(..) | Expr::FString(..) => Some("String".to_string()),
+            Expr::Bool(..) => Some("Bool".to_string()),
+            Expr::Char(..) => Some("Char".to_string()),
+            Expr::Paren(inner, _) => self.kind_of(inner),
+            Expr::Ident(name, _) => self.locals.get(name).cloned().flatten(),
+            Expr::Call { callee, .. } => match &**callee {
+                Expr::Ident(name, _) if self.newtypes.is_newtype(name) => Some(name.clone()),
+                Expr::Ident(name, _) if !self.locals.contains_key(name) && !self.rebound.contains(name) => {
+                    self.newtypes.signatures.get(name).and_then(|s| s.ret.clone())
+                }
+                _ => None,
+            },
+            Expr::EnumVariant { enum_name, variant, .. } if variant == "unwrap" => {
+                self.newtypes.wraps.get(enum_name).cloned().flatten()
+            }
+            _ => None,
+        }
+    }
+
+    /// `value` must be of kind `expected`, where either side is a newtype
+    fn expect(&self, expected: Option<&str>, value: &Expr) -> KainResult<()> {
+        let (Some(expected), Some(found)) = (expected, self.kind_of(value)) else { return Ok(()) };
+        if expected == found || !(self.newtypes.is_newtype(expected) || self.newtypes.is_newtype(&found)) {
+            return Ok(());
+        }
+        let hint = if self.newtypes.wraps.get(expected).is_some_and(|k| k.as_deref() == Some(found.as_str())) {
+            format!("; wrap it with `{}(...)`", expected)
+        } else if self.newtypes.wraps.get(&found).is_some_and(|k| k.as_deref() == Some(expected)) {
+            format!("; unwrap it with `{}::unwrap(...)`", found)
+        } else {
+            String::new()
+        };
+        Err(KainError::type_error(format!("expected `{}`, found `{}`{}", expected, found, hint), value.span()))
+    }
+
+    fn check_expr(&self, expr: &Expr) -> KainResult<()> {
+        match expr {
+            Expr::Call { callee, args, span } => {
+                let Expr::Ident(name, _) = &**callee else { return Ok(()) };
+                if self.locals.contains_key(name) || self.rebound.contains(name) {
+                    return Ok(());
+                }
+                if let Some(wrapped) = self.newtypes.wraps.get(name) {
+                    let [arg] = args.as_slice() else {
+                        return Err(KainError::type_error(
+                            format!("`{}(...)` wraps exactly one value, found {}", name, args.len()),
+                            *span,
+                        ));
+                    };
+                    // An Int literal is a fine Float, as it is for any Float parameter
+                    if wrapped.as_deref() == Some("Float") && matches!(arg.value, Expr::Int(..)) {
+                        return Ok(());
+                    }
+                    if let (Some(wrapped), Some(found)) = (wrapped, self.kind_of(&arg.value)) {
+                        if *wrapped != found {
+                            return Err(KainError::type_error(
+                                format!("`{}` wraps `{}`, found `{}`", name, wrapped, found),
+                                arg.span,
+                            ));
+                        }
+                    }
+                } else if let Some(signature) = self.newtypes.signatures.get(name) {
+                    for (i, arg) in args.iter().enumerate() {
+                        let param = match &arg.name {
+                            Some(named) => signature.params.iter().find(|(p, _)| p == named),
+                            None => signature.params.get(i),
+                        };
+                        if let Some((_, expected)) = param {
+                            self.expect(expected.as_deref(), &arg.value)?;
+                        }
+                    }
+                }
+            }
+            Expr::EnumVariant { enum_name, variant, fields: EnumVariantFields::Tuple(args), span }
+                if variant == "unwrap" && self.newtypes.is_newtype(enum_name) =>
+            {
+                if let [arg] = args.as_slice() {
+                    if let Some(found) = self.kind_of(arg).filter(|found| found != enum_name) {
+                        return Err(KainError::type_error(
+                            format!("`{}::unwrap` takes a `{}`, found `{}`", enum_name, enum_name, found),
+                            *span,
+                        ));
+                    }
+                }
+            }
+            Expr::Return(Some(value), _) => self.expect(self.ret.as_deref(), value)?,
+            _ => {}
+        }
+        Ok(())
+    }
+}
+
+impl Visitor for NewtypeChecker<'_> {
+    fn visit_function(&mut self, function: &Function) {
+        let mut bound = BindingCounter::default();
+        walk_function(&mut bound, function);
+        let outer = (
+            std::mem::take(&mut self.locals),
+            std::mem::replace(&mut self.rebound, bound.0.iter().filter(|(_, &n)| n > 1).map(|(name, _)| name.clone()).collect()),
+            std::mem::replace(&mut self.ret, function.return_type.as_ref().and_then(|t| self.newtypes.kind(t))),
+        );
+        for param in &function.params {
+            if !self.rebound.contains(&param.name) {
+                self.locals.insert(param.name.clone(), self.newtypes.kind(&param.ty));
+            }
+        }
+        walk_function(self, function);
+        (self.locals, self.rebound, self.ret) = outer;
+    }
+
+    fn visit_stmt(&mut self, stmt: &Stmt) {
+        if self.error.is_some() {
+            return;
+        }
+        walk_stmt(self, stmt);
+        let result = match stmt {
+            Stmt::Let { pattern: Pattern::Binding { name, .. }, ty, value, .. } => {
+                let declared = ty.as_ref().and_then(|t| self.newtypes.kind(t));
+                let result = match value {
+                    Some(value) if declared.is_some() => self.expect(declared.as_deref(), value),
+                    _ => Ok(()),
+                };
+                if !self.rebound.contains(name) {
+                    let kind = match (ty, value) {
+                        (Some(_), _) => declared,
+                        (None, Some(value)) => self.kind_of(value),
+                        (None, None) => None,
+                    };
+                    self.locals.insert(name.clone(), kind);
+                }
+                result
+            }
+            Stmt::Return(Some(value), _) => self.expect(self.ret.as_deref(), value),
+            _ => Ok(()),
+        };
+        if let Err(e) = result {
+            self.error.get_or_insert(e);
+        }
+    }
+
+    fn visit_expr(&mut self, expr: &Expr) {
+        if self.error.is_some() {
+            return;
+        }
+        if let Err(e) = self.check_expr(expr) {
+            self.error = Some(e);
+            return;
+        }
+        walk_expr(self, expr);
+    }
+}
+
+/// How many times each name is bound in a function
+#[derive(Default)]
+pub(super) struct BindingCounter(pub(super) HashMap<String, usize>);
+
+impl Visitor for BindingCounter {
+    fn visit_param(&mut self, param: &Param) {
+        *self.0.entry(param.name.clone()).or_default() += 1;
+        walk_param(self, param);
+    }
+
+    fn visit_pattern(&mut self, pattern: &Pattern) {
+        if let Pattern::Binding { name, .. } = pattern {
+            *self.0.entry(name.clone()).or_default() += 1;
+        }
+        walk_pattern(self, pattern);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{compile, eval_snippet, CompileOptions, CompileSession, CompileTarget};
+
+    #[test]
+    fn test_newtypes_are_checked_and_erased() {
+        let source = "type UserId = new Int\ntype OrderId = new Int\n\nfn next(id: UserId) -> UserId:\n    return UserId(UserId::unwrap(id) + 1)\n\nfn main():\n    let id = next(UserId(41))\n    println(UserId::unwrap(id))\n";
+        let result = eval_snippet(source, &CompileOptions::default());
+        assert!(result.diagnostics.is_empty(), "{:?}", result.diagnostics);
+        assert_eq!(result.stdout.trim_end(), "42");
+
+        let js = String::from_utf8(compile(source, CompileTarget::Js).unwrap()).unwrap();
+        assert!(!js.contains("UserId"), "{}", js);
+        // Multi-target builds erase them too
+        let mut session = CompileSession::new(source, CompileOptions::default()).unwrap();
+        for target in [CompileTarget::Js, CompileTarget::Wasm, CompileTarget::Rust] {
+            assert_eq!(session.compile(target).unwrap(), compile(source, target).unwrap(), "{:?}", target);
+        }
+        let rust = String::from_utf8(session.compile(CompileTarget::Rust).unwrap()).unwrap();
+        assert!(!rust.contains("UserId"), "{}", rust);
+        let err = compile(&source.replace("next(UserId(41))", "next(41)"), CompileTarget::Js).unwrap_err();
+        assert!(err.to_string().contains("expected `UserId`, found `Int`; wrap it with `UserId(...)`"), "{}", err);
+        let err = compile(&source.replace("next(UserId(41))", "next(OrderId(41))"), CompileTarget::Js).unwrap_err();
+        assert!(err.to_string().contains("expected `UserId`, found `OrderId`"), "{}", err);
+        let err = compile(&source.replace("UserId(41)", "UserId(\"41\")"), CompileTarget::Js).unwrap_err();
+        assert!(err.to_string().contains("`UserId` wraps `Int`, found `String`"), "{}", err);
+    }
+}
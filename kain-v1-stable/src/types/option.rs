@@ -0,0 +1,183 @@
+//! Options on the compiled targets: `Some(x)`, `None` and `T?`
+//!
+//! ```ignore
+//! fn half(n: Int) -> Int?:
+//!     if n % 2 == 1:
+//!         return None
+//!     return Some(n / 2)
+//! ```
+//!
+//! The interpreter has options as values of their own. Compiled targets get
+//! them as a generic enum instead, declared once when the program uses one:
+//!
+//! ```text
+//! enum Option<T>:
+//!     Some(T)
+//!     None
+//! ```
+//!
+//! `Some(x)` becomes `Option::Some(x)`, `None` and `none` become
+//! `Option::None`, and the `Some(x)` and `None` patterns name the enum, so
+//! backends compile options like any other enum. `?` stays as it is for the
+//! backends, which return `Option::None` when the value is `None`. A program
+//! that declares an `Option`, `Some` or `None` of its own keeps it.
+
+use std::collections::HashMap;
+
+use crate::ast::visit::{walk_expr_mut, walk_function_mut, walk_pattern_mut, walk_stmt_mut, VisitorMut};
+use crate::ast::*;
+use crate::span::Span;
+
+use super::typed_visit::{walk_typed_item_mut, TypedVisitorMut};
+use super::{ResolvedType, TypedEnum, TypedItem, TypedProgram};
+
+/// The enum options are compiled as
+pub const OPTION: &str = "Option";
+
+/// Turn the program's options into values of the `Option` enum, declaring it if they are used
+pub fn lower_options(program: &mut TypedProgram) {
+    if program.items.iter().any(declares_option) {
+        return;
+    }
+    let mut lowering = Lowering { used: false };
+    lowering.visit_typed_program_mut(program);
+    if lowering.used {
+        program.items.insert(0, TypedItem::Enum(option_enum()));
+    }
+}
+
+fn declares_option(item: &TypedItem) -> bool {
+    let name = match item {
+        TypedItem::Enum(e) => &e.ast.name,
+        TypedItem::Struct(s) => &s.ast.name,
+        TypedItem::Function(f) => &f.ast.name,
+        TypedItem::Const(c) => &c.ast.name,
+        TypedItem::Static(s) => &s.ast.name,
+        _ => return false,
+    };
+    matches!(name.as_str(), "Option" | "Some" | "None")
+}
+
+fn option_enum() -> TypedEnum {
+    let span = Span::default();
+    let variant = |name: &str, fields| Variant { name: name.to_string(), fields, span };
+    let t = Type::Named { name: "T".to_string(), generics: vec![], span };
+    TypedEnum {
+        ast: Enum {
+            name: OPTION.to_string(),
+            generics: vec![Generic { name: "T".to_string(), bounds: vec![], span }],
+            variants: vec![variant("Some", VariantFields::Tuple(vec![t])), variant("None", VariantFields::Unit)],
+            visibility: Visibility::Private,
+            attributes: vec![],
+            doc: None,
+            span,
+        },
+        variant_payload_types: HashMap::from([
+            ("Some".to_string(), vec![ResolvedType::Generic("T".to_string())]),
+            ("None".to_string(), vec![]),
+        ]),
+    }
+}
+
+/// Whether a value of type `ty` can hold an option
+fn mentions_option(ty: &Type) -> bool {
+    match ty {
+        Type::Option(..) => true,
+        Type::Named { name, generics, .. } => name == OPTION || generics.iter().any(mentions_option),
+        Type::Tuple(items, _) => items.iter().any(mentions_option),
+        Type::Array(inner, _, _) | Type::Slice(inner, _) | Type::Ref { inner, .. } => mentions_option(inner),
+        Type::Function { params, return_type, .. } => params.iter().any(mentions_option) || mentions_option(return_type),
+        Type::Result(ok, err, _) => mentions_option(ok) || mentions_option(err),
+        _ => false,
+    }
+}
+
+struct Lowering {
+    /// Whether the program uses options, and so needs the enum
+    used: bool,
+}
+
+impl Lowering {
+    fn variant(&mut self, variant: &str, fields: EnumVariantFields, span: Span) -> Expr {
+        self.used = true;
+        Expr::EnumVariant { enum_name: OPTION.to_string(), variant: variant.to_string(), fields, span }
+    }
+}
+
+impl VisitorMut for Lowering {
+    fn visit_function_mut(&mut self, function: &mut Function) {
+        self.used |= function.params.iter().any(|p| mentions_option(&p.ty)) || function.return_type.as_ref().is_some_and(mentions_option);
+        walk_function_mut(self, function);
+    }
+
+    fn visit_stmt_mut(&mut self, stmt: &mut Stmt) {
+        if let Stmt::Let { ty: Some(ty), .. } = stmt {
+            self.used |= mentions_option(ty);
+        }
+        walk_stmt_mut(self, stmt);
+    }
+
+    fn visit_expr_mut(&mut self, expr: &mut Expr) {
+        walk_expr_mut(self, expr);
+        let span = expr.span();
+        let lowered = match expr {
+            Expr::None(_) => self.variant("None", EnumVariantFields::Unit, span),
+            Expr::Ident(name, _) if name == "None" => self.variant("None", EnumVariantFields::Unit, span),
+            Expr::Call { callee, args, .. }
+                if matches!(&**callee, Expr::Ident(name, _) if name == "Some") && args.len() == 1 && args[0].name.is_none() =>
+            {
+                let value = args.remove(0).value;
+                self.variant("Some", EnumVariantFields::Tuple(vec![value]), span)
+            }
+            Expr::Try(..) => {
+                self.used = true;
+                return;
+            }
+            _ => return,
+        };
+        *expr = lowered;
+    }
+
+    fn visit_pattern_mut(&mut self, pattern: &mut Pattern) {
+        if let Pattern::Variant { enum_name, variant, fields, .. } = pattern {
+            let is_option = match (variant.as_str(), &*fields) {
+                ("Some", VariantPatternFields::Tuple(fields)) => fields.len() == 1,
+                ("None", VariantPatternFields::Unit) => true,
+                _ => false,
+            };
+            if enum_name.is_none() && is_option {
+                *enum_name = Some(OPTION.to_string());
+                self.used = true;
+            }
+        }
+        walk_pattern_mut(self, pattern);
+    }
+}
+
+impl TypedVisitorMut for Lowering {
+    fn visit_typed_item_mut(&mut self, item: &mut TypedItem) {
+        if let TypedItem::Struct(s) = item {
+            self.used |= s.ast.fields.iter().any(|f| mentions_option(&f.ty));
+        }
+        walk_typed_item_mut(self, item);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{eval_snippet, CompileOptions};
+
+    #[test]
+    fn test_options_are_values() {
+        let source = "fn half(n: Int) -> Int?:\n    if n % 2 == 1:\n        return None\n    return Some(n / 2)\n\nfn quarter(n: Int) -> Int?:\n    let h = half(n)?\n    return half(h)\n\nfn describe(o: Option<Int?>) -> String:\n    return match o:\n        Some(Some(n)) => \"some \" + str(n)\n        Some(None) => \"inner none\"\n        None => \"none\"\n\nfn main():\n    println(half(6), half(7), quarter(8), quarter(6), quarter(7))\n    println(describe(Some(Some(1))), describe(Some(None)), describe(None))\n    println(Some(2) == Some(2), Some(2) == Some(3), half(7) == None, half(3).unwrap_or(0), half(4).is_some())\n";
+        let result = eval_snippet(source, &CompileOptions::default());
+        assert!(result.diagnostics.is_empty(), "{:?}", result.diagnostics);
+        assert_eq!(
+            result.stdout.trim(),
+            "Some(3) none Some(2) none none \nsome 1 inner none none \ntrue false true 0 true"
+        );
+
+        let result = eval_snippet("fn twice(n: Int?) -> Int:\n    return n? * 2\n\nfn main():\n    println(twice(Some(1)))\n", &CompileOptions::default());
+        assert!(result.diagnostics[0].to_string().contains("so 'twice' must return an Option or a Result, not Int"));
+    }
+}
@@ -0,0 +1,282 @@
+//! Traits: interfaces that impls implement and type parameters are bound by
+//!
+//! ```ignore
+//! trait Shape:
+//!     fn area(self) -> Float
+//!     fn describe(self) -> String:
+//!         return "a shape of area " + str(self.area())
+//!
+//! impl Shape for Circle:
+//!     fn area(self) -> Float:
+//!         return 3.14 * self.r * self.r
+//!
+//! fn report<T: Shape>(shape: T) -> String:
+//!     return shape.describe()
+//! ```
+//!
+//! An impl of a trait defines each of the trait's methods that has no default
+//! body, with the same parameters, and no other methods; the ones it leaves
+//! out get the trait's defaults, so after checking an impl of a trait is like
+//! any other impl. A method called on a parameter whose type is a bounded type
+//! parameter must come from one of its traits. Dispatch is static:
+//! monomorphization checks that each type a generic function is called with
+//! implements its bounds, rejecting a call whose type it can't tell, and the
+//! instance for that type calls the type's own methods. No backend ever sees a trait.
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::hash::{Hash, Hasher};
+
+use crate::ast::visit::{walk_expr, walk_function, walk_item, Visitor};
+use crate::ast::*;
+use crate::error::{KainError, KainResult};
+use crate::span::Span;
+
+use super::alias::items;
+use super::newtype::BindingCounter;
+
+/// The traits a program declares
+#[derive(Debug, Default)]
+pub(super) struct Traits {
+    traits: BTreeMap<String, Trait>,
+    /// Whether the program still imports modules, whose traits aren't known until they are linked in
+    imports: bool,
+}
+
+/// Default bodies don't implement `Hash`, so traits hash by their `Debug` text
+impl Hash for Traits {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        format!("{:?}", self).hash(state);
+    }
+}
+
+impl Traits {
+    pub(super) fn of_program(program: &Program) -> KainResult<Self> {
+        let mut traits = BTreeMap::new();
+        for item in items(&program.items) {
+            let Item::Trait(def) = item else { continue };
+            let mut names = HashSet::new();
+            if let Some(method) = def.methods.iter().find(|m| !names.insert(&m.name)) {
+                return Err(KainError::type_error(
+                    format!("trait `{}` declares `{}` twice", def.name, method.name),
+                    method.span,
+                ));
+            }
+            if traits.insert(def.name.clone(), def.clone()).is_some() {
+                return Err(KainError::type_error(format!("trait `{}` is declared twice", def.name), def.span));
+            }
+        }
+        Ok(Traits { traits, imports: program.items.iter().any(|item| matches!(item, Item::Use(_))) })
+    }
+
+    /// The trait called `name`; `None` for one an imported module may declare
+    fn get(&self, name: &str, span: Span) -> KainResult<Option<&Trait>> {
+        match self.traits.get(name) {
+            Some(def) => Ok(Some(def)),
+            None if self.imports => Ok(None),
+            None => Err(KainError::type_error(format!("unknown trait `{}`", name), span)),
+        }
+    }
+
+    /// An impl of a trait must define the methods without a default, as the trait declares them, and nothing else
+    fn check_impl(&self, imp: &Impl) -> KainResult<()> {
+        let Some(trait_name) = &imp.trait_name else { return Ok(()) };
+        let Some(def) = self.get(trait_name, imp.span)? else { return Ok(()) };
+        for method in &imp.methods {
+            let Some(declared) = def.methods.iter().find(|m| m.name == method.name) else {
+                return Err(KainError::type_error(
+                    format!("`{}` is not a method of trait `{}`", method.name, trait_name),
+                    method.span,
+                ));
+            };
+            let takes_self = |params: &[Param]| params.first().is_some_and(|p| p.name == "self");
+            if takes_self(&declared.params) != takes_self(&method.params) {
+                let takes = if takes_self(&declared.params) { "takes" } else { "does not take" };
+                return Err(KainError::type_error(
+                    format!("`{}` {} `self` in trait `{}`", method.name, takes, trait_name),
+                    method.span,
+                ));
+            }
+            if declared.params.len() != method.params.len() {
+                return Err(KainError::type_error(
+                    format!(
+                        "`{}` takes {} in trait `{}`, but {} here",
+                        method.name,
+                        parameters(declared.params.len()),
+                        trait_name,
+                        method.params.len()
+                    ),
+                    method.span,
+                ));
+            }
+        }
+        let missing: Vec<String> = def
+            .methods
+            .iter()
+            .filter(|m| m.default_impl.is_none() && !imp.methods.iter().any(|f| f.name == m.name))
+            .map(|m| format!("`{}`", m.name))
+            .collect();
+        if !missing.is_empty() {
+            return Err(KainError::type_error(
+                format!(
+                    "impl of `{}` for `{}` is missing {}",
+                    trait_name,
+                    crate::lsp::format_type(&imp.target_type),
+                    missing.join(", ")
+                ),
+                imp.span,
+            ));
+        }
+        Ok(())
+    }
+
+    /// Give an impl of a trait the trait's default bodies for the methods it leaves out
+    pub(super) fn add_defaults(&self, imp: &mut Impl) {
+        let Some(def) = imp.trait_name.as_ref().and_then(|name| self.traits.get(name)) else { return };
+        for method in &def.methods {
+            let Some(body) = &method.default_impl else { continue };
+            if imp.methods.iter().any(|f| f.name == method.name) {
+                continue;
+            }
+            imp.methods.push(Function {
+                name: method.name.clone(),
+                generics: vec![],
+                params: method.params.clone(),
+                return_type: method.return_type.clone(),
+                effects: method.effects.clone(),
+                body: body.clone(),
+                visibility: def.visibility,
+                attributes: vec![],
+                doc: None,
+                span: method.span,
+            });
+        }
+    }
+}
+
+fn parameters(n: usize) -> String {
+    format!("{} parameter{}", n, if n == 1 { "" } else { "s" })
+}
+
+/// Checks impls of traits, and the bounds of functions' type parameters
+pub(super) struct TraitChecker<'a> {
+    traits: &'a Traits,
+    /// Parameters of the current function whose type is a bounded type
+    /// parameter, with its name and bounds; those bound more than once are left out
+    bounded: HashMap<String, (String, Vec<String>)>,
+    pub(super) error: Option<KainError>,
+}
+
+impl<'a> TraitChecker<'a> {
+    pub(super) fn new(traits: &'a Traits) -> Self {
+        TraitChecker { traits, bounded: HashMap::new(), error: None }
+    }
+
+    fn check_bounds(&self, function: &Function) -> KainResult<HashMap<String, (String, Vec<String>)>> {
+        for bound in function.generics.iter().flat_map(|g| &g.bounds) {
+            self.traits.get(&bound.trait_name, bound.span)?;
+        }
+        let mut bound = BindingCounter::default();
+        walk_function(&mut bound, function);
+        Ok(function
+            .params
+            .iter()
+            .filter(|p| bound.0.get(&p.name).is_some_and(|&n| n == 1))
+            .filter_map(|p| {
+                let Type::Named { name, generics, .. } = &p.ty else { return None };
+                let generic = function.generics.iter().find(|g| &g.name == name && generics.is_empty() && !g.bounds.is_empty())?;
+                let bounds = generic.bounds.iter().map(|b| b.trait_name.clone()).collect();
+                Some((p.name.clone(), (generic.name.clone(), bounds)))
+            })
+            .collect())
+    }
+
+    /// `receiver.method(...)` on a value of a bounded type parameter must call a method of its traits
+    fn check_call(&self, receiver: &Expr, method: &str, span: Span) -> KainResult<()> {
+        let Expr::Ident(name, _) = receiver else { return Ok(()) };
+        let Some((generic, bounds)) = self.bounded.get(name) else { return Ok(()) };
+        let declared = bounds.iter().any(|b| match self.traits.traits.get(b) {
+            Some(def) => def.methods.iter().any(|m| m.name == method),
+            // A trait from an imported module, which may well declare it
+            None => true,
+        });
+        if declared {
+            return Ok(());
+        }
+        let traits: Vec<String> = bounds.iter().map(|b| format!("`{}`", b)).collect();
+        let which = if traits.len() == 1 { "which bounds" } else { "which bound" };
+        Err(KainError::type_error(
+            format!("`{}` is not a method of {}, {} `{}`", method, traits.join(" or "), which, generic),
+            span,
+        ))
+    }
+}
+
+impl Visitor for TraitChecker<'_> {
+    fn visit_item(&mut self, item: &Item) {
+        if let Item::Impl(imp) = item {
+            if let Err(e) = self.traits.check_impl(imp) {
+                self.error.get_or_insert(e);
+                return;
+            }
+        }
+        walk_item(self, item);
+    }
+
+    fn visit_function(&mut self, function: &Function) {
+        if self.error.is_some() {
+            return;
+        }
+        match self.check_bounds(function) {
+            Ok(bounded) => {
+                let outer = std::mem::replace(&mut self.bounded, bounded);
+                walk_function(self, function);
+                self.bounded = outer;
+            }
+            Err(e) => self.error = Some(e),
+        }
+    }
+
+    fn visit_expr(&mut self, expr: &Expr) {
+        if self.error.is_some() {
+            return;
+        }
+        if let Expr::MethodCall { receiver, method, span, .. } = expr {
+            if let Err(e) = self.check_call(receiver, method, *span) {
+                self.error = Some(e);
+                return;
+            }
+        }
+        walk_expr(self, expr);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{eval_snippet, CompileOptions};
+
+    #[test]
+    fn test_traits_dispatch_statically() {
+        let shapes = "trait Shape:\n    fn area(self) -> Int\n    fn describe(self) -> String:\n        return \"area \" + str(self.area())\n\nstruct Square:\n    side: Int\n\nstruct Rect:\n    w: Int\n    h: Int\n\nimpl Shape for Square:\n    fn area(self) -> Int:\n        return self.side * self.side\n\nimpl Shape for Rect:\n    fn area(self) -> Int:\n        return self.w * self.h\n    fn describe(self) -> String:\n        return \"rect \" + str(self.area())\n\n";
+        let source = format!("{}fn report<T: Shape>(s: T) -> String:\n    return s.describe()\n\nfn main():\n    println(report(Square {{ side: 3 }}), report(Rect {{ w: 2, h: 5 }}))\n", shapes);
+        let result = eval_snippet(&source, &CompileOptions::default());
+        assert!(result.diagnostics.is_empty(), "{:?}", result.diagnostics);
+        assert_eq!(result.stdout.trim(), "area 9 rect 10");
+        let typed = format!("{}fn report<T: Shape>(s: T) -> String:\n    return s.describe()\n\nfn main():\n    let s: Square = [Square {{ side: 3 }}][0]\n    println(report(s))\n", shapes);
+        assert_eq!(eval_snippet(&typed, &CompileOptions::default()).stdout.trim(), "area 9");
+
+        let errors = [
+            ("impl Shape for Square:\n    fn describe(self) -> String:\n        return \"square\"\n", "impl of `Shape` for `Square` is missing `area`"),
+            ("impl Drawable for Square:\n    fn draw(self):\n        return\n", "unknown trait `Drawable`"),
+            ("fn report<T: Shape>(s: T) -> Int:\n    return s.perimeter()\n", "`perimeter` is not a method of `Shape`, which bounds `T`"),
+            ("struct Circle:\n    r: Int\n\nfn report<T: Shape>(s: T) -> Int:\n    return s.area()\n\nfn main():\n    println(report(Circle { r: 1 }))\n", "`Circle` does not implement `Shape`, which `report` requires of `T`"),
+            // An element's type isn't inferred, so the bound can't be checked
+            ("struct Circle:\n    r: Int\n\nfn report<T: Shape>(s: T) -> Int:\n    return s.area()\n\nfn main():\n    println(report([Circle { r: 1 }][0]))\n", "can't tell which type `T` is in this call to `report`, so it can't be checked to implement `Shape`"),
+        ];
+        let shapes = shapes.split("impl Shape for Square").next().unwrap_or_default();
+        for (program, expected) in errors {
+            let result = eval_snippet(&format!("{}{}", shapes, program), &CompileOptions::default());
+            let message = result.diagnostics.first().map(|d| d.to_string()).unwrap_or_default();
+            assert!(message.contains(expected), "{}: {}", expected, message);
+        }
+    }
+}
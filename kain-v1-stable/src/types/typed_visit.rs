@@ -0,0 +1,73 @@
+//! Typed-AST traversal
+//!
+//! Typed items keep their source AST in `.ast`, so the typed visitors are thin
+//! layers over `ast::visit`: override `visit_typed_item` to inspect resolved
+//! types, and the `ast::visit::Visitor` methods for everything below the item.
+
+use crate::ast::visit::{self, Visitor, VisitorMut};
+use super::{TypedItem, TypedProgram};
+
+pub trait TypedVisitor: Visitor {
+    fn visit_typed_program(&mut self, program: &TypedProgram) {
+        walk_typed_program(self, program)
+    }
+
+    fn visit_typed_item(&mut self, item: &TypedItem) {
+        walk_typed_item(self, item)
+    }
+}
+
+pub fn walk_typed_program<V: TypedVisitor>(v: &mut V, program: &TypedProgram) {
+    for item in &program.items {
+        v.visit_typed_item(item);
+    }
+}
+
+pub fn walk_typed_item<V: TypedVisitor>(v: &mut V, item: &TypedItem) {
+    match item {
+        TypedItem::Function(f) => v.visit_function(&f.ast),
+        TypedItem::Component(c) => visit::walk_component(v, &c.ast),
+        TypedItem::Shader(s) => visit::walk_shader(v, &s.ast),
+        TypedItem::Actor(a) => visit::walk_actor(v, &a.ast),
+        TypedItem::Struct(s) => visit::walk_struct(v, &s.ast),
+        TypedItem::Impl(i) => visit::walk_impl(v, &i.ast),
+        TypedItem::Const(c) => v.visit_expr(&c.ast.value),
+        TypedItem::Static(s) => v.visit_expr(&s.ast.value),
+        TypedItem::Comptime(c) => v.visit_block(&c.ast),
+        TypedItem::Test(t) => v.visit_block(&t.ast.body),
+        TypedItem::Enum(_) | TypedItem::Macro(_) | TypedItem::Use(_) => {}
+    }
+}
+
+/// Like `TypedVisitor`, but with mutable access to the underlying AST
+pub trait TypedVisitorMut: VisitorMut {
+    fn visit_typed_program_mut(&mut self, program: &mut TypedProgram) {
+        walk_typed_program_mut(self, program)
+    }
+
+    fn visit_typed_item_mut(&mut self, item: &mut TypedItem) {
+        walk_typed_item_mut(self, item)
+    }
+}
+
+pub fn walk_typed_program_mut<V: TypedVisitorMut>(v: &mut V, program: &mut TypedProgram) {
+    for item in &mut program.items {
+        v.visit_typed_item_mut(item);
+    }
+}
+
+pub fn walk_typed_item_mut<V: TypedVisitorMut>(v: &mut V, item: &mut TypedItem) {
+    match item {
+        TypedItem::Function(f) => v.visit_function_mut(&mut f.ast),
+        TypedItem::Component(c) => visit::walk_component_mut(v, &mut c.ast),
+        TypedItem::Shader(s) => visit::walk_shader_mut(v, &mut s.ast),
+        TypedItem::Actor(a) => visit::walk_actor_mut(v, &mut a.ast),
+        TypedItem::Struct(s) => visit::walk_struct_mut(v, &mut s.ast),
+        TypedItem::Impl(i) => visit::walk_impl_mut(v, &mut i.ast),
+        TypedItem::Const(c) => v.visit_expr_mut(&mut c.ast.value),
+        TypedItem::Static(s) => v.visit_expr_mut(&mut s.ast.value),
+        TypedItem::Comptime(c) => v.visit_block_mut(&mut c.ast),
+        TypedItem::Test(t) => v.visit_block_mut(&mut t.ast.body),
+        TypedItem::Enum(_) | TypedItem::Macro(_) | TypedItem::Use(_) => {}
+    }
+}
@@ -0,0 +1,236 @@
+//! Source files the compiler reads
+//!
+//! The entry file, the modules it imports and the files the LSP has open all
+//! go through one [`SourceMap`]. Each path gets a [`FileId`] the first time it
+//! is read, and its text is indexed by line, so diagnostics turn byte offsets
+//! into lines and columns without rescanning the file.
+//!
+//! An overlay is text that replaces a file's contents on disk, such as an
+//! editor buffer that has not been saved. While a path has an overlay, every
+//! read of it, module resolution included, sees the overlay instead.
+
+use once_cell::sync::Lazy;
+use std::collections::HashMap;
+use std::io;
+use std::path::{Component, Path, PathBuf};
+use std::sync::{Arc, RwLock};
+
+static SOURCES: Lazy<RwLock<SourceMap>> = Lazy::new(Default::default);
+
+/// Identifies a file in the [`SourceMap`]; stable for as long as the process runs
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct FileId(u32);
+
+/// Byte offsets where each line of a text starts
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LineIndex {
+    starts: Vec<usize>,
+}
+
+impl LineIndex {
+    pub fn new(text: &str) -> Self {
+        let starts = std::iter::once(0).chain(text.match_indices('\n').map(|(i, _)| i + 1)).collect();
+        LineIndex { starts }
+    }
+
+    /// 0-based line of the byte `offset`, and the offset that line starts at
+    pub fn line_of(&self, offset: usize) -> (usize, usize) {
+        let line = self.starts.partition_point(|&start| start <= offset) - 1;
+        (line, self.starts[line])
+    }
+
+    /// 1-based line and column of the byte `offset` in `text`, the column
+    /// counted in characters. Offsets past the end are clamped to it.
+    pub fn line_col(&self, text: &str, offset: usize) -> (usize, usize) {
+        let offset = floor_char_boundary(text, offset);
+        let (line, start) = self.line_of(offset);
+        (line + 1, text[start..offset].chars().count() + 1)
+    }
+
+    /// Byte offset of 0-based `line` and `column` (in characters), if the text has that line
+    pub fn offset(&self, text: &str, line: usize, column: usize) -> Option<usize> {
+        let start = *self.starts.get(line)?;
+        let content = &text[start..self.line_end(text, line)];
+        Some(start + content.char_indices().nth(column).map_or(content.len(), |(i, _)| i))
+    }
+
+    /// Text of the 0-based `line`, without its line break
+    pub fn line<'a>(&self, text: &'a str, line: usize) -> &'a str {
+        match self.starts.get(line) {
+            Some(&start) => &text[start..self.line_end(text, line)],
+            None => "",
+        }
+    }
+
+    fn line_end(&self, text: &str, line: usize) -> usize {
+        let end = self.starts.get(line + 1).map_or(text.len(), |&next| next - 1);
+        if text[..end].ends_with('\r') { end - 1 } else { end }
+    }
+}
+
+fn floor_char_boundary(text: &str, offset: usize) -> usize {
+    let mut offset = offset.min(text.len());
+    while !text.is_char_boundary(offset) {
+        offset -= 1;
+    }
+    offset
+}
+
+/// One file's text as the compiler saw it
+#[derive(Debug)]
+pub struct SourceFile {
+    pub id: FileId,
+    pub path: PathBuf,
+    pub text: Arc<str>,
+    pub lines: LineIndex,
+}
+
+impl SourceFile {
+    /// 1-based line and column of the byte `offset`
+    pub fn line_col(&self, offset: usize) -> (usize, usize) {
+        self.lines.line_col(&self.text, offset)
+    }
+
+    /// Text of the 1-based `line`
+    pub fn line(&self, line: usize) -> &str {
+        self.lines.line(&self.text, line.saturating_sub(1))
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct SourceMap {
+    files: Vec<Arc<SourceFile>>,
+    ids: HashMap<PathBuf, FileId>,
+    overlays: HashMap<PathBuf, Arc<str>>,
+}
+
+impl SourceMap {
+    /// The map the compiler, the interpreter's imports and the LSP share
+    pub fn global() -> &'static RwLock<SourceMap> {
+        &SOURCES
+    }
+
+    /// Read `path` from its overlay, or else from disk. The file keeps its id
+    /// across reads, and its entry is only replaced when the text changed.
+    pub fn load(&mut self, path: &Path) -> io::Result<Arc<SourceFile>> {
+        let key = normalize(path);
+        let text: Arc<str> = match self.overlays.get(&key) {
+            Some(text) => text.clone(),
+            None => std::fs::read_to_string(path)?.into(),
+        };
+        Ok(self.insert(key, path, text))
+    }
+
+    /// Add text that is not read from disk, such as a snippet or stdin, under `path`
+    pub fn add(&mut self, path: impl AsRef<Path>, text: impl Into<Arc<str>>) -> Arc<SourceFile> {
+        let path = path.as_ref();
+        self.insert(normalize(path), path, text.into())
+    }
+
+    pub fn get(&self, id: FileId) -> Option<Arc<SourceFile>> {
+        self.files.get(id.0 as usize).cloned()
+    }
+
+    /// The file `path` was last read as, if it has been
+    pub fn lookup(&self, path: &Path) -> Option<Arc<SourceFile>> {
+        self.get(*self.ids.get(&normalize(path))?)
+    }
+
+    /// Whether `path` has an overlay or exists on disk
+    pub fn exists(&self, path: &Path) -> bool {
+        self.overlays.contains_key(&normalize(path)) || path.exists()
+    }
+
+    /// Read `path` as `text` until the overlay is removed
+    pub fn set_overlay(&mut self, path: &Path, text: impl Into<Arc<str>>) {
+        self.overlays.insert(normalize(path), text.into());
+    }
+
+    pub fn remove_overlay(&mut self, path: &Path) {
+        self.overlays.remove(&normalize(path));
+    }
+
+    fn insert(&mut self, key: PathBuf, path: &Path, text: Arc<str>) -> Arc<SourceFile> {
+        let id = match self.ids.get(&key) {
+            Some(&id) if *self.files[id.0 as usize].text == *text => return self.files[id.0 as usize].clone(),
+            Some(&id) => id,
+            None => FileId(self.files.len() as u32),
+        };
+        let file = Arc::new(SourceFile { id, path: path.to_path_buf(), lines: LineIndex::new(&text), text });
+        match self.files.get_mut(id.0 as usize) {
+            Some(slot) => *slot = file.clone(),
+            None => {
+                self.ids.insert(key, id);
+                self.files.push(file.clone());
+            }
+        }
+        file
+    }
+}
+
+/// Read `path` through the shared [`SourceMap`]
+pub fn read(path: &Path) -> io::Result<Arc<SourceFile>> {
+    SourceMap::global().write().unwrap_or_else(|e| e.into_inner()).load(path)
+}
+
+/// Whether `path` can be read through the shared [`SourceMap`]
+pub fn exists(path: &Path) -> bool {
+    SourceMap::global().read().unwrap_or_else(|e| e.into_inner()).exists(path)
+}
+
+/// An absolute form of `path` with `.` and `..` resolved without touching the
+/// disk, so a file named two ways, or not saved yet, has one key
+fn normalize(path: &Path) -> PathBuf {
+    let absolute = std::path::absolute(path).unwrap_or_else(|_| path.to_path_buf());
+    let mut out = PathBuf::new();
+    for component in absolute.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                out.pop();
+            }
+            other => out.push(other),
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_line_index_maps_offsets_both_ways() {
+        let text = "let x = 5\r\nlet é = x\n\nlast";
+        let lines = LineIndex::new(text);
+        assert_eq!(lines.line_col(text, 0), (1, 1));
+        assert_eq!(lines.line_col(text, 15), (2, 5));
+        assert_eq!(lines.line_col(text, text.len() + 10), (4, 5));
+        assert_eq!(lines.line(text, 0), "let x = 5");
+        assert_eq!((lines.line(text, 2), lines.line(text, 3)), ("", "last"));
+        assert_eq!(lines.offset(text, 1, 6), Some(18));
+        assert_eq!(lines.offset(text, 1, 99), Some(21));
+        assert_eq!(lines.offset(text, 9, 0), None);
+    }
+
+    #[test]
+    fn test_overlays_shadow_the_disk_and_ids_are_stable() {
+        let mut map = SourceMap::default();
+        let path = Path::new("tests/modules/shapes.kn");
+        let disk = map.load(path).unwrap();
+        assert!(map.exists(path));
+
+        map.set_overlay(path, "fn area() -> Int:\n    return 1\n");
+        let edited = map.load(Path::new("tests/modules/../modules/shapes.kn")).unwrap();
+        assert_eq!((edited.id, edited.line(2)), (disk.id, "    return 1"));
+
+        let unsaved = Path::new("tests/modules/unsaved.kn");
+        assert!(!map.exists(unsaved));
+        map.set_overlay(unsaved, "fn f():\n    return\n");
+        assert!(map.exists(unsaved) && map.load(unsaved).unwrap().id != disk.id);
+
+        map.remove_overlay(path);
+        assert_eq!(*map.load(path).unwrap().text, *disk.text);
+        assert_eq!(map.lookup(path).unwrap().id, disk.id);
+    }
+}
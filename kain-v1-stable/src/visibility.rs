@@ -0,0 +1,208 @@
+//! Visibility of imported items
+//!
+//! From edition 0.2 the items of a module are private to it unless marked
+//! `pub`. Linking puts every module's items in one program, so this pass reads
+//! the modules a program imports before they are linked and reports the first
+//! use of a private item with the span of the use.
+
+use crate::ast::*;
+use crate::ast::visit::{walk_expr, Visitor};
+use crate::edition::Edition;
+use crate::error::{KainError, KainResult};
+use crate::modules::ModuleGraph;
+use crate::span::Span;
+use crate::types::BoundNames;
+use std::collections::{HashMap, HashSet};
+
+/// Report the first use in `program` of an item private to the module it is imported from
+pub fn check(program: &Program, graph: &ModuleGraph, edition: Edition) -> KainResult<()> {
+    if !edition.enforce_visibility() {
+        return Ok(());
+    }
+    let mut bound = BoundNames::default();
+    bound.visit_program(program);
+    let mut checker = Checker {
+        graph,
+        local: bound.0,
+        plain: Vec::new(),
+        aliases: HashMap::new(),
+        error: None,
+    };
+    for item in &program.items {
+        if let Item::Use(u) = item {
+            checker.import(u)?;
+        }
+    }
+    checker.visit_program(program);
+    checker.error.map_or(Ok(()), Err)
+}
+
+/// Declared items of an imported module
+struct ModuleItems {
+    path: String,
+    items: HashMap<String, (&'static str, Visibility)>,
+}
+
+impl ModuleItems {
+    /// The items of the module the program's `u` imports; `None` for the core stdlib
+    fn of(graph: &ModuleGraph, u: &Use) -> Option<ModuleItems> {
+        let module = graph.get(None, u)?;
+        let items = module
+            .program
+            .items
+            .iter()
+            .filter_map(|item| Some((item.name()?.to_string(), (kind(item), item.visibility()?))))
+            .collect();
+        Some(ModuleItems { path: module.path.clone(), items })
+    }
+
+    fn private(&self, name: &str) -> Option<&'static str> {
+        match self.items.get(name) {
+            Some((kind, Visibility::Private)) => Some(kind),
+            _ => None,
+        }
+    }
+
+    fn public(&self, name: &str) -> bool {
+        matches!(self.items.get(name), Some((_, visibility)) if *visibility != Visibility::Private)
+    }
+
+    fn error(&self, kind: &str, name: &str, span: Span) -> KainError {
+        KainError::type_error(
+            format!("{} `{}` is private to module {}, consider making it pub", kind, name, self.path),
+            span,
+        )
+    }
+}
+
+fn kind(item: &Item) -> &'static str {
+    match item {
+        Item::Function(_) => "function",
+        Item::Component(_) => "component",
+        Item::Struct(_) => "struct",
+        Item::Enum(_) => "enum",
+        Item::Trait(_) => "trait",
+        Item::TypeAlias(_) => "type",
+        Item::Effect(_) => "effect",
+        Item::Const(_) => "const",
+        Item::Static(_) => "static",
+        Item::Cfg(c) => kind(&c.item),
+        _ => "item",
+    }
+}
+
+struct Checker<'a> {
+    graph: &'a ModuleGraph,
+    /// Names the program binds itself, which shadow anything imported
+    local: HashSet<String>,
+    /// Modules imported with a plain `use path`
+    plain: Vec<ModuleItems>,
+    /// Modules imported with `use path as alias`
+    aliases: HashMap<String, ModuleItems>,
+    error: Option<KainError>,
+}
+
+impl Checker<'_> {
+    fn import(&mut self, u: &Use) -> KainResult<()> {
+        let Some(module) = ModuleItems::of(self.graph, u) else {
+            return Ok(());
+        };
+        if let Some(alias) = &u.alias {
+            self.aliases.insert(alias.clone(), module);
+        } else if let Some(names) = &u.items {
+            for name in names {
+                if let Some(kind) = module.private(name) {
+                    return Err(module.error(kind, name, u.span));
+                }
+            }
+        } else {
+            self.plain.push(module);
+        }
+        Ok(())
+    }
+
+    /// A name the program uses without binding it, private to every plain import that declares it
+    fn check_name(&mut self, name: &str, span: Span) {
+        if self.local.contains(name) || self.plain.iter().any(|m| m.public(name)) {
+            return;
+        }
+        if let Some((module, kind)) = self.plain.iter().find_map(|m| Some((m, m.private(name)?))) {
+            self.error = Some(module.error(kind, name, span));
+        }
+    }
+
+    /// `alias.name`, where `alias` names an imported module
+    fn check_member(&mut self, object: &Expr, name: &str, span: Span) {
+        let Expr::Ident(alias, _) = object else {
+            return;
+        };
+        if self.local.contains(alias) {
+            return;
+        }
+        if let Some(module) = self.aliases.get(alias) {
+            if let Some(kind) = module.private(name) {
+                self.error = Some(module.error(kind, name, span));
+            }
+        }
+    }
+}
+
+impl Visitor for Checker<'_> {
+    fn visit_expr(&mut self, expr: &Expr) {
+        if self.error.is_some() {
+            return;
+        }
+        match expr {
+            Expr::Ident(name, span) => self.check_name(name, *span),
+            Expr::Struct { name, span, .. } => self.check_name(name, *span),
+            Expr::Field { object, field, span } => self.check_member(object, field, *span),
+            Expr::MethodCall { receiver, method, span, .. } => self.check_member(receiver, method, *span),
+            _ => {}
+        }
+        walk_expr(self, expr);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{compile, compile_with_options, edition, CompileOptions, CompileTarget, Lexer, Parser};
+
+    fn check_source(source: &str) -> KainResult<()> {
+        let tokens = Lexer::with_edition(source, Edition::V0_2).tokenize()?;
+        let program = Parser::new(&tokens).parse()?;
+        check(&program, &crate::modules::graph(&program, None, Edition::V0_2)?, Edition::V0_2)
+    }
+
+    #[test]
+    fn test_private_items_are_reported_where_used() {
+        let err = check_source("use tests/modules/shapes\n\nfn main():\n    println(double(2))\n").unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("function `double` is private to module tests/modules/shapes, consider making it pub"));
+
+        let err = check_source("use tests/modules/circles as c\n\nfn main():\n    println(c.square(2))\n").unwrap_err();
+        assert!(err.to_string().contains("function `square` is private to module tests/modules/circles"));
+
+        let err = check_source("use tests/modules/circles: {area, square}\n").unwrap_err();
+        assert!(err.to_string().contains("function `square` is private"));
+
+        // Public items, and private names the program binds itself, are fine
+        check_source("use tests/modules/shapes\n\nfn double(x: Int) -> Int:\n    return x + x\n\nfn main():\n    println(area(2, 3))\n    println(double(2))\n").unwrap();
+        check_source("use tests/modules/circles as c\n\nfn main():\n    println(c.area(2))\n").unwrap();
+    }
+
+    #[test]
+    fn test_only_pub_items_are_exported() {
+        let source = "pub fn area(r: Int) -> Int:\n    return square(r)\n\nfn square(x: Int) -> Int:\n    return x * x\n\npub struct P:\n    x: Int\n";
+        let js = String::from_utf8(compile(source, CompileTarget::Js).unwrap()).unwrap();
+        assert!(js.contains("export function area(r)") && js.contains("export class P"));
+        assert!(js.contains("function square(x)") && !js.contains("export function square"));
+
+        // Compiled targets reject private imports too, not only the interpreter
+        let options = CompileOptions { edition: edition::Edition::V0_2, ..Default::default() };
+        let private = "use tests/modules/shapes\n\nfn main():\n    println(double(2))\n";
+        let err = compile_with_options(private, CompileTarget::Js, &options).unwrap_err();
+        assert!(err.to_string().contains("function `double` is private to module tests/modules/shapes, consider making it pub"));
+    }
+}
@@ -0,0 +1,417 @@
+//! The serialization format shared by `serialize`, snapshots and recordings
+//!
+//! A value is written as a tag byte followed by its contents, so a reader
+//! needs no schema to decode it. Encoded values start with [`MAGIC`] and the
+//! format's version:
+//!
+//! | Tag | Value | Contents |
+//! |-----|-------|----------|
+//! | 0 | `None` | |
+//! | 1 | `()` | |
+//! | 2, 3 | `false`, `true` | |
+//! | 4 | `Int` | zigzag varint |
+//! | 5 | `Float` | 8 bytes, little endian |
+//! | 6 | `String` | varint length, UTF-8 |
+//! | 7, 8 | array, tuple | varint count, items |
+//! | 9 | struct | name, varint count, then each field's name and value, by name |
+//! | 10 | enum variant | enum name, variant name, varint count, fields |
+//! | 11, 12 | `Ok`, `Err` | the value inside |
+//! | 13 | `Char` | varint code point |
+//! | 14 | `Some` | the value inside |
+//!
+//! Names are written like strings. Functions, actors and other values that
+//! only mean something inside a running program cannot be encoded.
+//!
+//! The JSON form is for talking to other programs. Structs are objects with
+//! their name under `"$type"`, variants `{"$type": "Enum::Variant", "$fields": [...]}`,
+//! tuples `{"$tuple": [...]}`, results `{"$ok": ...}` or `{"$err": ...}` and
+//! options `{"$some": ...}` or `null`;
+//! reading plain JSON gives `Json` structs for objects, as `json_parse` does.
+
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+
+use crate::runtime::Value;
+
+/// The first bytes of every encoded value
+pub const MAGIC: &[u8; 4] = b"KSER";
+/// Bumped when a reader of the previous version could misread the output
+pub const VERSION: u8 = 1;
+
+/// Deeper values are refused, so a hostile input cannot overflow the stack
+const MAX_DEPTH: usize = 512;
+
+const NONE: u8 = 0;
+const UNIT: u8 = 1;
+const FALSE: u8 = 2;
+const TRUE: u8 = 3;
+const INT: u8 = 4;
+const FLOAT: u8 = 5;
+const STRING: u8 = 6;
+const ARRAY: u8 = 7;
+const TUPLE: u8 = 8;
+const STRUCT: u8 = 9;
+const VARIANT: u8 = 10;
+const OK: u8 = 11;
+const ERR: u8 = 12;
+const CHAR: u8 = 13;
+const SOME: u8 = 14;
+
+/// Encode `value`, with the header
+pub fn encode(value: &Value) -> Result<Vec<u8>, String> {
+    let mut out = MAGIC.to_vec();
+    out.push(VERSION);
+    write_value(&mut out, value)?;
+    Ok(out)
+}
+
+/// Decode a value written by [`encode`]
+pub fn decode(bytes: &[u8]) -> Result<Value, String> {
+    let Some(body) = bytes.strip_prefix(MAGIC.as_slice()) else {
+        return Err("not in the kain wire format".to_string());
+    };
+    let (&version, body) = body.split_first().ok_or("the header is cut short")?;
+    if version > VERSION {
+        return Err(format!("written by a newer compiler (format version {}, this one reads up to {})", version, VERSION));
+    }
+    let mut reader = Reader { bytes: body, at: 0 };
+    let value = reader.value(0)?;
+    if reader.at != body.len() {
+        return Err(format!("{} bytes left over after the value", body.len() - reader.at));
+    }
+    Ok(value)
+}
+
+/// Whether `bytes` starts like a value written by [`encode`]
+pub fn is_encoded(bytes: &[u8]) -> bool {
+    bytes.starts_with(MAGIC)
+}
+
+fn write_value(out: &mut Vec<u8>, value: &Value) -> Result<(), String> {
+    match value {
+        Value::None => out.push(NONE),
+        Value::Unit => out.push(UNIT),
+        Value::Bool(false) => out.push(FALSE),
+        Value::Bool(true) => out.push(TRUE),
+        Value::Int(n) => {
+            out.push(INT);
+            // Zigzag, so small negative numbers stay short: 0, -1, 1, -2 are 0, 1, 2, 3
+            write_varint(out, ((*n << 1) ^ (*n >> 63)) as u64);
+        }
+        Value::Char(c) => {
+            out.push(CHAR);
+            write_varint(out, *c as u64);
+        }
+        Value::Float(f) => {
+            out.push(FLOAT);
+            out.extend_from_slice(&f.to_le_bytes());
+        }
+        Value::String(s) => {
+            out.push(STRING);
+            write_str(out, s);
+        }
+        Value::Array(items) => {
+            out.push(ARRAY);
+            write_items(out, &items.read().unwrap())?;
+        }
+        Value::Tuple(items) => {
+            out.push(TUPLE);
+            write_items(out, items)?;
+        }
+        Value::Struct(name, fields) => {
+            out.push(STRUCT);
+            write_str(out, name);
+            let fields = fields.read().unwrap();
+            // By name, so equal structs encode to equal bytes
+            let mut sorted: Vec<_> = fields.iter().collect();
+            sorted.sort_by(|a, b| a.0.cmp(b.0));
+            write_varint(out, sorted.len() as u64);
+            for (field, value) in sorted {
+                write_str(out, field);
+                write_value(out, value)?;
+            }
+        }
+        Value::EnumVariant(enum_name, variant, fields) => {
+            out.push(VARIANT);
+            write_str(out, enum_name);
+            write_str(out, variant);
+            write_items(out, fields)?;
+        }
+        Value::Result(ok, inner) => {
+            out.push(if *ok { OK } else { ERR });
+            write_value(out, inner)?;
+        }
+        Value::Some(inner) => {
+            out.push(SOME);
+            write_value(out, inner)?;
+        }
+        other => return Err(format!("cannot serialize {}, which only exists in a running program", kind(other))),
+    }
+    Ok(())
+}
+
+fn write_items(out: &mut Vec<u8>, items: &[Value]) -> Result<(), String> {
+    write_varint(out, items.len() as u64);
+    items.iter().try_for_each(|item| write_value(out, item))
+}
+
+fn write_str(out: &mut Vec<u8>, s: &str) {
+    write_varint(out, s.len() as u64);
+    out.extend_from_slice(s.as_bytes());
+}
+
+/// LEB128: seven bits a byte, low bits first, the high bit set on all but the last
+fn write_varint(out: &mut Vec<u8>, mut n: u64) {
+    while n >= 0x80 {
+        out.push(n as u8 | 0x80);
+        n >>= 7;
+    }
+    out.push(n as u8);
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    at: usize,
+}
+
+impl Reader<'_> {
+    fn value(&mut self, depth: usize) -> Result<Value, String> {
+        if depth > MAX_DEPTH {
+            return Err(format!("values nest more than {} deep", MAX_DEPTH));
+        }
+        let at = self.at;
+        Ok(match self.byte()? {
+            NONE => Value::None,
+            UNIT => Value::Unit,
+            FALSE => Value::Bool(false),
+            TRUE => Value::Bool(true),
+            INT => {
+                let n = self.varint()?;
+                Value::Int((n >> 1) as i64 ^ -((n & 1) as i64))
+            }
+            FLOAT => Value::Float(f64::from_le_bytes(self.take(8)?.try_into().expect("took 8 bytes"))),
+            STRING => Value::String(self.string()?),
+            CHAR => {
+                let code = self.varint()?;
+                let c = u32::try_from(code).ok().and_then(char::from_u32);
+                Value::Char(c.ok_or_else(|| format!("{:#x} at byte {} is not a char", code, at + MAGIC.len() + 1))?)
+            }
+            ARRAY => Value::Array(Arc::new(RwLock::new(self.items(depth)?))),
+            TUPLE => Value::Tuple(self.items(depth)?),
+            STRUCT => {
+                let name = self.string()?;
+                let count = self.count()?;
+                let mut fields = HashMap::with_capacity(count);
+                for _ in 0..count {
+                    let field = self.string()?;
+                    fields.insert(field, self.value(depth + 1)?);
+                }
+                Value::Struct(name, Arc::new(RwLock::new(fields)))
+            }
+            VARIANT => {
+                let enum_name = self.string()?;
+                let variant = self.string()?;
+                Value::EnumVariant(enum_name, variant, self.items(depth)?)
+            }
+            OK => Value::Result(true, Box::new(self.value(depth + 1)?)),
+            ERR => Value::Result(false, Box::new(self.value(depth + 1)?)),
+            SOME => Value::Some(Box::new(self.value(depth + 1)?)),
+            tag => return Err(format!("unknown tag {} at byte {}", tag, at + MAGIC.len() + 1)),
+        })
+    }
+
+    fn items(&mut self, depth: usize) -> Result<Vec<Value>, String> {
+        let count = self.count()?;
+        let mut items = Vec::with_capacity(count);
+        for _ in 0..count {
+            items.push(self.value(depth + 1)?);
+        }
+        Ok(items)
+    }
+
+    /// A count of things that follow, each at least a byte long
+    fn count(&mut self) -> Result<usize, String> {
+        let count = self.varint()?;
+        if count > (self.bytes.len() - self.at) as u64 {
+            return Err(format!("a count of {} is more than the bytes left", count));
+        }
+        Ok(count as usize)
+    }
+
+    fn string(&mut self) -> Result<String, String> {
+        let len = self.count()?;
+        String::from_utf8(self.take(len)?.to_vec()).map_err(|_| "a string is not UTF-8".to_string())
+    }
+
+    fn varint(&mut self) -> Result<u64, String> {
+        let mut n = 0u64;
+        for shift in (0..64).step_by(7) {
+            let byte = self.byte()?;
+            n |= ((byte & 0x7f) as u64) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(n);
+            }
+        }
+        Err("a number is longer than 64 bits".to_string())
+    }
+
+    fn byte(&mut self) -> Result<u8, String> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn take(&mut self, len: usize) -> Result<&[u8], String> {
+        let end = self.at.checked_add(len).filter(|&end| end <= self.bytes.len()).ok_or("the value is cut short")?;
+        let taken = &self.bytes[self.at..end];
+        self.at = end;
+        Ok(taken)
+    }
+}
+
+/// The JSON form of `value`
+pub fn to_json(value: &Value) -> Result<serde_json::Value, String> {
+    use serde_json::{json, Map, Value as Json};
+    Ok(match value {
+        Value::None | Value::Unit => Json::Null,
+        Value::Bool(b) => Json::Bool(*b),
+        Value::Int(n) => json!(n),
+        Value::Float(f) if f.is_finite() => json!(f),
+        Value::Float(f) => return Err(format!("JSON has no form for {}", f)),
+        Value::String(s) => Json::String(s.clone()),
+        Value::Char(c) => Json::String(c.to_string()),
+        Value::Array(items) => Json::Array(items.read().unwrap().iter().map(to_json).collect::<Result<_, _>>()?),
+        Value::Tuple(items) => json!({ "$tuple": items.iter().map(to_json).collect::<Result<Vec<_>, _>>()? }),
+        Value::Struct(name, fields) => {
+            let mut object = Map::new();
+            // `json_parse` names objects `Json`, which is what they are without a type
+            if name != "Json" {
+                object.insert("$type".to_string(), Json::String(name.clone()));
+            }
+            for (field, value) in fields.read().unwrap().iter() {
+                object.insert(field.clone(), to_json(value)?);
+            }
+            Json::Object(object)
+        }
+        Value::EnumVariant(enum_name, variant, fields) => json!({
+            "$type": format!("{}::{}", enum_name, variant),
+            "$fields": fields.iter().map(to_json).collect::<Result<Vec<_>, _>>()?,
+        }),
+        Value::Result(true, inner) => json!({ "$ok": to_json(inner)? }),
+        Value::Result(false, inner) => json!({ "$err": to_json(inner)? }),
+        Value::Some(inner) => json!({ "$some": to_json(inner)? }),
+        other => return Err(format!("cannot serialize {}, which only exists in a running program", kind(other))),
+    })
+}
+
+/// The value whose JSON form is `json`
+pub fn from_json(json: &serde_json::Value) -> Value {
+    use serde_json::Value as Json;
+    let Json::Object(object) = json else {
+        return match json {
+            Json::Array(items) => Value::Array(Arc::new(RwLock::new(items.iter().map(from_json).collect()))),
+            other => crate::runtime::json_to_value(other),
+        };
+    };
+    let list = |items: &Json| match items {
+        Json::Array(items) => Some(items.iter().map(from_json).collect::<Vec<_>>()),
+        _ => None,
+    };
+    if object.len() == 1 {
+        let (key, inner) = object.iter().next().expect("one entry");
+        match key.as_str() {
+            "$tuple" => {
+                if let Some(items) = list(inner) {
+                    return Value::Tuple(items);
+                }
+            }
+            "$ok" => return Value::Result(true, Box::new(from_json(inner))),
+            "$err" => return Value::Result(false, Box::new(from_json(inner))),
+            "$some" => return Value::Some(Box::new(from_json(inner))),
+            _ => {}
+        }
+    }
+    let name = object.get("$type").and_then(Json::as_str);
+    if let (Some(name), 2, Some(fields)) = (name, object.len(), object.get("$fields").and_then(list)) {
+        if let Some((enum_name, variant)) = name.split_once("::") {
+            return Value::EnumVariant(enum_name.to_string(), variant.to_string(), fields);
+        }
+    }
+    let fields = object
+        .iter()
+        .filter(|(key, _)| name.is_none() || *key != "$type")
+        .map(|(key, value)| (key.clone(), from_json(value)))
+        .collect();
+    Value::Struct(name.unwrap_or("Json").to_string(), Arc::new(RwLock::new(fields)))
+}
+
+/// The type a decoded value says it is, for checking it against the one expected
+pub fn type_name(value: &Value) -> &str {
+    match value {
+        Value::Struct(name, _) => name,
+        Value::EnumVariant(enum_name, _, _) => enum_name,
+        other => kind(other),
+    }
+}
+
+fn kind(value: &Value) -> &'static str {
+    match value {
+        Value::None => "None",
+        Value::Unit => "()",
+        Value::Bool(_) => "Bool",
+        Value::Int(_) => "Int",
+        Value::Float(_) => "Float",
+        Value::String(_) => "String",
+        Value::Char(_) => "Char",
+        Value::Array(_) => "an array",
+        Value::Tuple(_) => "a tuple",
+        Value::Range(_) => "a range",
+        Value::Struct(..) => "a struct",
+        Value::EnumVariant(..) => "an enum variant",
+        Value::Result(..) => "a Result",
+        Value::Some(_) => "an Option",
+        Value::Function(_) | Value::NativeFn(..) | Value::Closure(..) | Value::BoundMethod(..) | Value::StructConstructor(..) => "a function",
+        Value::ActorRef(_) => "an actor",
+        Value::Future(..) | Value::Poll(..) => "a future",
+        Value::JSX(_) => "a JSX element",
+        Value::Quote(_) => "quoted code",
+        Value::Module(_) => "a module",
+        Value::Return(_) | Value::Break(..) | Value::Continue(_) => "control flow",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{eval_snippet, CompileOptions};
+
+    #[test]
+    fn test_wire_format_round_trips_and_derive() {
+        let point = Value::Struct(
+            "Point".to_string(),
+            Arc::new(RwLock::new(HashMap::from([("x".to_string(), Value::Int(-1)), ("y".to_string(), Value::Float(0.5))]))),
+        );
+        let value = Value::Tuple(vec![
+            point,
+            Value::EnumVariant("Shape".to_string(), "Circle".to_string(), vec![Value::Int(300)]),
+            Value::Result(false, Box::new(Value::String("é".to_string()))),
+            Value::Some(Box::new(Value::None)),
+            Value::None,
+        ]);
+        let bytes = encode(&value).unwrap();
+        assert_eq!(&bytes[..5], b"KSER\x01");
+        assert_eq!(&encode(&Value::Int(-1)).unwrap()[5..], &[INT, 1]);
+        assert_eq!(&encode(&Value::Int(300)).unwrap()[5..], &[INT, 0xd8, 0x04]);
+        // Fields are encoded in order, so equal bytes mean equal values
+        assert_eq!(encode(&decode(&bytes).unwrap()).unwrap(), bytes);
+        assert_eq!(encode(&from_json(&to_json(&value).unwrap())).unwrap(), bytes);
+        assert!(decode(&bytes[..bytes.len() - 1]).unwrap_err().contains("cut short"));
+        assert!(decode(b"KSER\x01\x07\xff\xff\xff\xff\x0f").unwrap_err().contains("more than the bytes left"));
+        assert!(encode(&Value::Function("main".to_string())).unwrap_err().contains("cannot serialize a function"));
+
+        let source = "@derive(Serialize, Deserialize)\nstruct Point:\n    x: Int\n    y: Int\n\nfn main():\n    let p = Point { x: 1, y: 2 }\n    let bytes = p.serialize()\n    let q = Point_deserialize(bytes)\n    println(q.x, q.y)\n    println(p.to_json())\n    println(Point_from_json(\"{\\\"x\\\": 3, \\\"y\\\": 4}\").y)\n    println(deserialize(serialize([1, 2]))[1])\n    Point_deserialize(serialize((1, 2)))\n";
+        let result = eval_snippet(source, &CompileOptions::default());
+        assert_eq!(result.stdout.lines().collect::<Vec<_>>(), ["1 2", "{\"$type\":\"Point\",\"x\":1,\"y\":2}", "4", "2"]);
+        assert!(result.diagnostics[0].to_string().contains("deserialize: expected Point, found a tuple"), "{:?}", result.diagnostics);
+        let result = eval_snippet("@derive(Debug)\nstruct P:\n    x: Int\n", &CompileOptions::default());
+        assert!(result.diagnostics[0].to_string().contains("cannot derive `Debug`"), "{:?}", result.diagnostics);
+    }
+}